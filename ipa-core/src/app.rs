@@ -6,14 +6,17 @@ use crate::{
     cli::LoggingHandle,
     executor::IpaRuntime,
     helpers::{
-        query::{CompareStatusRequest, PrepareQuery, QueryConfig, QueryInput},
+        query::{CompareStatusRequest, CreateQuery, PrepareQuery, QueryConfig, QueryInput},
         routing::{Addr, RouteId},
         ApiError, BodyStream, HandlerBox, HandlerRef, HelperIdentity, HelperResponse,
         MpcTransportImpl, RequestHandler, ShardTransportImpl, Transport, TransportIdentity,
     },
     hpke::{KeyRegistry, PrivateKeyOnly},
     protocol::QueryId,
-    query::{NewQueryError, QueryProcessor, QueryStatus},
+    query::{
+        BudgetLedger, NewQueryError, QueryCompletionError, QueryProcessor, QueryStatus,
+        ReplayStore, ResultStore,
+    },
     sharding::ShardIndex,
     sync::Arc,
     utils::NonZeroU32PowerOfTwo,
@@ -24,6 +27,9 @@ pub struct AppConfig {
     active_work: Option<NonZeroU32PowerOfTwo>,
     key_registry: Option<KeyRegistry<PrivateKeyOnly>>,
     runtime: IpaRuntime,
+    result_store: Option<ResultStore>,
+    replay_store: Option<ReplayStore>,
+    budget_ledger: Option<BudgetLedger>,
 }
 
 impl AppConfig {
@@ -44,6 +50,24 @@ impl AppConfig {
         self.runtime = runtime;
         self
     }
+
+    #[must_use]
+    pub fn with_result_store(mut self, result_store: Option<ResultStore>) -> Self {
+        self.result_store = result_store;
+        self
+    }
+
+    #[must_use]
+    pub fn with_replay_store(mut self, replay_store: Option<ReplayStore>) -> Self {
+        self.replay_store = replay_store;
+        self
+    }
+
+    #[must_use]
+    pub fn with_budget_ledger(mut self, budget_ledger: Option<BudgetLedger>) -> Self {
+        self.budget_ledger = budget_ledger;
+        self
+    }
 }
 
 pub struct Setup {
@@ -73,7 +97,17 @@ impl Setup {
     #[must_use]
     pub fn new(config: AppConfig) -> (Self, HandlerRef<HelperIdentity>, HandlerRef<ShardIndex>) {
         let key_registry = config.key_registry.unwrap_or_else(KeyRegistry::empty);
-        let query_processor = QueryProcessor::new(key_registry, config.active_work, config.runtime);
+        let mut query_processor =
+            QueryProcessor::new(key_registry, config.active_work, config.runtime);
+        if let Some(result_store) = config.result_store {
+            query_processor = query_processor.with_result_store(result_store);
+        }
+        if let Some(replay_store) = config.replay_store {
+            query_processor = query_processor.with_replay_store(replay_store);
+        }
+        if let Some(budget_ledger) = config.budget_ledger {
+            query_processor = query_processor.with_budget_ledger(budget_ledger);
+        }
         let mpc_handler = HandlerBox::empty();
         let shard_handler = HandlerBox::empty();
         let this = Self {
@@ -130,7 +164,7 @@ impl HelperApp {
             .new_query(
                 self.inner.mpc_transport.clone_ref(),
                 self.inner.shard_transport.clone_ref(),
-                query_config,
+                query_config.into(),
             )
             .await?
             .query_id)
@@ -218,6 +252,12 @@ impl RequestHandler<ShardIndex> for Inner {
                 // and pass it down to the MPC handler.
                 RequestHandler::<HelperIdentity>::handle(self, req.erase_origin(), data).await?
             }
+            RouteId::KillQuery => {
+                let query_id = ext_query_id(&req)?;
+                let killed = qp.kill(query_id)?;
+                self.logging_handle.metrics_handle.query_completed(query_id);
+                HelperResponse::from(killed)
+            }
             r => {
                 return Err(ApiError::BadRequest(
                     format!("{r:?} request must not be handled by shard query processing flow")
@@ -237,14 +277,14 @@ impl RequestHandler<HelperIdentity> for Inner {
     ) -> Result<HelperResponse, ApiError> {
         let qp = &self.query_processor;
         Ok(match req.route {
-            r @ RouteId::Records => {
+            r @ (RouteId::Records | RouteId::RecordsAck) => {
                 return Err(ApiError::BadRequest(
                     format!("{r:?} request must not be handled by MPC query processing flow")
                         .into(),
                 ))
             }
             RouteId::ReceiveQuery => {
-                let req = req.into::<QueryConfig>()?;
+                let req = req.into::<CreateQuery>()?;
                 HelperResponse::from(
                     qp.new_query(
                         self.mpc_transport.clone_ref(),
@@ -282,19 +322,34 @@ impl RequestHandler<HelperIdentity> for Inner {
             }
             RouteId::CompleteQuery => {
                 let query_id = ext_query_id(&req)?;
-                HelperResponse::from(
-                    qp.complete(query_id, self.shard_transport.clone_ref())
-                        .await?,
-                )
+                let result = qp
+                    .complete(query_id, self.shard_transport.clone_ref())
+                    .await;
+                // the query may still be running if it could not be completed yet
+                if matches!(
+                    result,
+                    Ok(_)
+                        | Err(QueryCompletionError::ExecutionError(_)
+                            | QueryCompletionError::TimedOut(_))
+                ) {
+                    self.logging_handle.metrics_handle.query_completed(query_id);
+                }
+                HelperResponse::from(result?)
             }
             RouteId::KillQuery => {
                 let query_id = ext_query_id(&req)?;
-                HelperResponse::from(qp.kill(query_id)?)
+                let killed = qp.kill(query_id)?;
+                self.logging_handle.metrics_handle.query_completed(query_id);
+                HelperResponse::from(killed)
             }
             RouteId::Metrics => {
                 let logging_handler = &self.logging_handle;
                 let metrics_handle = &logging_handler.metrics_handle;
-                HelperResponse::from(metrics_handle.scrape_metrics())
+                HelperResponse::from(
+                    metrics_handle
+                        .scrape_metrics(self.shard_transport.identity())
+                        .map_err(ApiError::MetricsUnavailable)?,
+                )
             }
         })
     }
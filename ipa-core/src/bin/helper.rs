@@ -5,6 +5,7 @@ use std::{
     os::fd::{FromRawFd, RawFd},
     path::{Path, PathBuf},
     process,
+    time::Duration,
 };
 
 use clap::{self, Parser, Subcommand};
@@ -16,7 +17,10 @@ use ipa_core::{
         test_setup, ConfGenArgs, KeygenArgs, LoggingHandle, ShardedConfGenArgs, TestSetupArgs,
         Verbosity,
     },
-    config::{hpke_registry, HpkeServerConfig, ServerConfig, TlsConfig},
+    config::{
+        hpke_registry, privacy_budget_ledger, query_result_store, HpkeServerConfig,
+        PrivacyBudgetConfig, QueryResultsConfig, ReportCollectorConfig, ServerConfig, TlsConfig,
+    },
     error::BoxError,
     executor::IpaRuntime,
     helpers::HelperIdentity,
@@ -24,6 +28,7 @@ use ipa_core::{
         ClientIdentity, ConnectionFlavor, IpaHttpClient, MpcHttpTransport, Shard,
         ShardHttpTransport,
     },
+    query::ReplayStore,
     sharding::ShardIndex,
     AppConfig, AppSetup, NonZeroU32PowerOfTwo,
 };
@@ -111,9 +116,52 @@ struct ServerArgs {
     #[arg(long, requires = "mk_public_key")]
     mk_private_key: Option<PathBuf>,
 
+    /// Manifest listing the rotating set of keys for decrypting match keys, as generated by
+    /// `keygen --mk-key-dir`
+    #[arg(long, conflicts_with = "mk_private_key")]
+    mk_key_manifest: Option<PathBuf>,
+
     /// Override the amount of active work processed in parallel
     #[arg(long)]
     active_work: Option<NonZeroU32PowerOfTwo>,
+
+    /// Directory to persist results of completed queries to, so they can be retrieved after
+    /// this helper restarts
+    #[arg(long)]
+    query_results_dir: Option<PathBuf>,
+
+    /// How long persisted query results are kept, in hours
+    #[arg(long, default_value = "72")]
+    query_results_retention_hours: u32,
+
+    /// Directory to remember reports used by hybrid queries in, so they are rejected if a report
+    /// collector submits them again. Reports are remembered until their HPKE key expires, keys
+    /// without an expiry date make the store grow without bound.
+    #[arg(long)]
+    replay_store_dir: Option<PathBuf>,
+
+    /// Directory to read query inputs given as `file://` paths from. Report collectors can't
+    /// point this helper at local files unless this is set.
+    #[arg(long)]
+    query_input_dir: Option<PathBuf>,
+
+    /// TLS client certificate of a report collector, as `NAME=PATH`. Queries created by a client
+    /// that authenticates with it are charged to the privacy budget of `NAME`. May be repeated.
+    #[arg(long = "report-collector-cert", value_name = "NAME=PATH")]
+    report_collector_certs: Vec<String>,
+
+    /// File to keep the privacy budget spent by report collectors in. Hybrid queries are not
+    /// charged to any budget unless this is set.
+    #[arg(long, requires = "privacy_budget_epsilon")]
+    privacy_budget_file: Option<PathBuf>,
+
+    /// Epsilon a report collector can spend on reports from one conversion site in one epoch
+    #[arg(long, requires = "privacy_budget_file")]
+    privacy_budget_epsilon: Option<f64>,
+
+    /// Length of privacy budget epochs, in days
+    #[arg(long, default_value = "7")]
+    privacy_budget_epoch_days: u32,
 }
 
 #[derive(Debug, Subcommand)]
@@ -193,15 +241,50 @@ async fn server(args: ServerArgs, logging_handle: LoggingHandle) -> Result<(), B
     let (shard_identity, shard_server_tls) =
         create_client_identity(shard_index, args.tls_cert, args.tls_key)?;
 
-    let mk_encryption = args.mk_private_key.map(|sk_path| HpkeServerConfig::File {
-        private_key_file: sk_path,
+    let mk_encryption = match (args.mk_private_key, args.mk_key_manifest) {
+        (Some(sk_path), _) => Some(HpkeServerConfig::File {
+            private_key_file: sk_path,
+        }),
+        (None, Some(manifest_file)) => Some(HpkeServerConfig::Manifest { manifest_file }),
+        (None, None) => None,
+    };
+
+    let query_results = args.query_results_dir.map(|dir| QueryResultsConfig {
+        dir,
+        retention: Duration::from_secs(u64::from(args.query_results_retention_hours) * 60 * 60),
     });
 
+    let key_registry = hpke_registry(mk_encryption.as_ref()).await?;
+    let replay_store = args
+        .replay_store_dir
+        .map(|dir| ReplayStore::open(dir, &key_registry))
+        .transpose()?;
+    let privacy_budget = args
+        .privacy_budget_file
+        .map(|ledger_file| PrivacyBudgetConfig {
+            ledger_file,
+            epsilon_per_epoch: args.privacy_budget_epsilon.expect("enforced by clap"),
+            epoch: Duration::from_secs(u64::from(args.privacy_budget_epoch_days) * 24 * 60 * 60),
+        });
+    let report_collectors = args
+        .report_collector_certs
+        .iter()
+        .map(|arg| {
+            let (name, path) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("expected NAME=PATH, got {arg}"))?;
+            ReportCollectorConfig::from_pem_file(name, path)
+        })
+        .collect::<Result<Vec<_>, BoxError>>()?;
+
     let query_runtime = new_query_runtime(&logging_handle);
     let app_config = AppConfig::default()
-        .with_key_registry(hpke_registry(mk_encryption.as_ref()).await?)
+        .with_key_registry(key_registry)
         .with_active_work(args.active_work)
-        .with_runtime(IpaRuntime::from_tokio_runtime(&query_runtime));
+        .with_runtime(IpaRuntime::from_tokio_runtime(&query_runtime))
+        .with_result_store(query_result_store(query_results.as_ref())?)
+        .with_replay_store(replay_store)
+        .with_budget_ledger(privacy_budget_ledger(privacy_budget.as_ref())?);
 
     let (setup, handler, shard_handler) = AppSetup::new(app_config);
 
@@ -210,6 +293,9 @@ async fn server(args: ServerArgs, logging_handle: LoggingHandle) -> Result<(), B
         disable_https: args.disable_https,
         tls: server_tls,
         hpke_config: mk_encryption.clone(),
+        query_results: query_results.clone(),
+        query_input_dir: args.query_input_dir,
+        report_collectors,
     };
 
     let shard_server_config = ServerConfig {
@@ -217,6 +303,11 @@ async fn server(args: ServerArgs, logging_handle: LoggingHandle) -> Result<(), B
         disable_https: args.disable_https,
         tls: shard_server_tls,
         hpke_config: mk_encryption,
+        query_results,
+        // inputs are only submitted to the MPC port
+        query_input_dir: None,
+        // report collectors create queries on the MPC port
+        report_collectors: Vec::new(),
     };
 
     let scheme = if args.disable_https {
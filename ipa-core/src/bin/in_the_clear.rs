@@ -3,6 +3,7 @@ use std::{error::Error, fs::File, io::Write, num::NonZeroU32, path::PathBuf};
 use clap::Parser;
 use ipa_core::{
     cli::{playbook::InputSource, Verbosity},
+    helpers::query::{BreakdownDimensions, HybridAggregationMode},
     test_fixture::hybrid::{hybrid_in_the_clear, TestHybridRecord},
 };
 
@@ -41,6 +42,20 @@ struct Args {
 
     #[arg(long, default_value = "20")]
     max_breakdown_key: NonZeroU32,
+
+    #[arg(long, default_value = "8")]
+    per_user_credit_cap: u32,
+
+    #[arg(long)]
+    attribution_window_seconds: Option<NonZeroU32>,
+
+    /// Break the histogram down by the cross product of two dimensions, e.g. `6x10`.
+    #[arg(long)]
+    breakdown_dimensions: Option<BreakdownDimensions>,
+
+    /// Metrics aggregated per breakdown key, `sum` or `sum-and-reach`.
+    #[arg(long, default_value_t = HybridAggregationMode::default())]
+    aggregation_mode: HybridAggregationMode,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -50,9 +65,16 @@ fn main() -> Result<(), Box<dyn Error>> {
     let input = InputSource::from(&args.input);
 
     let input_rows = input.iter::<TestHybridRecord>();
+    let max_breakdown = args
+        .breakdown_dimensions
+        .map_or(args.max_breakdown_key.get(), BreakdownDimensions::buckets);
     let expected = hybrid_in_the_clear(
         input_rows,
-        usize::try_from(args.max_breakdown_key.get()).unwrap(),
+        usize::try_from(max_breakdown).unwrap(),
+        args.per_user_credit_cap,
+        args.attribution_window_seconds,
+        args.breakdown_dimensions,
+        args.aggregation_mode,
     );
 
     let mut file = File::options()
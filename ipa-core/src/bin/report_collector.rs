@@ -8,10 +8,11 @@ use std::{
     iter::zip,
     ops::Deref,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use clap::{Parser, Subcommand};
-use hyper::{http::uri::Scheme, Uri};
+use hyper::http::uri::Scheme;
 use ipa_core::{
     cli::{
         playbook::{
@@ -25,12 +26,12 @@ use ipa_core::{
     ff::{boolean_array::BA32, FieldType},
     helpers::{
         query::{
-            DpMechanism, HybridQueryParams, IpaQueryConfig, QueryConfig, QueryInput, QuerySize,
-            QueryType,
+            DpMechanism, HybridQueryParams, IpaQueryConfig, QueryConfig, QueryDeadlines,
+            QueryInput, QuerySize, QueryType,
         },
         BodyStream,
     },
-    net::{Helper, IpaHttpClient},
+    net::{query_input::QueryInputSource, Helper, IpaHttpClient},
     protocol::QueryId,
     report::{EncryptedOprfReportStreams, DEFAULT_KEY_ID},
     test_fixture::{
@@ -70,10 +71,27 @@ struct Args {
     #[arg(long, default_value_t = 1)]
     shard_count: usize,
 
+    /// Cancel the query if helpers don't receive their inputs within this many seconds
+    #[arg(long)]
+    input_deadline: Option<u64>,
+
+    /// Cancel the query if it doesn't finish within this many seconds
+    #[arg(long)]
+    query_deadline: Option<u64>,
+
     #[command(subcommand)]
     action: ReportCollectorCommand,
 }
 
+impl Args {
+    fn deadlines(&self) -> QueryDeadlines {
+        QueryDeadlines {
+            inputs: self.input_deadline.map(Duration::from_secs),
+            total: self.query_deadline.map(Duration::from_secs),
+        }
+    }
+}
+
 #[derive(Debug, Parser)]
 pub struct CommandInput {
     #[arg(
@@ -150,7 +168,9 @@ enum ReportCollectorCommand {
 
         #[arg(
             long,
-            help = "Read the list of URLs that contain the input from the provided file",
+            help = "Read the list of URLs that contain the input from the provided file. Each \
+                    line holds the inputs of one shard, multiple URLs or file:// paths on the \
+                    same line are separated by whitespace",
             conflicts_with_all = ["enc_input_file1", "enc_input_file2", "enc_input_file3"]
         )]
         url_file_list: Option<PathBuf>,
@@ -323,38 +343,34 @@ fn inputs_from_url_file(
             if file.read_line(&mut buf)? == 0 {
                 break;
             }
-            helper_input
-                .push(Uri::try_from(buf.trim()).map_err(|e| format!("Invalid URL {buf:?}: {e}"))?);
+            // each line lists all the inputs for one shard, separated by whitespace
+            let urls = buf
+                .split_whitespace()
+                .map(|url| {
+                    url.parse::<QueryInputSource>()
+                        .map(|_| url.to_owned())
+                        .map_err(|e| format!("Invalid URL {url:?}: {e}"))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if urls.is_empty() {
+                return Err(format!("No URLs found on line {buf:?} of {url_file_path:?}").into());
+            }
+            helper_input.push(urls);
         }
     }
 
-    // make sure all helpers have the expected number of inputs (one per shard)
+    // make sure all helpers have the expected number of inputs (one line per shard)
     let all_rows = inputs.iter().map(|v| v.len()).sum::<usize>();
     if all_rows != 3 * shard_count {
         return Err(format!(
-            "The number of URLs in {url_file_path:?} '{all_rows}' is less than 3*{shard_count}."
+            "The number of lines in {url_file_path:?} '{all_rows}' is less than 3*{shard_count}."
         )
         .into());
     }
 
     let [h1, h2, h3] = inputs;
     Ok(zip(zip(h1, h2), h3)
-        .map(|((h1, h2), h3)| {
-            [
-                QueryInput::FromUrl {
-                    url: h1.to_string(),
-                    query_id,
-                },
-                QueryInput::FromUrl {
-                    url: h2.to_string(),
-                    query_id,
-                },
-                QueryInput::FromUrl {
-                    url: h3.to_string(),
-                    query_id,
-                },
-            ]
-        })
+        .map(|((h1, h2), h3)| [h1, h2, h3].map(|urls| QueryInput::FromUrls { query_id, urls }))
         .collect())
 }
 
@@ -548,6 +564,7 @@ async fn hybrid<F: FnOnce(QueryId) -> Result<Vec<[QueryInput; 3]>, Box<dyn Error
         size: QuerySize::try_from(count).unwrap(),
         field_type: FieldType::Fp32BitPrime,
         query_type,
+        deadlines: args.deadlines(),
     };
 
     let query_id = helper_clients[0][0]
@@ -555,7 +572,7 @@ async fn hybrid<F: FnOnce(QueryId) -> Result<Vec<[QueryInput; 3]>, Box<dyn Error
         .await
         .expect("Unable to create query!");
 
-    tracing::info!("Starting query for OPRF");
+    tracing::info!("Starting query {query_id} for OPRF");
     let submissions = make_inputs_fn(query_id)?;
 
     // the value for histogram values (BA32) must be kept in sync with the server-side
@@ -599,6 +616,7 @@ async fn ipa(
         size: QuerySize::try_from(encrypted_oprf_report_streams.query_size).unwrap(),
         field_type: FieldType::Fp32BitPrime,
         query_type,
+        deadlines: args.deadlines(),
     };
 
     let query_id = helper_clients[0]
@@ -606,7 +624,7 @@ async fn ipa(
         .await
         .expect("Unable to create query!");
 
-    tracing::info!("Starting query for OPRF");
+    tracing::info!("Starting query {query_id} for OPRF");
     // the value for histogram values (BA32) must be kept in sync with the server-side
     // implementation, otherwise a runtime reconstruct error will be generated.
     // see ipa-core/src/query/executor.rs
@@ -642,6 +660,7 @@ async fn ipa_test(
         size: QuerySize::try_from(input_rows.len()).unwrap(),
         field_type: FieldType::Fp32BitPrime,
         query_type,
+        deadlines: args.deadlines(),
     };
     let query_id = helper_clients[0]
         .create_query(query_config)
@@ -653,6 +672,7 @@ async fn ipa_test(
             &input_rows,
             ipa_query_config.per_user_credit_cap,
             ipa_query_config.attribution_window_seconds,
+            ipa_query_config.attribution_model,
             ipa_query_config.max_breakdown_key,
             &CappingOrder::CapMostRecentFirst,
         );
@@ -688,19 +708,16 @@ async fn ipa_test(
 
     tracing::info!("{m:?}", m = ipa_query_config);
 
-    match ipa_query_config.with_dp {
-        0 => {
+    match ipa_query_config.dp_mechanism {
+        DpMechanism::NoDp => {
             validate(&expected, &actual.breakdowns);
         }
-        _ => {
+        dp_mechanism => {
             validate_dp(
                 expected,
                 actual.breakdowns,
-                ipa_query_config.epsilon,
                 ipa_query_config.per_user_credit_cap,
-                DpMechanism::DiscreteLaplace {
-                    epsilon: ipa_query_config.epsilon,
-                },
+                dp_mechanism,
             );
         }
     }
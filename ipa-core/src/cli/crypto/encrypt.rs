@@ -108,7 +108,7 @@ mod tests {
             CsvSerializer,
         },
         ff::{boolean_array::BA16, U128Conversions},
-        helpers::query::{IpaQueryConfig, QuerySize},
+        helpers::query::{DpMechanism, IpaQueryConfig, QuerySize},
         hpke::{IpaPrivateKey, KeyRegistry, PrivateKeyOnly},
         query::OprfIpaQuery,
         report::EncryptedOprfReportStreams,
@@ -192,8 +192,7 @@ mod tests {
                         .unwrap();
                     let query_config = IpaQueryConfig {
                         max_breakdown_key: 3,
-                        with_dp: 0,
-                        epsilon: 1.0,
+                        dp_mechanism: DpMechanism::NoDp,
                         ..Default::default()
                     };
 
@@ -392,6 +392,7 @@ mod tests {
                 match_key: 23456,
                 breakdown_key: 4,
                 key_id: 0,
+                timestamp: 100,
             },
         ];
         let mut input_file = NamedTempFile::new().unwrap();
@@ -29,8 +29,9 @@ impl Serializer for crate::test_fixture::hybrid::TestHybridRecord {
                 match_key,
                 breakdown_key,
                 key_id,
+                timestamp,
             } => {
-                write!(buf, "i,{match_key},{breakdown_key},{key_id}")?;
+                write!(buf, "i,{match_key},{breakdown_key},{key_id},{timestamp}")?;
             }
             crate::test_fixture::hybrid::TestHybridRecord::TestConversion {
                 match_key,
@@ -14,4 +14,8 @@ pub struct QueryResult {
     )]
     pub latency: Duration,
     pub breakdowns: Vec<u32>,
+    /// The `(breakdown key, value)` pairs of the buckets that exceeded the DP threshold, for
+    /// queries with sparse histogram output. Suppressed buckets are zero in `breakdowns`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sparse_breakdowns: Option<Vec<(u32, u32)>>,
 }
@@ -1,7 +1,9 @@
 use std::{
     fs::File,
     io::{self, Write},
+    num::NonZeroU16,
     path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use clap::Args;
@@ -13,7 +15,11 @@ use rcgen::{
 };
 use time::{Duration, OffsetDateTime};
 
-use crate::{error::BoxError, hpke::KeyPair};
+use crate::{
+    config::{HpkeKeyManifest, HpkeKeyManifestEntry, HpkePublicKeyEntry, HpkePublicKeySet},
+    error::BoxError,
+    hpke::KeyPair,
+};
 
 #[derive(Debug, Clone, Args)]
 #[clap(
@@ -45,6 +51,20 @@ pub struct KeygenArgs {
     /// Optional: Writes the generated report private key to the file
     #[arg(long)]
     pub(crate) mk_private_key: Option<PathBuf>,
+
+    /// Optional: Generates a set of report keys with consecutive validity periods into this
+    /// directory. Along with the private keys, writes `manifest.toml` for the helper and
+    /// `public_keys.toml` for report collectors.
+    #[arg(long)]
+    pub(crate) mk_key_dir: Option<PathBuf>,
+
+    /// Number of report keys to generate into the key directory
+    #[arg(long, default_value = "1")]
+    pub(crate) mk_key_count: u8,
+
+    /// How long each report key from the key directory stays valid. Must not be zero
+    #[arg(long, default_value = "30")]
+    pub(crate) mk_valid_days: NonZeroU16,
 }
 
 fn create_new<P: AsRef<Path>>(path: P) -> io::Result<File> {
@@ -112,6 +132,49 @@ fn keygen_matchkey<R: Rng + CryptoRng>(args: &KeygenArgs, mut rng: &mut R) -> Re
     Ok(())
 }
 
+/// Generates a set of match key pairs, rotated by key id. The first key becomes valid now and
+/// every next key takes over when the previous one expires.
+fn keygen_matchkey_set<R: Rng + CryptoRng>(
+    args: &KeygenArgs,
+    mut rng: &mut R,
+) -> Result<(), BoxError> {
+    let Some(key_dir) = args.mk_key_dir.as_ref() else {
+        return Ok(());
+    };
+    let valid_secs = u64::from(args.mk_valid_days.get()) * 24 * 60 * 60;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let mut manifest = HpkeKeyManifest::default();
+    let mut public_keys = HpkePublicKeySet::default();
+    for key_id in 0..args.mk_key_count {
+        let keypair = KeyPair::gen(&mut rng);
+        let not_before = now + u64::from(key_id) * valid_secs;
+        let not_after = not_before + valid_secs - 1;
+        let private_key_file = PathBuf::from(format!("mk_{key_id}.key"));
+
+        create_new(key_dir.join(&private_key_file))?
+            .write_all(hex::encode(keypair.sk_bytes()).as_bytes())?;
+        manifest.keys.push(HpkeKeyManifestEntry {
+            key_id,
+            private_key_file,
+            not_before: Some(not_before),
+            not_after: Some(not_after),
+        });
+        public_keys.keys.push(HpkePublicKeyEntry {
+            key_id,
+            public_key: hex::encode(keypair.pk_bytes()),
+            not_before: Some(not_before),
+            not_after: Some(not_after),
+        });
+    }
+
+    create_new(key_dir.join("manifest.toml"))?.write_all(toml::to_string(&manifest)?.as_bytes())?;
+    create_new(key_dir.join("public_keys.toml"))?
+        .write_all(toml::to_string(&public_keys)?.as_bytes())?;
+
+    Ok(())
+}
+
 /// Generate keys necessary for running a helper service.
 ///
 /// # Errors
@@ -123,5 +186,6 @@ pub fn keygen(args: &KeygenArgs) -> Result<(), BoxError> {
     let mut rng = thread_rng();
     keygen_tls(args, &mut rng)?;
     keygen_matchkey(args, &mut rng)?;
+    keygen_matchkey_set(args, &mut rng)?;
     Ok(())
 }
@@ -1,17 +1,34 @@
-use std::{io, thread, thread::JoinHandle};
+use std::{io, mem, sync::Mutex, thread, thread::JoinHandle};
 
 use ipa_metrics::{
-    MetricChannelType, MetricsCollectorController, MetricsCurrentThreadContext, MetricsProducer,
+    MetricChannelType, MetricPartition, MetricsCollectorController, MetricsCurrentThreadContext,
+    MetricsProducer,
 };
 use ipa_metrics_prometheus::PrometheusMetricsExporter;
 use tokio::runtime::Builder;
 
+use crate::{
+    protocol::QueryId,
+    sharding::ShardIndex,
+    telemetry::{labels::SHARD, partition::query_partition},
+};
+
 /// Holds a reference to metrics controller and producer
 pub struct CollectorHandle {
     thread_handle: JoinHandle<()>,
     /// This will be used once we start consuming metrics
     controller: MetricsCollectorController,
     producer: MetricsProducer,
+    completed: Mutex<CompletedPartitions>,
+}
+
+/// Metric partitions of completed queries, that are dropped from the collector after they
+/// have been scraped. Runtime threads may flush metrics of a query after it completes, so
+/// each partition is kept for one more scrape to export them too.
+#[derive(Default)]
+struct CompletedPartitions {
+    not_scraped: Vec<MetricPartition>,
+    scraped: Vec<MetricPartition>,
 }
 
 ///
@@ -29,6 +46,7 @@ pub fn install_collector() -> io::Result<CollectorHandle> {
         thread_handle: handle,
         controller,
         producer,
+        completed: Mutex::default(),
     })
 }
 
@@ -55,16 +73,88 @@ impl CollectorHandle {
             .on_thread_park(flush_fn)
     }
 
-    /// Export the metrics to be consumed by metrics scraper, e.g. Prometheus
+    /// Export the metrics to be consumed by metrics scraper, e.g. Prometheus. Metrics
+    /// emitted by queries are labelled with the query id, and all metrics are labelled
+    /// with the `shard` this helper runs.
+    ///
+    /// The snapshot is taken by the collector thread, so it is safe to call this while
+    /// queries are running. It only includes metrics that runtime threads have flushed
+    /// to the collector, which happens every time a thread parks.
     ///
-    /// # Panics
-    /// If metrics is not initialized
-    #[must_use]
-    pub fn scrape_metrics(&self) -> Vec<u8> {
-        let mut store = self.controller.snapshot().expect("Metrics must be set up");
+    /// Metrics of completed queries are exported by two scrapes after their completion, and
+    /// then dropped, see [`Self::query_completed`].
+    ///
+    /// ## Errors
+    /// If the collector thread is not running.
+    ///
+    /// ## Panics
+    /// If the mutex guarding the completed queries is poisoned.
+    pub fn scrape_metrics(&self, shard: ShardIndex) -> Result<Vec<u8>, String> {
+        let (not_scraped, scraped) = {
+            let mut completed = self.completed.lock().unwrap();
+            (
+                mem::take(&mut completed.not_scraped),
+                mem::take(&mut completed.scraped),
+            )
+        };
+        let mut store = self.controller.snapshot()?;
         let mut buff = Vec::new();
-        store.export(&mut buff);
+        store.export_with_labels(&mut buff, &[(SHARD, shard.to_string())]);
+
+        self.controller.remove_partitions(scraped)?;
+        self.completed.lock().unwrap().scraped.extend(not_scraped);
+
+        Ok(buff)
+    }
+
+    /// Marks the metrics of `query_id` to be dropped once they have been scraped, so the
+    /// collector does not keep them for every query this helper has run.
+    ///
+    /// ## Panics
+    /// If the mutex guarding the completed queries is poisoned.
+    pub fn query_completed(&self, query_id: QueryId) {
+        self.completed
+            .lock()
+            .unwrap()
+            .not_scraped
+            .push(query_partition(query_id));
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use ipa_metrics::{counter, CurrentThreadPartitionContext, MetricsCurrentThreadContext};
+
+    use super::install_collector;
+    use crate::{protocol::QueryId, sharding::ShardIndex, telemetry::partition::query_partition};
+
+    #[test]
+    fn completed_query_is_dropped_after_scrape() {
+        let handle = install_collector().unwrap();
+        let query_id = QueryId::from(1);
+        let partition = query_partition(query_id);
+        let is_collected = || {
+            handle
+                .controller
+                .snapshot()
+                .unwrap()
+                .with_partition(partition, |_| ())
+                .is_some()
+        };
+
+        handle.producer.install();
+        CurrentThreadPartitionContext::set(partition);
+        counter!("foo", 1);
+        CurrentThreadPartitionContext::toggle(None);
+        MetricsCurrentThreadContext::flush();
+        while !is_collected() {
+            std::thread::yield_now();
+        }
 
-        buff
+        handle.query_completed(query_id);
+        handle.scrape_metrics(ShardIndex::FIRST).unwrap();
+        assert!(is_collected());
+        handle.scrape_metrics(ShardIndex::FIRST).unwrap();
+        assert!(!is_collected());
     }
 }
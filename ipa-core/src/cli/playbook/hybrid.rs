@@ -10,10 +10,13 @@ use serde::{Deserialize, Serialize};
 use tokio::time::sleep;
 
 use crate::{
+    cli::playbook::all_completed,
     ff::{Serializable, U128Conversions},
-    helpers::query::{HybridQueryParams, QueryInput, QuerySize},
+    helpers::query::{
+        BreakdownDimensions, DpMechanism, HistogramOutput, HybridAggregationMode,
+        HybridQueryParams, QueryInput, QuerySize,
+    },
     net::{Helper, IpaHttpClient},
-    query::QueryStatus,
     secret_sharing::{replicated::semi_honest::AdditiveShare, SharedValue},
     test_fixture::Reconstruct,
 };
@@ -60,16 +63,14 @@ where
     };
 
     loop {
-        if try_join_all(
+        let statuses = try_join_all(
             leader_clients
                 .each_ref()
                 .map(|client| client.query_status(query_id)),
         )
         .await
-        .unwrap()
-        .into_iter()
-        .all(|status| status == QueryStatus::Completed)
-        {
+        .unwrap();
+        if all_completed(query_id, &statuses) {
             break;
         }
 
@@ -103,30 +104,78 @@ where
     let lat = mpc_time.elapsed();
 
     tracing::info!("Running IPA for {query_size:?} records took {t:?}", t = lat);
-    let mut breakdowns = vec![0; usize::try_from(query_config.max_breakdown_key).unwrap()];
-    for (breakdown_key, trigger_value) in results.into_iter().enumerate() {
+    // queries with two breakdown dimensions report a bucket for every pair of keys
+    let max_breakdown_key = query_config
+        .breakdown_dimensions
+        .map_or(query_config.max_breakdown_key, BreakdownDimensions::buckets);
+    let (results, sparse_breakdowns) = match query_config.histogram_output {
+        HistogramOutput::Dense => (results, None),
+        HistogramOutput::Sparse { .. } => {
+            // Buckets that exceeded the DP threshold are returned as (breakdown key, value) pairs.
+            let mut dense = vec![HV::ZERO; usize::try_from(max_breakdown_key).unwrap()];
+            let mut sparse = Vec::with_capacity(results.len() / 2);
+            for pair in results.chunks_exact(2) {
+                let breakdown_key = u32::try_from(pair[0].as_u128()).unwrap();
+                if let Some(bucket) = dense.get_mut(usize::try_from(breakdown_key).unwrap()) {
+                    *bucket = pair[1];
+                }
+                sparse.push((breakdown_key, u32::try_from(pair[1].as_u128()).unwrap()));
+            }
+            (dense, Some(sparse))
+        }
+    };
+    // the reach histogram, if any, follows the histogram of sums
+    let histogram_len =
+        results.len() / usize::try_from(query_config.aggregation_mode.histograms()).unwrap();
+    let (sums, counts) = results.split_at(histogram_len);
+    let breakdowns = into_breakdowns(sums, max_breakdown_key, &query_config);
+    let reach = (query_config.aggregation_mode == HybridAggregationMode::SumAndReach)
+        .then(|| into_breakdowns(counts, max_breakdown_key, &query_config));
+
+    let breakdown_grid = query_config
+        .breakdown_dimensions
+        .map(|dimensions| BreakdownGrid {
+            dimensions,
+            values: dimensions.reshape(&breakdowns),
+            reach: reach.as_deref().map(|reach| dimensions.reshape(reach)),
+        });
+
+    HybridQueryResult {
+        input_size: QuerySize::try_from(query_size).unwrap(),
+        config: query_config,
+        latency: lat,
+        breakdowns,
+        sparse_breakdowns,
+        reach,
+        breakdown_grid,
+    }
+}
+
+fn into_breakdowns<HV>(
+    histogram: &[HV],
+    max_breakdown_key: u32,
+    query_config: &HybridQueryParams,
+) -> Vec<u32>
+where
+    HV: SharedValue + U128Conversions,
+{
+    let mut breakdowns = vec![0; usize::try_from(max_breakdown_key).unwrap()];
+    for (breakdown_key, trigger_value) in histogram.iter().enumerate() {
         // TODO: make the data type used consistent with `ipa_in_the_clear`
         // I think using u32 is wrong, we should move to u128
-        if query_config.with_dp == 0 {
+        if query_config.dp_mechanism == DpMechanism::NoDp {
             // otherwise if DP is added trigger_values will not be zero due to noise
             assert!(
-                breakdown_key < query_config.max_breakdown_key.try_into().unwrap()
-                    || trigger_value == HV::ZERO,
+                breakdown_key < max_breakdown_key.try_into().unwrap() || *trigger_value == HV::ZERO,
                 "trigger values were attributed to buckets more than max breakdown key"
             );
         }
 
-        if breakdown_key < query_config.max_breakdown_key.try_into().unwrap() {
+        if breakdown_key < max_breakdown_key.try_into().unwrap() {
             breakdowns[breakdown_key] += u32::try_from(trigger_value.as_u128()).unwrap();
         }
     }
-
-    HybridQueryResult {
-        input_size: QuerySize::try_from(query_size).unwrap(),
-        config: query_config,
-        latency: lat,
-        breakdowns,
-    }
+    breakdowns
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -139,4 +188,25 @@ pub struct HybridQueryResult {
     )]
     pub latency: Duration,
     pub breakdowns: Vec<u32>,
+    /// The `(breakdown key, value)` pairs of the buckets that exceeded the DP threshold, for
+    /// queries with sparse histogram output. Suppressed buckets are zero in `breakdowns`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sparse_breakdowns: Option<Vec<(u32, u32)>>,
+    /// The number of users attributed to every breakdown, for queries that aggregate reach.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reach: Option<Vec<u32>>,
+    /// The breakdowns arranged by both dimensions, for queries with two breakdown dimensions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breakdown_grid: Option<BreakdownGrid>,
+}
+
+/// A histogram broken down by two dimensions. `values[first_key][second_key]` holds the value
+/// of the bucket for that pair of keys, and `reach` holds the number of users attributed to it
+/// for queries that aggregate reach.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BreakdownGrid {
+    pub dimensions: BreakdownDimensions,
+    pub values: Vec<Vec<u32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reach: Option<Vec<Vec<u32>>>,
 }
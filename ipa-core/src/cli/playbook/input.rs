@@ -62,25 +62,36 @@ impl InputItem for TestHybridRecord {
         let event_type = s.chars().nth(0).unwrap();
         match event_type {
             'i' => {
-                if let [_, match_key, number, key_id] = s.splitn(4, ',').collect::<Vec<_>>()[..] {
-                    let match_key: u64 = match_key
-                        .parse()
-                        .unwrap_or_else(|e| panic!("Expected a u64, got {match_key}: {e}"));
-
-                    let number: u32 = number
-                        .parse()
-                        .unwrap_or_else(|e| panic!("Expected a u32, got {number}: {e}"));
-
-                    let key_id: u8 = key_id
-                        .parse()
-                        .unwrap_or_else(|e| panic!("Expected a u8, got {key_id}: {e}"));
-                    TestHybridRecord::TestImpression {
-                        match_key,
-                        breakdown_key: number,
-                        key_id,
-                    }
-                } else {
-                    panic!("{s} is not a valid {}", type_name::<Self>())
+                // the impression timestamp is optional, and defaults to 0
+                let (match_key, number, key_id, timestamp) =
+                    match s.splitn(5, ',').collect::<Vec<_>>()[..] {
+                        [_, match_key, number, key_id] => (match_key, number, key_id, "0"),
+                        [_, match_key, number, key_id, timestamp] => {
+                            (match_key, number, key_id, timestamp)
+                        }
+                        _ => panic!("{s} is not a valid {}", type_name::<Self>()),
+                    };
+
+                let match_key: u64 = match_key
+                    .parse()
+                    .unwrap_or_else(|e| panic!("Expected a u64, got {match_key}: {e}"));
+
+                let number: u32 = number
+                    .parse()
+                    .unwrap_or_else(|e| panic!("Expected a u32, got {number}: {e}"));
+
+                let key_id: u8 = key_id
+                    .parse()
+                    .unwrap_or_else(|e| panic!("Expected a u8, got {key_id}: {e}"));
+
+                let timestamp: u64 = timestamp
+                    .parse()
+                    .unwrap_or_else(|e| panic!("Expected a u64, got {timestamp}: {e}"));
+                TestHybridRecord::TestImpression {
+                    match_key,
+                    breakdown_key: number,
+                    key_id,
+                    timestamp,
                 }
             }
 
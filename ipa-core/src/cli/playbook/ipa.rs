@@ -14,18 +14,17 @@ use typenum::Unsigned;
 
 use crate::{
     cli::{
-        playbook::{BreakdownKey, Timestamp, TriggerValue},
+        playbook::{all_completed, BreakdownKey, Timestamp, TriggerValue},
         IpaQueryResult,
     },
     ff::{Serializable, U128Conversions},
     helpers::{
-        query::{IpaQueryConfig, QueryInput, QuerySize},
+        query::{DpMechanism, HistogramOutput, IpaQueryConfig, QueryInput, QuerySize},
         BodyStream,
     },
     hpke::PublicKeyRegistry,
     net::{Helper, IpaHttpClient},
     protocol::{ipa_prf::OPRFIPAInputRow, QueryId},
-    query::QueryStatus,
     report::{KeyIdentifier, OprfReport},
     secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares, SharedValue},
     test_fixture::{ipa::TestRawDataRecord, Reconstruct},
@@ -126,12 +125,10 @@ where
 
     let mut delay = Duration::from_millis(125);
     loop {
-        if try_join_all(clients.iter().map(|client| client.query_status(query_id)))
+        let statuses = try_join_all(clients.iter().map(|client| client.query_status(query_id)))
             .await
-            .unwrap()
-            .into_iter()
-            .all(|status| status == QueryStatus::Completed)
-        {
+            .unwrap();
+        if all_completed(query_id, &statuses) {
             break;
         }
 
@@ -159,11 +156,29 @@ where
     let lat = mpc_time.elapsed();
 
     tracing::info!("Running IPA for {query_size:?} records took {t:?}", t = lat);
+    let (results, sparse_breakdowns) = match query_config.histogram_output {
+        HistogramOutput::Dense => (results, None),
+        HistogramOutput::Sparse { .. } => {
+            // Buckets that exceeded the DP threshold are returned as (breakdown key, value) pairs.
+            let mut dense =
+                vec![HV::ZERO; usize::try_from(query_config.max_breakdown_key).unwrap()];
+            let mut sparse = Vec::with_capacity(results.len() / 2);
+            for pair in results.chunks_exact(2) {
+                let breakdown_key = u32::try_from(pair[0].as_u128()).unwrap();
+                if let Some(bucket) = dense.get_mut(usize::try_from(breakdown_key).unwrap()) {
+                    *bucket = pair[1];
+                }
+                sparse.push((breakdown_key, u32::try_from(pair[1].as_u128()).unwrap()));
+            }
+            (dense, Some(sparse))
+        }
+    };
+
     let mut breakdowns = vec![0; usize::try_from(query_config.max_breakdown_key).unwrap()];
     for (breakdown_key, trigger_value) in results.into_iter().enumerate() {
         // TODO: make the data type used consistent with `ipa_in_the_clear`
         // I think using u32 is wrong, we should move to u128
-        if query_config.with_dp == 0 {
+        if query_config.dp_mechanism == DpMechanism::NoDp {
             // otherwise if DP is added trigger_values will not be zero due to noise
             assert!(
                 breakdown_key < query_config.max_breakdown_key.try_into().unwrap()
@@ -182,5 +197,6 @@ where
         config: query_config,
         latency: lat,
         breakdowns,
+        sparse_breakdowns,
     }
 }
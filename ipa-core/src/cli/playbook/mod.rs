@@ -20,7 +20,7 @@ pub use sharded_shuffle::secure_shuffle;
 use tokio::time::sleep;
 
 pub use self::{
-    hybrid::{run_hybrid_query_and_validate, HybridQueryResult},
+    hybrid::{run_hybrid_query_and_validate, BreakdownGrid, HybridQueryResult},
     ipa::{playbook_oprf_ipa, run_query_and_validate},
     streaming::{RoundRobinSubmission, StreamingSubmission},
 };
@@ -31,7 +31,8 @@ use crate::{
     ff::boolean_array::{BA20, BA3, BA8},
     helpers::query::DpMechanism,
     net::{ClientIdentity, Helper, IpaHttpClient},
-    protocol::{dp::NoiseParams, ipa_prf::oprf_padding::insecure::OPRFPaddingDp},
+    protocol::{dp::NoiseParams, ipa_prf::oprf_padding::insecure::OPRFPaddingDp, QueryId},
+    query::QueryStatus,
 };
 
 pub type BreakdownKey = BA8;
@@ -96,10 +97,15 @@ where
 pub fn validate_dp(
     expected: Vec<u32>,
     actual: Vec<u32>,
-    epsilon: f64,
     per_user_credit_cap: u32,
     dp_mechanism: DpMechanism,
 ) {
+    let epsilon = dp_mechanism.epsilon();
+    let delta = match dp_mechanism {
+        DpMechanism::Binomial { delta, .. } => delta,
+        DpMechanism::NoDp | DpMechanism::DiscreteLaplace { .. } => NoiseParams::default().delta,
+    };
+
     let mut expected = expected.into_iter().fuse();
     let mut actual = actual.into_iter().fuse();
     let mut mismatch = Vec::new();
@@ -126,7 +132,8 @@ pub fn validate_dp(
         let next_actual_f64: f64 = next_actual.unwrap().into();
 
         let noise_params = NoiseParams {
-            epsilon,
+            epsilon: epsilon.unwrap_or_default(),
+            delta,
             per_user_credit_cap,
             ell_1_sensitivity: per_user_credit_cap.into(),
             ell_2_sensitivity: per_user_credit_cap.into(),
@@ -135,12 +142,12 @@ pub fn validate_dp(
             ..Default::default()
         };
         let same = match dp_mechanism {
-            DpMechanism::Binomial { epsilon: _ } => {
+            DpMechanism::Binomial { .. } => {
                 let (mean, std) = crate::protocol::dp::binomial_noise_mean_std(&noise_params);
                 next_actual_f64 - mean > next_expected_f64 - 10.0 * std
                     && next_actual_f64 - mean < next_expected_f64 + 10.0 * std
             }
-            DpMechanism::DiscreteLaplace { epsilon: _ } => {
+            DpMechanism::DiscreteLaplace { .. } => {
                 let truncated_discrete_laplace = OPRFPaddingDp::new(
                     noise_params.epsilon,
                     noise_params.delta,
@@ -190,7 +197,7 @@ pub fn validate_dp(
 
     // make sure DP noise actually changed the results. For large epsilon and few breakdowns keys
     // we might end up not adding any noise
-    if epsilon <= 1.0 {
+    if epsilon.is_some_and(|epsilon| epsilon <= 1.0) {
         assert!(!all_equal,
                 "Expected and actual results match exactly...probably DP noise is not being added when it should be");
     }
@@ -252,6 +259,26 @@ pub async fn make_sharded_clients(
     (clients, network)
 }
 
+/// Returns `true` if the query has completed on all the helpers that reported `statuses`.
+///
+/// ## Panics
+/// If the query has failed or did not finish before its deadline on any of them.
+fn all_completed(query_id: QueryId, statuses: &[QueryStatus]) -> bool {
+    for (i, status) in statuses.iter().enumerate() {
+        match status {
+            QueryStatus::Failed { .. } => panic!("query {query_id} on helper {}: {status}", i + 1),
+            QueryStatus::TimedOut => {
+                panic!("query {query_id} did not finish before its deadline")
+            }
+            _ => {}
+        }
+    }
+
+    statuses
+        .iter()
+        .all(|status| *status == QueryStatus::Completed)
+}
+
 async fn wait_for_servers(mut wait: usize, clients: &[[IpaHttpClient<Helper>; 3]]) {
     while wait > 0 && !clients_ready(clients).await {
         tracing::debug!("waiting for servers to come up");
@@ -8,11 +8,11 @@ use futures_util::future::try_join_all;
 use generic_array::ArrayLength;
 
 use crate::{
+    cli::playbook::all_completed,
     ff::{boolean_array::BooleanArray, Serializable},
     helpers::{query::QueryInput, BodyStream},
     net::{Helper, IpaHttpClient},
     protocol::QueryId,
-    query::QueryStatus,
     secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
     test_fixture::Reconstruct,
 };
@@ -59,16 +59,14 @@ where
 
     let mut delay = Duration::from_millis(125);
     loop {
-        if try_join_all(
+        let statuses = try_join_all(
             leader_clients
                 .iter()
                 .map(|client| client.query_status(query_id)),
         )
         .await
-        .unwrap()
-        .into_iter()
-        .all(|status| status == QueryStatus::Completed)
-        {
+        .unwrap();
+        if all_completed(query_id, &statuses) {
             break;
         }
 
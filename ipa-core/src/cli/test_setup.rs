@@ -1,6 +1,7 @@
 use std::{
     fs::{self, DirBuilder, File},
     iter::zip,
+    num::NonZeroU16,
     path::{Path, PathBuf},
 };
 
@@ -169,6 +170,9 @@ fn make_client_configs(
                 tls_expire_after: 365,
                 mk_public_key: Some(config_dir.helper_mk_public_key(id)),
                 mk_private_key: Some(config_dir.helper_mk_private_key(id)),
+                mk_key_dir: None,
+                mk_key_count: 1,
+                mk_valid_days: NonZeroU16::new(30).unwrap(),
             };
 
             keygen(&keygen_args)?;
@@ -10,6 +10,8 @@ use tracing_subscriber::{
     fmt, fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
 };
 
+#[cfg(feature = "otlp")]
+use crate::telemetry::otlp::{OtlpConfig, OtlpExporter};
 use crate::{
     cli::{install_collector, metric_collector::CollectorHandle},
     error::set_global_panic_hook,
@@ -27,10 +29,18 @@ pub struct Verbosity {
 
     #[arg(long, help = "Specify the output file for logs")]
     log_file: Option<PathBuf>,
+
+    /// File with the configuration of the OpenTelemetry collector to export traces to
+    #[cfg(feature = "otlp")]
+    #[arg(long, global = true)]
+    otlp_config: Option<PathBuf>,
 }
 
 pub struct LoggingHandle {
     pub metrics_handle: CollectorHandle,
+    /// Exports traces while it is alive, if the OTLP exporter is configured
+    #[cfg(feature = "otlp")]
+    pub otlp_exporter: Option<OtlpExporter>,
 }
 
 impl Verbosity {
@@ -52,6 +62,19 @@ impl Verbosity {
             .with(filter_layer)
             .with(stderr_writer);
 
+        #[cfg(feature = "otlp")]
+        let otlp_exporter = self.otlp_config.as_ref().map(|path| {
+            OtlpConfig::from_file(path)
+                .and_then(|config| OtlpExporter::install(&config))
+                .unwrap_or_else(|e| panic!("failed to set up OTLP trace exporter: {e}"))
+        });
+        #[cfg(feature = "otlp")]
+        let registry = registry.with(
+            otlp_exporter
+                .as_ref()
+                .map(|exporter| tracing_opentelemetry::layer().with_tracer(exporter.tracer())),
+        );
+
         if let Some(path) = &self.log_file {
             let log_file = OpenOptions::new()
                 .append(true)
@@ -73,7 +96,11 @@ impl Verbosity {
 
         let metrics_handle = install_collector().expect("Can install metrics");
 
-        let handle = LoggingHandle { metrics_handle };
+        let handle = LoggingHandle {
+            metrics_handle,
+            #[cfg(feature = "otlp")]
+            otlp_exporter,
+        };
         set_global_panic_hook();
 
         handle
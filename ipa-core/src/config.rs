@@ -1,9 +1,10 @@
 use std::{
-    borrow::{Borrow, Cow},
+    borrow::Borrow,
+    collections::HashSet,
     fmt::{Debug, Formatter},
     iter::zip,
-    path::PathBuf,
-    time::Duration,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
@@ -16,12 +17,14 @@ use tokio::fs;
 
 use crate::{
     error::BoxError,
-    helpers::HelperIdentity,
+    helpers::{query::ReportCollector, HelperIdentity},
     hpke::{
-        Deserializable as _, IpaPrivateKey, IpaPublicKey, KeyRegistry, PrivateKeyOnly,
+        Deserializable as _, IpaPrivateKey, IpaPublicKey, KeyRegistry, KeyValidity, PrivateKeyOnly,
         PublicKeyOnly, Serializable as _,
     },
     net::{ConnectionFlavor, Helper, Shard},
+    query::{BudgetLedger, ResultStore},
+    report::KeyIdentifier,
     sharding::ShardIndex,
 };
 
@@ -269,6 +272,77 @@ pub enum HpkeServerConfig {
         // Private key in hex format
         private_key: String,
     },
+    Manifest {
+        /// Path to [`HpkeKeyManifest`] in TOML format listing all the keys this helper
+        /// accepts reports for
+        manifest_file: PathBuf,
+    },
+}
+
+/// Set of private keys a helper uses to decrypt reports. Keys are rotated by giving each one
+/// its own identifier and validity period; reports encrypted under a key that is outside of its
+/// validity period are rejected.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HpkeKeyManifest {
+    pub keys: Vec<HpkeKeyManifestEntry>,
+    /// How long after the end of their validity period keys are still used to decrypt reports,
+    /// in seconds. Defaults to [`crate::hpke::DEFAULT_EXPIRED_KEY_GRACE_PERIOD`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grace_period_secs: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HpkeKeyManifestEntry {
+    pub key_id: KeyIdentifier,
+    /// Path to file containing private key in hex format. Relative paths are resolved
+    /// against the directory where the manifest is located.
+    pub private_key_file: PathBuf,
+    /// Start of the validity period, in seconds since Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u64>,
+    /// End of the validity period, in seconds since Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<u64>,
+}
+
+/// Public counterpart of [`HpkeKeyManifest`] that report collectors use to pick the key
+/// to encrypt reports with.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HpkePublicKeySet {
+    pub keys: Vec<HpkePublicKeyEntry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HpkePublicKeyEntry {
+    pub key_id: KeyIdentifier,
+    /// Public key in hex format
+    pub public_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<u64>,
+}
+
+fn key_validity(not_before: Option<u64>, not_after: Option<u64>) -> Result<KeyValidity, BoxError> {
+    if let (Some(not_before), Some(not_after)) = (not_before, not_after) {
+        if not_before > not_after {
+            return Err(format!(
+                "validity period ends at {not_after} before it starts at {not_before}"
+            )
+            .into());
+        }
+    }
+    let to_time = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+    Ok(KeyValidity {
+        not_before: not_before.map(to_time),
+        not_after: not_after.map(to_time),
+    })
+}
+
+async fn private_key_from_file(path: &Path) -> Result<IpaPrivateKey, BoxError> {
+    let sk = hex::decode(fs::read_to_string(path).await?.trim())?;
+    Ok(IpaPrivateKey::from_bytes(&sk)?)
 }
 
 /// # Errors
@@ -276,21 +350,69 @@ pub enum HpkeServerConfig {
 pub async fn hpke_registry(
     config: Option<&HpkeServerConfig>,
 ) -> Result<KeyRegistry<PrivateKeyOnly>, BoxError> {
-    let sk_str = match config {
+    let sk = match config {
         None => return Ok(KeyRegistry::<PrivateKeyOnly>::empty()),
         Some(HpkeServerConfig::Inline { private_key }) => {
-            Cow::Borrowed(private_key.trim().as_bytes())
+            IpaPrivateKey::from_bytes(&hex::decode(private_key.trim())?)?
         }
         Some(HpkeServerConfig::File { private_key_file }) => {
-            Cow::Owned(fs::read_to_string(private_key_file).await?.trim().into())
+            private_key_from_file(private_key_file).await?
+        }
+        Some(HpkeServerConfig::Manifest { manifest_file }) => {
+            return hpke_manifest_registry(manifest_file).await;
         }
     };
 
-    let sk = hex::decode(sk_str)?;
+    Ok(KeyRegistry::from_keys([PrivateKeyOnly(sk)]))
+}
+
+async fn hpke_manifest_registry(
+    manifest_file: &Path,
+) -> Result<KeyRegistry<PrivateKeyOnly>, BoxError> {
+    let manifest: HpkeKeyManifest = toml::from_str(&fs::read_to_string(manifest_file).await?)?;
+    let key_dir = manifest_file.parent().unwrap_or(Path::new(""));
+
+    let mut key_ids = HashSet::new();
+    let mut entries = Vec::with_capacity(manifest.keys.len());
+    for entry in manifest.keys {
+        if !key_ids.insert(entry.key_id) {
+            return Err(format!(
+                "{}: duplicate key identifier {}",
+                manifest_file.display(),
+                entry.key_id
+            )
+            .into());
+        }
+        let sk = private_key_from_file(&key_dir.join(&entry.private_key_file)).await?;
+        let validity = key_validity(entry.not_before, entry.not_after)?;
+        entries.push((entry.key_id, PrivateKeyOnly(sk), validity));
+    }
+
+    let registry = KeyRegistry::from_entries(entries);
+    Ok(match manifest.grace_period_secs {
+        Some(secs) => registry.with_grace_period(Duration::from_secs(secs)),
+        None => registry,
+    })
+}
+
+/// Where a helper persists the results of completed queries and for how long it keeps
+/// serving them, including across restarts.
+#[derive(Clone, Debug)]
+pub struct QueryResultsConfig {
+    /// Directory where results are written to
+    pub dir: PathBuf,
+    /// How long results stay available after the query completes
+    pub retention: Duration,
+}
 
-    Ok(KeyRegistry::from_keys([PrivateKeyOnly(
-        IpaPrivateKey::from_bytes(&sk)?,
-    )]))
+/// # Errors
+/// If the results directory can't be created or read.
+pub fn query_result_store(
+    config: Option<&QueryResultsConfig>,
+) -> Result<Option<ResultStore>, BoxError> {
+    Ok(config
+        .map(|config| ResultStore::open(&config.dir, config.retention))
+        .transpose()?)
 }
 
 /// Configuration information for launching an instance of the helper party web service.
@@ -307,6 +429,80 @@ pub struct ServerConfig {
 
     /// Configuration needed for decrypting match keys
     pub hpke_config: Option<HpkeServerConfig>,
+
+    /// Configuration needed for persisting query results
+    pub query_results: Option<QueryResultsConfig>,
+
+    /// Directory that query inputs specified as `file://` paths are read from. Reading query
+    /// inputs from local files is disabled if not set.
+    pub query_input_dir: Option<PathBuf>,
+
+    /// Report collectors that authenticate with a TLS client certificate. Queries they create
+    /// are charged to their own privacy budget.
+    pub report_collectors: Vec<ReportCollectorConfig>,
+}
+
+/// TLS client certificate that identifies a report collector.
+///
+/// Like peer certificates, it must be an exact match, the certificate subject is not used.
+#[derive(Clone, Debug)]
+pub struct ReportCollectorConfig {
+    pub name: ReportCollector,
+    pub certificate: OwnedCertificate,
+}
+
+impl ReportCollectorConfig {
+    /// Reads the certificate of report collector `name` from a PEM file.
+    ///
+    /// ## Errors
+    /// If the file can't be read or does not start with a certificate.
+    pub fn from_pem_file<P: AsRef<Path>>(name: &str, path: P) -> Result<Self, BoxError> {
+        let pem = std::fs::read(path.as_ref())?;
+        match rustls_pemfile::read_one(&mut pem.as_slice())? {
+            Some(Item::X509Certificate(certificate)) => Ok(Self {
+                name: ReportCollector(name.to_string()),
+                certificate,
+            }),
+            _ => Err(format!("{} is not a certificate", path.as_ref().display()).into()),
+        }
+    }
+}
+
+/// Configuration of the privacy budget that report collectors can spend on this helper.
+#[derive(Clone, Debug)]
+pub struct PrivacyBudgetConfig {
+    /// File the spent budget is persisted to
+    pub ledger_file: PathBuf,
+    /// Epsilon each report collector can spend on reports from one site in one epoch
+    pub epsilon_per_epoch: f64,
+    /// Reports are assigned to epochs of this length, according to their timestamp
+    pub epoch: Duration,
+}
+
+/// # Errors
+/// If the budget is not a positive number, the epoch is empty or the ledger can't be read.
+pub fn privacy_budget_ledger(
+    config: Option<&PrivacyBudgetConfig>,
+) -> Result<Option<BudgetLedger>, BoxError> {
+    let Some(config) = config else {
+        return Ok(None);
+    };
+    if !(config.epsilon_per_epoch.is_finite() && config.epsilon_per_epoch > 0.0) {
+        return Err(format!(
+            "privacy budget must be a positive number, got {}",
+            config.epsilon_per_epoch
+        )
+        .into());
+    }
+    if config.epoch.as_secs() == 0 {
+        return Err("privacy budget epoch must be at least one second long".into());
+    }
+
+    Ok(Some(BudgetLedger::open(
+        config.ledger_file.clone(),
+        config.epsilon_per_epoch,
+        config.epoch,
+    )?))
 }
 
 pub trait HyperClientConfigurator {
@@ -501,17 +697,25 @@ impl KeyRegistries {
 
 #[cfg(all(test, unit_test))]
 mod tests {
-    use std::time::Duration;
+    use std::{
+        num::NonZeroU16,
+        time::{Duration, SystemTime},
+    };
 
-    use hpke::{kem::X25519HkdfSha256, Kem};
+    use hpke::{kem::X25519HkdfSha256, Kem, Serializable};
     use hyper::Uri;
     use rand::rngs::StdRng;
     use rand_core::SeedableRng;
 
     use super::{NetworkConfig, PeerConfig};
     use crate::{
-        config::{ClientConfig, HpkeClientConfig, Http2Configurator, HttpClientConfigurator},
+        cli::{keygen, KeygenArgs},
+        config::{
+            hpke_registry, ClientConfig, HpkeClientConfig, HpkePublicKeySet, HpkeServerConfig,
+            Http2Configurator, HttpClientConfigurator,
+        },
         helpers::HelperIdentity,
+        hpke::{KeyPair, PrivateKeyRegistry},
         net::test::TestConfigBuilder,
         sharding::ShardIndex,
     };
@@ -612,4 +816,108 @@ mod tests {
         let conf = NetworkConfig::new_shards(vec![pc1.clone()], client);
         assert_eq!(conf.peers[ShardIndex::FIRST].url, pc1.url);
     }
+
+    #[tokio::test]
+    async fn hpke_registry_from_keygen_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = KeygenArgs {
+            name: "localhost".to_string(),
+            tls_cert: dir.path().join("tls.pem"),
+            tls_key: dir.path().join("tls.key"),
+            tls_expire_after: 1,
+            mk_public_key: None,
+            mk_private_key: None,
+            mk_key_dir: Some(dir.path().to_path_buf()),
+            mk_key_count: 3,
+            mk_valid_days: NonZeroU16::new(7).unwrap(),
+        };
+        keygen(&args).unwrap();
+
+        let registry = hpke_registry(Some(&HpkeServerConfig::Manifest {
+            manifest_file: dir.path().join("manifest.toml"),
+        }))
+        .await
+        .unwrap();
+        let public_keys: HpkePublicKeySet =
+            toml::from_str(&std::fs::read_to_string(dir.path().join("public_keys.toml")).unwrap())
+                .unwrap();
+
+        assert_eq!(
+            vec![0, 1, 2],
+            public_keys
+                .keys
+                .iter()
+                .map(|k| k.key_id)
+                .collect::<Vec<_>>()
+        );
+        let now = SystemTime::now();
+        for entry in public_keys.keys {
+            let sk = registry.private_key(entry.key_id).unwrap();
+            assert_eq!(
+                entry.public_key,
+                hex::encode(X25519HkdfSha256::sk_to_pk(sk).to_bytes())
+            );
+            // only the first key in the rotation is active right away
+            assert_eq!(entry.key_id != 0, registry.is_expired(entry.key_id, now));
+        }
+        assert!(registry.private_key(3).is_none());
+    }
+
+    #[tokio::test]
+    async fn hpke_manifest_grace_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = KeyPair::gen(&mut StdRng::seed_from_u64(1));
+        std::fs::write(dir.path().join("mk.key"), hex::encode(key.sk_bytes())).unwrap();
+        std::fs::write(
+            dir.path().join("manifest.toml"),
+            r#"
+            grace_period_secs = 3600
+
+            [[keys]]
+            key_id = 1
+            private_key_file = "mk.key"
+            not_after = 1000
+            "#,
+        )
+        .unwrap();
+
+        let registry = hpke_registry(Some(&HpkeServerConfig::Manifest {
+            manifest_file: dir.path().join("manifest.toml"),
+        }))
+        .await
+        .unwrap();
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        assert!(!registry.is_expired(1, at(1000)));
+        assert!(!registry.is_expired(1, at(4600)));
+        assert!(registry.is_expired(1, at(4601)));
+    }
+
+    #[tokio::test]
+    async fn hpke_manifest_rejects_duplicate_key_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = KeyPair::gen(&mut StdRng::seed_from_u64(1));
+        std::fs::write(dir.path().join("mk.key"), hex::encode(key.sk_bytes())).unwrap();
+        std::fs::write(
+            dir.path().join("manifest.toml"),
+            r#"
+            [[keys]]
+            key_id = 1
+            private_key_file = "mk.key"
+
+            [[keys]]
+            key_id = 1
+            private_key_file = "mk.key"
+            not_after = 1
+            "#,
+        )
+        .unwrap();
+
+        let err = hpke_registry(Some(&HpkeServerConfig::Manifest {
+            manifest_file: dir.path().join("manifest.toml"),
+        }))
+        .await
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("duplicate key identifier 1"));
+    }
 }
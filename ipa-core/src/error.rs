@@ -8,7 +8,7 @@ use thiserror::Error;
 
 use crate::{
     helpers::{Role, ZeroRecordsError},
-    protocol::RecordId,
+    protocol::{Gate, RecordId},
     report::{hybrid::InvalidHybridReportError, InvalidReportError},
     sharding::ShardIndex,
     task::JoinError,
@@ -78,7 +78,7 @@ pub enum Error {
     #[error("Current Context is unsafe, call validate to make it safe: {0}")]
     ContextUnsafe(String),
     #[error("DZKP Validation failed")]
-    DZKPValidationFailed,
+    DZKPValidationFailed { step: Gate },
     /// Because errors are not `Clone`, when a batch fails to verify, one record gets the actual
     /// error (above, possibly with additional detail in the future), and the rest get this error.
     #[error("Parallel DZKP Validation failed")]
@@ -87,6 +87,12 @@ pub enum Error {
     InconsistentShares,
     #[error("Inconsistent padding")]
     InconsistentPadding,
+    #[error("Helpers are configured with different padding parameters")]
+    InconsistentPaddingParameters,
+    #[error("Helpers decrypted different information from the conversion reports")]
+    InconsistentReportInfo,
+    #[error("Query size is {expected}, but the input contained {actual} records")]
+    RecordCountMismatch { expected: usize, actual: usize },
     #[error("The Masks cannot be set safely, i.e. without deleting non-zero field elements")]
     DZKPMasks,
     #[error("Attempt to operate on zero records")]
@@ -95,6 +101,8 @@ pub enum Error {
     DPPaddingError(#[from] crate::protocol::ipa_prf::oprf_padding::insecure::DpError),
     #[error("Epsilon submitted to query is out of bounds")]
     EpsilonOutOfBounds,
+    #[error("Delta submitted to query is out of bounds")]
+    DeltaOutOfBounds,
     #[error("Missing total records in {0}")]
     MissingTotalRecords(String),
     #[error("Record ID {record_id:?} is out of range (expected {total_records} records)")]
@@ -106,6 +114,15 @@ pub enum Error {
     ShuffleValidationFailed(String),
     #[error("Duplicate bytes found after {0} checks")]
     DuplicateBytes(usize),
+    #[error("{count} reports appear more than once in the query input")]
+    DuplicateReports { count: usize, step: Gate },
+    #[error("{count} reports have already been used by earlier queries")]
+    ReplayedReports { count: usize, step: Gate },
+    #[error("Privacy budget of {report_collector} is exhausted for {site_epochs} site epochs")]
+    PrivacyBudgetExceeded {
+        report_collector: String,
+        site_epochs: usize,
+    },
 }
 
 impl Default for Error {
@@ -119,6 +136,19 @@ impl Error {
     pub fn path_parse_error(source: &str) -> Error {
         Error::ParseError(format!("unexpected value \"{source}\" in path").into())
     }
+
+    /// Returns the protocol step at which this error was raised, if it is known.
+    #[must_use]
+    pub fn step(&self) -> Option<&str> {
+        match self {
+            Self::MpcInfraError(e) => Some(e.gate().as_ref()),
+            Self::ShardInfraError(e) => Some(e.gate().as_ref()),
+            Self::DZKPValidationFailed { step }
+            | Self::DuplicateReports { step, .. }
+            | Self::ReplayedReports { step, .. } => Some(step.as_ref()),
+            _ => None,
+        }
+    }
 }
 
 impl From<std::num::ParseIntError> for Error {
@@ -8,7 +8,7 @@ use bitvec::{
     slice::Iter,
 };
 use generic_array::GenericArray;
-use typenum::{Unsigned, U12, U14, U18, U2, U32, U8};
+use typenum::{Unsigned, U12, U128, U14, U16, U18, U2, U20, U3, U32, U36, U8};
 
 use crate::{
     error::LengthError,
@@ -964,12 +964,24 @@ store_impl!(U12, 96);
 //impl store for U14
 store_impl!(U14, 112);
 
+//impl store for U16
+store_impl!(U16, 128);
+
 //impl store for U18
 store_impl!(U18, 144);
 
+//impl store for U20
+store_impl!(U20, 160);
+
 //impl store for U32
 store_impl!(U32, 256);
 
+//impl store for U36
+store_impl!(U36, 288);
+
+//impl store for U128
+store_impl!(U128, 1024);
+
 // These macro invocations define the supported boolean array sizes. Sizes ≤ 128 should use
 // `boolean_array_impl_small!` to get `u128` conversions and helpers. Larger sizes must
 // use `boolean_array_impl!`. At any size, you may need to add `store_impl!`, and for large
@@ -991,8 +1003,12 @@ boolean_array_impl_small!(boolean_array_32, BA32, 32, infallible);
 boolean_array_impl_small!(boolean_array_64, BA64, 64, infallible);
 boolean_array_impl_small!(boolean_array_96, BA96, 96, infallible);
 boolean_array_impl_small!(boolean_array_112, BA112, 112, infallible);
+boolean_array_impl_small!(boolean_array_128, BA128, 128, infallible);
 boolean_array_impl_large!(boolean_array_144, BA144, 144, infallible, U18, U2);
+boolean_array_impl_large!(boolean_array_160, BA160, 160, infallible, U20, U2);
 boolean_array_impl_large!(boolean_array_256, BA256, 256, infallible, U32, U2);
+boolean_array_impl_large!(boolean_array_288, BA288, 288, infallible, U36, U3);
+boolean_array_impl_large!(boolean_array_1024, BA1024, 1024, infallible, U128, U8);
 
 impl Vectorizable<256> for BA64 {
     type Array = StdArray<BA64, 256>;
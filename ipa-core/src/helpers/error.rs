@@ -5,7 +5,7 @@ use crate::{
         buffers::{DeserializeError, EndOfStreamError},
         ChannelId, TotalRecords, TransportIdentity,
     },
-    protocol::RecordId,
+    protocol::{Gate, RecordId},
 };
 
 /// An error raised by the IPA supporting infrastructure.
@@ -27,4 +27,21 @@ pub enum Error<I: TransportIdentity> {
         channel_id: ChannelId<I>,
         total_records: TotalRecords,
     },
+    #[error("Failed to send records over {channel_id:?}: {inner}")]
+    SendFailed {
+        channel_id: ChannelId<I>,
+        inner: String,
+    },
+}
+
+impl<I: TransportIdentity> Error<I> {
+    /// Returns the gate of the channel this error was raised on.
+    pub fn gate(&self) -> &Gate {
+        match self {
+            Self::EndOfStream { channel_id, .. }
+            | Self::DeserializeFailed { channel_id, .. }
+            | Self::TooManyRecords { channel_id, .. }
+            | Self::SendFailed { channel_id, .. } => &channel_id.gate,
+        }
+    }
 }
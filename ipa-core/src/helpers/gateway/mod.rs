@@ -152,6 +152,11 @@ impl Gateway {
         }
     }
 
+    #[must_use]
+    pub fn query_id(&self) -> QueryId {
+        self.query_id
+    }
+
     #[must_use]
     pub fn role(&self) -> Role {
         self.transports.mpc.identity()
@@ -338,13 +343,18 @@ impl GatewayConfig {
 mod tests {
     use std::{
         iter::{repeat, zip},
-        sync::Arc,
+        pin::pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
     };
 
     use futures::{
-        future::{join, try_join, try_join_all},
+        future::{join, select, try_join, try_join_all, Either},
         stream,
         stream::StreamExt,
+        TryStreamExt,
     };
     use proptest::proptest;
     use tokio::sync::Barrier;
@@ -356,18 +366,20 @@ mod tests {
         },
         helpers::{
             gateway::QueryConfig,
-            query::{QuerySize, QueryType},
+            in_memory_config::{DropConnection, InspectContext},
+            query::{QueryDeadlines, QuerySize, QueryType},
             ChannelId, Direction, GatewayConfig, MpcMessage, MpcReceivingEnd, Role, SendingEnd,
-            TotalRecords,
+            TotalRecords, RECORDS_ACK_INTERVAL,
         },
         protocol::{
             context::{Context, ShardedContext},
             Gate, RecordId,
         },
         secret_sharing::{
-            replicated::semi_honest::AdditiveShare, SharedValue, SharedValueArray, StdArray,
+            replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+            SharedValue, SharedValueArray, StdArray,
         },
-        seq_join::seq_join,
+        seq_join::{seq_join, SeqJoin},
         sharding::ShardConfiguration,
         test_executor::run,
         test_fixture::{Reconstruct, Runner, TestWorld, TestWorldConfig, WithShards},
@@ -628,6 +640,143 @@ mod tests {
         });
     }
 
+    /// Connections carrying helper streams are dropped twice while the protocol is running.
+    /// Senders must reconnect and resume them, so receivers get every record exactly once.
+    #[tokio::test]
+    async fn resumes_after_connection_drops() {
+        let chunks = Arc::new(AtomicUsize::new(0));
+        let (input, output) = exchange_records(100, 4, {
+            let chunks = Arc::clone(&chunks);
+            move |_ctx, _data| matches!(chunks.fetch_add(1, Ordering::Relaxed), 5 | 20)
+        })
+        .await;
+
+        assert!(chunks.load(Ordering::Relaxed) > 20);
+        assert_eq!(input, output);
+    }
+
+    /// Connections are dropped after receivers acknowledged some of the data, so senders
+    /// resume streams from the data that hasn't been acknowledged yet.
+    #[tokio::test]
+    async fn resumes_after_acknowledged_data() {
+        // each helper sends 4 bytes per record, twice as much as it takes to get acknowledged
+        let ack_interval = usize::try_from(RECORDS_ACK_INTERVAL).unwrap();
+        let total_records = ack_interval / 2;
+
+        let sent = Arc::new(AtomicUsize::new(0));
+        let (input, output) = exchange_records(total_records, 1024, {
+            let sent = Arc::clone(&sent);
+            move |_ctx, data| {
+                // drop once, after helpers sent 1.5 times the acknowledgement interval each
+                let before = sent.fetch_add(data.len(), Ordering::Relaxed);
+                let threshold = 3 * 3 * ack_interval / 2;
+                before < threshold && before + data.len() >= threshold
+            }
+        })
+        .await;
+
+        assert_eq!(input, output);
+    }
+
+    /// Connections that keep dropping are given up on after a few attempts. Sending records
+    /// through them fails, instead of bringing the helper down.
+    #[tokio::test]
+    async fn fails_after_repeated_connection_drops() {
+        let mut config = TestWorldConfig {
+            gateway_config: GatewayConfig {
+                active: 4.try_into().unwrap(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        config.stream_interceptor = DropConnection::new(|_ctx, _data| true);
+
+        let world = TestWorld::new_with(config);
+        let input = (0u128..100)
+            .map(Fp32BitPrime::truncate_from)
+            .collect::<Vec<_>>();
+        let results = world
+            .semi_honest(input.into_iter(), |ctx, shares| async move {
+                let ctx = ctx.narrow("fail").set_total_records(shares.len());
+                let role = ctx.role();
+                let send_channel = ctx.send_channel::<Fp32BitPrime>(role.peer(Direction::Right));
+                let recv_channel = ctx.recv_channel::<Fp32BitPrime>(role.peer(Direction::Left));
+                let send = async {
+                    for (i, share) in shares.iter().enumerate() {
+                        send_channel.send(RecordId::from(i), share.left()).await?;
+                    }
+                    Ok(())
+                };
+                // nothing is ever received, but the stream needs a reader to see it dropping
+                let receive = recv_channel.receive(RecordId::FIRST);
+                let result = match select(pin!(send), pin!(receive)).await {
+                    Either::Left((result, _)) => result,
+                    Either::Right((_, _)) => panic!("no records are expected to be received"),
+                };
+                result
+            })
+            .await;
+
+        for result in results {
+            assert!(
+                matches!(result, Err(crate::helpers::Error::SendFailed { .. })),
+                "{result:?}"
+            );
+        }
+    }
+
+    /// Each helper sends `total_records` records to its right peer, keeping `active` of them
+    /// in flight, while the connections are dropped before chunks for which `disconnect` returns `true`. Returns the input and
+    /// the records reconstructed from what was received.
+    async fn exchange_records<F>(
+        total_records: usize,
+        active: usize,
+        disconnect: F,
+    ) -> (Vec<Fp32BitPrime>, Vec<Fp32BitPrime>)
+    where
+        F: Fn(&InspectContext, &[u8]) -> bool + Send + Sync + 'static,
+    {
+        let mut config = TestWorldConfig {
+            gateway_config: GatewayConfig {
+                active: active.try_into().unwrap(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        config.stream_interceptor = DropConnection::new(disconnect);
+
+        let world = TestWorld::new_with(config);
+        let input = (0..total_records)
+            .map(|i| Fp32BitPrime::truncate_from(u128::try_from(i).unwrap()))
+            .collect::<Vec<_>>();
+        let output = world
+            .semi_honest(input.clone().into_iter(), |ctx, shares| async move {
+                let ctx = ctx.narrow("resume").set_total_records(shares.len());
+                let role = ctx.role();
+                let send_channel = ctx.send_channel::<Fp32BitPrime>(role.peer(Direction::Right));
+                let recv_channel = ctx.recv_channel::<Fp32BitPrime>(role.peer(Direction::Left));
+                seq_join(
+                    ctx.active_work(),
+                    stream::iter(shares).enumerate().map(|(i, share)| {
+                        let send_channel = &send_channel;
+                        let recv_channel = &recv_channel;
+                        async move {
+                            let record_id = RecordId::from(i);
+                            send_channel.send(record_id, share.left()).await?;
+                            let left = recv_channel.receive(record_id).await?;
+                            Ok::<_, crate::error::Error>(AdditiveShare::new(left, share.left()))
+                        }
+                    }),
+                )
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+            })
+            .await;
+
+        (input, output.reconstruct())
+    }
+
     macro_rules! send_recv_test {
         (
             message: $message:expr,
@@ -705,6 +854,7 @@ mod tests {
             size: QuerySize::try_from(5).unwrap(),
             field_type: FieldType::Fp31,
             query_type: QueryType::TestAddInPrimeField,
+            deadlines: QueryDeadlines::default(),
         });
         assert_eq!(8, config.active_work().get());
     }
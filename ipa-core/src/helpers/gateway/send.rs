@@ -1,22 +1,26 @@
 use std::{
     borrow::Borrow,
+    collections::VecDeque,
     fmt::Debug,
     marker::PhantomData,
     num::NonZeroUsize,
-    pin::Pin,
+    pin::{pin, Pin},
     task::{Context, Poll},
+    time::Duration,
 };
 
+use ::tokio::sync::{oneshot, watch};
 use dashmap::{mapref::entry::Entry, DashMap};
-use futures::Stream;
+use futures::{
+    future::{select, BoxFuture, Either},
+    ready, Stream,
+};
 use ipa_metrics::counter;
-#[cfg(all(test, feature = "shuttle"))]
-use shuttle::future as tokio;
 use typenum::Unsigned;
 
 use crate::{
     helpers::{
-        buffers::OrderingSender, routing::RouteId, ChannelId, Error, GatewayConfig, Message,
+        buffers::OrderingSender, ChannelId, Error, GatewayConfig, Message, RecordsRoute,
         TotalRecords, Transport, TransportIdentity,
     },
     protocol::{QueryId, RecordId},
@@ -28,6 +32,19 @@ use crate::{
     utils::non_zero_prev_power_of_two,
 };
 
+/// Shuttle has no timers, so reconnect backoff just yields to other tasks when running
+/// under it.
+#[cfg(all(test, feature = "shuttle"))]
+mod tokio {
+    pub use shuttle::future::*;
+
+    pub mod time {
+        pub async fn sleep(_: std::time::Duration) {
+            shuttle::future::yield_now().await;
+        }
+    }
+}
+
 /// Sending end of the gateway channel.
 pub struct SendingEnd<I: TransportIdentity, M> {
     sender_id: I,
@@ -45,10 +62,109 @@ pub(super) struct GatewaySender<I> {
     channel_id: ChannelId<I>,
     ordering_tx: OrderingSender,
     total_records: TotalRecords,
+    /// Set to the reason the records could not be delivered to the peer, once the channel
+    /// gives up on it.
+    failure: watch::Sender<Option<String>>,
+}
+
+/// Number of times the channel is (re)connected before giving up on it. Attempts are
+/// counted from the last time any new data was sent through the channel.
+const MAX_SEND_ATTEMPTS: u32 = 5;
+
+/// Delay before the first reconnect. It doubles after every failed attempt.
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Maximum number of unacknowledged bytes kept for replay. Once the replay buffer reaches
+/// this size, no new data is taken from the sender until the receiver acknowledges some of
+/// it, which pushes back on the records sent through the channel. It must be comfortably
+/// larger than [`RECORDS_ACK_INTERVAL`], as receivers acknowledge data in steps of that size.
+///
+/// [`RECORDS_ACK_INTERVAL`]: crate::helpers::RECORDS_ACK_INTERVAL
+const MAX_REPLAY_SIZE: u64 = 4 * crate::helpers::RECORDS_ACK_INTERVAL;
+
+/// Data that has been handed to the transport, but hasn't been acknowledged by the receiver
+/// yet. Receivers acknowledge the data every [`RECORDS_ACK_INTERVAL`] bytes, and the sender
+/// stops taking new data once [`MAX_REPLAY_SIZE`] bytes are waiting for an acknowledgement.
+///
+/// [`RECORDS_ACK_INTERVAL`]: crate::helpers::RECORDS_ACK_INTERVAL
+struct ReplayBuffer {
+    chunks: VecDeque<Vec<u8>>,
+    /// Offset of the first byte in `chunks` from the start of the stream.
+    offset: u64,
+    /// Total size of `chunks`, in bytes.
+    size: usize,
+}
+
+impl ReplayBuffer {
+    fn new() -> Self {
+        Self {
+            chunks: VecDeque::new(),
+            offset: 0,
+            size: 0,
+        }
+    }
+
+    /// Number of bytes taken from the sender so far.
+    fn end(&self) -> u64 {
+        self.offset + self.size as u64
+    }
+
+    /// Whether the buffer has reached [`MAX_REPLAY_SIZE`] and must not take new data.
+    fn is_full(&self) -> bool {
+        self.size as u64 >= MAX_REPLAY_SIZE
+    }
+
+    /// Keeps `chunk` for replay, until the receiver acknowledges it.
+    fn push(&mut self, chunk: Vec<u8>) {
+        self.size += chunk.len();
+        self.chunks.push_back(chunk);
+    }
+
+    /// Drops the chunks the receiver has acknowledged, looking at the first `count` chunks
+    /// only. Returns the number of chunks dropped.
+    fn trim(&mut self, acked: u64, count: usize) -> usize {
+        let mut trimmed = 0;
+        while trimmed < count {
+            match self.chunks.front() {
+                Some(chunk) if self.offset + chunk.len() as u64 <= acked => {
+                    self.offset += chunk.len() as u64;
+                    self.size -= chunk.len();
+                    self.chunks.pop_front();
+                    trimmed += 1;
+                }
+                _ => break,
+            }
+        }
+
+        trimmed
+    }
 }
 
+/// Stream of data sent through a single connection to the peer. It starts with the data
+/// kept for replay, followed by new data from the sender.
+///
+/// Once the stream is dropped, it reports back whether it has been sent completely. If not,
+/// it hands over the replay buffer, so the next connection can resume from it.
 struct GatewaySendStream<I> {
     inner: Arc<GatewaySender<I>>,
+    replay: Option<ReplayBuffer>,
+    /// Offset up to which the receiver has acknowledged the stream.
+    acks: watch::Receiver<u64>,
+    /// Resolves once the receiver acknowledges more data, while the replay buffer is full.
+    /// Yields `false` if acknowledgements for this stream will never come.
+    ack_changed: Option<BoxFuture<'static, bool>>,
+    /// Index of the next chunk in the replay buffer to send.
+    next: usize,
+    done: Option<oneshot::Sender<Result<(), ReplayBuffer>>>,
+}
+
+impl<I> Drop for GatewaySendStream<I> {
+    fn drop(&mut self) {
+        if let (Some(done), Some(replay)) = (self.done.take(), self.replay.take()) {
+            // the task waiting for this stream may be gone, if the query is aborted
+            let _ = done.send(Err(replay));
+        }
+    }
 }
 
 /// Configuration for each [`GatewaySender`]. All values stored here
@@ -82,6 +198,7 @@ impl<I: TransportIdentity> GatewaySender<I> {
             channel_id,
             ordering_tx: tx,
             total_records,
+            failure: watch::Sender::new(None),
         }
     }
 
@@ -104,15 +221,38 @@ impl<I: TransportIdentity> GatewaySender<I> {
             }
         }
 
-        // TODO: make OrderingSender::send fallible
         // TODO: test channel close
         let i = usize::from(record_id);
-        self.ordering_tx.send(i, msg).await;
-        if self.total_records.is_last(record_id) {
-            self.ordering_tx.close(i + 1).await;
+        let send = async move {
+            self.ordering_tx.send(i, msg).await;
+            if self.total_records.is_last(record_id) {
+                self.ordering_tx.close(i + 1).await;
+            }
+        };
+        let mut failure = self.failure.subscribe();
+        let failed = async move {
+            // the sender lives as long as `self`, so this never returns an error
+            let failure = failure.wait_for(Option::is_some).await.unwrap();
+            failure.clone().unwrap()
+        };
+
+        match select(pin!(send), pin!(failed)).await {
+            Either::Left(((), _)) => Ok(()),
+            Either::Right((reason, _)) => Err(Error::SendFailed {
+                channel_id: self.channel_id.clone(),
+                inner: reason,
+            }),
         }
+    }
 
-        Ok(())
+    /// Gives up on delivering the records to the peer. Records sent through this channel
+    /// after that fail with the returned error.
+    fn fail(&self, reason: String) -> Error<I> {
+        self.failure.send_replace(Some(reason.clone()));
+        Error::SendFailed {
+            channel_id: self.channel_id.clone(),
+            inner: reason,
+        }
     }
 
     #[cfg(feature = "stall-detection")]
@@ -211,17 +351,12 @@ impl<I: TransportIdentity> GatewaySenders<I> {
                 entry.insert(Arc::clone(&sender));
 
                 tokio::spawn({
-                    let ChannelId { peer, gate } = channel_id.clone();
+                    let sender = Arc::clone(&sender);
                     let transport = transport.clone();
-                    let stream = GatewaySendStream {
-                        inner: Arc::clone(&sender),
-                    };
                     async move {
-                        // TODO(651): In the HTTP case we probably need more robust error handling here.
-                        transport
-                            .send(peer, (RouteId::Records, query_id, gate), stream)
-                            .await
-                            .expect("{channel_id:?} receiving end should be accepted by transport");
+                        if let Err(e) = Self::send_with_retries(sender, transport, query_id).await {
+                            tracing::error!("{e}");
+                        }
                     }
                 });
 
@@ -230,6 +365,79 @@ impl<I: TransportIdentity> GatewaySenders<I> {
         }
     }
 
+    /// Sends all data from `sender` to its peer. If the connection drops before the data is
+    /// fully sent, it is re-established with backoff and the stream is resumed from the data
+    /// the peer hasn't acknowledged yet.
+    ///
+    /// ## Errors
+    /// If the stream can't be sent after [`MAX_SEND_ATTEMPTS`] attempts, or if the peer
+    /// rejects it. Records sent through `sender` after that fail with the same error.
+    ///
+    /// ## Panics
+    /// If the transport leaks the stream, instead of dropping it.
+    async fn send_with_retries<T: Transport<Identity = I>>(
+        sender: Arc<GatewaySender<I>>,
+        transport: T,
+        query_id: QueryId,
+    ) -> Result<(), Error<I>> {
+        let ChannelId { peer, gate } = sender.channel_id.clone();
+        let acks = transport.acks(peer, (query_id, gate.clone()));
+        let mut replay = ReplayBuffer::new();
+        let mut resume_from = None;
+        let mut backoff = INITIAL_RECONNECT_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            let (done_tx, done_rx) = oneshot::channel();
+            let route = RecordsRoute {
+                query_id,
+                gate: gate.clone(),
+                resume_from,
+            };
+            let end = replay.end();
+            let stream = GatewaySendStream {
+                inner: Arc::clone(&sender),
+                replay: Some(replay),
+                acks: acks.clone(),
+                ack_changed: None,
+                next: 0,
+                done: Some(done_tx),
+            };
+            let sent = transport.send(peer, route, stream).await;
+            // the stream always reports back, unless it has been leaked by the transport
+            let outcome = done_rx.await.unwrap_or_else(|_| {
+                panic!("{:?} send stream has not been dropped", sender.channel_id)
+            });
+            match outcome {
+                Ok(()) => {
+                    return sent.map_err(|e| {
+                        sender.fail(format!("receiving end was rejected by transport: {e:?}"))
+                    });
+                }
+                Err(unsent) => replay = unsent,
+            }
+
+            if replay.end() > end {
+                attempt = 1;
+                backoff = INITIAL_RECONNECT_BACKOFF;
+            }
+            if attempt >= MAX_SEND_ATTEMPTS {
+                return Err(sender.fail(format!(
+                    "connection dropped {attempt} times in a row, last error: {sent:?}"
+                )));
+            }
+            tracing::warn!(
+                "{:?} connection dropped after {} bytes, resuming from {} in {backoff:?}: {sent:?}",
+                sender.channel_id,
+                replay.end(),
+                replay.offset
+            );
+            resume_from = Some(replay.offset);
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
     fn new_sender(config: &SendChannelConfig, channel_id: ChannelId<I>) -> Arc<GatewaySender<I>> {
         Arc::new(GatewaySender::new(
             channel_id,
@@ -244,7 +452,44 @@ impl<I: Debug> Stream for GatewaySendStream<I> {
 
     #[tracing::instrument(level = "trace", name = "send_stream", skip_all, fields(to = ?self.inner.channel_id.peer, gate = ?self.inner.channel_id.gate))]
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        Pin::get_mut(self).inner.ordering_tx.take_next(cx)
+        let this = Pin::get_mut(self);
+        loop {
+            let Some(replay) = this.replay.as_mut() else {
+                return Poll::Ready(None);
+            };
+            this.next -= replay.trim(*this.acks.borrow_and_update(), this.next);
+            if let Some(chunk) = replay.chunks.get(this.next) {
+                this.next += 1;
+                return Poll::Ready(Some(chunk.clone()));
+            }
+
+            if replay.is_full() {
+                let ack_changed = this.ack_changed.get_or_insert_with(|| {
+                    let mut acks = this.acks.clone();
+                    Box::pin(async move { acks.changed().await.is_ok() })
+                });
+                let open = ready!(ack_changed.as_mut().poll(cx));
+                this.ack_changed = None;
+                if !open {
+                    // the query is being torn down, there is no point in waiting any longer
+                    tracing::warn!("no acknowledgements will be received, stopping the stream");
+                    return Poll::Ready(None);
+                }
+                continue;
+            }
+
+            return if let Some(chunk) = ready!(this.inner.ordering_tx.take_next(cx)) {
+                replay.push(chunk.clone());
+                this.next = replay.chunks.len();
+                Poll::Ready(Some(chunk))
+            } else {
+                this.replay = None;
+                if let Some(done) = this.done.take() {
+                    let _ = done.send(Ok(()));
+                }
+                Poll::Ready(None)
+            };
+        }
     }
 }
 
@@ -299,7 +544,9 @@ impl SendChannelConfig {
 mod test {
     use std::num::NonZeroUsize;
 
+    use futures::{FutureExt, StreamExt};
     use proptest::proptest;
+    use tokio::sync::{oneshot, watch};
     use typenum::Unsigned;
 
     use crate::{
@@ -307,8 +554,16 @@ mod test {
             boolean_array::{BA16, BA20, BA256, BA3, BA32, BA7},
             Serializable,
         },
-        helpers::{gateway::send::SendChannelConfig, GatewayConfig, TotalRecords},
+        helpers::{
+            buffers::OrderingSender,
+            gateway::send::{
+                GatewaySendStream, GatewaySender, ReplayBuffer, SendChannelConfig, MAX_REPLAY_SIZE,
+            },
+            ChannelId, GatewayConfig, Role, TotalRecords,
+        },
+        protocol::{Gate, RecordId},
         secret_sharing::SharedValue,
+        sync::Arc,
     };
 
     impl Default for SendChannelConfig {
@@ -434,6 +689,85 @@ mod test {
         assert_eq!(3, send_config::<BA20, 8, 1>(50.into()).read_size.get());
     }
 
+    #[test]
+    fn replay_keeps_unacknowledged_data() {
+        let mut replay = ReplayBuffer::new();
+        for _ in 0..4 {
+            replay.push(vec![0; 1 << 20]);
+        }
+        assert_eq!(4 << 20, replay.end());
+        assert_eq!(0, replay.offset);
+
+        // nothing is acknowledged, nothing is dropped
+        assert_eq!(0, replay.trim(0, 4));
+        // only whole chunks are dropped
+        assert_eq!(1, replay.trim((2 << 20) - 1, 4));
+        assert_eq!(1 << 20, replay.offset);
+        assert_eq!(3, replay.chunks.len());
+        // chunks that haven't been resent yet are kept
+        assert_eq!(1, replay.trim(4 << 20, 1));
+        assert_eq!(2 << 20, replay.offset);
+        assert_eq!(4 << 20, replay.end());
+    }
+
+    /// Once the replay buffer is full, no new data is taken from the sender until the
+    /// receiver acknowledges some of it.
+    #[tokio::test]
+    async fn replay_is_capped() {
+        const CHUNK_SIZE: usize = 1 << 15;
+        let record_size = <BA256 as Serializable>::Size::USIZE;
+        let total_records = 2 * usize::try_from(MAX_REPLAY_SIZE).unwrap() / record_size;
+        let sender = Arc::new(GatewaySender::new(
+            ChannelId::new(Role::H2, Gate::default()),
+            OrderingSender::new(
+                NonZeroUsize::new(CHUNK_SIZE).unwrap(),
+                NonZeroUsize::new(record_size).unwrap(),
+                NonZeroUsize::new(CHUNK_SIZE).unwrap(),
+            ),
+            TotalRecords::specified(total_records).unwrap(),
+        ));
+        let (ack_tx, ack_rx) = watch::channel(0);
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut stream = GatewaySendStream {
+            inner: Arc::clone(&sender),
+            replay: Some(ReplayBuffer::new()),
+            acks: ack_rx,
+            ack_changed: None,
+            next: 0,
+            done: Some(done_tx),
+        };
+        let send = tokio::spawn({
+            let sender = Arc::clone(&sender);
+            async move {
+                for i in 0..total_records {
+                    sender
+                        .send::<BA256, _>(RecordId::from(i), BA256::ZERO)
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        let mut taken = 0;
+        while taken < MAX_REPLAY_SIZE {
+            taken += stream.next().await.unwrap().len() as u64;
+        }
+        // give the sender a chance to fill the ordering buffer
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(stream.next().now_or_never().is_none());
+
+        ack_tx.send(taken).unwrap();
+        while let Some(chunk) = stream.next().await {
+            taken += chunk.len() as u64;
+            ack_tx.send(taken).unwrap();
+        }
+        send.await.unwrap();
+        assert_eq!((total_records * record_size) as u64, taken);
+        assert!(done_rx.await.unwrap().is_ok());
+    }
+
     fn ensure_config(
         total_records: Option<usize>,
         active: usize,
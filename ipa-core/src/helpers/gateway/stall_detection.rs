@@ -94,6 +94,9 @@ mod gateway {
         delegate! {
             to self.inner().gateway {
 
+                #[inline]
+                pub fn query_id(&self) -> QueryId;
+
                 #[inline]
                 pub fn role(&self) -> Role;
 
@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 use futures::Stream;
+use tokio::sync::watch;
 
 use crate::{
     helpers::{
@@ -91,4 +92,12 @@ impl Transport for RoleResolvingTransport {
 
         self.inner.receive(origin_helper, route)
     }
+
+    fn acks<R: RouteParams<NoResourceIdentifier, QueryId, Gate>>(
+        &self,
+        to: Role,
+        route: R,
+    ) -> watch::Receiver<u64> {
+        self.inner.acks(self.roles.identity(to), route)
+    }
 }
@@ -110,6 +110,23 @@ where
     hash
 }
 
+/// Computes Hash of a sequence of byte strings of any length, as they are added.
+#[derive(Default)]
+pub struct BytesHasher(Sha256);
+
+impl BytesHasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        // the length prefix keeps the boundaries between the strings
+        self.0.update((bytes.len() as u64).to_le_bytes());
+        self.0.update(bytes);
+    }
+
+    #[must_use]
+    pub fn finalize(self) -> Hash {
+        Hash(self.0.finalize())
+    }
+}
+
 /// This function takes two hashes, combines them together and returns a single field element.
 ///
 /// Its use is tailored to malicious security requirements where the random challenge point `r`
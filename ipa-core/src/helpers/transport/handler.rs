@@ -174,6 +174,8 @@ pub enum Error {
     DeserializationFailure(#[from] serde_json::Error),
     #[error("MalformedRequest: {0}")]
     BadRequest(BoxError),
+    #[error("Metrics are not available: {0}")]
+    MetricsUnavailable(String),
 }
 
 /// Trait for custom-handling different request types made against MPC helper parties.
@@ -41,6 +41,13 @@ pub trait StreamInterceptor: Send + Sync {
     /// at the transport layer, like checksumming, share consistency
     /// checks, etc.
     fn peek(&self, ctx: &Self::Context, data: &mut Vec<u8>);
+
+    /// Decides whether the connection carrying the stream drops before `data` is delivered.
+    /// The receiver observes an error instead of `data` and the stream ends, so the sender
+    /// must reconnect to deliver the rest of it.
+    fn disconnect(&self, _ctx: &Self::Context, _data: &[u8]) -> bool {
+        false
+    }
 }
 
 impl<F: Fn(&InspectContext, &mut Vec<u8>) + Send + Sync + 'static> StreamInterceptor for F {
@@ -152,6 +159,30 @@ impl<F: Fn(&MaliciousHelperContext, &mut Vec<u8>) + Send + Sync> MaliciousHelper
     }
 }
 
+/// Simulates network failures by dropping connections between helpers or shards.
+/// The connection carrying a stream is dropped right before the chunk for which
+/// the provided closure returns `true`. Data is not modified.
+#[derive(Debug)]
+pub struct DropConnection<F> {
+    inner: F,
+}
+
+impl<F: Fn(&InspectContext, &[u8]) -> bool + Send + Sync> DropConnection<F> {
+    pub fn new(disconnect: F) -> Arc<Self> {
+        Arc::new(Self { inner: disconnect })
+    }
+}
+
+impl<F: Fn(&InspectContext, &[u8]) -> bool + Send + Sync> StreamInterceptor for DropConnection<F> {
+    type Context = InspectContext;
+
+    fn peek(&self, _ctx: &Self::Context, _data: &mut Vec<u8>) {}
+
+    fn disconnect(&self, ctx: &Self::Context, data: &[u8]) -> bool {
+        (self.inner)(ctx, data)
+    }
+}
+
 /// Special contexts for stream inspectors
 /// created with [`MaliciousHelper`].
 /// It provides convenient access to the
@@ -11,6 +11,7 @@ use crate::{
         in_memory_config::DynStreamInterceptor, transport::in_memory::config::passthrough,
         HandlerRef, HelperIdentity,
     },
+    protocol::QueryId,
     sharding::ShardIndex,
     sync::{Arc, Weak},
 };
@@ -94,4 +95,11 @@ impl InMemoryMpcNetwork {
             t.reset();
         }
     }
+
+    /// Reset the state associated with the given query on all transports.
+    pub fn reset_query(&self, query_id: QueryId) {
+        for t in &self.transports {
+            t.reset_query(query_id);
+        }
+    }
 }
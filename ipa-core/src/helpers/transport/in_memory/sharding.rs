@@ -4,6 +4,7 @@ use crate::{
         transport::in_memory::transport::{InMemoryTransport, Setup, TransportConfigBuilder},
         HandlerBox, HelperIdentity, RequestHandler,
     },
+    protocol::QueryId,
     sharding::ShardIndex,
     sync::{Arc, Weak},
 };
@@ -123,6 +124,14 @@ impl InMemoryShardNetwork {
             }
         }
     }
+
+    pub fn reset_query(&self, query_id: QueryId) {
+        for helper in &self.shard_network {
+            for shard in helper {
+                shard.reset_query(query_id);
+            }
+        }
+    }
 }
 
 #[cfg(all(test, unit_test))]
@@ -169,7 +178,7 @@ mod tests {
                         .transport(identity, a)
                         .send(
                             b,
-                            (RouteId::Records, QueryId, Gate::default()),
+                            (RouteId::Records, QueryId::from(0), Gate::default()),
                             ReceiverStream::new(rx),
                         )
                         .await
@@ -180,7 +189,7 @@ mod tests {
                 for (a, b) in shard_pairs(shard_count) {
                     sum += shard_network
                         .transport(identity, a)
-                        .receive(b, (QueryId, Gate::default()))
+                        .receive(b, (QueryId::from(0), Gate::default()))
                         .into_bytes_stream()
                         .collect::<Vec<_>>()
                         .await
@@ -219,7 +228,7 @@ mod tests {
                 .transport(HelperIdentity::ONE, src_shard)
                 .send(
                     dst_shard,
-                    (RouteId::Records, QueryId, Gate::default()),
+                    (RouteId::Records, QueryId::from(0), Gate::default()),
                     ReceiverStream::new(rx),
                 )
                 .await
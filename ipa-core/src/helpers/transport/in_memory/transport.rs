@@ -8,7 +8,7 @@ use std::{
 
 use ::tokio::sync::{
     mpsc::{channel, Receiver, Sender},
-    oneshot,
+    oneshot, watch,
 };
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -24,8 +24,8 @@ use crate::{
         in_memory_config::{self, DynStreamInterceptor},
         transport::routing::{Addr, RouteId},
         ApiError, BodyStream, HandlerRef, HelperIdentity, HelperResponse, NoResourceIdentifier,
-        QueryIdBinding, ReceiveRecords, RequestHandler, RouteParams, StepBinding, StreamCollection,
-        Transport, TransportIdentity,
+        QueryIdBinding, ReceiveRecords, RecordsAckRoute, RequestHandler, RouteParams, StepBinding,
+        StreamAcks, StreamCollection, Transport, TransportIdentity,
     },
     protocol::{Gate, QueryId},
     sharding::ShardIndex,
@@ -68,6 +68,7 @@ pub struct InMemoryTransport<I> {
     identity: I,
     connections: HashMap<I, ConnectionTx<I>>,
     record_streams: StreamCollection<I, InMemoryStream>,
+    record_acks: StreamAcks<I>,
     config: TransportConfig,
 }
 
@@ -82,6 +83,7 @@ impl<I: TransportIdentity> InMemoryTransport<I> {
             identity,
             connections,
             record_streams: StreamCollection::default(),
+            record_acks: StreamAcks::default(),
             config,
         }
     }
@@ -99,18 +101,36 @@ impl<I: TransportIdentity> InMemoryTransport<I> {
         tokio::spawn(
             {
                 let streams = self.record_streams.clone();
+                let acks = self.record_acks.clone();
                 async move {
                     while let Some((addr, stream, ack)) = rx.recv().await {
                         tracing::trace!("received new message: {addr:?}");
 
                         let result = match addr.route {
-                            RouteId::Records => {
-                                let query_id = addr.query_id.unwrap();
-                                let gate = addr.gate.unwrap();
-                                let from = addr.origin.unwrap();
-                                streams.add_stream((query_id, from, gate), stream);
-                                Ok(HelperResponse::ok())
-                            }
+                            RouteId::Records => addr.records_offset().map_or_else(
+                                |e| Err(ApiError::DeserializationFailure(e)),
+                                |resume_from| {
+                                    let query_id = addr.query_id.unwrap();
+                                    let gate = addr.gate.unwrap();
+                                    let from = addr.origin.unwrap();
+                                    streams.add_stream_at(
+                                        (query_id, from, gate),
+                                        resume_from,
+                                        stream,
+                                    );
+                                    Ok(HelperResponse::ok())
+                                },
+                            ),
+                            RouteId::RecordsAck => addr.records_offset().map_or_else(
+                                |e| Err(ApiError::DeserializationFailure(e)),
+                                |offset| {
+                                    let query_id = addr.query_id.unwrap();
+                                    let gate = addr.gate.unwrap();
+                                    let from = addr.origin.unwrap();
+                                    acks.ack(&(query_id, from, gate), offset.unwrap_or_default());
+                                    Ok(HelperResponse::ok())
+                                },
+                            ),
                             RouteId::ReceiveQuery
                             | RouteId::PrepareQuery
                             | RouteId::QueryInput
@@ -149,6 +169,14 @@ impl<I: TransportIdentity> InMemoryTransport<I> {
     /// Resets this transport, making it forget its state and be ready for processing another query.
     pub fn reset(&self) {
         self.record_streams.clear();
+        self.record_acks.clear();
+    }
+
+    /// Makes this transport forget all streams associated with the given query. Other queries
+    /// that may be running at the same time are not affected.
+    pub fn reset_query(&self, query_id: QueryId) {
+        self.record_streams.clear_query(query_id);
+        self.record_acks.clear_query(query_id);
     }
 }
 
@@ -199,12 +227,22 @@ impl<I: TransportIdentity> Transport for Weak<InMemoryTransport<I>> {
         channel
             .send((
                 addr,
-                InMemoryStream::wrap(data.map({
-                    move |mut chunk| {
+                InMemoryStream::wrap(data.scan(false, {
+                    move |disconnected, mut chunk| {
+                        if *disconnected {
+                            return futures::future::ready(None);
+                        }
                         if let Some(ref context) = context {
+                            if this.config.stream_interceptor.disconnect(context, &chunk) {
+                                *disconnected = true;
+                                return futures::future::ready(Some(Err(io::Error::from(
+                                    io::ErrorKind::ConnectionReset,
+                                )
+                                .into())));
+                            }
                             this.config.stream_interceptor.peek(context, &mut chunk);
                         }
-                        Ok(Bytes::from(chunk))
+                        futures::future::ready(Some(Ok(Bytes::from(chunk))))
                     }
                 })),
                 ack_tx,
@@ -233,11 +271,41 @@ impl<I: TransportIdentity> Transport for Weak<InMemoryTransport<I>> {
         from: I,
         route: R,
     ) -> Self::RecordsStream {
+        let (query_id, gate) = (route.query_id(), route.gate());
+        let ack = {
+            let transport = Weak::clone(self);
+            let gate = gate.clone();
+            move |offset| {
+                let transport = Weak::clone(&transport);
+                let route = RecordsAckRoute {
+                    query_id,
+                    gate: gate.clone(),
+                    offset,
+                };
+                tokio::spawn(async move {
+                    if let Err(e) = transport.send(from, route, futures::stream::empty()).await {
+                        tracing::warn!("failed to acknowledge records up to {offset}: {e:?}");
+                    }
+                });
+            }
+        };
         ReceiveRecords::new(
-            (route.query_id(), from, route.gate()),
+            (query_id, from, gate),
             self.upgrade().unwrap().record_streams.clone(),
+            Box::new(ack),
         )
     }
+
+    fn acks<R: RouteParams<NoResourceIdentifier, QueryId, Gate>>(
+        &self,
+        to: I,
+        route: R,
+    ) -> watch::Receiver<u64> {
+        self.upgrade()
+            .unwrap()
+            .record_acks
+            .subscribe((route.query_id(), to, route.gate()))
+    }
 }
 
 /// Convenience struct to support heterogeneous in-memory streams
@@ -422,9 +490,10 @@ mod tests {
                     .send(query_config)
                     .unwrap();
                 Ok(HelperResponse::from(PrepareQuery {
-                    query_id: QueryId,
+                    query_id: QueryId::from(0),
                     config: query_config,
                     roles: RoleAssignment::try_from([Role::H1, Role::H2, Role::H3]).unwrap(),
+                    report_collector: None,
                 }))
             }
         });
@@ -449,7 +518,7 @@ mod tests {
         let expected = vec![vec![1], vec![2]];
 
         let mut stream = transport
-            .receive(HelperIdentity::TWO, (QueryId, Gate::from(STEP)))
+            .receive(HelperIdentity::TWO, (QueryId::from(0), Gate::from(STEP)))
             .into_bytes_stream();
 
         // make sure it is not ready as it hasn't received the records stream yet.
@@ -459,7 +528,7 @@ mod tests {
         ));
         send_and_ack(
             &tx,
-            Addr::records(HelperIdentity::TWO, QueryId, Gate::from(STEP)),
+            Addr::records(HelperIdentity::TWO, QueryId::from(0), Gate::from(STEP)),
             stream::iter(expected.clone()),
         )
         .await;
@@ -474,13 +543,13 @@ mod tests {
 
         send_and_ack(
             &tx,
-            Addr::records(HelperIdentity::TWO, QueryId, Gate::from(STEP)),
+            Addr::records(HelperIdentity::TWO, QueryId::from(0), Gate::from(STEP)),
             stream::iter(expected.clone()),
         )
         .await;
 
         let stream = Arc::downgrade(&transport)
-            .receive(HelperIdentity::TWO, (QueryId, Gate::from(STEP)))
+            .receive(HelperIdentity::TWO, (QueryId::from(0), Gate::from(STEP)))
             .into_bytes_stream();
 
         assert_eq!(expected, stream.collect::<Vec<_>>().await);
@@ -501,7 +570,7 @@ mod tests {
             let gate = Gate::from(STEP);
 
             let mut recv = to_transport
-                .receive(from, (QueryId, gate.clone()))
+                .receive(from, (QueryId::from(0), gate.clone()))
                 .into_bytes_stream();
             assert!(matches!(
                 poll_immediate(&mut recv).next().await,
@@ -509,7 +578,11 @@ mod tests {
             ));
 
             from_transport
-                .send(to, (RouteId::Records, QueryId, gate.clone()), stream)
+                .send(
+                    to,
+                    (RouteId::Records, QueryId::from(0), gate.clone()),
+                    stream,
+                )
                 .await
                 .unwrap();
             stream_tx.send(vec![1, 2, 3]).await.unwrap();
@@ -555,11 +628,11 @@ mod tests {
         let transport = Arc::downgrade(&owned_transport);
 
         let mut recv_stream = transport
-            .receive(HelperIdentity::TWO, (QueryId, gate.clone()))
+            .receive(HelperIdentity::TWO, (QueryId::from(0), gate.clone()))
             .into_bytes_stream();
         send_and_ack(
             &tx,
-            Addr::records(HelperIdentity::TWO, QueryId, gate.clone()),
+            Addr::records(HelperIdentity::TWO, QueryId::from(0), gate.clone()),
             stream,
         )
         .await;
@@ -568,7 +641,7 @@ mod tests {
         assert_eq!(vec![4, 5, 6], recv_stream.next().await.unwrap());
 
         // the same stream cannot be received again
-        let mut err_recv = transport.receive(HelperIdentity::TWO, (QueryId, gate.clone()));
+        let mut err_recv = transport.receive(HelperIdentity::TWO, (QueryId::from(0), gate.clone()));
         let err = AssertUnwindSafe(err_recv.next()).catch_unwind().await;
         assert_eq!(
             Some(true),
@@ -579,7 +652,7 @@ mod tests {
 
         // even after the input stream is closed
         drop(stream_tx);
-        let mut err_recv = transport.receive(HelperIdentity::TWO, (QueryId, gate.clone()));
+        let mut err_recv = transport.receive(HelperIdentity::TWO, (QueryId::from(0), gate.clone()));
         let err = AssertUnwindSafe(err_recv.next()).catch_unwind().await;
         assert_eq!(
             Some(true),
@@ -606,13 +679,13 @@ mod tests {
         transport1
             .send(
                 HelperIdentity::TWO,
-                (RouteId::Records, QueryId, gate.clone()),
+                (RouteId::Records, QueryId::from(0), gate.clone()),
                 rx,
             )
             .await
             .unwrap();
         let mut recv = transport2
-            .receive(HelperIdentity::ONE, (QueryId, gate))
+            .receive(HelperIdentity::ONE, (QueryId::from(0), gate))
             .into_bytes_stream();
 
         tx.send(0, Fp31::try_from(0_u128).unwrap()).await;
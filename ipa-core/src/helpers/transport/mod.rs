@@ -6,6 +6,7 @@ use std::{
 
 use async_trait::async_trait;
 use futures::{stream::FuturesUnordered, FutureExt, Stream, StreamExt};
+use tokio::sync::watch;
 
 #[cfg(feature = "in-memory-infra")]
 use crate::helpers::in_memory_config::InspectContext;
@@ -31,11 +32,11 @@ pub use in_memory::{
     config, InMemoryMpcNetwork, InMemoryShardNetwork, InMemoryTransport, InMemoryTransportError,
 };
 use ipa_metrics::LabelValue;
-pub use receive::{LogErrors, ReceiveRecords};
+pub use receive::{AckRecords, LogErrors, ReceiveRecords, RECORDS_ACK_INTERVAL};
 #[cfg(feature = "web-app")]
 pub use stream::WrappedAxumBodyStream;
 pub use stream::{
-    BodyStream, BytesStream, LengthDelimitedStream, RecordsStream, SingleRecordStream,
+    BodyStream, BytesStream, LengthDelimitedStream, RecordsStream, SingleRecordStream, StreamAcks,
     StreamCollection, StreamKey, WrappedBoxBodyStream,
 };
 
@@ -289,6 +290,66 @@ impl RouteParams<RouteId, QueryId, Gate> for (RouteId, QueryId, Gate) {
     }
 }
 
+/// Route for the records stream of the given gate. Senders that resume the stream after the
+/// connection carrying it dropped set `resume_from` to the offset of the first byte they send.
+#[derive(Debug, Clone)]
+pub struct RecordsRoute {
+    pub query_id: QueryId,
+    pub gate: Gate,
+    pub resume_from: Option<u64>,
+}
+
+impl RouteParams<RouteId, QueryId, Gate> for RecordsRoute {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        RouteId::Records
+    }
+
+    fn query_id(&self) -> QueryId {
+        self.query_id
+    }
+
+    fn gate(&self) -> Gate {
+        self.gate.clone()
+    }
+
+    fn extra(&self) -> Self::Params {
+        self.resume_from
+            .map(|offset| serde_json::to_string(&offset).unwrap())
+            .unwrap_or_default()
+    }
+}
+
+/// Route for the acknowledgement of the records stream of the given gate. Receivers send it
+/// to tell the sender that all data before `offset` has been received.
+#[derive(Debug, Clone)]
+pub struct RecordsAckRoute {
+    pub query_id: QueryId,
+    pub gate: Gate,
+    pub offset: u64,
+}
+
+impl RouteParams<RouteId, QueryId, Gate> for RecordsAckRoute {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        RouteId::RecordsAck
+    }
+
+    fn query_id(&self) -> QueryId {
+        self.query_id
+    }
+
+    fn gate(&self) -> Gate {
+        self.gate.clone()
+    }
+
+    fn extra(&self) -> Self::Params {
+        serde_json::to_string(&self.offset).unwrap()
+    }
+}
+
 impl RouteParams<RouteId, QueryId, NoStep> for (RouteId, QueryId) {
     type Params = &'static str;
 
@@ -366,6 +427,15 @@ pub trait Transport: Clone + Send + Sync + 'static {
         route: R,
     ) -> Self::RecordsStream;
 
+    /// Returns the offset up to which `to` has acknowledged receiving the records stream sent
+    /// to it for the specific query and step. Senders keep the data past this offset, to
+    /// replay it if the connection carrying the stream drops.
+    fn acks<R: RouteParams<NoResourceIdentifier, QueryId, Gate>>(
+        &self,
+        to: Self::Identity,
+        route: R,
+    ) -> watch::Receiver<u64>;
+
     /// Broadcasts a message to all peers, excluding this instance, collecting all failures and
     /// successes. This method waits for all responses and returns only when all peers responded.
     async fn broadcast<Q, S, R>(
@@ -1,17 +1,77 @@
+use std::{
+    fmt::Display,
+    num::{NonZeroU32, ParseIntError},
+    str::FromStr,
+};
+
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    helpers::query::{DpMechanism, HistogramOutput, QueryConfigError},
+    protocol::ipa_prf::oprf_padding::{AggregationPadding, OPRFPadding, PaddingParameters},
+};
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
 #[cfg_attr(feature = "clap", derive(clap::Args))]
 pub struct HybridQueryParams {
     #[cfg_attr(feature = "clap", arg(long, default_value = "5"))]
     pub max_breakdown_key: u32,
-    #[cfg_attr(feature = "clap", arg(short = 'd', long, default_value = "1"))]
-    pub with_dp: u32,
-    #[cfg_attr(feature = "clap", arg(short = 'e', long, default_value = "5.0"))]
-    pub epsilon: f64,
+    /// Upper bound on the total value a single user can contribute to the histogram.
+    /// Conversions that share a match key are summed and then capped to this value.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = DEFAULT_PER_USER_CREDIT_CAP))]
+    #[serde(default = "default_per_user_credit_cap")]
+    pub per_user_credit_cap: u32,
+    /// Conversions are only attributed to an impression if they happen within this many seconds
+    /// after it. There is no attribution window if not set.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub attribution_window_seconds: Option<NonZeroU32>,
+    /// DP mechanism applied to the output histogram, e.g. `none`,
+    /// `discrete-laplace:epsilon=5`, or `binomial:epsilon=5,delta=0.000001`.
+    #[cfg_attr(
+        feature = "clap",
+        arg(short = 'd', long, default_value = "discrete-laplace:epsilon=5")
+    )]
+    pub dp_mechanism: DpMechanism,
     #[cfg_attr(feature = "clap", arg(long))]
     #[serde(default)]
     pub plaintext_match_keys: bool,
+    /// Width of the breakdown key carried by impression reports.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = BreakdownKeyBits::default()))]
+    #[serde(default)]
+    pub breakdown_key_bits: BreakdownKeyBits,
+    /// Width of the value carried by conversion reports.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = ValueBits::default()))]
+    #[serde(default)]
+    pub value_bits: ValueBits,
+    /// DP padding added to the reports before the OPRF is revealed.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = OPRFPadding::default()))]
+    #[serde(default)]
+    pub oprf_padding: OPRFPadding,
+    /// DP padding added before breakdown keys are revealed for aggregation.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = AggregationPadding::default()))]
+    #[serde(default)]
+    pub aggregation_padding: AggregationPadding,
+    /// Breaks the histogram down by the cross product of two dimensions, e.g. `6x10` for
+    /// 6 campaigns and 10 regions. See [`BreakdownDimensions`] for how impressions carry them.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub breakdown_dimensions: Option<BreakdownDimensions>,
+    /// Metrics aggregated per breakdown key, `sum` or `sum-and-reach`.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = HybridAggregationMode::default()))]
+    #[serde(default)]
+    pub aggregation_mode: HybridAggregationMode,
+    /// Shape of the output histogram, e.g. `dense` or `sparse:delta=0.000001`. Sparse output
+    /// requires the discrete Laplace DP mechanism and the `sum` aggregation mode.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "dense"))]
+    #[serde(default)]
+    pub histogram_output: HistogramOutput,
+}
+
+const DEFAULT_PER_USER_CREDIT_CAP: u32 = 8;
+
+fn default_per_user_credit_cap() -> u32 {
+    DEFAULT_PER_USER_CREDIT_CAP
 }
 
 #[cfg(test)]
@@ -21,9 +81,517 @@ impl Default for HybridQueryParams {
     fn default() -> Self {
         Self {
             max_breakdown_key: 5,
-            with_dp: 1,
-            epsilon: 5.0,
+            per_user_credit_cap: DEFAULT_PER_USER_CREDIT_CAP,
+            attribution_window_seconds: None,
+            dp_mechanism: DpMechanism::DiscreteLaplace { epsilon: 5.0 },
             plaintext_match_keys: false,
+            breakdown_key_bits: BreakdownKeyBits::default(),
+            value_bits: ValueBits::default(),
+            oprf_padding: OPRFPadding::default(),
+            aggregation_padding: AggregationPadding::default(),
+            breakdown_dimensions: None,
+            aggregation_mode: HybridAggregationMode::default(),
+            histogram_output: HistogramOutput::default(),
+        }
+    }
+}
+
+impl HybridQueryParams {
+    #[must_use]
+    pub fn padding_params(&self) -> PaddingParameters {
+        PaddingParameters {
+            aggregation_padding: self.aggregation_padding,
+            oprf_padding: self.oprf_padding,
+        }
+    }
+
+    /// Total epsilon spent by the query. Every histogram the query outputs is noised with the
+    /// DP mechanism, so the epsilon of the mechanism is spent once per histogram. `None` if the
+    /// query does not add noise.
+    #[must_use]
+    pub fn epsilon(&self) -> Option<f64> {
+        self.dp_mechanism
+            .epsilon()
+            .map(|epsilon| epsilon * f64::from(self.aggregation_mode.histograms()))
+    }
+
+    /// ## Errors
+    /// If sparse histogram output is requested without the discrete Laplace DP mechanism or
+    /// together with reach.
+    pub fn validate(&self) -> Result<(), QueryConfigError> {
+        if matches!(self.histogram_output, HistogramOutput::Sparse { .. }) {
+            if !matches!(self.dp_mechanism, DpMechanism::DiscreteLaplace { .. }) {
+                return Err(QueryConfigError::UnsupportedHistogramOutput(
+                    self.histogram_output,
+                    self.dp_mechanism,
+                ));
+            }
+            if self.aggregation_mode != HybridAggregationMode::Sum {
+                return Err(QueryConfigError::UnsupportedAggregationMode(
+                    self.histogram_output,
+                    self.aggregation_mode,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Metrics a hybrid query aggregates per breakdown key. Query configurations carry it as a
+/// string: `sum` or `sum-and-reach`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum HybridAggregationMode {
+    /// The values attributed to every breakdown key are summed.
+    #[default]
+    Sum,
+    /// In addition to the sums, the users attributed a non-zero value are counted for every
+    /// breakdown key (reach). The query outputs the histogram of counts after the histogram
+    /// of sums.
+    SumAndReach,
+}
+
+impl HybridAggregationMode {
+    pub const SUM_STR: &'static str = "sum";
+    pub const SUM_AND_REACH_STR: &'static str = "sum-and-reach";
+
+    /// Number of histograms the query outputs.
+    #[must_use]
+    pub fn histograms(self) -> u32 {
+        match self {
+            Self::Sum => 1,
+            Self::SumAndReach => 2,
+        }
+    }
+}
+
+impl FromStr for HybridAggregationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            Self::SUM_STR => Ok(Self::Sum),
+            Self::SUM_AND_REACH_STR => Ok(Self::SumAndReach),
+            _ => Err(format!(
+                "{s:?} is not a supported aggregation mode. Expected {} or {}",
+                Self::SUM_STR,
+                Self::SUM_AND_REACH_STR,
+            )),
         }
     }
 }
+
+impl Display for HybridAggregationMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Sum => f.write_str(Self::SUM_STR),
+            Self::SumAndReach => f.write_str(Self::SUM_AND_REACH_STR),
+        }
+    }
+}
+
+impl TryFrom<String> for HybridAggregationMode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<HybridAggregationMode> for String {
+    fn from(value: HybridAggregationMode) -> Self {
+        value.to_string()
+    }
+}
+
+/// Number of bits used to encode breakdown keys in hybrid reports. The number of
+/// histogram buckets produced by the query is `2^bits` for 5 and 8 bits.
+///
+/// Aggregation vectorizes the histogram across all buckets, and `2^16` buckets are too many
+/// for that. Queries with 16-bit keys produce 1024 buckets instead, so `max_breakdown_key`
+/// must not exceed 1024 and values attributed to larger keys are dropped.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(try_from = "u32")]
+pub struct BreakdownKeyBits(u32);
+
+impl BreakdownKeyBits {
+    #[must_use]
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl TryFrom<u32> for BreakdownKeyBits {
+    type Error = String;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            5 | 8 | 16 => Ok(Self(value)),
+            _ => Err(format!(
+                "{value} breakdown key bits is not supported. Please set to 5, 8 or 16."
+            )),
+        }
+    }
+}
+
+impl Default for BreakdownKeyBits {
+    fn default() -> Self {
+        Self(8)
+    }
+}
+
+impl FromStr for BreakdownKeyBits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<u32>()
+            .map_err(|e| e.to_string())
+            .and_then(Self::try_from)
+    }
+}
+
+impl Display for BreakdownKeyBits {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Number of bits used to encode conversion values in hybrid reports.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(try_from = "u32")]
+pub struct ValueBits(u32);
+
+impl ValueBits {
+    #[must_use]
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl TryFrom<u32> for ValueBits {
+    type Error = String;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            3 | 8 | 16 => Ok(Self(value)),
+            _ => Err(format!(
+                "{value} value bits is not supported. \
+                 Please set to 3, 8, or 16, or add an new implementation."
+            )),
+        }
+    }
+}
+
+impl Default for ValueBits {
+    fn default() -> Self {
+        Self(3)
+    }
+}
+
+impl FromStr for ValueBits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<u32>()
+            .map_err(|e| e.to_string())
+            .and_then(Self::try_from)
+    }
+}
+
+impl Display for ValueBits {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Sizes of the two dimensions of a query that breaks its histogram down by the cross product
+/// of two keys, e.g. campaign × device.
+///
+/// Impressions carry both keys in their breakdown key: the key of the first dimension in the
+/// lowest [`Self::first_bits`] bits, and the key of the second dimension in the bits above them
+/// (see [`Self::encode`]). The helpers combine the two keys into the bucket
+/// `first_key + first * second_key` without revealing them, so the histogram has
+/// `first * second` buckets, and a user still contributes to a single bucket. Impressions whose
+/// keys fall outside of either dimension do not contribute to the histogram.
+///
+/// Query configurations carry the dimensions as a string, e.g. `6x10`.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct BreakdownDimensions {
+    first: u32,
+    second: u32,
+}
+
+impl BreakdownDimensions {
+    /// The largest number of keys supported in a single dimension.
+    pub const MAX_SIZE: u32 = 1 << 16;
+
+    /// Creates dimensions with `first` and `second` keys.
+    ///
+    /// ## Errors
+    /// If either dimension is empty or larger than [`Self::MAX_SIZE`].
+    pub fn new(first: u32, second: u32) -> Result<Self, String> {
+        if !(1..=Self::MAX_SIZE).contains(&first) || !(1..=Self::MAX_SIZE).contains(&second) {
+            return Err(format!(
+                "breakdown dimensions {first}x{second} are not supported. \
+                 Both dimensions must have between 1 and {} keys.",
+                Self::MAX_SIZE
+            ));
+        }
+        Ok(Self { first, second })
+    }
+
+    #[must_use]
+    pub fn first(self) -> u32 {
+        self.first
+    }
+
+    #[must_use]
+    pub fn second(self) -> u32 {
+        self.second
+    }
+
+    /// Number of histogram buckets, one for every pair of keys.
+    #[must_use]
+    pub fn buckets(self) -> u32 {
+        self.first * self.second
+    }
+
+    /// Number of breakdown key bits that encode the key of the first dimension.
+    #[must_use]
+    pub fn first_bits(self) -> u32 {
+        u32::BITS - (self.first - 1).leading_zeros()
+    }
+
+    /// Number of breakdown key bits needed to encode the keys of both dimensions.
+    #[must_use]
+    pub fn encoded_bits(self) -> u32 {
+        self.first_bits() + u32::BITS - (self.second - 1).leading_zeros()
+    }
+
+    /// Encodes the keys of both dimensions into the breakdown key of an impression.
+    ///
+    /// ## Panics
+    /// If either key is outside of its dimension.
+    #[must_use]
+    pub fn encode(self, first_key: u32, second_key: u32) -> u32 {
+        assert!(
+            first_key < self.first && second_key < self.second,
+            "keys ({first_key}, {second_key}) are outside of breakdown dimensions {self}"
+        );
+        first_key | (second_key << self.first_bits())
+    }
+
+    /// Returns the histogram bucket of an impression with `breakdown_key`, or `None` if the
+    /// breakdown key does not encode keys within both dimensions.
+    #[must_use]
+    pub fn bucket(self, breakdown_key: u32) -> Option<u32> {
+        let first_key = breakdown_key & ((1 << self.first_bits()) - 1);
+        let second_key = breakdown_key >> self.first_bits();
+        (first_key < self.first && second_key < self.second)
+            .then(|| first_key + self.first * second_key)
+    }
+
+    /// Arranges a histogram with [`Self::buckets`] buckets into rows, one for every key of the
+    /// first dimension, with a column for every key of the second dimension.
+    ///
+    /// ## Panics
+    /// If `histogram` has fewer than [`Self::buckets`] buckets.
+    #[must_use]
+    pub fn reshape<T: Clone>(self, histogram: &[T]) -> Vec<Vec<T>> {
+        let first = usize::try_from(self.first).unwrap();
+        let second = usize::try_from(self.second).unwrap();
+        (0..first)
+            .map(|first_key| {
+                (0..second)
+                    .map(|second_key| histogram[first_key + first * second_key].clone())
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<String> for BreakdownDimensions {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<BreakdownDimensions> for String {
+    fn from(value: BreakdownDimensions) -> Self {
+        value.to_string()
+    }
+}
+
+impl FromStr for BreakdownDimensions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, second) = s
+            .split_once('x')
+            .ok_or_else(|| format!("expected breakdown dimensions like 6x10, got {s}"))?;
+        Self::new(
+            first
+                .trim()
+                .parse()
+                .map_err(|e: ParseIntError| e.to_string())?,
+            second
+                .trim()
+                .parse()
+                .map_err(|e: ParseIntError| e.to_string())?,
+        )
+    }
+}
+
+impl Display for BreakdownDimensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}x{}", self.first, self.second)
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::{
+        BreakdownDimensions, BreakdownKeyBits, HybridAggregationMode, HybridQueryParams, ValueBits,
+    };
+    use crate::helpers::query::{DpMechanism, HistogramOutput, QueryConfigError};
+
+    #[test]
+    fn widths_validated() {
+        assert_eq!(8, BreakdownKeyBits::try_from(8).unwrap().bits());
+        assert_eq!(16, BreakdownKeyBits::try_from(16).unwrap().bits());
+        assert!(BreakdownKeyBits::try_from(12)
+            .unwrap_err()
+            .contains("5, 8 or 16"));
+        assert_eq!(16, "16".parse::<ValueBits>().unwrap().bits());
+        assert!("4".parse::<ValueBits>().is_err());
+        assert!("x".parse::<ValueBits>().is_err());
+    }
+
+    #[test]
+    fn widths_default_when_missing() {
+        let params: HybridQueryParams =
+            serde_json::from_str(r#"{"max_breakdown_key":5,"dp_mechanism":"none"}"#).unwrap();
+        assert_eq!(BreakdownKeyBits::default(), params.breakdown_key_bits);
+        assert_eq!(ValueBits::default(), params.value_bits);
+        assert_eq!(
+            HybridQueryParams::default().per_user_credit_cap,
+            params.per_user_credit_cap
+        );
+        assert_eq!(None, params.attribution_window_seconds);
+        assert_eq!(None, params.breakdown_dimensions);
+        assert_eq!(HybridAggregationMode::Sum, params.aggregation_mode);
+
+        let err = serde_json::from_str::<HybridQueryParams>(
+            r#"{"max_breakdown_key":5,"dp_mechanism":"none","value_bits":7}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("7 value bits is not supported"));
+    }
+
+    #[test]
+    fn breakdown_dimensions() {
+        let dimensions = "6x10".parse::<BreakdownDimensions>().unwrap();
+        assert_eq!(60, dimensions.buckets());
+        assert_eq!(3, dimensions.first_bits());
+        assert_eq!(7, dimensions.encoded_bits());
+        assert_eq!("6x10", dimensions.to_string());
+
+        let breakdown_key = dimensions.encode(5, 9);
+        assert_eq!((9 << 3) | 5, breakdown_key);
+        assert_eq!(Some(5 + 6 * 9), dimensions.bucket(breakdown_key));
+        // first key 6 and second key 10 are outside of the dimensions
+        assert_eq!(None, dimensions.bucket(6));
+        assert_eq!(None, dimensions.bucket(10 << 3));
+
+        let histogram = (0..60).collect::<Vec<_>>();
+        let grid = dimensions.reshape(&histogram);
+        assert_eq!(6, grid.len());
+        assert_eq!(vec![2, 8, 14, 20, 26, 32, 38, 44, 50, 56], grid[2]);
+
+        assert_eq!(4, BreakdownDimensions::new(1, 4).unwrap().buckets());
+        assert_eq!(2, BreakdownDimensions::new(1, 4).unwrap().encoded_bits());
+        assert!("0x4".parse::<BreakdownDimensions>().is_err());
+        assert!("4".parse::<BreakdownDimensions>().is_err());
+
+        let params: HybridQueryParams = serde_json::from_str(
+            r#"{"max_breakdown_key":5,"dp_mechanism":"none",
+                "breakdown_dimensions":"6x10"}"#,
+        )
+        .unwrap();
+        assert_eq!(Some(dimensions), params.breakdown_dimensions);
+        assert!(serde_json::from_str::<HybridQueryParams>(
+            r#"{"max_breakdown_key":5,"dp_mechanism":"none",
+                "breakdown_dimensions":"6x0"}"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn aggregation_mode() {
+        for mode in [
+            HybridAggregationMode::Sum,
+            HybridAggregationMode::SumAndReach,
+        ] {
+            assert_eq!(mode, mode.to_string().parse().unwrap());
+        }
+        assert!("reach".parse::<HybridAggregationMode>().is_err());
+
+        let params: HybridQueryParams = serde_json::from_str(
+            r#"{"max_breakdown_key":5,"dp_mechanism":"discrete-laplace:epsilon=2",
+                "aggregation_mode":"sum-and-reach"}"#,
+        )
+        .unwrap();
+        assert_eq!(HybridAggregationMode::SumAndReach, params.aggregation_mode);
+        // both histograms are noised
+        assert_eq!(Some(4.0), params.epsilon());
+        let params = HybridQueryParams {
+            dp_mechanism: DpMechanism::NoDp,
+            ..params
+        };
+        assert_eq!(None, params.epsilon());
+    }
+
+    #[test]
+    fn sparse_output_validated() {
+        let sparse = HybridQueryParams {
+            dp_mechanism: DpMechanism::DiscreteLaplace { epsilon: 1.0 },
+            histogram_output: HistogramOutput::Sparse { delta: 1e-6 },
+            ..Default::default()
+        };
+        sparse.validate().unwrap();
+        HybridQueryParams {
+            dp_mechanism: DpMechanism::NoDp,
+            ..Default::default()
+        }
+        .validate()
+        .unwrap();
+
+        assert!(matches!(
+            HybridQueryParams {
+                dp_mechanism: DpMechanism::NoDp,
+                ..sparse
+            }
+            .validate(),
+            Err(QueryConfigError::UnsupportedHistogramOutput(..))
+        ));
+        assert!(matches!(
+            HybridQueryParams {
+                aggregation_mode: HybridAggregationMode::SumAndReach,
+                ..sparse
+            }
+            .validate(),
+            Err(QueryConfigError::UnsupportedAggregationMode(..))
+        ));
+
+        let params: HybridQueryParams = serde_json::from_str(
+            r#"{"max_breakdown_key":5,"dp_mechanism":"discrete-laplace:epsilon=2",
+                "histogram_output":"sparse:delta=0.000001"}"#,
+        )
+        .unwrap();
+        assert_eq!(sparse.histogram_output, params.histogram_output);
+    }
+}
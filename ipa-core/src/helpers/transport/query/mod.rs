@@ -1,12 +1,18 @@
 mod hybrid;
+mod walr;
 
 use std::{
     fmt::{Debug, Display, Formatter},
     num::NonZeroU32,
+    str::FromStr,
+    time::Duration,
 };
 
-pub use hybrid::HybridQueryParams;
+pub use hybrid::{
+    BreakdownDimensions, BreakdownKeyBits, HybridAggregationMode, HybridQueryParams, ValueBits,
+};
 use serde::{Deserialize, Deserializer, Serialize};
+pub use walr::{FeatureCount, ModelWeights, WalrQueryParams};
 
 use crate::{
     ff::FieldType,
@@ -14,7 +20,13 @@ use crate::{
         transport::{routing::RouteId, BodyStream, NoQueryId, NoStep},
         RoleAssignment, RouteParams,
     },
-    protocol::QueryId,
+    protocol::{
+        ipa_prf::{
+            oprf_padding::{AggregationPadding, OPRFPadding, PaddingParameters},
+            prf_sharding::MAX_PER_USER_CREDIT_CAP,
+        },
+        QueryId,
+    },
     query::QueryStatus,
 };
 
@@ -87,12 +99,71 @@ pub struct QueryConfig {
     pub size: QuerySize,
     pub field_type: FieldType,
     pub query_type: QueryType,
+    #[serde(default)]
+    pub deadlines: QueryDeadlines,
+}
+
+/// Limits on how long a query is allowed to take. Each helper and shard measures them from
+/// the moment it registers the query. Once a deadline passes, the query is cancelled on all
+/// helpers and shards and its status becomes [`QueryStatus::TimedOut`].
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct QueryDeadlines {
+    /// Time allowed for the inputs to arrive.
+    pub inputs: Option<Duration>,
+    /// Time allowed for the whole query, including the input phase.
+    pub total: Option<Duration>,
+}
+
+impl QueryDeadlines {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_none() && self.total.is_none()
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum QueryConfigError {
     #[error(transparent)]
     BadQuerySize(#[from] BadQuerySizeError),
+    #[error("per-user credit cap must be between 1 and {MAX_PER_USER_CREDIT_CAP}, got {0}")]
+    InvalidPerUserCreditCap(u32),
+    #[error("{0} histogram output is not supported with the {1} DP mechanism")]
+    UnsupportedHistogramOutput(HistogramOutput, DpMechanism),
+    #[error("{0} histogram output is not supported with the {1} aggregation mode")]
+    UnsupportedAggregationMode(HistogramOutput, HybridAggregationMode),
+}
+
+/// Name of a report collector, as configured for its TLS client certificate on the helpers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ReportCollector(pub String);
+
+impl Display for ReportCollector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Request to start a new query, as received by the helper that coordinates it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct CreateQuery {
+    #[serde(flatten)]
+    pub config: QueryConfig,
+    /// Report collector that asked for this query, if it authenticated with a known
+    /// certificate.
+    #[serde(default)]
+    pub report_collector: Option<ReportCollector>,
+}
+
+impl From<QueryConfig> for CreateQuery {
+    fn from(config: QueryConfig) -> Self {
+        Self {
+            config,
+            report_collector: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -101,6 +172,8 @@ pub struct PrepareQuery {
     pub query_id: QueryId,
     pub config: QueryConfig,
     pub roles: RoleAssignment,
+    #[serde(default)]
+    pub report_collector: Option<ReportCollector>,
 }
 
 impl RouteParams<RouteId, QueryId, NoStep> for PrepareQuery {
@@ -143,6 +216,26 @@ impl RouteParams<RouteId, NoQueryId, NoStep> for &QueryConfig {
     }
 }
 
+impl RouteParams<RouteId, NoQueryId, NoStep> for CreateQuery {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        RouteId::ReceiveQuery
+    }
+
+    fn query_id(&self) -> NoQueryId {
+        NoQueryId
+    }
+
+    fn gate(&self) -> NoStep {
+        NoStep
+    }
+
+    fn extra(&self) -> Self::Params {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
 impl QueryConfig {
     /// Initialize new query configuration.
     ///
@@ -160,8 +253,31 @@ impl QueryConfig {
             size: size.try_into()?,
             field_type,
             query_type,
+            deadlines: QueryDeadlines::default(),
         })
     }
+
+    /// Cancel the query if it does not finish within the given deadlines.
+    #[must_use]
+    pub fn with_deadlines(mut self, deadlines: QueryDeadlines) -> Self {
+        self.deadlines = deadlines;
+        self
+    }
+
+    /// Checks the parameters of the query that cannot be checked when they are parsed, so that
+    /// an invalid query is rejected when it is created instead of failing once it runs.
+    ///
+    /// ## Errors
+    /// If the query type parameters are not supported by the protocol.
+    pub fn validate(&self) -> Result<(), QueryConfigError> {
+        match &self.query_type {
+            QueryType::SemiHonestOprfIpa(config) | QueryType::MaliciousOprfIpa(config) => {
+                config.validate()
+            }
+            QueryType::MaliciousHybrid(config) => config.validate(),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl RouteParams<RouteId, QueryId, NoStep> for &PrepareQuery {
@@ -185,9 +301,11 @@ impl RouteParams<RouteId, QueryId, NoStep> for &PrepareQuery {
 }
 
 pub enum QueryInput {
-    FromUrl {
+    /// Helper downloads the input from the given locations, in order. Each location is either
+    /// a URL or a `file://` path on the helper's local disk.
+    FromUrls {
         query_id: QueryId,
-        url: String,
+        urls: Vec<String>,
     },
     Inline {
         query_id: QueryId,
@@ -199,7 +317,7 @@ impl QueryInput {
     #[must_use]
     pub fn query_id(&self) -> QueryId {
         match self {
-            Self::FromUrl { query_id, .. } | Self::Inline { query_id, .. } => *query_id,
+            Self::FromUrls { query_id, .. } | Self::Inline { query_id, .. } => *query_id,
         }
     }
 
@@ -207,15 +325,15 @@ impl QueryInput {
     pub fn input_stream(self) -> Option<BodyStream> {
         match self {
             Self::Inline { input_stream, .. } => Some(input_stream),
-            Self::FromUrl { .. } => None,
+            Self::FromUrls { .. } => None,
         }
     }
 
     #[must_use]
-    pub fn url(&self) -> Option<&str> {
+    pub fn urls(&self) -> &[String] {
         match self {
-            Self::FromUrl { url, .. } => Some(url),
-            Self::Inline { .. } => None,
+            Self::FromUrls { urls, .. } => urls,
+            Self::Inline { .. } => &[],
         }
     }
 }
@@ -230,10 +348,10 @@ impl Debug for QueryInput {
                 .debug_struct("QueryInput::Inline")
                 .field("query_id", query_id)
                 .finish(),
-            QueryInput::FromUrl { query_id, url } => f
-                .debug_struct("QueryInput::FromUrl")
+            QueryInput::FromUrls { query_id, urls } => f
+                .debug_struct("QueryInput::FromUrls")
                 .field("query_id", query_id)
-                .field("url", url)
+                .field("urls", urls)
                 .finish(),
         }
     }
@@ -278,6 +396,7 @@ pub enum QueryType {
     SemiHonestOprfIpa(IpaQueryConfig),
     MaliciousOprfIpa(IpaQueryConfig),
     MaliciousHybrid(HybridQueryParams),
+    MaliciousWalr(WalrQueryParams),
 }
 
 impl QueryType {
@@ -288,6 +407,7 @@ impl QueryType {
     pub const SEMI_HONEST_OPRF_IPA_STR: &'static str = "semi-honest-oprf-ipa";
     pub const MALICIOUS_OPRF_IPA_STR: &'static str = "malicious-oprf-ipa";
     pub const MALICIOUS_HYBRID_STR: &'static str = "malicious-hybrid";
+    pub const MALICIOUS_WALR_STR: &'static str = "malicious-walr";
 }
 
 /// TODO: should this `AsRef` impl (used for `Substep`) take into account config of IPA?
@@ -303,17 +423,293 @@ impl AsRef<str> for QueryType {
             QueryType::SemiHonestOprfIpa(_) => Self::SEMI_HONEST_OPRF_IPA_STR,
             QueryType::MaliciousOprfIpa(_) => Self::MALICIOUS_OPRF_IPA_STR,
             QueryType::MaliciousHybrid(_) => Self::MALICIOUS_HYBRID_STR,
+            QueryType::MaliciousWalr(_) => Self::MALICIOUS_WALR_STR,
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+/// Differential privacy mechanism used to add noise to the output histogram. Query
+/// configurations carry it as a string: `none`, `discrete-laplace:epsilon=<f64>`, or
+/// `binomial:epsilon=<f64>,delta=<f64>`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub enum DpMechanism {
     NoDp,
-    Binomial { epsilon: f64 },
+    Binomial { epsilon: f64, delta: f64 },
     DiscreteLaplace { epsilon: f64 },
 }
 
+impl DpMechanism {
+    pub const NO_DP_STR: &'static str = "none";
+    pub const BINOMIAL_STR: &'static str = "binomial";
+    pub const DISCRETE_LAPLACE_STR: &'static str = "discrete-laplace";
+
+    /// Returns the privacy budget spent by this mechanism, or `None` if no noise is added.
+    #[must_use]
+    pub fn epsilon(&self) -> Option<f64> {
+        match self {
+            Self::NoDp => None,
+            Self::Binomial { epsilon, .. } | Self::DiscreteLaplace { epsilon } => Some(*epsilon),
+        }
+    }
+}
+
+impl FromStr for DpMechanism {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, params) = s.split_once(':').unwrap_or((s, ""));
+        match kind.trim() {
+            Self::NO_DP_STR if params.trim().is_empty() => Ok(Self::NoDp),
+            Self::DISCRETE_LAPLACE_STR => {
+                let [epsilon] = parse_parameters(params, ["epsilon"])?;
+                Ok(Self::DiscreteLaplace {
+                    epsilon: parse_parameter_value("epsilon", epsilon)?,
+                })
+            }
+            Self::BINOMIAL_STR => {
+                let [epsilon, delta] = parse_parameters(params, ["epsilon", "delta"])?;
+                Ok(Self::Binomial {
+                    epsilon: parse_parameter_value("epsilon", epsilon)?,
+                    delta: parse_parameter_value("delta", delta)?,
+                })
+            }
+            _ => Err(format!(
+                "{s:?} is not a supported DP mechanism. Expected one of {}, \
+                 {}:epsilon=<f64>, or {}:epsilon=<f64>,delta=<f64>",
+                Self::NO_DP_STR,
+                Self::DISCRETE_LAPLACE_STR,
+                Self::BINOMIAL_STR,
+            )),
+        }
+    }
+}
+
+impl Display for DpMechanism {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoDp => f.write_str(Self::NO_DP_STR),
+            Self::Binomial { epsilon, delta } => {
+                write!(f, "{}:epsilon={epsilon},delta={delta}", Self::BINOMIAL_STR)
+            }
+            Self::DiscreteLaplace { epsilon } => {
+                write!(f, "{}:epsilon={epsilon}", Self::DISCRETE_LAPLACE_STR)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for DpMechanism {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<DpMechanism> for String {
+    fn from(value: DpMechanism) -> Self {
+        value.to_string()
+    }
+}
+
+/// Attribution model used to credit trigger values to source events. Query configurations carry
+/// it as a string: `last-touch`, `first-touch`, `linear`, or `time-decay:half_life=<u32>`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum AttributionModel {
+    /// Trigger values are credited to the most recent preceding source event.
+    #[default]
+    LastTouch,
+    /// Trigger values are credited to the first source event of the user.
+    FirstTouch,
+    /// Trigger values are split equally between all preceding source events of the user.
+    Linear,
+    /// Trigger values are split between all preceding source events of the user. The weight of a
+    /// source event halves with every `half_life_seconds` period that separates it from the most
+    /// recent source event. The half-life must be a power of two.
+    TimeDecay { half_life_seconds: u32 },
+}
+
+impl AttributionModel {
+    pub const LAST_TOUCH_STR: &'static str = "last-touch";
+    pub const FIRST_TOUCH_STR: &'static str = "first-touch";
+    pub const LINEAR_STR: &'static str = "linear";
+    pub const TIME_DECAY_STR: &'static str = "time-decay";
+
+    /// Returns `true` if this model may split a trigger value between several source events.
+    #[must_use]
+    pub fn is_multi_touch(&self) -> bool {
+        matches!(self, Self::Linear | Self::TimeDecay { .. })
+    }
+}
+
+impl FromStr for AttributionModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, params) = s.split_once(':').unwrap_or((s, ""));
+        match kind.trim() {
+            Self::LAST_TOUCH_STR if params.trim().is_empty() => Ok(Self::LastTouch),
+            Self::FIRST_TOUCH_STR if params.trim().is_empty() => Ok(Self::FirstTouch),
+            Self::LINEAR_STR if params.trim().is_empty() => Ok(Self::Linear),
+            Self::TIME_DECAY_STR => {
+                let [half_life] = parse_parameters(params, ["half_life"])?;
+                let half_life_seconds: u32 = parse_parameter_value("half_life", half_life)?;
+                if !half_life_seconds.is_power_of_two() {
+                    return Err(format!(
+                        "half_life must be a power of two, got {half_life_seconds}"
+                    ));
+                }
+                Ok(Self::TimeDecay { half_life_seconds })
+            }
+            _ => Err(format!(
+                "{s:?} is not a supported attribution model. Expected one of {}, {}, {}, \
+                 or {}:half_life=<u32>",
+                Self::LAST_TOUCH_STR,
+                Self::FIRST_TOUCH_STR,
+                Self::LINEAR_STR,
+                Self::TIME_DECAY_STR,
+            )),
+        }
+    }
+}
+
+impl Display for AttributionModel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LastTouch => f.write_str(Self::LAST_TOUCH_STR),
+            Self::FirstTouch => f.write_str(Self::FIRST_TOUCH_STR),
+            Self::Linear => f.write_str(Self::LINEAR_STR),
+            Self::TimeDecay { half_life_seconds } => {
+                write!(f, "{}:half_life={half_life_seconds}", Self::TIME_DECAY_STR)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for AttributionModel {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<AttributionModel> for String {
+    fn from(value: AttributionModel) -> Self {
+        value.to_string()
+    }
+}
+
+/// Shape of the histogram returned by an IPA query. Query configurations carry it as a string:
+/// `dense` or `sparse:delta=<f64>`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum HistogramOutput {
+    /// Every bucket of the histogram is returned, in breakdown key order.
+    #[default]
+    Dense,
+    /// Only buckets whose noisy value exceeds a DP threshold are returned, as a list of
+    /// `(breakdown key, value)` pairs. The threshold is chosen so that a bucket no more than one
+    /// user contributed to is returned with probability at most `delta`.
+    ///
+    /// This only changes the shape of the output. All buckets are still aggregated and noised
+    /// before thresholding, and the breakdown key width supported by the query is unchanged.
+    Sparse { delta: f64 },
+}
+
+impl HistogramOutput {
+    pub const DENSE_STR: &'static str = "dense";
+    pub const SPARSE_STR: &'static str = "sparse";
+}
+
+impl FromStr for HistogramOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, params) = s.split_once(':').unwrap_or((s, ""));
+        match kind.trim() {
+            Self::DENSE_STR if params.trim().is_empty() => Ok(Self::Dense),
+            Self::SPARSE_STR => {
+                let [delta] = parse_parameters(params, ["delta"])?;
+                let delta: f64 = parse_parameter_value("delta", delta)?;
+                if !(delta > 0.0 && delta < 1.0) {
+                    return Err(format!("delta must be within (0, 1), got {delta}"));
+                }
+                Ok(Self::Sparse { delta })
+            }
+            _ => Err(format!(
+                "{s:?} is not a supported histogram output. Expected {} or {}:delta=<f64>",
+                Self::DENSE_STR,
+                Self::SPARSE_STR,
+            )),
+        }
+    }
+}
+
+impl Display for HistogramOutput {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dense => f.write_str(Self::DENSE_STR),
+            Self::Sparse { delta } => write!(f, "{}:delta={delta}", Self::SPARSE_STR),
+        }
+    }
+}
+
+impl TryFrom<String> for HistogramOutput {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<HistogramOutput> for String {
+    fn from(value: HistogramOutput) -> Self {
+        value.to_string()
+    }
+}
+
+/// Splits `key=value` pairs separated by commas and returns the values in the order of `keys`.
+/// Every key must be present exactly once.
+pub(crate) fn parse_parameters<'a, const N: usize>(
+    s: &'a str,
+    keys: [&str; N],
+) -> Result<[&'a str; N], String> {
+    let mut values = [None; N];
+    for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, got {pair:?}"))?;
+        let key = key.trim();
+        let index = keys
+            .iter()
+            .position(|k| *k == key)
+            .ok_or_else(|| format!("unknown parameter {key:?}"))?;
+        if values[index].replace(value.trim()).is_some() {
+            return Err(format!("parameter {key:?} is specified more than once"));
+        }
+    }
+
+    let mut result = [""; N];
+    for ((key, value), slot) in keys.iter().zip(values).zip(&mut result) {
+        *slot = value.ok_or_else(|| format!("parameter {key:?} is missing"))?;
+    }
+
+    Ok(result)
+}
+
+pub(crate) fn parse_parameter_value<T>(key: &str, value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("invalid value {value:?} for parameter {key:?}: {e}"))
+}
+
 #[cfg(test)]
 impl Eq for IpaQueryConfig {}
 
@@ -326,10 +722,25 @@ pub struct IpaQueryConfig {
     pub max_breakdown_key: u32,
     #[cfg_attr(feature = "clap", arg(long))]
     pub attribution_window_seconds: Option<NonZeroU32>,
-    #[arg(short = 'd', long, default_value = "1")]
-    pub with_dp: u32,
-    #[arg(short = 'e', long, default_value = "5.0")]
-    pub epsilon: f64,
+    /// DP mechanism applied to the output histogram, e.g. `none`,
+    /// `discrete-laplace:epsilon=5`, or `binomial:epsilon=5,delta=0.000001`.
+    #[cfg_attr(
+        feature = "clap",
+        arg(short = 'd', long, default_value = "discrete-laplace:epsilon=5")
+    )]
+    pub dp_mechanism: DpMechanism,
+
+    /// Attribution model, e.g. `last-touch`, `first-touch`, `linear`, or
+    /// `time-decay:half_life=1024`.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "last-touch"))]
+    #[serde(default)]
+    pub attribution_model: AttributionModel,
+
+    /// Shape of the output histogram, e.g. `dense` or `sparse:delta=0.000001`. Sparse output
+    /// requires the discrete Laplace DP mechanism.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "dense"))]
+    #[serde(default)]
+    pub histogram_output: HistogramOutput,
 
     /// If false, IPA decrypts match key shares in the input reports. If true, IPA uses match key
     /// shares from input reports directly. Setting this to true also activates an alternate
@@ -338,6 +749,16 @@ pub struct IpaQueryConfig {
     #[cfg_attr(feature = "clap", arg(long))]
     #[serde(default)]
     pub plaintext_match_keys: bool,
+
+    /// DP padding added to the input rows before the OPRF is revealed.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = OPRFPadding::default()))]
+    #[serde(default)]
+    pub oprf_padding: OPRFPadding,
+
+    /// DP padding added before breakdown keys are revealed for aggregation.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = AggregationPadding::default()))]
+    #[serde(default)]
+    pub aggregation_padding: AggregationPadding,
 }
 
 impl Default for IpaQueryConfig {
@@ -346,9 +767,12 @@ impl Default for IpaQueryConfig {
             per_user_credit_cap: 8,
             max_breakdown_key: 20,
             attribution_window_seconds: None,
-            with_dp: 1,
-            epsilon: 0.10,
+            dp_mechanism: DpMechanism::DiscreteLaplace { epsilon: 0.10 },
+            attribution_model: AttributionModel::LastTouch,
+            histogram_output: HistogramOutput::Dense,
             plaintext_match_keys: false,
+            oprf_padding: OPRFPadding::default(),
+            aggregation_padding: AggregationPadding::default(),
         }
     }
 }
@@ -361,8 +785,7 @@ impl IpaQueryConfig {
         per_user_credit_cap: u32,
         max_breakdown_key: u32,
         attribution_window_seconds: u32,
-        with_dp: u32,
-        epsilon: f64,
+        dp_mechanism: DpMechanism,
     ) -> Self {
         Self {
             per_user_credit_cap,
@@ -371,10 +794,12 @@ impl IpaQueryConfig {
                 NonZeroU32::new(attribution_window_seconds)
                     .expect("attribution window must be a positive value > 0"),
             ),
-            with_dp,
-            epsilon,
-            // dp_params,
+            dp_mechanism,
+            attribution_model: AttributionModel::LastTouch,
+            histogram_output: HistogramOutput::Dense,
             plaintext_match_keys: false,
+            oprf_padding: OPRFPadding::default(),
+            aggregation_padding: AggregationPadding::default(),
         }
     }
 
@@ -386,16 +811,47 @@ impl IpaQueryConfig {
     pub fn no_window(
         per_user_credit_cap: u32,
         max_breakdown_key: u32,
-        with_dp: u32,
-        epsilon: f64,
+        dp_mechanism: DpMechanism,
     ) -> Self {
         Self {
             per_user_credit_cap,
             max_breakdown_key,
             attribution_window_seconds: None,
-            with_dp,
-            epsilon,
+            dp_mechanism,
+            attribution_model: AttributionModel::LastTouch,
+            histogram_output: HistogramOutput::Dense,
             plaintext_match_keys: false,
+            oprf_padding: OPRFPadding::default(),
+            aggregation_padding: AggregationPadding::default(),
+        }
+    }
+
+    /// ## Errors
+    /// If the per-user credit cap is zero or larger than [`MAX_PER_USER_CREDIT_CAP`], or if sparse
+    /// histogram output is requested without the discrete Laplace DP mechanism.
+    pub fn validate(&self) -> Result<(), QueryConfigError> {
+        if !(1..=MAX_PER_USER_CREDIT_CAP).contains(&self.per_user_credit_cap) {
+            return Err(QueryConfigError::InvalidPerUserCreditCap(
+                self.per_user_credit_cap,
+            ));
+        }
+        if matches!(self.histogram_output, HistogramOutput::Sparse { .. })
+            && !matches!(self.dp_mechanism, DpMechanism::DiscreteLaplace { .. })
+        {
+            return Err(QueryConfigError::UnsupportedHistogramOutput(
+                self.histogram_output,
+                self.dp_mechanism,
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn padding_params(&self) -> PaddingParameters {
+        PaddingParameters {
+            aggregation_padding: self.aggregation_padding,
+            oprf_padding: self.oprf_padding,
         }
     }
 }
@@ -429,3 +885,191 @@ impl std::fmt::Display for ContributionBits {
         write!(f, "{}", self.0)
     }
 }
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::{
+        AttributionModel, CreateQuery, DpMechanism, HistogramOutput, HybridQueryParams,
+        IpaQueryConfig, QueryConfig, QueryConfigError, QueryType, ReportCollector,
+    };
+    use crate::{ff::FieldType, helpers::RouteParams};
+
+    #[test]
+    fn dp_mechanism_round_trip() {
+        for mechanism in [
+            DpMechanism::NoDp,
+            DpMechanism::DiscreteLaplace { epsilon: 0.1 },
+            DpMechanism::Binomial {
+                epsilon: 5.0,
+                delta: 1e-6,
+            },
+        ] {
+            assert_eq!(mechanism, mechanism.to_string().parse().unwrap());
+            let json = serde_json::to_string(&mechanism).unwrap();
+            assert_eq!(mechanism, serde_json::from_str(&json).unwrap());
+        }
+
+        assert_eq!(
+            DpMechanism::Binomial {
+                epsilon: 1.0,
+                delta: 1e-4
+            },
+            "binomial:delta=0.0001,epsilon=1".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn dp_mechanism_rejects_malformed() {
+        for s in [
+            "",
+            "laplace:epsilon=1",
+            "none:epsilon=1",
+            "discrete-laplace",
+            "discrete-laplace:epsilon=x",
+            "binomial:epsilon=1",
+            "binomial:epsilon=1,delta=0.1,sensitivity=2",
+        ] {
+            assert!(s.parse::<DpMechanism>().is_err(), "{s:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn attribution_model_round_trip() {
+        for model in [
+            AttributionModel::LastTouch,
+            AttributionModel::FirstTouch,
+            AttributionModel::Linear,
+            AttributionModel::TimeDecay {
+                half_life_seconds: 1024,
+            },
+        ] {
+            assert_eq!(model, model.to_string().parse().unwrap());
+            let json = serde_json::to_string(&model).unwrap();
+            assert_eq!(model, serde_json::from_str(&json).unwrap());
+        }
+    }
+
+    #[test]
+    fn attribution_model_rejects_malformed() {
+        for s in [
+            "",
+            "last",
+            "linear:half_life=2",
+            "time-decay",
+            "time-decay:half_life=0",
+            "time-decay:half_life=1000",
+            "time-decay:half_life=-2",
+        ] {
+            assert!(
+                s.parse::<AttributionModel>().is_err(),
+                "{s:?} should not parse"
+            );
+        }
+    }
+
+    #[test]
+    fn histogram_output_round_trip() {
+        for output in [
+            HistogramOutput::Dense,
+            HistogramOutput::Sparse { delta: 1e-6 },
+        ] {
+            assert_eq!(output, output.to_string().parse().unwrap());
+            let json = serde_json::to_string(&output).unwrap();
+            assert_eq!(output, serde_json::from_str(&json).unwrap());
+        }
+
+        for s in [
+            "",
+            "sparse",
+            "dense:delta=0.1",
+            "sparse:delta=0",
+            "sparse:delta=1",
+            "sparse:delta=x",
+        ] {
+            assert!(
+                s.parse::<HistogramOutput>().is_err(),
+                "{s:?} should not parse"
+            );
+        }
+    }
+
+    #[test]
+    fn validate_histogram_output() {
+        let config = |dp_mechanism| {
+            QueryConfig::new(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                    dp_mechanism,
+                    histogram_output: HistogramOutput::Sparse { delta: 1e-6 },
+                    ..Default::default()
+                }),
+                FieldType::Fp32BitPrime,
+                100,
+            )
+            .unwrap()
+        };
+
+        config(DpMechanism::DiscreteLaplace { epsilon: 1.0 })
+            .validate()
+            .unwrap();
+        for dp_mechanism in [
+            DpMechanism::NoDp,
+            DpMechanism::Binomial {
+                epsilon: 1.0,
+                delta: 1e-6,
+            },
+        ] {
+            assert!(matches!(
+                config(dp_mechanism).validate(),
+                Err(QueryConfigError::UnsupportedHistogramOutput(..))
+            ));
+        }
+    }
+
+    #[test]
+    fn validate_per_user_credit_cap() {
+        let config = |per_user_credit_cap| {
+            QueryConfig::new(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig::no_window(
+                    per_user_credit_cap,
+                    20,
+                    DpMechanism::NoDp,
+                )),
+                FieldType::Fp32BitPrime,
+                100,
+            )
+            .unwrap()
+        };
+
+        for cap in [1, 10, 50, 100, 128, 255] {
+            config(cap).validate().unwrap();
+        }
+        for cap in [0, 256, 1000] {
+            assert!(matches!(
+                config(cap).validate(),
+                Err(QueryConfigError::InvalidPerUserCreditCap(c)) if c == cap
+            ));
+        }
+    }
+
+    #[test]
+    fn create_query_from_config_params() {
+        let config = QueryConfig::new(
+            QueryType::MaliciousHybrid(HybridQueryParams::default()),
+            FieldType::Fp32BitPrime,
+            100,
+        )
+        .unwrap();
+
+        let parsed = serde_json::from_str::<CreateQuery>(&config.extra()).unwrap();
+        assert_eq!(CreateQuery::from(config), parsed);
+
+        let create = CreateQuery {
+            config,
+            report_collector: Some(ReportCollector("rc".to_string())),
+        };
+        assert_eq!(
+            create,
+            serde_json::from_str::<CreateQuery>(&create.extra()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    helpers::query::DpMechanism,
+    protocol::ipa_prf::oprf_padding::{AggregationPadding, OPRFPadding, PaddingParameters},
+};
+
+/// Parameters of the WALR (logistic regression gradient) query. The output is the noisy
+/// gradient of the logistic loss for the given model, with one coordinate per feature.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct WalrQueryParams {
+    /// Number of features carried by each report.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = FeatureCount::default()))]
+    #[serde(default)]
+    pub features: FeatureCount,
+    /// Weights of the model to compute the gradient for, as a comma-separated list of
+    /// fixed-point numbers with 4 fractional bits, e.g. `16,-8` for the weights 1 and -0.5.
+    /// Features without a weight have a weight of zero.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = ModelWeights::default()))]
+    #[serde(default)]
+    pub model_weights: ModelWeights,
+    /// DP mechanism applied to the output gradient, e.g. `none`,
+    /// `discrete-laplace:epsilon=5`, or `binomial:epsilon=5,delta=0.000001`.
+    #[cfg_attr(
+        feature = "clap",
+        arg(short = 'd', long, default_value = "discrete-laplace:epsilon=5")
+    )]
+    pub dp_mechanism: DpMechanism,
+    /// DP padding added to the reports before the OPRF is revealed.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = OPRFPadding::default()))]
+    #[serde(default)]
+    pub oprf_padding: OPRFPadding,
+}
+
+#[cfg(test)]
+impl Eq for WalrQueryParams {}
+
+impl Default for WalrQueryParams {
+    fn default() -> Self {
+        Self {
+            features: FeatureCount::default(),
+            model_weights: ModelWeights::default(),
+            dp_mechanism: DpMechanism::DiscreteLaplace { epsilon: 5.0 },
+            oprf_padding: OPRFPadding::default(),
+        }
+    }
+}
+
+impl WalrQueryParams {
+    /// Breakdown keys are never revealed by this query, so there is no aggregation padding.
+    #[must_use]
+    pub fn padding_params(&self) -> PaddingParameters {
+        PaddingParameters {
+            aggregation_padding: AggregationPadding::NoAggPadding,
+            oprf_padding: self.oprf_padding,
+        }
+    }
+}
+
+/// Largest number of features supported by WALR queries.
+const MAX_FEATURES: usize = 16;
+
+/// Number of features carried by WALR reports. Every report of a query carries the same number
+/// of features, each of them 8 bits wide.
+///
+/// Features are processed with one vectorized lane per feature, and are shuffled together with
+/// the rest of the report in a single 256-bit share, so only 8 and 16 features are supported.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(try_from = "u32")]
+pub struct FeatureCount(u32);
+
+impl FeatureCount {
+    #[must_use]
+    pub fn count(self) -> u32 {
+        self.0
+    }
+}
+
+impl TryFrom<u32> for FeatureCount {
+    type Error = String;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            8 | 16 => Ok(Self(value)),
+            _ => Err(format!(
+                "{value} features are not supported. Please set to 8 or 16."
+            )),
+        }
+    }
+}
+
+impl Default for FeatureCount {
+    fn default() -> Self {
+        Self(16)
+    }
+}
+
+impl FromStr for FeatureCount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<u32>()
+            .map_err(|e| e.to_string())
+            .and_then(Self::try_from)
+    }
+}
+
+impl Display for FeatureCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Weights of a logistic-regression model, one per feature.
+///
+/// Each weight is a signed fixed-point number with 4 fractional bits, so the weights range from
+/// -8 to 7.9375. This matches the input of the sigmoid approximation evaluated in MPC, which
+/// saturates outside of that range.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(try_from = "String", into = "String")]
+pub struct ModelWeights {
+    weights: [i8; MAX_FEATURES],
+    len: usize,
+}
+
+impl ModelWeights {
+    /// Returns the weights of the features that have one.
+    #[must_use]
+    pub fn weights(&self) -> &[i8] {
+        &self.weights[..self.len]
+    }
+}
+
+impl TryFrom<&[i8]> for ModelWeights {
+    type Error = String;
+
+    fn try_from(value: &[i8]) -> Result<Self, Self::Error> {
+        if value.len() > MAX_FEATURES {
+            return Err(format!(
+                "{} model weights are not supported, at most {MAX_FEATURES} are allowed.",
+                value.len()
+            ));
+        }
+        let mut weights = [0; MAX_FEATURES];
+        weights[..value.len()].copy_from_slice(value);
+        Ok(Self {
+            weights,
+            len: value.len(),
+        })
+    }
+}
+
+impl TryFrom<String> for ModelWeights {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ModelWeights> for String {
+    fn from(value: ModelWeights) -> Self {
+        value.to_string()
+    }
+}
+
+impl FromStr for ModelWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(Self::default());
+        }
+        let weights = s
+            .split(',')
+            .map(|weight| {
+                weight
+                    .trim()
+                    .parse::<i8>()
+                    .map_err(|e| format!("invalid model weight {weight}: {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::try_from(weights.as_slice())
+    }
+}
+
+impl Display for ModelWeights {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, weight) in self.weights().iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{weight}")?;
+        }
+        Ok(())
+    }
+}
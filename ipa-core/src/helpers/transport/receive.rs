@@ -1,6 +1,8 @@
 use std::{
-    pin::{pin, Pin},
+    future::Future,
+    pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use futures::Stream;
@@ -12,7 +14,7 @@ use crate::{
     error::BoxError,
     helpers::{
         transport::stream::{StreamCollection, StreamKey},
-        TransportIdentity,
+        BytesStream, TransportIdentity,
     },
 };
 
@@ -66,20 +68,56 @@ where
     }
 }
 
+/// Receivers acknowledge the data received from each records stream after every this many
+/// bytes. Senders keep the data that hasn't been acknowledged yet, to replay it if the
+/// connection carrying the stream drops.
+pub const RECORDS_ACK_INTERVAL: u64 = 1 << 18;
+
+/// How long receivers wait for the sender to resume a records stream that failed, before
+/// giving up on it. Senders retry with exponential backoff and give up after a few attempts,
+/// so this leaves them plenty of time.
+pub const RECORDS_RESUME_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Sends an acknowledgement back to the sender of a records stream, telling it that all bytes
+/// before the given offset have been received.
+pub type AckRecords = Box<dyn Fn(u64) + Send + Sync>;
+
 /// Represents a stream of records.
 /// If stream is not received yet, each poll generates a waker that is used internally to wake up
 /// the task when stream is received.
 /// Once stream is received, it is moved to this struct and it acts as a proxy to it.
+///
+/// If the stream fails, it is assumed that the connection carrying it dropped. The sender
+/// then resumes it from the data it kept for replay, and this struct picks the resumed
+/// stream up, skipping the bytes that have been received already. The received data is
+/// acknowledged every [`RECORDS_ACK_INTERVAL`] bytes, so the sender doesn't need to keep it.
+/// If the stream is not resumed within [`RECORDS_RESUME_TIMEOUT`], this stream fails.
 #[pin_project]
 pub struct ReceiveRecords<I, S> {
-    #[pin]
-    inner: ReceiveRecordsInner<I, S>,
+    key: StreamKey<I>,
+    streams: StreamCollection<I, S>,
+    state: ReceiveRecordsState<S>,
+    /// Number of bytes received from this stream so far.
+    received: u64,
+    /// Number of bytes at the start of the current stream that have been received already.
+    skip: u64,
+    /// Number of bytes acknowledged to the sender.
+    acked: u64,
+    ack: AckRecords,
+    resume_timeout: Duration,
 }
 
 impl<I, S> ReceiveRecords<I, S> {
-    pub(crate) fn new(key: StreamKey<I>, coll: StreamCollection<I, S>) -> Self {
+    pub(crate) fn new(key: StreamKey<I>, coll: StreamCollection<I, S>, ack: AckRecords) -> Self {
         Self {
-            inner: ReceiveRecordsInner::Pending(key, coll),
+            key,
+            streams: coll,
+            state: ReceiveRecordsState::Pending,
+            received: 0,
+            skip: 0,
+            acked: 0,
+            ack,
+            resume_timeout: RECORDS_RESUME_TIMEOUT,
         }
     }
 }
@@ -90,41 +128,164 @@ impl<I: TransportIdentity, S: crate::helpers::BytesStream> ReceiveRecords<I, S>
     ///
     /// ## Panics
     /// If inner stream yields [`Err`] chunk.
-    pub(crate) fn into_bytes_stream(self) -> impl Stream<Item = Vec<u8>> {
-        self.inner.map(Result::unwrap).map(Into::into)
+    pub(crate) fn into_bytes_stream(self) -> impl Stream<Item = Vec<u8>>
+    where
+        S: Unpin,
+    {
+        self.map(Result::unwrap).map(Into::into)
     }
 }
 
-impl<I: TransportIdentity, S: Stream> Stream for ReceiveRecords<I, S> {
+impl<I: TransportIdentity, S: BytesStream + Unpin> Stream for ReceiveRecords<I, S> {
     type Item = S::Item;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        self.project().inner.poll_next(cx)
+        let this = self.project();
+        loop {
+            match this.state {
+                ReceiveRecordsState::Pending => {
+                    let Some(stream) = this.streams.add_waker(this.key, cx.waker()) else {
+                        return Poll::Pending;
+                    };
+                    *this.state = ReceiveRecordsState::Ready(stream);
+                }
+                ReceiveRecordsState::Resuming(deadline) => {
+                    let Some((offset, stream)) = this.streams.resume(this.key, cx.waker()) else {
+                        if deadline.as_mut().poll(cx).is_ready() {
+                            *this.state = ReceiveRecordsState::Failed;
+                            return Poll::Ready(Some(Err(format!(
+                                "{:?} stream has not been resumed within {:?} after {} bytes",
+                                this.key, this.resume_timeout, this.received
+                            )
+                            .into())));
+                        }
+                        return Poll::Pending;
+                    };
+                    let Some(skip) = this.received.checked_sub(offset) else {
+                        return Poll::Ready(Some(Err(format!(
+                            "{:?} stream can't be resumed from {offset}, only {} bytes were received",
+                            this.key, this.received
+                        )
+                        .into())));
+                    };
+                    tracing::info!("{:?} stream resumed from {offset}", this.key);
+                    *this.skip = skip;
+                    *this.state = ReceiveRecordsState::Ready(stream);
+                }
+                ReceiveRecordsState::Ready(stream) => match stream.poll_next_unpin(cx) {
+                    Poll::Ready(Some(Ok(mut chunk))) => {
+                        if *this.skip > 0 {
+                            let len = usize::try_from(*this.skip)
+                                .map_or(chunk.len(), |skip| skip.min(chunk.len()));
+                            *this.skip -= len as u64;
+                            chunk = chunk.slice(len..);
+                            if chunk.is_empty() {
+                                continue;
+                            }
+                        }
+                        *this.received += chunk.len() as u64;
+                        if *this.received - *this.acked >= RECORDS_ACK_INTERVAL {
+                            *this.acked = *this.received;
+                            (this.ack)(*this.acked);
+                        }
+                        return Poll::Ready(Some(Ok(chunk)));
+                    }
+                    Poll::Ready(Some(Err(err))) => {
+                        tracing::warn!(
+                            "{:?} stream failed after {} bytes, waiting for it to be resumed: {err}",
+                            this.key,
+                            this.received
+                        );
+                        *this.state = ReceiveRecordsState::Resuming(Box::pin(tokio::time::sleep(
+                            *this.resume_timeout,
+                        )));
+                    }
+                    other => return other,
+                },
+                ReceiveRecordsState::Failed => return Poll::Ready(None),
+            }
+        }
     }
 }
 
 /// Inner state for [`ReceiveRecords`] struct
-#[pin_project(project = ReceiveRecordsInnerProj)]
-enum ReceiveRecordsInner<I, S> {
-    Pending(StreamKey<I>, StreamCollection<I, S>),
-    Ready(#[pin] S),
+enum ReceiveRecordsState<S> {
+    /// Waiting for the stream to arrive.
+    Pending,
+    Ready(S),
+    /// The stream failed, waiting for the sender to resume it until the deadline.
+    Resuming(Pin<Box<tokio::time::Sleep>>),
+    /// The stream has not been resumed in time.
+    Failed,
 }
 
-impl<I: TransportIdentity, S: Stream> Stream for ReceiveRecordsInner<I, S> {
-    type Item = S::Item;
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::Duration;
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        loop {
-            match self.as_mut().project() {
-                ReceiveRecordsInnerProj::Pending(key, streams) => {
-                    if let Some(stream) = streams.add_waker(key, cx.waker()) {
-                        self.set(Self::Ready(stream));
-                    } else {
-                        return Poll::Pending;
-                    }
-                }
-                ReceiveRecordsInnerProj::Ready(stream) => return stream.poll_next(cx),
-            }
-        }
+    use bytes::Bytes;
+    use futures::{stream, StreamExt};
+
+    use crate::{
+        error::BoxError,
+        helpers::{
+            transport::{stream::StreamCollection, ReceiveRecords},
+            HelperIdentity,
+        },
+        protocol::{Gate, QueryId},
+    };
+
+    type Chunks = stream::Iter<std::vec::IntoIter<Result<Bytes, BoxError>>>;
+
+    fn key() -> (QueryId, HelperIdentity, Gate) {
+        (QueryId::from(0), HelperIdentity::ONE, Gate::default())
+    }
+
+    fn chunks(chunks: Vec<Result<Bytes, BoxError>>) -> Chunks {
+        stream::iter(chunks)
+    }
+
+    #[tokio::test]
+    async fn resumes_failed_stream() {
+        let streams = StreamCollection::<HelperIdentity, Chunks>::default();
+        let mut records = ReceiveRecords::new(key(), streams.clone(), Box::new(|_| {}));
+        streams.add_stream(
+            key(),
+            chunks(vec![
+                Ok(Bytes::from_static(b"abc")),
+                Err("connection dropped".into()),
+            ]),
+        );
+        assert_eq!(b"abc".as_slice(), records.next().await.unwrap().unwrap());
+
+        streams.add_stream_at(
+            key(),
+            Some(1),
+            chunks(vec![Ok(Bytes::from_static(b"bcde"))]),
+        );
+        assert_eq!(b"de".as_slice(), records.next().await.unwrap().unwrap());
+        assert!(records.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn fails_when_not_resumed_in_time() {
+        let streams = StreamCollection::<HelperIdentity, Chunks>::default();
+        let mut records = ReceiveRecords::new(key(), streams.clone(), Box::new(|_| {}));
+        records.resume_timeout = Duration::from_millis(10);
+        streams.add_stream(
+            key(),
+            chunks(vec![
+                Ok(Bytes::from_static(b"abc")),
+                Err("connection dropped".into()),
+            ]),
+        );
+        assert_eq!(b"abc".as_slice(), records.next().await.unwrap().unwrap());
+
+        let err = records.next().await.unwrap().unwrap_err();
+        assert!(
+            err.to_string().contains("has not been resumed"),
+            "unexpected error: {err}"
+        );
+        assert!(records.next().await.is_none());
     }
 }
@@ -11,6 +11,9 @@ use crate::{
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum RouteId {
     Records,
+    /// Acknowledges the data received from a records stream, so the sender can stop keeping
+    /// it for replay.
+    RecordsAck,
     ReceiveQuery,
     PrepareQuery,
     QueryInput,
@@ -77,6 +80,20 @@ impl<I: TransportIdentity> Addr<I> {
         serde_json::from_str(&self.params)
     }
 
+    /// Offset the records stream sent with this request resumes from, or the offset that
+    /// has been acknowledged by the receiver of a records stream. Streams sent without an
+    /// offset start at the beginning and don't resume another stream.
+    ///
+    /// ## Errors
+    /// If the offset can't be deserialized.
+    pub fn records_offset(&self) -> Result<Option<u64>, serde_json::Error> {
+        if self.params.is_empty() {
+            Ok(None)
+        } else {
+            serde_json::from_str(&self.params).map(Some)
+        }
+    }
+
     #[cfg(all(test, unit_test))]
     pub fn records(from: I, query_id: QueryId, gate: Gate) -> Self {
         Self {
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use tokio::sync::watch;
+
+use crate::{
+    helpers::{transport::stream::StreamKey, TransportIdentity},
+    protocol::QueryId,
+    sync::{Arc, Mutex},
+};
+
+/// Thread-safe collection of acknowledgements for the record streams sent to other peers.
+/// Acknowledgements are indexed by [`StreamKey`], where the identity is the one of the
+/// receiver. Each of them is the offset up to which the receiver has got the stream.
+pub struct StreamAcks<I> {
+    inner: Arc<Mutex<HashMap<StreamKey<I>, watch::Sender<u64>>>>,
+}
+
+impl<I> Default for StreamAcks<I> {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HashMap::default())),
+        }
+    }
+}
+
+impl<I> Clone for StreamAcks<I> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<I: TransportIdentity> StreamAcks<I> {
+    /// Starts tracking acknowledgements for the given stream. Acknowledgements that arrive
+    /// for streams that are not tracked are ignored.
+    ///
+    /// ## Panics
+    /// if mutex is poisoned.
+    pub fn subscribe(&self, key: StreamKey<I>) -> watch::Receiver<u64> {
+        let mut acks = self.inner.lock().unwrap();
+        acks.entry(key)
+            .or_insert_with(|| watch::channel(0).0)
+            .subscribe()
+    }
+
+    /// Records that the receiver has got all bytes of the stream before `offset`. Stale
+    /// acknowledgements, that arrive after the later ones, are ignored.
+    ///
+    /// ## Panics
+    /// if mutex is poisoned.
+    pub fn ack(&self, key: &StreamKey<I>, offset: u64) {
+        let acks = self.inner.lock().unwrap();
+        if let Some(acked) = acks.get(key) {
+            acked.send_if_modified(|acked| {
+                let modified = offset > *acked;
+                *acked = (*acked).max(offset);
+                modified
+            });
+        } else {
+            tracing::debug!("{key:?} acknowledged up to {offset}, but it is not known");
+        }
+    }
+
+    /// Clears up this collection, leaving no acknowledgements inside it.
+    ///
+    /// ## Panics
+    /// if mutex is poisoned.
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+
+    /// Removes acknowledgements for all streams that belong to the given query.
+    ///
+    /// ## Panics
+    /// if mutex is poisoned.
+    pub fn clear_query(&self, query_id: QueryId) {
+        let mut acks = self.inner.lock().unwrap();
+        acks.retain(|(stream_query_id, _, _), _| *stream_query_id != query_id);
+    }
+}
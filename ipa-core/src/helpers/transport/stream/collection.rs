@@ -20,8 +20,9 @@ pub type StreamKey<I> = (QueryId, I, Gate);
 /// Streams are indexed by [`StreamKey`] and the lifecycle of each stream is described by the
 /// [`StreamState`] struct.
 ///
-/// Each stream can be inserted and taken away exactly once, any deviation from this behaviour will
-/// result in panic.
+/// Each stream can be taken away exactly once, any deviation from this behaviour will result
+/// in panic. After that, the sender may reconnect and resume the stream from some offset,
+/// if the connection carrying it dropped. Resumed streams are picked up by [`Self::resume`].
 pub struct StreamCollection<I, S> {
     inner: Arc<Mutex<HashMap<StreamKey<I>, StreamState<S>>>>,
 }
@@ -48,18 +49,38 @@ impl<I: TransportIdentity, S: Stream> StreamCollection<I, S> {
     /// ## Panics
     /// If there was another stream associated with the same key some time in the past.
     pub fn add_stream(&self, key: StreamKey<I>, stream: S) {
+        self.add_stream_at(key, None, stream);
+    }
+
+    /// Adds a stream associated with the given key. Streams that resume another stream after
+    /// the connection carrying it dropped come with `resume_from`, the offset of their first
+    /// byte in the records stream.
+    ///
+    /// Only resumed streams may replace a stream that is ready or has been taken away
+    /// already, as they can only come from a sender that has given up on the old connection.
+    /// Streams that resume a stream this collection doesn't know about past its start, i.e.
+    /// because its query has been cleared already, are dropped.
+    ///
+    /// ## Panics
+    /// If `resume_from` is not set and there was another stream associated with the same key
+    /// some time in the past.
+    pub fn add_stream_at(&self, key: StreamKey<I>, resume_from: Option<u64>, stream: S) {
         let mut streams = self.inner.lock().unwrap();
+        let offset = resume_from.unwrap_or_default();
         match streams.entry(key) {
             Entry::Occupied(mut entry) => match entry.get_mut() {
                 rs @ StreamState::Waiting(_) => {
                     let StreamState::Waiting(waker) =
-                        std::mem::replace(rs, StreamState::Ready(stream))
+                        std::mem::replace(rs, StreamState::Ready(offset, stream))
                     else {
                         unreachable!()
                     };
                     waker.wake();
                 }
-                rs @ (StreamState::Ready(_) | StreamState::Completed) => {
+                rs @ (StreamState::Ready(..) | StreamState::Completed) if resume_from.is_some() => {
+                    *rs = StreamState::Ready(offset, stream);
+                }
+                rs @ (StreamState::Ready(..) | StreamState::Completed) => {
                     let state = format!("{rs:?}");
                     let key = entry.key().clone();
                     drop(streams);
@@ -67,7 +88,14 @@ impl<I: TransportIdentity, S: Stream> StreamCollection<I, S> {
                 }
             },
             Entry::Vacant(entry) => {
-                entry.insert(StreamState::Ready(stream));
+                if offset > 0 {
+                    tracing::warn!(
+                        "{:?} stream can't be resumed from {offset}, it is not known",
+                        entry.key()
+                    );
+                    return;
+                }
+                entry.insert(StreamState::Ready(offset, stream));
             }
         }
     }
@@ -86,8 +114,9 @@ impl<I: TransportIdentity, S: Stream> StreamCollection<I, S> {
                     old_waker.clone_from(waker);
                     None
                 }
-                rs @ StreamState::Ready(_) => {
-                    let StreamState::Ready(stream) = std::mem::replace(rs, StreamState::Completed)
+                rs @ StreamState::Ready(..) => {
+                    let StreamState::Ready(_, stream) =
+                        std::mem::replace(rs, StreamState::Completed)
                     else {
                         unreachable!();
                     };
@@ -106,6 +135,38 @@ impl<I: TransportIdentity, S: Stream> StreamCollection<I, S> {
         }
     }
 
+    /// Takes the stream that was resumed by the sender after the one taken before got
+    /// disconnected. If it hasn't arrived yet, `waker` is notified when it does.
+    /// Returns the resumed stream along with the offset it starts at.
+    ///
+    /// ## Panics
+    /// if mutex is poisoned.
+    pub fn resume(&self, key: &StreamKey<I>, waker: &Waker) -> Option<(u64, S)> {
+        let mut streams = self.inner.lock().unwrap();
+        let state = streams
+            .entry(key.clone())
+            .or_insert_with(|| StreamState::Waiting(waker.clone()));
+        match state {
+            StreamState::Waiting(old_waker) => {
+                old_waker.clone_from(waker);
+                None
+            }
+            StreamState::Completed => {
+                *state = StreamState::Waiting(waker.clone());
+                None
+            }
+            StreamState::Ready(..) => {
+                let StreamState::Ready(offset, stream) =
+                    std::mem::replace(state, StreamState::Completed)
+                else {
+                    unreachable!();
+                };
+
+                Some((offset, stream))
+            }
+        }
+    }
+
     /// Clears up this collection, leaving no streams inside it.
     ///
     /// ## Panics
@@ -115,6 +176,16 @@ impl<I: TransportIdentity, S: Stream> StreamCollection<I, S> {
         streams.clear();
     }
 
+    /// Removes all streams that belong to the given query, leaving streams of other queries
+    /// intact.
+    ///
+    /// ## Panics
+    /// if mutex is poisoned.
+    pub fn clear_query(&self, query_id: QueryId) {
+        let mut streams = self.inner.lock().unwrap();
+        streams.retain(|(stream_query_id, _, _), _| *stream_query_id != query_id);
+    }
+
     /// Returns the number of streams inside this collection.
     ///
     /// ## Panics
@@ -140,10 +211,10 @@ impl<I: TransportIdentity, S: Stream> StreamCollection<I, S> {
 enum StreamState<S> {
     /// There was a request to receive this stream, but it hasn't arrived yet
     Waiting(Waker),
-    /// Stream is ready to be consumed
-    Ready(S),
+    /// Stream is ready to be consumed, starting at the given offset
+    Ready(u64, S),
     /// Stream was successfully received and taken away from [`StreamCollection`].
-    /// It may not be requested or received again.
+    /// It may not be requested again, but it can be resumed by the sender.
     Completed,
 }
 
@@ -153,8 +224,8 @@ impl<S> Debug for StreamState<S> {
             StreamState::Waiting(_) => {
                 write!(f, "Waiting")
             }
-            StreamState::Ready(_) => {
-                write!(f, "Ready")
+            StreamState::Ready(offset, _) => {
+                write!(f, "Ready({offset})")
             }
             StreamState::Completed => {
                 write!(f, "Completed")
@@ -162,3 +233,62 @@ impl<S> Debug for StreamState<S> {
         }
     }
 }
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use futures::{
+        stream::{self, Empty},
+        task::noop_waker,
+    };
+
+    use crate::{
+        helpers::{transport::stream::StreamCollection, HelperIdentity},
+        protocol::{Gate, QueryId},
+    };
+
+    type Collection = StreamCollection<HelperIdentity, Empty<()>>;
+
+    fn key() -> (QueryId, HelperIdentity, Gate) {
+        (QueryId::from(0), HelperIdentity::ONE, Gate::default())
+    }
+
+    #[test]
+    #[should_panic(expected = "entry state expected to be waiting, got \"Ready(0)\"")]
+    fn rejects_duplicate_streams() {
+        let streams = Collection::default();
+        streams.add_stream(key(), stream::empty());
+        streams.add_stream(key(), stream::empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "entry state expected to be waiting, got \"Completed\"")]
+    fn rejects_streams_after_completion() {
+        let streams = Collection::default();
+        streams.add_stream(key(), stream::empty());
+        assert!(streams.add_waker(&key(), &noop_waker()).is_some());
+        streams.add_stream(key(), stream::empty());
+    }
+
+    #[test]
+    fn replaces_resumed_streams() {
+        let streams = Collection::default();
+        streams.add_stream(key(), stream::empty());
+        assert!(streams.add_waker(&key(), &noop_waker()).is_some());
+
+        streams.add_stream_at(key(), Some(0), stream::empty());
+        streams.add_stream_at(key(), Some(10), stream::empty());
+        assert_eq!(
+            Some(10),
+            streams
+                .resume(&key(), &noop_waker())
+                .map(|(offset, _)| offset)
+        );
+    }
+
+    #[test]
+    fn drops_unknown_resumed_streams() {
+        let streams = Collection::default();
+        streams.add_stream_at(key(), Some(10), stream::empty());
+        assert!(streams.is_empty());
+    }
+}
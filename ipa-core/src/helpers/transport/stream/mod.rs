@@ -1,3 +1,4 @@
+mod acks;
 #[cfg(feature = "web-app")]
 mod axum_body;
 mod box_body;
@@ -11,6 +12,7 @@ use std::{
     task::{Context, Poll},
 };
 
+pub use acks::StreamAcks;
 #[cfg(feature = "web-app")]
 pub use axum_body::WrappedAxumBodyStream;
 pub use box_body::WrappedBoxBodyStream;
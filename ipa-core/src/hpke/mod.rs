@@ -17,7 +17,8 @@ mod registry;
 
 pub use info::Info;
 pub use registry::{
-    KeyPair, KeyRegistry, PrivateKeyOnly, PrivateKeyRegistry, PublicKeyOnly, PublicKeyRegistry,
+    KeyPair, KeyRegistry, KeyValidity, PrivateKeyOnly, PrivateKeyRegistry, PublicKeyOnly,
+    PublicKeyRegistry, DEFAULT_EXPIRED_KEY_GRACE_PERIOD,
 };
 
 use crate::{
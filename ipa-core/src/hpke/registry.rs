@@ -1,4 +1,7 @@
-use std::ops::Deref;
+use std::{
+    ops::Deref,
+    time::{Duration, SystemTime},
+};
 
 use hpke::Serializable;
 
@@ -79,17 +82,66 @@ pub trait PublicKeyRegistry {
 
 pub trait PrivateKeyRegistry: Send + Sync + 'static {
     fn private_key(&self, key_id: KeyIdentifier) -> Option<&IpaPrivateKey>;
+
+    /// Returns `true` if the key with the given identifier must not be used at `time`, because
+    /// its validity period has not started yet, or ended longer ago than the registry's grace
+    /// period. Registries that do not track validity periods never expire their keys.
+    fn is_expired(&self, _key_id: KeyIdentifier, _time: SystemTime) -> bool {
+        false
+    }
+}
+
+/// Reports are encrypted when the events happen, but a report collector submits them to a query
+/// only later. Unless configured otherwise, helpers keep decrypting reports encrypted under a key
+/// for this long after the end of its validity period, so reports encrypted just before the key
+/// expired can still be used.
+pub const DEFAULT_EXPIRED_KEY_GRACE_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Period of time during which a key may be used to encrypt reports. Both bounds are
+/// inclusive; a missing bound means the period is open on that side.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyValidity {
+    pub not_before: Option<SystemTime>,
+    pub not_after: Option<SystemTime>,
+}
+
+impl KeyValidity {
+    #[must_use]
+    pub fn contains(&self, time: SystemTime) -> bool {
+        self.not_before.is_none_or(|t| t <= time) && self.not_after.is_none_or(|t| time <= t)
+    }
+
+    /// Returns `true` if reports encrypted under the key may be decrypted at `time`, which is
+    /// the case during the validity period and for `grace_period` after it.
+    #[must_use]
+    pub fn accepts_reports_at(&self, time: SystemTime, grace_period: Duration) -> bool {
+        self.not_before.is_none_or(|t| t <= time)
+            && self
+                .not_after
+                .and_then(|t| t.checked_add(grace_period))
+                .is_none_or(|t| time <= t)
+    }
+}
+
+#[derive(Clone)]
+struct KeyEntry<K> {
+    key: K,
+    validity: KeyValidity,
 }
 
-/// A registry that holds all the keys available for helper/UA to use.
+/// A registry that holds all the keys available for helper/UA to use. Keys are looked up by
+/// their identifier, which does not need to be contiguous when keys are rotated.
 pub struct KeyRegistry<K> {
-    keys: Box<[K]>,
+    keys: Box<[Option<KeyEntry<K>>]>,
+    /// How long after the end of its validity period a key is still used to decrypt reports.
+    grace_period: Duration,
 }
 
 impl<K: Clone> Clone for KeyRegistry<K> {
     fn clone(&self) -> Self {
         Self {
             keys: self.keys.clone(),
+            grace_period: self.grace_period,
         }
     }
 }
@@ -99,31 +151,86 @@ impl<K> KeyRegistry<K> {
     /// but this avoids `Option<KeyRegistry>` when the registry is ultimately not optional.
     #[must_use]
     pub fn empty() -> Self {
-        Self { keys: Box::new([]) }
+        Self {
+            keys: Box::new([]),
+            grace_period: DEFAULT_EXPIRED_KEY_GRACE_PERIOD,
+        }
     }
 
+    /// Create a registry where the key at position `i` has key identifier `i` and never expires.
     pub fn from_keys<const N: usize>(pairs: [K; N]) -> Self {
         Self {
-            keys: pairs.into_iter().collect::<Vec<_>>().into_boxed_slice(),
+            keys: pairs
+                .into_iter()
+                .map(|key| {
+                    Some(KeyEntry {
+                        key,
+                        validity: KeyValidity::default(),
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_boxed_slice(),
+            grace_period: DEFAULT_EXPIRED_KEY_GRACE_PERIOD,
         }
     }
 
-    fn key(&self, key_id: KeyIdentifier) -> Option<&K> {
-        match key_id as usize {
-            key_id if key_id < self.keys.len() => Some(&self.keys[key_id]),
-            _ => None,
+    /// Create a registry from keys with explicit identifiers and validity periods.
+    ///
+    /// # Panics
+    /// If the same key identifier is used more than once.
+    pub fn from_entries<I: IntoIterator<Item = (KeyIdentifier, K, KeyValidity)>>(
+        entries: I,
+    ) -> Self {
+        let mut keys = Vec::new();
+        for (key_id, key, validity) in entries {
+            let idx = usize::from(key_id);
+            if keys.len() <= idx {
+                keys.resize_with(idx + 1, || None);
+            }
+            assert!(keys[idx].is_none(), "duplicate key identifier {key_id}");
+            keys[idx] = Some(KeyEntry { key, validity });
+        }
+
+        Self {
+            keys: keys.into_boxed_slice(),
+            grace_period: DEFAULT_EXPIRED_KEY_GRACE_PERIOD,
         }
     }
+
+    /// Sets how long after the end of their validity period keys are still used to decrypt
+    /// reports. Defaults to [`DEFAULT_EXPIRED_KEY_GRACE_PERIOD`].
+    #[must_use]
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    fn entry(&self, key_id: KeyIdentifier) -> Option<&KeyEntry<K>> {
+        self.keys.get(usize::from(key_id)).and_then(Option::as_ref)
+    }
+
+    fn key(&self, key_id: KeyIdentifier) -> Option<&K> {
+        self.entry(key_id).map(|entry| &entry.key)
+    }
+
+    fn is_valid_at(&self, key_id: KeyIdentifier, time: SystemTime) -> bool {
+        self.entry(key_id)
+            .is_none_or(|entry| entry.validity.accepts_reports_at(time, self.grace_period))
+    }
 }
 
 impl KeyRegistry<KeyPair> {
+    /// # Panics
+    /// If `keys_count` exceeds the number of available key identifiers.
     #[cfg(any(test, feature = "test-fixture"))]
     pub fn random<R: rand::RngCore + rand::CryptoRng>(keys_count: usize, r: &mut R) -> Self {
-        let keys = (0..keys_count).map(|_| KeyPair::gen(r)).collect::<Vec<_>>();
-
-        Self {
-            keys: keys.into_boxed_slice(),
-        }
+        Self::from_entries((0..keys_count).map(|key_id| {
+            (
+                KeyIdentifier::try_from(key_id).unwrap(),
+                KeyPair::gen(r),
+                KeyValidity::default(),
+            )
+        }))
     }
 }
 
@@ -132,6 +239,10 @@ impl PrivateKeyRegistry for KeyRegistry<KeyPair> {
     fn private_key(&self, key_id: KeyIdentifier) -> Option<&IpaPrivateKey> {
         self.key(key_id).map(|v| &v.sk)
     }
+
+    fn is_expired(&self, key_id: KeyIdentifier, time: SystemTime) -> bool {
+        !self.is_valid_at(key_id, time)
+    }
 }
 
 impl PrivateKeyRegistry for KeyRegistry<PrivateKeyOnly> {
@@ -139,6 +250,10 @@ impl PrivateKeyRegistry for KeyRegistry<PrivateKeyOnly> {
     fn private_key(&self, key_id: KeyIdentifier) -> Option<&IpaPrivateKey> {
         self.key(key_id).map(|sk| &**sk)
     }
+
+    fn is_expired(&self, key_id: KeyIdentifier, time: SystemTime) -> bool {
+        !self.is_valid_at(key_id, time)
+    }
 }
 
 impl PublicKeyRegistry for KeyRegistry<KeyPair> {
@@ -155,6 +270,8 @@ impl PublicKeyRegistry for KeyRegistry<PublicKeyOnly> {
 
 #[cfg(all(test, unit_test))]
 mod tests {
+    use std::time::Duration;
+
     use hpke::{HpkeError, OpModeR, OpModeS};
     use rand::rngs::StdRng;
     use rand_core::{CryptoRng, RngCore, SeedableRng};
@@ -226,4 +343,91 @@ mod tests {
             decrypt(private_registry.private_key(0).unwrap(), &ct_payload).unwrap_err()
         );
     }
+
+    #[test]
+    fn rotated_keys() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let epoch = SystemTime::UNIX_EPOCH;
+        let day = Duration::from_secs(24 * 60 * 60);
+        let old = KeyPair::gen(&mut rng);
+        let current = KeyPair::gen(&mut rng);
+        let old_pk = old.pk_bytes();
+
+        let registry = KeyRegistry::<KeyPair>::from_entries([
+            (
+                3,
+                old,
+                KeyValidity {
+                    not_before: None,
+                    not_after: Some(epoch + day),
+                },
+            ),
+            (
+                7,
+                current,
+                KeyValidity {
+                    not_before: Some(epoch + day),
+                    not_after: None,
+                },
+            ),
+        ]);
+
+        assert!(registry.private_key(0).is_none());
+        assert!(registry.private_key(8).is_none());
+        assert_eq!(
+            old_pk,
+            Box::from(<[u8; 32]>::from(registry.public_key(3).unwrap().to_bytes()))
+        );
+
+        assert!(!registry.is_expired(3, epoch));
+        // reports encrypted before the key expired are accepted for a grace period
+        assert!(!registry.is_expired(3, epoch + 2 * day));
+        assert!(!registry.is_expired(3, epoch + day + DEFAULT_EXPIRED_KEY_GRACE_PERIOD));
+        assert!(registry.is_expired(
+            3,
+            epoch + day + DEFAULT_EXPIRED_KEY_GRACE_PERIOD + Duration::from_secs(1)
+        ));
+        assert!(registry.is_expired(7, epoch));
+        assert!(!registry.is_expired(7, epoch + day));
+        assert!(!registry.is_expired(7, epoch + 2 * day));
+        // unknown keys are reported as missing, not expired
+        assert!(!registry.is_expired(5, epoch));
+    }
+
+    #[test]
+    fn configured_grace_period() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let epoch = SystemTime::UNIX_EPOCH;
+        let hour = Duration::from_secs(60 * 60);
+        let registry = KeyRegistry::<KeyPair>::from_entries([(
+            0,
+            KeyPair::gen(&mut rng),
+            KeyValidity {
+                not_before: None,
+                not_after: Some(epoch + hour),
+            },
+        )])
+        .with_grace_period(hour);
+
+        assert!(!registry.is_expired(0, epoch + hour));
+        assert!(!registry.is_expired(0, epoch + 2 * hour - Duration::from_secs(1)));
+        assert!(!registry.is_expired(0, epoch + 2 * hour));
+        assert!(registry.is_expired(0, epoch + 2 * hour + Duration::from_secs(1)));
+        assert!(registry.is_expired(0, epoch + DEFAULT_EXPIRED_KEY_GRACE_PERIOD));
+
+        // without a grace period, keys expire as soon as their validity period ends
+        let registry = registry.with_grace_period(Duration::ZERO);
+        assert!(!registry.is_expired(0, epoch + hour));
+        assert!(registry.is_expired(0, epoch + hour + Duration::from_secs(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate key identifier 1")]
+    fn duplicate_key_ids() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let _ = KeyRegistry::<KeyPair>::from_entries([
+            (1, KeyPair::gen(&mut rng), KeyValidity::default()),
+            (1, KeyPair::gen(&mut rng), KeyValidity::default()),
+        ]);
+    }
 }
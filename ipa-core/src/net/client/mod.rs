@@ -36,7 +36,9 @@ use crate::{
         query::{CompareStatusRequest, PrepareQuery, QueryConfig, QueryInput},
         TransportIdentity,
     },
-    net::{error::ShardQueryStatusMismatchError, http_serde, Error, CRYPTO_PROVIDER},
+    net::{
+        error::ShardQueryStatusMismatchError, http_serde, trace_context, Error, CRYPTO_PROVIDER,
+    },
     protocol::{Gate, QueryId},
 };
 
@@ -308,6 +310,7 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
         if let Some((k, v)) = self.auth_header.clone() {
             req.headers_mut().insert(k, v);
         }
+        trace_context::inject(req.headers_mut());
         ResponseFuture {
             authority: self.authority.clone(),
             inner: self.client.request(req),
@@ -343,7 +346,8 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
 
     /// Sends a batch of messages associated with a query's step to another helper. Messages are a
     /// contiguous block of records. Also includes [`crate::protocol::RecordId`] information and
-    /// [`crate::helpers::network::ChannelId`].
+    /// [`crate::helpers::network::ChannelId`]. `resume_from` is the position of the first byte of
+    /// `data` in the stream, if the stream is resumed after a dropped connection.
     /// # Errors
     /// If the request has illegal arguments, or fails to deliver to helper
     /// # Panics
@@ -352,15 +356,32 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
         &self,
         query_id: QueryId,
         gate: &Gate,
+        resume_from: Option<u64>,
         data: S,
     ) -> Result<ResponseFuture, Error> {
         let data = data.map(|v| Ok::<bytes::Bytes, Error>(Bytes::from(v)));
         let body = axum::body::Body::from_stream(data);
-        let req = http_serde::query::step::Request::new(query_id, gate.clone(), body);
+        let req = http_serde::query::step::Request::new(query_id, gate.clone(), resume_from, body);
         let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
         Ok(self.request(req))
     }
 
+    /// Tells the helper that sends the records stream of the given step that all bytes of it
+    /// before `offset` have been received, so it doesn't need to keep them for replay.
+    /// # Errors
+    /// If the request has illegal arguments, or fails to deliver to helper
+    pub async fn records_ack(
+        &self,
+        query_id: QueryId,
+        gate: &Gate,
+        offset: u64,
+    ) -> Result<(), Error> {
+        let req = http_serde::query::ack::Request::new(query_id, gate.clone(), offset);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        resp_ok(resp).await
+    }
+
     /// Used to communicate from one helper to another. Specifically, the helper that receives a
     /// "create query" from an external party must communicate the intent to start a query to the
     /// other helpers, which this prepare query does.
@@ -385,6 +406,18 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
         resp_ok(resp).await
     }
 
+    /// Kills the query on the receiving helper or shard. It is used to cancel a query on peers,
+    /// once it misses a deadline.
+    ///
+    /// # Errors
+    /// If the request has illegal arguments, or fails to be delivered
+    pub async fn kill_query(&self, query_id: QueryId) -> Result<(), Error> {
+        let req = http_serde::query::kill::Request::new(query_id);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        resp_ok(resp).await
+    }
+
     /// This API is used by leader shards in MPC to request query status information on peers.
     /// If a given peer has status that doesn't match the one provided by the leader, it responds
     /// with 412 error and encodes its status inside the response body. Otherwise, 200 is returned.
@@ -561,12 +594,15 @@ pub(crate) mod tests {
     use crate::{
         ff::{FieldType, Fp31},
         helpers::{
-            make_owned_handler, query::QueryType::TestMultiply, BytesStream, HelperIdentity,
-            HelperResponse, RequestHandler, RoleAssignment, MESSAGE_PAYLOAD_SIZE_BYTES,
+            make_owned_handler,
+            query::{QueryType::TestMultiply, ReportCollector},
+            routing::RouteId,
+            BytesStream, HelperIdentity, HelperResponse, RequestHandler, RoleAssignment,
+            MESSAGE_PAYLOAD_SIZE_BYTES,
         },
         net::test::TestServer,
         protocol::step::TestExecutionStep,
-        query::ProtocolResult,
+        query::{ProtocolResult, QueryKilled},
         secret_sharing::replicated::semi_honest::AdditiveShare as Replicated,
         sync::Arc,
     };
@@ -658,7 +694,7 @@ pub(crate) mod tests {
 
     #[tokio::test]
     async fn create() {
-        let expected_query_id = QueryId;
+        let expected_query_id = QueryId::from(0);
         let expected_query_config = QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap();
 
         let handler = || {
@@ -670,6 +706,7 @@ pub(crate) mod tests {
                     query_id: expected_query_id,
                     config: query_config,
                     roles: RoleAssignment::new(HelperIdentity::make_three()),
+                    report_collector: None,
                 }))
             })
         };
@@ -687,9 +724,10 @@ pub(crate) mod tests {
         let handler = move || {
             make_owned_handler(move |addr, _| async move {
                 let input = PrepareQuery {
-                    query_id: QueryId,
+                    query_id: QueryId::from(0),
                     config,
                     roles: RoleAssignment::new(HelperIdentity::make_three()),
+                    report_collector: Some(ReportCollector("rc".to_string())),
                 };
                 let prepare_query = addr.into::<PrepareQuery>().unwrap();
                 assert_eq!(prepare_query, input);
@@ -701,9 +739,10 @@ pub(crate) mod tests {
         test_query_command(
             |client| {
                 let req = PrepareQuery {
-                    query_id: QueryId,
+                    query_id: QueryId::from(0),
                     config,
                     roles: RoleAssignment::new(HelperIdentity::make_three()),
+                    report_collector: Some(ReportCollector("rc".to_string())),
                 };
                 async move { client.prepare_query(req).await.unwrap() }
             },
@@ -714,7 +753,7 @@ pub(crate) mod tests {
 
     #[tokio::test]
     async fn input() {
-        let expected_query_id = QueryId;
+        let expected_query_id = QueryId::from(0);
         let expected_input = &[8u8; 25];
         let handler = move || {
             make_owned_handler(move |addr, data| async move {
@@ -742,7 +781,7 @@ pub(crate) mod tests {
         let TestServer {
             client, transport, ..
         } = TestServer::builder().build().await;
-        let expected_query_id = QueryId;
+        let expected_query_id = QueryId::from(0);
         let expected_step = Gate::default().narrow(&TestExecutionStep::Iter(0));
         let expected_payload = vec![7u8; MESSAGE_PAYLOAD_SIZE_BYTES];
 
@@ -750,6 +789,7 @@ pub(crate) mod tests {
             .step(
                 expected_query_id,
                 &expected_step,
+                None,
                 once(ready(expected_payload.clone())),
             )
             .unwrap()
@@ -759,7 +799,10 @@ pub(crate) mod tests {
         resp_ok(resp).await.unwrap();
 
         let mut stream = transport
-            .receive(HelperIdentity::ONE, &(QueryId, expected_step.clone()))
+            .receive(
+                HelperIdentity::ONE,
+                &(QueryId::from(0), expected_step.clone()),
+            )
             .into_bytes_stream();
 
         assert_eq!(
@@ -774,7 +817,7 @@ pub(crate) mod tests {
             Fp31::try_from(1u128).unwrap(),
             Fp31::try_from(2u128).unwrap(),
         ];
-        let expected_query_id = QueryId;
+        let expected_query_id = QueryId::from(0);
         let handler = move || {
             make_owned_handler(move |addr, _| async move {
                 let results: Box<dyn ProtocolResult> = Box::new(
@@ -796,4 +839,21 @@ pub(crate) mod tests {
                 .to_bytes()
         );
     }
+
+    #[tokio::test]
+    async fn kill() {
+        let expected_query_id = QueryId::from(0);
+        let handler = move || {
+            make_owned_handler(move |addr, _| async move {
+                assert_eq!(addr.route, RouteId::KillQuery);
+                assert_eq!(addr.query_id, Some(expected_query_id));
+                Ok(HelperResponse::from(QueryKilled(expected_query_id)))
+            })
+        };
+        test_query_command(
+            |client| async move { client.kill_query(expected_query_id).await.unwrap() },
+            handler,
+        )
+        .await;
+    }
 }
@@ -28,7 +28,7 @@ pub enum Error {
     BadPathString(#[source] BoxError),
     #[error(transparent)]
     MissingExtension(#[from] axum::extract::rejection::ExtensionRejection),
-    #[error("query id not found: {}", .0.as_ref())]
+    #[error("query id not found: {0}")]
     QueryIdNotFound(QueryId),
     #[error(transparent)]
     HyperPassthrough(#[from] hyper::Error),
@@ -60,6 +60,12 @@ pub enum Error {
         #[source]
         inner: hyper_util::client::legacy::Error,
     },
+    #[error("Failed to read query input from {path:?}: {inner}")]
+    QueryInputFile {
+        path: std::path::PathBuf,
+        #[source]
+        inner: std::io::Error,
+    },
     #[error("{code}: {error}")]
     Application { code: StatusCode, error: BoxError },
     #[error(transparent)]
@@ -168,7 +174,8 @@ impl IntoResponse for Error {
             | Self::InvalidJsonBody(_)
             | Self::InvalidBytesBody(_)
             | Self::QueryIdNotFound(_)
-            | Self::ConnectError { .. } => StatusCode::BAD_REQUEST,
+            | Self::ConnectError { .. }
+            | Self::QueryInputFile { .. } => StatusCode::BAD_REQUEST,
 
             Self::HyperPassthrough { .. }
             | Self::HyperHttpPassthrough(_)
@@ -76,10 +76,16 @@ pub mod metrics {
     pub struct Request {}
 
     pub const AXUM_PATH: &str = "/metrics";
+
+    /// Content type of the Prometheus text exposition format.
+    pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
 }
 
 pub mod query {
-    use std::fmt::{Display, Formatter};
+    use std::{
+        fmt::{Display, Formatter},
+        time::Duration,
+    };
 
     use async_trait::async_trait;
     use axum::{
@@ -91,7 +97,7 @@ pub mod query {
 
     use crate::{
         ff::FieldType,
-        helpers::query::{QueryConfig, QuerySize, QueryType},
+        helpers::query::{QueryConfig, QueryDeadlines, QuerySize, QueryType},
         net::Error,
     };
 
@@ -120,11 +126,15 @@ pub mod query {
                 size: QuerySize,
                 field_type: FieldType,
                 query_type: String,
+                input_deadline_ms: Option<u64>,
+                total_deadline_ms: Option<u64>,
             }
             let Query(QueryTypeParam {
                 size,
                 field_type,
                 query_type,
+                input_deadline_ms,
+                total_deadline_ms,
             }) = req.extract().await?;
 
             let query_type = match query_type.as_str() {
@@ -146,12 +156,20 @@ pub mod query {
                     let Query(q) = req.extract().await?;
                     Ok(QueryType::MaliciousHybrid(q))
                 }
+                QueryType::MALICIOUS_WALR_STR => {
+                    let Query(q) = req.extract().await?;
+                    Ok(QueryType::MaliciousWalr(q))
+                }
                 other => Err(Error::bad_query_value("query_type", other)),
             }?;
             Ok(QueryConfigQueryParams(QueryConfig {
                 size,
                 field_type,
                 query_type,
+                deadlines: QueryDeadlines {
+                    inputs: input_deadline_ms.map(Duration::from_millis),
+                    total: total_deadline_ms.map(Duration::from_millis),
+                },
             }))
         }
     }
@@ -165,6 +183,12 @@ pub mod query {
                 f = self.field_type,
                 size = self.size
             )?;
+            if let Some(deadline) = self.deadlines.inputs {
+                write!(f, "&input_deadline_ms={}", deadline.as_millis())?;
+            }
+            if let Some(deadline) = self.deadlines.total {
+                write!(f, "&total_deadline_ms={}", deadline.as_millis())?;
+            }
             match self.query_type {
                 #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
                 QueryType::TestMultiply | QueryType::TestAddInPrimeField => Ok(()),
@@ -173,11 +197,16 @@ pub mod query {
                 QueryType::SemiHonestOprfIpa(config) | QueryType::MaliciousOprfIpa(config) => {
                     write!(
                         f,
-                        "&per_user_credit_cap={}&max_breakdown_key={}&with_dp={}&epsilon={}",
+                        "&per_user_credit_cap={}&max_breakdown_key={}&dp_mechanism={}\
+                         &attribution_model={}&histogram_output={}\
+                         &oprf_padding={}&aggregation_padding={}",
                         config.per_user_credit_cap,
                         config.max_breakdown_key,
-                        config.with_dp,
-                        config.epsilon,
+                        config.dp_mechanism,
+                        config.attribution_model,
+                        config.histogram_output,
+                        config.oprf_padding,
+                        config.aggregation_padding,
                     )?;
 
                     if config.plaintext_match_keys {
@@ -193,16 +222,40 @@ pub mod query {
                 QueryType::MaliciousHybrid(config) => {
                     write!(
                         f,
-                        "&max_breakdown_key={}&with_dp={}&epsilon={}",
-                        config.max_breakdown_key, config.with_dp, config.epsilon,
+                        "&max_breakdown_key={}&per_user_credit_cap={}&dp_mechanism={}\
+                         &breakdown_key_bits={}&value_bits={}\
+                         &oprf_padding={}&aggregation_padding={}&aggregation_mode={}\
+                         &histogram_output={}",
+                        config.max_breakdown_key,
+                        config.per_user_credit_cap,
+                        config.dp_mechanism,
+                        config.breakdown_key_bits,
+                        config.value_bits,
+                        config.oprf_padding,
+                        config.aggregation_padding,
+                        config.aggregation_mode,
+                        config.histogram_output,
                     )?;
 
                     if config.plaintext_match_keys {
                         write!(f, "&plaintext_match_keys=true")?;
                     }
 
+                    if let Some(window) = config.attribution_window_seconds {
+                        write!(f, "&attribution_window_seconds={}", window.get())?;
+                    }
+
+                    if let Some(dimensions) = config.breakdown_dimensions {
+                        write!(f, "&breakdown_dimensions={dimensions}")?;
+                    }
+
                     Ok(())
                 }
+                QueryType::MaliciousWalr(config) => write!(
+                    f,
+                    "&features={}&model_weights={}&dp_mechanism={}&oprf_padding={}",
+                    config.features, config.model_weights, config.dp_mechanism, config.oprf_padding,
+                ),
             }
         }
     }
@@ -271,7 +324,10 @@ pub mod query {
         use serde::{Deserialize, Serialize};
 
         use crate::{
-            helpers::{query::PrepareQuery, RoleAssignment},
+            helpers::{
+                query::{PrepareQuery, ReportCollector},
+                RoleAssignment,
+            },
             net::{
                 http_serde::query::{QueryConfigQueryParams, BASE_AXUM_PATH},
                 APPLICATION_JSON,
@@ -298,12 +354,13 @@ pub mod query {
                     .path_and_query(format!(
                         "{}/{}?{}",
                         BASE_AXUM_PATH,
-                        self.data.query_id.as_ref(),
+                        self.data.query_id,
                         QueryConfigQueryParams(self.data.config),
                     ))
                     .build()?;
                 let body = RequestBody {
                     roles: self.data.roles,
+                    report_collector: self.data.report_collector,
                 };
                 let body = serde_json::to_string(&body)?;
                 let body = Body::from(body);
@@ -316,6 +373,8 @@ pub mod query {
         #[derive(Serialize, Deserialize)]
         pub struct RequestBody {
             pub roles: RoleAssignment,
+            #[serde(default)]
+            pub report_collector: Option<ReportCollector>,
         }
 
         pub const AXUM_PATH: &str = "/:query_id";
@@ -328,16 +387,13 @@ pub mod query {
             extract::FromRequestParts,
             http::{request::Parts, uri},
         };
-        use hyper::{
-            header::{HeaderValue, CONTENT_TYPE},
-            Uri,
-        };
+        use hyper::header::{HeaderValue, CONTENT_TYPE};
 
         use crate::{
             helpers::query::QueryInput,
             net::{
-                http_serde::query::BASE_AXUM_PATH, Error, APPLICATION_OCTET_STREAM,
-                HTTP_QUERY_INPUT_URL_HEADER,
+                http_serde::query::BASE_AXUM_PATH, query_input::QueryInputSource, Error,
+                APPLICATION_OCTET_STREAM, HTTP_QUERY_INPUT_URL_HEADER,
             },
         };
 
@@ -362,18 +418,18 @@ pub mod query {
                     .path_and_query(format!(
                         "{}/{}/input",
                         BASE_AXUM_PATH,
-                        self.query_input.query_id().as_ref(),
+                        self.query_input.query_id(),
                     ))
                     .build()?;
-                let query_input_url = self.query_input.url().map(ToOwned::to_owned);
+                let query_input_urls = self.query_input.urls().to_vec();
                 let body = self
                     .query_input
                     .input_stream()
                     .map_or_else(Body::empty, Body::from_stream);
                 let mut request =
                     hyper::Request::post(uri).header(CONTENT_TYPE, APPLICATION_OCTET_STREAM);
-                if let Some(url) = query_input_url {
-                    request.headers_mut().unwrap().insert(
+                for url in query_input_urls {
+                    request.headers_mut().unwrap().append(
                         &HTTP_QUERY_INPUT_URL_HEADER,
                         HeaderValue::try_from(url).unwrap(),
                     );
@@ -384,29 +440,29 @@ pub mod query {
 
         pub const AXUM_PATH: &str = "/:query_id/input";
 
-        pub struct QueryInputUrl(Option<Uri>);
+        /// Locations to read the query input from, in the order they appear in the request
+        /// headers. Empty if the input is sent inline.
+        pub struct QueryInputUrls(Vec<QueryInputSource>);
 
         #[async_trait]
-        impl<S: Send + Sync> FromRequestParts<S> for QueryInputUrl {
+        impl<S: Send + Sync> FromRequestParts<S> for QueryInputUrls {
             type Rejection = Error;
 
             async fn from_request_parts(
                 req: &mut Parts,
                 _state: &S,
             ) -> Result<Self, Self::Rejection> {
-                match req.headers.get(&HTTP_QUERY_INPUT_URL_HEADER) {
-                    None => Ok(QueryInputUrl(None)),
-                    Some(value) => {
-                        let value_str = value.to_str()?;
-                        let uri = value_str.parse()?;
-                        Ok(QueryInputUrl(Some(uri)))
-                    }
-                }
+                req.headers
+                    .get_all(&HTTP_QUERY_INPUT_URL_HEADER)
+                    .iter()
+                    .map(|value| value.to_str()?.parse())
+                    .collect::<Result<_, _>>()
+                    .map(QueryInputUrls)
             }
         }
 
-        impl From<QueryInputUrl> for Option<Uri> {
-            fn from(value: QueryInputUrl) -> Self {
+        impl From<QueryInputUrls> for Vec<QueryInputSource> {
+            fn from(value: QueryInputUrls) -> Self {
                 value.0
             }
         }
@@ -414,6 +470,7 @@ pub mod query {
 
     pub mod step {
         use axum::{body::Body, http::uri};
+        use serde::{Deserialize, Serialize};
 
         use crate::{
             net::{http_serde::query::BASE_AXUM_PATH, Error},
@@ -426,19 +483,30 @@ pub mod query {
         pub struct Request<B> {
             pub query_id: QueryId,
             pub gate: Gate,
+            /// Offset of the first byte in `body` from the start of the stream, if `body`
+            /// resumes a stream after a dropped connection.
+            pub resume_from: Option<u64>,
             pub body: B,
         }
 
         impl<B> Request<B> {
-            pub fn new(query_id: QueryId, gate: Gate, body: B) -> Self {
+            pub fn new(query_id: QueryId, gate: Gate, resume_from: Option<u64>, body: B) -> Self {
                 Self {
                     query_id,
                     gate,
+                    resume_from,
                     body,
                 }
             }
         }
 
+        /// Query string of the step request. Streams resumed after a dropped
+        /// connection don't start from the beginning.
+        #[derive(Debug, Default, Serialize, Deserialize)]
+        pub struct StepQueryString {
+            pub offset: Option<u64>,
+        }
+
         /// Convert to hyper request. Used on client side.
         impl Request<Body> {
             pub fn try_into_http_request(
@@ -450,10 +518,13 @@ pub mod query {
                     .scheme(scheme)
                     .authority(authority)
                     .path_and_query(format!(
-                        "{}/{}/step/{}",
+                        "{}/{}/step/{}{}",
                         BASE_AXUM_PATH,
-                        self.query_id.as_ref(),
-                        self.gate.as_ref()
+                        self.query_id,
+                        self.gate.as_ref(),
+                        self.resume_from
+                            .map(|offset| format!("?offset={offset}"))
+                            .unwrap_or_default(),
                     ))
                     .build()?;
                 Ok(hyper::Request::post(uri).body(self.body)?)
@@ -463,6 +534,61 @@ pub mod query {
         pub const AXUM_PATH: &str = "/:query_id/step/*step";
     }
 
+    pub mod ack {
+        use axum::{body::Body, http::uri};
+        use serde::{Deserialize, Serialize};
+
+        use crate::{
+            net::{http_serde::query::BASE_AXUM_PATH, Error},
+            protocol::{Gate, QueryId},
+        };
+
+        /// Acknowledges that the records stream of the given step has been received up to
+        /// `offset`.
+        #[derive(Debug)]
+        pub struct Request {
+            pub query_id: QueryId,
+            pub gate: Gate,
+            pub offset: u64,
+        }
+
+        impl Request {
+            pub fn new(query_id: QueryId, gate: Gate, offset: u64) -> Self {
+                Self {
+                    query_id,
+                    gate,
+                    offset,
+                }
+            }
+
+            pub fn try_into_http_request(
+                self,
+                scheme: uri::Scheme,
+                authority: uri::Authority,
+            ) -> Result<hyper::Request<Body>, Error> {
+                let uri = uri::Uri::builder()
+                    .scheme(scheme)
+                    .authority(authority)
+                    .path_and_query(format!(
+                        "{}/{}/ack/{}?offset={}",
+                        BASE_AXUM_PATH,
+                        self.query_id,
+                        self.gate.as_ref(),
+                        self.offset,
+                    ))
+                    .build()?;
+                Ok(hyper::Request::post(uri).body(Body::empty())?)
+            }
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        pub struct AckQueryString {
+            pub offset: u64,
+        }
+
+        pub const AXUM_PATH: &str = "/:query_id/ack/*step";
+    }
+
     pub mod status {
         use serde::{Deserialize, Serialize};
 
@@ -515,7 +641,7 @@ pub mod query {
                     .path_and_query(format!(
                         "{}/{}",
                         crate::net::http_serde::query::BASE_AXUM_PATH,
-                        self.query_id.as_ref()
+                        self.query_id
                     ))
                     .build()?;
                 Ok(hyper::Request::get(uri).body(axum::body::Body::empty())?)
@@ -583,7 +709,7 @@ pub mod query {
                     .path_and_query(format!(
                         "{}/{}/complete",
                         crate::net::http_serde::query::BASE_AXUM_PATH,
-                        self.query_id.as_ref()
+                        self.query_id
                     ))
                     .build()?;
                 Ok(hyper::Request::get(uri).body(axum::body::Body::empty())?)
@@ -626,18 +752,12 @@ pub mod query {
         }
 
         impl Request {
-            /// Currently, it is only possible to kill
-            /// a query by issuing an HTTP request manually.
-            /// Maybe report collector can support this API,
-            /// but for now, only tests exercise this path
-            /// hence methods here are hidden behind feature
-            /// flags
-            #[cfg(all(test, unit_test))]
+            /// Queries are killed by helpers and shards whose deadline for the query has
+            /// passed. It is also possible to kill a query by issuing an HTTP request manually.
             pub fn new(query_id: QueryId) -> Self {
                 Self { query_id }
             }
 
-            #[cfg(all(test, unit_test))]
             pub fn try_into_http_request(
                 self,
                 scheme: axum::http::uri::Scheme,
@@ -649,7 +769,7 @@ pub mod query {
                     .path_and_query(format!(
                         "{}/{}/kill",
                         crate::net::http_serde::query::BASE_AXUM_PATH,
-                        self.query_id.as_ref()
+                        self.query_id
                     ))
                     .build()?;
                 Ok(hyper::Request::post(uri).body(axum::body::Body::empty())?)
@@ -705,8 +825,8 @@ pub mod query {
                 .path_and_query(format!(
                     "{}/{}/status-match?{}",
                     crate::net::http_serde::query::BASE_AXUM_PATH,
-                    req.query_id.as_ref(),
-                    StatusQueryString::from(req.status).url_encode(),
+                    req.query_id,
+                    StatusQueryString::from(req.status.clone()).url_encode(),
                 ))
                 .build()?;
             Ok(hyper::Request::get(uri).body(axum::body::Body::empty())?)
@@ -22,6 +22,7 @@ pub mod query_input;
 mod server;
 #[cfg(all(test, not(feature = "shuttle")))]
 pub mod test;
+mod trace_context;
 mod transport;
 
 pub use client::{ClientIdentity, IpaHttpClient};
@@ -1,36 +1,215 @@
+use std::{
+    fmt::{Display, Formatter},
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    time::Duration,
+};
+
 use axum::{body::Body, BoxError};
+use bytes::{Bytes, BytesMut};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
 use http_body_util::BodyExt;
-use hyper::Uri;
-use hyper_rustls::HttpsConnectorBuilder;
+use hyper::{StatusCode, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use hyper_util::{
-    client::legacy::Client,
+    client::legacy::{connect::HttpConnector, Client},
     rt::{TokioExecutor, TokioTimer},
 };
+use tokio::io::AsyncReadExt;
 
 use crate::{helpers::BodyStream, net::Error};
 
-/// Connect to a remote URL to download query input.
+/// Prefix that marks a query input source as a path on the helper's local disk.
+const FILE_PREFIX: &str = "file://";
+
+/// Number of times a source is opened before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry. It doubles after every failed attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Size of the chunks local files are read in.
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+type InputClient = Client<HttpsConnector<HttpConnector>, Body>;
+type InputStream = Pin<Box<dyn Stream<Item = Result<Bytes, BoxError>> + Send>>;
+
+/// A single location to read the query input from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryInputSource {
+    Url(Uri),
+    /// Path on the helper's local disk, specified as `file://<path>`.
+    File(PathBuf),
+}
+
+impl FromStr for QueryInputSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix(FILE_PREFIX) {
+            Ok(Self::File(PathBuf::from(path)))
+        } else {
+            Ok(Self::Url(s.parse()?))
+        }
+    }
+}
+
+impl Display for QueryInputSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Url(uri) => write!(f, "{uri}"),
+            Self::File(path) => write!(f, "{FILE_PREFIX}{}", path.display()),
+        }
+    }
+}
+
+/// Streams query input from all `sources`, one after another, in the order they are given.
+/// Opening a source that failed with a transient error (connection failure, 5xx or 429 response)
+/// is retried with exponential backoff.
+///
+/// Local files can only be read if `input_dir` is set and the file is located inside that
+/// directory. Relative paths are resolved against `input_dir`.
+///
+/// The first source is opened before this function returns, the remaining ones are opened once
+/// the stream gets to them.
 ///
 /// # Errors
-/// If the connection to the remote URL fails or returns an HTTP error.
+/// If any of the files is not accessible, or if the first source can't be opened.
 ///
 /// # Panics
 /// If unable to create an HTTPS client using the system truststore.
-pub async fn stream_query_input_from_url(uri: &Uri) -> Result<BodyStream, Error> {
+pub async fn stream_query_input(
+    sources: Vec<QueryInputSource>,
+    input_dir: Option<&Path>,
+) -> Result<BodyStream, Error> {
+    // check all files upfront, so access errors are reported back to the report collector
+    // instead of failing the query half way through the input
+    let mut sources = sources
+        .into_iter()
+        .map(|source| match source {
+            QueryInputSource::File(path) => {
+                resolve_input_file(&path, input_dir).map(QueryInputSource::File)
+            }
+            QueryInputSource::Url(_) => Ok(source),
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter();
+
+    let Some(first) = sources.next() else {
+        return Ok(BodyStream::empty());
+    };
+    let client = input_client();
+    let first = open_with_retries(&client, &first).await?;
+    let rest = stream::iter(sources)
+        .then(move |source| {
+            let client = client.clone();
+            async move {
+                open_with_retries(&client, &source)
+                    .await
+                    .map_err(BoxError::from)
+            }
+        })
+        .try_flatten();
+
+    Ok(BodyStream::from_bytes_stream(first.chain(rest)))
+}
+
+fn input_client() -> InputClient {
     let mut builder = Client::builder(TokioExecutor::new());
     // the following timer is necessary for http2, in particular for any timeouts
     // and waits the clients will need to make
     // TODO: implement IpaTimer to allow wrapping other than Tokio runtimes
     builder.timer(TokioTimer::new());
-    let client = builder.build::<_, Body>(
+    builder.build::<_, Body>(
         HttpsConnectorBuilder::default()
             .with_native_roots()
             .expect("System truststore is required")
             .https_or_http()
             .enable_all_versions()
             .build(),
-    );
+    )
+}
+
+fn resolve_input_file(path: &Path, input_dir: Option<&Path>) -> Result<PathBuf, Error> {
+    let file_error = |inner| Error::QueryInputFile {
+        path: path.to_path_buf(),
+        inner,
+    };
+    let Some(input_dir) = input_dir else {
+        return Err(file_error(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "reading query input from local files is not enabled on this helper",
+        )));
+    };
 
+    let input_dir = input_dir.canonicalize().map_err(file_error)?;
+    let resolved = input_dir.join(path).canonicalize().map_err(file_error)?;
+    if resolved.starts_with(&input_dir) && resolved.is_file() {
+        Ok(resolved)
+    } else {
+        Err(file_error(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("not a file inside {}", input_dir.display()),
+        )))
+    }
+}
+
+fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::ConnectError { .. } => true,
+        Error::FailedHttpRequest { status, .. } => {
+            status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+        }
+        _ => false,
+    }
+}
+
+async fn open_with_retries(
+    client: &InputClient,
+    source: &QueryInputSource,
+) -> Result<InputStream, Error> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match open(client, source).await {
+            Err(e) if attempt < MAX_ATTEMPTS && is_transient(&e) => {
+                tracing::warn!(
+                    "attempt {attempt} to read query input from {source} failed, retrying in {backoff:?}: {e}"
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            r => return r,
+        }
+    }
+}
+
+async fn open(client: &InputClient, source: &QueryInputSource) -> Result<InputStream, Error> {
+    match source {
+        QueryInputSource::Url(uri) => open_url(client, uri).await,
+        QueryInputSource::File(path) => open_file(path).await,
+    }
+}
+
+async fn open_file(path: &Path) -> Result<InputStream, Error> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .map_err(|inner| Error::QueryInputFile {
+            path: path.to_path_buf(),
+            inner,
+        })?;
+
+    Ok(Box::pin(stream::try_unfold(file, |mut file| async move {
+        let mut buf = BytesMut::with_capacity(FILE_CHUNK_SIZE);
+        let len = file.read_buf(&mut buf).await?;
+        Ok::<_, BoxError>((len > 0).then(|| (buf.freeze(), file)))
+    })))
+}
+
+async fn open_url(client: &InputClient, uri: &Uri) -> Result<InputStream, Error> {
     let resp = client
         .get(uri.clone())
         .await
@@ -53,7 +232,69 @@ pub async fn stream_query_input_from_url(uri: &Uri) -> Result<BodyStream, Error>
         );
     }
 
-    Ok(BodyStream::from_bytes_stream(
+    Ok(Box::pin(
         resp.into_body().map_err(BoxError::from).into_data_stream(),
     ))
 }
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::fs;
+
+    use bytes::BytesMut;
+    use futures::TryStreamExt;
+
+    use crate::net::{
+        query_input::{stream_query_input, QueryInputSource},
+        Error,
+    };
+
+    #[test]
+    fn parse_source() {
+        assert_eq!(
+            QueryInputSource::File("/data/input.bin".into()),
+            "file:///data/input.bin".parse().unwrap()
+        );
+        assert_eq!(
+            QueryInputSource::Url("https://example.com/input.bin".parse().unwrap()),
+            "https://example.com/input.bin".parse().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn files_are_streamed_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), b"first").unwrap();
+        fs::write(dir.path().join("b"), b"second").unwrap();
+
+        let sources = vec![
+            QueryInputSource::File("b".into()),
+            QueryInputSource::File(dir.path().join("a")),
+        ];
+        let input = stream_query_input(sources, Some(dir.path()))
+            .await
+            .unwrap()
+            .try_collect::<BytesMut>()
+            .await
+            .unwrap();
+
+        assert_eq!(input, "secondfirst");
+    }
+
+    #[tokio::test]
+    async fn files_outside_input_dir_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let input_dir = dir.path().join("inputs");
+        fs::create_dir(&input_dir).unwrap();
+        fs::write(dir.path().join("secret"), b"secret").unwrap();
+
+        for path in [dir.path().join("secret"), "../secret".into()] {
+            let Err(e) =
+                stream_query_input(vec![QueryInputSource::File(path)], Some(&input_dir)).await
+            else {
+                panic!("reading a file outside of the input directory must fail");
+            };
+            assert!(matches!(e, Error::QueryInputFile { .. }), "{e:?}");
+        }
+    }
+}
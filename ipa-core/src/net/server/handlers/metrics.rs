@@ -1,5 +1,5 @@
-use axum::{routing::get, Extension, Router};
-use hyper::StatusCode;
+use axum::{response::IntoResponse, routing::get, Extension, Router};
+use hyper::{header::CONTENT_TYPE, StatusCode};
 
 use crate::{
     helpers::{routing::RouteId, BodyStream},
@@ -9,14 +9,21 @@ use crate::{
     },
 };
 
-/// Takes details from the HTTP request and creates a `[TransportCommand]::CreateQuery` that is sent
-/// to the [`HttpTransport`].
-async fn handler(transport: Extension<MpcHttpTransport>) -> Result<Vec<u8>, Error> {
+/// Scrape endpoint for Prometheus. Returns all metrics collected by this helper in the
+/// text exposition format. Metrics emitted by queries carry the `query_id` label, and
+/// every metric is labelled with the `shard` that serves the request. Protocol metrics,
+/// like `records.sent` or `bytes.sent`, are also labelled with the `role` and `step`.
+///
+/// Scraping does not interfere with running queries, so it can be done at any time.
+async fn handler(transport: Extension<MpcHttpTransport>) -> Result<impl IntoResponse, Error> {
     match transport
         .dispatch(RouteId::Metrics, BodyStream::empty())
         .await
     {
-        Ok(resp) => Ok(resp.into_body()),
+        Ok(resp) => Ok((
+            [(CONTENT_TYPE, http_serde::metrics::CONTENT_TYPE)],
+            resp.into_body(),
+        )),
         Err(err) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, err)),
     }
 }
@@ -36,10 +43,26 @@ mod tests {
 
     use super::*;
     use crate::{
+        helpers::ApiError,
         helpers::{make_owned_handler, routing::Addr, HelperIdentity, HelperResponse},
-        net::server::handlers::query::test_helpers::assert_success_with,
+        net::{
+            server::handlers::query::test_helpers::{
+                assert_fails_with_handler, assert_success_with,
+            },
+            test::TestServer,
+        },
     };
 
+    fn metrics_req() -> hyper::Request<Body> {
+        let uri = uri::Builder::new()
+            .scheme(Scheme::HTTP)
+            .authority(Authority::from_static("localhost"))
+            .path_and_query(String::from("/metrics"))
+            .build()
+            .unwrap();
+        hyper::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
     #[tokio::test]
     async fn happy_case() {
         let handler = make_owned_handler(
@@ -50,13 +73,43 @@ mod tests {
                 Ok(HelperResponse::from(Vec::new()))
             },
         );
-        let uri = uri::Builder::new()
-            .scheme(Scheme::HTTP)
-            .authority(Authority::from_static("localhost"))
-            .path_and_query(String::from("/metrics"))
+        assert_success_with(metrics_req(), handler).await;
+    }
+
+    #[tokio::test]
+    async fn prometheus_text_format() {
+        const METRICS: &str = "records_sent_total{query_id=\"1\",shard=\"0\"} 5\n";
+        let handler = make_owned_handler(
+            move |_addr: Addr<HelperIdentity>, _data: BodyStream| async move {
+                Ok(HelperResponse::from(METRICS.as_bytes().to_vec()))
+            },
+        );
+        let test_server = TestServer::builder()
+            .with_request_handler(handler)
             .build()
+            .await;
+        let resp = test_server.server.handle_req(metrics_req()).await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            http_serde::metrics::CONTENT_TYPE,
+            resp.headers()[CONTENT_TYPE].to_str().unwrap()
+        );
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
             .unwrap();
-        let req = hyper::Request::get(uri).body(Body::empty()).unwrap();
-        assert_success_with(req, handler).await;
+        assert_eq!(METRICS.as_bytes(), body);
+    }
+
+    #[tokio::test]
+    async fn metrics_unavailable() {
+        let handler = make_owned_handler(
+            move |_addr: Addr<HelperIdentity>, _data: BodyStream| async move {
+                Err(ApiError::MetricsUnavailable(
+                    "collector stopped".to_string(),
+                ))
+            },
+        );
+        assert_fails_with_handler(metrics_req(), handler, StatusCode::INTERNAL_SERVER_ERROR).await;
     }
 }
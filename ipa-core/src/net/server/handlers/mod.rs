@@ -2,6 +2,8 @@ mod echo;
 mod metrics;
 mod query;
 
+use std::path::PathBuf;
+
 use axum::Router;
 
 use crate::{
@@ -9,13 +11,13 @@ use crate::{
     sync::Arc,
 };
 
-pub fn mpc_router(transport: MpcHttpTransport) -> Router {
+pub fn mpc_router(transport: MpcHttpTransport, query_input_dir: Option<PathBuf>) -> Router {
     echo::router()
         .merge(metrics::router(transport.clone()))
         .nest(
             http_serde::query::BASE_AXUM_PATH,
             Router::new()
-                .merge(query::query_router(transport.clone()))
+                .merge(query::query_router(transport.clone(), query_input_dir))
                 .merge(query::h2h_router(transport.inner_transport)),
         )
 }
@@ -0,0 +1,119 @@
+use axum::{
+    extract::{Path, Query},
+    routing::post,
+    Extension, Router,
+};
+
+use crate::{
+    net::{
+        http_serde::{self, query::ack::AckQueryString},
+        server::{ClientIdentity, Error},
+        ConnectionFlavor, HttpTransport,
+    },
+    protocol::{Gate, QueryId},
+    sync::Arc,
+};
+
+#[allow(clippy::unused_async)] // axum doesn't like synchronous handler
+#[tracing::instrument(level = "trace", "ack", skip_all, fields(from = ?**from, gate = ?gate))]
+async fn handler<F: ConnectionFlavor>(
+    transport: Extension<Arc<HttpTransport<F>>>,
+    from: Extension<ClientIdentity<F::Identity>>,
+    Path((query_id, gate)): Path<(QueryId, Gate)>,
+    Query(AckQueryString { offset }): Query<AckQueryString>,
+) -> Result<(), Error> {
+    transport.receive_ack(query_id, gate, **from, offset);
+    Ok(())
+}
+
+pub fn router<F: ConnectionFlavor>(transport: Arc<HttpTransport<F>>) -> Router {
+    Router::new()
+        .route(http_serde::query::ack::AXUM_PATH, post(handler::<F>))
+        .layer(Extension(transport))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use axum::body::Body;
+    use hyper::StatusCode;
+    use ipa_step::StepNarrow;
+
+    use super::*;
+    use crate::{
+        helpers::HelperIdentity,
+        net::{
+            server::handlers::query::test_helpers::{assert_fails_with, MaybeExtensionExt},
+            test::TestServer,
+        },
+        protocol::{Gate, QueryId},
+    };
+
+    #[tokio::test]
+    async fn ack() {
+        let req = OverrideReq {
+            client_id: Some(ClientIdentity(HelperIdentity::TWO)),
+            ..Default::default()
+        };
+        let test_server = TestServer::builder().build().await;
+        let acks = test_server.transport.acks(
+            HelperIdentity::TWO,
+            &(QueryId::from(0), Gate::default().narrow("test")),
+        );
+
+        test_server.server.handle_req(req.into()).await;
+
+        assert_eq!(42, *acks.borrow());
+    }
+
+    struct OverrideReq {
+        client_id: Option<ClientIdentity<HelperIdentity>>,
+        query_id: String,
+        gate: Gate,
+        offset: String,
+    }
+
+    impl From<OverrideReq> for hyper::Request<Body> {
+        fn from(val: OverrideReq) -> Self {
+            let uri = format!(
+                "http://localhost{}/{}/ack/{}?offset={}",
+                http_serde::query::BASE_AXUM_PATH,
+                val.query_id,
+                val.gate.as_ref(),
+                val.offset,
+            );
+            hyper::Request::post(uri)
+                .maybe_extension(val.client_id)
+                .body(Body::empty())
+                .unwrap()
+        }
+    }
+
+    impl Default for OverrideReq {
+        fn default() -> Self {
+            Self {
+                client_id: Some(ClientIdentity(HelperIdentity::ONE)),
+                query_id: QueryId::from(0).to_string(),
+                gate: Gate::default().narrow("test"),
+                offset: "42".into(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_offset_fails() {
+        let req = OverrideReq {
+            offset: "not-an-offset".into(),
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::BAD_REQUEST).await;
+    }
+
+    #[tokio::test]
+    async fn auth_required() {
+        let req = OverrideReq {
+            client_id: None,
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::UNAUTHORIZED).await;
+    }
+}
@@ -2,7 +2,10 @@ use axum::{routing::post, Extension, Json, Router};
 use hyper::StatusCode;
 
 use crate::{
-    helpers::{ApiError, BodyStream},
+    helpers::{
+        query::{CreateQuery, ReportCollector},
+        ApiError, BodyStream,
+    },
     net::{
         http_serde::{self, query::QueryConfigQueryParams},
         transport::MpcHttpTransport,
@@ -12,16 +15,25 @@ use crate::{
 };
 
 /// Takes details from the HTTP request and creates a `[TransportCommand]::CreateQuery` that is sent
-/// to the [`HttpTransport`].
+/// to the [`HttpTransport`]. The query is attributed to the report collector that authenticated
+/// with its client certificate, if any.
 async fn handler(
     transport: Extension<MpcHttpTransport>,
-    QueryConfigQueryParams(query_config): QueryConfigQueryParams,
+    report_collector: Option<Extension<ReportCollector>>,
+    QueryConfigQueryParams(config): QueryConfigQueryParams,
 ) -> Result<Json<http_serde::query::create::ResponseBody>, Error> {
-    match transport.dispatch(query_config, BodyStream::empty()).await {
+    let req = CreateQuery {
+        config,
+        report_collector: report_collector.map(|Extension(rc)| rc),
+    };
+    match transport.dispatch(req, BodyStream::empty()).await {
         Ok(resp) => Ok(Json(resp.try_into()?)),
         Err(err @ ApiError::NewQuery(NewQueryError::State { .. })) => {
             Err(Error::application(StatusCode::CONFLICT, err))
         }
+        Err(err @ ApiError::NewQuery(NewQueryError::Config { .. })) => {
+            Err(Error::application(StatusCode::BAD_REQUEST, err))
+        }
         Err(err) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, err)),
     }
 }
@@ -34,7 +46,7 @@ pub fn router(transport: MpcHttpTransport) -> Router {
 
 #[cfg(all(test, unit_test))]
 mod tests {
-    use std::num::NonZeroU32;
+    use std::{num::NonZeroU32, time::Duration};
 
     use axum::body::Body;
     use hyper::{
@@ -46,7 +58,11 @@ mod tests {
         ff::FieldType,
         helpers::{
             make_owned_handler,
-            query::{IpaQueryConfig, PrepareQuery, QueryConfig, QueryType},
+            query::{
+                AttributionModel, BreakdownKeyBits, DpMechanism, FeatureCount, HistogramOutput,
+                HybridQueryParams, IpaQueryConfig, ModelWeights, PrepareQuery, QueryConfig,
+                QueryDeadlines, QueryType, ValueBits, WalrQueryParams,
+            },
             routing::RouteId,
             HelperResponse, Role, RoleAssignment,
         },
@@ -54,7 +70,10 @@ mod tests {
             http_serde,
             server::handlers::query::test_helpers::{assert_fails_with, assert_success_with},
         },
-        protocol::QueryId,
+        protocol::{
+            ipa_prf::oprf_padding::{AggregationPadding, PaddingParameters},
+            QueryId,
+        },
     };
 
     async fn create_test(expected_query_config: QueryConfig) {
@@ -69,15 +88,16 @@ mod tests {
             let query_config = addr.into().unwrap();
             assert_eq!(query_config, expected_query_config);
             Ok(HelperResponse::from(PrepareQuery {
-                query_id: QueryId,
+                query_id: QueryId::from(0),
                 config: query_config,
                 roles: RoleAssignment::try_from([Role::H1, Role::H2, Role::H3]).unwrap(),
+                report_collector: None,
             }))
         });
         let resp = assert_success_with(req, handler).await;
         let http_serde::query::create::ResponseBody { query_id } =
             serde_json::from_slice(&resp).unwrap();
-        assert_eq!(QueryId, query_id);
+        assert_eq!(QueryId::from(0), query_id);
     }
 
     #[tokio::test]
@@ -85,6 +105,19 @@ mod tests {
         create_test(QueryConfig::new(QueryType::TestMultiply, FieldType::Fp31, 1).unwrap()).await;
     }
 
+    #[tokio::test]
+    async fn create_test_with_deadlines() {
+        create_test(
+            QueryConfig::new(QueryType::TestMultiply, FieldType::Fp31, 1)
+                .unwrap()
+                .with_deadlines(QueryDeadlines {
+                    inputs: Some(Duration::from_secs(30)),
+                    total: Some(Duration::from_millis(3_600_500)),
+                }),
+        )
+        .await;
+    }
+
     #[tokio::test]
     async fn create_test_ipa_no_attr_window() {
         create_test(
@@ -93,9 +126,9 @@ mod tests {
                     per_user_credit_cap: 1,
                     max_breakdown_key: 1,
                     attribution_window_seconds: None,
-                    with_dp: 0,
-                    epsilon: 5.0,
+                    dp_mechanism: DpMechanism::NoDp,
                     plaintext_match_keys: true,
+                    ..Default::default()
                 }),
                 FieldType::Fp32BitPrime,
                 1,
@@ -113,9 +146,9 @@ mod tests {
                     per_user_credit_cap: 8,
                     max_breakdown_key: 20,
                     attribution_window_seconds: None,
-                    with_dp: 1,
-                    epsilon: 5.0,
+                    dp_mechanism: DpMechanism::DiscreteLaplace { epsilon: 5.0 },
                     plaintext_match_keys: true,
+                    ..Default::default()
                 }),
                 FieldType::Fp32BitPrime,
                 1,
@@ -133,9 +166,48 @@ mod tests {
                     per_user_credit_cap: 8,
                     max_breakdown_key: 20,
                     attribution_window_seconds: None,
-                    with_dp: 1,
-                    epsilon: 5.0,
+                    dp_mechanism: DpMechanism::DiscreteLaplace { epsilon: 5.0 },
                     plaintext_match_keys: true,
+                    ..Default::default()
+                }),
+                FieldType::Fp32BitPrime,
+                1,
+            )
+            .unwrap(),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_malicious_ipa_sparse_output() {
+        create_test(
+            QueryConfig::new(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                    dp_mechanism: DpMechanism::DiscreteLaplace { epsilon: 5.0 },
+                    attribution_model: AttributionModel::TimeDecay {
+                        half_life_seconds: 1024,
+                    },
+                    histogram_output: HistogramOutput::Sparse { delta: 1e-6 },
+                    ..Default::default()
+                }),
+                FieldType::Fp32BitPrime,
+                1,
+            )
+            .unwrap(),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_malicious_ipa_relaxed_padding() {
+        let padding = PaddingParameters::relaxed();
+        create_test(
+            QueryConfig::new(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                    dp_mechanism: DpMechanism::DiscreteLaplace { epsilon: 5.0 },
+                    oprf_padding: padding.oprf_padding,
+                    aggregation_padding: AggregationPadding::NoAggPadding,
+                    ..Default::default()
                 }),
                 FieldType::Fp32BitPrime,
                 1,
@@ -154,14 +226,94 @@ mod tests {
                 per_user_credit_cap: 1,
                 max_breakdown_key: 1,
                 attribution_window_seconds: NonZeroU32::new(86_400),
-                with_dp: 0,
-                epsilon: 5.0,
+                dp_mechanism: DpMechanism::NoDp,
                 plaintext_match_keys: true,
+                ..Default::default()
             }),
+            deadlines: QueryDeadlines::default(),
         })
         .await;
     }
 
+    #[tokio::test]
+    async fn create_test_hybrid_with_widths() {
+        create_test(
+            QueryConfig::new(
+                QueryType::MaliciousHybrid(HybridQueryParams {
+                    max_breakdown_key: 20,
+                    per_user_credit_cap: 16,
+                    dp_mechanism: DpMechanism::NoDp,
+                    breakdown_key_bits: BreakdownKeyBits::try_from(5).unwrap(),
+                    value_bits: ValueBits::try_from(16).unwrap(),
+                    breakdown_dimensions: Some("4x5".parse().unwrap()),
+                    ..Default::default()
+                }),
+                FieldType::Fp32BitPrime,
+                1,
+            )
+            .unwrap(),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_hybrid_binomial_dp() {
+        create_test(
+            QueryConfig::new(
+                QueryType::MaliciousHybrid(HybridQueryParams {
+                    dp_mechanism: DpMechanism::Binomial {
+                        epsilon: 1.5,
+                        delta: 1e-7,
+                    },
+                    ..Default::default()
+                }),
+                FieldType::Fp32BitPrime,
+                1,
+            )
+            .unwrap(),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_hybrid_sparse_output() {
+        create_test(
+            QueryConfig::new(
+                QueryType::MaliciousHybrid(HybridQueryParams {
+                    dp_mechanism: DpMechanism::DiscreteLaplace { epsilon: 3.0 },
+                    histogram_output: HistogramOutput::Sparse { delta: 1e-6 },
+                    ..Default::default()
+                }),
+                FieldType::Fp32BitPrime,
+                1,
+            )
+            .unwrap(),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_walr() {
+        create_test(
+            QueryConfig::new(
+                QueryType::MaliciousWalr(WalrQueryParams {
+                    features: FeatureCount::try_from(8).unwrap(),
+                    model_weights: ModelWeights::try_from([16, -8, 0, 127, -128].as_slice())
+                        .unwrap(),
+                    dp_mechanism: DpMechanism::Binomial {
+                        epsilon: 1.5,
+                        delta: 1e-7,
+                    },
+                    oprf_padding: PaddingParameters::relaxed().oprf_padding,
+                }),
+                FieldType::Fp32BitPrime,
+                1,
+            )
+            .unwrap(),
+        )
+        .await;
+    }
+
     struct OverrideReq {
         field_type: String,
         query_type_params: String,
@@ -227,24 +379,24 @@ mod tests {
         per_user_credit_cap: String,
         max_breakdown_key: String,
         attribution_window_seconds: Option<String>,
-        with_dp: String,
-        epsilon: String,
+        dp_mechanism: String,
+        oprf_padding: Option<String>,
     }
 
     impl From<OverrideIPAReq> for hyper::Request<Body> {
         fn from(val: OverrideIPAReq) -> Self {
             let mut query = format!(
-                "query_type={}&per_user_credit_cap={}&max_breakdown_key={}&with_dp={}&epsilon={}",
-                val.query_type,
-                val.per_user_credit_cap,
-                val.max_breakdown_key,
-                val.with_dp,
-                val.epsilon,
+                "query_type={}&per_user_credit_cap={}&max_breakdown_key={}&dp_mechanism={}",
+                val.query_type, val.per_user_credit_cap, val.max_breakdown_key, val.dp_mechanism,
             );
 
             if let Some(window) = val.attribution_window_seconds {
                 query.push_str(&format!("&attribution_window_seconds={window}"));
             }
+            if let Some(padding) = val.oprf_padding {
+                query.push_str("&oprf_padding=");
+                query.push_str(&padding);
+            }
             OverrideReq {
                 field_type: val.field_type,
                 query_type_params: query,
@@ -261,8 +413,8 @@ mod tests {
                 per_user_credit_cap: "1".into(),
                 max_breakdown_key: "1".into(),
                 attribution_window_seconds: None,
-                with_dp: "1".into(),
-                epsilon: "3.0".into(),
+                dp_mechanism: "discrete-laplace:epsilon=3.0".into(),
+                oprf_padding: None,
             }
         }
     }
@@ -311,4 +463,46 @@ mod tests {
         };
         assert_fails_with(req.into(), StatusCode::UNPROCESSABLE_ENTITY).await;
     }
+
+    #[tokio::test]
+    async fn malformed_oprf_padding_ipa() {
+        let req = OverrideIPAReq {
+            oprf_padding: Some("epsilon=1,delta=0.1".to_string()),
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::UNPROCESSABLE_ENTITY).await;
+    }
+
+    #[tokio::test]
+    async fn malformed_dp_mechanism_ipa() {
+        let req = OverrideIPAReq {
+            dp_mechanism: "binomial:epsilon=1".into(),
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::UNPROCESSABLE_ENTITY).await;
+    }
+
+    #[tokio::test]
+    async fn malformed_features_walr() {
+        let req = OverrideReq {
+            field_type: format!("{:?}", FieldType::Fp32BitPrime),
+            query_type_params: format!(
+                "query_type={}&features=12&dp_mechanism=none",
+                QueryType::MALICIOUS_WALR_STR
+            ),
+        };
+        assert_fails_with(req.into(), StatusCode::UNPROCESSABLE_ENTITY).await;
+    }
+
+    #[tokio::test]
+    async fn malformed_model_weights_walr() {
+        let req = OverrideReq {
+            field_type: format!("{:?}", FieldType::Fp32BitPrime),
+            query_type_params: format!(
+                "query_type={}&features=8&model_weights=1,128&dp_mechanism=none",
+                QueryType::MALICIOUS_WALR_STR
+            ),
+        };
+        assert_fails_with(req.into(), StatusCode::UNPROCESSABLE_ENTITY).await;
+    }
 }
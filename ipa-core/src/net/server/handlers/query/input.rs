@@ -1,27 +1,35 @@
+use std::path::PathBuf;
+
 use axum::{extract::Path, routing::post, Extension, Router};
 use hyper::StatusCode;
 
 use crate::{
     helpers::{routing::RouteId, BodyStream},
     net::{
-        http_serde::{self, query::input::QueryInputUrl},
-        query_input::stream_query_input_from_url,
+        http_serde::{self, query::input::QueryInputUrls},
+        query_input::{stream_query_input, QueryInputSource},
         transport::MpcHttpTransport,
         Error,
     },
     protocol::QueryId,
 };
 
+/// Directory that inputs given as local files are allowed to be read from.
+#[derive(Clone)]
+struct QueryInputDir(Option<PathBuf>);
+
 async fn handler(
     transport: Extension<MpcHttpTransport>,
+    Extension(QueryInputDir(input_dir)): Extension<QueryInputDir>,
     Path(query_id): Path<QueryId>,
-    input_url: QueryInputUrl,
+    input_urls: QueryInputUrls,
     input_stream: BodyStream,
 ) -> Result<(), Error> {
-    let input_stream = if let Some(url) = input_url.into() {
-        stream_query_input_from_url(&url).await?
-    } else {
+    let sources = Vec::<QueryInputSource>::from(input_urls);
+    let input_stream = if sources.is_empty() {
         input_stream
+    } else {
+        stream_query_input(sources, input_dir.as_deref()).await?
     };
     let _ = transport
         .dispatch((RouteId::QueryInput, query_id), input_stream)
@@ -31,10 +39,11 @@ async fn handler(
     Ok(())
 }
 
-pub fn router(transport: MpcHttpTransport) -> Router {
+pub fn router(transport: MpcHttpTransport, input_dir: Option<PathBuf>) -> Router {
     Router::new()
         .route(http_serde::query::input::AXUM_PATH, post(handler))
         .layer(Extension(transport))
+        .layer(Extension(QueryInputDir(input_dir)))
 }
 
 #[cfg(all(test, unit_test))]
@@ -65,7 +74,7 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn input_inline() {
-        const QUERY_ID: QueryId = QueryId;
+        let query_id = QueryId::from(0);
         let expected_input = &[4u8; 4];
 
         let req_handler = make_owned_handler(move |addr, data| async move {
@@ -73,7 +82,7 @@ mod tests {
                 panic!("unexpected call");
             };
 
-            assert_eq!(addr.query_id, Some(QUERY_ID));
+            assert_eq!(addr.query_id, Some(query_id));
             assert_eq!(
                 tokio::task::block_in_place(move || {
                     Handle::current().block_on(async move { data.to_vec().await })
@@ -85,7 +94,7 @@ mod tests {
         });
 
         let req = http_serde::query::input::Request::new(QueryInput::Inline {
-            query_id: QUERY_ID,
+            query_id,
             input_stream: expected_input.to_vec().into(),
         });
         let hyper_req = req
@@ -97,8 +106,8 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn input_from_url() {
-        const QUERY_ID: QueryId = QueryId;
         const DATA: &str = "<input records>";
+        let query_id = QueryId::from(0);
 
         let server = tiny_http::Server::http("localhost:0").unwrap();
         let addr = server.server_addr();
@@ -113,7 +122,7 @@ mod tests {
                 panic!("unexpected call");
             };
 
-            assert_eq!(addr.query_id, Some(QUERY_ID));
+            assert_eq!(addr.query_id, Some(query_id));
             assert_eq!(body.try_collect::<BytesMut>().await.unwrap(), DATA);
 
             Ok(HelperResponse::ok())
@@ -127,9 +136,9 @@ mod tests {
             "http://localhost:{}/input-data",
             addr.to_ip().unwrap().port(),
         );
-        let req = http_serde::query::input::Request::new(QueryInput::FromUrl {
-            query_id: QUERY_ID,
-            url,
+        let req = http_serde::query::input::Request::new(QueryInput::FromUrls {
+            query_id,
+            urls: vec![url],
         });
         let hyper_req = req
             .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
@@ -144,6 +153,75 @@ mod tests {
         }
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn input_from_multiple_urls() {
+        let query_id = QueryId::from(0);
+
+        let server = tiny_http::Server::http("localhost:0").unwrap();
+        let addr = server.server_addr();
+        thread::spawn(move || {
+            // the first attempt to download the first part fails and must be retried
+            let request = server.recv().unwrap();
+            assert_eq!(request.url(), "/part1");
+            request
+                .respond(tiny_http::Response::from_string("busy").with_status_code(503))
+                .unwrap();
+            for (url, data) in [("/part1", "<first part>"), ("/part2", "<second part>")] {
+                let request = server.recv().unwrap();
+                assert_eq!(request.url(), url);
+                request
+                    .respond(tiny_http::Response::from_string(data))
+                    .unwrap();
+            }
+        });
+
+        let req_handler = make_owned_handler(move |addr, body| async move {
+            let RouteId::QueryInput = addr.route else {
+                panic!("unexpected call");
+            };
+
+            assert_eq!(
+                body.try_collect::<BytesMut>().await.unwrap(),
+                "<first part><second part>"
+            );
+
+            Ok(HelperResponse::ok())
+        });
+        let test_server = TestServer::builder()
+            .with_request_handler(req_handler)
+            .build()
+            .await;
+
+        let port = addr.to_ip().unwrap().port();
+        let req = http_serde::query::input::Request::new(QueryInput::FromUrls {
+            query_id,
+            urls: vec![
+                format!("http://localhost:{port}/part1"),
+                format!("http://localhost:{port}/part2"),
+            ],
+        });
+        let hyper_req = req
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+
+        let resp = test_server.server.handle_req(hyper_req).await;
+        assert!(resp.status().is_success(), "{resp:?}");
+    }
+
+    #[tokio::test]
+    async fn input_from_file_requires_input_dir() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let req = http_serde::query::input::Request::new(QueryInput::FromUrls {
+            query_id: QueryId::from(0),
+            urls: vec![format!("file://{}", file.path().display())],
+        });
+        let hyper_req = req
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+
+        assert_fails_with(hyper_req, StatusCode::BAD_REQUEST).await;
+    }
+
     struct OverrideReq {
         query_id: String,
         input_stream: Vec<u8>,
@@ -165,7 +243,7 @@ mod tests {
     impl Default for OverrideReq {
         fn default() -> Self {
             Self {
-                query_id: QueryId.as_ref().to_string(),
+                query_id: QueryId::from(0).to_string(),
                 input_stream: vec![4; 4],
             }
         }
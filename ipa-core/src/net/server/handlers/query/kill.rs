@@ -6,19 +6,24 @@ use crate::{
     net::{
         http_serde::query::kill::{self, Request},
         server::Error,
-        transport::MpcHttpTransport,
+        ConnectionFlavor,
         Error::QueryIdNotFound,
+        HttpTransport,
     },
     protocol::QueryId,
     query::QueryKillStatus,
+    sync::Arc,
 };
 
-async fn handler(
-    transport: Extension<MpcHttpTransport>,
+async fn handler<F: ConnectionFlavor>(
+    transport: Extension<Arc<HttpTransport<F>>>,
     Path(query_id): Path<QueryId>,
 ) -> Result<Json<kill::ResponseBody>, Error> {
     let req = Request { query_id };
-    match transport.dispatch(req, BodyStream::empty()).await {
+    match Arc::clone(&transport)
+        .dispatch(req, BodyStream::empty())
+        .await
+    {
         Ok(state) => Ok(Json(kill::ResponseBody::from(state))),
         Err(ApiError::QueryKill(QueryKillStatus::NoSuchQuery(query_id))) => Err(
             Error::application(StatusCode::NOT_FOUND, QueryIdNotFound(query_id)),
@@ -27,9 +32,11 @@ async fn handler(
     }
 }
 
-pub fn router(transport: MpcHttpTransport) -> Router {
+/// Kill requests are accepted from report collectors and other helpers without authentication,
+/// and from other shards of this helper.
+pub fn router<F: ConnectionFlavor>(transport: Arc<HttpTransport<F>>) -> Router {
     Router::new()
-        .route(kill::AXUM_PATH, post(handler))
+        .route(kill::AXUM_PATH, post(handler::<F>))
         .layer(Extension(transport))
 }
 
@@ -59,7 +66,7 @@ mod tests {
 
     #[tokio::test]
     async fn calls_kill() {
-        let expected_query_id = QueryId;
+        let expected_query_id = QueryId::from(0);
 
         let handler = make_owned_handler(
             move |addr: Addr<HelperIdentity>, _data: BodyStream| async move {
@@ -71,7 +78,7 @@ mod tests {
             },
         );
 
-        let req = http_serde::query::kill::Request::new(QueryId);
+        let req = http_serde::query::kill::Request::new(QueryId::from(0));
         let req = req
             .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
             .unwrap();
@@ -82,11 +89,11 @@ mod tests {
     async fn no_such_query() {
         let handler = make_owned_handler(
             move |_addr: Addr<HelperIdentity>, _data: BodyStream| async move {
-                Err(QueryKillStatus::NoSuchQuery(QueryId).into())
+                Err(QueryKillStatus::NoSuchQuery(QueryId::from(0)).into())
             },
         );
 
-        let req = http_serde::query::kill::Request::new(QueryId)
+        let req = http_serde::query::kill::Request::new(QueryId::from(0))
             .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
             .unwrap();
         assert_fails_with_handler(req, handler, StatusCode::NOT_FOUND).await;
@@ -102,7 +109,7 @@ mod tests {
             },
         );
 
-        let req = http_serde::query::kill::Request::new(QueryId)
+        let req = http_serde::query::kill::Request::new(QueryId::from(0))
             .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
             .unwrap();
         assert_fails_with_handler(req, handler, StatusCode::INTERNAL_SERVER_ERROR).await;
@@ -1,3 +1,4 @@
+mod ack;
 mod create;
 mod input;
 mod kill;
@@ -7,7 +8,7 @@ mod status;
 mod status_match;
 mod step;
 
-use std::marker::PhantomData;
+use std::{marker::PhantomData, path::PathBuf};
 
 use axum::{
     response::{IntoResponse, Response},
@@ -33,12 +34,12 @@ use crate::{
 /// In principle, this web service could be backed by either an HTTP-interconnected helper network or
 /// an in-memory helper network. These are the APIs used by external callers (report collectors) to
 /// examine attribution results.
-pub fn query_router(transport: MpcHttpTransport) -> Router {
+pub fn query_router(transport: MpcHttpTransport, input_dir: Option<PathBuf>) -> Router {
     Router::new()
         .merge(create::router(transport.clone()))
-        .merge(input::router(transport.clone()))
+        .merge(input::router(transport.clone(), input_dir))
         .merge(status::router(transport.clone()))
-        .merge(kill::router(transport.clone()))
+        .merge(kill::router(Arc::clone(&transport.inner_transport)))
         .merge(results::router(transport.inner_transport))
 }
 
@@ -52,6 +53,7 @@ pub fn query_router(transport: MpcHttpTransport) -> Router {
 pub fn h2h_router(transport: Arc<HttpTransport<Helper>>) -> Router {
     Router::new()
         .merge(step::router(Arc::clone(&transport)))
+        .merge(ack::router(Arc::clone(&transport)))
         .merge(prepare::router(transport))
         .layer(layer_fn(HelperAuthentication::<_, Helper>::new))
 }
@@ -60,8 +62,10 @@ pub fn h2h_router(transport: Arc<HttpTransport<Helper>>) -> Router {
 pub fn s2s_router(transport: Arc<HttpTransport<Shard>>) -> Router {
     Router::new()
         .merge(step::router(Arc::clone(&transport)))
+        .merge(ack::router(Arc::clone(&transport)))
         .merge(prepare::router(Arc::clone(&transport)))
         .merge(results::router(Arc::clone(&transport)))
+        .merge(kill::router(Arc::clone(&transport)))
         .merge(status_match::router(transport))
         .layer(layer_fn(HelperAuthentication::<_, Shard>::new))
 }
@@ -25,12 +25,16 @@ async fn handler<F: ConnectionFlavor>(
     _: Extension<ClientIdentity<F::Identity>>, // require that client is an authenticated helper
     Path(query_id): Path<QueryId>,
     QueryConfigQueryParams(config): QueryConfigQueryParams,
-    Json(RequestBody { roles }): Json<RequestBody>,
+    Json(RequestBody {
+        roles,
+        report_collector,
+    }): Json<RequestBody>,
 ) -> Result<(), Error> {
     let data = PrepareQuery {
         query_id,
         config,
         roles,
+        report_collector,
     };
     let _ = Arc::clone(&transport)
         .dispatch(data, BodyStream::empty())
@@ -87,9 +91,10 @@ mod tests {
                 panic!("unexpected call");
             };
             let expected_prepare_query = PrepareQuery {
-                query_id: QueryId,
+                query_id: QueryId::from(0),
                 config: QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap(),
                 roles: RoleAssignment::new(HelperIdentity::make_three()),
+                report_collector: None,
             };
             let actual_prepare_query = addr.into::<PrepareQuery>().unwrap();
             assert_eq!(actual_prepare_query, expected_prepare_query);
@@ -123,7 +128,7 @@ mod tests {
         fn default() -> Self {
             Self {
                 client_id: Some(ClientIdentity(HelperIdentity::TWO)),
-                query_id: QueryId.as_ref().to_string(),
+                query_id: QueryId::from(0).to_string(),
                 field_type: format!("{:?}", FieldType::Fp31),
                 size: Some(1),
                 roles: OverrideReqRoles {
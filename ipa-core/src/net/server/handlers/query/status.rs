@@ -50,26 +50,45 @@ mod tests {
         query::QueryStatus,
     };
 
-    #[tokio::test]
-    async fn status_test() {
-        let expected_status = QueryStatus::Running;
-        let expected_query_id = QueryId;
+    async fn assert_status(expected_status: QueryStatus) {
+        let expected_query_id = QueryId::from(0);
 
-        let handler = make_owned_handler(
-            move |addr: Addr<HelperIdentity>, _data: BodyStream| async move {
-                let RouteId::QueryStatus = addr.route else {
-                    panic!("unexpected call");
-                };
-                assert_eq!(addr.query_id, Some(expected_query_id));
-                Ok(HelperResponse::from(expected_status))
-            },
-        );
+        let handler = make_owned_handler({
+            let expected_status = expected_status.clone();
+            move |addr: Addr<HelperIdentity>, _data: BodyStream| {
+                let expected_status = expected_status.clone();
+                async move {
+                    let RouteId::QueryStatus = addr.route else {
+                        panic!("unexpected call");
+                    };
+                    assert_eq!(addr.query_id, Some(expected_query_id));
+                    Ok(HelperResponse::from(expected_status))
+                }
+            }
+        });
 
-        let req = http_serde::query::status::Request::new(QueryId);
+        let req = http_serde::query::status::Request::new(QueryId::from(0));
         let req = req
             .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
             .unwrap();
-        assert_success_with(req, handler).await;
+        let body = assert_success_with(req, handler).await;
+        let http_serde::query::status::ResponseBody { status } =
+            serde_json::from_slice(&body).unwrap();
+        assert_eq!(expected_status, status);
+    }
+
+    #[tokio::test]
+    async fn status_test() {
+        assert_status(QueryStatus::Running).await;
+    }
+
+    #[tokio::test]
+    async fn failed_status_test() {
+        assert_status(QueryStatus::Failed {
+            reason: "DZKP Validation failed".to_string(),
+            step: Some("protocol/validate".to_string()),
+        })
+        .await;
     }
 
     struct OverrideReq {
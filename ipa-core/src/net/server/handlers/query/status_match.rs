@@ -74,7 +74,7 @@ mod tests {
 
     fn for_status(status: QueryStatus) -> CompareStatusRequest {
         CompareStatusRequest {
-            query_id: QueryId,
+            query_id: QueryId::from(0),
             status,
         }
     }
@@ -95,17 +95,18 @@ mod tests {
     }
 
     fn handler_status_match(expected_status: QueryStatus) -> Arc<dyn RequestHandler<ShardIndex>> {
-        make_owned_handler(
-            move |addr: Addr<ShardIndex>, _data: BodyStream| async move {
+        make_owned_handler(move |addr: Addr<ShardIndex>, _data: BodyStream| {
+            let expected_status = expected_status.clone();
+            async move {
                 let RouteId::QueryStatus = addr.route else {
                     panic!("unexpected call");
                 };
                 let req = addr.into::<CompareStatusRequest>().unwrap();
-                assert_eq!(req.query_id, QueryId);
+                assert_eq!(req.query_id, QueryId::from(0));
                 assert_eq!(req.status, expected_status);
                 Ok(HelperResponse::ok())
-            },
-        )
+            }
+        })
     }
 
     fn handler_status_mismatch(
@@ -113,26 +114,27 @@ mod tests {
     ) -> Arc<dyn RequestHandler<ShardIndex>> {
         assert_ne!(expected_status, QueryStatus::Running);
 
-        make_owned_handler(
-            move |addr: Addr<ShardIndex>, _data: BodyStream| async move {
+        make_owned_handler(move |addr: Addr<ShardIndex>, _data: BodyStream| {
+            let expected_status = expected_status.clone();
+            async move {
                 let RouteId::QueryStatus = addr.route else {
                     panic!("unexpected call");
                 };
                 let req = addr.into::<CompareStatusRequest>().unwrap();
-                assert_eq!(req.query_id, QueryId);
+                assert_eq!(req.query_id, QueryId::from(0));
                 Err(ApiError::QueryStatus(QueryStatusError::DifferentStatus {
-                    query_id: QueryId,
+                    query_id: QueryId::from(0),
                     my_status: QueryStatus::Running,
                     other_status: expected_status,
                 }))
-            },
-        )
+            }
+        })
     }
 
     #[tokio::test]
     async fn status_success() {
         let expected_status = QueryStatus::Running;
-        let req = authenticated(http_request(for_status(expected_status)));
+        let req = authenticated(http_request(for_status(expected_status.clone())));
 
         TestServer::<Shard>::oneshot_success(req, handler_status_match(expected_status)).await;
     }
@@ -141,7 +143,7 @@ mod tests {
     async fn status_client_success() {
         let expected_status = QueryStatus::Running;
         let test_server = TestServerBuilder::<Shard>::default()
-            .with_request_handler(handler_status_match(expected_status))
+            .with_request_handler(handler_status_match(expected_status.clone()))
             .build()
             .await;
 
@@ -156,7 +158,7 @@ mod tests {
     async fn status_client_mismatch() {
         let diff_status = QueryStatus::Preparing;
         let test_server = TestServerBuilder::<Shard>::default()
-            .with_request_handler(handler_status_mismatch(diff_status))
+            .with_request_handler(handler_status_mismatch(diff_status.clone()))
             .build()
             .await;
         let e = test_server
@@ -177,7 +179,7 @@ mod tests {
     #[tokio::test]
     async fn status_mismatch() {
         let req_status = QueryStatus::Completed;
-        let handler = handler_status_mismatch(req_status);
+        let handler = handler_status_mismatch(req_status.clone());
         let req = authenticated(http_request(for_status(req_status)));
 
         let resp = TestServer::<Shard>::oneshot(req, handler).await;
@@ -189,7 +191,7 @@ mod tests {
         let handler = make_owned_handler(
             move |_addr: Addr<ShardIndex>, _data: BodyStream| async move {
                 Err(ApiError::QueryStatus(QueryStatusError::NoSuchQuery(
-                    QueryId,
+                    QueryId::from(0),
                 )))
             },
         );
@@ -1,9 +1,13 @@
-use axum::{extract::Path, routing::post, Extension, Router};
+use axum::{
+    extract::{Path, Query},
+    routing::post,
+    Extension, Router,
+};
 
 use crate::{
     helpers::BodyStream,
     net::{
-        http_serde,
+        http_serde::{self, query::step::StepQueryString},
         server::{ClientIdentity, Error},
         ConnectionFlavor, HttpTransport,
     },
@@ -17,9 +21,10 @@ async fn handler<F: ConnectionFlavor>(
     transport: Extension<Arc<HttpTransport<F>>>,
     from: Extension<ClientIdentity<F::Identity>>,
     Path((query_id, gate)): Path<(QueryId, Gate)>,
+    Query(StepQueryString { offset }): Query<StepQueryString>,
     body: BodyStream,
 ) -> Result<(), Error> {
-    transport.receive_stream(query_id, gate, **from, body);
+    transport.receive_stream(query_id, gate, **from, offset, body);
     Ok(())
 }
 
@@ -66,7 +71,7 @@ mod tests {
 
         let mut stream = test_server
             .transport
-            .receive(HelperIdentity::TWO, &(QueryId, step))
+            .receive(HelperIdentity::TWO, &(QueryId::from(0), step))
             .into_bytes_stream();
 
         assert_eq!(
@@ -101,7 +106,7 @@ mod tests {
         fn default() -> Self {
             Self {
                 client_id: Some(ClientIdentity(HelperIdentity::ONE)),
-                query_id: QueryId.as_ref().to_string(),
+                query_id: QueryId::from(0).to_string(),
                 gate: Gate::default().narrow("test"),
                 payload: vec![1; DATA_LEN * MESSAGE_PAYLOAD_SIZE_BYTES],
             }
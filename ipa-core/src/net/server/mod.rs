@@ -42,13 +42,14 @@ use tracing::{error, Span};
 use super::{transport::MpcHttpTransport, HttpTransport, Shard};
 use crate::{
     config::{
-        NetworkConfig, OwnedCertificate, OwnedPrivateKey, PeerConfig, ServerConfig, TlsConfig,
+        NetworkConfig, OwnedCertificate, OwnedPrivateKey, PeerConfig, ReportCollectorConfig,
+        ServerConfig, TlsConfig,
     },
     error::BoxError,
     executor::{IpaJoinHandle, IpaRuntime},
-    helpers::TransportIdentity,
+    helpers::{query::ReportCollector, TransportIdentity},
     net::{
-        parse_certificate_and_private_key_bytes, server::config::HttpServerConfig,
+        parse_certificate_and_private_key_bytes, server::config::HttpServerConfig, trace_context,
         ConnectionFlavor, Error, Helper, CRYPTO_PROVIDER,
     },
     sync::Arc,
@@ -96,9 +97,12 @@ impl IpaHttpServer<Helper> {
         config: ServerConfig,
         network_config: NetworkConfig<Helper>,
     ) -> Self {
-        let router = handlers::mpc_router(MpcHttpTransport {
-            inner_transport: transport,
-        });
+        let router = handlers::mpc_router(
+            MpcHttpTransport {
+                inner_transport: transport,
+            },
+            config.query_input_dir.clone(),
+        );
         IpaHttpServer {
             config,
             network_config,
@@ -162,7 +166,10 @@ impl<F: ConnectionFlavor> IpaHttpServer<F> {
 
         let svc = self.router.clone().layer(
             TraceLayer::new_for_http()
-                .make_span_with(move |_request: &hyper::Request<_>| tracing.make_span())
+                .make_span_with(move |request: &hyper::Request<_>| {
+                    // requests sent by other helpers and shards continue the trace of the query
+                    trace_context::request_span(request).unwrap_or_else(|| tracing.make_span())
+                })
                 .on_request(|request: &hyper::Request<_>, _: &Span| {
                     counter!(RequestProtocolVersion::from(request.version()).as_str(), 1);
                     counter!(REQUESTS_RECEIVED, 1);
@@ -197,7 +204,11 @@ impl<F: ConnectionFlavor> IpaHttpServer<F> {
                 spawn_server(
                     runtime,
                     axum_server::from_tcp_rustls(listener, rustls_config).map(|a| {
-                        ClientCertRecognizingAcceptor::new(a, self.network_config.clone())
+                        ClientCertRecognizingAcceptor::new(
+                            a,
+                            self.network_config.clone(),
+                            &self.config.report_collectors,
+                        )
                     }),
                     handle.clone(),
                     svc.into_make_service(),
@@ -212,7 +223,11 @@ impl<F: ConnectionFlavor> IpaHttpServer<F> {
                 spawn_server(
                     runtime,
                     axum_server::bind_rustls(addr, rustls_config).map(|a| {
-                        ClientCertRecognizingAcceptor::new(a, self.network_config.clone())
+                        ClientCertRecognizingAcceptor::new(
+                            a,
+                            self.network_config.clone(),
+                            &self.config.report_collectors,
+                        )
                     }),
                     handle.clone(),
                     svc.into_make_service(),
@@ -308,7 +323,15 @@ async fn rustls_config(
     let (cert, key) = certificate_and_key(config).await?;
 
     let mut trusted_certs = RootCertStore::empty();
-    for cert in certs.into_iter().filter_map(|peer| peer.certificate) {
+    let report_collector_certs = config
+        .report_collectors
+        .iter()
+        .map(|rc| rc.certificate.clone());
+    for cert in certs
+        .into_iter()
+        .filter_map(|peer| peer.certificate)
+        .chain(report_collector_certs)
+    {
         // Note that this uses `webpki::TrustAnchor::try_from_cert_der`, which *does not* validate
         // the certificate. That is not required for security, but might be desirable to flag
         // configuration errors.
@@ -361,20 +384,27 @@ impl<I: TransportIdentity> TryFrom<&HeaderValue> for ClientIdentity<I> {
     }
 }
 
-/// `Accept`or that sets an axum `Extension` indiciating the authenticated remote helper identity.
-/// Validating the certificate is something that happens earlier at connection time, this just
-/// provide identity to the inner server handlers.
+/// `Accept`or that sets an axum `Extension` indiciating the authenticated remote helper identity,
+/// or the [`ReportCollector`] if the certificate belongs to one. Validating the certificate is
+/// something that happens earlier at connection time, this just provide identity to the inner
+/// server handlers.
 #[derive(Clone)]
 struct ClientCertRecognizingAcceptor<F: ConnectionFlavor> {
     inner: RustlsAcceptor,
     network_config: Arc<NetworkConfig<F>>,
+    report_collectors: Arc<[ReportCollectorConfig]>,
 }
 
 impl<F: ConnectionFlavor> ClientCertRecognizingAcceptor<F> {
-    fn new(inner: RustlsAcceptor, network_config: NetworkConfig<F>) -> Self {
+    fn new(
+        inner: RustlsAcceptor,
+        network_config: NetworkConfig<F>,
+        report_collectors: &[ReportCollectorConfig],
+    ) -> Self {
         Self {
             inner,
             network_config: Arc::new(network_config),
+            report_collectors: report_collectors.into(),
         }
     }
 }
@@ -392,6 +422,7 @@ where
     fn accept(&self, stream: I, service: S) -> Self::Future {
         let acceptor = self.inner.clone();
         let network_config = Arc::clone(&self.network_config);
+        let report_collectors = Arc::clone(&self.report_collectors);
 
         Box::pin(async move {
             let (stream, service) = acceptor.accept(stream, service).await.map_err(|err| {
@@ -411,11 +442,22 @@ where
                 .1
                 .peer_certificates()
                 .and_then(<[_]>::first);
-            let option_id: Option<F::Identity> = network_config.identify_cert(opt_cert);
+            let report_collector = opt_cert.and_then(|cert| {
+                report_collectors
+                    .iter()
+                    .find(|rc| &rc.certificate == cert)
+                    .map(|rc| rc.name.clone())
+            });
+            let option_id: Option<F::Identity> = if report_collector.is_some() {
+                None
+            } else {
+                network_config.identify_cert(opt_cert)
+            };
             let client_id = option_id.map(ClientIdentity);
             let service = SetClientIdentityFromCertificate {
                 inner: service,
                 id: client_id,
+                report_collector,
             };
             Ok((stream, service))
         })
@@ -426,6 +468,7 @@ where
 struct SetClientIdentityFromCertificate<S, F: ConnectionFlavor> {
     inner: S,
     id: Option<ClientIdentity<F::Identity>>,
+    report_collector: Option<ReportCollector>,
 }
 
 impl<B, F, S> Service<Request<B>> for SetClientIdentityFromCertificate<S, F>
@@ -446,6 +489,9 @@ where
         if let Some(id) = self.id {
             req.extensions_mut().insert(id);
         }
+        if let Some(report_collector) = &self.report_collector {
+            req.extensions_mut().insert(report_collector.clone());
+        }
         self.inner.call(req)
     }
 }
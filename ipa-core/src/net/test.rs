@@ -31,7 +31,9 @@ use crate::{
         TlsConfig,
     },
     executor::IpaRuntime,
-    helpers::{HandlerBox, HelperIdentity, RequestHandler, StreamCollection, TransportIdentity},
+    helpers::{
+        HandlerBox, HelperIdentity, RequestHandler, StreamAcks, StreamCollection, TransportIdentity,
+    },
     hpke::{Deserializable as _, IpaPublicKey},
     net::{ClientIdentity, Helper, IpaHttpClient, IpaHttpServer},
     sharding::{ShardIndex, ShardedHelperIdentity},
@@ -192,6 +194,9 @@ fn server_config_insecure_http(port: u16, matchkey_encryption: bool) -> ServerCo
         disable_https: true,
         tls: None,
         hpke_config: get_dummy_matchkey_encryption_info(matchkey_encryption),
+        query_results: None,
+        query_input_dir: None,
+        report_collectors: Vec::new(),
     }
 }
 
@@ -210,6 +215,9 @@ fn server_config_https(
             private_key: String::from_utf8(private_key.to_owned()).unwrap(),
         }),
         hpke_config: get_dummy_matchkey_encryption_info(matchkey_encryption),
+        query_results: None,
+        query_input_dir: None,
+        report_collectors: Vec::new(),
     }
 }
 
@@ -655,6 +663,7 @@ trait TestTransportConfigurator {
             identity: Self::IDENTITY,
             clients,
             record_streams: StreamCollection::default(),
+            record_acks: StreamAcks::default(),
             handler,
         };
 
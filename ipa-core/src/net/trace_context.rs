@@ -0,0 +1,141 @@
+//! Propagation of the trace context across helper and shard requests, in the
+//! [W3C Trace Context](https://www.w3.org/TR/trace-context/) format. This is what makes spans
+//! of a query on all helpers and shards appear in a single distributed trace.
+//!
+//! Trace context is only propagated when the `otlp` feature is enabled and the exporter is
+//! installed. Otherwise these functions do nothing.
+
+use hyper::{HeaderMap, Request};
+use tracing::Span;
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use hyper::{
+        header::{HeaderName, HeaderValue},
+        HeaderMap, Request,
+    };
+    use opentelemetry::{
+        global,
+        propagation::{Extractor, Injector},
+        trace::TraceContextExt,
+    };
+    use tracing::Span;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+    impl Injector for HeaderInjector<'_> {
+        fn set(&mut self, key: &str, value: String) {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(key.as_bytes()),
+                HeaderValue::from_str(&value),
+            ) {
+                self.0.insert(name, value);
+            }
+        }
+    }
+
+    struct HeaderExtractor<'a>(&'a HeaderMap);
+
+    impl Extractor for HeaderExtractor<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|v| v.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(HeaderName::as_str).collect()
+        }
+    }
+
+    pub fn inject(headers: &mut HeaderMap) {
+        let context = Span::current().context();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut HeaderInjector(headers));
+        });
+    }
+
+    pub fn request_span<B>(request: &Request<B>) -> Option<Span> {
+        let context = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(request.headers()))
+        });
+        if !context.span().span_context().is_valid() {
+            return None;
+        }
+
+        let span = tracing::info_span!(
+            "request",
+            method = %request.method(),
+            path = request.uri().path(),
+        );
+        span.set_parent(context);
+
+        Some(span)
+    }
+}
+
+/// Adds the context of the current span to the headers of an outgoing request.
+#[cfg(feature = "otlp")]
+pub fn inject(headers: &mut HeaderMap) {
+    otlp::inject(headers);
+}
+
+#[cfg(not(feature = "otlp"))]
+pub fn inject(_headers: &mut HeaderMap) {}
+
+/// Returns the span for an incoming request that continues the trace of the helper or shard
+/// that sent it, or [`None`] if the request does not carry a trace context.
+#[cfg(feature = "otlp")]
+pub fn request_span<B>(request: &Request<B>) -> Option<Span> {
+    otlp::request_span(request)
+}
+
+#[cfg(not(feature = "otlp"))]
+pub fn request_span<B>(_request: &Request<B>) -> Option<Span> {
+    None
+}
+
+#[cfg(all(test, unit_test, feature = "otlp"))]
+mod tests {
+    use hyper::{HeaderMap, Request};
+    use opentelemetry::{global, trace::TraceContextExt};
+    use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::TracerProvider};
+    use tracing::info_span;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::{inject, request_span};
+
+    #[test]
+    fn request_continues_trace() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = TracerProvider::builder().build();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(
+                opentelemetry::trace::TracerProvider::tracer(&provider, "test"),
+            ));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let query_span = info_span!("query");
+            let mut headers = HeaderMap::new();
+            query_span.in_scope(|| inject(&mut headers));
+            assert!(headers.contains_key("traceparent"));
+
+            let mut request = Request::get("/query/1/step/foo").body(()).unwrap();
+            *request.headers_mut() = headers;
+            let span = request_span(&request).unwrap();
+
+            assert_eq!(
+                query_span.context().span().span_context().trace_id(),
+                span.context().span().span_context().trace_id()
+            );
+        });
+    }
+
+    #[test]
+    fn request_without_context() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let request = Request::get("/query").body(()).unwrap();
+
+        assert!(request_span(&request).is_none());
+    }
+}
@@ -8,6 +8,7 @@ use std::{
 use async_trait::async_trait;
 use futures::{Stream, TryFutureExt};
 use pin_project::{pin_project, pinned_drop};
+use tokio::sync::watch;
 
 use super::{client::resp_ok, error::ShardError, ConnectionFlavor, Helper, Shard};
 use crate::{
@@ -17,8 +18,9 @@ use crate::{
         query::QueryConfig,
         routing::{Addr, RouteId},
         ApiError, BodyStream, HandlerRef, HelperIdentity, HelperResponse, NoQueryId,
-        NoResourceIdentifier, NoStep, QueryIdBinding, ReceiveRecords, RequestHandler, RouteParams,
-        StepBinding, StreamCollection, Transport, TransportIdentity,
+        NoResourceIdentifier, NoStep, QueryIdBinding, ReceiveRecords, RecordsAckRoute,
+        RequestHandler, RouteParams, StepBinding, StreamAcks, StreamCollection, Transport,
+        TransportIdentity,
     },
     net::{client::IpaHttpClient, error::Error, IpaHttpServer},
     protocol::{Gate, QueryId},
@@ -32,6 +34,7 @@ pub struct HttpTransport<F: ConnectionFlavor> {
     pub(super) identity: F::Identity,
     pub(super) clients: Vec<IpaHttpClient<F>>,
     pub(super) record_streams: StreamCollection<F::Identity, BodyStream>,
+    pub(super) record_acks: StreamAcks<F::Identity>,
     pub(super) handler: Option<HandlerRef<F::Identity>>,
 }
 
@@ -93,7 +96,13 @@ impl<F: ConnectionFlavor> HttpTransport<F> {
                     .expect("query_id required when sending records");
                 let step =
                     <Option<Gate>>::from(route.gate()).expect("step required when sending records");
-                let resp_future = self.clients[client_ix].step(query_id, &step, data)?;
+                // streams that don't carry an offset start at the beginning
+                let resume_from = match route.extra().borrow() {
+                    "" => None,
+                    offset => Some(serde_json::from_str(offset)?),
+                };
+                let resp_future =
+                    self.clients[client_ix].step(query_id, &step, resume_from, data)?;
                 // Use a dedicated HTTP runtime to poll this future for several reasons:
                 // - avoid blocking this task, if the current runtime is overloaded
                 // - use the runtime that enables IO (current runtime may not).
@@ -102,6 +111,16 @@ impl<F: ConnectionFlavor> HttpTransport<F> {
                     .await?;
                 Ok(())
             }
+            RouteId::RecordsAck => {
+                let query_id = <Option<QueryId>>::from(route.query_id())
+                    .expect("query_id required when acknowledging records");
+                let step = <Option<Gate>>::from(route.gate())
+                    .expect("step required when acknowledging records");
+                let offset = serde_json::from_str(route.extra().borrow())?;
+                self.clients[client_ix]
+                    .records_ack(query_id, &step, offset)
+                    .await
+            }
             RouteId::PrepareQuery => {
                 let req = serde_json::from_str(route.extra().borrow()).unwrap();
                 self.clients[client_ix].prepare_query(req).await
@@ -111,14 +130,16 @@ impl<F: ConnectionFlavor> HttpTransport<F> {
                     .expect("query_id is required to call complete query API");
                 self.clients[client_ix].complete_query(query_id).await
             }
+            RouteId::KillQuery => {
+                let query_id = <Option<QueryId>>::from(route.query_id())
+                    .expect("query_id is required to call kill query API");
+                self.clients[client_ix].kill_query(query_id).await
+            }
             RouteId::QueryStatus => {
                 let req = serde_json::from_str(route.extra().borrow())?;
                 self.clients[client_ix].status_match(req).await
             }
-            evt @ (RouteId::QueryInput
-            | RouteId::ReceiveQuery
-            | RouteId::KillQuery
-            | RouteId::Metrics) => {
+            evt @ (RouteId::QueryInput | RouteId::ReceiveQuery | RouteId::Metrics) => {
                 unimplemented!(
                     "attempting to send client-specific request {evt:?} to another helper"
                 )
@@ -127,28 +148,68 @@ impl<F: ConnectionFlavor> HttpTransport<F> {
     }
 
     pub(crate) fn receive<R: RouteParams<NoResourceIdentifier, QueryId, Gate>>(
-        &self,
+        self: &Arc<Self>,
         from: F::Identity,
         route: &R,
     ) -> ReceiveRecords<F::Identity, BodyStream> {
+        let (query_id, gate) = (route.query_id(), route.gate());
+        let ack = {
+            let transport = Arc::downgrade(self);
+            let gate = gate.clone();
+            move |offset| {
+                let Some(transport) = transport.upgrade() else {
+                    return;
+                };
+                let route = RecordsAckRoute {
+                    query_id,
+                    gate: gate.clone(),
+                    offset,
+                };
+                drop(transport.http_runtime.clone().spawn(async move {
+                    if let Err(e) = transport.send(from, route, futures::stream::empty()).await {
+                        tracing::warn!("failed to acknowledge records up to {offset}: {e:?}");
+                    }
+                }));
+            }
+        };
         ReceiveRecords::new(
-            (route.query_id(), from, route.gate()),
+            (query_id, from, gate),
             self.record_streams.clone(),
+            Box::new(ack),
         )
     }
 
+    pub(crate) fn acks<R: RouteParams<NoResourceIdentifier, QueryId, Gate>>(
+        &self,
+        to: F::Identity,
+        route: &R,
+    ) -> watch::Receiver<u64> {
+        self.record_acks
+            .subscribe((route.query_id(), to, route.gate()))
+    }
+
     /// Connect an inbound stream of record data.
     ///
-    /// This is called by peer entities (shards or helpers) via the HTTP server.
+    /// This is called by peer entities (shards or helpers) via the HTTP server. `resume_from`
+    /// is the position of the first byte of `stream`, if it resumes a stream that was cut short.
     pub fn receive_stream(
         &self,
         query_id: QueryId,
         gate: Gate,
         from: F::Identity,
+        resume_from: Option<u64>,
         stream: BodyStream,
     ) {
         self.record_streams
-            .add_stream((query_id, from, gate), stream);
+            .add_stream_at((query_id, from, gate), resume_from, stream);
+    }
+
+    /// Records that `from` has received all bytes of the records stream sent to it for the
+    /// given query and step, up to `offset`.
+    ///
+    /// This is called by peer entities (shards or helpers) via the HTTP server.
+    pub fn receive_ack(&self, query_id: QueryId, gate: Gate, from: F::Identity, offset: u64) {
+        self.record_acks.ack(&(query_id, from, gate), offset);
     }
 
     /// Dispatches the given request to the [`RequestHandler`] connected to this transport.
@@ -166,14 +227,12 @@ impl<F: ConnectionFlavor> HttpTransport<F> {
     where
         Option<QueryId>: From<Q>,
     {
-        /// Cleans up the `records_stream` collection after drop to ensure this transport
-        /// can process the next query even in case of a panic.
-        ///
-        /// This implementation is a poor man's safety net and only works because we run
-        /// one query at a time and don't use query identifiers.
+        /// Cleans up streams that belong to the given query from the `records_stream` collection
+        /// after drop, even in case of a panic. Streams of other queries are not affected.
         #[pin_project(PinnedDrop)]
         struct ClearOnDrop<CF: ConnectionFlavor, F: Future> {
             transport: Arc<HttpTransport<CF>>,
+            query_id: QueryId,
             #[pin]
             inner: F,
         }
@@ -189,25 +248,29 @@ impl<F: ConnectionFlavor> HttpTransport<F> {
         #[pinned_drop]
         impl<CF: ConnectionFlavor, F: Future> PinnedDrop for ClearOnDrop<CF, F> {
             fn drop(self: Pin<&mut Self>) {
-                self.transport.record_streams.clear();
+                self.transport.record_streams.clear_query(self.query_id);
+                self.transport.record_acks.clear_query(self.query_id);
             }
         }
 
         let route_id = req.resource_identifier();
+        let query_id = <Option<QueryId>>::from(req.query_id());
         let r = self
             .handler
             .as_ref()
             .expect("A Handler should be set by now")
             .handle(Addr::from_route(None, req), body);
 
-        if let RouteId::CompleteQuery | RouteId::KillQuery = route_id {
-            ClearOnDrop {
-                transport: Arc::clone(&self),
-                inner: r,
+        match (route_id, query_id) {
+            (RouteId::CompleteQuery | RouteId::KillQuery, Some(query_id)) => {
+                ClearOnDrop {
+                    transport: Arc::clone(&self),
+                    query_id,
+                    inner: r,
+                }
+                .await
             }
-            .await
-        } else {
-            r.await
+            _ => r.await,
         }
     }
 }
@@ -228,6 +291,7 @@ impl MpcHttpTransport {
             clients: clients.to_vec(),
             handler,
             record_streams: StreamCollection::default(),
+            record_acks: StreamAcks::default(),
         });
 
         let server =
@@ -243,10 +307,11 @@ impl MpcHttpTransport {
         query_id: QueryId,
         gate: Gate,
         from: HelperIdentity,
+        resume_from: Option<u64>,
         stream: BodyStream,
     ) {
         self.inner_transport
-            .receive_stream(query_id, gate, from, stream);
+            .receive_stream(query_id, gate, from, resume_from, stream);
     }
 
     /// Dispatches the given request to the [`RequestHandler`] connected to this transport.
@@ -315,6 +380,14 @@ impl Transport for MpcHttpTransport {
     ) -> Self::RecordsStream {
         self.inner_transport.receive(from, &route)
     }
+
+    fn acks<R: RouteParams<NoResourceIdentifier, QueryId, Gate>>(
+        &self,
+        to: Self::Identity,
+        route: R,
+    ) -> watch::Receiver<u64> {
+        self.inner_transport.acks(to, &route)
+    }
 }
 
 impl ShardHttpTransport {
@@ -335,6 +408,7 @@ impl ShardHttpTransport {
             clients,
             handler,
             record_streams: StreamCollection::default(),
+            record_acks: StreamAcks::default(),
         });
 
         let server =
@@ -398,6 +472,14 @@ impl Transport for ShardHttpTransport {
     ) -> Self::RecordsStream {
         self.inner_transport.receive(from, &route)
     }
+
+    fn acks<R: RouteParams<NoResourceIdentifier, QueryId, Gate>>(
+        &self,
+        to: Self::Identity,
+        route: R,
+    ) -> watch::Receiver<u64> {
+        self.inner_transport.acks(to, &route)
+    }
 }
 
 #[cfg(all(test, web_test, descriptive_gate))]
@@ -446,18 +528,23 @@ mod tests {
             .build()
             .await;
 
-        transport.record_streams.add_stream(
-            (QueryId, HelperIdentity::ONE, Gate::default()),
-            BodyStream::empty(),
-        );
-        assert_eq!(1, transport.record_streams.len());
+        let killed = QueryId::from(1);
+        let other = QueryId::from(2);
+        for query_id in [killed, other] {
+            transport.record_streams.add_stream(
+                (query_id, HelperIdentity::ONE, Gate::default()),
+                BodyStream::empty(),
+            );
+        }
+        assert_eq!(2, transport.record_streams.len());
 
         Arc::clone(&transport)
-            .dispatch((RouteId::KillQuery, QueryId), BodyStream::empty())
+            .dispatch((RouteId::KillQuery, killed), BodyStream::empty())
             .await
             .unwrap();
 
-        assert!(transport.record_streams.is_empty());
+        // streams that belong to other queries must not be affected
+        assert_eq!(1, transport.record_streams.len());
     }
 
     #[tokio::test]
@@ -471,11 +558,17 @@ mod tests {
         let body = BodyStream::from_bytes_stream(ReceiverStream::new(rx));
 
         // Register the stream with the transport (normally called by step data HTTP API handler)
-        transport.receive_stream(QueryId, STEP.clone(), HelperIdentity::TWO, body);
+        transport.receive_stream(
+            QueryId::from(0),
+            STEP.clone(),
+            HelperIdentity::TWO,
+            None,
+            body,
+        );
 
         // Request step data reception (normally called by protocol)
         let mut stream = transport
-            .receive(HelperIdentity::TWO, &(QueryId, STEP.clone()))
+            .receive(HelperIdentity::TWO, &(QueryId::from(0), STEP.clone()))
             .into_bytes_stream();
 
         // make sure it is not ready as it hasn't received any data yet.
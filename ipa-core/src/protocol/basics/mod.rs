@@ -104,6 +104,12 @@ impl<B: ShardBinding> BooleanProtocols<DZKPUpgradedSemiHonestContext<'_, B>, 8>
 {
 }
 
+// Used by WALR queries with 8 features.
+impl<B: ShardBinding> BooleanProtocols<DZKPUpgradedMaliciousContext<'_, B>, 8>
+    for AdditiveShare<Boolean, 8>
+{
+}
+
 impl<B: ShardBinding> BooleanProtocols<DZKPUpgradedSemiHonestContext<'_, B>, PRF_CHUNK>
     for AdditiveShare<Boolean, PRF_CHUNK>
 {
@@ -161,4 +167,15 @@ const_assert_eq!(
     256,
     "Implementation for N = 256 required for num_breakdowns"
 );
+
+// Used by hybrid queries with 16-bit breakdown keys.
+impl<B: ShardBinding> BooleanProtocols<DZKPUpgradedSemiHonestContext<'_, B>, 1024>
+    for AdditiveShare<Boolean, 1024>
+{
+}
+
+impl<B: ShardBinding> BooleanProtocols<DZKPUpgradedMaliciousContext<'_, B>, 1024>
+    for AdditiveShare<Boolean, 1024>
+{
+}
 // End implementations for num_breakdowns
@@ -1,6 +1,7 @@
 use std::{cmp::min, collections::VecDeque, future::Future};
 
 use bitvec::{bitvec, prelude::BitVec};
+use ipa_metrics::counter;
 use tokio::sync::watch;
 
 use crate::{
@@ -8,6 +9,7 @@ use crate::{
     helpers::TotalRecords,
     protocol::{context::dzkp_validator::TARGET_PROOF_SIZE, RecordId},
     sync::Mutex,
+    telemetry::metrics::DZKP_BATCH_INCREMENTS,
 };
 
 /// Manages validation of batches of records for malicious protocols.
@@ -115,7 +117,9 @@ impl<'a, B> Batcher<'a, B> {
 
     fn get_batch_by_offset(&mut self, batch_offset: usize) -> &mut BatchState<B> {
         if self.batches.len() <= batch_offset {
-            self.batches.reserve(batch_offset - self.batches.len() + 1);
+            let new_batches = batch_offset - self.batches.len() + 1;
+            counter!(DZKP_BATCH_INCREMENTS, new_batches as u64);
+            self.batches.reserve(new_batches);
             let pending_records_capacity = self.records_per_batch.min(TARGET_PROOF_SIZE);
             while self.batches.len() <= batch_offset {
                 let (validation_result, _) = watch::channel::<bool>(false);
@@ -291,6 +295,7 @@ mod tests {
     };
 
     use super::*;
+    use crate::protocol::Gate;
 
     #[test]
     fn makes_batches() {
@@ -471,7 +476,9 @@ mod tests {
             .unwrap()
             .validate_record(RecordId::from(1), |i, b| {
                 assert!(i == 0 && b.as_slice() == [0, 1]);
-                ready(Err(Error::DZKPValidationFailed))
+                ready(Err(Error::DZKPValidationFailed {
+                    step: Gate::default(),
+                }))
             }));
         let mut fut2 = pin!(batcher
             .lock()
@@ -488,7 +495,10 @@ mod tests {
         assert!(poll_immediate(&mut fut0).await.is_none());
         assert!(poll_immediate(&mut fut2).await.is_none());
 
-        assert!(matches!(fut1.await, Err(Error::DZKPValidationFailed)));
+        assert!(matches!(
+            fut1.await,
+            Err(Error::DZKPValidationFailed { .. })
+        ));
         assert!(matches!(
             poll_immediate(&mut fut0).await,
             Some(Err(Error::ParallelDZKPValidationFailed))
@@ -408,6 +408,61 @@ where
     S: Fn(C, RecordId, &K) -> ShardIndex,
     K: Message + Clone,
     C: ShardedContext,
+{
+    let shard_records_est = {
+        let v = input.size_hint().1.unwrap_or(0) / usize::from(ctx.shard_count());
+        // this gives us ~ 1.25 capacity, very close to 1.26 overhead estimated
+        // If 25% extra capacity becomes a problem and number of events/shards is not close
+        // to the worst case, this can be tuned down to 1.01
+        v + v / 4
+    };
+
+    // This contains the deterministic order of events after resharding is complete.
+    // Each shard will hold the records in this order:
+    // [shard_0_records], [shard_1_records], ..., [shard_N].
+    // There is no reason why this strategy was chosen. As long as it is consistent across helpers,
+    // other ways to build the total order work too. For example, we could put records with
+    // record_id = 0 first, then records with record_id = 1, etc.
+    let mut r: Vec<Vec<_>> = ctx
+        .shard_count()
+        .iter()
+        .map(|_| Vec::with_capacity(shard_records_est))
+        .collect();
+
+    reshard_try_stream_for_each(ctx, input, shard_picker, |shard_id, m| {
+        r[usize::from(shard_id)].push(m);
+    })
+    .await?;
+
+    Ok(r.into_iter().flatten().collect())
+}
+
+/// Same as [`reshard_try_stream`], but instead of collecting the records that stay on this shard,
+/// it passes each of them to `f` as soon as it is available, along with the shard it came from.
+///
+/// Records are not buffered, so peak memory does not depend on the input size. The order in
+/// which `f` sees the records is not deterministic, so this is meant for records that don't need
+/// to be consistently ordered across helpers, such as tags that are only checked for uniqueness.
+///
+/// ## Panics
+/// When `shard_picker` returns an out-of-bounds index or if the input stream size
+/// upper bound is not known.
+///
+/// ## Errors
+/// If cross-shard communication fails or if an input stream
+/// yields an `Err` element.
+pub async fn reshard_try_stream_for_each<L, K, C, S, F>(
+    ctx: C,
+    input: L,
+    shard_picker: S,
+    mut f: F,
+) -> Result<(), crate::error::Error>
+where
+    L: Stream<Item = Result<K, crate::error::Error>>,
+    S: Fn(C, RecordId, &K) -> ShardIndex,
+    K: Message + Clone,
+    C: ShardedContext,
+    F: FnMut(ShardIndex, K),
 {
     let (_, Some(input_len)) = input.size_hint() else {
         panic!("input stream must have size upper bound for resharding to work")
@@ -497,25 +552,6 @@ where
         },
     )
     .fuse();
-    let shard_records_est = {
-        let v = input_len / usize::from(ctx.shard_count());
-        // this gives us ~ 1.25 capacity, very close to 1.26 overhead estimated
-        // If 25% extra capacity becomes a problem and number of events/shards is not close
-        // to the worst case, this can be tuned down to 1.01
-        v + v / 4
-    };
-
-    // This contains the deterministic order of events after resharding is complete.
-    // Each shard will hold the records in this order:
-    // [shard_0_records], [shard_1_records], ..., [shard_N].
-    // There is no reason why this strategy was chosen. As long as it is consistent across helpers,
-    // other ways to build the total order work too. For example, we could put records with
-    // record_id = 0 first, then records with record_id = 1, etc.
-    let mut r: Vec<Vec<_>> = ctx
-        .shard_count()
-        .iter()
-        .map(|_| Vec::with_capacity(shard_records_est))
-        .collect();
 
     // Interleave send and receive streams to ensure the backpressure does not block the flow.
     // For example, if this shard just sends all the data and then receives, the flow control from
@@ -526,11 +562,11 @@ where
 
     while let Some((shard_id, v)) = send_recv.try_next().await? {
         if let Some(m) = v {
-            r[usize::from(shard_id)].push(m);
+            f(shard_id, m);
         }
     }
 
-    Ok(r.into_iter().flatten().collect())
+    Ok(())
 }
 
 /// Provides the same functionality as [`reshard_try_stream`] on
@@ -638,7 +674,7 @@ mod tests {
         protocol::{
             basics::ShareKnownValue,
             context::{
-                reshard_iter, reshard_stream, reshard_try_stream,
+                reshard_iter, reshard_stream, reshard_try_stream, reshard_try_stream_for_each,
                 step::MaliciousProtocolStep::MaliciousProtocol, upgrade::Upgradable, Context,
                 ShardedContext, UpgradableContext, Validator,
             },
@@ -997,6 +1033,39 @@ mod tests {
         });
     }
 
+    #[test]
+    fn reshard_try_stream_for_each_basic() {
+        run(|| async move {
+            const SHARDS: u32 = 5;
+            let input: Vec<_> = (0..SHARDS).map(BA8::truncate_from).collect();
+            let world: TestWorld<WithShards<5>> =
+                TestWorld::with_shards(TestWorldConfig::default());
+            let r = world
+                .semi_honest(input.clone().into_iter(), |ctx, shard_input| async move {
+                    let mut received = Vec::new();
+                    reshard_try_stream_for_each(
+                        ctx,
+                        stream::iter(shard_input).map(Ok),
+                        |_, record_id, _| ShardIndex::from(u32::from(record_id) % SHARDS),
+                        |from, v| received.push((from, v)),
+                    )
+                    .await
+                    .unwrap();
+
+                    // records from the same shard arrive in order, so grouping them by the
+                    // shard they came from makes the output consistent across helpers
+                    received.sort_by_key(|(from, _)| *from);
+                    received.into_iter().map(|(_, v)| v).collect::<Vec<_>>()
+                })
+                .await
+                .into_iter()
+                .flat_map(|v| v.reconstruct())
+                .collect::<Vec<_>>();
+
+            assert_eq!(input, r);
+        });
+    }
+
     #[test]
     #[should_panic(expected = "RecordIdOutOfRange { record_id: RecordId(1), total_records: 1 }")]
     fn reshard_try_stream_more_items_than_expected() {
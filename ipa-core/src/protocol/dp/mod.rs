@@ -1,28 +1,31 @@
 // DP in MPC
 pub mod step;
 
-use std::{convert::Infallible, f64};
+use std::{convert::Infallible, f64, iter::zip};
 
-use futures_util::{stream, StreamExt};
+use futures_util::{stream, StreamExt, TryStreamExt};
 use rand_core::{CryptoRng, RngCore};
 
 use crate::{
     error::{
-        Error::{self, EpsilonOutOfBounds},
+        Error::{self, DeltaOutOfBounds, EpsilonOutOfBounds},
         LengthError,
     },
-    ff::{boolean::Boolean, boolean_array::BooleanArray, U128Conversions},
+    ff::{boolean::Boolean, boolean_array::BooleanArray, ArrayAccess, U128Conversions},
     helpers::{query::DpMechanism, Direction, Role, TotalRecords},
     protocol::{
+        basics::{reveal, Reveal, ShareKnownValue},
         boolean::step::ThirtyTwoBitStep,
         context::{
-            dzkp_validator::DZKPValidator, Context, DZKPUpgraded, MaliciousProtocolSteps,
-            UpgradableContext,
+            dzkp_validator::{validated_seq_join, DZKPValidator, TARGET_PROOF_SIZE},
+            Context, DZKPUpgraded, MaliciousProtocolSteps, UpgradableContext,
         },
         dp::step::{ApplyDpNoise, DPStep},
         ipa_prf::{
             aggregation::{aggregate_values, aggregate_values_proof_chunk},
-            boolean_ops::addition_sequential::integer_add,
+            boolean_ops::{
+                addition_sequential::integer_add, comparison_and_subtraction_sequential::compare_gt,
+            },
             oprf_padding::insecure::OPRFPaddingDp,
             step::IpaPrfStep,
         },
@@ -31,8 +34,10 @@ use crate::{
     },
     secret_sharing::{
         replicated::{semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing},
-        BitDecomposed, FieldSimd, TransposeFrom, Vectorizable,
+        BitDecomposed, FieldSimd, SharedValue, TransposeFrom, Vectorizable,
     },
+    seq_join::{seq_join, SeqJoin},
+    utils::non_zero_prev_power_of_two,
 };
 
 /// For documentation on the Binomial DP noise generation in MPC see
@@ -214,22 +219,113 @@ where
     Ok(Vec::transposed_from(&histogram_noised)?)
 }
 
-// dp_for_aggregation is currently where the DP parameters epsilon, delta
+/// Bounds on how much a single user can change an aggregate that is protected with DP noise.
+///
+/// A user may change at most `nonzero_coordinates` coordinates of the aggregate, each by at
+/// most `max_value`. The ℓ1, ℓ2 and ℓ∞ sensitivities used to calibrate the noise follow
+/// from these two numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sensitivity {
+    nonzero_coordinates: u32,
+    max_value: u32,
+}
+
+impl Sensitivity {
+    /// Sensitivity of a histogram where each user contributes at most `per_user_credit_cap`
+    /// to a single bucket.
+    #[must_use]
+    pub fn histogram(per_user_credit_cap: u32) -> Self {
+        Self {
+            nonzero_coordinates: 1,
+            max_value: per_user_credit_cap,
+        }
+    }
+
+    /// Sensitivity of a sum of vectors, where each user contributes at most one vector
+    /// with `dimensions` coordinates, each bounded by `max_value`.
+    #[must_use]
+    pub fn vector(dimensions: u32, max_value: u32) -> Self {
+        Self {
+            nonzero_coordinates: dimensions,
+            max_value,
+        }
+    }
+
+    /// ## Panics
+    /// If the ℓ1 sensitivity does not fit in a `u32`.
+    #[must_use]
+    pub fn ell_1(&self) -> u32 {
+        self.nonzero_coordinates
+            .checked_mul(self.max_value)
+            .expect("l1 sensitivity must fit in u32")
+    }
+
+    #[must_use]
+    pub fn ell_2(&self) -> f64 {
+        f64::from(self.nonzero_coordinates).sqrt() * f64::from(self.max_value)
+    }
+
+    #[must_use]
+    pub fn ell_infty(&self) -> u32 {
+        self.max_value
+    }
+}
+
+// dp_for_histogram is currently where the DP parameters epsilon, delta
 // are introduced and then from those the parameters of the noise distribution to generate are
 // calculated for use in aggregating histograms.  The DP parameters query_epsilon and
-// per_user_credit_cap come as inputs to the query with per_user_sensitivity_cap = 2^{SS_BITS}
+// per_user_credit_cap come as inputs to the query
 /// # Errors
 /// will propogate errors from `apply_dp_noise`
 /// Will return an error epsilon is not in the range (0,`MAX_EPSILON`); we allow very large
 /// epsilons to make the noise gen circuit small enough for concurency testing to be possible.
+/// With binomial noise, will also return an error if delta is not in the range (0, 1).
+/// # Panics
+/// may panic from asserts down in  `gen_binomial_noise`
+///
+#[tracing::instrument(name = "dp_for_histogram", skip_all)]
+pub async fn dp_for_histogram<C, const B: usize, OV>(
+    ctx: C,
+    histogram_bin_values: BitDecomposed<Replicated<Boolean, B>>,
+    per_user_credit_cap: u32,
+    dp_params: DpMechanism,
+) -> Result<Vec<Replicated<OV>>, Error>
+where
+    C: UpgradableContext,
+    Boolean: Vectorizable<B> + FieldSimd<B>,
+    BitDecomposed<Replicated<Boolean, B>>: FromPrss<usize>,
+    OV: BooleanArray + U128Conversions,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+    Vec<Replicated<OV>>:
+        for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<OV>; B], Error = Infallible>,
+{
+    dp_for_aggregation::<_, B, OV>(
+        ctx,
+        histogram_bin_values,
+        dp_params,
+        Sensitivity::histogram(per_user_credit_cap),
+    )
+    .await
+}
+
+/// Adds DP noise to an aggregate of `B` values, calibrated to the given per-user `sensitivity`.
+///
+/// # Errors
+/// will propogate errors from `apply_dp_noise`
+/// Will return an error epsilon is not in the range (0,`MAX_EPSILON`).
+/// With binomial noise, will also return an error if delta is not in the range (0, 1).
 /// # Panics
 /// may panic from asserts down in  `gen_binomial_noise`
 ///
 #[allow(clippy::too_many_lines)]
-pub async fn dp_for_histogram<C, const B: usize, OV, const SS_BITS: usize>(
+#[tracing::instrument(name = "dp_for_aggregation", skip_all)]
+pub async fn dp_for_aggregation<C, const B: usize, OV>(
     ctx: C,
     histogram_bin_values: BitDecomposed<Replicated<Boolean, B>>,
     dp_params: DpMechanism,
+    sensitivity: Sensitivity,
 ) -> Result<Vec<Replicated<OV>>, Error>
 where
     C: UpgradableContext,
@@ -248,21 +344,25 @@ where
     };
     match dp_params {
         DpMechanism::NoDp => Ok(Vec::transposed_from(&histogram_bin_values)?),
-        DpMechanism::Binomial { epsilon } => {
+        DpMechanism::Binomial { epsilon, delta } => {
             if epsilon <= 0.0 || epsilon > MAX_EPSILON {
                 return Err(EpsilonOutOfBounds);
             }
+            if delta <= 0.0 || delta >= MAX_PROBABILITY {
+                return Err(DeltaOutOfBounds);
+            }
 
-            let per_user_credit_cap = 2_u32.pow(u32::try_from(SS_BITS).unwrap());
+            let per_user_credit_cap = sensitivity.ell_1();
 
             let dimensions = f64::from(u32::try_from(B).unwrap());
 
             let noise_params = NoiseParams {
                 epsilon,
+                delta,
                 per_user_credit_cap,
-                ell_1_sensitivity: f64::from(per_user_credit_cap),
-                ell_2_sensitivity: f64::from(per_user_credit_cap),
-                ell_infty_sensitivity: f64::from(per_user_credit_cap),
+                ell_1_sensitivity: f64::from(sensitivity.ell_1()),
+                ell_2_sensitivity: sensitivity.ell_2(),
+                ell_infty_sensitivity: f64::from(sensitivity.ell_infty()),
                 dimensions,
                 ..Default::default()
             };
@@ -272,7 +372,7 @@ where
             let epsilon = noise_params.epsilon;
             let delta = noise_params.delta;
             tracing::info!(
-                "In dp_for_histogram with Binomial noise: \
+                "In dp_for_aggregation with Binomial noise: \
                 epsilon = {epsilon}, \
                 delta = {delta}, \
                 num_breakdowns (dimension) = {dimensions}, \
@@ -308,9 +408,10 @@ where
             Ok(noisy_histogram)
         }
         DpMechanism::DiscreteLaplace { epsilon } => {
+            // The Laplace mechanism is calibrated to the ℓ1 sensitivity of the aggregate.
             let noise_params = NoiseParams {
                 epsilon,
-                per_user_credit_cap: 2_u32.pow(u32::try_from(SS_BITS).unwrap()),
+                per_user_credit_cap: sensitivity.ell_1(),
                 ..Default::default()
             };
 
@@ -323,7 +424,7 @@ where
             assert!((epsilon - noise_params.epsilon).abs() < 0.001);
             let (mean, _) = truncated_discret_laplace.mean_and_std();
             tracing::info!(
-                "In dp_for_histogram with Truncated Discrete Laplace noise: \
+                "In dp_for_aggregation with Truncated Discrete Laplace noise: \
                 epsilon = {epsilon}, \
                 delta = {}, \
                 per_user_credit_cap = {}, \
@@ -368,6 +469,152 @@ where
     }
 }
 
+/// Returns the threshold that a bucket of a histogram noised with the discrete Laplace mechanism
+/// must exceed to be reported in sparse histogram output.
+///
+/// The threshold is chosen so that a bucket with a true value of at most `per_user_credit_cap`,
+/// i.e. a bucket that a single user may have contributed to, exceeds it with probability at most
+/// `delta` once the noise of all three helpers has been added. Every bucket is noised before it
+/// is compared with the threshold, so suppressing buckets does not spend any additional epsilon.
+///
+/// # Errors
+/// If `delta` is not in the range (0, 1), or if the noise distribution cannot be constructed from
+/// `epsilon` and `per_user_credit_cap`.
+pub fn sparse_histogram_threshold(
+    epsilon: f64,
+    delta: f64,
+    per_user_credit_cap: u32,
+) -> Result<u32, Error> {
+    if delta <= 0.0 || delta >= MAX_PROBABILITY {
+        return Err(DeltaOutOfBounds);
+    }
+    // Must match the noise added by `dp_for_aggregation`.
+    let noise_params = NoiseParams {
+        epsilon,
+        per_user_credit_cap,
+        ..Default::default()
+    };
+    let shift = OPRFPaddingDp::new(
+        noise_params.epsilon,
+        noise_params.delta,
+        noise_params.per_user_credit_cap,
+    )?
+    .get_shift();
+
+    // A noise sample takes the value `k` in `[-shift, shift]` with probability `a * r^|k|`, so it
+    // is at least `t` with probability at most `a * r^t / (1 - r)`. If none of the three samples
+    // reaches `t`, their sum is at most `3 * (t - 1)`. By the union bound, it is enough to pick
+    // the smallest `t` for which a single sample reaches it with probability at most `delta / 3`.
+    let r = (-epsilon).exp();
+    let a = (1.0 - r) / (1.0 + r - 2.0 * r.powf(f64::from(shift) + 1.0));
+    let t = ((3.0 * a / ((1.0 - r) * delta)).ln() / epsilon).ceil();
+    // Noise samples never exceed `shift`.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let t = if t > f64::from(shift) {
+        shift + 1
+    } else {
+        t.max(1.0) as u32
+    };
+
+    Ok(per_user_credit_cap + 3 * (t - 1))
+}
+
+/// Suppresses the buckets of a noisy histogram whose value does not exceed `threshold`. Returns
+/// the remaining buckets, along with their index in the histogram.
+///
+/// Noise may wrap the value of a small bucket around zero, so noisy values are compared as signed
+/// integers. Only the outcome of the comparison is revealed; the values of the returned buckets
+/// stay secret-shared.
+///
+/// # Errors
+/// Propagates errors from the comparison, from revealing the outcome of the comparison, and from
+/// validation.
+#[tracing::instrument(name = "dp_threshold_histogram", skip_all, fields(buckets = noisy_histogram.len()))]
+pub async fn dp_threshold_histogram<C, OV>(
+    ctx: C,
+    noisy_histogram: Vec<Replicated<OV>>,
+    threshold: u32,
+) -> Result<Vec<(usize, Replicated<OV>)>, Error>
+where
+    C: UpgradableContext,
+    OV: BooleanArray + U128Conversions,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>
+        + Reveal<DZKPUpgraded<C>, Output = <Boolean as Vectorizable<1>>::Array>,
+{
+    // Flipping the sign bit maps a signed value `x` to the unsigned value `x + 2^(OV::BITS - 1)`,
+    // which preserves the order of values.
+    let sign_bit = 1_u128 << (OV::BITS - 1);
+    if noisy_histogram.is_empty() || u128::from(threshold) >= sign_bit - 1 {
+        return Ok(Vec::new());
+    }
+    let offset_threshold = u128::from(threshold) + sign_bit;
+    let total_records = TotalRecords::specified(noisy_histogram.len())?;
+
+    let mut compare_validator = ctx.clone().dzkp_validator(
+        MaliciousProtocolSteps {
+            protocol: &IpaPrfStep::DpThreshold,
+            validate: &IpaPrfStep::DpThresholdValidate,
+        },
+        non_zero_prev_power_of_two(TARGET_PROOF_SIZE / OV::BITS as usize),
+    );
+    compare_validator.set_total_records(total_records);
+    let compare_ctx = compare_validator.context().set_total_records(total_records);
+    let threshold_bits = BitDecomposed::new((0..OV::BITS).map(|i| {
+        Replicated::share_known_value(&compare_ctx, Boolean::from(offset_threshold >> i & 1 == 1))
+    }));
+
+    let compare_work = stream::iter(&noisy_histogram)
+        .enumerate()
+        .map(|(i, value)| {
+            let compare_ctx = compare_ctx.clone();
+            let threshold_bits = &threshold_bits;
+            async move {
+                let mut bits = value.to_bits();
+                let sign = bits.len() - 1;
+                bits[sign] = !bits[sign].clone();
+                compare_gt::<_, ThirtyTwoBitStep, 1>(
+                    compare_ctx,
+                    RecordId::from(i),
+                    &bits,
+                    threshold_bits,
+                )
+                .await
+            }
+        });
+    let above_threshold: Vec<_> = validated_seq_join(compare_validator, compare_work)
+        .try_collect()
+        .await?;
+
+    // Revealing the outcome doesn't do any multiplies, so won't make it as far as doing a proof,
+    // but we need the validator to obtain an upgraded malicious context.
+    let reveal_validator = ctx.dzkp_validator(
+        MaliciousProtocolSteps {
+            protocol: &IpaPrfStep::RevealDpThreshold,
+            validate: &IpaPrfStep::RevealDpThresholdValidate,
+        },
+        usize::MAX,
+    );
+    let reveal_ctx = reveal_validator.context().set_total_records(total_records);
+    let reveal_work = stream::iter(above_threshold)
+        .enumerate()
+        .map(|(i, above_threshold)| {
+            let reveal_ctx = reveal_ctx.clone();
+            async move {
+                let revealed = reveal(reveal_ctx, RecordId::from(i), &above_threshold).await?;
+                Ok::<_, Error>(bool::from(Boolean::from_array(&revealed)))
+            }
+        });
+    let above_threshold: Vec<bool> = seq_join(reveal_ctx.active_work(), reveal_work)
+        .try_collect()
+        .await?;
+    reveal_validator.validate().await?;
+
+    Ok(zip(above_threshold, noisy_histogram)
+        .enumerate()
+        .filter_map(|(i, (above_threshold, value))| above_threshold.then_some((i, value)))
+        .collect())
+}
+
 struct ShiftedTruncatedDiscreteLaplace {
     truncated_discrete_laplace: OPRFPaddingDp,
     shift: u32,
@@ -598,6 +845,7 @@ pub fn binomial_noise_mean_std(noise_params: &NoiseParams) -> (f64, f64) {
 mod test {
 
     use crate::{
+        error::Error,
         ff::{
             boolean::Boolean,
             boolean_array::{
@@ -608,9 +856,9 @@ mod test {
         helpers::{query::DpMechanism, Direction},
         protocol::{
             dp::{
-                apply_dp_noise, delta_constraint, dp_for_histogram, epsilon_constraint, error,
-                find_smallest_num_bernoulli, gen_binomial_noise, NoiseParams,
-                ShiftedTruncatedDiscreteLaplace,
+                apply_dp_noise, delta_constraint, dp_for_histogram, dp_threshold_histogram,
+                epsilon_constraint, error, find_smallest_num_bernoulli, gen_binomial_noise,
+                sparse_histogram_threshold, NoiseParams, ShiftedTruncatedDiscreteLaplace,
             },
             ipa_prf::oprf_padding::insecure::OPRFPaddingDp,
         },
@@ -707,16 +955,17 @@ mod test {
     }
 
     /// Test for discrete truncated laplace
-    // pub async fn dp_for_histogram<C, const B: usize, OV, const SS_BITS: usize>(
+    // pub async fn dp_for_histogram<C, const B: usize, OV>(
     //     ctx: C,
     //     histogram_bin_values: BitDecomposed<Replicated<Boolean, B>>,
+    //     per_user_credit_cap: u32,
     //     dp_params: DpMechanism,
     // ) -> Result<Vec<Replicated<OV>>, Error>
     #[tokio::test]
     pub async fn test_laplace_noise() {
         type OV = BA8;
         const NUM_BREAKDOWNS: u32 = 16;
-        const SS_BITS: usize = 3;
+        const PER_USER_CREDIT_CAP: u32 = 8;
         let epsilon = 2.0;
         let dp_params = DpMechanism::DiscreteLaplace { epsilon };
         let world = TestWorld::default();
@@ -726,8 +975,11 @@ mod test {
             vectorize_input(OV::BITS as usize, &input_values); // bit_width passed here needs to match OV::BITS
         let result = world
             .semi_honest(input, |ctx, input| async move {
-                dp_for_histogram::<_, { NUM_BREAKDOWNS as usize }, OV, SS_BITS>(
-                    ctx, input, dp_params,
+                dp_for_histogram::<_, { NUM_BREAKDOWNS as usize }, OV>(
+                    ctx,
+                    input,
+                    PER_USER_CREDIT_CAP,
+                    dp_params,
                 )
                 .await
                 .unwrap()
@@ -738,8 +990,7 @@ mod test {
             .iter()
             .map(|&v| u32::try_from(v.as_u128()).unwrap())
             .collect::<Vec<_>>();
-        let per_user_credit_cap = 2_u32.pow(u32::try_from(SS_BITS).unwrap());
-        let truncated_discrete_laplace = OPRFPaddingDp::new(epsilon, 1e-6, per_user_credit_cap);
+        let truncated_discrete_laplace = OPRFPaddingDp::new(epsilon, 1e-6, PER_USER_CREDIT_CAP);
         let (_, std) = truncated_discrete_laplace.unwrap().mean_and_std();
         let three_std = 3.0 * std;
         assert_eq!(NUM_BREAKDOWNS as usize, result_u32.len());
@@ -766,6 +1017,137 @@ mod test {
         }
     }
 
+    fn reconstruct_sparse<OV: BooleanArray>(
+        result: [Vec<(usize, Replicated<OV>)>; 3],
+    ) -> Vec<(usize, OV)> {
+        let [h1, h2, h3] =
+            result.map(|buckets| buckets.into_iter().unzip::<_, _, Vec<_>, Vec<_>>());
+        assert_eq!(h1.0, h2.0);
+        assert_eq!(h1.0, h3.0);
+        let values: Vec<OV> = [h1.1, h2.1, h3.1].reconstruct();
+        h1.0.into_iter().zip(values).collect()
+    }
+
+    #[test]
+    fn sparse_histogram_threshold_bounds() {
+        const PER_USER_CREDIT_CAP: u32 = 8;
+        let epsilon = 2.0;
+        let shift = OPRFPaddingDp::new(epsilon, 1e-6, PER_USER_CREDIT_CAP)
+            .unwrap()
+            .get_shift();
+
+        let mut previous = u32::MAX;
+        for delta in [1e-12, 1e-6, 1e-3, 0.5] {
+            let threshold =
+                sparse_histogram_threshold(epsilon, delta, PER_USER_CREDIT_CAP).unwrap();
+            assert!(threshold >= PER_USER_CREDIT_CAP);
+            assert!(threshold <= PER_USER_CREDIT_CAP + 3 * shift);
+            assert!(threshold <= previous);
+            previous = threshold;
+        }
+
+        for delta in [0.0, 1.0] {
+            assert!(matches!(
+                sparse_histogram_threshold(epsilon, delta, PER_USER_CREDIT_CAP),
+                Err(Error::DeltaOutOfBounds)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn threshold_histogram() {
+        const THRESHOLD: u32 = 10;
+        let world = TestWorld::default();
+        // 0xFFFF and 0x8000 are negative once noise wraps them around zero.
+        let input = [0_u128, 9, 10, 11, 500, 0xFFFF, 0x8000, 0x7FFF].map(BA16::truncate_from);
+
+        let result = world
+            .malicious(input.into_iter(), |ctx, input| async move {
+                dp_threshold_histogram(ctx, input, THRESHOLD).await.unwrap()
+            })
+            .await;
+
+        assert_eq!(
+            reconstruct_sparse(result),
+            [(3, 11_u128), (4, 500), (7, 0x7FFF)].map(|(i, v)| (i, BA16::truncate_from(v))),
+        );
+
+        let result = world
+            .malicious(input.into_iter(), |ctx, input| async move {
+                dp_threshold_histogram(ctx, input, 0x7FFF).await.unwrap()
+            })
+            .await;
+        assert!(reconstruct_sparse(result).is_empty());
+    }
+
+    #[tokio::test]
+    async fn sparse_laplace_histogram() {
+        type OV = BA8;
+        const NUM_BREAKDOWNS: usize = 16;
+        const PER_USER_CREDIT_CAP: u32 = 8;
+        let epsilon = 2.0;
+        let threshold = sparse_histogram_threshold(epsilon, 1e-6, PER_USER_CREDIT_CAP).unwrap();
+        let world = TestWorld::default();
+        let input_values = [0, 0, 80, 0, 8, 0, 0, 0, 80, 0, 0, 0, 0, 0, 0, 80];
+
+        let input: BitDecomposed<[Boolean; NUM_BREAKDOWNS]> =
+            vectorize_input(OV::BITS as usize, &input_values);
+        let result = world
+            .semi_honest(input, |ctx, input| async move {
+                let noisy_histogram = dp_for_histogram::<_, NUM_BREAKDOWNS, OV>(
+                    ctx.clone(),
+                    input,
+                    PER_USER_CREDIT_CAP,
+                    DpMechanism::DiscreteLaplace { epsilon },
+                )
+                .await
+                .unwrap();
+                dp_threshold_histogram(ctx, noisy_histogram, threshold)
+                    .await
+                    .unwrap()
+            })
+            .await;
+
+        // Buckets that a single user may have filled are suppressed with probability at least
+        // 1 - 1e-6. The noise of the three helpers adds up to at most 42, which is too small to
+        // suppress the large buckets, or to wrap them past the largest signed value.
+        let buckets = reconstruct_sparse(result)
+            .into_iter()
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        assert_eq!(buckets, [2, 8, 15]);
+    }
+
+    #[tokio::test]
+    async fn binomial_noise_rejects_bad_delta() {
+        type OV = BA8;
+        const NUM_BREAKDOWNS: usize = 16;
+        const PER_USER_CREDIT_CAP: u32 = 8;
+        for delta in [0.0, 1.0] {
+            let dp_params = DpMechanism::Binomial {
+                epsilon: 1.0,
+                delta,
+            };
+            let world = TestWorld::default();
+            let input: BitDecomposed<[Boolean; NUM_BREAKDOWNS]> =
+                vectorize_input(OV::BITS as usize, &[0; NUM_BREAKDOWNS]);
+            let result = world
+                .semi_honest(input, |ctx, input| async move {
+                    dp_for_histogram::<_, NUM_BREAKDOWNS, OV>(
+                        ctx,
+                        input,
+                        PER_USER_CREDIT_CAP,
+                        dp_params,
+                    )
+                    .await
+                })
+                .await;
+            assert!(result
+                .iter()
+                .all(|r| matches!(r, Err(Error::DeltaOutOfBounds))));
+        }
+    }
+
     #[test]
     fn test_epsilon_simple_aggregation_case() {
         let noise_params = NoiseParams {
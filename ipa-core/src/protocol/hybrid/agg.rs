@@ -1,37 +1,58 @@
-use std::collections::BTreeMap;
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    iter::{self, repeat_n, zip},
+    num::NonZeroU32,
+};
 
 use futures::{stream, StreamExt, TryStreamExt};
+use ipa_step::StepNarrow;
 
 use crate::{
+    const_assert,
     error::Error,
-    ff::{boolean::Boolean, boolean_array::BooleanArray, ArrayAccess},
-    helpers::TotalRecords,
+    ff::{boolean::Boolean, boolean_array::BooleanArray, ArrayAccess, U128Conversions},
+    helpers::{query::BreakdownDimensions, TotalRecords},
     protocol::{
-        boolean::step::EightBitStep,
+        basics::{select, BooleanArrayMul, SecureMul, ShareKnownValue},
+        boolean::{
+            or::or,
+            step::{EightBitStep, SixteenBitStep, ThirtyTwoBitStep},
+            NBitStep,
+        },
         context::{
             dzkp_validator::{validated_seq_join, DZKPValidator, TARGET_PROOF_SIZE},
             Context, DZKPUpgraded, MaliciousProtocolSteps, ShardedContext, UpgradableContext,
         },
-        hybrid::step::{AggregateReportsStep, HybridStep},
-        ipa_prf::boolean_ops::addition_sequential::integer_add,
-        BooleanProtocols,
+        hybrid::step::{AggregateReportsStep, HybridStep, MergeReportStep},
+        ipa_prf::boolean_ops::{
+            addition_sequential::{integer_add, integer_sat_add},
+            comparison_and_subtraction_sequential::{compare_gt, integer_sub_with_carry},
+        },
+        BooleanProtocols, Gate, RecordId,
+    },
+    report::hybrid::{AggregateableHybridReport, HybridTimestamp, PrfHybridReport},
+    secret_sharing::{
+        replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, SharedValue,
     },
-    report::hybrid::{AggregateableHybridReport, PrfHybridReport},
-    secret_sharing::replicated::semi_honest::AdditiveShare as Replicated,
     utils::non_zero_prev_power_of_two,
 };
 
+// Timestamps are compared bit by bit using `ThirtyTwoBitStep`.
+const_assert!(ThirtyTwoBitStep::BITS >= HybridTimestamp::BITS);
+
+/// The largest number of reports that can share a `match_key` and still be aggregated.
+/// Reports from a `match_key` that appears more often than this are dropped, which bounds
+/// the depth of the per-`match_key` circuit.
+pub(crate) const MAX_REPORTS_PER_MATCH_KEY: usize = 8;
+
 enum MatchEntry<BK, V>
 where
     BK: BooleanArray,
     V: BooleanArray,
 {
-    Single(AggregateableHybridReport<BK, V>),
-    Pair(
-        AggregateableHybridReport<BK, V>,
-        AggregateableHybridReport<BK, V>,
-    ),
-    MoreThanTwo,
+    Reports(Vec<PrfHybridReport<BK, V>>),
+    TooMany,
 }
 
 impl<BK, V> MatchEntry<BK, V>
@@ -39,43 +60,47 @@ where
     BK: BooleanArray,
     V: BooleanArray,
 {
-    pub fn add_report(&mut self, new_report: AggregateableHybridReport<BK, V>) {
+    pub fn add_report(&mut self, new_report: PrfHybridReport<BK, V>) {
         match self {
-            Self::Single(old_report) => {
-                *self = Self::Pair(old_report.clone(), new_report);
+            Self::Reports(reports) if reports.len() < MAX_REPORTS_PER_MATCH_KEY => {
+                reports.push(new_report);
             }
-            Self::Pair { .. } | Self::MoreThanTwo => *self = Self::MoreThanTwo,
+            Self::Reports(_) | Self::TooMany => *self = Self::TooMany,
         }
     }
 
-    pub fn into_pair(self) -> Option<[AggregateableHybridReport<BK, V>; 2]> {
+    pub fn into_group(self) -> Option<Vec<PrfHybridReport<BK, V>>> {
         match self {
-            Self::Pair(r1, r2) => Some([r1, r2]),
+            Self::Reports(reports) if reports.len() > 1 => Some(reports),
             _ => None,
         }
     }
 }
 
 /// This function takes in a vector of `PrfHybridReports`, groups them by the oprf of the `match_key`,
-/// and collects all pairs of reports with the same `match_key` into a vector of paris (as an array.)
+/// and collects the reports of every `match_key` that appears at least twice into a group.
 ///
-/// *Note*: Any `match_key` which appears once or more than twice is removed.
-/// An honest report collector will only provide a single impression report per `match_key` and
-/// an honest client will only provide a single conversion report per `match_key`.
-/// Also note that a malicious client (intenional or bug) could provide exactly two conversions.
+/// *Note*: Any `match_key` which appears once or more than [`MAX_REPORTS_PER_MATCH_KEY`] times
+/// is removed. An honest report collector will usually provide a single impression report per
+/// `match_key`, which may be followed by several conversion reports from the same user.
+/// Also note that a malicious client (intenional or bug) could provide only conversions.
 /// This would put the sum of conversion values into `breakdown_key` 0. As this is undetectable,
 /// this makes `breakdown_key = 0` *unreliable*.
 ///
+/// The groups are returned largest first, which lets [`aggregate_reports`] use dense record ids
+/// for every report position.
+///
 /// Note: Possible Perf opportunity by removing the `collect()`.
 /// See [#1443](https://github.com/private-attribution/ipa/issues/1443).
 ///
-/// *Note*: In order to add the pairs, the vector of pairs must be in the same order across all
+/// *Note*: In order to add the reports, the vector of groups must be in the same order across all
 /// three helpers. A standard `HashMap` uses system randomness for insertion placement, so we
-/// use a `BTreeMap` to maintain consistent ordering across the helpers.
+/// use a `BTreeMap` to maintain consistent ordering across the helpers, and a stable sort to
+/// order the groups by size.
 ///
-fn group_report_pairs_ordered<BK, V>(
+fn group_reports_ordered<BK, V>(
     reports: Vec<PrfHybridReport<BK, V>>,
-) -> Vec<[AggregateableHybridReport<BK, V>; 2]>
+) -> Vec<Vec<PrfHybridReport<BK, V>>>
 where
     BK: BooleanArray,
     V: BooleanArray,
@@ -85,40 +110,110 @@ where
     for report in reports {
         reports_by_matchkey
             .entry(report.match_key)
-            .and_modify(|e| e.add_report(report.clone().into()))
-            .or_insert(MatchEntry::Single(report.into()));
+            .and_modify(|e| e.add_report(report.clone()))
+            .or_insert(MatchEntry::Reports(vec![report]));
     }
 
-    // we only keep the reports from match_keys that provided exactly 2 reports
-    reports_by_matchkey
+    // we only keep the reports from match_keys that provided between 2 and
+    // `MAX_REPORTS_PER_MATCH_KEY` reports
+    let mut groups = reports_by_matchkey
         .into_values()
-        .filter_map(MatchEntry::into_pair)
-        .collect::<Vec<_>>()
+        .filter_map(MatchEntry::into_group)
+        .collect::<Vec<_>>();
+    groups.sort_by_key(|group| Reverse(group.len()));
+    groups
+}
+
+/// Upper bound on the number of multiplications needed to merge the reports of a single
+/// `match_key` when the largest group has `max_reports` reports.
+fn multiplications_per_match_key<BK, V>(
+    max_reports: usize,
+    has_attribution_window: bool,
+    breakdown_dimensions: Option<BreakdownDimensions>,
+) -> usize
+where
+    BK: BooleanArray,
+    V: BooleanArray,
+{
+    let bk_bits = BK::BITS as usize;
+    let v_bits = V::BITS as usize;
+    let ts_bits = HybridTimestamp::BITS as usize;
+    // every report is checked for being an impression, its timestamp is compared to the latest
+    // conversion twice and to the selected impression once, and the conversion timestamp, the
+    // impression timestamp and the breakdown key are selected. Every report but the first one
+    // saturates the sum of values. The cap is compared and applied once per match key.
+    let mut multiplications = max_reports * (2 * bk_bits + 2 + 5 * ts_bits)
+        + max_reports.saturating_sub(1) * 2 * v_bits
+        + 2 * v_bits;
+    if has_attribution_window {
+        // every report is checked against the attribution window. Reports without an impression
+        // are dropped once per match key.
+        multiplications += max_reports * (2 * ts_bits + 1 + v_bits) + bk_bits - 1 + v_bits;
+    }
+    if let Some(dimensions) = breakdown_dimensions {
+        // both keys are compared to the size of their dimension, the second key is added once
+        // for every bit set in the size of the first dimension, and values of keys outside the
+        // dimensions are dropped.
+        let additions = dimensions.first().count_ones() as usize;
+        multiplications += bk_bits + additions * bk_bits + 1 + v_bits;
+    }
+    multiplications
 }
 
 /// This protocol is used to aggregate `PRFHybridReports` and returns `AggregateableHybridReports`.
 /// It groups all the reports by the PRF of the `match_key`, finds all reports from `match_keys`
-/// with that provided exactly 2 reports, then adds those 2 reports.
+/// that provided between 2 and [`MAX_REPORTS_PER_MATCH_KEY`] reports, then merges the reports of
+/// every `match_key` into a single report:
+/// * The breakdown key is taken from the latest impression (a report with a non-zero breakdown
+///   key) that does not follow the latest conversion, so when a user has several impressions,
+///   all of their conversions are credited to one of them (last touch). Impressions are ordered
+///   by their timestamps, which are compared without revealing them. If no impression precedes
+///   the latest conversion, the breakdown key is 0.
+/// * When an `attribution_window` is set, only the conversions that happened within that many
+///   seconds after the selected impression keep their value, and `match_key`s without an
+///   impression contribute nothing. Conversions that precede the selected impression are
+///   dropped as well, however far apart the timestamps (see [`HybridTimestamp`]) are.
+/// * The values are summed with saturating addition, and the sum is capped at
+///   `per_user_credit_cap`. This bounds the contribution of every user to the histogram, which
+///   is what the DP noise is calibrated against.
+/// * When `breakdown_dimensions` are set, the breakdown key carries the keys of two dimensions,
+///   which are combined into a single bucket as described in [`BreakdownDimensions`]. The value
+///   of a report whose keys fall outside of the dimensions is dropped.
+///
 /// TODO (Performance opportunity): These additions are not currently vectorized.
 /// We are currently deferring that work until the protocol is complete.
+#[tracing::instrument(name = "aggregate_reports", skip_all)]
 pub async fn aggregate_reports<BK, V, C>(
     ctx: C,
     reports: Vec<PrfHybridReport<BK, V>>,
+    per_user_credit_cap: u32,
+    attribution_window: Option<NonZeroU32>,
+    breakdown_dimensions: Option<BreakdownDimensions>,
 ) -> Result<Vec<AggregateableHybridReport<BK, V>>, Error>
 where
     C: UpgradableContext + ShardedContext,
     BK: BooleanArray,
-    V: BooleanArray,
+    V: BooleanArray + U128Conversions,
     Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<BK>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<V>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<HybridTimestamp>: BooleanArrayMul<DZKPUpgraded<C>>,
 {
-    let report_pairs = group_report_pairs_ordered(reports);
+    let report_groups = group_reports_ordered(reports);
+    let max_reports = report_groups.first().map_or(0, Vec::len);
 
-    let chunk_size =
-        non_zero_prev_power_of_two(TARGET_PROOF_SIZE / (BK::BITS as usize + V::BITS as usize));
+    let chunk_size = non_zero_prev_power_of_two(
+        TARGET_PROOF_SIZE
+            / multiplications_per_match_key::<BK, V>(
+                max_reports,
+                attribution_window.is_some(),
+                breakdown_dimensions,
+            ),
+    );
 
-    let ctx = ctx.set_total_records(TotalRecords::specified(report_pairs.len())?);
+    let total_records = TotalRecords::specified(report_groups.len())?;
 
-    let dzkp_validator = ctx.dzkp_validator(
+    let mut dzkp_validator = ctx.dzkp_validator(
         MaliciousProtocolSteps {
             protocol: &HybridStep::GroupBySum,
             validate: &HybridStep::GroupBySumValidate,
@@ -126,33 +221,38 @@ where
         chunk_size,
     );
 
-    let agg_ctx = dzkp_validator.context();
+    dzkp_validator.set_total_records(total_records);
+    let validator_ctx = dzkp_validator.context();
+    let agg_ctx = validator_ctx.set_total_records(total_records);
 
-    let agg_work = stream::iter(report_pairs)
+    // Groups are sorted by size, so the groups that have a report at a given position
+    // are always a prefix of `report_groups`.
+    let ctx_for_report_number = (0..max_reports)
+        .map(|report_number| {
+            let groups_with_report =
+                report_groups.partition_point(|group| group.len() > report_number);
+            Ok::<_, Error>(
+                validator_ctx
+                    .narrow(&AggregateReportsStep::Report(report_number))
+                    .set_total_records(TotalRecords::specified(groups_with_report)?),
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let agg_work = stream::iter(report_groups)
         .enumerate()
         .map(|(idx, reports)| {
             let agg_ctx = agg_ctx.clone();
-            async move {
-                let (breakdown_key, _) = integer_add::<_, EightBitStep, 1>(
-                    agg_ctx.narrow(&AggregateReportsStep::AddBK),
-                    idx.into(),
-                    &reports[0].breakdown_key.to_bits(),
-                    &reports[1].breakdown_key.to_bits(),
-                )
-                .await?;
-                let (value, _) = integer_add::<_, EightBitStep, 1>(
-                    agg_ctx.narrow(&AggregateReportsStep::AddV),
-                    idx.into(),
-                    &reports[0].value.to_bits(),
-                    &reports[1].value.to_bits(),
-                )
-                .await?;
-                Ok::<_, Error>(AggregateableHybridReport::<BK, V> {
-                    match_key: (),
-                    breakdown_key: breakdown_key.collect_bits(),
-                    value: value.collect_bits(),
-                })
-            }
+            let contexts = ctx_for_report_number[..reports.len()].to_owned();
+            merge_match_key_reports(
+                agg_ctx,
+                contexts,
+                RecordId::from(idx),
+                reports,
+                per_user_credit_cap,
+                attribution_window,
+                breakdown_dimensions,
+            )
         });
 
     validated_seq_join(dzkp_validator, agg_work)
@@ -160,18 +260,449 @@ where
         .await
 }
 
+/// Counts every user towards the reach of the breakdown key they were attributed to. Returns a
+/// report for every aggregated report, with the same breakdown key and a value of 1 if the user
+/// was attributed a non-zero value, or 0 otherwise.
+#[tracing::instrument(name = "count_reach", skip_all)]
+pub async fn count_reach<BK, V, C>(
+    ctx: C,
+    reports: &[AggregateableHybridReport<BK, V>],
+) -> Result<Vec<AggregateableHybridReport<BK, V>>, Error>
+where
+    C: UpgradableContext,
+    BK: BooleanArray,
+    V: BooleanArray,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+{
+    if reports.is_empty() {
+        return Ok(Vec::new());
+    }
+    let chunk_size = non_zero_prev_power_of_two(TARGET_PROOF_SIZE / V::BITS as usize);
+    let total_records = TotalRecords::specified(reports.len())?;
+
+    let mut dzkp_validator = ctx.dzkp_validator(
+        MaliciousProtocolSteps {
+            protocol: &HybridStep::CountReach,
+            validate: &HybridStep::CountReachValidate,
+        },
+        chunk_size,
+    );
+    dzkp_validator.set_total_records(total_records);
+    let reach_ctx = dzkp_validator.context().set_total_records(total_records);
+
+    let reach_work = stream::iter(reports).enumerate().map(|(idx, report)| {
+        let reach_ctx = reach_ctx.clone();
+        async move {
+            let reached = is_nonzero::<_, SixteenBitStep>(
+                reach_ctx,
+                RecordId::from(idx),
+                &report.value.to_bits(),
+            )
+            .await?;
+            Ok(AggregateableHybridReport {
+                match_key: (),
+                breakdown_key: report.breakdown_key.clone(),
+                value: iter::once(reached).collect(),
+                timestamp: (),
+                is_impression: (),
+            })
+        }
+    });
+
+    validated_seq_join(dzkp_validator, reach_work)
+        .try_collect()
+        .await
+}
+
+/// Merges all the reports that share a `match_key` into a single report, as described in
+/// [`aggregate_reports`]. `ctx_for_report_number` holds a context for every report.
+#[allow(clippy::too_many_lines)]
+async fn merge_match_key_reports<C, BK, V>(
+    ctx: C,
+    ctx_for_report_number: Vec<C>,
+    record_id: RecordId,
+    reports: Vec<PrfHybridReport<BK, V>>,
+    per_user_credit_cap: u32,
+    attribution_window: Option<NonZeroU32>,
+    breakdown_dimensions: Option<BreakdownDimensions>,
+) -> Result<AggregateableHybridReport<BK, V>, Error>
+where
+    C: Context,
+    BK: BooleanArray,
+    V: BooleanArray + U128Conversions,
+    Replicated<Boolean>: BooleanProtocols<C>,
+    Replicated<BK>: BooleanArrayMul<C>,
+    Replicated<V>: BooleanArrayMul<C>,
+    Replicated<HybridTimestamp>: BooleanArrayMul<C>,
+{
+    if reports.is_empty() {
+        return Ok(AggregateableHybridReport::default());
+    }
+
+    // Find the time of the latest conversion.
+    let mut conversion_timestamp = Replicated::<HybridTimestamp>::ZERO;
+    for (ctx, report) in zip(&ctx_for_report_number, &reports) {
+        let is_later = compare_gt::<_, ThirtyTwoBitStep, 1>(
+            ctx.narrow(&MergeReportStep::CompareConversionTimestamps),
+            record_id,
+            &report.timestamp.to_bits(),
+            &conversion_timestamp.to_bits(),
+        )
+        .await?;
+        let is_latest_conversion = is_later
+            .multiply(
+                &!report.is_impression.clone(),
+                ctx.narrow(&MergeReportStep::IsLatestConversion),
+                record_id,
+            )
+            .await?;
+        conversion_timestamp = select(
+            ctx.narrow(&MergeReportStep::SelectConversionTimestamp),
+            record_id,
+            &is_latest_conversion,
+            &report.timestamp,
+            &conversion_timestamp,
+        )
+        .await?;
+    }
+
+    // Select the latest impression that does not follow that conversion. When several
+    // impressions share a timestamp, the one that comes last is selected.
+    let mut breakdown_key = Replicated::<BK>::ZERO;
+    let mut impression_timestamp = Replicated::<HybridTimestamp>::ZERO;
+    let mut has_impression = Replicated::<Boolean>::ZERO;
+    for (ctx, report) in zip(&ctx_for_report_number, &reports) {
+        let follows_conversion = compare_gt::<_, ThirtyTwoBitStep, 1>(
+            ctx.narrow(&MergeReportStep::CompareToConversionTimestamp),
+            record_id,
+            &report.timestamp.to_bits(),
+            &conversion_timestamp.to_bits(),
+        )
+        .await?;
+        let precedes_selected = compare_gt::<_, ThirtyTwoBitStep, 1>(
+            ctx.narrow(&MergeReportStep::CompareToImpressionTimestamp),
+            record_id,
+            &impression_timestamp.to_bits(),
+            &report.timestamp.to_bits(),
+        )
+        .await?;
+        let precedes_conversion = report
+            .is_impression
+            .multiply(
+                &!follows_conversion,
+                ctx.narrow(&MergeReportStep::PrecedesConversion),
+                record_id,
+            )
+            .await?;
+        let is_latest_impression = precedes_conversion
+            .multiply(
+                &!precedes_selected,
+                ctx.narrow(&MergeReportStep::IsLatestImpression),
+                record_id,
+            )
+            .await?;
+        breakdown_key = select(
+            ctx.narrow(&MergeReportStep::SelectBK),
+            record_id,
+            &is_latest_impression,
+            &report.breakdown_key,
+            &breakdown_key,
+        )
+        .await?;
+        impression_timestamp = select(
+            ctx.narrow(&MergeReportStep::SelectTimestamp),
+            record_id,
+            &is_latest_impression,
+            &report.timestamp,
+            &impression_timestamp,
+        )
+        .await?;
+        has_impression = or(
+            ctx.narrow(&MergeReportStep::HasImpression),
+            record_id,
+            &has_impression,
+            &precedes_conversion,
+        )
+        .await?;
+    }
+
+    let mut value = None;
+    for (ctx, report) in zip(&ctx_for_report_number, &reports) {
+        let report_value = match attribution_window {
+            Some(window) => {
+                filter_by_attribution_window(ctx, record_id, report, &impression_timestamp, window)
+                    .await?
+            }
+            None => report.value.clone(),
+        };
+        value = Some(match value {
+            None => report_value.to_bits(),
+            Some(value) => {
+                integer_sat_add::<_, ThirtyTwoBitStep, 1>(
+                    ctx.narrow(&MergeReportStep::AddV),
+                    record_id,
+                    &value,
+                    &report_value.to_bits(),
+                )
+                .await?
+            }
+        });
+    }
+
+    let mut value: Replicated<V> = value.map_or(Replicated::ZERO, BitDecomposed::collect_bits);
+    if attribution_window.is_some() {
+        // conversions are only attributed to impressions
+        value = select(
+            ctx.narrow(&AggregateReportsStep::DropUnattributed),
+            record_id,
+            &has_impression,
+            &value,
+            &Replicated::ZERO,
+        )
+        .await?;
+    }
+
+    // saturation already caps the sum at the largest value `V` can hold
+    if u128::from(per_user_credit_cap) < (1 << V::BITS) - 1 {
+        let cap = Replicated::share_known_value(&ctx, V::truncate_from(per_user_credit_cap));
+        let exceeds_cap = compare_gt::<_, SixteenBitStep, 1>(
+            ctx.narrow(&AggregateReportsStep::CompareCap),
+            record_id,
+            &value.to_bits(),
+            &cap.to_bits(),
+        )
+        .await?;
+        value = select(
+            ctx.narrow(&AggregateReportsStep::ApplyCap),
+            record_id,
+            &exceeds_cap,
+            &cap,
+            &value,
+        )
+        .await?;
+    }
+
+    if let Some(dimensions) = breakdown_dimensions {
+        (breakdown_key, value) =
+            combine_breakdown_dimensions(&ctx, record_id, &breakdown_key, value, dimensions)
+                .await?;
+    }
+
+    Ok(AggregateableHybridReport {
+        match_key: (),
+        breakdown_key,
+        value,
+        timestamp: (),
+        is_impression: (),
+    })
+}
+
+/// Returns the value of `report`, or a share of 0 if the report happened more than
+/// `attribution_window` seconds after `impression_timestamp`, or before it.
+///
+/// A report that precedes the impression is detected from the borrow of the subtraction, so the
+/// time delta never wraps around into the window.
+async fn filter_by_attribution_window<C, BK, V>(
+    ctx: &C,
+    record_id: RecordId,
+    report: &PrfHybridReport<BK, V>,
+    impression_timestamp: &Replicated<HybridTimestamp>,
+    attribution_window: NonZeroU32,
+) -> Result<Replicated<V>, Error>
+where
+    C: Context,
+    BK: BooleanArray,
+    V: BooleanArray,
+    Replicated<Boolean>: BooleanProtocols<C>,
+    Replicated<V>: BooleanArrayMul<C>,
+{
+    let (time_delta, follows_impression) = integer_sub_with_carry::<_, ThirtyTwoBitStep>(
+        ctx.narrow(&MergeReportStep::ComputeTimeDelta),
+        record_id,
+        &report.timestamp.to_bits(),
+        &impression_timestamp.to_bits(),
+    )
+    .await?;
+    let window = Replicated::share_known_value(
+        ctx,
+        HybridTimestamp::truncate_from(attribution_window.get()),
+    );
+    let exceeds_window = compare_gt::<_, ThirtyTwoBitStep, 1>(
+        ctx.narrow(&MergeReportStep::CompareTimeDeltaToAttributionWindow),
+        record_id,
+        &time_delta,
+        &window.to_bits(),
+    )
+    .await?;
+    let outside_window = or(
+        ctx.narrow(&MergeReportStep::OutsideAttributionWindow),
+        record_id,
+        &!follows_impression,
+        &exceeds_window,
+    )
+    .await?;
+    select(
+        ctx.narrow(&MergeReportStep::FilterValue),
+        record_id,
+        &outside_window,
+        &Replicated::ZERO,
+        &report.value,
+    )
+    .await
+}
+
+/// Combines the keys of the two dimensions carried by `breakdown_key` into a single bucket,
+/// `first_key + first * second_key`, as described in [`BreakdownDimensions`]. The bucket is
+/// computed modulo the size of `BK`.
+///
+/// Returns the bucket together with `value`, or a share of 0 if either key falls outside of its
+/// dimension. The bits above the second dimension are treated as part of the second key, so
+/// setting them puts the key outside of the dimensions.
+async fn combine_breakdown_dimensions<C, BK, V>(
+    ctx: &C,
+    record_id: RecordId,
+    breakdown_key: &Replicated<BK>,
+    value: Replicated<V>,
+    dimensions: BreakdownDimensions,
+) -> Result<(Replicated<BK>, Replicated<V>), Error>
+where
+    C: Context,
+    BK: BooleanArray,
+    V: BooleanArray,
+    Replicated<Boolean>: BooleanProtocols<C>,
+    Replicated<V>: BooleanArrayMul<C>,
+{
+    let bk_bits = BK::BITS as usize;
+    let (first_key, second_key) = breakdown_key.to_bits().split_at(dimensions.first_bits());
+
+    let outside_first = exceeds_known_value(
+        ctx.narrow(&AggregateReportsStep::CompareFirstDimension),
+        record_id,
+        &first_key,
+        dimensions.first() - 1,
+    )
+    .await?;
+    let outside_second = exceeds_known_value(
+        ctx.narrow(&AggregateReportsStep::CompareSecondDimension),
+        record_id,
+        &second_key,
+        dimensions.second() - 1,
+    )
+    .await?;
+    let outside_dimensions = match (outside_first, outside_second) {
+        (Some(first), Some(second)) => Some(
+            or(
+                ctx.narrow(&AggregateReportsStep::OutsideDimensions),
+                record_id,
+                &first,
+                &second,
+            )
+            .await?,
+        ),
+        (outside, None) | (None, outside) => outside,
+    };
+
+    // first * second_key is the sum of second_key shifted by every bit set in first
+    let mut bucket = first_key;
+    bucket.resize(bk_bits, Replicated::ZERO);
+    let shifts = (0..u32::BITS).filter(|shift| dimensions.first() & (1 << shift) != 0);
+    for (i, shift) in shifts.enumerate() {
+        let shift = shift as usize;
+        if shift >= bk_bits {
+            break;
+        }
+        let shifted_key = BitDecomposed::new(
+            repeat_n(Replicated::ZERO, shift)
+                .chain(second_key.iter().cloned())
+                .take(bk_bits),
+        );
+        (bucket, _) = integer_add::<_, EightBitStep, 1>(
+            ctx.narrow(&AggregateReportsStep::CombineDimensions(i)),
+            record_id,
+            &bucket,
+            &shifted_key,
+        )
+        .await?;
+    }
+
+    let value = match outside_dimensions {
+        Some(outside_dimensions) => {
+            select(
+                ctx.narrow(&AggregateReportsStep::DropOutsideDimensions),
+                record_id,
+                &outside_dimensions,
+                &Replicated::ZERO,
+                &value,
+            )
+            .await?
+        }
+        None => value,
+    };
+
+    Ok((bucket.collect_bits(), value))
+}
+
+/// Returns a share of 1 if `bits` encode a number larger than `known_value`, and a share of 0
+/// otherwise, or `None` if `bits` cannot encode a number that large.
+async fn exceeds_known_value<C>(
+    ctx: C,
+    record_id: RecordId,
+    bits: &BitDecomposed<Replicated<Boolean>>,
+    known_value: u32,
+) -> Result<Option<Replicated<Boolean>>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+{
+    if bits.len() < 32 && known_value >= (1 << bits.len()) - 1 {
+        return Ok(None);
+    }
+    let known_value =
+        BitDecomposed::new((0..bits.len()).map(|i| {
+            Replicated::share_known_value(&ctx, Boolean::from(known_value >> i & 1 == 1))
+        }));
+    compare_gt::<_, EightBitStep, 1>(ctx, record_id, bits, &known_value)
+        .await
+        .map(Some)
+}
+
+/// Returns a share of 1 if any of the `bits` is set, and a share of 0 otherwise.
+async fn is_nonzero<C, S>(
+    ctx: C,
+    record_id: RecordId,
+    bits: &BitDecomposed<Replicated<Boolean>>,
+) -> Result<Replicated<Boolean>, Error>
+where
+    C: Context,
+    S: NBitStep,
+    Gate: StepNarrow<S>,
+    Replicated<Boolean>: BooleanProtocols<C>,
+{
+    let mut bits = bits.iter().enumerate();
+    let Some((_, first_bit)) = bits.next() else {
+        return Ok(Replicated::ZERO);
+    };
+    let mut any = first_bit.clone();
+    for (i, bit) in bits {
+        any = or(ctx.narrow(&S::from(i)), record_id, &any, bit).await?;
+    }
+    Ok(any)
+}
+
 #[cfg(all(test, unit_test))]
 pub mod test {
+    use std::{iter::zip, num::NonZeroU32};
+
     use rand::Rng;
 
-    use super::{aggregate_reports, group_report_pairs_ordered};
+    use super::{aggregate_reports, group_reports_ordered, MAX_REPORTS_PER_MATCH_KEY};
     use crate::{
         ff::{
             boolean_array::{BA3, BA8},
             U128Conversions,
         },
-        helpers::Role,
-        protocol::hybrid::step::AggregateReportsStep,
+        helpers::{query::BreakdownDimensions, Role},
+        protocol::hybrid::step::MergeReportStep,
         report::hybrid::{
             AggregateableHybridReport, IndistinguishableHybridReport, PrfHybridReport,
         },
@@ -204,6 +735,7 @@ pub mod test {
                 match_key: SHARD1_MKS[0],
                 breakdown_key: 45,
                 key_id: 0,
+                timestamp: 100,
             },
             TestHybridRecord::TestConversion {
                 match_key: SHARD1_MKS[1],
@@ -236,12 +768,14 @@ pub mod test {
                 match_key: SHARD1_MKS[4],
                 breakdown_key: 1,
                 key_id: 0,
+                timestamp: 90,
             }, // duplicated impression with same match_key
             TestHybridRecord::TestImpression {
                 match_key: SHARD1_MKS[4],
                 breakdown_key: 2,
                 key_id: 0,
-            }, // duplicated impression with same match_key
+                timestamp: 101,
+            }, // duplicated impression with same match_key, last touch
             TestHybridRecord::TestConversion {
                 match_key: SHARD1_MKS[5],
                 value: 7,
@@ -250,13 +784,14 @@ pub mod test {
                 timestamp: 105,
                 epsilon: 0.0,
                 sensitivity: 0.0,
-            }, // removed
+            }, // attributed to the last impression
         ];
         let shard2_records = [
             TestHybridRecord::TestImpression {
                 match_key: SHARD2_MKS[0],
                 breakdown_key: 56,
                 key_id: 0,
+                timestamp: 95,
             },
             TestHybridRecord::TestConversion {
                 match_key: SHARD2_MKS[1],
@@ -271,6 +806,7 @@ pub mod test {
                 match_key: SHARD2_MKS[2],
                 breakdown_key: 78,
                 key_id: 0,
+                timestamp: 100,
             }, // NOT attributed
             TestHybridRecord::TestConversion {
                 match_key: SHARD2_MKS[3],
@@ -285,7 +821,8 @@ pub mod test {
                 match_key: SHARD2_MKS[4],
                 breakdown_key: 90,
                 key_id: 0,
-            }, // attributed twice, removed
+                timestamp: 103,
+            }, // attributed twice
             TestHybridRecord::TestConversion {
                 match_key: SHARD2_MKS[5],
                 value: 6,
@@ -294,7 +831,7 @@ pub mod test {
                 timestamp: 102,
                 epsilon: 0.0,
                 sensitivity: 0.0,
-            }, // attributed twice, removed
+            }, // attributed, unless there is an attribution window as it precedes the impression
             TestHybridRecord::TestConversion {
                 match_key: SHARD2_MKS[6],
                 value: 7,
@@ -303,7 +840,7 @@ pub mod test {
                 timestamp: 103,
                 epsilon: 0.0,
                 sensitivity: 0.0,
-            }, // attributed twice, removed
+            }, // attributed, the sum saturates
         ];
 
         shard1_records
@@ -318,48 +855,23 @@ pub mod test {
     fn group_reports_mpc() {
         run(|| async {
             let records = get_records();
+            let report = |value, breakdown_key| TestAggregateableHybridReport {
+                match_key: (),
+                value,
+                breakdown_key,
+            };
+            // within each shard, larger groups come first
             let expected = vec![
-                [
-                    TestAggregateableHybridReport {
-                        match_key: (),
-                        value: 0,
-                        breakdown_key: 45,
-                    },
-                    TestAggregateableHybridReport {
-                        match_key: (),
-                        value: 1,
-                        breakdown_key: 0,
-                    },
-                ],
-                [
-                    TestAggregateableHybridReport {
-                        match_key: (),
-                        value: 3,
-                        breakdown_key: 0,
-                    },
-                    TestAggregateableHybridReport {
-                        match_key: (),
-                        value: 4,
-                        breakdown_key: 0,
-                    },
-                ],
-                [
-                    TestAggregateableHybridReport {
-                        match_key: (),
-                        value: 0,
-                        breakdown_key: 56,
-                    },
-                    TestAggregateableHybridReport {
-                        match_key: (),
-                        value: 2,
-                        breakdown_key: 0,
-                    },
-                ],
+                vec![report(0, 1), report(0, 2), report(7, 0)],
+                vec![report(0, 45), report(1, 0)],
+                vec![report(3, 0), report(4, 0)],
+                vec![report(0, 90), report(6, 0), report(7, 0)],
+                vec![report(0, 56), report(2, 0)],
             ];
 
             let world = TestWorld::<WithShards<SHARDS>>::with_shards(TestWorldConfig::default());
             #[allow(clippy::type_complexity)]
-            let results: Vec<[Vec<[AggregateableHybridReport<BA8, BA3>; 2]>; 3]> = world
+            let results: Vec<[Vec<Vec<PrfHybridReport<BA8, BA3>>>; 3]> = world
                 .malicious(records.clone().into_iter(), |ctx, input| {
                     let match_keys = match ctx.shard_id() {
                         ShardIndex::FIRST => SHARD1_MKS,
@@ -378,14 +890,16 @@ pub mod test {
                                 match_key,
                                 value: indist_report.value.clone(),
                                 breakdown_key: indist_report.breakdown_key.clone(),
+                                timestamp: indist_report.timestamp.clone(),
+                                is_impression: indist_report.is_impression.clone(),
                             })
                             .collect::<Vec<_>>();
-                        group_report_pairs_ordered(prf_reports)
+                        group_reports_ordered(prf_reports)
                     }
                 })
                 .await;
 
-            let results: Vec<[TestAggregateableHybridReport; 2]> = results
+            let results: Vec<Vec<TestAggregateableHybridReport>> = results
                 .into_iter()
                 .flat_map(|shard_result| {
                     shard_result[0]
@@ -393,11 +907,19 @@ pub mod test {
                         .into_iter()
                         .zip(shard_result[1].clone())
                         .zip(shard_result[2].clone())
-                        .map(|((r1, r2), r3)| {
-                            [
-                                [&r1[0], &r2[0], &r3[0]].reconstruct(),
-                                [&r1[1], &r2[1], &r3[1]].reconstruct(),
-                            ]
+                        .map(|((g1, g2), g3)| {
+                            g1.iter()
+                                .zip(&g2)
+                                .zip(&g3)
+                                .map(|((r1, r2), r3)| {
+                                    [r1, r2, r3]
+                                        .map(|r| {
+                                            AggregateableHybridReport::<BA8, BA3>::from(r.clone())
+                                        })
+                                        .each_ref()
+                                        .reconstruct()
+                                })
+                                .collect::<Vec<_>>()
                         })
                         .collect::<Vec<_>>()
                 })
@@ -407,71 +929,281 @@ pub mod test {
         });
     }
 
+    async fn aggregate_test_records(
+        per_user_credit_cap: u32,
+        attribution_window: Option<NonZeroU32>,
+        breakdown_dimensions: Option<BreakdownDimensions>,
+    ) -> Vec<TestAggregateableHybridReport> {
+        aggregate_records(
+            get_records(),
+            per_user_credit_cap,
+            attribution_window,
+            breakdown_dimensions,
+        )
+        .await
+    }
+
+    /// Aggregates `records`, which are dealt to the shards in turn.
+    async fn aggregate_records(
+        records: Vec<TestHybridRecord>,
+        per_user_credit_cap: u32,
+        attribution_window: Option<NonZeroU32>,
+        breakdown_dimensions: Option<BreakdownDimensions>,
+    ) -> Vec<TestAggregateableHybridReport> {
+        let world = TestWorld::<WithShards<SHARDS>>::with_shards(TestWorldConfig::default());
+
+        let results: Vec<[Vec<AggregateableHybridReport<BA8, BA3>>; 3]> = world
+            .malicious(records.clone().into_iter(), |ctx, input| {
+                let match_keys = records
+                    .iter()
+                    .skip(usize::from(ctx.shard_id()))
+                    .step_by(SHARDS)
+                    .map(|record| match record {
+                        TestHybridRecord::TestImpression { match_key, .. }
+                        | TestHybridRecord::TestConversion { match_key, .. } => *match_key,
+                    })
+                    .collect::<Vec<_>>();
+                async move {
+                    let indistinguishable_reports: Vec<IndistinguishableHybridReport<BA8, BA3>> =
+                        input.iter().map(|r| r.clone().into()).collect::<Vec<_>>();
+
+                    let prf_reports: Vec<PrfHybridReport<BA8, BA3>> = indistinguishable_reports
+                        .iter()
+                        .zip(match_keys)
+                        .map(|(indist_report, match_key)| PrfHybridReport {
+                            match_key,
+                            value: indist_report.value.clone(),
+                            breakdown_key: indist_report.breakdown_key.clone(),
+                            timestamp: indist_report.timestamp.clone(),
+                            is_impression: indist_report.is_impression.clone(),
+                        })
+                        .collect::<Vec<_>>();
+
+                    aggregate_reports(
+                        ctx.clone(),
+                        prf_reports,
+                        per_user_credit_cap,
+                        attribution_window,
+                        breakdown_dimensions,
+                    )
+                    .await
+                    .unwrap()
+                }
+            })
+            .await;
+
+        results
+            .into_iter()
+            .flat_map(|shard_result| {
+                shard_result[0]
+                    .clone()
+                    .into_iter()
+                    .zip(shard_result[1].clone())
+                    .zip(shard_result[2].clone())
+                    .map(|((r1, r2), r3)| [&r1, &r2, &r3].reconstruct())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    }
+
+    fn aggregated_report(value: u32, breakdown_key: u32) -> TestAggregateableHybridReport {
+        TestAggregateableHybridReport {
+            match_key: (),
+            value,
+            breakdown_key,
+        }
+    }
+
     #[test]
     fn aggregate_reports_test() {
         run(|| async {
-            let records = get_records();
             let expected = vec![
-                TestAggregateableHybridReport {
-                    match_key: (),
-                    value: 1,
-                    breakdown_key: 45,
-                },
-                TestAggregateableHybridReport {
-                    match_key: (),
-                    value: 7,
-                    breakdown_key: 0,
-                },
-                TestAggregateableHybridReport {
-                    match_key: (),
-                    value: 2,
-                    breakdown_key: 56,
-                },
+                aggregated_report(7, 2),
+                aggregated_report(1, 45),
+                aggregated_report(7, 0),
+                aggregated_report(7, 90), // 6 + 7 saturates
+                aggregated_report(2, 56),
             ];
 
-            let world = TestWorld::<WithShards<SHARDS>>::with_shards(TestWorldConfig::default());
+            assert_eq!(aggregate_test_records(8, None, None).await, expected);
+        });
+    }
 
-            let results: Vec<[Vec<AggregateableHybridReport<BA8, BA3>>; 3]> = world
-                .malicious(records.clone().into_iter(), |ctx, input| {
-                    let match_keys = match ctx.shard_id() {
-                        ShardIndex::FIRST => SHARD1_MKS,
-                        SECOND_SHARD => SHARD2_MKS,
-                        _ => panic!("invalid shard_id"),
-                    };
-                    async move {
-                        let indistinguishable_reports: Vec<
-                            IndistinguishableHybridReport<BA8, BA3>,
-                        > = input.iter().map(|r| r.clone().into()).collect::<Vec<_>>();
+    #[test]
+    fn aggregate_reports_per_user_credit_cap() {
+        run(|| async {
+            let expected = vec![
+                aggregated_report(5, 2),
+                aggregated_report(1, 45),
+                aggregated_report(5, 0),
+                aggregated_report(5, 90),
+                aggregated_report(2, 56),
+            ];
 
-                        let prf_reports: Vec<PrfHybridReport<BA8, BA3>> = indistinguishable_reports
-                            .iter()
-                            .zip(match_keys)
-                            .map(|(indist_report, match_key)| PrfHybridReport {
-                                match_key,
-                                value: indist_report.value.clone(),
-                                breakdown_key: indist_report.breakdown_key.clone(),
-                            })
-                            .collect::<Vec<_>>();
+            assert_eq!(aggregate_test_records(5, None, None).await, expected);
+        });
+    }
 
-                        aggregate_reports(ctx.clone(), prf_reports).await.unwrap()
-                    }
-                })
-                .await;
+    #[test]
+    fn aggregate_reports_attribution_window() {
+        run(|| async {
+            let expected = vec![
+                aggregated_report(7, 2),
+                aggregated_report(1, 45),
+                aggregated_report(0, 0),  // no impression
+                aggregated_report(7, 90), // 6 was converted before the impression
+                aggregated_report(0, 56), // 5 seconds after the impression
+            ];
 
-            let results: Vec<TestAggregateableHybridReport> = results
-                .into_iter()
-                .flat_map(|shard_result| {
-                    shard_result[0]
-                        .clone()
-                        .into_iter()
-                        .zip(shard_result[1].clone())
-                        .zip(shard_result[2].clone())
-                        .map(|((r1, r2), r3)| [&r1, &r2, &r3].reconstruct())
-                        .collect::<Vec<_>>()
-                })
+            assert_eq!(
+                aggregate_test_records(8, NonZeroU32::new(4), None).await,
+                expected
+            );
+        });
+    }
+
+    #[test]
+    fn aggregate_reports_breakdown_dimensions() {
+        run(|| async {
+            // 6x10 dimensions encode the first key in 3 bits and the second key above them
+            let dimensions = BreakdownDimensions::new(6, 10).unwrap();
+            let expected = vec![
+                aggregated_report(7, 2),          // (2, 0)
+                aggregated_report(1, 5 + 6 * 5),  // 45 encodes (5, 5)
+                aggregated_report(7, 0),          // (0, 0)
+                aggregated_report(0, 2 + 6 * 11), // 90 encodes (2, 11), outside
+                aggregated_report(2, 6 * 7),      // 56 encodes (0, 7)
+            ];
+
+            assert_eq!(
+                aggregate_test_records(8, None, Some(dimensions)).await,
+                expected
+            );
+        });
+    }
+
+    fn impression(match_key: u64, breakdown_key: u32, timestamp: u64) -> TestHybridRecord {
+        TestHybridRecord::TestImpression {
+            match_key,
+            breakdown_key,
+            key_id: 0,
+            timestamp,
+        }
+    }
+
+    fn conversion(match_key: u64, value: u32, timestamp: u64) -> TestHybridRecord {
+        TestHybridRecord::TestConversion {
+            match_key,
+            value,
+            key_id: 0,
+            conversion_site_domain: "meta.com".to_string(),
+            timestamp,
+            epsilon: 0.0,
+            sensitivity: 0.0,
+        }
+    }
+
+    #[test]
+    fn aggregate_reports_last_touch_by_timestamp() {
+        run(|| async {
+            // The reports of every user arrive in an order that differs from their timestamps.
+            let shard1_records = [
+                impression(1, 3, 110), // latest impression
+                impression(1, 5, 100),
+                conversion(1, 2, 120),
+                impression(2, 6, 90), // latest impression preceding the conversion
+                conversion(2, 4, 125),
+                impression(2, 7, 130),
+            ];
+            let shard2_records = [
+                conversion(3, 3, 50), // every impression follows the conversion
+                impression(3, 9, 60),
+                impression(3, 10, 70),
+                impression(4, 4, 10), // latest impression preceding the conversion
+                impression(4, 8, 30),
+                conversion(4, 1, 20),
+            ];
+            let records = zip(shard1_records, shard2_records)
+                .flat_map(<[_; 2]>::from)
                 .collect::<Vec<_>>();
 
-            assert_eq!(results, expected);
+            let expected = vec![
+                aggregated_report(2, 3),
+                aggregated_report(4, 6),
+                aggregated_report(3, 0),
+                aggregated_report(1, 4),
+            ];
+            assert_eq!(
+                aggregate_records(records.clone(), 8, None, None).await,
+                expected
+            );
+
+            // The window is measured from the selected impression.
+            let expected = vec![
+                aggregated_report(2, 3),
+                aggregated_report(0, 6), // 35 seconds after the impression
+                aggregated_report(0, 0),
+                aggregated_report(1, 4),
+            ];
+            assert_eq!(
+                aggregate_records(records, 8, NonZeroU32::new(15), None).await,
+                expected
+            );
+        });
+    }
+
+    #[test]
+    fn aggregate_reports_impression_with_zero_breakdown_key() {
+        run(|| async {
+            // Impressions may carry a breakdown key of 0, so they can't be told apart from
+            // conversions by their breakdown key.
+            let shard1_records = [
+                impression(1, 5, 90),
+                impression(1, 0, 100), // latest impression preceding the conversion
+                conversion(1, 3, 110),
+            ];
+            let shard2_records = [
+                conversion(2, 4, 50), // every impression follows the conversion
+                impression(2, 0, 60),
+                impression(2, 6, 70),
+            ];
+            let records = zip(shard1_records, shard2_records)
+                .flat_map(<[_; 2]>::from)
+                .collect::<Vec<_>>();
+
+            let expected = vec![aggregated_report(3, 0), aggregated_report(4, 0)];
+            assert_eq!(
+                aggregate_records(records.clone(), 8, None, None).await,
+                expected
+            );
+
+            // Within the window, the conversion is attributed to the impression with key 0,
+            // while the one with no preceding impression is dropped.
+            let expected = vec![aggregated_report(3, 0), aggregated_report(0, 0)];
+            assert_eq!(
+                aggregate_records(records, 8, NonZeroU32::new(15), None).await,
+                expected
+            );
+        });
+    }
+
+    #[test]
+    fn aggregate_reports_attribution_window_long_gap() {
+        run(|| async {
+            // The first conversion happens 2^20 + 5 seconds after the impression, which must not
+            // wrap around into the window.
+            let records = vec![
+                impression(1, 3, 100),
+                impression(2, 4, 100),
+                conversion(1, 2, 100 + (1 << 20) + 5),
+                conversion(2, 1, 105),
+            ];
+
+            let expected = vec![aggregated_report(0, 3), aggregated_report(1, 4)];
+            assert_eq!(
+                aggregate_records(records, 8, NonZeroU32::new(10), None).await,
+                expected
+            );
         });
     }
 
@@ -487,49 +1219,44 @@ pub mod test {
                 BA8::truncate_from(breakdown_key),
                 BA8::truncate_from(0_u128),
             ),
-        }
-    }
-
-    fn build_aggregateable_report(
-        value: u8,
-        breakdown_key: u8,
-    ) -> AggregateableHybridReport<BA8, BA3> {
-        AggregateableHybridReport::<BA8, BA3> {
-            match_key: (),
-            value: Replicated::new(BA3::truncate_from(value), BA3::truncate_from(0_u128)),
-            breakdown_key: Replicated::new(
-                BA8::truncate_from(breakdown_key),
-                BA8::truncate_from(0_u128),
-            ),
+            timestamp: Replicated::ZERO,
+            is_impression: Replicated::ZERO,
         }
     }
 
     #[test]
     fn group_reports() {
-        let reports = vec![
-            build_prf_hybrid_report(42, 2, 0),  // pair: index (1,0)
-            build_prf_hybrid_report(42, 0, 3),  // pair: index (1,1)
-            build_prf_hybrid_report(17, 4, 0),  // pair: index (0,0)
-            build_prf_hybrid_report(17, 0, 13), // pair: index (0,1)
+        let mut reports = vec![
+            build_prf_hybrid_report(42, 2, 0),  // pair: index (2,0)
+            build_prf_hybrid_report(42, 0, 3),  // pair: index (2,1)
+            build_prf_hybrid_report(17, 4, 0),  // pair: index (1,0)
+            build_prf_hybrid_report(17, 0, 13), // pair: index (1,1)
             build_prf_hybrid_report(13, 0, 5),  // single
             build_prf_hybrid_report(11, 2, 0),  // single
-            build_prf_hybrid_report(31, 1, 2),  // triple
-            build_prf_hybrid_report(31, 3, 4),  // triple
-            build_prf_hybrid_report(31, 5, 6),  // triple
+            build_prf_hybrid_report(31, 1, 2),  // triple: index (0,0)
+            build_prf_hybrid_report(31, 3, 4),  // triple: index (0,1)
+            build_prf_hybrid_report(31, 5, 6),  // triple: index (0,2)
         ];
+        // too many reports
+        reports.extend((0..=MAX_REPORTS_PER_MATCH_KEY).map(|_| build_prf_hybrid_report(7, 1, 0)));
 
         let expected = vec![
-            [
-                build_aggregateable_report(4, 0),
-                build_aggregateable_report(0, 13),
+            vec![
+                build_prf_hybrid_report(31, 1, 2),
+                build_prf_hybrid_report(31, 3, 4),
+                build_prf_hybrid_report(31, 5, 6),
+            ],
+            vec![
+                build_prf_hybrid_report(17, 4, 0),
+                build_prf_hybrid_report(17, 0, 13),
             ],
-            [
-                build_aggregateable_report(2, 0),
-                build_aggregateable_report(0, 3),
+            vec![
+                build_prf_hybrid_report(42, 2, 0),
+                build_prf_hybrid_report(42, 0, 3),
             ],
         ];
 
-        let results = group_report_pairs_ordered(reports);
+        let results = group_reports_ordered(reports);
         assert_eq!(results, expected);
     }
 
@@ -543,7 +1270,7 @@ pub mod test {
             let target_shard = ShardIndex::from(rng.gen_range(0..u32::try_from(SHARDS).unwrap()));
             let mut config = TestWorldConfig::default();
 
-            let step = format!("{}/{}", AggregateReportsStep::AddBK.as_ref(), "bit0",);
+            let step = MergeReportStep::SelectBK.as_ref().to_string();
             config.stream_interceptor =
                 MaliciousHelper::new(Role::H2, config.role_assignment(), move |ctx, data| {
                     // flip a bit of the match_key on the target shard, H1
@@ -576,10 +1303,14 @@ pub mod test {
                                 match_key,
                                 value: indist_report.value.clone(),
                                 breakdown_key: indist_report.breakdown_key.clone(),
+                                timestamp: indist_report.timestamp.clone(),
+                                is_impression: indist_report.is_impression.clone(),
                             })
                             .collect::<Vec<_>>();
 
-                        aggregate_reports(ctx.clone(), prf_reports).await.unwrap()
+                        aggregate_reports(ctx.clone(), prf_reports, 8, None, None)
+                            .await
+                            .unwrap()
                     }
                 })
                 .await;
@@ -190,7 +190,11 @@ where
     let mut grouped_tvs = ValueHistogram::<V, B>::new();
     let mut stream = pin!(seq_join(reveal_ctx.active_work(), reveal_work));
     while let Some((bk, tv)) = stream.try_next().await? {
-        grouped_tvs.push(bk, tv);
+        // 16-bit breakdown keys can exceed the number of buckets (see `BreakdownKey`). The
+        // values attributed to keys outside the histogram are dropped.
+        if bk < B {
+            grouped_tvs.push(bk, tv);
+        }
     }
 
     Ok(grouped_tvs)
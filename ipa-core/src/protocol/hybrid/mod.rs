@@ -3,7 +3,7 @@ pub(crate) mod breakdown_reveal;
 pub(crate) mod oprf;
 pub(crate) mod step;
 
-use std::{convert::Infallible, ops::Add};
+use std::{convert::Infallible, num::NonZeroU32, ops::Add};
 
 use generic_array::ArrayLength;
 use tracing::{info_span, Instrument};
@@ -14,7 +14,7 @@ use crate::{
         boolean::Boolean, boolean_array::BooleanArray, curve_points::RP25519,
         ec_prime_field::Fp25519, Serializable, U128Conversions,
     },
-    helpers::query::DpMechanism,
+    helpers::query::{BreakdownDimensions, DpMechanism, HybridAggregationMode},
     protocol::{
         basics::{
             shard_fin::{FinalizerContext, Histogram},
@@ -23,9 +23,9 @@ use crate::{
         context::{
             DZKPUpgraded, MacUpgraded, MaliciousProtocolSteps, ShardedContext, UpgradableContext,
         },
-        dp::dp_for_histogram,
+        dp::{dp_for_aggregation, Sensitivity},
         hybrid::{
-            agg::aggregate_reports,
+            agg::{aggregate_reports, count_reach},
             breakdown_reveal::breakdown_reveal_aggregation,
             oprf::{compute_prf_and_reshard, BreakdownKey, CONV_CHUNK, PRF_CHUNK},
             step::{FinalizeSteps, HybridStep as Step},
@@ -38,7 +38,7 @@ use crate::{
         prss::FromPrss,
         BooleanProtocols,
     },
-    report::hybrid::{IndistinguishableHybridReport, PrfHybridReport},
+    report::hybrid::{HybridTimestamp, IndistinguishableHybridReport, PrfHybridReport},
     secret_sharing::{
         replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, FieldSimd,
         TransposeFrom, Vectorizable,
@@ -57,24 +57,40 @@ use crate::{
 ///    that information leakage)
 /// 2. Shuffles the input
 /// 3. Computes an OPRF of these elliptic curve points and reveals this "pseudonym"
-/// 4. Groups together rows with the same OPRF and sums both the breakdown keys and values.
+/// 4. Groups together rows with the same OPRF, picks a single breakdown key for the group and sums
+///    the values of the conversions within the `attribution_window` (if any), capping the sum at
+///    `per_user_credit_cap`. When `breakdown_dimensions` are set, the breakdown key carries
+///    the keys of two dimensions, which are combined into a single bucket (see
+///    [`BreakdownDimensions`]).
 /// 5. Generates a random number of "dummy records" (needed to mask the information that will
 ///    be revealed in step 7)
 /// 6. Shuffles the input
 /// 7. Reveals breakdown keys
 /// 8. Sums the values by breakdown keys
 /// 9. Adds random noise to the total value for each breakdown key (to provide a
-///    differential privacy guarantee)
+///    differential privacy guarantee). Every user contributes to a single bucket, including
+///    when it combines two dimensions, so the sensitivity of the histogram is accounted once.
+///
+/// When the `aggregation_mode` includes reach, every user attributed a non-zero value is also
+/// counted once towards the breakdown key they were attributed to. Steps 5 to 9 are repeated
+/// for these counts, and the noisy counts are returned after the noisy sums. Revealing the
+/// breakdown keys a second time spends the aggregation padding budget twice, and noising
+/// the counts spends the epsilon of `dp_params` a second time.
 ///
 /// # Errors
 /// Propagates errors from config issues or while running the protocol
 /// # Panics
 /// Propagates errors from config issues or while running the protocol
-pub async fn hybrid_protocol<'ctx, C, BK, V, HV, const SS_BITS: usize, const B: usize>(
+#[allow(clippy::too_many_arguments)]
+pub async fn hybrid_protocol<'ctx, C, BK, V, HV, const B: usize>(
     ctx: C,
     input_rows: Vec<IndistinguishableHybridReport<BK, V>>,
     dp_params: DpMechanism,
     dp_padding_params: PaddingParameters,
+    per_user_credit_cap: u32,
+    attribution_window: Option<NonZeroU32>,
+    breakdown_dimensions: Option<BreakdownDimensions>,
+    aggregation_mode: HybridAggregationMode,
 ) -> Result<Vec<Replicated<HV>>, Error>
 where
     C: UpgradableContext
@@ -97,6 +113,8 @@ where
     Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
     Replicated<BK>: BooleanArrayMul<DZKPUpgraded<C>>
         + Reveal<DZKPUpgraded<C>, Output = <BK as Vectorizable<1>>::Array>,
+    Replicated<V>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<HybridTimestamp>: BooleanArrayMul<DZKPUpgraded<C>>,
     BitDecomposed<Replicated<Boolean, B>>:
         for<'a> TransposeFrom<&'a [Replicated<V>; B], Error = Infallible>,
     BitDecomposed<Replicated<Boolean, B>>:
@@ -108,7 +126,8 @@ where
     DZKPUpgraded<C>: ShardedContext,
 {
     if input_rows.is_empty() {
-        return Ok(vec![Replicated::ZERO; B]);
+        let histograms = usize::try_from(aggregation_mode.histograms()).unwrap();
+        return Ok(vec![Replicated::ZERO; B * histograms]);
     }
 
     // Apply DP padding for OPRF
@@ -127,7 +146,21 @@ where
 
     let sharded_reports = compute_prf_and_reshard(ctx.clone(), shuffled_input_rows).await?;
 
-    let aggregated_reports = aggregate_reports::<BK, V, C>(ctx.clone(), sharded_reports).await?;
+    let aggregated_reports = aggregate_reports::<BK, V, C>(
+        ctx.clone(),
+        sharded_reports,
+        per_user_credit_cap,
+        attribution_window,
+        breakdown_dimensions,
+    )
+    .await?;
+
+    let reach_reports = match aggregation_mode {
+        HybridAggregationMode::Sum => None,
+        HybridAggregationMode::SumAndReach => {
+            Some(count_reach(ctx.clone(), &aggregated_reports).await?)
+        }
+    };
 
     let histogram = breakdown_reveal_aggregation::<C, BK, V, HV, B>(
         ctx.narrow(&Step::Aggregate),
@@ -136,10 +169,66 @@ where
     )
     .await?;
 
+    // saturation in `aggregate_reports` never lets a user contribute more than `V` can hold
+    let max_value = u32::try_from((1_u64 << V::BITS) - 1).unwrap_or(u32::MAX);
+    let mut noisy_histogram = finalize_histogram::<C, HV, B>(
+        ctx.narrow(&Step::Finalize),
+        ctx.clone(),
+        histogram,
+        dp_params,
+        Sensitivity::histogram(per_user_credit_cap.min(max_value)),
+    )
+    .await?;
+
+    if let Some(reach_reports) = reach_reports {
+        let reach_histogram = breakdown_reveal_aggregation::<C, BK, V, HV, B>(
+            ctx.narrow(&Step::AggregateReach),
+            reach_reports,
+            &dp_padding_params,
+        )
+        .await?;
+        // every user is counted at most once
+        noisy_histogram.extend(
+            finalize_histogram::<C, HV, B>(
+                ctx.narrow(&Step::FinalizeReach),
+                ctx.narrow(&Step::ReachDp),
+                reach_histogram,
+                dp_params,
+                Sensitivity::histogram(1),
+            )
+            .await?,
+        );
+    }
+
+    Ok(noisy_histogram)
+}
+
+/// Sums the histograms of all shards on the leader shard, and adds DP noise calibrated to
+/// `sensitivity` to the sum. Followers return an empty histogram.
+async fn finalize_histogram<C, HV, const B: usize>(
+    ctx: C,
+    dp_ctx: C,
+    histogram: BitDecomposed<Replicated<Boolean, B>>,
+    dp_params: DpMechanism,
+    sensitivity: Sensitivity,
+) -> Result<Vec<Replicated<HV>>, Error>
+where
+    C: UpgradableContext + ShardedContext + FinalizerContext<FinalizingContext = DZKPUpgraded<C>>,
+    HV: BooleanArray + U128Conversions,
+    <HV as Serializable>::Size: Add<<HV as Serializable>::Size, Output: ArrayLength>,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<HV>; B], Error = Infallible>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a Vec<Replicated<HV>>, Error = LengthError>,
+    Vec<Replicated<HV>>:
+        for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
+    DZKPUpgraded<C>: ShardedContext,
+{
     let histogram: Histogram<HV, B> = Histogram::from(histogram);
 
     let finalized_histogram = ctx
-        .narrow(&Step::Finalize)
         .finalize(
             MaliciousProtocolSteps {
                 protocol: &FinalizeSteps::Add,
@@ -149,11 +238,10 @@ where
         )
         .await?;
 
-    let noisy_histogram = if ctx.is_leader() {
-        dp_for_histogram::<_, B, HV, SS_BITS>(ctx, finalized_histogram.values, dp_params).await?
+    if dp_ctx.is_leader() {
+        dp_for_aggregation::<_, B, HV>(dp_ctx, finalized_histogram.values, dp_params, sensitivity)
+            .await
     } else {
-        finalized_histogram.compose()
-    };
-
-    Ok(noisy_histogram)
+        Ok(finalized_histogram.compose())
+    }
 }
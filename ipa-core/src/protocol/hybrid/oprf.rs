@@ -7,7 +7,7 @@ use crate::{
     error::{Error, UnwrapInfallible},
     ff::{
         boolean::Boolean,
-        boolean_array::{BooleanArray, BA5, BA64, BA8},
+        boolean_array::{BooleanArray, BA16, BA5, BA64, BA8},
         curve_points::RP25519,
         ec_prime_field::Fp25519,
         Serializable, U128Conversions,
@@ -42,10 +42,11 @@ use crate::{
 };
 
 // In theory, we could support (runtime-configured breakdown count) ≤ (compile-time breakdown count)
-// ≤ 2^|bk|, with all three values distinct, but at present, there is no runtime configuration and
-// the latter two must be equal. The implementation of `move_single_value_to_bucket` does support a
-// runtime-specified count via the `breakdown_count` parameter, and implements a runtime check of
-// its value.
+// ≤ 2^|bk|, with all three values distinct. At present, the latter two are equal, except for 16-bit
+// breakdown keys: a histogram of 2^16 buckets is too large to vectorize, so they are aggregated
+// into 1024 buckets and `max_breakdown_key` must fit into those. The implementation of
+// `move_single_value_to_bucket` does support a runtime-specified count via the `breakdown_count`
+// parameter, and implements a runtime check of its value.
 //
 // It would usually be more appropriate to make `MAX_BREAKDOWNS` an associated constant rather than
 // a const parameter. However, we want to use it to enforce a correct pairing of the `BK` type
@@ -60,6 +61,7 @@ use crate::{
 pub trait BreakdownKey<const MAX_BREAKDOWNS: usize>: BooleanArray + U128Conversions {}
 impl BreakdownKey<32> for BA5 {}
 impl BreakdownKey<256> for BA8 {}
+impl BreakdownKey<1024> for BA16 {}
 
 /// Match key type
 pub type MatchKey = BA64;
@@ -177,6 +179,8 @@ where
             match_key: prf_of_match_key,
             value: input.value,
             breakdown_key: input.breakdown_key,
+            timestamp: input.timestamp,
+            is_impression: input.is_impression,
         });
 
     // reshard reports based on OPRF values. This ensures at the end of this function
@@ -233,11 +237,13 @@ mod test {
                     match_key: 12345,
                     breakdown_key: 2,
                     key_id: 0,
+                    timestamp: 100,
                 },
                 TestHybridRecord::TestImpression {
                     match_key: 68362,
                     breakdown_key: 1,
                     key_id: 0,
+                    timestamp: 100,
                 },
                 TestHybridRecord::TestConversion {
                     match_key: 12345,
@@ -261,6 +267,7 @@ mod test {
                     match_key: 68362,
                     breakdown_key: 1,
                     key_id: 0,
+                    timestamp: 100,
                 },
                 TestHybridRecord::TestConversion {
                     match_key: 68362,
@@ -2,7 +2,14 @@ use ipa_step_derive::CompactStep;
 
 #[derive(CompactStep)]
 pub(crate) enum HybridStep {
+    ValidatePaddingParameters,
     ReshardByTag,
+    ValidateConversionInfo,
+    ValidateRecordCount,
+    ReshardBySiteEpoch,
+    ValidatePrivacyBudget,
+    ValidateUniqueReports,
+    ValidateReplayedReports,
     #[step(child = crate::protocol::ipa_prf::oprf_padding::step::PaddingDpStep, name="report_padding_dp")]
     PaddingDp,
     #[step(child = crate::protocol::ipa_prf::shuffle::step::ShardedShuffleStep)]
@@ -19,17 +26,63 @@ pub(crate) enum HybridStep {
     GroupBySum,
     #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
     GroupBySumValidate,
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+    CountReach,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    CountReachValidate,
     #[step(child = AggregationStep)]
     Aggregate,
+    #[step(child = AggregationStep)]
+    AggregateReach,
     #[step(child = FinalizeSteps)]
     Finalize,
+    #[step(child = FinalizeSteps)]
+    FinalizeReach,
+    ReachDp,
 }
 
 #[derive(CompactStep)]
 pub(crate) enum AggregateReportsStep {
+    // The count must be at least `agg::MAX_REPORTS_PER_MATCH_KEY`.
+    #[step(count = 8, child = MergeReportStep, name = "report")]
+    Report(usize),
+    DropUnattributed,
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+    CompareCap,
+    ApplyCap,
     #[step(child = crate::protocol::boolean::step::EightBitStep)]
-    AddBK,
+    CompareFirstDimension,
     #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    CompareSecondDimension,
+    OutsideDimensions,
+    // The count must be at least the number of bits in a breakdown key.
+    #[step(count = 8, child = crate::protocol::boolean::step::EightBitStep)]
+    CombineDimensions(usize),
+    DropOutsideDimensions,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum MergeReportStep {
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    CompareConversionTimestamps,
+    IsLatestConversion,
+    SelectConversionTimestamp,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    CompareToConversionTimestamp,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    CompareToImpressionTimestamp,
+    PrecedesConversion,
+    IsLatestImpression,
+    SelectBK,
+    SelectTimestamp,
+    HasImpression,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    ComputeTimeDelta,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    CompareTimeDeltaToAttributionWindow,
+    OutsideAttributionWindow,
+    FilterValue,
+    #[step(child = crate::protocol::ipa_prf::boolean_ops::step::SaturatedAdditionStep)]
     AddV,
 }
 
@@ -50,8 +103,9 @@ pub(crate) enum AggregationStep {
     Reveal,
     #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
     RevealValidate, // only partly used -- see code
-    #[step(count = 4, child = crate::protocol::ipa_prf::aggregation::step::AggregateChunkStep, name = "chunks")]
+    // Tests with small proofs aggregate 1024 buckets two rows at a time, which takes more layers.
+    #[step(count = 8, child = crate::protocol::ipa_prf::aggregation::step::AggregateChunkStep, name = "chunks")]
     Aggregate(usize),
-    #[step(count = 4, child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    #[step(count = 8, child = crate::protocol::context::step::DzkpValidationProtocolStep)]
     AggregateValidate(usize),
 }
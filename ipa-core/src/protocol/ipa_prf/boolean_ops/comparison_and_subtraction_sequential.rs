@@ -91,6 +91,33 @@ where
     subtraction_circuit::<_, S, 1>(ctx, record_id, x, y, &mut carry).await
 }
 
+/// Subtracts `y` from `x` like [`integer_sub`], and also returns a share of 1 if `x >= y`, or a
+/// share of 0 if the difference wrapped around.
+/// # Errors
+/// propagates errors from multiply
+pub async fn integer_sub_with_carry<C, S>(
+    ctx: C,
+    record_id: RecordId,
+    x: &BitDecomposed<AdditiveShare<Boolean>>,
+    y: &BitDecomposed<AdditiveShare<Boolean>>,
+) -> Result<
+    (
+        BitDecomposed<AdditiveShare<Boolean>>,
+        AdditiveShare<Boolean>,
+    ),
+    Error,
+>
+where
+    C: Context,
+    S: NBitStep,
+    AdditiveShare<Boolean>: BooleanProtocols<C>,
+    Gate: StepNarrow<S>,
+{
+    let mut carry = AdditiveShare::<Boolean>::share_known_value(&ctx, Boolean::ONE);
+    let difference = subtraction_circuit::<_, S, 1>(ctx, record_id, x, y, &mut carry).await?;
+    Ok((difference, carry))
+}
+
 /// saturated unsigned integer subtraction
 /// subtracts y from x, Output has same length as x (we dont seem to need support for different length).
 /// when y>x, it outputs 0. Only correct when length(x) >= log2(y).
@@ -231,7 +258,7 @@ mod test {
             boolean::step::DefaultBitStep,
             context::Context,
             ipa_prf::boolean_ops::comparison_and_subtraction_sequential::{
-                compare_geq, compare_gt, integer_sat_sub, integer_sub,
+                compare_geq, compare_gt, integer_sat_sub, integer_sub, integer_sub_with_carry,
             },
             RecordId,
         },
@@ -545,6 +572,41 @@ mod test {
         });
     }
 
+    #[test]
+    fn semi_honest_sub_with_carry() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let mut rng = thread_rng();
+
+            let records: Vec<BA64> = vec![rng.gen::<BA64>(), rng.gen::<BA64>()];
+            let x = records[0].as_u128();
+            let y = records[1].as_u128();
+            let z = 1_u128 << 64;
+
+            let expected = (((x + z) - y) % z, <Boolean>::from(x >= y));
+
+            let (result, carry): (BA64, Boolean) = world
+                .dzkp_semi_honest(records.into_iter(), |ctx, x_y| async move {
+                    let (difference, carry) = integer_sub_with_carry::<_, DefaultBitStep>(
+                        ctx.set_total_records(1),
+                        protocol::RecordId(0),
+                        &x_y[0].to_bits(),
+                        &x_y[1].to_bits(),
+                    )
+                    .await
+                    .unwrap();
+                    (difference.collect_bits(), carry)
+                })
+                .await
+                .reconstruct();
+            assert_eq!(
+                (x, y, result.as_u128(), carry),
+                (x, y, expected.0, expected.1)
+            );
+        });
+    }
+
     #[test]
     fn semi_honest_sat_sub() {
         run(|| async move {
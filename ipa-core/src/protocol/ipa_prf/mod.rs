@@ -11,7 +11,7 @@ use crate::{
     ff::{
         boolean::Boolean,
         boolean_array::{
-            BooleanArray, BooleanArrayReader, BooleanArrayWriter, BA112, BA5, BA64, BA8,
+            BooleanArray, BooleanArrayReader, BooleanArrayWriter, BA112, BA16, BA5, BA64, BA8,
         },
         curve_points::RP25519,
         ec_prime_field::Fp25519,
@@ -58,6 +58,7 @@ mod quicksort;
 pub(crate) mod shuffle;
 pub(crate) mod step;
 pub mod validation_protocol;
+pub mod walr;
 
 pub use malicious_security::{
     CompressedProofGenerator, FirstProofGenerator, LagrangeTable, ProverTableIndices,
@@ -102,7 +103,7 @@ pub const SORT_CHUNK: usize = 256;
 use step::IpaPrfStep as Step;
 
 use crate::{
-    helpers::query::DpMechanism,
+    helpers::query::{AttributionModel, DpMechanism},
     protocol::{
         context::Validator,
         dp::dp_for_histogram,
@@ -313,8 +314,8 @@ where
 /// 4. Computes an OPRF of these elliptic curve points and reveals this "pseudonym"
 /// 5. Groups together rows with the same OPRF, and then obliviously sorts each group by the
 ///    secret-shared timestamp
-/// 6. Attributes trigger events to source events
-/// 7. Caps each user's total contribution to the final result
+/// 6. Attributes trigger events to source events, using `attribution_model`
+/// 7. Caps each user's total contribution to the final result at `per_user_credit_cap`
 /// 8. Aggregates the contributions of all users
 /// 9. Adds random noise to the total for each breakdown key (to provide a differential
 ///    privacy guarantee)
@@ -322,10 +323,12 @@ where
 /// Propagates errors from config issues or while running the protocol
 /// # Panics
 /// Propagates errors from config issues or while running the protocol
-pub async fn oprf_ipa<'ctx, C, BK, TV, HV, TS, const SS_BITS: usize, const B: usize>(
+pub async fn oprf_ipa<'ctx, C, BK, TV, HV, TS, const B: usize>(
     ctx: C,
     input_rows: Vec<OPRFIPAInputRow<BK, TV, TS>>,
+    per_user_credit_cap: u32,
     attribution_window_seconds: Option<NonZeroU32>,
+    attribution_model: AttributionModel,
     dp_params: DpMechanism,
     dp_padding_params: PaddingParameters,
 ) -> Result<Vec<Replicated<HV>>, Error>
@@ -349,6 +352,8 @@ where
         + Reveal<DZKPUpgraded<C>, Output = <BK as Vectorizable<1>>::Array>,
     Replicated<TS>: BooleanArrayMul<DZKPUpgraded<C>>,
     Replicated<TV>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<BA8>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<BA16>: BooleanArrayMul<DZKPUpgraded<C>>,
     BitDecomposed<Replicated<Boolean, AGG_CHUNK>>:
         for<'a> TransposeFrom<&'a Vec<Replicated<BK>>, Error = LengthError>,
     BitDecomposed<Replicated<Boolean, AGG_CHUNK>>:
@@ -359,6 +364,8 @@ where
     >,
     BitDecomposed<Replicated<Boolean, B>>:
         for<'a> TransposeFrom<&'a [Replicated<TV>; B], Error = Infallible>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<BA8>; B], Error = Infallible>,
     Vec<Replicated<HV>>:
         for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
     BitDecomposed<AdditiveShare<Boolean, B>>:
@@ -399,17 +406,19 @@ where
     )
     .await?;
 
-    let output_histogram = attribute_cap_aggregate::<_, _, _, _, _, SS_BITS, B>(
+    let output_histogram = attribute_cap_aggregate::<_, _, _, _, _, B>(
         ctx.narrow(&Step::Attribution),
         prfd_inputs,
+        per_user_credit_cap,
         attribution_window_seconds,
+        attribution_model,
         &row_count_histogram,
         &dp_padding_params,
     )
     .await?;
 
     let noisy_output_histogram =
-        dp_for_histogram::<_, B, HV, SS_BITS>(ctx, output_histogram, dp_params).await?;
+        dp_for_histogram::<_, B, HV>(ctx, output_histogram, per_user_credit_cap, dp_params).await?;
     Ok(noisy_output_histogram)
 }
 
@@ -440,6 +449,48 @@ where
         PrfSharing<MacUpgraded<C, Fp25519>, PRF_CHUNK, Field = Fp25519> + FromPrss,
     Replicated<RP25519, PRF_CHUNK>:
         Reveal<MacUpgraded<C, Fp25519>, Output = <RP25519 as Vectorizable<PRF_CHUNK>>::Array>,
+{
+    let prf_of_match_keys =
+        compute_prf_of_match_keys(ctx, input_rows, |row| &row.match_key).await?;
+
+    Ok(zip(input_rows, prf_of_match_keys)
+        .map(|(input, prf_of_match_key)| {
+            let OPRFIPAInputRow {
+                match_key: _,
+                is_trigger,
+                breakdown_key,
+                trigger_value,
+                timestamp,
+            } = &input;
+
+            PrfShardedIpaInputRow {
+                prf_of_match_key,
+                is_trigger_bit: is_trigger.clone(),
+                breakdown_key: breakdown_key.clone(),
+                trigger_value: trigger_value.clone(),
+                timestamp: timestamp.clone(),
+                sort_key: Replicated::ZERO,
+            }
+        })
+        .collect())
+}
+
+/// Computes the OPRF of the match key of every input row and reveals it. The output is in
+/// the same order as `input_rows`, which must already have been shuffled.
+pub(crate) async fn compute_prf_of_match_keys<C, R, F>(
+    ctx: C,
+    input_rows: &[R],
+    match_key: F,
+) -> Result<Vec<u64>, Error>
+where
+    C: UpgradableContext,
+    R: Clone + Default + Sync,
+    F: Fn(&R) -> &Replicated<MatchKey> + Copy + Send + Sync,
+    Replicated<Boolean, CONV_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, CONV_CHUNK>,
+    Replicated<Fp25519, PRF_CHUNK>:
+        PrfSharing<MacUpgraded<C, Fp25519>, PRF_CHUNK, Field = Fp25519> + FromPrss,
+    Replicated<RP25519, PRF_CHUNK>:
+        Reveal<MacUpgraded<C, Fp25519>, Output = <RP25519 as Vectorizable<PRF_CHUNK>>::Array>,
 {
     let conv_records =
         TotalRecords::specified(div_round_up(input_rows.len(), Const::<CONV_CHUNK>))?;
@@ -460,7 +511,7 @@ where
         process_slice_by_chunks(input_rows, move |idx, records: ChunkData<_, CONV_CHUNK>| {
             let record_id = RecordId::from(idx);
             let input_match_keys: &dyn Fn(usize) -> Replicated<MatchKey> =
-                &|i| records[i].match_key.clone();
+                &|i| match_key(&records[i]).clone();
             let match_keys =
                 BitDecomposed::<Replicated<Boolean, 256>>::transposed_from(input_match_keys)
                     .unwrap_infallible();
@@ -492,25 +543,10 @@ where
     .try_collect::<Vec<_>>()
     .await?;
 
-    Ok(zip(input_rows, prf_of_match_keys.into_iter().flatten())
-        .map(|(input, prf_of_match_key)| {
-            let OPRFIPAInputRow {
-                match_key: _,
-                is_trigger,
-                breakdown_key,
-                trigger_value,
-                timestamp,
-            } = &input;
-
-            PrfShardedIpaInputRow {
-                prf_of_match_key,
-                is_trigger_bit: is_trigger.clone(),
-                breakdown_key: breakdown_key.clone(),
-                trigger_value: trigger_value.clone(),
-                timestamp: timestamp.clone(),
-                sort_key: Replicated::ZERO,
-            }
-        })
+    Ok(prf_of_match_keys
+        .into_iter()
+        .flatten()
+        .take(input_rows.len())
         .collect())
 }
 
@@ -522,7 +558,7 @@ pub mod tests {
             boolean_array::{BA16, BA20, BA3, BA5, BA8},
             U128Conversions,
         },
-        helpers::query::DpMechanism,
+        helpers::query::{AttributionModel, DpMechanism},
         protocol::{
             dp::NoiseParams,
             ipa_prf::{oprf_ipa, oprf_padding::PaddingParameters},
@@ -575,10 +611,12 @@ pub mod tests {
 
             let mut result: Vec<_> = world
                 .semi_honest(records.into_iter(), |ctx, input_rows| async move {
-                    oprf_ipa::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                    oprf_ipa::<_, BA5, BA3, BA16, BA20, 32>(
                         ctx,
                         input_rows,
+                        32,
                         None,
+                        AttributionModel::LastTouch,
                         dp_params,
                         padding_params,
                     )
@@ -615,10 +653,12 @@ pub mod tests {
 
             let mut result: Vec<_> = world
                 .malicious(records.into_iter(), |ctx, input_rows| async move {
-                    oprf_ipa::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                    oprf_ipa::<_, BA5, BA3, BA16, BA20, 32>(
                         ctx,
                         input_rows,
+                        32,
                         None,
+                        AttributionModel::LastTouch,
                         dp_params,
                         padding_params,
                     )
@@ -637,29 +677,34 @@ pub mod tests {
 
     #[test]
     fn semi_honest_with_dp() {
-        const SS_BITS: usize = 1;
-        // setting SS_BITS this small will cause clipping in capping
-        // since per_user_credit_cap == 2^SS_BITS
-        semi_honest_with_dp_internal::<SS_BITS>(DpMechanism::DiscreteLaplace { epsilon: 5.0 });
+        // setting the cap this small will cause clipping in capping
+        semi_honest_with_dp_internal(2, DpMechanism::DiscreteLaplace { epsilon: 5.0 });
     }
     #[test]
     fn semi_honest_with_dp_slow() {
-        const SS_BITS: usize = 6;
         if std::env::var("EXEC_SLOW_TESTS").is_err() {
             return;
         }
-        semi_honest_with_dp_internal::<SS_BITS>(DpMechanism::Binomial { epsilon: 10.0 });
+        semi_honest_with_dp_internal(
+            64,
+            DpMechanism::Binomial {
+                epsilon: 10.0,
+                delta: 1e-6,
+            },
+        );
     }
 
-    fn semi_honest_with_dp_internal<const SS_BITS: usize>(_dp_mechanism: DpMechanism) {
+    fn semi_honest_with_dp_internal(per_user_credit_cap: u32, _dp_mechanism: DpMechanism) {
         // TODO match on DpMechanism but get error if try to move into run
         println!("Running semi_honest_with_dp");
-        run(move || async {
+        run(move || async move {
             const B: usize = 32; // number of histogram bins
             let expected: Vec<u32> = vec![0, 2, 5, 0, 0, 0, 0, 0];
             let epsilon = 10.0;
-            let dp_params = DpMechanism::Binomial { epsilon };
-            let per_user_credit_cap = 2_f64.powi(i32::try_from(SS_BITS).unwrap());
+            let dp_params = DpMechanism::Binomial {
+                epsilon,
+                delta: 1e-6,
+            };
             let padding_params = PaddingParameters::relaxed();
             let config = TestWorldConfig::default().with_timeout_secs(60);
             let world = TestWorld::<NotSharded>::with_config(&config);
@@ -673,10 +718,12 @@ pub mod tests {
             ];
             let mut result: Vec<_> = world
                 .semi_honest(records.into_iter(), |ctx, input_rows| async move {
-                    oprf_ipa::<_, BA5, BA3, BA16, BA20, SS_BITS, B>(
+                    oprf_ipa::<_, BA5, BA3, BA16, BA20, B>(
                         ctx,
                         input_rows,
+                        per_user_credit_cap,
                         None,
+                        AttributionModel::LastTouch,
                         dp_params,
                         padding_params,
                     )
@@ -689,9 +736,9 @@ pub mod tests {
 
             let noise_params = NoiseParams {
                 epsilon,
-                ell_1_sensitivity: per_user_credit_cap,
-                ell_2_sensitivity: per_user_credit_cap,
-                ell_infty_sensitivity: per_user_credit_cap,
+                ell_1_sensitivity: f64::from(per_user_credit_cap),
+                ell_2_sensitivity: f64::from(per_user_credit_cap),
+                ell_infty_sensitivity: f64::from(per_user_credit_cap),
                 dimensions: f64::from(u32::try_from(B).unwrap()),
                 ..Default::default()
             };
@@ -734,10 +781,12 @@ pub mod tests {
 
             let mut result: Vec<_> = world
                 .semi_honest(records.into_iter(), |ctx, input_rows| async move {
-                    oprf_ipa::<_, BA5, BA3, BA8, BA20, 5, 32>(
+                    oprf_ipa::<_, BA5, BA3, BA8, BA20, 32>(
                         ctx,
                         input_rows,
+                        32,
                         None,
+                        AttributionModel::LastTouch,
                         dp_params,
                         padding_params,
                     )
@@ -770,10 +819,12 @@ pub mod tests {
 
             let mut result: Vec<_> = world
                 .semi_honest(records.into_iter(), |ctx, input_rows| async move {
-                    oprf_ipa::<_, BA5, BA3, BA8, BA20, 5, 32>(
+                    oprf_ipa::<_, BA5, BA3, BA8, BA20, 32>(
                         ctx,
                         input_rows,
+                        32,
                         None,
+                        AttributionModel::LastTouch,
                         dp_params,
                         padding_params,
                     )
@@ -824,10 +875,12 @@ pub mod tests {
             let padding_params = PaddingParameters::no_padding();
             let mut result: Vec<_> = world
                 .semi_honest(records.into_iter(), |ctx, input_rows| async move {
-                    oprf_ipa::<_, BA8, BA3, BA16, BA20, 5, 256>(
+                    oprf_ipa::<_, BA8, BA3, BA16, BA20, 256>(
                         ctx,
                         input_rows,
+                        32,
                         None,
+                        AttributionModel::LastTouch,
                         dp_params,
                         padding_params,
                     )
@@ -941,10 +994,12 @@ mod compact_gate_tests {
 
             let mut result: Vec<_> = world
                 .semi_honest(records.into_iter(), |ctx, input_rows| async move {
-                    oprf_ipa::<_, BA5, BA8, BA8, BA20, 5, 32>(
+                    oprf_ipa::<_, BA5, BA8, BA8, BA20, 32>(
                         ctx,
                         input_rows,
+                        32,
                         None,
+                        AttributionModel::LastTouch,
                         dp_params,
                         padding_params,
                     )
@@ -2,11 +2,16 @@ pub(crate) mod distributions;
 pub mod insecure;
 pub mod step;
 
-use std::iter::{repeat, repeat_with};
+use std::{
+    fmt::{Display, Formatter},
+    iter::{repeat_n, repeat_with},
+    str::FromStr,
+};
 
 #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
 pub use insecure::DiscreteDp as InsecureDiscreteDp;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use tokio::try_join;
 
 use crate::{
@@ -17,7 +22,10 @@ use crate::{
         boolean_array::{BooleanArray, BA32, BA64},
         U128Conversions,
     },
-    helpers::{Direction, Role, TotalRecords},
+    helpers::{
+        query::{parse_parameter_value, parse_parameters},
+        Direction, Role, TotalRecords,
+    },
     protocol::{
         context::{prss::InstrumentedSequentialSharedRandomness, Context},
         ipa_prf::{
@@ -26,6 +34,7 @@ use crate::{
                 step::{PaddingDpStep, SendTotalRows},
             },
             prf_sharding::AttributionOutputs,
+            walr::WalrInputRow,
             OPRFIPAInputRow,
         },
         RecordId,
@@ -38,13 +47,16 @@ use crate::{
 };
 
 /// Parameter struct for padding parameters.
-#[derive(Default, Copy, Clone, Debug)]
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
 pub struct PaddingParameters {
     pub aggregation_padding: AggregationPadding,
     pub oprf_padding: OPRFPadding,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// Aggregation padding is carried in query configurations as a string, either `none` or
+/// `epsilon=<f64>,delta=<f64>,sensitivity=<u32>`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub enum AggregationPadding {
     NoAggPadding,
     Parameters {
@@ -54,7 +66,10 @@ pub enum AggregationPadding {
     },
 }
 
-#[derive(Copy, Clone, Debug)]
+/// OPRF padding is carried in query configurations as a string, either `none` or
+/// `epsilon=<f64>,delta=<f64>,cardinality_cap=<u32>,sensitivity=<u32>`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub enum OPRFPadding {
     NoOPRFPadding,
     Parameters {
@@ -87,6 +102,108 @@ impl Default for OPRFPadding {
     }
 }
 
+const NO_PADDING_STR: &str = "none";
+
+impl FromStr for AggregationPadding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == NO_PADDING_STR {
+            return Ok(Self::NoAggPadding);
+        }
+
+        let keys = ["epsilon", "delta", "sensitivity"];
+        let [epsilon, delta, sensitivity] = parse_parameters(s, keys)?;
+        Ok(Self::Parameters {
+            aggregation_epsilon: parse_parameter_value(keys[0], epsilon)?,
+            aggregation_delta: parse_parameter_value(keys[1], delta)?,
+            aggregation_padding_sensitivity: parse_parameter_value(keys[2], sensitivity)?,
+        })
+    }
+}
+
+impl Display for AggregationPadding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoAggPadding => f.write_str(NO_PADDING_STR),
+            Self::Parameters {
+                aggregation_epsilon,
+                aggregation_delta,
+                aggregation_padding_sensitivity,
+            } => write!(
+                f,
+                "epsilon={aggregation_epsilon},delta={aggregation_delta},\
+                 sensitivity={aggregation_padding_sensitivity}"
+            ),
+        }
+    }
+}
+
+impl TryFrom<String> for AggregationPadding {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<AggregationPadding> for String {
+    fn from(value: AggregationPadding) -> Self {
+        value.to_string()
+    }
+}
+
+impl FromStr for OPRFPadding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == NO_PADDING_STR {
+            return Ok(Self::NoOPRFPadding);
+        }
+
+        let keys = ["epsilon", "delta", "cardinality_cap", "sensitivity"];
+        let [epsilon, delta, cardinality_cap, sensitivity] = parse_parameters(s, keys)?;
+        Ok(Self::Parameters {
+            oprf_epsilon: parse_parameter_value(keys[0], epsilon)?,
+            oprf_delta: parse_parameter_value(keys[1], delta)?,
+            matchkey_cardinality_cap: parse_parameter_value(keys[2], cardinality_cap)?,
+            oprf_padding_sensitivity: parse_parameter_value(keys[3], sensitivity)?,
+        })
+    }
+}
+
+impl Display for OPRFPadding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoOPRFPadding => f.write_str(NO_PADDING_STR),
+            Self::Parameters {
+                oprf_epsilon,
+                oprf_delta,
+                matchkey_cardinality_cap,
+                oprf_padding_sensitivity,
+            } => write!(
+                f,
+                "epsilon={oprf_epsilon},delta={oprf_delta},\
+                 cardinality_cap={matchkey_cardinality_cap},sensitivity={oprf_padding_sensitivity}"
+            ),
+        }
+    }
+}
+
+impl TryFrom<String> for OPRFPadding {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<OPRFPadding> for String {
+    fn from(value: OPRFPadding) -> Self {
+        value.to_string()
+    }
+}
+
 impl PaddingParameters {
     #[must_use]
     pub fn relaxed() -> Self {
@@ -112,6 +229,43 @@ impl PaddingParameters {
             oprf_padding: OPRFPadding::NoOPRFPadding,
         }
     }
+
+    /// Flattens the parameters into a fixed number of words, so helpers can compare them.
+    fn to_words(self) -> [u64; 9] {
+        let oprf = match self.oprf_padding {
+            OPRFPadding::NoOPRFPadding => [0; 5],
+            OPRFPadding::Parameters {
+                oprf_epsilon,
+                oprf_delta,
+                matchkey_cardinality_cap,
+                oprf_padding_sensitivity,
+            } => [
+                1,
+                oprf_epsilon.to_bits(),
+                oprf_delta.to_bits(),
+                u64::from(matchkey_cardinality_cap),
+                u64::from(oprf_padding_sensitivity),
+            ],
+        };
+        let aggregation = match self.aggregation_padding {
+            AggregationPadding::NoAggPadding => [0; 4],
+            AggregationPadding::Parameters {
+                aggregation_epsilon,
+                aggregation_delta,
+                aggregation_padding_sensitivity,
+            } => [
+                1,
+                aggregation_epsilon.to_bits(),
+                aggregation_delta.to_bits(),
+                u64::from(aggregation_padding_sensitivity),
+            ],
+        };
+
+        let mut words = [0; 9];
+        words[..5].copy_from_slice(&oprf);
+        words[5..].copy_from_slice(&aggregation);
+        words
+    }
 }
 
 /// Paddable trait to support generation of padding for both `OPRFIPAInputRow`s and `AttributionOutputs`
@@ -168,13 +322,15 @@ where
                     padding_input_rows.extend(
                         repeat_with(|| {
                             let dummy_mk: BA64 = rng.gen();
-                            repeat(IndistinguishableHybridReport::from(
-                                AdditiveShare::new_excluding_direction(
-                                    dummy_mk,
-                                    direction_to_excluded_helper,
+                            repeat_n(
+                                IndistinguishableHybridReport::from(
+                                    AdditiveShare::new_excluding_direction(
+                                        dummy_mk,
+                                        direction_to_excluded_helper,
+                                    ),
                                 ),
-                            ))
-                            .take(cardinality as usize)
+                                cardinality as usize,
+                            )
                         })
                         // this means there will be `sample` many unique
                         // matchkeys to add each with cardinality = `cardinality`
@@ -193,14 +349,14 @@ where
         padding_input_rows: &mut VC,
         total_number_of_fake_rows: u32,
     ) {
-        padding_input_rows.extend(
-            repeat(IndistinguishableHybridReport::<BK, V>::ZERO)
-                .take(total_number_of_fake_rows as usize),
-        );
+        padding_input_rows.extend(repeat_n(
+            IndistinguishableHybridReport::<BK, V>::ZERO,
+            total_number_of_fake_rows as usize,
+        ));
     }
 }
 
-impl<BK, V> Paddable for IndistinguishableHybridReport<BK, V, ()>
+impl<BK, V> Paddable for IndistinguishableHybridReport<BK, V, (), (), ()>
 where
     BK: BooleanArray + U128Conversions,
     V: BooleanArray,
@@ -247,10 +403,12 @@ where
                             ),
                         };
 
-                        let row = IndistinguishableHybridReport::<BK, V, ()> {
+                        let row = IndistinguishableHybridReport::<BK, V, (), (), ()> {
                             match_key: (),
                             value: AdditiveShare::new(V::ZERO, V::ZERO),
                             breakdown_key: breakdownkey_shares,
+                            timestamp: (),
+                            is_impression: (),
                         };
 
                         padding_input_rows.extend(std::iter::once(row));
@@ -267,10 +425,10 @@ where
         padding_input_rows: &mut VC,
         total_number_of_fake_rows: u32,
     ) {
-        padding_input_rows.extend(
-            repeat(IndistinguishableHybridReport::<BK, V, ()>::ZERO)
-                .take(total_number_of_fake_rows as usize),
-        );
+        padding_input_rows.extend(repeat_n(
+            IndistinguishableHybridReport::<BK, V, (), (), ()>::ZERO,
+            total_number_of_fake_rows as usize,
+        ));
     }
 }
 
@@ -344,6 +502,62 @@ where
     }
 }
 
+impl<FV, TS, const N: usize> Paddable for WalrInputRow<FV, TS, N>
+where
+    FV: BooleanArray,
+    TS: BooleanArray,
+{
+    /// Dummy rows are source events with a random `match_key` and an all-zero feature vector,
+    /// so they never contribute to the gradient.
+    fn add_padding_items<V: Extend<Self>, const B: usize>(
+        direction_to_excluded_helper: Direction,
+        padding_input_rows: &mut V,
+        padding_params: &PaddingParameters,
+        rng: &mut InstrumentedSequentialSharedRandomness,
+    ) -> Result<u32, Error> {
+        let mut total_number_of_fake_rows = 0;
+        match padding_params.oprf_padding {
+            OPRFPadding::NoOPRFPadding => {}
+            OPRFPadding::Parameters {
+                oprf_epsilon,
+                oprf_delta,
+                matchkey_cardinality_cap,
+                oprf_padding_sensitivity,
+            } => {
+                let oprf_padding =
+                    OPRFPaddingDp::new(oprf_epsilon, oprf_delta, oprf_padding_sensitivity)?;
+                for cardinality in 1..=matchkey_cardinality_cap {
+                    let sample = oprf_padding.sample(rng);
+                    total_number_of_fake_rows += sample * cardinality;
+
+                    for _ in 0..sample {
+                        let dummy_mk: BA64 = rng.gen();
+                        for _ in 0..cardinality {
+                            let match_key = match direction_to_excluded_helper {
+                                Direction::Left => AdditiveShare::new(BA64::ZERO, dummy_mk),
+                                Direction::Right => AdditiveShare::new(dummy_mk, BA64::ZERO),
+                            };
+                            padding_input_rows.extend(std::iter::once(WalrInputRow {
+                                match_key,
+                                ..WalrInputRow::default()
+                            }));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(total_number_of_fake_rows)
+    }
+
+    fn add_zero_shares<V: Extend<Self>>(
+        padding_input_rows: &mut V,
+        total_number_of_fake_rows: u32,
+    ) {
+        padding_input_rows
+            .extend(repeat_with(WalrInputRow::default).take(total_number_of_fake_rows as usize));
+    }
+}
+
 impl<BK, TV> Paddable for AttributionOutputs<AdditiveShare<BK>, AdditiveShare<TV>>
 where
     BK: BooleanArray + U128Conversions,
@@ -547,10 +761,63 @@ where
     Ok(input)
 }
 
+/// Checks that every helper runs the query with the same padding parameters. Padding parameters
+/// come from the query configuration, so a leader that sent different configurations to its
+/// peers would otherwise go unnoticed. Each helper sends its parameters to both peers and
+/// compares what it receives with its own.
+///
+/// ## Errors
+/// [`Error::InconsistentPaddingParameters`] if any of the peers was configured differently, or
+/// any error that occurred while communicating with them.
+pub async fn validate_padding_parameters<C: Context>(
+    ctx: C,
+    padding_params: &PaddingParameters,
+) -> Result<(), Error> {
+    let words = padding_params.to_words();
+    let ctx = ctx.set_total_records(TotalRecords::specified(words.len())?);
+
+    let send = |direction| {
+        let channel = ctx.send_channel::<BA64>(ctx.role().peer(direction));
+        async move {
+            for (i, word) in words.iter().enumerate() {
+                channel
+                    .send(RecordId::from(i), BA64::truncate_from(u128::from(*word)))
+                    .await?;
+            }
+            Ok::<_, Error>(())
+        }
+    };
+    let receive = |direction| {
+        let channel = ctx.recv_channel::<BA64>(ctx.role().peer(direction));
+        async move {
+            let mut consistent = true;
+            for (i, word) in words.iter().enumerate() {
+                let received = channel.receive(RecordId::from(i)).await?;
+                consistent &= received.as_u128() == u128::from(*word);
+            }
+            Ok::<_, Error>(consistent)
+        }
+    };
+
+    let ((), (), from_left, from_right) = try_join!(
+        send(Direction::Left),
+        send(Direction::Right),
+        receive(Direction::Left),
+        receive(Direction::Right),
+    )?;
+    if from_left && from_right {
+        Ok(())
+    } else {
+        Err(Error::InconsistentPaddingParameters)
+    }
+}
+
 #[cfg(all(test, unit_test))]
 mod tests {
     use std::collections::{BTreeMap, HashMap};
 
+    use futures::future::join_all;
+
     use crate::{
         error::Error,
         ff::{
@@ -562,8 +829,9 @@ mod tests {
             context::Context,
             ipa_prf::{
                 oprf_padding::{
-                    apply_dp_padding_pass, insecure, insecure::OPRFPaddingDp, AggregationPadding,
-                    OPRFPadding, PaddingParameters,
+                    apply_dp_padding_pass, insecure, insecure::OPRFPaddingDp,
+                    validate_padding_parameters, AggregationPadding, OPRFPadding,
+                    PaddingParameters,
                 },
                 prf_sharding::{tests::PreAggregationTestOutputInDecimal, AttributionOutputs},
                 OPRFIPAInputRow,
@@ -1034,4 +1302,67 @@ mod tests {
         let value_h3 = result[2].as_ref().expect("Failed to get result for H3");
         assert_eq!(value_h1, value_h3, "H1 and H3 should agree");
     }
+
+    #[test]
+    fn padding_string_round_trip() {
+        for params in [
+            PaddingParameters::default(),
+            PaddingParameters::relaxed(),
+            PaddingParameters::no_padding(),
+        ] {
+            let oprf = params.oprf_padding.to_string();
+            assert_eq!(params.oprf_padding, oprf.parse::<OPRFPadding>().unwrap());
+            let aggregation = params.aggregation_padding.to_string();
+            assert_eq!(
+                params.aggregation_padding,
+                aggregation.parse::<AggregationPadding>().unwrap()
+            );
+        }
+
+        assert_eq!(
+            OPRFPadding::Parameters {
+                oprf_epsilon: 10.0,
+                oprf_delta: 1e-4,
+                matchkey_cardinality_cap: 3,
+                oprf_padding_sensitivity: 2,
+            },
+            "sensitivity=2, epsilon=10,cardinality_cap=3,delta=0.0001"
+                .parse()
+                .unwrap()
+        );
+        assert!("epsilon=1,delta=0.1".parse::<OPRFPadding>().is_err());
+        assert!("epsilon=1,delta=0.1,sensitivity=x"
+            .parse::<AggregationPadding>()
+            .is_err());
+        assert!("epsilon=1,delta=0.1,sensitivity=2,epsilon=2"
+            .parse::<AggregationPadding>()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn padding_parameters_agree() {
+        let world = TestWorld::default();
+        let results = join_all(world.contexts().map(|ctx| async move {
+            validate_padding_parameters(ctx, &PaddingParameters::relaxed()).await
+        }))
+        .await;
+        assert!(results.iter().all(Result::is_ok));
+    }
+
+    #[tokio::test]
+    async fn padding_parameters_disagree() {
+        let world = TestWorld::default();
+        let results = join_all(world.contexts().map(|ctx| async move {
+            let padding_params = if ctx.role() == Role::H2 {
+                PaddingParameters::relaxed()
+            } else {
+                PaddingParameters::default()
+            };
+            validate_padding_parameters(ctx, &padding_params).await
+        }))
+        .await;
+        assert!(results
+            .iter()
+            .all(|r| matches!(r, Err(Error::InconsistentPaddingParameters))));
+    }
 }
@@ -1,36 +1,44 @@
 use std::{
+    cmp::min,
     convert::Infallible,
     iter::{repeat_n, zip},
 };
 
 use futures::stream;
-use futures_util::{future::try_join, stream::unfold, Stream, StreamExt};
+use futures_util::{future::try_join, stream::unfold, Stream, StreamExt, TryStreamExt};
+use ipa_step::StepNarrow;
 
 use crate::{
-    error::{Error, LengthError, UnwrapInfallible},
+    error::{Error, UnwrapInfallible},
     ff::{boolean::Boolean, boolean_array::BooleanArray, Field, U128Conversions},
     helpers::{stream::TryFlattenItersExt, TotalRecords},
     protocol::{
         basics::{SecureMul, ShareKnownValue},
         boolean::{and::bool_and_8_bit, or::or},
-        context::Context,
+        context::{
+            dzkp_validator::{DZKPValidator, TARGET_PROOF_SIZE},
+            Context, DZKPUpgraded, MaliciousProtocolSteps, UpgradableContext,
+        },
         ipa_prf::{
-            aggregation::aggregate_values,
-            prf_sharding::step::{FeatureLabelDotProductStep as Step, UserNthRowStep},
+            aggregation::{aggregate_values, aggregate_values_proof_chunk, AGGREGATE_DEPTH},
+            prf_sharding::step::{
+                FeatureLabelDotProductPerRowStep as RowStep, FeatureLabelDotProductStep as Step,
+                FeatureLabelDotProductUserNthRowStep,
+            },
         },
-        BooleanProtocols, RecordId,
+        BooleanProtocols, Gate, RecordId,
     },
     secret_sharing::{
-        replicated::semi_honest::{AdditiveShare as Replicated, AdditiveShare},
-        BitDecomposed, FieldSimd, SharedValue, TransposeFrom,
+        replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, FieldSimd,
+        SharedValue, TransposeFrom,
     },
-    seq_join::seq_join,
+    utils::non_zero_prev_power_of_two,
 };
 
 pub struct PrfShardedIpaInputRow<FV: SharedValue, const B: usize> {
-    prf_of_match_key: u64,
-    is_trigger_bit: Replicated<Boolean>,
-    feature_vector: [Replicated<FV>; B],
+    pub prf_of_match_key: u64,
+    pub is_trigger_bit: Replicated<Boolean>,
+    pub feature_vector: [Replicated<FV>; B],
 }
 
 struct InputsRequiredFromPrevRow {
@@ -75,14 +83,14 @@ impl InputsRequiredFromPrevRow {
 
         let (ever_encountered_a_trigger_event, did_source_get_attributed) = try_join(
             or(
-                ctx.narrow(&Step::EverEncounteredTriggerEvent),
+                ctx.narrow(&RowStep::EverEncounteredTriggerEvent),
                 record_id,
                 &input_row.is_trigger_bit,
                 &self.ever_encountered_a_trigger_event,
             ),
             is_source_event.multiply(
                 &self.ever_encountered_a_trigger_event,
-                ctx.narrow(&Step::DidSourceReceiveAttribution),
+                ctx.narrow(&RowStep::DidSourceReceiveAttribution),
                 record_id,
             ),
         )
@@ -90,14 +98,14 @@ impl InputsRequiredFromPrevRow {
 
         let (updated_is_saturated, capped_label) = try_join(
             or(
-                ctx.narrow(&Step::ComputeSaturatingSum),
+                ctx.narrow(&RowStep::ComputeSaturatingSum),
                 record_id,
                 &self.is_saturated,
                 &did_source_get_attributed,
             ),
             did_source_get_attributed.multiply(
                 &(share_of_one - &self.is_saturated),
-                ctx.narrow(&Step::IsAttributedSourceAndPrevRowNotSaturated),
+                ctx.narrow(&RowStep::IsAttributedSourceAndPrevRowNotSaturated),
                 record_id,
             ),
         )
@@ -107,7 +115,7 @@ impl InputsRequiredFromPrevRow {
         let bit_decomposed_output =
             BitDecomposed::transposed_from(&input_row.feature_vector).unwrap_infallible();
         let capped_attributed_feature_vector = bool_and_8_bit(
-            ctx,
+            ctx.narrow(&RowStep::ComputeCappedFeatureVector),
             record_id,
             &bit_decomposed_output,
             repeat_n(&condition, FV::BITS.try_into().unwrap()),
@@ -133,7 +141,7 @@ where
         } else {
             let total_records = TotalRecords::specified(*num_users_having_that_row_number)?;
             let ctx_for_row_number = root_ctx
-                .narrow(&UserNthRowStep::from(row_number))
+                .narrow(&FeatureLabelDotProductUserNthRowStep::from(row_number))
                 .set_total_records(total_records);
             context_per_row_depth.push(ctx_for_row_number);
         }
@@ -155,16 +163,19 @@ where
 {
     unfold(Some((input_stream, first_row)), |state| async move {
         let (mut s, last_row) = state?;
-        let last_row_prf = last_row.prf_of_match_key;
+        let mut last_row_prf = last_row.prf_of_match_key;
         let mut current_chunk = vec![last_row];
         while let Some(row) = s.next().await {
             if row.prf_of_match_key == last_row_prf {
                 current_chunk.push(row);
-            } else {
+            } else if current_chunk.len() > 1 {
                 return Some((current_chunk, Some((s, row))));
+            } else {
+                last_row_prf = row.prf_of_match_key;
+                current_chunk = vec![row];
             }
         }
-        Some((current_chunk, None))
+        (current_chunk.len() > 1).then_some((current_chunk, None))
     })
 }
 
@@ -199,54 +210,174 @@ where
 ///   Then the data-structure that should be provided for the `users_having_n_records` is:
 ///     - [3, 3, 2, 2, 1, 1]
 ///
+/// The attribution circuit and the aggregation are both run under DZKP validation, so the
+/// output is a bit-decomposed vector of `HV::BITS` bits with one lane per feature.
+///
 /// # Errors
-/// Propagates errors from multiplications
+/// Propagates errors from multiplications and from validating them
 /// # Panics
-/// Propagates errors from multiplications
-pub async fn compute_feature_label_dot_product<'ctx, C, TV, HV, const B: usize>(
+/// If `users_having_n_records` does not describe `input_rows`.
+pub async fn compute_feature_label_dot_product<C, TV, HV, const B: usize>(
     sh_ctx: C,
     input_rows: Vec<PrfShardedIpaInputRow<TV, B>>,
     users_having_n_records: &[usize],
-) -> Result<[Replicated<HV>; B], Error>
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
 where
-    C: Context,
+    C: UpgradableContext,
     Boolean: FieldSimd<B>,
-    Replicated<Boolean>: SecureMul<C>,
-    Replicated<Boolean, B>: BooleanProtocols<C, B>,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
     TV: SharedValue,
     HV: BooleanArray + U128Conversions,
     BitDecomposed<Replicated<Boolean, B>>:
         for<'a> TransposeFrom<&'a [Replicated<TV>; B], Error = Infallible>,
-    Vec<Replicated<HV>>:
-        for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
 {
-    // Get the validator and context to use for Boolean multiplication operations
-    let binary_m_ctx = sh_ctx.narrow(&Step::BinaryValidator);
+    let empty_output = || {
+        BitDecomposed::new(repeat_n(
+            Replicated::<Boolean, B>::ZERO,
+            usize::try_from(HV::BITS).unwrap(),
+        ))
+    };
 
     // Tricky hacks to work around the limitations of our current infrastructure
     // There will be 0 outputs for users with just one row.
     // There will be 1 output for users with at least 2 rows.
     // So we just use the number of users having at least 2 rows.
-    let num_outputs = users_having_n_records[1];
-    let ctx_for_row_number = set_up_contexts(&binary_m_ctx, users_having_n_records)?;
+    let Some(&num_outputs) = users_having_n_records.get(1) else {
+        return Ok(empty_output());
+    };
+
+    // Record IDs count users. The attribution circuit is only evaluated for the second and
+    // subsequent rows of each user.
+    let chunk_size = TARGET_PROOF_SIZE
+        / ((users_having_n_records.len() - 1) * multiplications_per_row::<TV, B>());
+    let mut dzkp_validator = sh_ctx.clone().dzkp_validator(
+        MaliciousProtocolSteps {
+            protocol: &Step::Attribute,
+            validate: &Step::AttributeValidate,
+        },
+        min(
+            sh_ctx.active_work().get(),
+            non_zero_prev_power_of_two(chunk_size),
+        ),
+    );
+    dzkp_validator.set_total_records(TotalRecords::specified(num_outputs)?);
+    let ctx_for_row_number = set_up_contexts(&dzkp_validator.context(), users_having_n_records)?;
 
     // Chunk the incoming stream of records into stream of vectors of records with the same PRF
     let mut input_stream = stream::iter(input_rows);
     let Some(first_row) = input_stream.next().await else {
-        return Ok([Replicated::<HV>::ZERO; B]);
+        return Ok(empty_output());
     };
     let rows_chunked_by_user = chunk_rows_by_user(input_stream, first_row);
 
     let mut collected = rows_chunked_by_user.collect::<Vec<_>>().await;
     collected.sort_by(|a, b| std::cmp::Ord::cmp(&b.len(), &a.len()));
 
+    let intermediate_results = attribute::<_, TV, B>(dzkp_validator, ctx_for_row_number, collected)
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    aggregate_contributions::<_, HV, _, B>(
+        sh_ctx,
+        intermediate_results,
+        usize::try_from(TV::BITS).unwrap(),
+        Step::aggregate,
+        Step::aggregate_validate,
+    )
+    .await
+}
+
+/// Sums the per-user or per-row contributions to a vector with `B` coordinates, each of them
+/// `contribution_bits` wide. The output is `HV::BITS` wide and saturates on overflow.
+///
+/// Aggregation proceeds in layers, the same way as in `breakdown_reveal_aggregation`.
+/// Each chunk is validated with a single proof, so the batch size must be `usize::MAX`
+/// and record IDs are tracked across chunks.
+pub(super) async fn aggregate_contributions<C, HV, S, const B: usize>(
+    sh_ctx: C,
+    mut intermediate_results: Vec<BitDecomposed<Replicated<Boolean, B>>>,
+    contribution_bits: usize,
+    aggregate_step: fn(usize) -> S,
+    validate_step: fn(usize) -> S,
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    C: UpgradableContext,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+    HV: BooleanArray + U128Conversions,
+    S: ipa_step::Step,
+    Gate: StepNarrow<S>,
+{
+    if intermediate_results.is_empty() {
+        return Ok(BitDecomposed::new(repeat_n(
+            Replicated::<Boolean, B>::ZERO,
+            usize::try_from(HV::BITS).unwrap(),
+        )));
+    }
+
+    let mut depth = 0;
+    let agg_proof_chunk = aggregate_values_proof_chunk(B, contribution_bits);
+
+    while intermediate_results.len() > 1 {
+        let mut record_ids = [RecordId::FIRST; AGGREGATE_DEPTH];
+        let mut next_intermediate_results = Vec::new();
+        for (chunk_counter, chunk) in intermediate_results.chunks(agg_proof_chunk).enumerate() {
+            let chunk_len = chunk.len();
+            let validator = sh_ctx.clone().dzkp_validator(
+                MaliciousProtocolSteps {
+                    protocol: &aggregate_step(depth),
+                    validate: &validate_step(depth),
+                },
+                usize::MAX,
+            );
+            let result = aggregate_values::<_, HV, B>(
+                validator.context(),
+                stream::iter(chunk).map(|v| Ok(v.clone())).boxed(),
+                chunk_len,
+                Some(&mut record_ids),
+            )
+            .await?;
+            validator.validate_indexed(chunk_counter).await?;
+            next_intermediate_results.push(result);
+        }
+        depth += 1;
+        intermediate_results = next_intermediate_results;
+    }
+
+    let mut result = intermediate_results.into_iter().next().unwrap();
+
+    // A single contribution, or too few contributions to carry into the top bits,
+    // produce a short output, so pad it to the full width.
+    result.resize(
+        usize::try_from(HV::BITS).unwrap(),
+        Replicated::<Boolean, B>::ZERO,
+    );
+
+    Ok(result)
+}
+
+fn attribute<'ctx, V, FV, const B: usize>(
+    dzkp_validator: V,
+    contexts: Vec<V::Context>,
+    input: Vec<Vec<PrfShardedIpaInputRow<FV, B>>>,
+) -> impl Stream<Item = Result<BitDecomposed<Replicated<Boolean, B>>, Error>> + Send + 'ctx
+where
+    V: DZKPValidator + 'ctx,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean>: SecureMul<V::Context>,
+    Replicated<Boolean, B>: BooleanProtocols<V::Context, B>,
+    FV: SharedValue,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<FV>; B], Error = Infallible>,
+{
     let chunked_user_results =
-        collected
+        input
             .into_iter()
             .enumerate()
-            .map(|(record_id, rows_for_user)| {
+            .map(move |(record_id, rows_for_user)| {
                 let num_user_rows = rows_for_user.len();
-                let contexts = ctx_for_row_number[..num_user_rows - 1].to_owned();
+                let contexts = contexts[..num_user_rows - 1].to_owned();
 
                 evaluate_per_user_attribution_circuit(
                     contexts,
@@ -257,16 +388,16 @@ where
 
     // Execute all of the async futures (sequentially), and flatten the result
     // The call to `try_flatten_iters` only serves to eliminate the "Option" wrapping, and filter out `None` elements
-    let flattened_stream = Box::pin(
-        seq_join(sh_ctx.active_work(), stream::iter(chunked_user_results)).try_flatten_iters(),
-    );
-    let aggregated_result: BitDecomposed<AdditiveShare<Boolean, B>> =
-        aggregate_values::<_, HV, B>(binary_m_ctx, flattened_stream, num_outputs, None).await?;
-
-    let transposed_aggregated_result: Vec<Replicated<HV>> =
-        Vec::transposed_from(&aggregated_result)?;
+    dzkp_validator
+        .validated_seq_join::<_, _, Option<BitDecomposed<_>>>(stream::iter(chunked_user_results))
+        .try_flatten_iters()
+}
 
-    Ok(transposed_aggregated_result.try_into().unwrap())
+/// Returns the number of Boolean multiplications per input row, for use in sizing the DZKP
+/// batches. Four single-bit multiplications update the attribution state, and then every bit
+/// of the feature vector is multiplied by the capped label.
+fn multiplications_per_row<FV: SharedValue, const B: usize>() -> usize {
+    4 + usize::try_from(FV::BITS).unwrap() * B
 }
 
 async fn evaluate_per_user_attribution_circuit<'ctx, C, FV, const B: usize>(
@@ -343,6 +474,7 @@ pub mod tests {
         rand::Rng,
         secret_sharing::{
             replicated::semi_honest::AdditiveShare as Replicated, IntoShares, SharedValue,
+            TransposeFrom,
         },
         test_executor::run,
         test_fixture::{Reconstruct, Runner, TestWorld},
@@ -411,63 +543,108 @@ pub mod tests {
 
     const ZERO_FEATURES: [u8; 32] = [0; 32];
 
+    /// Returns the test records along with the expected dot product and the
+    /// `users_having_n_records` histogram that describes them.
+    fn test_records() -> (
+        Vec<PreShardedAndSortedOPRFTestInput<BA8, 32>>,
+        Vec<u128>,
+        Vec<usize>,
+    ) {
+        let mut rng = thread_rng();
+        let attributed_features: [[u8; 32]; 3] =
+            [[rng.gen(); 32], [rng.gen(); 32], [rng.gen(); 32]];
+
+        let records: Vec<PreShardedAndSortedOPRFTestInput<BA8, 32>> = vec![
+            /* First User */
+            test_input(123, true, ZERO_FEATURES), // trigger
+            test_input(123, false, attributed_features[0]), // this source DOES receive attribution
+            test_input(123, true, ZERO_FEATURES), // trigger
+            test_input(123, false, [rng.gen(); 32]), // this source does not receive attribution (capped)
+            /* Second User */
+            test_input(234, true, ZERO_FEATURES), // trigger
+            test_input(234, false, attributed_features[1]), // this source DOES receive attribution
+            /* Third User */
+            test_input(345, true, ZERO_FEATURES), // trigger
+            test_input(345, true, ZERO_FEATURES), // trigger
+            test_input(345, true, ZERO_FEATURES), // trigger
+            test_input(345, true, ZERO_FEATURES), // trigger
+            test_input(345, false, attributed_features[2]), // this source DOES receive attribution
+            test_input(345, false, [rng.gen(); 32]), // this source does not receive attribution (capped)
+            test_input(345, true, ZERO_FEATURES),    // trigger
+            test_input(345, false, [rng.gen(); 32]), // this source does not receive attribution (capped)
+            /* Fourth User */
+            test_input(456, false, [rng.gen(); 32]), // this source does NOT receive any attribution because this user has no trigger events
+        ];
+
+        let expected = attributed_features
+            .into_iter()
+            .fold(vec![0_u128; 32], |mut acc, x| {
+                zip(acc.iter_mut(), x).for_each(|(a, b)| *a += u128::from(b));
+                acc
+            });
+
+        (records, expected, vec![4, 3, 2, 2, 1, 1, 1, 1])
+    }
+
     #[test]
     fn semi_honest() {
         run(|| async move {
             let world = TestWorld::default();
+            let (records, expected, users_having_n_records) = test_records();
 
-            let mut rng = thread_rng();
-            let attributed_features: [[u8; 32]; 3] =
-                [[rng.gen(); 32], [rng.gen(); 32], [rng.gen(); 32]];
-
-            let records: Vec<PreShardedAndSortedOPRFTestInput<BA8, 32>> = vec![
-                /* First User */
-                test_input(123, true, ZERO_FEATURES), // trigger
-                test_input(123, false, attributed_features[0]), // this source DOES receive attribution
-                test_input(123, true, ZERO_FEATURES),           // trigger
-                test_input(123, false, [rng.gen(); 32]), // this source does not receive attribution (capped)
-                /* Second User */
-                test_input(234, true, ZERO_FEATURES), // trigger
-                test_input(234, false, attributed_features[1]), // this source DOES receive attribution
-                /* Third User */
-                test_input(345, true, ZERO_FEATURES), // trigger
-                test_input(345, true, ZERO_FEATURES), // trigger
-                test_input(345, true, ZERO_FEATURES), // trigger
-                test_input(345, true, ZERO_FEATURES), // trigger
-                test_input(345, false, attributed_features[2]), // this source DOES receive attribution
-                test_input(345, false, [rng.gen(); 32]), // this source does not receive attribution (capped)
-                test_input(345, true, ZERO_FEATURES),    // trigger
-                test_input(345, false, [rng.gen(); 32]), // this source does not receive attribution (capped)
-                /* Fourth User */
-                test_input(456, false, [rng.gen(); 32]), // this source does NOT receive any attribution because this user has no trigger events
-            ];
-
-            let expected: [u128; 32] =
-                attributed_features
-                    .into_iter()
-                    .fold([0_u128; 32], |mut acc, x| {
-                        zip(acc.iter_mut(), x).for_each(|(a, b)| *a += u128::from(b));
-                        acc
-                    });
-
-            let users_having_n_records = vec![4, 3, 2, 2, 1, 1, 1, 1];
-
-            let result = world
-                .dzkp_semi_honest(records.into_iter(), |ctx, input_rows| {
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .semi_honest(records.into_iter(), |ctx, input_rows| {
                     let h = users_having_n_records.as_slice();
                     async move {
-                        compute_feature_label_dot_product::<_, BA8, BA16, 32>(ctx, input_rows, h)
+                        Vec::transposed_from(
+                            &compute_feature_label_dot_product::<_, BA8, BA16, 32>(
+                                ctx, input_rows, h,
+                            )
                             .await
-                            .unwrap()
+                            .unwrap(),
+                        )
+                        .unwrap()
                     }
                 })
-                .await
+                .await;
+
+            let result = result
                 .reconstruct()
                 .iter()
                 .map(U128Conversions::as_u128)
                 .collect::<Vec<_>>();
+            assert_eq!(result, expected);
+        });
+    }
+
+    #[test]
+    fn malicious() {
+        run(|| async move {
+            let world = TestWorld::default();
+            let (records, expected, users_having_n_records) = test_records();
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .malicious(records.into_iter(), |ctx, input_rows| {
+                    let h = users_having_n_records.as_slice();
+                    async move {
+                        Vec::transposed_from(
+                            &compute_feature_label_dot_product::<_, BA8, BA16, 32>(
+                                ctx, input_rows, h,
+                            )
+                            .await
+                            .unwrap(),
+                        )
+                        .unwrap()
+                    }
+                })
+                .await;
 
-            assert_eq!(&result, &expected);
+            let result = result
+                .reconstruct()
+                .iter()
+                .map(U128Conversions::as_u128)
+                .collect::<Vec<_>>();
+            assert_eq!(result, expected);
         });
     }
 }
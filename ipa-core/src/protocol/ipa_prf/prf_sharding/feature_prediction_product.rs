@@ -0,0 +1,455 @@
+use std::{
+    cmp::min,
+    convert::Infallible,
+    iter::{repeat_n, zip},
+    ops::Not,
+};
+
+use futures::stream;
+use futures_util::TryStreamExt;
+
+use crate::{
+    error::{Error, UnwrapInfallible},
+    ff::{boolean::Boolean, boolean_array::BooleanArray, ArrayAccess, Field, U128Conversions},
+    helpers::TotalRecords,
+    protocol::{
+        basics::ShareKnownValue,
+        boolean::{
+            and::bool_and_8_bit,
+            or::or,
+            step::{SixteenBitStep, ThirtyTwoBitStep},
+        },
+        context::{
+            dzkp_validator::{validated_seq_join, DZKPValidator, TARGET_PROOF_SIZE},
+            Context, DZKPUpgraded, MaliciousProtocolSteps, UpgradableContext,
+        },
+        ipa_prf::{
+            boolean_ops::{
+                addition_sequential::integer_add,
+                comparison_and_subtraction_sequential::integer_sub, sigmoid::sigmoid,
+            },
+            prf_sharding::{
+                feature_label_dot_product::{aggregate_contributions, PrfShardedIpaInputRow},
+                step::{
+                    FeaturePredictionProductPerRowStep as RowStep,
+                    FeaturePredictionProductStep as Step,
+                },
+            },
+        },
+        BooleanProtocols, RecordId,
+    },
+    secret_sharing::{
+        replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, FieldSimd,
+        TransposeFrom,
+    },
+    utils::non_zero_prev_power_of_two,
+};
+
+/// Width of the dot product of a feature vector with the model weights. The dot product of
+/// 16 features of 8 bits with weights of at most 128 in magnitude needs 19 bits, plus a sign bit.
+const WEIGHTED_SUM_BITS: usize = 20;
+
+/// Width of the input of the sigmoid approximation.
+const SIGMOID_INPUT_BITS: usize = 8;
+
+/// Number of multiplications in the sigmoid approximation.
+const SIGMOID_MULTIPLICATIONS: usize = 31;
+
+/// Sub-protocol of the WALR protocol, computing the prediction term `X^T σ(Xw)` of the gradient
+/// of the logistic loss of the model `w`.
+///
+/// For every source event, the dot product of its feature vector with the model weights is
+/// computed, saturated to the input range of the [`sigmoid`] approximation, and the prediction
+/// is multiplied by the feature vector. Trigger events contribute nothing. The prediction term
+/// is the sum of these products over all rows.
+///
+/// The model weights are signed fixed-point numbers with 4 fractional bits, the scale of the
+/// sigmoid input, and features are integers. The predictions are in units of 1/256, so the
+/// output is the prediction term scaled by 256.
+///
+/// The rows can be in any order. The output is a bit-decomposed vector of `HV::BITS` bits with
+/// one lane per feature, and saturates on overflow.
+///
+/// # Errors
+/// Propagates errors from multiplications and from validating them
+/// # Panics
+/// If the features are wider than 8 bits, or if there are more than 16 of them.
+pub async fn compute_feature_prediction_product<C, FV, HV, const B: usize>(
+    sh_ctx: C,
+    input_rows: &[PrfShardedIpaInputRow<FV, B>],
+    model_weights: &[i8; B],
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    C: UpgradableContext,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+    FV: BooleanArray,
+    HV: BooleanArray + U128Conversions,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<FV>; B], Error = Infallible>,
+{
+    assert!(FV::BITS <= 8, "features must be at most 8 bits wide");
+    assert!(B <= 16, "at most 16 features are supported");
+
+    let products = if input_rows.is_empty() {
+        Vec::new()
+    } else {
+        let chunk_size = TARGET_PROOF_SIZE / multiplications_per_row::<B>();
+        let mut dzkp_validator = sh_ctx.clone().dzkp_validator(
+            MaliciousProtocolSteps {
+                protocol: &Step::Predict,
+                validate: &Step::PredictValidate,
+            },
+            min(
+                sh_ctx.active_work().get(),
+                non_zero_prev_power_of_two(chunk_size),
+            ),
+        );
+        let total_records = TotalRecords::specified(input_rows.len())?;
+        dzkp_validator.set_total_records(total_records);
+        let ctx = dzkp_validator.context().set_total_records(total_records);
+
+        validated_seq_join(
+            dzkp_validator,
+            stream::iter(input_rows.iter().enumerate().map(|(record_id, row)| {
+                predict_row::<_, FV, B>(ctx.clone(), RecordId::from(record_id), row, model_weights)
+            })),
+        )
+        .try_collect::<Vec<_>>()
+        .await?
+    };
+
+    aggregate_contributions::<_, HV, _, B>(
+        sh_ctx,
+        products,
+        usize::try_from(FV::BITS).unwrap() + SIGMOID_INPUT_BITS,
+        Step::aggregate,
+        Step::aggregate_validate,
+    )
+    .await
+}
+
+/// Returns an upper bound on the number of Boolean multiplications per input row, for use in
+/// sizing the DZKP batches.
+fn multiplications_per_row<const B: usize>() -> usize {
+    // Every bit of every weight set, plus the subtraction of the negative terms.
+    let weighted_sum = (8 * B + 1) * WEIGHTED_SUM_BITS;
+    // Saturating the dot product, evaluating the sigmoid and zeroing it for trigger events.
+    let prediction = (WEIGHTED_SUM_BITS - SIGMOID_INPUT_BITS - 1)
+        + SIGMOID_INPUT_BITS
+        + SIGMOID_MULTIPLICATIONS
+        + SIGMOID_INPUT_BITS;
+    // Multiplying the 8-bit feature vector by the prediction, one lane per feature.
+    let product = SIGMOID_INPUT_BITS * (8 + 16) * B;
+    weighted_sum + prediction + product
+}
+
+async fn predict_row<C, FV, const B: usize>(
+    ctx: C,
+    record_id: RecordId,
+    row: &PrfShardedIpaInputRow<FV, B>,
+    model_weights: &[i8; B],
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    C: Context,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean>: BooleanProtocols<C>,
+    Replicated<Boolean, B>: BooleanProtocols<C, B>,
+    FV: BooleanArray,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<FV>; B], Error = Infallible>,
+{
+    let dot_product = weighted_sum(&ctx, record_id, &row.feature_vector, model_weights).await?;
+    let prediction = sigmoid::<_, 1>(
+        ctx.narrow(&RowStep::Sigmoid),
+        record_id,
+        &saturate(&ctx, record_id, &dot_product).await?,
+    )
+    .await?;
+
+    let is_source_event = Replicated::share_known_value(&ctx, Boolean::ONE) - &row.is_trigger_bit;
+    let prediction = bool_and_8_bit::<_, _, 1>(
+        ctx.narrow(&RowStep::IsSourceEvent),
+        record_id,
+        &prediction,
+        repeat_n(&is_source_event, SIGMOID_INPUT_BITS),
+    )
+    .await?;
+
+    // Long multiplication of the feature vector by the prediction, which is the same for all
+    // lanes.
+    let features = BitDecomposed::transposed_from(&row.feature_vector).unwrap_infallible();
+    let partial_products = ctx
+        .parallel_join(
+            prediction.iter().enumerate().map(|(i, bit)| {
+                let ctx = ctx.narrow(&RowStep::MultiplyByPredictionBit(i));
+                let bit = bit.expand::<B>();
+                let features = &features;
+                async move {
+                    bool_and_8_bit(ctx, record_id, features, repeat_n(&bit, features.len())).await
+                }
+            }),
+        )
+        .await?;
+
+    let product_bits = features.len() + SIGMOID_INPUT_BITS;
+    let mut partial_products = partial_products.into_iter();
+    let mut product = partial_products.next().unwrap();
+    product.resize(product_bits, Replicated::ZERO);
+    for (i, partial_product) in partial_products.enumerate() {
+        let shifted = BitDecomposed::new(
+            repeat_n(Replicated::ZERO, i + 1).chain(partial_product),
+        );
+        (product, _) = integer_add::<_, SixteenBitStep, B>(
+            ctx.narrow(&RowStep::AddPartialProduct(i + 1)),
+            record_id,
+            &product,
+            &shifted,
+        )
+        .await?;
+    }
+
+    Ok(product)
+}
+
+/// Computes the dot product of the feature vector with the public model weights, as a
+/// two's complement number of [`WEIGHTED_SUM_BITS`] bits.
+///
+/// The terms of the positive and the negative weights are summed separately, by adding the
+/// features shifted by every bit set in the weight.
+async fn weighted_sum<C, FV, const B: usize>(
+    ctx: &C,
+    record_id: RecordId,
+    feature_vector: &[Replicated<FV>; B],
+    model_weights: &[i8; B],
+) -> Result<BitDecomposed<Replicated<Boolean>>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+    FV: BooleanArray,
+{
+    let mut positive = None;
+    let mut negative = None;
+    let mut term = 0;
+    for (feature, &weight) in zip(feature_vector, model_weights) {
+        let bits = feature.to_bits();
+        let sum: &mut Option<BitDecomposed<Replicated<Boolean>>> = if weight < 0 {
+            &mut negative
+        } else {
+            &mut positive
+        };
+        let magnitude = weight.unsigned_abs();
+        for shift in (0..8).filter(|shift| (magnitude >> shift) & 1 == 1) {
+            let mut shifted =
+                BitDecomposed::new(repeat_n(Replicated::ZERO, shift).chain(bits.iter().cloned()));
+            shifted.resize(WEIGHTED_SUM_BITS, Replicated::ZERO);
+            *sum = Some(match sum.take() {
+                None => shifted,
+                Some(sum) => {
+                    let step = RowStep::AddWeightedFeature(term);
+                    term += 1;
+                    integer_add::<_, ThirtyTwoBitStep, 1>(
+                        ctx.narrow(&step),
+                        record_id,
+                        &sum,
+                        &shifted,
+                    )
+                    .await?
+                    .0
+                }
+            });
+        }
+    }
+
+    let zero = || BitDecomposed::new(repeat_n(Replicated::ZERO, WEIGHTED_SUM_BITS));
+    match (positive, negative) {
+        (positive, None) => Ok(positive.unwrap_or_else(zero)),
+        (positive, Some(negative)) => {
+            integer_sub::<_, ThirtyTwoBitStep>(
+                ctx.narrow(&RowStep::SubtractNegativeTerms),
+                record_id,
+                &positive.unwrap_or_else(zero),
+                &negative,
+            )
+            .await
+        }
+    }
+}
+
+/// Saturates a two's complement number of [`WEIGHTED_SUM_BITS`] bits to the range of an 8-bit
+/// two's complement number, the input of the [`sigmoid`] approximation.
+async fn saturate<C>(
+    ctx: &C,
+    record_id: RecordId,
+    value: &BitDecomposed<Replicated<Boolean>>,
+) -> Result<BitDecomposed<Replicated<Boolean>>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+{
+    let sign = &value[WEIGHTED_SUM_BITS - 1];
+
+    // The value fits in 8 bits iff all the bits above the 7 lowest ones are equal to the sign.
+    let mut overflow = &value[SIGMOID_INPUT_BITS - 1] + sign;
+    for (i, bit) in value[SIGMOID_INPUT_BITS..WEIGHTED_SUM_BITS - 1]
+        .iter()
+        .enumerate()
+    {
+        overflow = or(
+            ctx.narrow(&RowStep::DetectOverflow(i)),
+            record_id,
+            &overflow,
+            &(bit + sign),
+        )
+        .await?;
+    }
+
+    // On overflow, replace the value with the largest or smallest 8-bit number, depending on
+    // its sign. The bits of both are the negated sign, except for the sign bit.
+    let saturated = BitDecomposed::new((0..SIGMOID_INPUT_BITS).map(|i| {
+        if i == SIGMOID_INPUT_BITS - 1 {
+            sign.clone()
+        } else {
+            sign.clone().not()
+        }
+    }));
+    let difference = BitDecomposed::new(
+        zip(value.iter(), saturated.iter())
+            .map(|(value_bit, saturated_bit)| value_bit + saturated_bit),
+    );
+    let correction = bool_and_8_bit(
+        ctx.narrow(&RowStep::Saturate),
+        record_id,
+        &difference,
+        repeat_n(&overflow, SIGMOID_INPUT_BITS),
+    )
+    .await?;
+
+    Ok(BitDecomposed::new(
+        zip(value.iter(), correction).map(|(value_bit, correction)| value_bit + &correction),
+    ))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use rand::thread_rng;
+
+    use crate::{
+        ff::{
+            boolean::Boolean,
+            boolean_array::{BA32, BA8},
+            Field, U128Conversions,
+        },
+        protocol::ipa_prf::prf_sharding::{
+            feature_label_dot_product::PrfShardedIpaInputRow,
+            feature_prediction_product::compute_feature_prediction_product,
+        },
+        rand::Rng,
+        secret_sharing::{
+            replicated::semi_honest::AdditiveShare as Replicated, IntoShares, SharedValue,
+            TransposeFrom,
+        },
+        test_executor::run,
+        test_fixture::{walr::sigmoid_in_the_clear, Reconstruct, Runner, TestWorld},
+    };
+
+    struct TestRow {
+        is_trigger: bool,
+        feature_vector: [u8; 8],
+    }
+
+    impl IntoShares<PrfShardedIpaInputRow<BA8, 8>> for TestRow {
+        fn share_with<R: Rng>(self, rng: &mut R) -> [PrfShardedIpaInputRow<BA8, 8>; 3] {
+            let is_trigger_bit = if self.is_trigger {
+                Boolean::ONE
+            } else {
+                <Boolean as SharedValue>::ZERO
+            };
+            let [t0, t1, t2] = is_trigger_bit.share_with(rng);
+            let [f0, f1, f2] = self
+                .feature_vector
+                .map(BA8::truncate_from)
+                .share_with(rng);
+
+            [(t0, f0), (t1, f1), (t2, f2)].map(|(is_trigger_bit, feature_vector)| {
+                PrfShardedIpaInputRow {
+                    prf_of_match_key: 0,
+                    is_trigger_bit,
+                    feature_vector,
+                }
+            })
+        }
+    }
+
+    fn expected(rows: &[TestRow], weights: [i8; 8]) -> Vec<u128> {
+        let mut sums = vec![0_u128; 8];
+        for row in rows.iter().filter(|row| !row.is_trigger) {
+            let dot_product = row
+                .feature_vector
+                .iter()
+                .zip(&weights)
+                .map(|(&feature, &weight)| i64::from(feature) * i64::from(weight))
+                .sum::<i64>();
+            let prediction = u128::from(sigmoid_in_the_clear(dot_product));
+            for (sum, &feature) in sums.iter_mut().zip(&row.feature_vector) {
+                *sum += u128::from(feature) * prediction;
+            }
+        }
+        sums
+    }
+
+    #[test]
+    fn malicious() {
+        run(|| async move {
+            let world = TestWorld::default();
+            let mut rng = thread_rng();
+
+            // Small features keep some dot products inside the range of the sigmoid, large ones
+            // saturate it in both directions.
+            let weights: [i8; 8] = [1, -1, 3, -2, 127, -128, 0, 5];
+            let mut rows = (0..6)
+                .map(|_| TestRow {
+                    is_trigger: false,
+                    feature_vector: std::array::from_fn(|_| rng.gen_range(0..4)),
+                })
+                .collect::<Vec<_>>();
+            rows.push(TestRow {
+                is_trigger: false,
+                feature_vector: [0, 0, 0, 0, 255, 0, 0, 0],
+            });
+            rows.push(TestRow {
+                is_trigger: false,
+                feature_vector: [0, 0, 0, 0, 0, 255, 0, 255],
+            });
+            // Trigger events contribute nothing, even if they carry features.
+            rows.push(TestRow {
+                is_trigger: true,
+                feature_vector: [200; 8],
+            });
+            let expected = expected(&rows, weights);
+
+            let result: [Vec<Replicated<BA32>>; 3] = world
+                .malicious(rows.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &compute_feature_prediction_product::<_, BA8, BA32, 8>(
+                            ctx,
+                            &input_rows,
+                            &weights,
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                    .unwrap()
+                })
+                .await;
+
+            let result = result
+                .reconstruct()
+                .iter()
+                .map(U128Conversions::as_u128)
+                .collect::<Vec<_>>();
+            assert_eq!(result, expected);
+        });
+    }
+}
@@ -16,10 +16,10 @@ use crate::{
     error::{Error, LengthError},
     ff::{
         boolean::Boolean,
-        boolean_array::{BooleanArray, BA32, BA7},
+        boolean_array::{BooleanArray, BA16, BA32, BA7, BA8},
         ArrayAccess, Field, U128Conversions,
     },
-    helpers::{stream::TryFlattenItersExt, TotalRecords},
+    helpers::{query::AttributionModel, stream::TryFlattenItersExt, TotalRecords},
     protocol::{
         basics::{select, BooleanArrayMul, BooleanProtocols, Reveal, SecureMul, ShareKnownValue},
         boolean::{
@@ -55,7 +55,13 @@ use crate::{
     utils::non_zero_prev_power_of_two,
 };
 
+/// Largest supported per-user credit cap. The saturating sum of a user's attributed trigger values
+/// must fit in [`EightBitStep`].
+pub const MAX_PER_USER_CREDIT_CAP: u32 = 255;
+
 pub mod feature_label_dot_product;
+pub mod feature_prediction_product;
+pub(crate) mod multi_touch;
 pub(crate) mod step;
 
 #[derive(Debug)]
@@ -115,9 +121,10 @@ struct InputsRequiredFromPrevRow<BK: SharedValue, TV: SharedValue, TS: SharedVal
 
 /// Returns the number of Boolean multiplications per input record, for use in computing the number
 /// of records in each DZKP. These multiplications are in `compute_row_with_previous` and the
-/// functions it calls.
+/// functions it calls, plus the multi-touch circuit for models that use it.
 fn multiplications_per_record<BK: SharedValue, TV: SharedValue, TS: SharedValue>(
     attribution_window: Option<NonZeroU32>,
+    attribution_model: AttributionModel,
 ) -> usize {
     let mut count =
         // breakdown_key_of_most_recent_source_event
@@ -140,9 +147,13 @@ fn multiplications_per_record<BK: SharedValue, TV: SharedValue, TS: SharedValue>
             3 * TS::BITS +
             // zero_out_flag
             1;
+    } else if matches!(attribution_model, AttributionModel::TimeDecay { .. }) {
+        // timestamp_of_most_recent_source_event
+        count += TS::BITS;
     }
 
     usize::try_from(count).unwrap()
+        + multi_touch::multiplications_per_record::<TV, TS>(attribution_model)
 }
 
 impl<BK, TV, TS> InputsRequiredFromPrevRow<BK, TV, TS>
@@ -159,10 +170,11 @@ where
     /// - Last touch attribution
     ///     - Every trigger event which is preceded by a source event is attributed
     ///     - Trigger events are attributed to the `breakdown_key` of the most recent preceding source event
+    ///     - With [`AttributionModel::FirstTouch`], they are attributed to the first source event instead
     /// - Per user capping
     ///     - A cumulative sum of "Attributed Trigger Value" is maintained
     ///     - Bitwise addition is used, and a single bit indicates if the sum is "saturated"
-    ///     - The only available values for "cap" are powers of 2 (i.e. 1, 2, 4, 8, 16, 32, ...)
+    ///     - The sum starts at an offset below a power of 2, so that it overflows when it reaches the cap
     ///     - Prior to the cumulative sum reaching saturation, attributed trigger values are passed along
     ///     - The row which puts the cumulative sum over the cap is "capped" to the delta between the cumulative sum of the last row and the cap
     ///     - All subsequent rows contribute zero
@@ -175,12 +187,14 @@ where
     ///         - `did_trigger_get_attributed` - a secret-shared bit indicating if this row corresponds to a trigger event
     ///           which was attributed. Might be able to reveal this (after a shuffle and the addition of dummies) to minimize
     ///           the amount of processing work that must be done in the Aggregation stage.
+    #[allow(clippy::too_many_lines)]
     pub async fn compute_row_with_previous<C>(
         &mut self,
         ctx: C,
         record_id: RecordId,
         input_row: &PrfShardedIpaInputRow<BK, TV, TS>,
         attribution_window_seconds: Option<NonZeroU32>,
+        attribution_model: AttributionModel,
     ) -> Result<AttributionOutputs<Replicated<BK>, Replicated<TV>>, Error>
     where
         C: Context,
@@ -190,6 +204,15 @@ where
         Replicated<TV>: BooleanArrayMul<C>,
     {
         let is_source_event = input_row.is_trigger_bit.clone().not();
+        // Rows that keep the attributed source event of the previous row: trigger events for
+        // last touch, and every row after the first source event for first touch.
+        let keep_previous_source_event = if attribution_model == AttributionModel::FirstTouch {
+            &self.ever_encountered_a_source_event
+        } else {
+            &input_row.is_trigger_bit
+        };
+        let track_source_event_timestamp = attribution_window_seconds.is_some()
+            || matches!(attribution_model, AttributionModel::TimeDecay { .. });
 
         let (
             ever_encountered_a_source_event,
@@ -205,15 +228,15 @@ where
             breakdown_key_of_most_recent_source_event(
                 ctx.narrow(&PerRowStep::AttributedBreakdownKey),
                 record_id,
-                &input_row.is_trigger_bit,
+                keep_previous_source_event,
                 &self.attributed_breakdown_key_bits,
                 &input_row.breakdown_key,
             ),
             timestamp_of_most_recent_source_event(
                 ctx.narrow(&PerRowStep::SourceEventTimestamp),
                 record_id,
-                attribution_window_seconds,
-                &input_row.is_trigger_bit,
+                track_source_event_timestamp,
+                keep_previous_source_event,
                 &self.source_event_timestamp,
                 &input_row.timestamp,
             ),
@@ -449,26 +472,22 @@ where
 /// This circuit expects to receive records from multiple users,
 /// but with all of the records from a given user adjacent to one another, and in time order.
 ///
-/// This circuit will compute attribution, per-user capping and aggregation.
+/// This circuit will compute attribution, per-user capping and aggregation. Last touch and
+/// first touch credit each trigger value to a single source event; linear and time decay split it
+/// between the source events of the user (see [`multi_touch`]). The total contribution of every
+/// user is capped at `per_user_credit_cap`.
 ///
 /// # Errors
 /// Propagates errors from multiplications
 /// # Panics
-/// Propagates errors from multiplications
+/// If `per_user_credit_cap` is zero or larger than [`MAX_PER_USER_CREDIT_CAP`].
 #[tracing::instrument(name = "attribute_cap_aggregate", skip_all)]
-pub async fn attribute_cap_aggregate<
-    'ctx,
-    C,
-    BK,
-    TV,
-    HV,
-    TS,
-    const SS_BITS: usize,
-    const B: usize,
->(
+pub async fn attribute_cap_aggregate<'ctx, C, BK, TV, HV, TS, const B: usize>(
     sh_ctx: C,
     input_rows: Vec<PrfShardedIpaInputRow<BK, TV, TS>>,
+    per_user_credit_cap: u32,
     attribution_window_seconds: Option<NonZeroU32>,
+    attribution_model: AttributionModel,
     histogram: &[usize],
     padding_parameters: &PaddingParameters,
 ) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
@@ -486,6 +505,8 @@ where
         + Reveal<DZKPUpgraded<C>, Output = <BK as Vectorizable<1>>::Array>,
     Replicated<TS>: BooleanArrayMul<DZKPUpgraded<C>>,
     Replicated<TV>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<BA8>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<BA16>: BooleanArrayMul<DZKPUpgraded<C>>,
     BitDecomposed<Replicated<Boolean, AGG_CHUNK>>:
         for<'a> TransposeFrom<&'a Vec<Replicated<BK>>, Error = LengthError>,
     BitDecomposed<Replicated<Boolean, AGG_CHUNK>>:
@@ -496,16 +517,32 @@ where
     >,
     BitDecomposed<Replicated<Boolean, B>>:
         for<'a> TransposeFrom<&'a [Replicated<TV>; B], Error = Infallible>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<BA8>; B], Error = Infallible>,
     Vec<Replicated<HV>>:
         for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
 {
+    assert!(
+        (1..=MAX_PER_USER_CREDIT_CAP).contains(&per_user_credit_cap),
+        "per-user credit cap must be between 1 and {MAX_PER_USER_CREDIT_CAP}, got {per_user_credit_cap}"
+    );
+
     // Get the validator and context to use for Boolean multiplication operations.
     // Record IDs count users. The maximum number of multiplications per record (user) is:
     // (max_events - 1) * multiplictions_per_record, because the attribution circuit is
-    // only evaluated for the second and subsequent records.
+    // only evaluated for the second and subsequent records. The multi-touch circuit also
+    // does some work for the first record.
+    let rows_with_multiplications = if attribution_model.is_multi_touch() {
+        histogram.len()
+    } else {
+        histogram.len() - 1
+    };
     let chunk_size = TARGET_PROOF_SIZE
-        / ((histogram.len() - 1)
-            * multiplications_per_record::<BK, TV, TS>(attribution_window_seconds));
+        / (rows_with_multiplications
+            * multiplications_per_record::<BK, TV, TS>(
+                attribution_window_seconds,
+                attribution_model,
+            ));
 
     // Tricky hacks to work around the limitations of our current infrastructure
     let mut dzkp_validator = sh_ctx.clone().dzkp_validator(
@@ -536,11 +573,40 @@ where
     let mut collected = rows_chunked_by_user.collect::<Vec<_>>().await;
     collected.sort_by(|a, b| std::cmp::Ord::cmp(&b.len(), &a.len()));
 
-    let flattened_user_results = attribute::<_, _, _, _, SS_BITS, B>(
+    if attribution_model.is_multi_touch() {
+        // The multi-touch circuit also credits the first row of every user.
+        let first_row_ctx = dzkp_validator
+            .context()
+            .narrow(&UserNthRowStep::from(0))
+            .set_total_records(TotalRecords::specified(histogram[1])?);
+        let ctx_for_row_number = iter::once(first_row_ctx)
+            .chain(ctx_for_row_number)
+            .collect();
+        let user_contributions = multi_touch::attribute(
+            dzkp_validator,
+            ctx_for_row_number,
+            collected,
+            per_user_credit_cap,
+            attribution_window_seconds,
+            attribution_model,
+        )
+        .try_collect::<Vec<_>>()
+        .await?;
+        return breakdown_reveal_aggregation::<_, BK, BA8, HV, B>(
+            sh_ctx.narrow(&Step::Aggregate),
+            user_contributions,
+            padding_parameters,
+        )
+        .await;
+    }
+
+    let flattened_user_results = attribute::<_, _, _, _, B>(
         dzkp_validator,
         ctx_for_row_number,
         collected,
+        per_user_credit_cap,
         attribution_window_seconds,
+        attribution_model,
     );
 
     let user_contributions = flattened_user_results.try_collect::<Vec<_>>().await?;
@@ -553,11 +619,13 @@ where
 }
 
 #[tracing::instrument(name = "attribute_cap", skip_all, fields(unique_match_keys = input.len()))]
-fn attribute<'ctx, V, BK, TV, TS, const SS_BITS: usize, const B: usize>(
+fn attribute<'ctx, V, BK, TV, TS, const B: usize>(
     dzkp_validator: V,
     contexts: Vec<V::Context>,
     input: Vec<Vec<PrfShardedIpaInputRow<BK, TV, TS>>>,
+    per_user_credit_cap: u32,
     attribution_window_seconds: Option<NonZeroU32>,
+    attribution_model: AttributionModel,
 ) -> impl Stream<Item = Result<SecretSharedAttributionOutputs<BK, TV>, Error>> + Send + 'ctx
 where
     V: DZKPValidator + 'ctx,
@@ -577,11 +645,13 @@ where
                 let num_user_rows = rows_for_user.len();
                 let contexts = contexts[..num_user_rows - 1].to_owned();
 
-                evaluate_per_user_attribution_circuit::<_, BK, TV, TS, SS_BITS>(
+                evaluate_per_user_attribution_circuit(
                     contexts,
                     RecordId::from(record_id),
                     rows_for_user,
+                    per_user_credit_cap,
                     attribution_window_seconds,
+                    attribution_model,
                 )
             });
 
@@ -593,11 +663,13 @@ where
 }
 
 #[tracing::instrument(level = "debug", name = "per_user", skip_all, fields(rows = rows_for_user.len()))]
-async fn evaluate_per_user_attribution_circuit<C, BK, TV, TS, const SS_BITS: usize>(
+async fn evaluate_per_user_attribution_circuit<C, BK, TV, TS>(
     ctx_for_row_number: Vec<C>,
     record_id: RecordId,
     rows_for_user: Vec<PrfShardedIpaInputRow<BK, TV, TS>>,
+    per_user_credit_cap: u32,
     attribution_window_seconds: Option<NonZeroU32>,
+    attribution_model: AttributionModel,
 ) -> Result<Vec<SecretSharedAttributionOutputs<BK, TV>>, Error>
 where
     C: DZKPContext,
@@ -614,13 +686,22 @@ where
         return Ok(Vec::new());
     }
     let first_row = &rows_for_user[0];
-    let mut prev_row_inputs =
-        initialize_new_device_attribution_variables::<BK, TV, TS, SS_BITS>(first_row);
+    let mut prev_row_inputs = initialize_new_device_attribution_variables(
+        &ctx_for_row_number[0],
+        first_row,
+        per_user_credit_cap,
+    );
 
     let mut output = Vec::with_capacity(rows_for_user.len() - 1);
     for (row, ctx) in zip(rows_for_user.iter().skip(1), ctx_for_row_number.into_iter()) {
         let capped_attribution_outputs = prev_row_inputs
-            .compute_row_with_previous(ctx, record_id, row, attribution_window_seconds)
+            .compute_row_with_previous(
+                ctx,
+                record_id,
+                row,
+                attribution_window_seconds,
+                attribution_model,
+            )
             .await?;
 
         output.push(capped_attribution_outputs);
@@ -632,26 +713,48 @@ where
 /// Upon encountering the first row of data from a new user (as distinguished by a different OPRF of the match key)
 /// this function encapsulates the variables that must be initialized. No communication is required for this first row.
 ///
-fn initialize_new_device_attribution_variables<BK, TV, TS, const SS_BITS: usize>(
+/// Capping saturates at `per_user_credit_cap` by starting the saturating sum at
+/// `2^saturating_sum_bits - per_user_credit_cap` instead of zero. The sum then overflows exactly when
+/// the attributed trigger values of the user reach the cap, and `0 - saturating_sum` is the difference
+/// to the cap, as long as the saturating sum is at least as wide as the trigger value.
+///
+fn initialize_new_device_attribution_variables<C, BK, TV, TS>(
+    ctx: &C,
     input_row: &PrfShardedIpaInputRow<BK, TV, TS>,
+    per_user_credit_cap: u32,
 ) -> InputsRequiredFromPrevRow<BK, TV, TS>
 where
+    C: Context,
     BK: SharedValue,
-    TV: SharedValue,
+    TV: BooleanArray + U128Conversions,
     TS: SharedValue,
 {
+    let saturating_sum_bits = saturating_sum_bits::<TV>(per_user_credit_cap);
+    let initial_sum = (1_u32 << saturating_sum_bits) - per_user_credit_cap;
     InputsRequiredFromPrevRow {
         ever_encountered_a_source_event: input_row.is_trigger_bit.clone().not(),
         attributed_breakdown_key_bits: input_row.breakdown_key.clone(),
-        saturating_sum: BitDecomposed::new(repeat_n(Replicated::ZERO, SS_BITS)),
+        saturating_sum: BitDecomposed::new((0..saturating_sum_bits).map(|i| {
+            Replicated::share_known_value(ctx, Boolean::from((initial_sum >> i) & 1 == 1))
+        })),
         is_saturated: Replicated::<Boolean>::ZERO,
-        // This is incorrect in the case that the CAP is less than the maximum value of "trigger value" for a single row
-        // Not a problem if you assume that's an invalid input
-        difference_to_cap: Replicated::<TV>::ZERO,
+        // Only used if a single trigger value reaches the cap, which requires the cap to fit in
+        // `TV`.
+        difference_to_cap: Replicated::share_known_value(
+            ctx,
+            TV::truncate_from(per_user_credit_cap),
+        ),
         source_event_timestamp: input_row.timestamp.clone(),
     }
 }
 
+/// Returns the number of bits of the saturating sum used to cap the contribution of a user at
+/// `per_user_credit_cap`. It is wide enough for the cap and for a single trigger value.
+fn saturating_sum_bits<TV: SharedValue>(per_user_credit_cap: u32) -> usize {
+    let cap_bits = u32::BITS - (per_user_credit_cap - 1).leading_zeros();
+    usize::try_from(cap_bits.max(TV::BITS)).unwrap()
+}
+
 ///
 /// To support "Last Touch Attribution" we move the `breakdown_key` of the most recent source event
 /// down to all of trigger events that follow it.
@@ -659,10 +762,13 @@ where
 /// The logic here is extremely simple. For each row:
 /// (a) if it is a source event, take the current `breakdown_key`.
 /// (b) if it is a trigger event, take the `breakdown_key` from the preceding line
+///
+/// For "First Touch Attribution", `keep_previous` is set on every row after the first source
+/// event instead, so the `breakdown_key` of the first source event moves down to all later rows.
 async fn breakdown_key_of_most_recent_source_event<C, BK>(
     ctx: C,
     record_id: RecordId,
-    is_trigger_bit: &Replicated<Boolean>,
+    keep_previous: &Replicated<Boolean>,
     prev_row_breakdown_key_bits: &Replicated<BK>,
     cur_row_breakdown_key_bits: &Replicated<BK>,
 ) -> Result<Replicated<BK>, Error>
@@ -674,20 +780,21 @@ where
     select(
         ctx,
         record_id,
-        is_trigger_bit,
+        keep_previous,
         prev_row_breakdown_key_bits,
         cur_row_breakdown_key_bits,
     )
     .await
 }
 
-/// Same as above but for timestamps. If `attribution_window_seconds` is `None`, just
-/// return the previous row's timestamp. The bits aren't used but saves some multiplications.
+/// Same as above but for timestamps. If the timestamp is not tracked (there is no attribution
+/// window and the model does not decay over time), just return the previous row's timestamp.
+/// The bits aren't used but saves some multiplications.
 async fn timestamp_of_most_recent_source_event<C, TS>(
     ctx: C,
     record_id: RecordId,
-    attribution_window_seconds: Option<NonZeroU32>,
-    is_trigger_bit: &Replicated<Boolean>,
+    track_timestamp: bool,
+    keep_previous: &Replicated<Boolean>,
     prev_row_timestamp_bits: &Replicated<TS>,
     cur_row_timestamp_bits: &Replicated<TS>,
 ) -> Result<Replicated<TS>, Error>
@@ -696,18 +803,17 @@ where
     TS: BooleanArray + U128Conversions,
     Replicated<TS>: BooleanArrayMul<C>,
 {
-    match attribution_window_seconds {
-        None => Ok(prev_row_timestamp_bits.clone()),
-        Some(_) => {
-            select(
-                ctx,
-                record_id,
-                is_trigger_bit,
-                prev_row_timestamp_bits,
-                cur_row_timestamp_bits,
-            )
-            .await
-        }
+    if track_timestamp {
+        select(
+            ctx,
+            record_id,
+            keep_previous,
+            prev_row_timestamp_bits,
+            cur_row_timestamp_bits,
+        )
+        .await
+    } else {
+        Ok(prev_row_timestamp_bits.clone())
     }
 }
 
@@ -891,6 +997,7 @@ pub mod tests {
             boolean_array::{BooleanArray, BA16, BA20, BA3, BA5, BA8},
             Field, U128Conversions,
         },
+        helpers::query::AttributionModel,
         protocol::ipa_prf::{
             oprf_padding::PaddingParameters, prf_sharding::attribute_cap_aggregate,
         },
@@ -1078,10 +1185,12 @@ pub mod tests {
             let result: [Vec<Replicated<BA16>>; 3] = world
                 .malicious(records.into_iter(), |ctx, input_rows| async move {
                     Vec::transposed_from(
-                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 32>(
                             ctx,
                             input_rows,
+                            32,
                             None,
+                            AttributionModel::LastTouch,
                             &histogram,
                             &PaddingParameters::relaxed(),
                         )
@@ -1102,6 +1211,75 @@ pub mod tests {
         });
     }
 
+    #[test]
+    fn semi_honest_capping_at_arbitrary_cap() {
+        run(|| async move {
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User */
+                oprf_test_input(123, false, 17, 0),
+                oprf_test_input(123, true, 0, 7),
+                oprf_test_input(123, false, 20, 0),
+                oprf_test_input(123, true, 0, 3),
+                /* Second User */
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, true, 0, 5),
+                /* Third User */
+                oprf_test_input(345, false, 20, 0),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, false, 18, 0),
+                oprf_test_input(345, false, 12, 0),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+            ];
+
+            let histogram = [3, 3, 2, 2, 1, 1, 1, 1];
+
+            // (cap, [expected for breakdown 12, 17, 20])
+            for (cap, [bk_12, bk_17, bk_20]) in [
+                (1, [1, 1, 1]),   // the first attributed trigger value exceeds the cap
+                (6, [5, 6, 6]),   // cap below 2^TV::BITS
+                (10, [8, 7, 10]), // user 1 reaches the cap exactly
+                (100, [33, 7, 10]),
+            ] {
+                let mut expected = [0_u128; 32];
+                expected[12] = bk_12;
+                expected[17] = bk_17;
+                expected[20] = bk_20;
+
+                let world = TestWorld::default();
+                let result: [Vec<Replicated<BA16>>; 3] = world
+                    .semi_honest(records.clone().into_iter(), |ctx, input_rows| async move {
+                        Vec::transposed_from(
+                            &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 32>(
+                                ctx,
+                                input_rows,
+                                cap,
+                                None,
+                                AttributionModel::LastTouch,
+                                &histogram,
+                                &PaddingParameters::relaxed(),
+                            )
+                            .await
+                            .unwrap(),
+                        )
+                    })
+                    .await
+                    .map(Result::unwrap);
+                let result_reconstructed: Vec<BA16> = result.reconstruct();
+                assert_eq!(
+                    result_reconstructed
+                        .iter()
+                        .map(U128Conversions::as_u128)
+                        .collect::<Vec<_>>(),
+                    &expected,
+                    "cap {cap}"
+                );
+            }
+        });
+    }
+
     #[test]
     fn semi_honest_aggregation_capping_attribution_with_attribution_window() {
         const ATTRIBUTION_WINDOW_SECONDS: u32 = 200;
@@ -1139,10 +1317,208 @@ pub mod tests {
             let result: [Vec<Replicated<BA16>>; 3] = world
                 .semi_honest(records.into_iter(), |ctx, input_rows| async move {
                     Vec::transposed_from(
-                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 32>(
                             ctx,
                             input_rows,
+                            32,
                             NonZeroU32::new(ATTRIBUTION_WINDOW_SECONDS),
+                            AttributionModel::LastTouch,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                })
+                .await
+                .map(Result::unwrap);
+            let result_reconstructed: Vec<BA16> = result.reconstruct();
+            assert_eq!(
+                result_reconstructed
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>(),
+                &expected
+            );
+        });
+    }
+
+    #[test]
+    fn semi_honest_first_touch_attribution() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User */
+                oprf_test_input(123, false, 17, 0),
+                oprf_test_input(123, true, 0, 7),
+                oprf_test_input(123, false, 20, 0),
+                oprf_test_input(123, true, 0, 3), // attributed to 17, the first source
+                /* Second User */
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, true, 0, 5),
+                /* Third User */
+                oprf_test_input(345, false, 20, 0),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, false, 18, 0),
+                oprf_test_input(345, false, 12, 0),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7), // partially capped
+            ];
+
+            let mut expected = [0_u128; 32];
+            expected[12] = 5;
+            expected[17] = 10;
+            expected[20] = 32;
+
+            let histogram = [3, 3, 2, 2, 1, 1, 1, 1];
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .semi_honest(records.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 32>(
+                            ctx,
+                            input_rows,
+                            32,
+                            None,
+                            AttributionModel::FirstTouch,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                })
+                .await
+                .map(Result::unwrap);
+            let result_reconstructed: Vec<BA16> = result.reconstruct();
+            assert_eq!(
+                result_reconstructed
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>(),
+                &expected
+            );
+        });
+    }
+
+    #[test]
+    fn semi_honest_linear_attribution() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User */
+                oprf_test_input_with_timestamp(123, false, 17, 0, 0),
+                oprf_test_input_with_timestamp(123, true, 0, 7, 10), // 17 gets 7
+                oprf_test_input_with_timestamp(123, false, 20, 0, 20),
+                oprf_test_input_with_timestamp(123, true, 0, 3, 30), // 17 and 20 get 1.5 each
+                /* Second User */
+                oprf_test_input_with_timestamp(234, false, 12, 0, 0),
+                oprf_test_input_with_timestamp(234, true, 0, 5, 10), // 12 gets 5
+                /* Third User */
+                oprf_test_input_with_timestamp(345, false, 20, 0, 0),
+                oprf_test_input_with_timestamp(345, true, 0, 7, 100), // 20 gets 7
+                oprf_test_input_with_timestamp(345, false, 18, 0, 200),
+                oprf_test_input_with_timestamp(345, false, 12, 0, 300),
+                // the remaining 25 up to the cap are split between 20, 18 and 12
+                oprf_test_input_with_timestamp(345, true, 0, 7, 400),
+                oprf_test_input_with_timestamp(345, true, 0, 7, 500),
+                oprf_test_input_with_timestamp(345, true, 0, 7, 600),
+                oprf_test_input_with_timestamp(345, true, 0, 7, 700),
+            ];
+
+            let mut expected = [0_u128; 32];
+            // fractional credit is rounded down
+            expected[12] = 13;
+            expected[17] = 8;
+            expected[18] = 8;
+            expected[20] = 16;
+
+            let histogram = [3, 3, 2, 2, 1, 1, 1, 1];
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .semi_honest(records.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 32>(
+                            ctx,
+                            input_rows,
+                            32,
+                            None,
+                            AttributionModel::Linear,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                })
+                .await
+                .map(Result::unwrap);
+            let result_reconstructed: Vec<BA16> = result.reconstruct();
+            assert_eq!(
+                result_reconstructed
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>(),
+                &expected
+            );
+        });
+    }
+
+    #[test]
+    fn malicious_time_decay_attribution() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User */
+                oprf_test_input_with_timestamp(123, false, 17, 0, 0),
+                oprf_test_input_with_timestamp(123, true, 0, 7, 10), // 17 gets 7
+                oprf_test_input_with_timestamp(123, false, 20, 0, 64),
+                oprf_test_input_with_timestamp(123, true, 0, 3, 70), // split 1:2 between 17 and 20
+                /* Second User */
+                oprf_test_input_with_timestamp(234, false, 12, 0, 0),
+                oprf_test_input_with_timestamp(234, true, 0, 5, 10), // 12 gets 5
+                /* Third User */
+                oprf_test_input_with_timestamp(345, false, 20, 0, 0),
+                oprf_test_input_with_timestamp(345, true, 0, 7, 100), // 20 gets 7
+                oprf_test_input_with_timestamp(345, false, 18, 0, 128),
+                oprf_test_input_with_timestamp(345, false, 12, 0, 256),
+                // the remaining 25 up to the cap are split 1:4:16 between 20, 18 and 12
+                oprf_test_input_with_timestamp(345, true, 0, 7, 300),
+                oprf_test_input_with_timestamp(345, true, 0, 7, 400),
+                oprf_test_input_with_timestamp(345, true, 0, 7, 500),
+                oprf_test_input_with_timestamp(345, true, 0, 7, 600),
+                /* Fourth User */
+                oprf_test_input_with_timestamp(456, false, 5, 0, 0), // too old to get credit
+                oprf_test_input_with_timestamp(456, false, 6, 0, 2000),
+                oprf_test_input_with_timestamp(456, true, 0, 6, 2010), // all to 6
+            ];
+
+            let mut expected = [0_u128; 32];
+            // fractional credit is rounded down and the sum of weights is rounded up
+            expected[6] = 5;
+            expected[12] = 22;
+            expected[17] = 7;
+            expected[18] = 4;
+            expected[20] = 8;
+
+            let histogram = [4, 4, 3, 2, 1, 1, 1, 1];
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 32>(
+                            ctx,
+                            input_rows,
+                            32,
+                            None,
+                            AttributionModel::TimeDecay {
+                                half_life_seconds: 64,
+                            },
                             &histogram,
                             &PaddingParameters::relaxed(),
                         )
@@ -1177,10 +1553,12 @@ pub mod tests {
 
             world
                 .malicious(records.into_iter(), |ctx, input_rows| async move {
-                    attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                    attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 32>(
                         ctx,
                         input_rows,
+                        32,
                         None,
+                        AttributionModel::LastTouch,
                         histogram_ref,
                         &PaddingParameters::relaxed(),
                     )
@@ -1261,18 +1639,12 @@ pub mod tests {
             let result: [Vec<Replicated<BA8>>; 3] = world
                 .semi_honest(records.into_iter(), |ctx, input_rows| async move {
                     Vec::transposed_from(
-                        &attribute_cap_aggregate::<
-                            _,
-                            BA8,
-                            BA3,
-                            BA8,
-                            BA20,
-                            { SaturatingSumType::BITS as usize },
-                            256,
-                        >(
+                        &attribute_cap_aggregate::<_, BA8, BA3, BA8, BA20, 256>(
                             ctx,
                             input_rows,
+                            1 << SaturatingSumType::BITS,
                             None,
+                            AttributionModel::LastTouch,
                             &HISTOGRAM,
                             &PaddingParameters::relaxed(),
                         )
@@ -0,0 +1,568 @@
+//! Multi-touch attribution, where the value of a trigger event may be split between several
+//! source events of the same user.
+//!
+//! Both multi-touch models reuse the last touch circuit to decide which trigger events are
+//! attributed (including the attribution window check against the most recent source event) and
+//! how much of their value survives per-user capping. The capped value of a trigger event is then
+//! split between all preceding source events of the user, in proportion to their weights:
+//! - [`AttributionModel::Linear`]: every source event has weight 1.
+//! - [`AttributionModel::TimeDecay`]: timestamps are grouped into periods of `half_life_seconds`.
+//!   A source event that is `k` periods older than the most recent source event has weight
+//!   `2^-k`, and a weight of zero once `k` reaches `2^DECAY_SHIFT_BITS`.
+//!
+//! Weights and shares are fixed-point numbers with [`FRACTIONAL_BITS`] fractional bits. The
+//! circuit makes two passes over the rows of a user:
+//! 1. Oldest to newest, it maintains the sum of source event weights, and divides the capped
+//!    trigger value of every row by that sum.
+//! 2. Newest to oldest, it accumulates the divided trigger values, decays the accumulator at every
+//!    source event and credits the source event with the integer part of the accumulator.
+//!
+//! Shares and credits are rounded down, and the time-decay weight sum is rounded up, so a user
+//! never receives more credit in total than the sum of their capped trigger values.
+//!
+//! Every row produces one output: the breakdown key of the row, and a credit that is zero unless
+//! the row is a source event.
+
+use std::{
+    iter::{repeat_n, zip},
+    num::NonZeroU32,
+    ops::Not,
+};
+
+use futures::{stream, Stream};
+
+use super::{
+    initialize_new_device_attribution_variables, AttributionOutputs, PrfShardedIpaInputRow,
+    SecretSharedAttributionOutputs,
+};
+use crate::{
+    error::Error,
+    ff::{
+        boolean::Boolean,
+        boolean_array::{BooleanArray, BA16, BA8},
+        ArrayAccess, U128Conversions,
+    },
+    helpers::{query::AttributionModel, stream::TryFlattenItersExt},
+    protocol::{
+        basics::{select, BooleanArrayMul, BooleanProtocols, ShareKnownValue},
+        boolean::{
+            step::{SixteenBitStep, ThirtyTwoBitStep},
+            NBitStep,
+        },
+        context::{dzkp_validator::DZKPValidator, Context, DZKPContext},
+        ipa_prf::{
+            boolean_ops::{
+                addition_sequential::integer_add,
+                comparison_and_subtraction_sequential::{compare_gt, integer_sub},
+            },
+            prf_sharding::step::{
+                AttributionPerRowStep as PerRowStep, DecayStep, DivisionBitStep, DivisionStep,
+                MultiTouchStep as Step,
+            },
+        },
+        RecordId,
+    },
+    secret_sharing::{
+        replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, SharedValue,
+    },
+};
+
+/// Number of fractional bits in source event weights, shares of trigger values and credits.
+pub(crate) const FRACTIONAL_BITS: usize = 5;
+
+/// Number of bits of the sum of source event weights. A user has at most 64 rows, each adding at
+/// most `2^FRACTIONAL_BITS + 1` to the sum.
+const WEIGHT_BITS: usize = 12;
+
+/// Number of low bits of the period count between two source events that select a right shift.
+/// Weights that are `2^DECAY_SHIFT_BITS` or more periods old are zero.
+pub(crate) const DECAY_SHIFT_BITS: usize = 4;
+
+/// Maximum number of bits of the quotient of a trigger value divided by the weight sum. This
+/// bounds the number of trigger value bits to `MAX_QUOTIENT_BITS - FRACTIONAL_BITS`.
+const MAX_QUOTIENT_BITS: usize = 12;
+
+/// Number of bits of weight sums, shares and credit accumulators (`BA16`).
+const ACCUMULATOR_BITS: usize = 16;
+
+/// Number of bits of a credit (`BA8`).
+const CREDIT_BITS: usize = 8;
+
+/// Returns the number of Boolean multiplications per input record made by the multi-touch circuit,
+/// in addition to the last touch circuit.
+pub(super) fn multiplications_per_record<TV: SharedValue, TS: SharedValue>(
+    attribution_model: AttributionModel,
+) -> usize {
+    let tv_bits = usize::try_from(TV::BITS).unwrap();
+    let ts_bits = usize::try_from(TS::BITS).unwrap();
+    match attribution_model {
+        AttributionModel::LastTouch | AttributionModel::FirstTouch => 0,
+        AttributionModel::Linear | AttributionModel::TimeDecay { .. } => {
+            let mut count =
+                // add_source_weight
+                // accumulate credit
+                2 * ACCUMULATOR_BITS +
+                // divide (subtract and select for each quotient bit)
+                (tv_bits + FRACTIONAL_BITS) * (WEIGHT_BITS + 2 + ACCUMULATOR_BITS) +
+                // select source credit
+                CREDIT_BITS;
+            if matches!(attribution_model, AttributionModel::TimeDecay { .. }) {
+                count +=
+                    // periods between source events
+                    // is decay expired
+                    2 * ts_bits +
+                    // decay source weights and credit
+                    2 * (DECAY_SHIFT_BITS + 1) * ACCUMULATOR_BITS;
+            }
+            count
+        }
+    }
+}
+
+/// Number of half-life periods between a source event and the source event that preceded it, as
+/// the shifts that decay a weight by that many periods. Both are zero for trigger events.
+struct Decay {
+    /// Bit `i` selects a right shift by `2^i`.
+    halvings: BitDecomposed<Replicated<Boolean>>,
+    /// Set if the weight decays to zero.
+    expired: Replicated<Boolean>,
+}
+
+impl Decay {
+    async fn apply<C>(
+        &self,
+        ctx: C,
+        record_id: RecordId,
+        value: Replicated<BA16>,
+    ) -> Result<Replicated<BA16>, Error>
+    where
+        C: Context,
+        Replicated<BA16>: BooleanArrayMul<C>,
+    {
+        let mut value = value;
+        for (i, halving) in self.halvings.iter().enumerate() {
+            let halved = shift_right(&value, 1 << i);
+            value = select(
+                ctx.narrow(&DecayStep::Halve(i)),
+                record_id,
+                halving,
+                &halved,
+                &value,
+            )
+            .await?;
+        }
+        select(
+            ctx.narrow(&DecayStep::Expire),
+            record_id,
+            &self.expired,
+            &Replicated::ZERO,
+            &value,
+        )
+        .await
+    }
+}
+
+/// A row of the forward pass, with what the backward pass needs to credit it.
+struct ForwardRow<BK: BooleanArray> {
+    is_source_event: Replicated<Boolean>,
+    breakdown_key: Replicated<BK>,
+    /// Capped trigger value divided by the weight sum. `None` for the first row, which cannot be
+    /// an attributed trigger event.
+    trigger_value_share: Option<Replicated<BA16>>,
+    /// Decay between this row and the preceding source event, for time decay.
+    decay: Option<Decay>,
+}
+
+fn shift_right(value: &Replicated<BA16>, by: usize) -> Replicated<BA16> {
+    value.to_bits().into_iter().skip(by).collect()
+}
+
+/// Returns `is_source_event` at the weight of a single source event, plus one at the lowest bit
+/// if the weight sum needs rounding up.
+fn source_weight(
+    is_source_event: &Replicated<Boolean>,
+    round_up: bool,
+) -> BitDecomposed<Replicated<Boolean>> {
+    BitDecomposed::new((0..ACCUMULATOR_BITS).map(|i| {
+        if i == FRACTIONAL_BITS || (round_up && i == 0) {
+            is_source_event.clone()
+        } else {
+            Replicated::ZERO
+        }
+    }))
+}
+
+pub(super) fn attribute<'ctx, V, BK, TV, TS>(
+    dzkp_validator: V,
+    contexts: Vec<V::Context>,
+    input: Vec<Vec<PrfShardedIpaInputRow<BK, TV, TS>>>,
+    per_user_credit_cap: u32,
+    attribution_window_seconds: Option<NonZeroU32>,
+    attribution_model: AttributionModel,
+) -> impl Stream<Item = Result<SecretSharedAttributionOutputs<BK, BA8>, Error>> + Send + 'ctx
+where
+    V: DZKPValidator + 'ctx,
+    Replicated<Boolean>: BooleanProtocols<V::Context>,
+    BK: BooleanArray + U128Conversions,
+    TV: BooleanArray + U128Conversions,
+    TS: BooleanArray + U128Conversions,
+    Replicated<BK>: BooleanArrayMul<V::Context>,
+    Replicated<TS>: BooleanArrayMul<V::Context>,
+    Replicated<TV>: BooleanArrayMul<V::Context>,
+    Replicated<BA8>: BooleanArrayMul<V::Context>,
+    Replicated<BA16>: BooleanArrayMul<V::Context>,
+{
+    let chunked_user_results =
+        input
+            .into_iter()
+            .enumerate()
+            .map(move |(record_id, rows_for_user)| {
+                let contexts = contexts[..rows_for_user.len()].to_owned();
+
+                evaluate_per_user_multi_touch_circuit(
+                    contexts,
+                    RecordId::from(record_id),
+                    rows_for_user,
+                    per_user_credit_cap,
+                    attribution_window_seconds,
+                    attribution_model,
+                )
+            });
+
+    dzkp_validator
+        .validated_seq_join::<_, _, Vec<AttributionOutputs<_, _>>>(stream::iter(
+            chunked_user_results,
+        ))
+        .try_flatten_iters()
+}
+
+/// Credits the source events of one user. `ctx_for_row_number` starts with the context for the
+/// first row.
+///
+/// ## Panics
+/// If the model is not a multi-touch model, if the cap does not fit in a `BA8` credit, or if the
+/// trigger value is too wide for the division.
+#[tracing::instrument(level = "debug", name = "per_user_multi_touch", skip_all, fields(rows = rows_for_user.len()))]
+#[allow(clippy::too_many_lines)]
+async fn evaluate_per_user_multi_touch_circuit<C, BK, TV, TS>(
+    ctx_for_row_number: Vec<C>,
+    record_id: RecordId,
+    rows_for_user: Vec<PrfShardedIpaInputRow<BK, TV, TS>>,
+    per_user_credit_cap: u32,
+    attribution_window_seconds: Option<NonZeroU32>,
+    attribution_model: AttributionModel,
+) -> Result<Vec<SecretSharedAttributionOutputs<BK, BA8>>, Error>
+where
+    C: DZKPContext,
+    Replicated<Boolean>: BooleanProtocols<C>,
+    BK: BooleanArray + U128Conversions,
+    TV: BooleanArray + U128Conversions,
+    TS: BooleanArray + U128Conversions,
+    Replicated<BK>: BooleanArrayMul<C>,
+    Replicated<TS>: BooleanArrayMul<C>,
+    Replicated<TV>: BooleanArrayMul<C>,
+    Replicated<BA8>: BooleanArrayMul<C>,
+    Replicated<BA16>: BooleanArrayMul<C>,
+{
+    let half_life_bits = match attribution_model {
+        AttributionModel::Linear => None,
+        AttributionModel::TimeDecay { half_life_seconds } => {
+            assert!(
+                half_life_seconds.is_power_of_two(),
+                "time decay half-life must be a power of two"
+            );
+            Some(usize::try_from(half_life_seconds.trailing_zeros()).unwrap())
+        }
+        AttributionModel::LastTouch | AttributionModel::FirstTouch => {
+            panic!("{attribution_model} is not a multi-touch attribution model")
+        }
+    };
+    assert!(
+        per_user_credit_cap < 1 << CREDIT_BITS,
+        "multi-touch credits are aggregated as BA8 and do not fit a cap of {per_user_credit_cap}"
+    );
+    assert_eq!(ctx_for_row_number.len(), rows_for_user.len());
+
+    let first_row = &rows_for_user[0];
+    let mut prev_row_inputs = initialize_new_device_attribution_variables(
+        &ctx_for_row_number[0],
+        first_row,
+        per_user_credit_cap,
+    );
+    let is_source_event = first_row.is_trigger_bit.clone().not();
+    let mut source_weights: Replicated<BA16> =
+        source_weight(&is_source_event, half_life_bits.is_some()).collect_bits();
+
+    let mut forward_rows = Vec::with_capacity(rows_for_user.len());
+    forward_rows.push(ForwardRow {
+        is_source_event,
+        breakdown_key: first_row.breakdown_key.clone(),
+        trigger_value_share: None,
+        decay: None,
+    });
+    for (row, ctx) in zip(
+        rows_for_user.iter().skip(1),
+        ctx_for_row_number.iter().skip(1),
+    ) {
+        let previous_source_timestamp = prev_row_inputs.source_event_timestamp.clone();
+        let capped_attribution_outputs = prev_row_inputs
+            .compute_row_with_previous(
+                ctx.clone(),
+                record_id,
+                row,
+                attribution_window_seconds,
+                attribution_model,
+            )
+            .await?;
+
+        let ctx = ctx.narrow(&PerRowStep::MultiTouch);
+        let is_source_event = row.is_trigger_bit.clone().not();
+        let decay = match half_life_bits {
+            Some(half_life_bits) => Some(
+                periods_between_source_events(
+                    &ctx,
+                    record_id,
+                    &previous_source_timestamp,
+                    &prev_row_inputs.source_event_timestamp,
+                    half_life_bits,
+                )
+                .await?,
+            ),
+            None => None,
+        };
+        if let Some(decay) = &decay {
+            source_weights = decay
+                .apply(
+                    ctx.narrow(&Step::DecaySourceWeights),
+                    record_id,
+                    source_weights,
+                )
+                .await?;
+        }
+        let (updated_weights, _) = integer_add::<_, SixteenBitStep, 1>(
+            ctx.narrow(&Step::AddSourceWeight),
+            record_id,
+            &source_weights.to_bits(),
+            &source_weight(&is_source_event, half_life_bits.is_some()),
+        )
+        .await?;
+        source_weights = updated_weights.collect_bits();
+
+        // Before the first source event the weight sum is zero, and so is the trigger value.
+        // Divide by one instead.
+        let no_source_event = prev_row_inputs
+            .ever_encountered_a_source_event
+            .clone()
+            .not();
+        let divisor = BitDecomposed::new(
+            source_weights
+                .to_bits()
+                .into_iter()
+                .take(WEIGHT_BITS)
+                .enumerate()
+                .map(|(i, bit)| if i == 0 { bit + &no_source_event } else { bit }),
+        );
+        let trigger_value_share = divide(
+            ctx.narrow(&Step::DivideTriggerValue),
+            record_id,
+            &capped_attribution_outputs
+                .capped_attributed_trigger_value
+                .to_bits(),
+            &divisor,
+        )
+        .await?;
+
+        forward_rows.push(ForwardRow {
+            is_source_event,
+            breakdown_key: row.breakdown_key.clone(),
+            trigger_value_share: Some(trigger_value_share),
+            decay,
+        });
+    }
+
+    let mut credit_accumulator = Replicated::<BA16>::ZERO;
+    let mut output = Vec::with_capacity(forward_rows.len());
+    for (row, ctx) in zip(forward_rows, ctx_for_row_number).rev() {
+        let ctx = ctx.narrow(&PerRowStep::MultiTouch);
+        if let Some(trigger_value_share) = row.trigger_value_share {
+            let (sum, _) = integer_add::<_, SixteenBitStep, 1>(
+                ctx.narrow(&Step::AccumulateCredit),
+                record_id,
+                &credit_accumulator.to_bits(),
+                &trigger_value_share.to_bits(),
+            )
+            .await?;
+            credit_accumulator = sum.collect_bits();
+        }
+
+        let credit = select(
+            ctx.narrow(&Step::SelectSourceCredit),
+            record_id,
+            &row.is_source_event,
+            &shift_right(&credit_accumulator, FRACTIONAL_BITS)
+                .to_bits()
+                .into_iter()
+                .take(CREDIT_BITS)
+                .collect::<Replicated<BA8>>(),
+            &Replicated::ZERO,
+        )
+        .await?;
+
+        if let Some(decay) = &row.decay {
+            credit_accumulator = decay
+                .apply(
+                    ctx.narrow(&Step::DecayCredit),
+                    record_id,
+                    credit_accumulator,
+                )
+                .await?;
+        }
+
+        output.push(AttributionOutputs {
+            attributed_breakdown_key_bits: row.breakdown_key,
+            capped_attributed_trigger_value: credit,
+        });
+    }
+    output.reverse();
+
+    Ok(output)
+}
+
+/// Computes the number of half-life periods between the previous and the current source event
+/// timestamps. They are equal on trigger events, which therefore do not decay anything.
+async fn periods_between_source_events<C, TS>(
+    ctx: &C,
+    record_id: RecordId,
+    previous_source_timestamp: &Replicated<TS>,
+    source_timestamp: &Replicated<TS>,
+    half_life_bits: usize,
+) -> Result<Decay, Error>
+where
+    C: Context,
+    TS: BooleanArray,
+    Replicated<Boolean>: BooleanProtocols<C>,
+{
+    assert!(
+        TS::BITS <= ThirtyTwoBitStep::BITS,
+        "ThirtyTwoBitStep is not large enough to accomodate this subtraction"
+    );
+    let period = |timestamp: &Replicated<TS>| {
+        BitDecomposed::new(timestamp.to_bits().into_iter().skip(half_life_bits))
+    };
+    let source_period = period(source_timestamp);
+    if source_period.is_empty() {
+        // The half-life exceeds the range of timestamps, so nothing decays.
+        return Ok(Decay {
+            halvings: BitDecomposed::new(iter_zero(0)),
+            expired: Replicated::ZERO,
+        });
+    }
+
+    let periods = integer_sub::<_, ThirtyTwoBitStep>(
+        ctx.narrow(&Step::PeriodsBetweenSourceEvents),
+        record_id,
+        &source_period,
+        &period(previous_source_timestamp),
+    )
+    .await?;
+
+    let expired = if periods.len() > DECAY_SHIFT_BITS {
+        let max_shift = (1 << DECAY_SHIFT_BITS) - 1;
+        let max_shift_bits =
+            BitDecomposed::new((0..periods.len()).map(|i| {
+                Replicated::share_known_value(ctx, Boolean::from((max_shift >> i) & 1 == 1))
+            }));
+        compare_gt::<_, ThirtyTwoBitStep, 1>(
+            ctx.narrow(&Step::IsDecayExpired),
+            record_id,
+            &periods,
+            &max_shift_bits,
+        )
+        .await?
+    } else {
+        Replicated::ZERO
+    };
+
+    Ok(Decay {
+        halvings: BitDecomposed::new(periods.into_iter().take(DECAY_SHIFT_BITS)),
+        expired,
+    })
+}
+
+fn iter_zero(n: usize) -> impl Iterator<Item = Replicated<Boolean>> {
+    repeat_n(Replicated::ZERO, n)
+}
+
+/// Computes `floor(dividend * 2^(2 * FRACTIONAL_BITS) / divisor)` with restoring division, which
+/// is `dividend / divisor` with `FRACTIONAL_BITS` fractional bits.
+///
+/// The divisor must be at least `2^FRACTIONAL_BITS` unless the dividend is zero, so the quotient
+/// has at most `dividend.len() + FRACTIONAL_BITS` bits and the higher bits of the scaled dividend
+/// can be taken as the initial remainder without comparing them to the divisor.
+async fn divide<C>(
+    ctx: C,
+    record_id: RecordId,
+    dividend: &BitDecomposed<Replicated<Boolean>>,
+    divisor: &BitDecomposed<Replicated<Boolean>>,
+) -> Result<Replicated<BA16>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+    Replicated<BA16>: BooleanArrayMul<C>,
+{
+    let quotient_bits = dividend.len() + FRACTIONAL_BITS;
+    assert!(
+        quotient_bits <= MAX_QUOTIENT_BITS,
+        "trigger values of {} bits are too wide for multi-touch attribution",
+        dividend.len()
+    );
+    debug_assert_eq!(divisor.len(), WEIGHT_BITS);
+    let scaled_dividend_bit = |i: usize| {
+        i.checked_sub(2 * FRACTIONAL_BITS)
+            .and_then(|i| dividend.get(i))
+            .cloned()
+            .unwrap_or(Replicated::ZERO)
+    };
+
+    // The remainder is always below the divisor, so it has `WEIGHT_BITS` bits, and one more
+    // once the next bit of the dividend is shifted in.
+    let mut remainder = BitDecomposed::new(
+        (quotient_bits..quotient_bits + FRACTIONAL_BITS)
+            .map(scaled_dividend_bit)
+            .chain(iter_zero(WEIGHT_BITS - FRACTIONAL_BITS)),
+    );
+    let mut quotient = Replicated::<BA16>::ZERO;
+    for i in (0..quotient_bits).rev() {
+        let ctx = ctx.narrow(&DivisionStep::from(i));
+        let shifted = BitDecomposed::new(
+            std::iter::once(scaled_dividend_bit(i)).chain(remainder.into_iter().take(WEIGHT_BITS)),
+        );
+        // One more bit, that is set in the difference if `shifted < divisor`.
+        let padded = BitDecomposed::new(shifted.iter().cloned().chain(iter_zero(1)));
+        let mut difference = integer_sub::<_, SixteenBitStep>(
+            ctx.narrow(&DivisionBitStep::Subtract),
+            record_id,
+            &padded,
+            divisor,
+        )
+        .await?;
+        let is_less = difference[WEIGHT_BITS + 1].clone();
+        difference.truncate(WEIGHT_BITS + 1);
+        quotient.set(i, is_less.clone().not());
+        remainder = BitDecomposed::new(
+            select(
+                ctx.narrow(&DivisionBitStep::Select),
+                record_id,
+                &is_less,
+                &shifted.collect_bits::<Replicated<BA16>>(),
+                &difference.collect_bits::<Replicated<BA16>>(),
+            )
+            .await?
+            .to_bits()
+            .into_iter()
+            .take(WEIGHT_BITS + 1),
+        );
+    }
+
+    Ok(quotient)
+}
@@ -28,6 +28,45 @@ pub(crate) enum AttributionPerRowStep {
     ComputeDifferenceToCap,
     ComputedCappedAttributedTriggerValueNotSaturatedCase,
     ComputedCappedAttributedTriggerValueJustSaturatedCase,
+    #[step(child = MultiTouchStep)]
+    MultiTouch,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum MultiTouchStep {
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    PeriodsBetweenSourceEvents,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    IsDecayExpired,
+    #[step(child = DecayStep)]
+    DecaySourceWeights,
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+    AddSourceWeight,
+    #[step(child = DivisionStep)]
+    DivideTriggerValue,
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+    AccumulateCredit,
+    SelectSourceCredit,
+    #[step(child = DecayStep)]
+    DecayCredit,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum DecayStep {
+    #[step(count = 4)]
+    Halve(usize),
+    Expire,
+}
+
+#[derive(CompactStep)]
+#[step(count = 12, child = DivisionBitStep, name = "bit")]
+pub(crate) struct DivisionStep(usize);
+
+#[derive(CompactStep)]
+pub(crate) enum DivisionBitStep {
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+    Subtract,
+    Select,
 }
 
 #[derive(CompactStep)]
@@ -48,10 +87,58 @@ pub(crate) enum AttributionWindowStep {
 
 #[derive(CompactStep)]
 pub(crate) enum FeatureLabelDotProductStep {
-    BinaryValidator,
-    PrimeFieldValidator,
+    #[step(child = FeatureLabelDotProductUserNthRowStep)]
+    Attribute,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    AttributeValidate,
+    #[step(count = 4, child = crate::protocol::ipa_prf::aggregation::step::AggregateChunkStep, name = "chunks")]
+    Aggregate(usize),
+    #[step(count = 4, child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    AggregateValidate(usize),
+}
+
+#[derive(CompactStep)]
+#[step(count = 64, child = FeatureLabelDotProductPerRowStep, name = "row")]
+pub struct FeatureLabelDotProductUserNthRowStep(usize);
+
+#[derive(CompactStep)]
+pub(crate) enum FeatureLabelDotProductPerRowStep {
     EverEncounteredTriggerEvent,
     DidSourceReceiveAttribution,
     ComputeSaturatingSum,
     IsAttributedSourceAndPrevRowNotSaturated,
+    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    ComputeCappedFeatureVector,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum FeaturePredictionProductStep {
+    #[step(child = FeaturePredictionProductPerRowStep)]
+    Predict,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    PredictValidate,
+    #[step(count = 4, child = crate::protocol::ipa_prf::aggregation::step::AggregateChunkStep, name = "chunks")]
+    Aggregate(usize),
+    #[step(count = 4, child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    AggregateValidate(usize),
+}
+
+#[derive(CompactStep)]
+pub(crate) enum FeaturePredictionProductPerRowStep {
+    #[step(count = 128, child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    AddWeightedFeature(usize),
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    SubtractNegativeTerms,
+    #[step(count = 11)]
+    DetectOverflow(usize),
+    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    Saturate,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    Sigmoid,
+    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    IsSourceEvent,
+    #[step(count = 8, child = crate::protocol::boolean::step::EightBitStep)]
+    MultiplyByPredictionBit(usize),
+    #[step(count = 8, child = crate::protocol::boolean::step::SixteenBitStep)]
+    AddPartialProduct(usize),
 }
@@ -23,7 +23,7 @@ use crate::{
     const_assert_eq,
     error::LengthError,
     ff::{
-        boolean_array::{BA112, BA144, BA32, BA64, BA96},
+        boolean_array::{BA112, BA128, BA144, BA160, BA256, BA288, BA32, BA64, BA96},
         Gf32Bit, Serializable, U128Conversions,
     },
     helpers::{Direction, Error, Role, TotalRecords},
@@ -349,6 +349,8 @@ macro_rules! impl_malicious_shuffle_share {
 impl_malicious_shuffle_share!(BA32, BA64);
 impl_malicious_shuffle_share!(BA64, BA96);
 impl_malicious_shuffle_share!(BA112, BA144);
+impl_malicious_shuffle_share!(BA128, BA160);
+impl_malicious_shuffle_share!(BA256, BA288);
 
 /// Sharded shuffle as performed by shards on H1.
 pub(super) async fn h1_shuffle_for_shard<I, S, C>(
@@ -2,6 +2,7 @@ use ipa_step_derive::CompactStep;
 
 #[derive(CompactStep)]
 pub(crate) enum IpaPrfStep {
+    ValidatePaddingParameters,
     #[step(child = crate::protocol::ipa_prf::oprf_padding::step::PaddingDpStep, name="padding_dp")]
     PaddingDp,
     #[step(child = crate::protocol::ipa_prf::shuffle::step::OPRFShuffleStep)]
@@ -17,10 +18,25 @@ pub(crate) enum IpaPrfStep {
     SortByTimestamp,
     #[step(child = crate::protocol::ipa_prf::prf_sharding::step::AttributionStep)]
     Attribution,
+    #[step(child = crate::protocol::ipa_prf::prf_sharding::step::FeatureLabelDotProductStep)]
+    FeatureLabelDotProduct,
+    #[step(child = crate::protocol::ipa_prf::prf_sharding::step::FeaturePredictionProductStep)]
+    FeaturePredictionProduct,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    ComputeGradient,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    ComputeGradientValidate,
     #[step(child = crate::protocol::dp::step::DPStep, name = "dp")]
     DifferentialPrivacy,
     #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
     DifferentialPrivacyValidate,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    DpThreshold,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    DpThresholdValidate,
+    RevealDpThreshold,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    RevealDpThresholdValidate, // only partly used -- see code
 }
 
 #[derive(CompactStep)]
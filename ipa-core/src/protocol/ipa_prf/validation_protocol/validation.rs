@@ -300,7 +300,9 @@ impl BatchToVerify {
             .collect::<Vec<_>>();
 
         if diff.ct_ne(&vec![Fp61BitPrime::ZERO; length]).into() {
-            return Err(Error::DZKPValidationFailed);
+            return Err(Error::DZKPValidationFailed {
+                step: ctx.gate().clone(),
+            });
         }
 
         Ok(())
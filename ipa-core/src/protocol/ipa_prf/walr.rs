@@ -0,0 +1,517 @@
+//! Weighted-average logistic regression (WALR) on attributed data.
+//!
+//! Each input row carries a match key, a timestamp, an event type and a vector of `B`
+//! features. Rows that belong to the same user are grouped by revealing an OPRF of the match
+//! key, and within each group source events that precede a trigger event are labelled as
+//! converted.
+//!
+//! The gradient of the logistic loss of a model `w` is `X^T (σ(Xw) - y)`, where `X` holds the
+//! feature vectors of the source events and `y` their labels. It splits into the prediction
+//! term `X^T σ(Xw)` and the label term `X^T y`, which are both computed in MPC. The sigmoid is
+//! approximated by the piecewise linear function of [`sigmoid`], and the output is scaled by 256,
+//! the scale of that approximation. The labels are private and are protected with DP; the
+//! prediction term only depends on the features of the source events. This is the WALR
+//! decomposition described in
+//! <https://github.com/patcg-individual-drafts/ipa/blob/main/logistic_regression.md>.
+//!
+//! [`sigmoid`]: crate::protocol::ipa_prf::boolean_ops::sigmoid::sigmoid
+
+use std::{
+    convert::Infallible,
+    iter::{repeat_n, zip},
+    ops::{Not, Range},
+};
+
+use tracing::{info_span, Instrument};
+
+use crate::{
+    error::{Error, LengthError},
+    ff::{
+        boolean::Boolean,
+        boolean_array::{BooleanArray, BooleanArrayReader, BooleanArrayWriter, BA256, BA32},
+        curve_points::RP25519,
+        ec_prime_field::Fp25519,
+        U128Conversions,
+    },
+    helpers::{query::DpMechanism, TotalRecords},
+    protocol::{
+        basics::{BooleanProtocols, Reveal},
+        boolean::step::ThirtyTwoBitStep,
+        context::{
+            dzkp_validator::DZKPValidator, Context, DZKPUpgraded, MacUpgraded,
+            MaliciousProtocolSteps, UpgradableContext,
+        },
+        dp::{dp_for_aggregation, Sensitivity},
+        ipa_prf::{
+            boolean_ops::addition_sequential::integer_add,
+            compute_prf_of_match_keys,
+            oprf_padding::{apply_dp_padding, PaddingParameters},
+            prf_eval::PrfSharing,
+            prf_sharding::{
+                feature_label_dot_product::{
+                    compute_feature_label_dot_product, PrfShardedIpaInputRow,
+                },
+                feature_prediction_product::compute_feature_prediction_product,
+            },
+            quicksort::quicksort_ranges_by_key_insecure,
+            shuffle::{Shuffle, Shuffleable},
+            step::IpaPrfStep as Step,
+            MatchKey, CONV_CHUNK, PRF_CHUNK, SORT_CHUNK,
+        },
+        prss::FromPrss,
+        RecordId,
+    },
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing},
+        BitDecomposed, FieldSimd, SharedValue, TransposeFrom, Vectorizable,
+    },
+};
+
+/// Input row of the WALR protocol, produced by decrypting a `WalrReport`.
+#[derive(Clone, Debug)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct WalrInputRow<FV: SharedValue, TS: SharedValue, const B: usize> {
+    pub match_key: Replicated<MatchKey>,
+    pub is_trigger: Replicated<Boolean>,
+    pub timestamp: Replicated<TS>,
+    pub feature_vector: [Replicated<FV>; B],
+}
+
+impl<FV: SharedValue, TS: SharedValue, const B: usize> Default for WalrInputRow<FV, TS, B> {
+    fn default() -> Self {
+        Self {
+            match_key: Replicated::ZERO,
+            is_trigger: Replicated::ZERO,
+            timestamp: Replicated::ZERO,
+            feature_vector: std::array::from_fn(|_| Replicated::ZERO),
+        }
+    }
+}
+
+impl<FV, TS, const B: usize> WalrInputRow<FV, TS, B>
+where
+    FV: BooleanArray,
+    TS: BooleanArray,
+{
+    fn join_fields(
+        match_key: MatchKey,
+        is_trigger: Boolean,
+        timestamp: TS,
+        feature_vector: [FV; B],
+    ) -> <Self as Shuffleable>::Share {
+        let mut share = <Self as Shuffleable>::Share::ZERO;
+
+        let writer = BooleanArrayWriter::new(&mut share)
+            .write(&match_key)
+            .write_boolean(is_trigger)
+            .write(&timestamp);
+        feature_vector
+            .iter()
+            .fold(writer, BooleanArrayWriter::write);
+
+        share
+    }
+
+    fn split_fields(share: &<Self as Shuffleable>::Share) -> (MatchKey, Boolean, TS, [FV; B]) {
+        let bits = BooleanArrayReader::new(share);
+        let (match_key, bits) = bits.read();
+        let (is_trigger, bits) = bits.read_boolean();
+        let (timestamp, mut bits) = bits.read();
+        let mut feature_vector = [FV::ZERO; B];
+        for feature in &mut feature_vector {
+            (*feature, bits) = bits.read();
+        }
+        (match_key, is_trigger, timestamp, feature_vector)
+    }
+
+    /// Key used to order the rows of a single user. The timestamp occupies the most
+    /// significant bits, and trigger events sort after source events with the same timestamp.
+    fn sort_key(&self) -> Replicated<BA32> {
+        let key = |is_trigger: Boolean, timestamp: TS| {
+            let mut key = BA32::ZERO;
+            BooleanArrayWriter::new(&mut key)
+                .write_boolean(is_trigger)
+                .write(&timestamp);
+            key
+        };
+        ReplicatedSecretSharing::new(
+            key(self.is_trigger.left(), self.timestamp.left()),
+            key(self.is_trigger.right(), self.timestamp.right()),
+        )
+    }
+}
+
+impl<FV, TS, const B: usize> Shuffleable for WalrInputRow<FV, TS, B>
+where
+    FV: BooleanArray,
+    TS: BooleanArray,
+{
+    type Share = BA256;
+
+    fn left(&self) -> Self::Share {
+        Self::join_fields(
+            ReplicatedSecretSharing::left(&self.match_key),
+            self.is_trigger.left(),
+            self.timestamp.left(),
+            self.feature_vector
+                .each_ref()
+                .map(ReplicatedSecretSharing::left),
+        )
+    }
+
+    fn right(&self) -> Self::Share {
+        Self::join_fields(
+            ReplicatedSecretSharing::right(&self.match_key),
+            self.is_trigger.right(),
+            self.timestamp.right(),
+            self.feature_vector
+                .each_ref()
+                .map(ReplicatedSecretSharing::right),
+        )
+    }
+
+    fn new(l: Self::Share, r: Self::Share) -> Self {
+        debug_assert!(
+            MatchKey::BITS as usize + 1 + TS::BITS as usize + FV::BITS as usize * B
+                <= Self::Share::BITS as usize,
+            "share type {} is too small",
+            std::any::type_name::<Self::Share>(),
+        );
+
+        let (l_match_key, l_is_trigger, l_timestamp, l_feature_vector) = Self::split_fields(&l);
+        let (r_match_key, r_is_trigger, r_timestamp, r_feature_vector) = Self::split_fields(&r);
+
+        Self {
+            match_key: ReplicatedSecretSharing::new(l_match_key, r_match_key),
+            is_trigger: ReplicatedSecretSharing::new(l_is_trigger, r_is_trigger),
+            timestamp: ReplicatedSecretSharing::new(l_timestamp, r_timestamp),
+            feature_vector: zip(l_feature_vector, r_feature_vector)
+                .map(|(l, r)| ReplicatedSecretSharing::new(l, r))
+                .collect::<Vec<_>>()
+                .try_into()
+                .unwrap_or_else(|_| unreachable!()),
+        }
+    }
+}
+
+/// A row whose OPRF has been revealed, waiting to be sorted by timestamp.
+struct PrfdWalrRow<FV: SharedValue, const B: usize> {
+    sort_key: Replicated<BA32>,
+    row: PrfShardedIpaInputRow<FV, B>,
+}
+
+/// Groups the rows, which must be sorted by PRF, by user. Returns the number of users having
+/// at least `n + 1` rows at index `n`, and the range of rows belonging to each user.
+fn histogram_and_ranges<FV: SharedValue, const B: usize>(
+    rows: &[PrfdWalrRow<FV, B>],
+) -> (Vec<usize>, Vec<Range<usize>>) {
+    let mut histogram = Vec::new();
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for end in 1..=rows.len() {
+        if end == rows.len() || rows[end].row.prf_of_match_key != rows[start].row.prf_of_match_key {
+            let user_rows = end - start;
+            if histogram.len() < user_rows {
+                histogram.resize(user_rows, 0);
+            }
+            histogram[..user_rows]
+                .iter_mut()
+                .for_each(|count| *count += 1);
+            ranges.push(start..end);
+            start = end;
+        }
+    }
+    (histogram, ranges)
+}
+
+/// WALR gradient protocol
+///
+/// The output is the secret-shared, noisy gradient `X^T (σ(Xw) - y)` of the logistic loss of the
+/// model `model_weights`, scaled by 256, with one coordinate per feature. The coordinates are
+/// two's complement numbers of `HV::BITS` bits. See the [module documentation].
+///
+/// The model weights are signed fixed-point numbers with 4 fractional bits. The features are
+/// integers.
+///
+/// The protocol performs the following steps
+/// 1. Adds DP padding so that the revealed OPRF does not leak the number of rows per user
+/// 2. Shuffles the input
+/// 3. Computes an OPRF of the match keys and reveals it
+/// 4. Groups together rows with the same OPRF, and obliviously sorts each group so that the
+///    most recent event comes first
+/// 5. Attributes each trigger event to the preceding source event, with at most one source
+///    event per user receiving attribution, and sums the feature vectors of attributed sources
+/// 6. Sums the feature vectors of all source events, weighted by the prediction of the model
+/// 7. Subtracts the label term from the prediction term
+/// 8. Adds DP noise calibrated to the sensitivity of a single user's label
+///
+/// [module documentation]: self
+///
+/// # Errors
+/// Propagates errors from config issues or while running the protocol, or if the ℓ1
+/// sensitivity of the gradient does not fit in a `u32`.
+pub async fn walr<'ctx, C, FV, TS, HV, const B: usize>(
+    ctx: C,
+    input_rows: Vec<WalrInputRow<FV, TS, B>>,
+    model_weights: &[i8; B],
+    dp_params: DpMechanism,
+    dp_padding_params: PaddingParameters,
+) -> Result<Vec<Replicated<HV>>, Error>
+where
+    C: UpgradableContext + 'ctx + Shuffle,
+    FV: BooleanArray + U128Conversions,
+    TS: BooleanArray + U128Conversions,
+    HV: BooleanArray + U128Conversions,
+    Boolean: FieldSimd<B>,
+    WalrInputRow<FV, TS, B>: crate::protocol::ipa_prf::oprf_padding::Paddable,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+    Replicated<Boolean, CONV_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, CONV_CHUNK>,
+    Replicated<Boolean, SORT_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, SORT_CHUNK>,
+    Replicated<Fp25519, PRF_CHUNK>:
+        PrfSharing<MacUpgraded<C, Fp25519>, PRF_CHUNK, Field = Fp25519> + FromPrss,
+    Replicated<RP25519, PRF_CHUNK>:
+        Reveal<MacUpgraded<C, Fp25519>, Output = <RP25519 as Vectorizable<PRF_CHUNK>>::Array>,
+    BitDecomposed<Replicated<Boolean, B>>: FromPrss<usize>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<FV>; B], Error = Infallible>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<HV>; B], Error = Infallible>,
+    Vec<Replicated<HV>>:
+        for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
+{
+    let sensitivity = gradient_sensitivity::<FV, B>()?;
+    if input_rows.is_empty() {
+        return Ok(vec![Replicated::ZERO; B]);
+    }
+
+    let padded_input_rows = apply_dp_padding::<_, WalrInputRow<FV, TS, B>, B>(
+        ctx.narrow(&Step::PaddingDp),
+        input_rows,
+        &dp_padding_params,
+    )
+    .await?;
+
+    let shuffled = ctx
+        .narrow(&Step::Shuffle)
+        .shuffle(padded_input_rows)
+        .instrument(info_span!("shuffle_inputs"))
+        .await?;
+    let prf_of_match_keys =
+        compute_prf_of_match_keys(ctx.clone(), &shuffled, |row| &row.match_key).await?;
+
+    let mut prfd_inputs = zip(shuffled, prf_of_match_keys)
+        .map(|(input, prf_of_match_key)| PrfdWalrRow {
+            sort_key: input.sort_key(),
+            row: PrfShardedIpaInputRow {
+                prf_of_match_key,
+                is_trigger_bit: input.is_trigger,
+                feature_vector: input.feature_vector,
+            },
+        })
+        .collect::<Vec<_>>();
+    prfd_inputs.sort_by_key(|x| x.row.prf_of_match_key);
+
+    let (users_having_n_records, ranges) = histogram_and_ranges(&prfd_inputs);
+    quicksort_ranges_by_key_insecure(
+        ctx.narrow(&Step::SortByTimestamp),
+        &mut prfd_inputs,
+        true,
+        |x| &x.sort_key,
+        ranges,
+    )
+    .await?;
+
+    let rows = prfd_inputs.into_iter().map(|x| x.row).collect::<Vec<_>>();
+    let prediction_term = compute_feature_prediction_product::<_, FV, HV, B>(
+        ctx.narrow(&Step::FeaturePredictionProduct),
+        &rows,
+        model_weights,
+    )
+    .await?;
+    let label_term = compute_feature_label_dot_product::<_, FV, HV, B>(
+        ctx.narrow(&Step::FeatureLabelDotProduct),
+        rows,
+        &users_having_n_records,
+    )
+    .await?;
+    let gradient =
+        subtract_label_term::<_, HV, B>(ctx.clone(), prediction_term, label_term).await?;
+
+    dp_for_aggregation::<_, B, HV>(ctx, gradient, dp_params, sensitivity).await
+}
+
+/// Computes `prediction_term - 256 * label_term`, modulo `2^HV::BITS`. The label term is scaled
+/// to the units of the sigmoid approximation that the prediction term is computed with.
+async fn subtract_label_term<C, HV, const B: usize>(
+    ctx: C,
+    prediction_term: BitDecomposed<Replicated<Boolean, B>>,
+    label_term: BitDecomposed<Replicated<Boolean, B>>,
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    C: UpgradableContext,
+    HV: BooleanArray,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+{
+    let bits = usize::try_from(HV::BITS).unwrap();
+    let validator = ctx.dzkp_validator(
+        MaliciousProtocolSteps {
+            protocol: &Step::ComputeGradient,
+            validate: &Step::ComputeGradientValidate,
+        },
+        1,
+    );
+
+    // With `!` the bitwise negation, `!x = -x - 1`, so `x - y = !(!x + y)`.
+    let scaled_label_term = BitDecomposed::new(
+        repeat_n(Replicated::ZERO, LABEL_SCALE_BITS)
+            .chain(label_term)
+            .take(bits),
+    );
+    let (negated_gradient, _) = integer_add::<_, ThirtyTwoBitStep, B>(
+        validator.context().set_total_records(TotalRecords::ONE),
+        RecordId::FIRST,
+        &BitDecomposed::new(prediction_term.into_iter().map(Not::not)),
+        &scaled_label_term,
+    )
+    .await?;
+    validator.validate().await?;
+
+    Ok(BitDecomposed::new(
+        negated_gradient.into_iter().map(Not::not),
+    ))
+}
+
+/// The label term is multiplied by `2^LABEL_SCALE_BITS` to bring it to the scale of the sigmoid
+/// approximation.
+const LABEL_SCALE_BITS: usize = 8;
+
+/// Changing the label of one source event changes the gradient by its feature vector, scaled by
+/// 256. Each user has at most one source event with a label of one, so the output changes by at
+/// most `B` features, every one of them bounded by 256 times the largest value of `FV`.
+fn gradient_sensitivity<FV: BooleanArray, const B: usize>() -> Result<Sensitivity, Error> {
+    let dimensions = u32::try_from(B).ok();
+    let max_value = u32::BITS
+        .checked_sub(FV::BITS)
+        .and_then(|shift| u32::MAX.checked_shr(shift))
+        .and_then(|max_feature| max_feature.checked_mul(1 << LABEL_SCALE_BITS));
+    match dimensions.zip(max_value) {
+        Some((dimensions, max_value)) if dimensions.checked_mul(max_value).is_some() => {
+            Ok(Sensitivity::vector(dimensions, max_value))
+        }
+        _ => Err(Error::Unsupported(format!(
+            "sensitivity of the gradient of {B} features of {} bits does not fit in u32",
+            FV::BITS
+        ))),
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use rand::thread_rng;
+
+    use crate::{
+        ff::{
+            boolean_array::{BA16, BA20, BA32, BA64, BA8},
+            U128Conversions,
+        },
+        helpers::query::DpMechanism,
+        protocol::ipa_prf::{
+            oprf_padding::PaddingParameters,
+            shuffle::Shuffleable,
+            walr::{gradient_sensitivity, walr, WalrInputRow},
+        },
+        rand::Rng,
+        secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, IntoShares},
+        test_executor::run,
+        test_fixture::{
+            walr::{walr_gradient_in_the_clear, walr_in_the_clear, TestWalrRecord},
+            Reconstruct, Runner, TestWorld,
+        },
+    };
+
+    fn random_features<R: Rng>(rng: &mut R) -> [u32; 16] {
+        std::array::from_fn(|_| rng.gen_range(0..256))
+    }
+
+    #[test]
+    fn shuffleable_round_trip() {
+        let mut rng = thread_rng();
+        let record = TestWalrRecord {
+            user_id: rng.gen(),
+            timestamp: rng.gen_range(0..1 << 20),
+            is_trigger_report: rng.gen(),
+            feature_vector: random_features(&mut rng),
+        };
+        let [row, _, _]: [WalrInputRow<BA8, BA20, 16>; 3] = record.share_with(&mut rng);
+
+        let round_tripped = WalrInputRow::<BA8, BA20, 16>::new(row.left(), row.right());
+        assert_eq!(row, round_tripped);
+    }
+
+    #[test]
+    fn sensitivity() {
+        assert_eq!(
+            16 * 255 * 256,
+            gradient_sensitivity::<BA8, 16>().unwrap().ell_1()
+        );
+        assert_eq!(
+            65535 * 256,
+            gradient_sensitivity::<BA16, 1>().unwrap().ell_1()
+        );
+        assert!(gradient_sensitivity::<BA16, 512>().is_err());
+        assert!(gradient_sensitivity::<BA32, 1>().is_err());
+        assert!(gradient_sensitivity::<BA64, 1>().is_err());
+    }
+
+    #[test]
+    fn malicious_gradient() {
+        run(|| async move {
+            let world = TestWorld::default();
+            let mut rng = thread_rng();
+
+            let records = vec![
+                // The most recent source before a trigger receives attribution.
+                TestWalrRecord::source(1, 10, random_features(&mut rng)),
+                TestWalrRecord::source(1, 20, random_features(&mut rng)),
+                TestWalrRecord::trigger(1, 30),
+                // Sources after the last trigger do not.
+                TestWalrRecord::source(1, 40, random_features(&mut rng)),
+                TestWalrRecord::source(2, 5, random_features(&mut rng)),
+                TestWalrRecord::trigger(2, 6),
+                TestWalrRecord::trigger(2, 7),
+                // No trigger for this user.
+                TestWalrRecord::source(3, 1, random_features(&mut rng)),
+            ];
+            assert_ne!(walr_in_the_clear(&records), vec![0; 16]);
+            // Most dot products of these weights with the random features stay inside the
+            // range of the sigmoid approximation.
+            let mut model_weights = [0; 16];
+            model_weights[..3].copy_from_slice(&[1, -1, 0]);
+            model_weights[15] = 2;
+            let expected = walr_gradient_in_the_clear(&records, &model_weights);
+
+            let result: [Vec<Replicated<BA32>>; 3] = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    walr::<_, BA8, BA20, BA32, 16>(
+                        ctx,
+                        input_rows,
+                        &model_weights,
+                        DpMechanism::NoDp,
+                        PaddingParameters::no_padding(),
+                    )
+                    .await
+                    .unwrap()
+                })
+                .await;
+
+            let result = result
+                .reconstruct()
+                .iter()
+                .map(U128Conversions::as_u128)
+                .collect::<Vec<_>>();
+            assert_eq!(result, expected);
+        });
+    }
+}
@@ -35,38 +35,50 @@ impl ipa_metrics::LabelValue for step::ProtocolGate {
     }
 }
 
-/// Unique identifier of the MPC query requested by report collectors
-/// TODO(615): Generating this unique id may be tricky as it may involve communication between helpers and
-/// them collaborating on constructing this unique id. These details haven't been flushed out yet,
-/// so for now it is just an empty struct. Once we know more about it, we will make necessary
-/// amendments to it
+/// Unique identifier of the MPC query requested by report collectors.
+///
+/// The value is chosen by the coordinator helper when it receives a new query request and it
+/// is shared with other helpers and shards inside [`PrepareQuery`]. Helpers use it to tell
+/// concurrently running queries apart, so it must not be reused while the query is known
+/// to any of them.
+///
+/// [`PrepareQuery`]: crate::helpers::query::PrepareQuery
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[serde(into = "&'static str", try_from = "&str")]
-pub struct QueryId;
+#[serde(into = "String", try_from = "&str")]
+pub struct QueryId(u64);
+
+impl QueryId {
+    /// Generates a new query identifier. Identifiers are picked at random, so helpers
+    /// that act as coordinators for different queries don't need to agree on them.
+    #[must_use]
+    pub fn random() -> Self {
+        use crate::rand::RngCore;
+
+        Self(crate::rand::thread_rng().next_u64())
+    }
+}
 
 impl Display for QueryId {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        // fail when query id becomes meaningful and change the display implementation
-        let _: QueryId = QueryId;
-        write!(f, "{self:?}")
+        write!(f, "{}", self.0)
     }
 }
 
-impl QueryId {
-    fn repr() -> &'static str {
-        "0"
+impl From<u64> for QueryId {
+    fn from(value: u64) -> Self {
+        Self(value)
     }
 }
 
-impl AsRef<str> for QueryId {
-    fn as_ref(&self) -> &str {
-        QueryId::repr()
+impl From<QueryId> for u64 {
+    fn from(value: QueryId) -> Self {
+        value.0
     }
 }
 
-impl From<QueryId> for &'static str {
-    fn from(_: QueryId) -> Self {
-        QueryId::repr()
+impl From<QueryId> for String {
+    fn from(value: QueryId) -> Self {
+        value.to_string()
     }
 }
 
@@ -74,11 +86,10 @@ impl TryFrom<&str> for QueryId {
     type Error = Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        if value == QueryId::repr() {
-            Ok(QueryId)
-        } else {
-            Err(Error::path_parse_error(value))
-        }
+        value
+            .parse()
+            .map(Self)
+            .map_err(|_| Error::path_parse_error(value))
     }
 }
 
@@ -197,3 +208,27 @@ pub struct NoRecord;
 impl RecordBinding for NoRecord {}
 
 impl RecordBinding for RecordId {}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use crate::protocol::QueryId;
+
+    #[test]
+    fn query_id_serde() {
+        let query_id = QueryId::from(u64::MAX);
+        let json = serde_json::to_string(&query_id).unwrap();
+        assert_eq!(format!("\"{}\"", u64::MAX), json);
+        assert_eq!(query_id, serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    fn query_id_parse_error() {
+        assert!(QueryId::try_from("not_a_query_id").is_err());
+        assert!(QueryId::try_from("-1").is_err());
+    }
+
+    #[test]
+    fn query_id_random() {
+        assert_ne!(QueryId::random(), QueryId::random());
+    }
+}
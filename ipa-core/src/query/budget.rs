@@ -0,0 +1,428 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+    io,
+    path::PathBuf,
+    time::Duration,
+};
+
+use generic_array::GenericArray;
+use ipa_metrics::counter;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use typenum::U16;
+
+use crate::{
+    error::Error,
+    ff::Serializable,
+    helpers::query::ReportCollector,
+    sharding::ShardIndex,
+    sync::{Arc, Mutex},
+    telemetry::metrics::QUERIES_OVER_BUDGET,
+};
+
+/// Sums of epsilon are compared to the budget with this much slack, so rounding errors don't
+/// refuse a query that spends exactly the remaining budget.
+const TOLERANCE: f64 = 1e-9;
+
+/// Conversion site and epoch that reports of a query belong to. Budget is accounted separately
+/// for each of them.
+///
+/// The site is identified by a hash of its domain, so it has a fixed size and can be exchanged
+/// between shards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SiteEpoch {
+    site: [u8; 8],
+    epoch: u64,
+}
+
+impl SiteEpoch {
+    #[must_use]
+    pub fn new(site_domain: &str, epoch: u64) -> Self {
+        let mut site = [0; 8];
+        site.copy_from_slice(&Sha256::digest(site_domain.as_bytes())[..8]);
+        Self { site, epoch }
+    }
+
+    /// Maps the site epoch into a consistent shard, which keeps the budget spent on it.
+    ///
+    /// ## Panics
+    /// If `shard_count` is zero.
+    #[must_use]
+    pub fn shard_picker(&self, shard_count: ShardIndex) -> ShardIndex {
+        let num = u64::from_le_bytes(self.site) ^ self.epoch;
+        ShardIndex::try_from(num % u64::from(shard_count)).expect("Modulo a u32 will fit in u32")
+    }
+}
+
+impl Serializable for SiteEpoch {
+    type Size = U16;
+    type DeserializationError = std::convert::Infallible;
+
+    fn serialize(&self, buf: &mut GenericArray<u8, Self::Size>) {
+        buf[..8].copy_from_slice(&self.site);
+        buf[8..].copy_from_slice(&self.epoch.to_le_bytes());
+    }
+
+    fn deserialize(buf: &GenericArray<u8, Self::Size>) -> Result<Self, Self::DeserializationError> {
+        Ok(Self {
+            site: buf[..8].try_into().unwrap(),
+            epoch: u64::from_le_bytes(buf[8..].try_into().unwrap()),
+        })
+    }
+}
+
+/// Budget is spent by a report collector on a site epoch. Queries of report collectors that
+/// did not authenticate share the same budget.
+type Account = (Option<ReportCollector>, SiteEpoch);
+
+/// Entry of the ledger file.
+#[derive(Serialize, Deserialize)]
+struct LedgerEntry {
+    report_collector: Option<ReportCollector>,
+    #[serde(with = "hex")]
+    site: [u8; 8],
+    epoch: u64,
+    epsilon: f64,
+}
+
+#[derive(Default)]
+struct Balances {
+    spent: HashMap<Account, f64>,
+    /// Spent by queries that are still running
+    reserved: HashMap<Account, f64>,
+}
+
+impl Balances {
+    fn total(&self, account: &Account) -> f64 {
+        self.spent.get(account).copied().unwrap_or_default()
+            + self.reserved.get(account).copied().unwrap_or_default()
+    }
+
+    fn release(&mut self, account: &Account, epsilon: f64) {
+        if let Some(reserved) = self.reserved.get_mut(account) {
+            *reserved -= epsilon;
+            if *reserved <= TOLERANCE {
+                self.reserved.remove(account);
+            }
+        }
+    }
+}
+
+/// Keeps track of the privacy budget report collectors spend on each conversion site, in
+/// epochs of fixed length. A query is refused if it would spend more than `epsilon_per_epoch`
+/// on any site epoch its reports belong to.
+///
+/// The spent budget is written to a JSON file after every query that completes, so it
+/// survives a restart of the helper.
+pub struct BudgetLedger {
+    path: PathBuf,
+    epsilon_per_epoch: f64,
+    epoch: Duration,
+    balances: Mutex<Balances>,
+    /// Makes sure the ledger file is written by one query at a time
+    writer: tokio::sync::Mutex<()>,
+}
+
+impl Debug for BudgetLedger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BudgetLedger[{}]", self.path.display())
+    }
+}
+
+impl BudgetLedger {
+    /// Opens the ledger stored at `path`. The ledger is empty if the file does not exist.
+    ///
+    /// ## Errors
+    /// If the ledger file can't be read or parsed.
+    ///
+    /// ## Panics
+    /// If `epoch` is shorter than a second.
+    pub fn open<P: Into<PathBuf>>(
+        path: P,
+        epsilon_per_epoch: f64,
+        epoch: Duration,
+    ) -> io::Result<Self> {
+        assert!(
+            epoch.as_secs() > 0,
+            "epoch must be at least one second long"
+        );
+        let path = path.into();
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice::<Vec<LedgerEntry>>(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        tracing::info!(
+            "loaded privacy budget spent on {} site epochs from {}",
+            entries.len(),
+            path.display()
+        );
+        let spent = entries
+            .into_iter()
+            .map(|entry| {
+                let site_epoch = SiteEpoch {
+                    site: entry.site,
+                    epoch: entry.epoch,
+                };
+                ((entry.report_collector, site_epoch), entry.epsilon)
+            })
+            .collect();
+
+        Ok(Self {
+            path,
+            epsilon_per_epoch,
+            epoch,
+            balances: Mutex::new(Balances {
+                spent,
+                reserved: HashMap::new(),
+            }),
+            writer: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Returns the epoch that an event with `timestamp`, in seconds, belongs to.
+    #[must_use]
+    pub fn epoch(&self, timestamp: u64) -> u64 {
+        timestamp / self.epoch.as_secs()
+    }
+
+    /// Returns the budget of `report_collector`, that queries created by it are charged to.
+    #[must_use]
+    pub fn for_collector(
+        self: &Arc<Self>,
+        report_collector: Option<ReportCollector>,
+    ) -> CollectorBudget {
+        CollectorBudget {
+            ledger: Arc::clone(self),
+            report_collector,
+        }
+    }
+
+    async fn write(&self) -> io::Result<()> {
+        let _guard = self.writer.lock().await;
+        let mut entries = self
+            .balances
+            .lock()
+            .unwrap()
+            .spent
+            .iter()
+            .map(|((report_collector, site_epoch), &epsilon)| LedgerEntry {
+                report_collector: report_collector.clone(),
+                site: site_epoch.site,
+                epoch: site_epoch.epoch,
+                epsilon,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| {
+            (&a.report_collector, a.site, a.epoch).cmp(&(&b.report_collector, b.site, b.epoch))
+        });
+        let bytes = serde_json::to_vec_pretty(&entries)?;
+
+        // The ledger is replaced atomically, so a crash while writing can't lose spent budget.
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        file.write_all(&bytes).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp_path, &self.path).await
+    }
+}
+
+/// Privacy budget of one report collector.
+#[derive(Clone, Debug)]
+pub struct CollectorBudget {
+    ledger: Arc<BudgetLedger>,
+    report_collector: Option<ReportCollector>,
+}
+
+impl CollectorBudget {
+    /// Returns the epoch that an event with `timestamp`, in seconds, belongs to.
+    #[must_use]
+    pub fn epoch(&self, timestamp: u64) -> u64 {
+        self.ledger.epoch(timestamp)
+    }
+
+    /// Sets aside `epsilon` of the budget of every site epoch in `site_epochs` for the current
+    /// query. The budget is only spent once [`ReservedBudget::commit`] is called. If the
+    /// reservation is dropped before that, for example because the query failed, it is
+    /// available to other queries again.
+    ///
+    /// ## Errors
+    /// If any of the site epochs does not have `epsilon` left. Nothing is reserved in this case.
+    ///
+    /// ## Panics
+    /// If the mutex guarding the balances is poisoned.
+    pub fn reserve<I: IntoIterator<Item = SiteEpoch>>(
+        &self,
+        site_epochs: I,
+        epsilon: f64,
+    ) -> Result<ReservedBudget, Error> {
+        let accounts = site_epochs
+            .into_iter()
+            .map(|site_epoch| (self.report_collector.clone(), site_epoch))
+            .collect::<Vec<_>>();
+
+        let mut balances = self.ledger.balances.lock().unwrap();
+        let exhausted = accounts
+            .iter()
+            .filter(|account| {
+                balances.total(account) + epsilon > self.ledger.epsilon_per_epoch + TOLERANCE
+            })
+            .count();
+        if exhausted > 0 {
+            counter!(QUERIES_OVER_BUDGET, 1);
+            return Err(self.exceeded(exhausted));
+        }
+
+        for account in &accounts {
+            *balances.reserved.entry(account.clone()).or_default() += epsilon;
+        }
+
+        Ok(ReservedBudget {
+            ledger: Arc::clone(&self.ledger),
+            accounts,
+            epsilon,
+        })
+    }
+
+    /// Returns the error that refuses a query, because `site_epochs` of the site epochs it uses
+    /// don't have enough budget left.
+    #[must_use]
+    pub fn exceeded(&self, site_epochs: usize) -> Error {
+        Error::PrivacyBudgetExceeded {
+            report_collector: self.report_collector.as_ref().map_or_else(
+                || "unauthenticated report collectors".to_string(),
+                ToString::to_string,
+            ),
+            site_epochs,
+        }
+    }
+}
+
+/// Budget reserved by a query with [`CollectorBudget::reserve`].
+pub struct ReservedBudget {
+    ledger: Arc<BudgetLedger>,
+    accounts: Vec<Account>,
+    epsilon: f64,
+}
+
+impl ReservedBudget {
+    /// Spends the reserved budget and writes the ledger to disk.
+    ///
+    /// ## Errors
+    /// If writing the ledger fails. The budget stays spent in memory in this case, so it is
+    /// never handed out twice.
+    ///
+    /// ## Panics
+    /// If the mutex guarding the balances is poisoned.
+    pub async fn commit(mut self) -> io::Result<()> {
+        if self.accounts.is_empty() {
+            return Ok(());
+        }
+
+        {
+            let mut balances = self.ledger.balances.lock().unwrap();
+            for account in std::mem::take(&mut self.accounts) {
+                balances.release(&account, self.epsilon);
+                *balances.spent.entry(account).or_default() += self.epsilon;
+            }
+        }
+
+        self.ledger.write().await
+    }
+}
+
+impl Drop for ReservedBudget {
+    fn drop(&mut self) {
+        let mut balances = self.ledger.balances.lock().unwrap();
+        for account in &self.accounts {
+            balances.release(account, self.epsilon);
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::Duration;
+
+    use generic_array::GenericArray;
+
+    use crate::{
+        error::Error,
+        ff::Serializable,
+        helpers::query::ReportCollector,
+        query::budget::{BudgetLedger, SiteEpoch},
+        sync::Arc,
+    };
+
+    fn open(path: &std::path::Path) -> Arc<BudgetLedger> {
+        Arc::new(BudgetLedger::open(path, 1.0, Duration::from_secs(60 * 60 * 24)).unwrap())
+    }
+
+    fn collector(name: &str) -> ReportCollector {
+        ReportCollector(name.to_string())
+    }
+
+    #[tokio::test]
+    async fn refuses_over_budget_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.json");
+        let site = SiteEpoch::new("www.example.com", 1);
+        let other_site = SiteEpoch::new("www.example.org", 1);
+
+        let ledger = open(&path);
+        let budget = ledger.for_collector(Some(collector("rc1")));
+        budget.reserve([site], 0.7).unwrap().commit().await.unwrap();
+        drop((budget, ledger));
+
+        let ledger = open(&path);
+        let budget = ledger.for_collector(Some(collector("rc1")));
+        let Err(Error::PrivacyBudgetExceeded { site_epochs: 1, .. }) =
+            budget.reserve([site, other_site], 0.5)
+        else {
+            panic!("expected the query to be refused");
+        };
+        budget.reserve([site], 0.3).unwrap();
+
+        // budget of other report collectors and epochs is not affected
+        ledger
+            .for_collector(Some(collector("rc2")))
+            .reserve([site], 1.0)
+            .unwrap();
+        budget
+            .reserve([SiteEpoch::new("www.example.com", 2)], 1.0)
+            .unwrap();
+    }
+
+    #[test]
+    fn released_if_not_committed() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = open(&dir.path().join("ledger.json"));
+        let budget = ledger.for_collector(None);
+        let site = SiteEpoch::new("www.example.com", 1);
+
+        let reserved = budget.reserve([site], 0.6).unwrap();
+        assert!(budget.reserve([site], 0.6).is_err());
+        drop(reserved);
+        budget.reserve([site], 0.6).unwrap();
+        assert!(!dir.path().join("ledger.json").exists());
+    }
+
+    #[test]
+    fn site_epoch_serialization() {
+        let site_epoch = SiteEpoch::new("www.example.com", 19_000);
+        let mut buf = GenericArray::default();
+        site_epoch.serialize(&mut buf);
+        assert_eq!(site_epoch, SiteEpoch::deserialize(&buf).unwrap());
+    }
+
+    #[test]
+    fn epochs() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = open(&dir.path().join("ledger.json"));
+        assert_eq!(0, ledger.epoch(86_399));
+        assert_eq!(19_000, ledger.epoch(19_000 * 86_400 + 5));
+    }
+}
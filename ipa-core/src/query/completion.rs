@@ -4,6 +4,7 @@ use std::{
     task::{Context, Poll},
 };
 
+use ::tokio::sync::oneshot;
 use futures::FutureExt;
 
 use crate::query::{
@@ -12,24 +13,37 @@ use crate::query::{
 };
 
 /// Query completion polls the tokio task to get the results and cleans up the query state after.
+/// It resolves to [`None`] if the query is cancelled before it completes.
 pub struct Handle<'a> {
     _query_state_guard: RemoveQuery<'a>,
     inner: RunningQuery,
+    cancel: Option<oneshot::Receiver<()>>,
 }
 
 impl Future for Handle<'_> {
-    type Output = QueryResult;
+    type Output = Option<QueryResult>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.inner.poll_unpin(cx)
+        if let Some(cancel) = self.cancel.as_mut() {
+            if let Poll::Ready(r) = cancel.poll_unpin(cx) {
+                self.cancel = None;
+                if r.is_ok() {
+                    self.inner.join_handle.abort();
+                    return Poll::Ready(None);
+                }
+            }
+        }
+
+        self.inner.poll_unpin(cx).map(Some)
     }
 }
 
 impl<'a> Handle<'a> {
-    pub fn new(guard: RemoveQuery<'a>, inner: RunningQuery) -> Self {
+    pub fn new(guard: RemoveQuery<'a>, inner: RunningQuery, cancel: oneshot::Receiver<()>) -> Self {
         Self {
             _query_state_guard: guard,
             inner,
+            cancel: Some(cancel),
         }
     }
 }
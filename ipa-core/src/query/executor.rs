@@ -15,6 +15,7 @@ use generic_array::GenericArray;
 use ipa_step::StepNarrow;
 use rand::rngs::StdRng;
 use rand_core::SeedableRng;
+use tracing::Instrument;
 use typenum::Unsigned;
 
 #[cfg(any(
@@ -39,10 +40,15 @@ use crate::{
         Gate,
     },
     query::{
-        runner::{execute_hybrid_protocol, OprfIpaQuery, QueryResult},
+        runner::{execute_hybrid_protocol, OprfIpaQuery, QueryResult, WalrQuery},
         state::RunningQuery,
+        CollectorBudget, ReplayStore,
     },
     sync::Arc,
+    telemetry::{
+        memory,
+        partition::{query_partition, MetricsPartitionExt},
+    },
 };
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 use crate::{
@@ -77,6 +83,8 @@ pub fn execute<R: PrivateKeyRegistry>(
     runtime: &IpaRuntime,
     config: QueryConfig,
     key_registry: Arc<R>,
+    replay_store: Option<Arc<ReplayStore>>,
+    privacy_budget: Option<CollectorBudget>,
     gateway: Gateway,
     input: BodyStream,
 ) -> RunningQuery {
@@ -178,9 +186,25 @@ pub fn execute<R: PrivateKeyRegistry>(
                     ipa_config,
                     config,
                     key_registry,
+                    replay_store,
+                    privacy_budget,
                 ))
             },
         ),
+        (QueryType::MaliciousWalr(walr_config), _) => do_query(
+            runtime,
+            config,
+            gateway,
+            input,
+            move |prss, gateway, config, input| {
+                let ctx = MaliciousContext::new(prss, gateway);
+                Box::pin(
+                    WalrQuery::<_, R>::new(walr_config, key_registry)
+                        .execute(ctx, config.size, input)
+                        .then(|res| ready(res.map(|out| Box::new(out) as Box<dyn Result>))),
+                )
+            },
+        ),
     }
 }
 
@@ -203,8 +227,9 @@ where
     B: Borrow<Gateway> + Send + 'static,
 {
     let (tx, rx) = oneshot::channel();
+    let partition = query_partition(gateway.borrow().query_id());
 
-    let join_handle = executor_handle.spawn(async move {
+    let query = async move {
         let gateway = gateway.borrow();
         // TODO: make it a generic argument for this function
         let mut rng = StdRng::from_entropy();
@@ -226,9 +251,13 @@ where
         } else {
             query_impl(&prss, gateway, &config, input_stream).await
         };
+        memory::report_memory("query");
 
         tx.send(v).unwrap();
-    });
+    };
+    // metrics emitted by this query are exported with its id, and its spans are nested under
+    // the span of the caller
+    let join_handle = executor_handle.spawn(query.in_current_span().in_partition(Some(partition)));
 
     RunningQuery {
         result: rx,
@@ -259,7 +288,7 @@ mod tests {
         executor::IpaRuntime,
         ff::{FieldType, Fp31, U128Conversions},
         helpers::{
-            query::{QueryConfig, QueryType},
+            query::{QueryConfig, QueryDeadlines, QueryType},
             BodyStream, Gateway, Role,
         },
         query::{executor::do_query, state::RunningQuery, ProtocolResult},
@@ -382,6 +411,7 @@ mod tests {
                 size: 1.try_into().unwrap(),
                 field_type: FieldType::Fp31,
                 query_type: QueryType::TestMultiply,
+                deadlines: QueryDeadlines::default(),
             },
             gateway,
             BodyStream::empty(),
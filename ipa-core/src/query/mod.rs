@@ -1,14 +1,20 @@
+mod budget;
 mod completion;
 mod executor;
 mod processor;
+mod replay_store;
+mod result_store;
 mod runner;
 mod state;
 
+pub use budget::{BudgetLedger, CollectorBudget, ReservedBudget, SiteEpoch};
 use completion::Handle as CompletionHandle;
 pub use executor::Result as ProtocolResult;
 pub use processor::{
     NewQueryError, PrepareQueryError, Processor as QueryProcessor, QueryCompletionError,
-    QueryInputError, QueryKillStatus, QueryKilled, QueryStatusError,
+    QueryInputError, QueryKillStatus, QueryKilled, QueryStatusError, TERMINATED_QUERY_TTL,
 };
+pub use replay_store::{ReplayStore, ReservedTags};
+pub use result_store::{ResultStore, StoredResult};
 pub use runner::OprfIpaQuery;
 pub use state::{min_status, QueryStatus};
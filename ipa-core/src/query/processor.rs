@@ -1,17 +1,21 @@
 use std::{
     collections::hash_map::Entry,
     fmt::{Debug, Formatter},
+    time::{Duration, Instant},
 };
 
 use futures::{future::try_join, stream};
 use serde::Serialize;
+use tracing::{Instrument, Span};
 
 use super::min_status;
 use crate::{
     error::Error as ProtocolError,
     executor::IpaRuntime,
     helpers::{
-        query::{CompareStatusRequest, PrepareQuery, QueryConfig},
+        query::{
+            CompareStatusRequest, CreateQuery, PrepareQuery, QueryConfigError, QueryDeadlines,
+        },
         routing::RouteId,
         BodyStream, BroadcastError, Gateway, GatewayConfig, MpcTransportError, MpcTransportImpl,
         Role, RoleAssignment, ShardTransportError, ShardTransportImpl, Transport,
@@ -21,13 +25,17 @@ use crate::{
     query::{
         executor,
         state::{QueryState, QueryStatus, RemoveQuery, RunningQueries, StateError},
-        CompletionHandle, ProtocolResult,
+        BudgetLedger, CompletionHandle, ProtocolResult, ReplayStore, ResultStore,
     },
     sharding::ShardIndex,
     sync::Arc,
     utils::NonZeroU32PowerOfTwo,
 };
 
+/// How long queries that timed out or failed are reported with that status, before helpers
+/// forget about them. Other queries are forgotten once their results are collected.
+pub const TERMINATED_QUERY_TTL: Duration = Duration::from_secs(60 * 60);
+
 /// [`Processor`] accepts and tracks requests to initiate new queries on this helper party
 /// network. It makes sure queries are coordinated and each party starts processing it when
 /// it has all the information required.
@@ -51,25 +59,42 @@ use crate::{
 ///
 /// [`AdditiveShare`]: crate::secret_sharing::replicated::semi_honest::AdditiveShare
 pub struct Processor {
-    queries: RunningQueries,
+    queries: Arc<RunningQueries>,
     key_registry: Arc<KeyRegistry<PrivateKeyOnly>>,
     active_work: Option<NonZeroU32PowerOfTwo>,
     runtime: IpaRuntime,
+    /// If set, results of completed queries are persisted here and can be retrieved after
+    /// they have been removed from memory.
+    result_store: Option<Arc<ResultStore>>,
+    /// If set, reports used by hybrid queries are remembered here and rejected if they are
+    /// submitted again in another query.
+    replay_store: Option<Arc<ReplayStore>>,
+    /// If set, hybrid queries are charged to the privacy budget of the report collector that
+    /// created them and refused once it is spent.
+    budget_ledger: Option<Arc<BudgetLedger>>,
+    /// How long queries that timed out or failed are kept around.
+    terminated_query_ttl: Duration,
 }
 
 impl Default for Processor {
     fn default() -> Self {
         Self {
-            queries: RunningQueries::default(),
+            queries: Arc::new(RunningQueries::default()),
             key_registry: Arc::new(KeyRegistry::<PrivateKeyOnly>::empty()),
             active_work: None,
             runtime: IpaRuntime::current(),
+            result_store: None,
+            replay_store: None,
+            budget_ledger: None,
+            terminated_query_ttl: TERMINATED_QUERY_TTL,
         }
     }
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum NewQueryError {
+    #[error(transparent)]
+    Config(#[from] QueryConfigError),
     #[error(transparent)]
     State(#[from] StateError),
     #[error(transparent)]
@@ -139,6 +164,10 @@ pub enum QueryCompletionError {
     ExecutionError(#[from] ProtocolError),
     #[error("one or more shards rejected this request: {0}")]
     ShardError(#[from] BroadcastError<ShardIndex, ShardTransportError>),
+    #[error("failed to read stored results: {0}")]
+    ResultStoreError(#[from] std::io::Error),
+    #[error("The query with id {0:?} did not finish before its deadline")]
+    TimedOut(QueryId),
 }
 
 impl Debug for Processor {
@@ -155,13 +184,49 @@ impl Processor {
         runtime: IpaRuntime,
     ) -> Self {
         Self {
-            queries: RunningQueries::default(),
+            queries: Arc::new(RunningQueries::default()),
             key_registry: Arc::new(key_registry),
             active_work,
             runtime,
+            result_store: None,
+            replay_store: None,
+            budget_ledger: None,
+            terminated_query_ttl: TERMINATED_QUERY_TTL,
         }
     }
 
+    /// Persist results of completed queries in `result_store`, so they survive a restart of
+    /// this helper.
+    #[must_use]
+    pub fn with_result_store(mut self, result_store: ResultStore) -> Self {
+        let result_store = Arc::new(result_store);
+        result_store.prune_periodically(&self.runtime);
+        self.result_store = Some(result_store);
+        self
+    }
+
+    /// Reject reports that were already used by earlier hybrid queries on this helper, according
+    /// to `replay_store`.
+    #[must_use]
+    pub fn with_replay_store(mut self, replay_store: ReplayStore) -> Self {
+        self.replay_store = Some(Arc::new(replay_store));
+        self
+    }
+
+    /// Charge hybrid queries to the privacy budget of report collectors kept in `budget_ledger`.
+    #[must_use]
+    pub fn with_budget_ledger(mut self, budget_ledger: BudgetLedger) -> Self {
+        self.budget_ledger = Some(Arc::new(budget_ledger));
+        self
+    }
+
+    /// Forget queries that timed out or failed after `ttl`, instead of [`TERMINATED_QUERY_TTL`].
+    #[must_use]
+    pub fn with_terminated_query_ttl(mut self, ttl: Duration) -> Self {
+        self.terminated_query_ttl = ttl;
+        self
+    }
+
     /// Upon receiving a new query request:
     /// * processor generates new query id
     /// * assigns roles to helpers in the ring.
@@ -175,6 +240,9 @@ impl Processor {
     /// * records newly created query id internally and sets query state to awaiting data
     /// * returns query configuration
     ///
+    /// The report collector that created the query, if known, is passed along to other helpers
+    /// and shards, so all of them charge the query to its privacy budget.
+    ///
     /// ## Errors
     /// When other peers failed to acknowledge this query
     #[allow(clippy::missing_panics_doc)]
@@ -182,12 +250,17 @@ impl Processor {
         &self,
         transport: MpcTransportImpl,
         shard_transport: ShardTransportImpl,
-        req: QueryConfig,
+        CreateQuery {
+            config: req,
+            report_collector,
+        }: CreateQuery,
     ) -> Result<PrepareQuery, NewQueryError> {
-        let query_id = QueryId;
+        req.validate()?;
+        let query_id = QueryId::random();
         let handle = self.queries.handle(query_id);
         handle.set_state(QueryState::Preparing(req))?;
         let guard = handle.remove_query_on_drop();
+        let span = Self::query_span(query_id);
 
         let id = transport.identity();
         let [right, left] = id.others();
@@ -199,6 +272,7 @@ impl Processor {
             query_id,
             config: req,
             roles: roles.clone(),
+            report_collector,
         };
         // Inform other helpers about new query. If any of them rejects it, this join will fail
         // TODO: If H2 succeeds and H3 fails, we need to rollback H2.
@@ -206,14 +280,24 @@ impl Processor {
             transport.send(left, prepare_request.clone(), stream::empty()),
             transport.send(right, prepare_request.clone(), stream::empty()),
         )
+        .instrument(span.clone())
         .await
         .map_err(NewQueryError::MpcTransport)?;
 
         // TODO: Similar to the todo above. If shards 1,2 and 3 succeed but 4 fails, then we need
         // to rollback 1,2 and 3
-        shard_transport.broadcast(prepare_request.clone()).await?;
-
-        handle.set_state(QueryState::AwaitingInputs(req, roles))?;
+        shard_transport
+            .broadcast(prepare_request.clone())
+            .instrument(span.clone())
+            .await?;
+
+        handle.set_state(QueryState::AwaitingInputs(
+            req,
+            roles,
+            prepare_request.report_collector.clone(),
+            span,
+        ))?;
+        self.enforce_deadlines(query_id, req.deadlines, Some(transport), shard_transport);
 
         guard.restore();
         Ok(prepare_request)
@@ -247,9 +331,24 @@ impl Processor {
         }
 
         // TODO: If shards 1,2 and 3 succeed but 4 fails, then we need to rollback 1,2 and 3.
-        shard_transport.broadcast(req.clone()).await?;
-
-        handle.set_state(QueryState::AwaitingInputs(req.config, req.roles))?;
+        let span = Self::query_span(req.query_id);
+        shard_transport
+            .broadcast(req.clone())
+            .instrument(span.clone())
+            .await?;
+
+        handle.set_state(QueryState::AwaitingInputs(
+            req.config,
+            req.roles,
+            req.report_collector,
+            span,
+        ))?;
+        self.enforce_deadlines(
+            req.query_id,
+            req.config.deadlines,
+            Some(mpc_transport),
+            shard_transport,
+        );
 
         Ok(())
     }
@@ -276,11 +375,101 @@ impl Processor {
             return Err(PrepareQueryError::AlreadyRunning);
         }
 
-        handle.set_state(QueryState::AwaitingInputs(req.config, req.roles))?;
+        let span = Self::query_span(req.query_id);
+        handle.set_state(QueryState::AwaitingInputs(
+            req.config,
+            req.roles,
+            req.report_collector,
+            span,
+        ))?;
+        self.enforce_deadlines(
+            req.query_id,
+            req.config.deadlines,
+            None,
+            shard_transport.clone_ref(),
+        );
 
         Ok(())
     }
 
+    /// Creates the span that all spans of this query on this helper are nested under. When a
+    /// query is prepared on request of another helper or shard, that request carries the trace
+    /// context of the query there, so the span continues the same trace.
+    fn query_span(query_id: QueryId) -> Span {
+        tracing::info_span!("query", query_id = %query_id)
+    }
+
+    /// Cancels the query if it does not meet its deadlines. Once that happens, its status
+    /// becomes [`QueryStatus::TimedOut`] and other helpers and shards are asked to kill it, in
+    /// case their deadlines have not passed yet. Only the leader shard can reach other helpers,
+    /// so `mpc_transport` is [`None`] on other shards. Queries that time out or fail are
+    /// forgotten once the terminated query TTL expires.
+    fn enforce_deadlines(
+        &self,
+        query_id: QueryId,
+        deadlines: QueryDeadlines,
+        mpc_transport: Option<MpcTransportImpl>,
+        shard_transport: ShardTransportImpl,
+    ) {
+        if deadlines.is_empty() {
+            return;
+        }
+
+        let registered = Instant::now();
+        let queries = Arc::clone(&self.queries);
+        let ttl = self.terminated_query_ttl;
+        let watchdog = async move {
+            if let Some(deadline) = deadlines.inputs {
+                ::tokio::time::sleep(deadline).await;
+                if queries.time_out(query_id, |state| {
+                    matches!(state, QueryState::AwaitingInputs(..))
+                }) {
+                    tracing::warn!("{query_id:?} did not receive inputs within {deadline:?}");
+                    Self::kill_everywhere(query_id, mpc_transport, shard_transport).await;
+                    ::tokio::time::sleep(ttl).await;
+                    queries.remove_terminated(query_id);
+                    return;
+                }
+            }
+            if let Some(deadline) = deadlines.total {
+                ::tokio::time::sleep(deadline.saturating_sub(registered.elapsed())).await;
+                if queries.time_out(query_id, |_| true) {
+                    tracing::warn!("{query_id:?} did not finish within {deadline:?}");
+                    Self::kill_everywhere(query_id, mpc_transport, shard_transport).await;
+                }
+                // the query may also have failed by now
+                ::tokio::time::sleep(ttl).await;
+                queries.remove_terminated(query_id);
+            }
+        };
+        drop(self.runtime.spawn(watchdog));
+    }
+
+    async fn kill_everywhere(
+        query_id: QueryId,
+        mpc_transport: Option<MpcTransportImpl>,
+        shard_transport: ShardTransportImpl,
+    ) {
+        if let Some(mpc_transport) = mpc_transport {
+            for peer in mpc_transport.peers() {
+                if let Err(e) = mpc_transport
+                    .send(peer, (RouteId::KillQuery, query_id), stream::empty())
+                    .await
+                {
+                    tracing::warn!("failed to kill {query_id:?} on {peer:?}: {e:?}");
+                }
+            }
+        }
+        if shard_transport.identity() == ShardIndex::FIRST {
+            if let Err(e) = shard_transport
+                .broadcast((RouteId::KillQuery, query_id))
+                .await
+            {
+                tracing::warn!("failed to kill {query_id:?} on other shards: {e:?}");
+            }
+        }
+    }
+
     /// Receive inputs for the specified query and creates gateway and network
     ///
     /// ## Errors
@@ -299,7 +488,9 @@ impl Processor {
         match queries.entry(query_id) {
             Entry::Occupied(entry) => {
                 let state = entry.remove();
-                if let QueryState::AwaitingInputs(config, role_assignment) = state {
+                if let QueryState::AwaitingInputs(config, role_assignment, report_collector, span) =
+                    state
+                {
                     let mut gateway_config = GatewayConfig::default();
                     if let Some(active_work) = self.active_work {
                         gateway_config.active = active_work;
@@ -313,16 +504,28 @@ impl Processor {
                         mpc_transport,
                         shard_transport,
                     );
-                    queries.insert(
-                        query_id,
-                        QueryState::Running(executor::execute(
+                    let mut running = span.in_scope(|| {
+                        executor::execute(
                             &self.runtime,
                             config,
                             Arc::clone(&self.key_registry),
+                            self.replay_store.clone(),
+                            self.budget_ledger
+                                .as_ref()
+                                .map(|ledger| ledger.for_collector(report_collector)),
                             gateway,
                             input_stream,
-                        )),
-                    );
+                        )
+                    });
+                    if let Some(result_store) = &self.result_store {
+                        running = result_store.save_on_completion(
+                            &self.runtime,
+                            query_id,
+                            config,
+                            running,
+                        );
+                    }
+                    queries.insert(query_id, QueryState::Running(running));
                     Ok(())
                 } else {
                     let error = StateError::InvalidState {
@@ -341,11 +544,20 @@ impl Processor {
     /// If the query was completed it updates the state to reflect that.
     fn get_status(&self, query_id: QueryId) -> Option<QueryStatus> {
         let mut queries = self.queries.inner.lock().unwrap();
-        let mut state = queries.remove(&query_id)?;
+        let Some(mut state) = queries.remove(&query_id) else {
+            return self
+                .result_store
+                .as_ref()
+                .is_some_and(|store| store.contains(query_id))
+                .then_some(QueryStatus::Completed);
+        };
 
         if let QueryState::Running(ref mut running) = state {
             if let Some(result) = running.try_complete() {
-                state = QueryState::Completed(result);
+                state = QueryState::finished(result);
+                if matches!(state, QueryState::Failed(_)) {
+                    self.remove_after_ttl(query_id);
+                }
             }
         }
 
@@ -365,7 +577,7 @@ impl Processor {
             my_status, ..
         })) = api_error
         {
-            return Some(*my_status);
+            return Some(my_status.clone());
         }
         None
     }
@@ -391,7 +603,7 @@ impl Processor {
     #[cfg(feature = "real-world-infra")]
     fn get_state_from_error(shard_error: &crate::net::ShardError) -> Option<QueryStatus> {
         if let crate::net::Error::ShardQueryStatusMismatch { error, .. } = &shard_error.source {
-            return Some(error.actual);
+            return Some(error.actual.clone());
         }
         None
     }
@@ -416,8 +628,15 @@ impl Processor {
         let mut status = self
             .get_status(query_id)
             .ok_or(QueryStatusError::NoSuchQuery(query_id))?;
+        // failure on the leader describes the whole helper, there is no need to ask other shards
+        if matches!(status, QueryStatus::Failed { .. }) {
+            return Ok(status);
+        }
 
-        let shard_query_status_req = CompareStatusRequest { query_id, status };
+        let shard_query_status_req = CompareStatusRequest {
+            query_id,
+            status: status.clone(),
+        };
 
         let shard_responses = shard_transport.broadcast(shard_query_status_req).await;
         if let Err(e) = shard_responses {
@@ -457,7 +676,7 @@ impl Processor {
             return Err(QueryStatusError::DifferentStatus {
                 query_id: req.query_id,
                 my_status: status,
-                other_status: req.status,
+                other_status: req.status.clone(),
             });
         }
         Ok(status)
@@ -481,9 +700,16 @@ impl Processor {
             match queries.remove(&query_id) {
                 Some(QueryState::Completed(result)) => return result.map_err(Into::into),
                 Some(QueryState::Running(handle)) => {
-                    queries.insert(query_id, QueryState::AwaitingCompletion);
-                    CompletionHandle::new(RemoveQuery::new(query_id, &self.queries), handle)
+                    let (cancel_tx, cancel_rx) = ::tokio::sync::oneshot::channel();
+                    queries.insert(query_id, QueryState::AwaitingCompletion(cancel_tx));
+                    Some(CompletionHandle::new(
+                        RemoveQuery::new(query_id, &self.queries),
+                        handle,
+                        cancel_rx,
+                    ))
                 }
+                Some(QueryState::TimedOut) => return Err(QueryCompletionError::TimedOut(query_id)),
+                Some(QueryState::Failed(error)) => return Err(error.into()),
                 Some(state) => {
                     let state_error = StateError::InvalidState {
                         from: QueryStatus::from(&state),
@@ -494,10 +720,21 @@ impl Processor {
                         source: state_error,
                     });
                 }
-                None => return Err(QueryCompletionError::NoSuchQuery(query_id)),
+                None => None,
             }
         }; // release mutex before await
 
+        let Some(handle) = handle else {
+            // The query is not in memory anymore, but its results may have been persisted
+            let stored = match &self.result_store {
+                Some(store) => store.load(query_id).await?,
+                None => None,
+            };
+            return stored
+                .map(|result| Box::new(result) as Box<dyn ProtocolResult>)
+                .ok_or(QueryCompletionError::NoSuchQuery(query_id));
+        };
+
         // Inform other shards about our intent to complete the query.
         // If any of them rejects it, report the error back. We expect all shards
         // to be in the same state. In normal cycle, this API is called only after
@@ -510,11 +747,15 @@ impl Processor {
                 .await?;
         }
 
-        Ok(handle.await?)
+        Ok(handle
+            .await
+            .ok_or(QueryCompletionError::TimedOut(query_id))??)
     }
 
-    /// Terminates a query with the given id. If query is running, then it
-    /// is unregistered and its task is terminated.
+    /// Terminates a query with the given id. If query is running, then its task is terminated.
+    /// Queries are killed when they miss their deadline on another helper or shard, so the
+    /// query is reported as [`QueryStatus::TimedOut`] afterwards, until the terminated query
+    /// TTL expires.
     ///
     /// ## Errors
     /// if query is not registered on this helper.
@@ -523,16 +764,34 @@ impl Processor {
     /// If failed to obtain exclusive access to the query collection.
     pub fn kill(&self, query_id: QueryId) -> Result<QueryKilled, QueryKillStatus> {
         let mut queries = self.queries.inner.lock().unwrap();
-        let Some(state) = queries.remove(&query_id) else {
+        let Some(state) = queries.get_mut(&query_id) else {
             return Err(QueryKillStatus::NoSuchQuery(query_id));
         };
 
-        if let QueryState::Running(handle) = state {
-            handle.join_handle.abort();
+        match std::mem::replace(state, QueryState::TimedOut) {
+            QueryState::Running(handle) => handle.join_handle.abort(),
+            QueryState::AwaitingCompletion(cancel) => {
+                // the task awaiting completion may have just finished
+                let _ = cancel.send(());
+            }
+            _ => {}
         }
+        drop(queries);
+        self.remove_after_ttl(query_id);
 
         Ok(QueryKilled(query_id))
     }
+
+    /// Forgets the query once the terminated query TTL expires, if it has timed out or
+    /// failed by then.
+    fn remove_after_ttl(&self, query_id: QueryId) {
+        let queries = Arc::clone(&self.queries);
+        let ttl = self.terminated_query_ttl;
+        drop(self.runtime.spawn(async move {
+            ::tokio::time::sleep(ttl).await;
+            queries.remove_terminated(query_id);
+        }));
+    }
 }
 
 #[derive(Clone, Serialize)]
@@ -557,7 +816,10 @@ mod tests {
         ff::{boolean_array::BA64, FieldType},
         helpers::{
             make_owned_handler,
-            query::{PrepareQuery, QueryConfig, QueryType::TestMultiply},
+            query::{
+                DpMechanism, IpaQueryConfig, PrepareQuery, QueryConfig, QueryConfigError,
+                QueryType::{self, TestMultiply},
+            },
             routing::Addr,
             ApiError, HandlerBox, HelperIdentity, HelperResponse, InMemoryMpcNetwork,
             InMemoryShardNetwork, InMemoryTransport, RequestHandler, RoleAssignment, Transport,
@@ -566,7 +828,7 @@ mod tests {
         protocol::QueryId,
         query::{
             processor::Processor,
-            state::{QueryState, RunningQuery, StateError},
+            state::{QueryState, RunningQuery},
             NewQueryError, PrepareQueryError, QueryStatus, QueryStatusError,
         },
         sharding::ShardIndex,
@@ -574,9 +836,10 @@ mod tests {
 
     fn prepare_query() -> PrepareQuery {
         PrepareQuery {
-            query_id: QueryId,
+            query_id: QueryId::from(1),
             config: test_multiply_config(),
             roles: RoleAssignment::new(HelperIdentity::make_three()),
+            report_collector: None,
         }
     }
 
@@ -662,7 +925,7 @@ mod tests {
     ///
     /// ```
     /// let t = TestComponents::new(TestComponentsArgs::default());
-    /// t.processor.query_status(QueryId)
+    /// t.processor.query_status(t.shard_transport, QueryId::from(1))
     /// ```
     #[allow(dead_code)]
     struct TestComponents {
@@ -717,18 +980,20 @@ mod tests {
         /// This initiates a new query on all shards and puts them all on running state.
         /// It also makes up a fake query result
         async fn new_running_query(&self) -> QueryId {
-            self.processor
+            let query_id = self
+                .processor
                 .new_query(
                     self.first_transport.clone_ref(),
                     self.shard_transport.clone_ref(),
-                    self.query_config,
+                    self.query_config.into(),
                 )
                 .await
-                .unwrap();
+                .unwrap()
+                .query_id;
             let (tx, rx) = tokio::sync::oneshot::channel();
             self.processor
                 .queries
-                .handle(QueryId)
+                .handle(query_id)
                 .set_state(QueryState::Running(RunningQuery {
                     result: rx,
                     join_handle: IpaRuntime::current().spawn(async {}),
@@ -736,7 +1001,7 @@ mod tests {
                 .unwrap();
             tx.send(Ok(Box::new(Self::COMPLETE_QUERY_RESULT))).unwrap();
 
-            QueryId
+            query_id
         }
     }
 
@@ -765,17 +1030,26 @@ mod tests {
         let qc_future = t.processor.new_query(
             t.first_transport,
             t.shard_transport.clone_ref(),
-            t.query_config,
+            t.query_config.into(),
         );
         pin_mut!(qc_future);
 
         // poll future once to trigger query status change
         let _qc = poll_immediate(&mut qc_future).await;
 
+        let query_id = *t
+            .processor
+            .queries
+            .inner
+            .lock()
+            .unwrap()
+            .keys()
+            .next()
+            .unwrap();
         assert_eq!(
             QueryStatus::Preparing,
             t.processor
-                .query_status(t.shard_transport.clone_ref(), QueryId)
+                .query_status(t.shard_transport.clone_ref(), query_id)
                 .await
                 .unwrap()
         );
@@ -787,40 +1061,55 @@ mod tests {
 
         assert_eq!(
             PrepareQuery {
-                query_id: QueryId,
+                query_id,
                 config: t.query_config,
                 roles: expected_assignment,
+                report_collector: None,
             },
             qc
         );
         assert_eq!(
             QueryStatus::AwaitingInputs,
             t.processor
-                .query_status(t.shard_transport.clone_ref(), QueryId)
+                .query_status(t.shard_transport.clone_ref(), query_id)
                 .await
                 .unwrap()
         );
     }
 
     #[tokio::test]
-    async fn rejects_duplicate_query_id() {
+    async fn accepts_concurrent_queries() {
         let t = TestComponents::new(TestComponentsArgs::default());
         let st = t.shard_transport;
-        let _ = t
+        let first = t
             .processor
             .new_query(
                 Transport::clone_ref(&t.first_transport),
                 Transport::clone_ref(&st),
-                t.query_config,
+                t.query_config.into(),
             )
             .await
             .unwrap();
-        assert!(matches!(
-            t.processor
-                .new_query(t.first_transport, st, t.query_config)
-                .await,
-            Err(NewQueryError::State(StateError::AlreadyRunning)),
-        ));
+        let second = t
+            .processor
+            .new_query(
+                Transport::clone_ref(&t.first_transport),
+                Transport::clone_ref(&st),
+                t.query_config.into(),
+            )
+            .await
+            .unwrap();
+
+        assert_ne!(first.query_id, second.query_id);
+        for query_id in [first.query_id, second.query_id] {
+            assert_eq!(
+                QueryStatus::AwaitingInputs,
+                t.processor
+                    .query_status(Transport::clone_ref(&st), query_id)
+                    .await
+                    .unwrap()
+            );
+        }
     }
 
     #[tokio::test]
@@ -834,13 +1123,32 @@ mod tests {
         let t = TestComponents::new(args);
         assert!(matches!(
             t.processor
-                .new_query(t.first_transport, t.shard_transport, t.query_config)
+                .new_query(t.first_transport, t.shard_transport, t.query_config.into())
                 .await
                 .unwrap_err(),
             NewQueryError::MpcTransport(_)
         ));
     }
 
+    #[tokio::test]
+    async fn rejects_invalid_query_config() {
+        let t = TestComponents::new(TestComponentsArgs::default());
+        let config = QueryConfig::new(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig::no_window(300, 20, DpMechanism::NoDp)),
+            FieldType::Fp31,
+            1,
+        )
+        .unwrap();
+        assert!(matches!(
+            t.processor
+                .new_query(t.first_transport, t.shard_transport, config.into())
+                .await
+                .unwrap_err(),
+            NewQueryError::Config(QueryConfigError::InvalidPerUserCreditCap(300))
+        ));
+        assert!(t.processor.queries.inner.lock().unwrap().is_empty());
+    }
+
     /// Context:
     /// * From the standpoint of the leader shard in Helper 1
     /// * When receiving a new query
@@ -869,7 +1177,7 @@ mod tests {
             .new_query(
                 t.first_transport,
                 t.shard_transport.clone_ref(),
-                t.query_config,
+                t.query_config.into(),
             )
             .await;
         // The following makes sure the error is a broadcast error from shard 2
@@ -881,13 +1189,7 @@ mod tests {
                 panic!("Unexpected error type");
             }
         }
-        assert!(matches!(
-            t.processor
-                .query_status(t.shard_transport, QueryId)
-                .await
-                .unwrap_err(),
-            QueryStatusError::NoSuchQuery(_)
-        ));
+        assert!(t.processor.queries.inner.lock().unwrap().is_empty());
     }
 
     /// Context:
@@ -913,7 +1215,7 @@ mod tests {
                 .new_query(
                     t.first_transport.clone_ref(),
                     t.shard_transport.clone_ref(),
-                    t.query_config
+                    t.query_config.into()
                 )
                 .await
                 .unwrap_err(),
@@ -921,19 +1223,23 @@ mod tests {
         ));
 
         // We check the internal state of the processor
-        assert!(t.processor.get_status(QueryId).is_none());
+        assert!(t.processor.queries.inner.lock().unwrap().is_empty());
     }
 
     mod complete {
+        use std::time::Duration;
 
         use crate::{
+            executor::IpaRuntime,
+            ff::{boolean_array::BA64, U128Conversions},
             helpers::{make_owned_handler, routing::RouteId, Transport},
             query::{
                 processor::{
                     tests::{HelperResponse, TestComponents, TestComponentsArgs},
-                    QueryId,
+                    Processor, QueryId,
                 },
-                ProtocolResult, QueryCompletionError,
+                state::{QueryState, RunningQuery},
+                ProtocolResult, QueryCompletionError, QueryStatus, ResultStore,
             },
             sharding::ShardIndex,
         };
@@ -954,7 +1260,7 @@ mod tests {
         }
 
         #[tokio::test]
-        #[should_panic(expected = "QueryCompletion(NoSuchQuery(QueryId))")]
+        #[should_panic(expected = "QueryCompletion(NoSuchQuery(QueryId(0)))")]
         async fn complete_one_shard_fails() {
             let mut args = TestComponentsArgs::default();
 
@@ -963,7 +1269,9 @@ mod tests {
                     if shard_id != ShardIndex::from(1) || req.route != RouteId::CompleteQuery {
                         futures::future::ok(HelperResponse::ok())
                     } else {
-                        futures::future::err(QueryCompletionError::NoSuchQuery(QueryId).into())
+                        futures::future::err(
+                            QueryCompletionError::NoSuchQuery(QueryId::from(0)).into(),
+                        )
                     }
                 })
             });
@@ -1000,6 +1308,76 @@ mod tests {
                 .await
                 .unwrap();
         }
+
+        #[tokio::test]
+        async fn complete_from_result_store() {
+            let dir = tempfile::tempdir().unwrap();
+            let open_store = || ResultStore::open(dir.path(), Duration::from_secs(60)).unwrap();
+            let mut t = TestComponents {
+                processor: Processor::default().with_result_store(open_store()),
+                ..TestComponents::default()
+            };
+
+            let query_id = QueryId::from(42);
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let running = t
+                .processor
+                .result_store
+                .as_ref()
+                .unwrap()
+                .save_on_completion(
+                    &IpaRuntime::current(),
+                    query_id,
+                    t.query_config,
+                    RunningQuery {
+                        result: rx,
+                        join_handle: IpaRuntime::current().spawn(async {}),
+                    },
+                );
+            t.processor
+                .queries
+                .inner
+                .lock()
+                .unwrap()
+                .insert(query_id, QueryState::Running(running));
+            let expected = vec![BA64::truncate_from(7_u128)];
+            tx.send(Ok(Box::new(expected.clone()))).unwrap();
+
+            // first completion takes the results from memory and removes the query
+            let result = t
+                .processor
+                .complete(query_id, t.shard_transport.clone_ref())
+                .await
+                .unwrap();
+            assert_eq!(expected.to_bytes(), result.to_bytes());
+            assert!(t.processor.queries.handle(query_id).status().is_none());
+
+            // results are still available from the store, even after a restart
+            for restart in [false, true] {
+                if restart {
+                    t.processor = Processor::default().with_result_store(open_store());
+                }
+                assert_eq!(
+                    QueryStatus::Completed,
+                    t.processor
+                        .query_status(t.shard_transport.clone_ref(), query_id)
+                        .await
+                        .unwrap()
+                );
+                let result = t
+                    .processor
+                    .complete(query_id, t.shard_transport.clone_ref())
+                    .await
+                    .unwrap();
+                assert_eq!(expected.to_bytes(), result.to_bytes());
+            }
+            assert!(matches!(
+                t.processor
+                    .complete(QueryId::from(43), t.shard_transport.clone_ref())
+                    .await,
+                Err(QueryCompletionError::NoSuchQuery(_))
+            ));
+        }
     }
 
     mod prepare {
@@ -1009,10 +1387,11 @@ mod tests {
         #[tokio::test]
         async fn happy_case() {
             let req = prepare_query();
+            let query_id = req.query_id;
             let t = TestComponents::new(TestComponentsArgs::default());
             assert!(matches!(
                 t.processor
-                    .query_status(t.shard_transport.clone_ref(), QueryId)
+                    .query_status(t.shard_transport.clone_ref(), query_id)
                     .await
                     .unwrap_err(),
                 QueryStatusError::NoSuchQuery(_)
@@ -1024,7 +1403,7 @@ mod tests {
             assert_eq!(
                 QueryStatus::AwaitingInputs,
                 t.processor
-                    .query_status(t.shard_transport, QueryId)
+                    .query_status(t.shard_transport, query_id)
                     .await
                     .unwrap()
             );
@@ -1125,9 +1504,15 @@ mod tests {
     }
 
     mod query_status {
+        use ipa_step::StepNarrow;
 
         use super::*;
-        use crate::{helpers::query::CompareStatusRequest, protocol::QueryId};
+        use crate::{
+            error::Error as ProtocolError,
+            helpers::{query::CompareStatusRequest, routing::RouteId},
+            protocol::{Gate, QueryId},
+            query::QueryCompletionError,
+        };
 
         /// * From the standpoint of leader shard in Helper 1
         /// * On query_status
@@ -1144,14 +1529,14 @@ mod tests {
                     match si {
                         FOURTH_SHARD => {
                             Err(ApiError::QueryStatus(QueryStatusError::DifferentStatus {
-                                query_id: QueryId,
+                                query_id: QueryId::from(1),
                                 my_status: QueryStatus::Completed,
                                 other_status: QueryStatus::Preparing,
                             }))
                         }
                         THIRD_SHARD => {
                             Err(ApiError::QueryStatus(QueryStatusError::DifferentStatus {
-                                query_id: QueryId,
+                                query_id: QueryId::from(1),
                                 my_status: QueryStatus::Running,
                                 other_status: QueryStatus::Preparing,
                             }))
@@ -1167,6 +1552,7 @@ mod tests {
             args.set_shard_handler(shard_handle);
             let t = TestComponents::new(args);
             let req = prepare_query();
+            let query_id = req.query_id;
             // Using prepare shard to set the inner state, but in reality we should be using prepare_helper
             // Prepare helper will use the shard_handle defined above though and will fail. The following
             // achieves the same state.
@@ -1179,7 +1565,7 @@ mod tests {
                 .unwrap();
             let r = t
                 .processor
-                .query_status(t.shard_transport.clone_ref(), QueryId)
+                .query_status(t.shard_transport.clone_ref(), query_id)
                 .await;
             if let Err(e) = r {
                 panic!("Unexpected error {e}");
@@ -1196,18 +1582,18 @@ mod tests {
         /// return an error despite other shards returning their status
         #[tokio::test]
         #[should_panic(
-            expected = "(ShardIndex(3), Rejected { dest: ShardIndex(3), inner: QueryStatus(NoSuchQuery(QueryId)) })"
+            expected = "(ShardIndex(3), Rejected { dest: ShardIndex(3), inner: QueryStatus(NoSuchQuery(QueryId(1))) })"
         )]
         async fn status_query_doesnt_exist() {
             fn shard_handle(si: ShardIndex) -> Arc<dyn RequestHandler<ShardIndex>> {
                 create_handler(move |_| async move {
                     if si == ShardIndex::from(3) {
                         Err(ApiError::QueryStatus(QueryStatusError::NoSuchQuery(
-                            QueryId,
+                            QueryId::from(1),
                         )))
                     } else if si == ShardIndex::from(2) {
                         Err(ApiError::QueryStatus(QueryStatusError::DifferentStatus {
-                            query_id: QueryId,
+                            query_id: QueryId::from(1),
                             my_status: QueryStatus::Running,
                             other_status: QueryStatus::Preparing,
                         }))
@@ -1223,6 +1609,7 @@ mod tests {
             args.set_shard_handler(shard_handle);
             let t = TestComponents::new(args);
             let req = prepare_query();
+            let query_id = req.query_id;
             // Using prepare shard to set the inner state, but in reality we should be using prepare_helper
             // Prepare_helper will use the shard_handle defined above though and will fail. The following
             // achieves the same state.
@@ -1234,7 +1621,7 @@ mod tests {
                 )
                 .unwrap();
             t.processor
-                .query_status(t.shard_transport.clone_ref(), QueryId)
+                .query_status(t.shard_transport.clone_ref(), query_id)
                 .await
                 .unwrap();
         }
@@ -1252,7 +1639,7 @@ mod tests {
                     .query_status(
                         t.shard_network
                             .transport(HelperIdentity::TWO, ShardIndex::from(1)),
-                        QueryId
+                        QueryId::from(1)
                     )
                     .await,
                 Err(QueryStatusError::NotLeader(_))
@@ -1267,7 +1654,7 @@ mod tests {
         #[tokio::test]
         async fn shard_not_leader() {
             let req = CompareStatusRequest {
-                query_id: QueryId,
+                query_id: QueryId::from(1),
                 status: QueryStatus::Running,
             };
             let t = TestComponents::new(TestComponentsArgs::default());
@@ -1282,6 +1669,117 @@ mod tests {
                 QueryStatusError::Leader
             ));
         }
+
+        /// * From the standpoint of leader shard in Helper 1
+        /// * On [`Processor::query_status`]
+        ///
+        /// If any shard reports a failure, the whole helper is considered failed.
+        #[tokio::test]
+        async fn failed_shard_status() {
+            fn shard_handle(si: ShardIndex) -> Arc<dyn RequestHandler<ShardIndex>> {
+                create_handler(move |_| async move {
+                    if si == ShardIndex::from(2) {
+                        Err(ApiError::QueryStatus(QueryStatusError::DifferentStatus {
+                            query_id: QueryId::from(1),
+                            my_status: QueryStatus::Failed {
+                                reason: "DZKP Validation failed".to_string(),
+                                step: None,
+                            },
+                            other_status: QueryStatus::AwaitingInputs,
+                        }))
+                    } else {
+                        Ok(HelperResponse::ok())
+                    }
+                })
+            }
+            let mut args = TestComponentsArgs {
+                shard_count: 4,
+                ..Default::default()
+            };
+            args.set_shard_handler(shard_handle);
+            let t = TestComponents::new(args);
+            let req = prepare_query();
+            let query_id = req.query_id;
+            t.processor
+                .prepare_shard(
+                    &t.shard_network
+                        .transport(HelperIdentity::ONE, ShardIndex::from(1)),
+                    req,
+                )
+                .unwrap();
+
+            assert_eq!(
+                QueryStatus::Failed {
+                    reason: "DZKP Validation failed".to_string(),
+                    step: None,
+                },
+                t.processor
+                    .query_status(t.shard_transport.clone_ref(), query_id)
+                    .await
+                    .unwrap()
+            );
+        }
+
+        /// A query that returned an error reports the reason, without asking other shards, and
+        /// the same error is returned on completion.
+        #[tokio::test]
+        async fn failed_query() {
+            let mut args = TestComponentsArgs::default();
+            args.set_shard_handler(|_| {
+                make_owned_handler(move |req, _| {
+                    assert_ne!(
+                        RouteId::QueryStatus,
+                        req.route,
+                        "shards must not be asked about a query that has failed on the leader"
+                    );
+                    futures::future::ok(HelperResponse::ok())
+                })
+            });
+            let t = TestComponents::new(args);
+            let query_id = t
+                .processor
+                .new_query(
+                    t.first_transport.clone_ref(),
+                    t.shard_transport.clone_ref(),
+                    t.query_config.into(),
+                )
+                .await
+                .unwrap()
+                .query_id;
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            t.processor
+                .queries
+                .handle(query_id)
+                .set_state(QueryState::Running(RunningQuery {
+                    result: rx,
+                    join_handle: IpaRuntime::current().spawn(async {}),
+                }))
+                .unwrap();
+            let step = Gate::default().narrow("verify");
+            tx.send(Err(ProtocolError::DZKPValidationFailed {
+                step: step.clone(),
+            }))
+            .unwrap();
+
+            assert_eq!(
+                QueryStatus::Failed {
+                    reason: "DZKP Validation failed".to_string(),
+                    step: Some(step.as_ref().to_string()),
+                },
+                t.processor
+                    .query_status(t.shard_transport.clone_ref(), query_id)
+                    .await
+                    .unwrap()
+            );
+            assert!(matches!(
+                t.processor
+                    .complete(query_id, t.shard_transport.clone_ref())
+                    .await,
+                Err(QueryCompletionError::ExecutionError(
+                    ProtocolError::DZKPValidationFailed { .. }
+                ))
+            ));
+        }
     }
 
     mod kill {
@@ -1295,7 +1793,7 @@ mod tests {
             query::{
                 processor::Processor,
                 state::{QueryState, RunningQuery},
-                QueryKillStatus,
+                QueryKillStatus, QueryStatus,
             },
             test_executor::run,
         };
@@ -1304,9 +1802,10 @@ mod tests {
         fn non_existent_query() {
             run(|| async {
                 let t = TestComponents::new(TestComponentsArgs::default());
+                let query_id = QueryId::from(1);
                 assert!(matches!(
-                    t.processor.kill(QueryId),
-                    Err(QueryKillStatus::NoSuchQuery(QueryId))
+                    t.processor.kill(query_id),
+                    Err(QueryKillStatus::NoSuchQuery(id)) if id == query_id
                 ));
             });
         }
@@ -1317,20 +1816,26 @@ mod tests {
                 let mut args = TestComponentsArgs::default();
                 args.mpc_handlers[0].take();
                 let t = TestComponents::new(args);
-                t.processor
+                let query_id = t
+                    .processor
                     .new_query(
                         t.first_transport.clone_ref(),
                         t.shard_transport.clone_ref(),
-                        t.query_config,
+                        t.query_config.into(),
                     )
                     .await
-                    .unwrap();
+                    .unwrap()
+                    .query_id;
 
-                t.processor.kill(QueryId).unwrap();
+                t.processor.kill(query_id).unwrap();
+                assert_eq!(
+                    Some(QueryStatus::TimedOut),
+                    t.processor.get_status(query_id)
+                );
 
                 // start query again - it should work because the query was killed
                 t.processor
-                    .new_query(t.first_transport, t.shard_transport, t.query_config)
+                    .new_query(t.first_transport, t.shard_transport, t.query_config.into())
                     .await
                     .unwrap();
             });
@@ -1351,8 +1856,9 @@ mod tests {
                         }
                     }
                 });
+                let query_id = QueryId::from(1);
                 processor.queries.inner.lock().unwrap().insert(
-                    QueryId,
+                    query_id,
                     QueryState::Running(RunningQuery {
                         result: rx,
                         join_handle: task,
@@ -1360,7 +1866,7 @@ mod tests {
                 );
 
                 assert_eq!(2, Arc::strong_count(&counter));
-                processor.kill(QueryId).unwrap();
+                processor.kill(query_id).unwrap();
                 while Arc::strong_count(&counter) > 1 {
                     tokio::task::yield_now().await;
                 }
@@ -1368,6 +1874,196 @@ mod tests {
         }
     }
 
+    mod deadlines {
+        use std::{
+            sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc,
+            },
+            time::Duration,
+        };
+
+        use tokio::time::{sleep, timeout};
+
+        use super::{create_handler, TestComponents, TestComponentsArgs};
+        use crate::{
+            executor::IpaRuntime,
+            helpers::{
+                query::QueryDeadlines, routing::RouteId, HelperResponse, RequestHandler, Transport,
+                TransportIdentity,
+            },
+            protocol::QueryId,
+            query::{
+                processor::Processor,
+                state::{QueryState, RunningQuery},
+                QueryCompletionError, QueryStatus,
+            },
+        };
+
+        /// Responds OK to every request and counts kill requests.
+        fn count_kills<I: TransportIdentity>(
+            kills: &Arc<AtomicUsize>,
+        ) -> Arc<dyn RequestHandler<I>> {
+            let kills = Arc::clone(kills);
+            create_handler(move |addr| {
+                if addr.route == RouteId::KillQuery {
+                    kills.fetch_add(1, Ordering::Relaxed);
+                }
+                async { Ok(HelperResponse::ok()) }
+            })
+        }
+
+        /// Creates a processor whose peer helpers and shards count kill requests they receive.
+        fn with_deadlines(kills: &Arc<AtomicUsize>, deadlines: QueryDeadlines) -> TestComponents {
+            let mut args = TestComponentsArgs::new(&count_kills(kills));
+            args.set_shard_handler(|_| count_kills(kills));
+            let mut t = TestComponents::new(args);
+            t.query_config = t.query_config.with_deadlines(deadlines);
+
+            t
+        }
+
+        async fn wait_until_forgotten(t: &TestComponents, query_id: QueryId) {
+            timeout(Duration::from_secs(5), async {
+                while t.processor.get_status(query_id).is_some() {
+                    sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap();
+        }
+
+        async fn new_query(t: &TestComponents) -> QueryId {
+            t.processor
+                .new_query(
+                    t.first_transport.clone_ref(),
+                    t.shard_transport.clone_ref(),
+                    t.query_config.into(),
+                )
+                .await
+                .unwrap()
+                .query_id
+        }
+
+        async fn wait_for_kills(kills: &AtomicUsize, expected: usize) {
+            timeout(Duration::from_secs(5), async {
+                while kills.load(Ordering::Relaxed) < expected {
+                    sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap();
+        }
+
+        #[tokio::test]
+        async fn inputs_deadline() {
+            let kills = Arc::new(AtomicUsize::new(0));
+            let t = with_deadlines(
+                &kills,
+                QueryDeadlines {
+                    inputs: Some(Duration::from_millis(10)),
+                    total: None,
+                },
+            );
+            let query_id = new_query(&t).await;
+
+            // H2, H3 and the other shard of this helper
+            wait_for_kills(&kills, 3).await;
+            assert_eq!(
+                QueryStatus::TimedOut,
+                t.processor
+                    .query_status(t.shard_transport.clone_ref(), query_id)
+                    .await
+                    .unwrap()
+            );
+        }
+
+        #[tokio::test]
+        async fn total_deadline_cancels_completion() {
+            let kills = Arc::new(AtomicUsize::new(0));
+            let t = with_deadlines(
+                &kills,
+                QueryDeadlines {
+                    inputs: Some(Duration::from_secs(60)),
+                    total: Some(Duration::from_millis(50)),
+                },
+            );
+            let query_id = new_query(&t).await;
+            let (_tx, rx) = tokio::sync::oneshot::channel();
+            t.processor
+                .queries
+                .handle(query_id)
+                .set_state(QueryState::Running(RunningQuery {
+                    result: rx,
+                    join_handle: IpaRuntime::current().spawn(std::future::pending()),
+                }))
+                .unwrap();
+
+            assert!(matches!(
+                t.processor
+                    .complete(query_id, t.shard_transport.clone_ref())
+                    .await,
+                Err(QueryCompletionError::TimedOut(id)) if id == query_id
+            ));
+            wait_for_kills(&kills, 3).await;
+        }
+
+        #[tokio::test]
+        async fn timed_out_query_is_forgotten() {
+            let kills = Arc::new(AtomicUsize::new(0));
+            let mut t = with_deadlines(
+                &kills,
+                QueryDeadlines {
+                    inputs: Some(Duration::from_millis(10)),
+                    total: None,
+                },
+            );
+            t.processor = Processor::default().with_terminated_query_ttl(Duration::from_millis(50));
+            let query_id = new_query(&t).await;
+
+            wait_for_kills(&kills, 3).await;
+            wait_until_forgotten(&t, query_id).await;
+        }
+
+        #[tokio::test]
+        async fn killed_query_is_timed_out_until_forgotten() {
+            let kills = Arc::new(AtomicUsize::new(0));
+            let mut t = with_deadlines(&kills, QueryDeadlines::default());
+            t.processor = Processor::default().with_terminated_query_ttl(Duration::from_millis(50));
+            let query_id = t.new_running_query().await;
+
+            t.processor.kill(query_id).unwrap();
+            assert_eq!(
+                Some(QueryStatus::TimedOut),
+                t.processor.get_status(query_id)
+            );
+            wait_until_forgotten(&t, query_id).await;
+        }
+
+        #[tokio::test]
+        async fn completed_query_is_not_cancelled() {
+            let kills = Arc::new(AtomicUsize::new(0));
+            let t = with_deadlines(
+                &kills,
+                QueryDeadlines {
+                    inputs: None,
+                    total: Some(Duration::from_millis(10)),
+                },
+            );
+            let query_id = t.new_running_query().await;
+
+            sleep(Duration::from_millis(50)).await;
+            assert_eq!(
+                QueryStatus::Completed,
+                t.processor
+                    .query_status(t.shard_transport.clone_ref(), query_id)
+                    .await
+                    .unwrap()
+            );
+            assert_eq!(0, kills.load(Ordering::Relaxed));
+        }
+    }
+
     mod e2e {
         use std::time::Duration;
 
@@ -1380,7 +2076,7 @@ mod tests {
                 boolean_array::{BA20, BA3, BA8},
                 Fp31, U128Conversions,
             },
-            helpers::query::{IpaQueryConfig, QueryType},
+            helpers::query::{DpMechanism, IpaQueryConfig, QueryDeadlines, QueryType},
             protocol::ipa_prf::OPRFIPAInputRow,
             secret_sharing::replicated::semi_honest,
             test_fixture::{ipa::TestRawDataRecord, Reconstruct, TestApp},
@@ -1432,6 +2128,35 @@ mod tests {
             Ok(())
         }
 
+        #[tokio::test]
+        async fn complete_concurrent_queries() -> Result<(), BoxError> {
+            let app = TestApp::default();
+            let inputs = [(4_u128, 5), (6, 3)];
+            let mut query_ids = Vec::new();
+            for (a, b) in inputs {
+                let query_id = app
+                    .start_query(
+                        vec![Fp31::truncate_from(a), Fp31::truncate_from(b)].into_iter(),
+                        test_multiply_config(),
+                    )
+                    .await?;
+                query_ids.push(query_id);
+            }
+            assert_ne!(query_ids[0], query_ids[1]);
+
+            // complete them in the reverse order to make sure they don't interfere
+            for (query_id, (a, b)) in query_ids.into_iter().zip(inputs).rev() {
+                let results = app.complete_query(query_id).await?.map(|bytes| {
+                    semi_honest::AdditiveShare::<Fp31>::from_byte_slice_unchecked(&bytes)
+                        .collect::<Vec<_>>()
+                });
+
+                assert_eq!(&[Fp31::truncate_from(a * b)] as &[_], results.reconstruct());
+            }
+
+            Ok(())
+        }
+
         #[tokio::test]
         async fn complete_query_ipa() -> Result<(), BoxError> {
             let app = TestApp::default();
@@ -1497,10 +2222,11 @@ mod tests {
                             per_user_credit_cap: 8,
                             max_breakdown_key: 3,
                             attribution_window_seconds: None,
-                            with_dp: 0,
-                            epsilon: 5.0,
+                            dp_mechanism: DpMechanism::NoDp,
                             plaintext_match_keys: true,
+                            ..Default::default()
                         }),
+                        deadlines: QueryDeadlines::default(),
                     },
                 )
                 .await?;
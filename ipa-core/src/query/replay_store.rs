@@ -0,0 +1,383 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fmt::{Debug, Formatter},
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use ipa_metrics::counter;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use typenum::Unsigned;
+
+use crate::{
+    error::Error,
+    ff::Serializable,
+    hpke::PrivateKeyRegistry,
+    protocol::Gate,
+    report::hybrid::{KeyIdentifier, KeyedUniqueTag, UniqueBytes, UniqueTag},
+    sync::{Arc, Mutex},
+    telemetry::metrics::REPLAYED_REPORTS,
+};
+
+const TAGS_EXTENSION: &str = "tags";
+const TAG_SIZE: usize = <UniqueTag as Serializable>::Size::USIZE;
+
+type Tag = [u8; TAG_SIZE];
+
+/// Remembers unique tags of the reports used by earlier queries, so a report collector can't
+/// replay an encrypted report in another query and have it counted twice.
+///
+/// Tags are appended to one file per HPKE key inside the store directory. Reports encrypted under
+/// an expired key are rejected anyway, so once a key expires its tags are forgotten and the file
+/// is removed.
+///
+/// All tags of the keys that have not expired are also kept in memory, which takes a few dozen
+/// bytes per report, so memory and disk usage of the store grow with the number of reports used
+/// until their key expires. The store is not bounded otherwise: tags of keys that have no end of
+/// validity are kept forever, so helpers that remember tags should only be given keys with a
+/// `not_after` date.
+pub struct ReplayStore {
+    dir: PathBuf,
+    seen: Mutex<HashMap<KeyIdentifier, HashSet<Tag>>>,
+    /// Makes sure appends to the tag files don't interleave
+    writer: tokio::sync::Mutex<()>,
+}
+
+impl Debug for ReplayStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ReplayStore[{}]", self.dir.display())
+    }
+}
+
+impl ReplayStore {
+    /// Opens the store in `dir`, creating the directory if it does not exist, and reloads tags
+    /// of all the keys in `key_registry` that have not expired yet.
+    ///
+    /// ## Errors
+    /// If the store directory can't be created or read.
+    ///
+    /// ## Panics
+    /// If the mutex guarding the set of seen tags is poisoned.
+    pub fn open<P: Into<PathBuf>, R: PrivateKeyRegistry>(
+        dir: P,
+        key_registry: &R,
+    ) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut seen = HashMap::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(OsStr::to_str) != Some(TAGS_EXTENSION) {
+                continue;
+            }
+            let Some(key_id) = path
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .and_then(|stem| stem.parse::<KeyIdentifier>().ok())
+            else {
+                tracing::warn!("skipping unexpected file {}", path.display());
+                continue;
+            };
+
+            seen.insert(key_id, read_tags(&path)?);
+        }
+
+        let store = Self {
+            dir,
+            seen: Mutex::new(seen),
+            writer: tokio::sync::Mutex::new(()),
+        };
+        store.prune(key_registry)?;
+
+        tracing::info!(
+            "reloaded {} report tags from {}",
+            store
+                .seen
+                .lock()
+                .unwrap()
+                .values()
+                .map(HashSet::len)
+                .sum::<usize>(),
+            store.dir.display()
+        );
+
+        Ok(store)
+    }
+
+    /// Forgets tags of the keys that have expired according to `key_registry`.
+    ///
+    /// ## Errors
+    /// If tags of an expired key can't be removed from disk.
+    ///
+    /// ## Panics
+    /// If the mutex guarding the set of seen tags is poisoned.
+    pub fn prune<R: PrivateKeyRegistry>(&self, key_registry: &R) -> io::Result<()> {
+        let now = SystemTime::now();
+        let mut seen = self.seen.lock().unwrap();
+        let expired = seen
+            .keys()
+            .copied()
+            .filter(|&key_id| key_registry.is_expired(key_id, now))
+            .collect::<Vec<_>>();
+
+        for key_id in expired {
+            match std::fs::remove_file(self.path(key_id)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            seen.remove(&key_id);
+            tracing::info!("key {key_id} has expired, forgetting its report tags");
+        }
+
+        Ok(())
+    }
+
+    /// Marks `tags` as used by the current query, so no other query can use them.
+    ///
+    /// The tags are only kept in memory until [`ReservedTags::persist`] is called. If the
+    /// reservation is dropped before that, for example because the query failed, they can be
+    /// used again.
+    ///
+    /// ## Errors
+    /// If any of the tags has already been used by another query. Nothing is reserved in this
+    /// case, and the error is reported at `step`.
+    ///
+    /// ## Panics
+    /// If the mutex guarding the set of seen tags is poisoned.
+    pub fn reserve(
+        self: &Arc<Self>,
+        tags: Vec<KeyedUniqueTag>,
+        step: &Gate,
+    ) -> Result<ReservedTags, Error> {
+        let mut seen = self.seen.lock().unwrap();
+        let replayed = tags
+            .iter()
+            .filter(|tag| {
+                seen.get(&tag.key_id())
+                    .is_some_and(|key_tags| key_tags.contains(&tag.unique_bytes()))
+            })
+            .count();
+        if replayed > 0 {
+            counter!(REPLAYED_REPORTS, u64::try_from(replayed).unwrap());
+            return Err(Error::ReplayedReports {
+                count: replayed,
+                step: step.clone(),
+            });
+        }
+
+        for tag in &tags {
+            seen.entry(tag.key_id())
+                .or_default()
+                .insert(tag.unique_bytes());
+        }
+
+        Ok(ReservedTags {
+            store: Arc::clone(self),
+            tags,
+        })
+    }
+
+    fn release(&self, tags: &[KeyedUniqueTag]) {
+        let mut seen = self.seen.lock().unwrap();
+        for tag in tags {
+            if let Some(key_tags) = seen.get_mut(&tag.key_id()) {
+                key_tags.remove(&tag.unique_bytes());
+            }
+        }
+    }
+
+    fn path(&self, key_id: KeyIdentifier) -> PathBuf {
+        self.dir.join(format!("{key_id}.{TAGS_EXTENSION}"))
+    }
+}
+
+/// Tags reserved by a query with [`ReplayStore::reserve`].
+pub struct ReservedTags {
+    store: Arc<ReplayStore>,
+    tags: Vec<KeyedUniqueTag>,
+}
+
+impl ReservedTags {
+    /// Writes the reserved tags to disk, so they can't be used again after the helper restarts.
+    ///
+    /// ## Errors
+    /// If writing to the store directory fails. The tags are released in this case.
+    pub async fn persist(mut self) -> io::Result<()> {
+        let mut by_key = HashMap::<_, Vec<u8>>::new();
+        for tag in &self.tags {
+            by_key
+                .entry(tag.key_id())
+                .or_default()
+                .extend_from_slice(&tag.unique_bytes());
+        }
+
+        let _guard = self.store.writer.lock().await;
+        for (key_id, bytes) in by_key {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.store.path(key_id))
+                .await?;
+            file.write_all(&bytes).await?;
+            file.sync_data().await?;
+        }
+        self.tags.clear();
+
+        Ok(())
+    }
+}
+
+impl Drop for ReservedTags {
+    fn drop(&mut self) {
+        self.store.release(&self.tags);
+    }
+}
+
+/// Reads tags from `path`. A trailing partial tag is a leftover of a write that did not finish,
+/// it is removed from the file.
+fn read_tags(path: &Path) -> io::Result<HashSet<Tag>> {
+    let bytes = std::fs::read(path)?;
+    let chunks = bytes.chunks_exact(TAG_SIZE);
+    if !chunks.remainder().is_empty() {
+        tracing::warn!("removing a partially written tag from {}", path.display());
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(u64::try_from(bytes.len() - chunks.remainder().len()).unwrap())?;
+    }
+
+    Ok(chunks.map(|chunk| Tag::try_from(chunk).unwrap()).collect())
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use rand::{rngs::StdRng, Rng};
+    use rand_core::SeedableRng;
+
+    use crate::{
+        error::Error,
+        ff::Serializable,
+        hpke::{KeyPair, KeyRegistry, KeyValidity, DEFAULT_EXPIRED_KEY_GRACE_PERIOD},
+        protocol::Gate,
+        query::replay_store::ReplayStore,
+        report::hybrid::KeyedUniqueTag,
+        sync::Arc,
+    };
+
+    fn tag<R: Rng>(key_id: u8, rng: &mut R) -> KeyedUniqueTag {
+        let mut buf = [0u8; 17];
+        rng.fill(&mut buf[..]);
+        buf[0] = key_id;
+        KeyedUniqueTag::deserialize(&buf.into()).unwrap()
+    }
+
+    fn registry(expired: &[u8]) -> KeyRegistry<KeyPair> {
+        let mut rng = StdRng::seed_from_u64(1);
+        KeyRegistry::from_entries((0..2).map(|key_id| {
+            let validity = if expired.contains(&key_id) {
+                KeyValidity {
+                    not_before: None,
+                    not_after: Some(
+                        SystemTime::now()
+                            - DEFAULT_EXPIRED_KEY_GRACE_PERIOD
+                            - Duration::from_secs(60),
+                    ),
+                }
+            } else {
+                KeyValidity::default()
+            };
+            (key_id, KeyPair::gen(&mut rng), validity)
+        }))
+    }
+
+    #[tokio::test]
+    async fn rejects_replay_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rng = StdRng::seed_from_u64(42);
+        let tags = vec![tag(0, &mut rng), tag(1, &mut rng)];
+
+        let store = Arc::new(ReplayStore::open(dir.path(), &registry(&[])).unwrap());
+        store
+            .reserve(tags.clone(), &Gate::default())
+            .unwrap()
+            .persist()
+            .await
+            .unwrap();
+        drop(store);
+
+        let store = Arc::new(ReplayStore::open(dir.path(), &registry(&[])).unwrap());
+        let Err(Error::ReplayedReports { count: 1, .. }) =
+            store.reserve(vec![tag(0, &mut rng), tags[1].clone()], &Gate::default())
+        else {
+            panic!("expected replayed report to be rejected");
+        };
+        store
+            .reserve(vec![tag(1, &mut rng)], &Gate::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn released_if_not_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rng = StdRng::seed_from_u64(42);
+        let tags = vec![tag(0, &mut rng)];
+
+        let store = Arc::new(ReplayStore::open(dir.path(), &registry(&[])).unwrap());
+        let reserved = store.reserve(tags.clone(), &Gate::default()).unwrap();
+        assert!(matches!(
+            store.reserve(tags.clone(), &Gate::default()),
+            Err(Error::ReplayedReports { count: 1, .. })
+        ));
+
+        drop(reserved);
+        store.reserve(tags, &Gate::default()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn forgets_expired_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rng = StdRng::seed_from_u64(42);
+        let tags = vec![tag(0, &mut rng), tag(1, &mut rng)];
+
+        let store = Arc::new(ReplayStore::open(dir.path(), &registry(&[])).unwrap());
+        store
+            .reserve(tags.clone(), &Gate::default())
+            .unwrap()
+            .persist()
+            .await
+            .unwrap();
+        store.prune(&registry(&[1])).unwrap();
+        assert!(!dir.path().join("1.tags").exists());
+        store
+            .reserve(vec![tags[1].clone()], &Gate::default())
+            .unwrap();
+        assert!(store
+            .reserve(vec![tags[0].clone()], &Gate::default())
+            .is_err());
+    }
+
+    #[test]
+    fn partial_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut bytes = vec![7u8; 16];
+        bytes.extend_from_slice(&[1, 2, 3]);
+        std::fs::write(dir.path().join("0.tags"), &bytes).unwrap();
+
+        let store = Arc::new(ReplayStore::open(dir.path(), &registry(&[])).unwrap());
+        assert_eq!(
+            16,
+            std::fs::metadata(dir.path().join("0.tags")).unwrap().len()
+        );
+
+        let mut buf = [7u8; 17];
+        buf[0] = 0;
+        let replayed = KeyedUniqueTag::deserialize(&buf.into()).unwrap();
+        assert!(store.reserve(vec![replayed], &Gate::default()).is_err());
+    }
+}
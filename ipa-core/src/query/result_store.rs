@@ -0,0 +1,363 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt::{Debug, Formatter},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt, sync::oneshot};
+
+use crate::{
+    executor::IpaRuntime,
+    helpers::query::QueryConfig,
+    protocol::QueryId,
+    query::{executor::Result as ProtocolResult, state::RunningQuery},
+    sync::{Arc, Mutex},
+};
+
+const RESULT_EXTENSION: &str = "json";
+const TMP_EXTENSION: &str = "tmp";
+/// How often results past the retention period are removed from disk.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Everything this helper knows about a completed query, as written to disk.
+#[derive(Serialize, Deserialize)]
+struct StoredQuery {
+    query_id: QueryId,
+    config: QueryConfig,
+    /// Seconds since Unix epoch
+    completed_at: u64,
+    #[serde(with = "hex")]
+    result: Vec<u8>,
+}
+
+/// Result shares loaded back from the [`ResultStore`].
+#[derive(Debug)]
+pub struct StoredResult(Vec<u8>);
+
+impl ProtocolResult for StoredResult {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+}
+
+/// Keeps the results of completed queries on disk, so report collectors can still retrieve them
+/// after the helper restarts. Each query is written into its own file inside the store directory,
+/// and results older than the retention period are no longer served and are removed from
+/// disk when the store is opened and then periodically, see [`ResultStore::prune_periodically`].
+pub struct ResultStore {
+    dir: PathBuf,
+    retention: Duration,
+    completed: Mutex<HashMap<QueryId, SystemTime>>,
+}
+
+impl Debug for ResultStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ResultStore[{}]", self.dir.display())
+    }
+}
+
+impl ResultStore {
+    /// Opens the store in `dir`, creating the directory if it does not exist, and reloads
+    /// all the queries that completed within the `retention` period.
+    ///
+    /// ## Errors
+    /// If the store directory can't be created or read.
+    pub fn open<P: Into<PathBuf>>(dir: P, retention: Duration) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let now = SystemTime::now();
+        let mut completed = HashMap::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(OsStr::to_str) {
+                Some(RESULT_EXTENSION) => {}
+                // leftover from a write that did not finish
+                Some(TMP_EXTENSION) => {
+                    std::fs::remove_file(&path)?;
+                    continue;
+                }
+                _ => continue,
+            }
+
+            let stored = match read_stored(&path) {
+                Ok(stored) => stored,
+                Err(e) => {
+                    tracing::warn!("skipping query results in {}: {e}", path.display());
+                    continue;
+                }
+            };
+            let completed_at = SystemTime::UNIX_EPOCH + Duration::from_secs(stored.completed_at);
+            if is_expired(completed_at, retention, now) {
+                std::fs::remove_file(&path)?;
+            } else {
+                completed.insert(stored.query_id, completed_at);
+            }
+        }
+
+        tracing::info!(
+            "reloaded {} completed queries from {}",
+            completed.len(),
+            dir.display()
+        );
+
+        Ok(Self {
+            dir,
+            retention,
+            completed: Mutex::new(completed),
+        })
+    }
+
+    /// Returns `true` if results for `query_id` are stored and still within the retention period.
+    ///
+    /// ## Panics
+    /// If the mutex guarding the index of stored queries is poisoned.
+    pub fn contains(&self, query_id: QueryId) -> bool {
+        self.completed
+            .lock()
+            .unwrap()
+            .get(&query_id)
+            .is_some_and(|&completed_at| {
+                !is_expired(completed_at, self.retention, SystemTime::now())
+            })
+    }
+
+    /// Reads the stored results for `query_id`, if they are still within the retention period.
+    ///
+    /// ## Errors
+    /// If the results file can't be read.
+    pub async fn load(&self, query_id: QueryId) -> io::Result<Option<StoredResult>> {
+        if !self.contains(query_id) {
+            return Ok(None);
+        }
+        let bytes = fs::read(self.path(query_id, RESULT_EXTENSION)).await?;
+        let stored = serde_json::from_slice::<StoredQuery>(&bytes)?;
+
+        Ok(Some(StoredResult(stored.result)))
+    }
+
+    /// Writes serialized result shares of `query_id` to disk. The results are written into a temporary file first
+    /// and then moved in place, so a crash in the middle of writing never leaves a partial
+    /// result behind.
+    ///
+    /// ## Errors
+    /// If writing to the store directory fails.
+    ///
+    /// ## Panics
+    /// If the mutex guarding the index of stored queries is poisoned.
+    pub async fn save(
+        &self,
+        query_id: QueryId,
+        config: QueryConfig,
+        result: Vec<u8>,
+    ) -> io::Result<()> {
+        let completed_at = SystemTime::now();
+        let stored = StoredQuery {
+            query_id,
+            config,
+            completed_at: completed_at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_err(io::Error::other)?
+                .as_secs(),
+            result,
+        };
+
+        let tmp_path = self.path(query_id, TMP_EXTENSION);
+        let mut file = fs::File::create(&tmp_path).await?;
+        file.write_all(&serde_json::to_vec(&stored)?).await?;
+        file.sync_all().await?;
+        fs::rename(&tmp_path, self.path(query_id, RESULT_EXTENSION)).await?;
+        // the rename itself is only durable once the directory entry is flushed
+        sync_dir(&self.dir).await?;
+
+        self.completed
+            .lock()
+            .unwrap()
+            .insert(query_id, completed_at);
+
+        Ok(())
+    }
+
+    /// Removes the results that are past the retention period from disk.
+    ///
+    /// ## Errors
+    /// If an expired results file can't be removed.
+    ///
+    /// ## Panics
+    /// If the mutex guarding the index of stored queries is poisoned.
+    pub async fn prune(&self) -> io::Result<()> {
+        let now = SystemTime::now();
+        let mut expired = Vec::new();
+        self.completed
+            .lock()
+            .unwrap()
+            .retain(|&query_id, &mut completed_at| {
+                let keep = !is_expired(completed_at, self.retention, now);
+                if !keep {
+                    expired.push(query_id);
+                }
+                keep
+            });
+
+        for query_id in expired {
+            match fs::remove_file(self.path(query_id, RESULT_EXTENSION)).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Calls [`ResultStore::prune`] every hour, for as long as the store is in use.
+    pub fn prune_periodically(self: &Arc<Self>, runtime: &IpaRuntime) {
+        let store = Arc::downgrade(self);
+        drop(runtime.spawn(async move {
+            loop {
+                ::tokio::time::sleep(PRUNE_INTERVAL).await;
+                let Some(store) = store.upgrade() else {
+                    break;
+                };
+                if let Err(e) = store.prune().await {
+                    tracing::warn!("failed to remove expired query results: {e}");
+                }
+            }
+        }));
+    }
+
+    /// Makes the results of `query` persisted as soon as it completes, before they are handed
+    /// over to whoever is waiting for them. Failed queries are not persisted.
+    pub fn save_on_completion(
+        self: &Arc<Self>,
+        runtime: &IpaRuntime,
+        query_id: QueryId,
+        config: QueryConfig,
+        query: RunningQuery,
+    ) -> RunningQuery {
+        let RunningQuery {
+            result,
+            join_handle,
+        } = query;
+        let (tx, rx) = oneshot::channel();
+        let store = Arc::clone(self);
+
+        // Dropping the handle does not terminate the task. If the query gets aborted, the
+        // sender is dropped without sending and this task ends too.
+        drop(runtime.spawn(async move {
+            if let Ok(result) = result.await {
+                if let Ok(output) = &result {
+                    if let Err(e) = store.save(query_id, config, output.to_bytes()).await {
+                        tracing::error!("failed to persist results of query {query_id}: {e}");
+                    }
+                }
+                // nobody may be waiting for the results anymore if the query was killed
+                let _ = tx.send(result);
+            }
+        }));
+
+        RunningQuery {
+            result: rx,
+            join_handle,
+        }
+    }
+
+    fn path(&self, query_id: QueryId, extension: &str) -> PathBuf {
+        self.dir.join(format!("{query_id}.{extension}"))
+    }
+}
+
+fn read_stored(path: &Path) -> io::Result<StoredQuery> {
+    Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+}
+
+#[cfg(unix)]
+async fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir).await?.sync_all().await
+}
+
+/// Directories can't be opened as files on other platforms.
+#[cfg(not(unix))]
+#[allow(clippy::unused_async)]
+async fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn is_expired(completed_at: SystemTime, retention: Duration, now: SystemTime) -> bool {
+    completed_at + retention < now
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        ff::{Fp31, U128Conversions},
+        helpers::query::{QueryConfig, QueryType},
+        protocol::QueryId,
+        query::{executor::Result as ProtocolResult, result_store::ResultStore},
+    };
+
+    fn config() -> QueryConfig {
+        QueryConfig::new(QueryType::TestMultiply, crate::ff::FieldType::Fp31, 1).unwrap()
+    }
+
+    #[tokio::test]
+    async fn survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let query_id = QueryId::from(7);
+        let result = vec![Fp31::truncate_from(3_u128), Fp31::truncate_from(5_u128)];
+
+        let store = ResultStore::open(dir.path(), Duration::from_secs(60)).unwrap();
+        assert!(store.load(query_id).await.unwrap().is_none());
+        store
+            .save(query_id, config(), result.to_bytes())
+            .await
+            .unwrap();
+        assert!(store.contains(query_id));
+        drop(store);
+
+        let store = ResultStore::open(dir.path(), Duration::from_secs(60)).unwrap();
+        assert!(store.contains(query_id));
+        assert!(!store.contains(QueryId::from(8)));
+        assert_eq!(
+            result.to_bytes(),
+            store.load(query_id).await.unwrap().unwrap().to_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_results_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let query_id = QueryId::from(7);
+
+        let store = ResultStore::open(dir.path(), Duration::ZERO).unwrap();
+        store.save(query_id, config(), vec![1]).await.unwrap();
+        // leftover of an interrupted write
+        std::fs::write(dir.path().join("8.tmp"), b"partial").unwrap();
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+
+        let store = ResultStore::open(dir.path(), Duration::ZERO).unwrap();
+        assert!(!store.contains(query_id));
+        assert!(store.load(query_id).await.unwrap().is_none());
+        assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[tokio::test]
+    async fn prune_removes_expired_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let query_id = QueryId::from(7);
+
+        let store = ResultStore::open(dir.path(), Duration::ZERO).unwrap();
+        store.save(query_id, config(), vec![1]).await.unwrap();
+        assert_eq!(1, std::fs::read_dir(dir.path()).unwrap().count());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        store.prune().await.unwrap();
+        assert!(!store.contains(query_id));
+        assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+    }
+}
@@ -1,111 +1,182 @@
 use std::{
+    collections::HashSet,
     convert::{Infallible, Into},
     marker::PhantomData,
     ops::Add,
     sync::Arc,
 };
 
-use futures::{StreamExt, TryStreamExt};
+use futures::{
+    future::{self, try_join},
+    stream, StreamExt, TryStreamExt,
+};
 use generic_array::ArrayLength;
+use ipa_metrics::counter;
+use subtle::ConstantTimeEq;
+use tokio::try_join;
+use typenum::U16;
 
 use super::QueryResult;
 use crate::{
     error::{Error, LengthError},
     ff::{
         boolean::Boolean,
-        boolean_array::{BooleanArray, BA3, BA32, BA8},
+        boolean_array::{BooleanArray, BA16, BA3, BA32, BA5, BA64, BA8},
         curve_points::RP25519,
         ec_prime_field::Fp25519,
         Serializable, U128Conversions,
     },
     helpers::{
-        query::{DpMechanism, HybridQueryParams, QueryConfig, QuerySize},
+        hashing::{BytesHasher, Hash},
+        query::{DpMechanism, HistogramOutput, HybridQueryParams, QueryConfig, QuerySize},
         setup_cross_shard_prss,
         stream::TryFlattenItersExt,
-        BodyStream, Gateway, LengthDelimitedStream,
+        BodyStream, Direction, Gateway, LengthDelimitedStream, TotalRecords,
     },
     hpke::PrivateKeyRegistry,
     protocol::{
-        basics::{shard_fin::FinalizerContext, BooleanArrayMul, BooleanProtocols, Reveal},
+        basics::{
+            shard_fin::FinalizerContext, BooleanArrayMul, BooleanProtocols, Reveal, ShareKnownValue,
+        },
         context::{
-            DZKPUpgraded, MacUpgraded, ShardedContext, ShardedMaliciousContext, UpgradableContext,
+            reshard_try_stream_for_each, Context, DZKPUpgraded, MacUpgraded, ShardedContext,
+            ShardedMaliciousContext, UpgradableContext,
         },
+        dp::{dp_threshold_histogram, sparse_histogram_threshold},
         hybrid::{
             hybrid_protocol,
-            oprf::{CONV_CHUNK, PRF_CHUNK},
+            oprf::{BreakdownKey, CONV_CHUNK, PRF_CHUNK},
             step::HybridStep,
         },
-        ipa_prf::{oprf_padding::PaddingParameters, prf_eval::PrfSharing, shuffle::ShardedShuffle},
+        ipa_prf::{
+            oprf_padding::validate_padding_parameters, prf_eval::PrfSharing,
+            shuffle::ShardedShuffle,
+        },
         prss::{Endpoint, FromPrss},
         step::ProtocolStep::Hybrid,
-        Gate,
+        Gate, RecordId,
     },
-    query::runner::reshard_tag::reshard_aad,
-    report::hybrid::{
-        EncryptedHybridReport, IndistinguishableHybridReport, UniqueTag, UniqueTagValidator,
+    query::{runner::reshard_tag::reshard_aad, CollectorBudget, ReplayStore, SiteEpoch},
+    report::{
+        hybrid::{
+            EncryptedHybridReport, HybridReport, HybridTimestamp, IndistinguishableHybridReport,
+            KeyedUniqueTag, PrfHybridReport, UniqueTagValidator,
+        },
+        hybrid_info::HybridConversionInfo,
     },
     secret_sharing::{
-        replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, TransposeFrom,
-        Vectorizable,
+        replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, FieldSimd,
+        TransposeFrom, Vectorizable,
     },
     seq_join::seq_join,
     sharding::{ShardConfiguration, Sharded},
+    telemetry::{memory, metrics::INCOMPATIBLE_PRIVACY_REPORTS},
 };
 
 #[allow(dead_code)]
-pub struct Query<C, HV, R: PrivateKeyRegistry> {
+pub struct Query<C, BK, V, HV, R: PrivateKeyRegistry> {
     config: HybridQueryParams,
     key_registry: Arc<R>,
-    phantom_data: PhantomData<(C, HV)>,
+    replay_store: Option<Arc<ReplayStore>>,
+    privacy_budget: Option<CollectorBudget>,
+    phantom_data: PhantomData<(C, BK, V, HV)>,
 }
 
 #[allow(dead_code)]
-impl<C, HV, R: PrivateKeyRegistry> Query<C, HV, R> {
+impl<C, BK, V, HV, R: PrivateKeyRegistry> Query<C, BK, V, HV, R> {
     pub fn new(query_params: HybridQueryParams, key_registry: Arc<R>) -> Self {
         Self {
             config: query_params,
             key_registry,
+            replay_store: None,
+            privacy_budget: None,
             phantom_data: PhantomData,
         }
     }
+
+    /// Reject reports that were used by earlier queries, according to `replay_store`, and
+    /// remember the reports used by this query if it succeeds.
+    #[must_use]
+    pub fn with_replay_store(mut self, replay_store: Option<Arc<ReplayStore>>) -> Self {
+        self.replay_store = replay_store;
+        self
+    }
+
+    /// Charge the query to `privacy_budget` for every site and epoch that its conversion reports
+    /// belong to, and refuse it if any of them does not have enough budget left.
+    #[must_use]
+    pub fn with_privacy_budget(mut self, privacy_budget: Option<CollectorBudget>) -> Self {
+        self.privacy_budget = privacy_budget;
+        self
+    }
 }
 
-impl<C, HV, R> Query<C, HV, R>
+impl<C, BK, V, HV, R> Query<C, BK, V, HV, R>
 where
     C: UpgradableContext
         + ShardedShuffle
         + ShardedContext
         + FinalizerContext<FinalizingContext = DZKPUpgraded<C>>,
+    BK: BooleanArray + U128Conversions,
+    V: BooleanArray + U128Conversions,
     HV: BooleanArray + U128Conversions,
     <HV as Serializable>::Size: Add<<HV as Serializable>::Size, Output: ArrayLength>,
     R: PrivateKeyRegistry,
+    Replicated<BK>: Serializable,
+    Replicated<V>: Serializable,
+    <Replicated<BK> as Serializable>::Size: Add<U16>,
+    <<Replicated<BK> as Serializable>::Size as Add<U16>>::Output: ArrayLength,
+    <Replicated<V> as Serializable>::Size: Add<U16>,
+    <<Replicated<V> as Serializable>::Size as Add<U16>>::Output: ArrayLength,
+    PrfHybridReport<BK, V>: Serializable,
     Replicated<Boolean, CONV_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, CONV_CHUNK>,
     Replicated<Fp25519, PRF_CHUNK>:
         PrfSharing<MacUpgraded<C, Fp25519>, PRF_CHUNK, Field = Fp25519> + FromPrss,
     Replicated<RP25519, PRF_CHUNK>:
         Reveal<MacUpgraded<C, Fp25519>, Output = <RP25519 as Vectorizable<PRF_CHUNK>>::Array>,
-    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>
+        + Reveal<DZKPUpgraded<C>, Output = <Boolean as Vectorizable<1>>::Array>,
     Replicated<HV>: Serializable,
-    Replicated<BA8>: BooleanArrayMul<DZKPUpgraded<C>>
-        + Reveal<DZKPUpgraded<C>, Output = <BA8 as Vectorizable<1>>::Array>,
-    BitDecomposed<Replicated<Boolean, 256>>:
-        for<'bt> TransposeFrom<&'bt Vec<Replicated<HV>>, Error = LengthError>,
-    BitDecomposed<Replicated<Boolean, 256>>:
-        for<'bt> TransposeFrom<&'bt [Replicated<HV>; 256], Error = Infallible>,
-    Vec<Replicated<HV>>:
-        for<'bt> TransposeFrom<&'bt BitDecomposed<Replicated<Boolean, 256>>, Error = LengthError>,
+    Replicated<BK>: BooleanArrayMul<DZKPUpgraded<C>>
+        + Reveal<DZKPUpgraded<C>, Output = <BK as Vectorizable<1>>::Array>,
+    Replicated<V>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<HybridTimestamp>: BooleanArrayMul<DZKPUpgraded<C>>,
     DZKPUpgraded<C>: ShardedContext,
 {
+    /// Runs the hybrid protocol over reports with breakdown keys of type `BK` and values of
+    /// type `V`, producing a histogram with `B` buckets.
+    ///
+    /// ## Errors
+    /// If the query configuration is not supported, if any of the reports were encrypted with
+    /// a breakdown key or value width different from `BK` and `V`, if any of the reports were
+    /// already used by an earlier query, if the query exceeds the privacy budget of the report
+    /// collector, or if the protocol fails.
+    #[allow(clippy::too_many_lines)]
     #[tracing::instrument("hybrid_query", skip_all, fields(sz=%query_size))]
-    pub async fn execute(
+    pub async fn execute<const B: usize>(
         self,
         ctx: C,
         query_size: QuerySize,
         input_stream: BodyStream,
-    ) -> Result<Vec<Replicated<HV>>, Error> {
+    ) -> Result<Vec<Replicated<HV>>, Error>
+    where
+        BK: BreakdownKey<B>,
+        Boolean: FieldSimd<B>,
+        Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+        BitDecomposed<Replicated<Boolean, B>>:
+            for<'bt> TransposeFrom<&'bt [Replicated<V>; B], Error = Infallible>,
+        BitDecomposed<Replicated<Boolean, B>>:
+            for<'bt> TransposeFrom<&'bt Vec<Replicated<HV>>, Error = LengthError>,
+        BitDecomposed<Replicated<Boolean, B>>:
+            for<'bt> TransposeFrom<&'bt [Replicated<HV>; B], Error = Infallible>,
+        Vec<Replicated<HV>>:
+            for<'bt> TransposeFrom<&'bt BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
+    {
         let Self {
             config,
             key_registry,
+            replay_store,
+            privacy_budget,
             phantom_data: _,
         } = &self;
 
@@ -119,55 +190,356 @@ where
             ));
         }
 
-        let stream = LengthDelimitedStream::<EncryptedHybridReport<BA8, BA3>, _>::new(input_stream)
+        if usize::try_from(config.max_breakdown_key).map_or(true, |max| max > B) {
+            return Err(Error::Unsupported(format!(
+                "max_breakdown_key {} does not fit into {} breakdown key bits",
+                config.max_breakdown_key,
+                BK::BITS,
+            )));
+        }
+
+        if let Some(dimensions) = config.breakdown_dimensions {
+            if dimensions.encoded_bits() > BK::BITS {
+                return Err(Error::Unsupported(format!(
+                    "breakdown dimensions {dimensions} do not fit into {} breakdown key bits",
+                    BK::BITS,
+                )));
+            }
+        }
+
+        if config.per_user_credit_cap == 0 {
+            return Err(Error::Unsupported(
+                "per_user_credit_cap must be greater than zero".to_string(),
+            ));
+        }
+
+        config
+            .validate()
+            .map_err(|e| Error::Unsupported(e.to_string()))?;
+
+        validate_padding_parameters(
+            ctx.narrow(&HybridStep::ValidatePaddingParameters),
+            &config.padding_params(),
+        )
+        .await?;
+
+        // Input is read past the query size, so oversized inputs are detected rather than
+        // silently truncated. Reports are converted to their indistinguishable form as soon as
+        // they are decrypted, so the information fields are never held for the whole input.
+        let mut received = 0;
+        let stream = LengthDelimitedStream::<EncryptedHybridReport<BK, V>, _>::new(input_stream)
             .map_err(Into::into)
             .try_flatten_iters()
+            .inspect(|_| received += 1)
             .map(|enc_report_res| async move {
                 enc_report_res.and_then(|enc_report| {
                     let dec_report = enc_report
                         .decrypt(key_registry.as_ref())
                         .map_err(Into::<Error>::into);
-                    let unique_tag = UniqueTag::from_unique_bytes(&enc_report);
-                    dec_report.map(|dec_report1| (dec_report1, unique_tag))
+                    let unique_tag = KeyedUniqueTag::from_report(&enc_report);
+                    dec_report.map(|dec_report1| {
+                        let info = match &dec_report1 {
+                            HybridReport::Conversion(conversion) => Some(conversion.info.clone()),
+                            HybridReport::Impression(_) => None,
+                        };
+                        (
+                            IndistinguishableHybridReport::<BK, V>::from(dec_report1),
+                            unique_tag,
+                            info,
+                        )
+                    })
                 })
             })
-            .take(sz);
+            .take(sz.saturating_add(1));
+
+        // Sites and epochs are collected as reports are decrypted, there are few of them
+        // compared to the number of reports. Conversions that don't allow the query's privacy
+        // parameters are dropped and only counted. Each helper decides that from the information
+        // it decrypted, which is hashed in input order and compared with the other helpers
+        // below, so that all of them drop the same reports.
+        let mut site_epochs = HashSet::new();
+        let mut incompatible_reports = 0_usize;
+        let mut conversion_info = BytesHasher::default();
+        let stream = seq_join(ctx.active_work(), stream).try_filter_map(|(report, tag, info)| {
+            let report = match info {
+                Some(info) => {
+                    hash_conversion_info(&mut conversion_info, &info);
+                    if privacy_compatible(&info, config) {
+                        site_epochs.extend(privacy_budget.as_ref().map(|budget| {
+                            SiteEpoch::new(
+                                &info.conversion_site_domain,
+                                budget.epoch(info.timestamp),
+                            )
+                        }));
+                        Some((report, tag))
+                    } else {
+                        incompatible_reports += 1;
+                        None
+                    }
+                }
+                None => Some((report, tag)),
+            };
+            future::ready(Ok(report))
+        });
 
-        let (decrypted_reports, resharded_tags) = reshard_aad(
+        // Tags are checked for duplicates as they arrive on the shard that owns them. Duplicates
+        // are only counted, and reported after resharding completes, so that all shards finish
+        // exchanging tags and record counts before the query fails.
+        let mut unique_encrypted_hybrid_reports =
+            UniqueTagValidator::new(sz / usize::from(ctx.shard_count()));
+        let mut duplicates = 0_usize;
+        // tags are only kept until the end of the query if they need to be remembered
+        let mut owned_tags = Vec::new();
+        let indistinguishable_reports = reshard_aad(
             ctx.narrow(&HybridStep::ReshardByTag),
-            seq_join(ctx.active_work(), stream),
+            stream,
             |ctx, _, tag| tag.shard_picker(ctx.shard_count()),
+            |tag| {
+                if unique_encrypted_hybrid_reports
+                    .check_duplicate(&tag)
+                    .is_err()
+                {
+                    duplicates += 1;
+                }
+                if replay_store.is_some() {
+                    owned_tags.push(tag);
+                }
+            },
         )
         .await?;
 
-        let mut unique_encrypted_hybrid_reports = UniqueTagValidator::new(resharded_tags.len());
-        unique_encrypted_hybrid_reports.check_duplicates(&resharded_tags)?;
-
-        let indistinguishable_reports: Vec<IndistinguishableHybridReport<BA8, BA3>> =
-            decrypted_reports.into_iter().map(Into::into).collect();
-
-        let dp_params: DpMechanism = match config.with_dp {
-            0 => DpMechanism::NoDp,
-            _ => DpMechanism::DiscreteLaplace {
-                epsilon: config.epsilon,
-            },
+        validate_conversion_info(
+            ctx.narrow(&HybridStep::ValidateConversionInfo),
+            conversion_info.finalize(),
+        )
+        .await?;
+        validate_record_count(ctx.narrow(&HybridStep::ValidateRecordCount), received, sz).await?;
+        if incompatible_reports > 0 {
+            counter!(
+                INCOMPATIBLE_PRIVACY_REPORTS,
+                u64::try_from(incompatible_reports).unwrap()
+            );
+            tracing::warn!(
+                "Dropped {incompatible_reports} conversion reports that do not allow epsilon {:?} \
+                 and per-user credit cap {}",
+                config.epsilon(),
+                config.per_user_credit_cap,
+            );
+        }
+        // Budget of a site epoch is kept by the shard that owns it, so every query that uses it
+        // is charged in the same place, no matter which shards its reports arrived at.
+        let mut owned_site_epochs = HashSet::new();
+        if privacy_budget.is_some() {
+            reshard_try_stream_for_each(
+                ctx.narrow(&HybridStep::ReshardBySiteEpoch),
+                stream::iter(site_epochs).map(Ok),
+                |ctx, _, site_epoch| site_epoch.shard_picker(ctx.shard_count()),
+                |_, site_epoch| {
+                    owned_site_epochs.insert(site_epoch);
+                },
+            )
+            .await?;
+        }
+        let reserved_budget = match privacy_budget {
+            Some(budget) => {
+                // A query without differential privacy can't be charged to a finite budget.
+                let epsilon = config.epsilon().unwrap_or(f64::INFINITY);
+                let reserved = budget.reserve(owned_site_epochs, epsilon);
+                let exhausted = match &reserved {
+                    Err(Error::PrivacyBudgetExceeded { site_epochs, .. }) => *site_epochs,
+                    _ => 0,
+                };
+                // Only the shards that own exhausted site epochs know the query is over budget,
+                // all shards must refuse it.
+                let exhausted =
+                    sum_over_shards(ctx.narrow(&HybridStep::ValidatePrivacyBudget), exhausted)
+                        .await?;
+                if exhausted > 0 {
+                    return Err(budget.exceeded(exhausted));
+                }
+                Some(reserved?)
+            }
+            None => None,
+        };
+        // Duplicated and replayed reports are only found by the shards that own their tags, all
+        // shards must refuse the query.
+        let unique_ctx = ctx.narrow(&HybridStep::ValidateUniqueReports);
+        let duplicates = sum_over_shards(unique_ctx.clone(), duplicates).await?;
+        if duplicates > 0 {
+            return Err(Error::DuplicateReports {
+                count: duplicates,
+                step: unique_ctx.gate().clone(),
+            });
+        }
+        let reserved_tags = match replay_store {
+            Some(store) => {
+                let replay_ctx = ctx.narrow(&HybridStep::ValidateReplayedReports);
+                let reserved = store
+                    .prune(key_registry.as_ref())
+                    .map_err(Error::from)
+                    .and_then(|()| store.reserve(owned_tags, replay_ctx.gate()));
+                let replayed = match &reserved {
+                    Err(Error::ReplayedReports { count, .. }) => *count,
+                    _ => 0,
+                };
+                let replayed = sum_over_shards(replay_ctx.clone(), replayed).await?;
+                if replayed > 0 {
+                    return Err(Error::ReplayedReports {
+                        count: replayed,
+                        step: replay_ctx.gate().clone(),
+                    });
+                }
+                Some(reserved?)
+            }
+            None => None,
         };
+        memory::report_memory("hybrid input");
 
-        #[cfg(feature = "relaxed-dp")]
-        let padding_params = PaddingParameters::relaxed();
-        #[cfg(not(feature = "relaxed-dp"))]
-        let padding_params = PaddingParameters::default();
+        let dp_params = config.dp_mechanism;
 
-        hybrid_protocol::<_, BA8, BA3, HV, 3, 256>(
-            ctx,
+        let result = hybrid_protocol::<_, BK, V, HV, B>(
+            ctx.clone(),
             indistinguishable_reports,
             dp_params,
-            padding_params,
+            config.padding_params(),
+            config.per_user_credit_cap,
+            config.attribution_window_seconds,
+            config.breakdown_dimensions,
+            config.aggregation_mode,
         )
-        .await
+        .await?;
+
+        let result = match (config.histogram_output, dp_params) {
+            (HistogramOutput::Sparse { delta }, DpMechanism::DiscreteLaplace { epsilon }) => {
+                // The sensitivity must match the noise added to the sums by `hybrid_protocol`.
+                let max_value = u32::try_from((1_u64 << V::BITS) - 1).unwrap_or(u32::MAX);
+                let threshold = sparse_histogram_threshold(
+                    epsilon,
+                    delta,
+                    config.per_user_credit_cap.min(max_value),
+                )?;
+                tracing::info!("Suppressing histogram buckets that do not exceed {threshold}");
+                let buckets = dp_threshold_histogram(ctx.clone(), result, threshold).await?;
+
+                // Every reported bucket is returned as a pair of its breakdown key and its value.
+                buckets
+                    .into_iter()
+                    .flat_map(|(breakdown_key, value)| {
+                        let breakdown_key = Replicated::<HV>::share_known_value(
+                            &ctx,
+                            HV::truncate_from(breakdown_key as u128),
+                        );
+                        [breakdown_key, value]
+                    })
+                    .collect()
+            }
+            // `validate` rejects sparse output with any other mechanism
+            _ => result,
+        };
+
+        if let Some(reserved_tags) = reserved_tags {
+            reserved_tags.persist().await?;
+        }
+        if let Some(reserved_budget) = reserved_budget {
+            reserved_budget.commit().await?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Conversion reports declare the privacy parameters their clients agreed to. A query may only
+/// use a report if it spends no more epsilon than the report declares, and does not let a single
+/// user contribute more than the declared sensitivity. Queries without differential privacy can
+/// only use reports that declare an infinite epsilon.
+fn privacy_compatible(info: &HybridConversionInfo, config: &HybridQueryParams) -> bool {
+    let epsilon = config.epsilon().unwrap_or(f64::INFINITY);
+    epsilon <= info.epsilon && f64::from(config.per_user_credit_cap) <= info.sensitivity
+}
+
+/// Adds the fields of `info` that decide whether the report is used, and how it is charged to the
+/// privacy budget, to `hasher`. The key identifier is left out, as each helper has its own keys.
+fn hash_conversion_info(hasher: &mut BytesHasher, info: &HybridConversionInfo) {
+    hasher.update(info.conversion_site_domain.as_bytes());
+    hasher.update(&info.timestamp.to_be_bytes());
+    hasher.update(&info.epsilon.to_be_bytes());
+    hasher.update(&info.sensitivity.to_be_bytes());
+}
+
+/// Makes sure the other helpers decrypted the same conversion information as this one, by
+/// comparing the hashes of it with both peers. A report could otherwise declare different
+/// privacy parameters to each helper, making them drop different reports.
+async fn validate_conversion_info<C: Context>(ctx: C, hash: Hash) -> Result<(), Error> {
+    let ctx = ctx.set_total_records(TotalRecords::ONE);
+    let send = |direction| {
+        let channel = ctx.send_channel::<Hash>(ctx.role().peer(direction));
+        let hash = hash.clone();
+        async move { channel.send(RecordId::FIRST, hash).await }
+    };
+    let receive = |direction| {
+        let channel = ctx.recv_channel::<Hash>(ctx.role().peer(direction));
+        async move { channel.receive(RecordId::FIRST).await }
+    };
+
+    let ((), (), from_left, from_right) = try_join!(
+        send(Direction::Left),
+        send(Direction::Right),
+        receive(Direction::Left),
+        receive(Direction::Right),
+    )?;
+    if bool::from(hash.ct_eq(&from_left) & hash.ct_eq(&from_right)) {
+        Ok(())
+    } else {
+        Err(Error::InconsistentReportInfo)
+    }
+}
+
+/// Makes sure all shards of this helper received `expected` reports in total. Every shard sends
+/// the number of reports it has read from its input to all other shards.
+async fn validate_record_count<C: ShardedContext>(
+    ctx: C,
+    received: usize,
+    expected: usize,
+) -> Result<(), Error> {
+    let actual = sum_over_shards(ctx, received).await?;
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(Error::RecordCountMismatch { expected, actual })
     }
 }
 
+/// Sends `value` to all other shards of this helper and returns the sum of the values of all
+/// shards.
+async fn sum_over_shards<C: ShardedContext>(ctx: C, value: usize) -> Result<usize, Error> {
+    let ctx = ctx.set_total_records(TotalRecords::ONE);
+    let count = BA64::truncate_from(u128::try_from(value).unwrap());
+    let peer_shards = ctx.peer_shards().collect::<Vec<_>>();
+
+    let send = ctx.try_join(peer_shards.clone().into_iter().map(|shard| {
+        let channel = ctx.shard_send_channel::<BA64>(shard);
+        async move {
+            channel
+                .send(RecordId::FIRST, count)
+                .await
+                .map_err(Error::from)
+        }
+    }));
+    let receive = ctx.try_join(peer_shards.into_iter().map(|shard| {
+        ctx.shard_recv_channel::<BA64>(shard)
+            .take(1)
+            .try_collect::<Vec<_>>()
+    }));
+    let (_, peer_counts) = try_join(send, receive).await?;
+
+    Ok(peer_counts
+        .into_iter()
+        .flatten()
+        .map(|count| usize::try_from(count.as_u128()).unwrap())
+        .fold(value, usize::saturating_add))
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn execute_hybrid_protocol<'a, R: PrivateKeyRegistry>(
     prss: &'a Endpoint,
     gateway: &'a Gateway,
@@ -175,6 +547,8 @@ pub async fn execute_hybrid_protocol<'a, R: PrivateKeyRegistry>(
     ipa_config: HybridQueryParams,
     config: &QueryConfig,
     key_registry: Arc<R>,
+    replay_store: Option<Arc<ReplayStore>>,
+    privacy_budget: Option<CollectorBudget>,
 ) -> QueryResult {
     let gate = Gate::default();
     let cross_shard_prss =
@@ -186,41 +560,63 @@ pub async fn execute_hybrid_protocol<'a, R: PrivateKeyRegistry>(
     };
 
     let ctx = ShardedMaliciousContext::new_with_gate(prss, gateway, gate, sharded);
+    let size = config.size;
+
+    #[rustfmt::skip]
+    let result = match (ipa_config.breakdown_key_bits.bits(), ipa_config.value_bits.bits()) {
+        (5, 3) => Query::<_, BA5, BA3, BA32, R>::new(ipa_config, key_registry).with_replay_store(replay_store).with_privacy_budget(privacy_budget).execute::<32>(ctx, size, input).await?,
+        (5, 8) => Query::<_, BA5, BA8, BA32, R>::new(ipa_config, key_registry).with_replay_store(replay_store).with_privacy_budget(privacy_budget).execute::<32>(ctx, size, input).await?,
+        (5, 16) => Query::<_, BA5, BA16, BA32, R>::new(ipa_config, key_registry).with_replay_store(replay_store).with_privacy_budget(privacy_budget).execute::<32>(ctx, size, input).await?,
+        (8, 3) => Query::<_, BA8, BA3, BA32, R>::new(ipa_config, key_registry).with_replay_store(replay_store).with_privacy_budget(privacy_budget).execute::<256>(ctx, size, input).await?,
+        (8, 8) => Query::<_, BA8, BA8, BA32, R>::new(ipa_config, key_registry).with_replay_store(replay_store).with_privacy_budget(privacy_budget).execute::<256>(ctx, size, input).await?,
+        (8, 16) => Query::<_, BA8, BA16, BA32, R>::new(ipa_config, key_registry).with_replay_store(replay_store).with_privacy_budget(privacy_budget).execute::<256>(ctx, size, input).await?,
+        (16, 3) => Box::pin(Query::<_, BA16, BA3, BA32, R>::new(ipa_config, key_registry).with_replay_store(replay_store).with_privacy_budget(privacy_budget).execute::<1024>(ctx, size, input)).await?,
+        (16, 8) => Box::pin(Query::<_, BA16, BA8, BA32, R>::new(ipa_config, key_registry).with_replay_store(replay_store).with_privacy_budget(privacy_budget).execute::<1024>(ctx, size, input)).await?,
+        (bk, v) => {
+            return Err(Error::Unsupported(format!(
+                "hybrid queries do not support {bk} breakdown key bits with {v} value bits"
+            )))
+        }
+    };
 
-    Ok(Box::new(
-        Query::<_, BA32, R>::new(ipa_config, key_registry)
-            .execute(ctx, config.size, input)
-            .await?,
-    ))
+    Ok(Box::new(result))
 }
 
 #[cfg(all(test, unit_test, feature = "in-memory-infra"))]
 mod tests {
     use std::{
         iter::{repeat, zip},
+        num::NonZeroU32,
+        ops::Add,
         sync::Arc,
+        time::Duration,
     };
 
+    use generic_array::ArrayLength;
     use rand::rngs::StdRng;
     use rand_core::SeedableRng;
+    use typenum::U16;
 
     use crate::{
         ff::{
-            boolean_array::{BA3, BA32, BA8},
-            U128Conversions,
+            boolean_array::{BooleanArray, BA16, BA3, BA32, BA5, BA64, BA8},
+            Serializable, U128Conversions,
         },
         helpers::{
-            query::{HybridQueryParams, QuerySize},
+            query::{
+                BreakdownDimensions, DpMechanism, HistogramOutput, HybridAggregationMode,
+                HybridQueryParams, QuerySize, ReportCollector,
+            },
             BodyStream,
         },
         hpke::{KeyPair, KeyRegistry},
-        query::runner::hybrid::Query as HybridQuery,
+        query::{runner::hybrid::Query as HybridQuery, BudgetLedger, ReplayStore},
         report::{hybrid::HybridReport, DEFAULT_KEY_ID},
-        secret_sharing::IntoShares,
+        secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, IntoShares},
         test_executor::run,
         test_fixture::{
             flatten3v,
-            hybrid::{build_hybrid_records_and_expectation, TestHybridRecord},
+            hybrid::{build_hybrid_records_and_expectation, hybrid_in_the_clear, TestHybridRecord},
             Reconstruct, RoundRobinInputDistribution, TestWorld, TestWorldConfig, WithShards,
         },
     };
@@ -232,12 +628,49 @@ mod tests {
     }
 
     fn build_buffers_from_records(records: &[TestHybridRecord], s: usize) -> BufferAndKeyRegistry {
+        build_buffers_from_records_with_widths::<BA8, BA3>(records, s)
+    }
+
+    fn build_buffers_from_records_with_widths<BK, V>(
+        records: &[TestHybridRecord],
+        s: usize,
+    ) -> BufferAndKeyRegistry
+    where
+        BK: BooleanArray + U128Conversions + IntoShares<Replicated<BK>>,
+        V: BooleanArray + U128Conversions + IntoShares<Replicated<V>>,
+        Replicated<BK>: Serializable,
+        Replicated<V>: Serializable,
+        <Replicated<BK> as Serializable>::Size: Add<U16>,
+        <Replicated<V> as Serializable>::Size: Add<U16>,
+        <<Replicated<BK> as Serializable>::Size as Add<<Replicated<BA64> as Serializable>::Size>>::Output: ArrayLength,
+        <<Replicated<V> as Serializable>::Size as Add<<Replicated<BA64> as Serializable>::Size>>::Output: ArrayLength,
+    {
+        let shares: [Vec<HybridReport<BK, V>>; 3] = records.iter().cloned().share();
+        build_buffers_from_shares(shares, s)
+    }
+
+    /// Encrypts the shares of each helper, dealing them to `s` shards in turn.
+    fn build_buffers_from_shares<BK, V>(
+        shares: [Vec<HybridReport<BK, V>>; 3],
+        s: usize,
+    ) -> BufferAndKeyRegistry
+    where
+        BK: BooleanArray + U128Conversions + IntoShares<Replicated<BK>>,
+        V: BooleanArray + U128Conversions + IntoShares<Replicated<V>>,
+        Replicated<BK>: Serializable,
+        Replicated<V>: Serializable,
+        <Replicated<BK> as Serializable>::Size: Add<U16>,
+        <Replicated<V> as Serializable>::Size: Add<U16>,
+        <<Replicated<BK> as Serializable>::Size as Add<<Replicated<BA64> as Serializable>::Size>>::Output: ArrayLength,
+        <<Replicated<V> as Serializable>::Size as Add<<Replicated<BA64> as Serializable>::Size>>::Output: ArrayLength,
+    {
         let mut rng = StdRng::seed_from_u64(42);
         let key_id = DEFAULT_KEY_ID;
         let key_registry = Arc::new(KeyRegistry::<KeyPair>::random(1, &mut rng));
 
+        // query size is the total number of records across all shards
+        let query_sizes = vec![QuerySize::try_from(shares[0].len()).unwrap(); s];
         let mut buffers: [_; 3] = std::array::from_fn(|_| vec![Vec::new(); s]);
-        let shares: [Vec<HybridReport<BA8, BA3>>; 3] = records.iter().cloned().share();
         for (buf, shares) in zip(&mut buffers, shares) {
             for (i, share) in shares.into_iter().enumerate() {
                 share
@@ -246,20 +679,6 @@ mod tests {
             }
         }
 
-        let total_query_size = records.len();
-        let base_size = total_query_size / s;
-        let remainder = total_query_size % s;
-        let query_sizes: Vec<_> = (0..s)
-            .map(|i| {
-                if i < remainder {
-                    base_size + 1
-                } else {
-                    base_size
-                }
-            })
-            .map(|size| QuerySize::try_from(size).unwrap())
-            .collect();
-
         BufferAndKeyRegistry {
             buffers,
             key_registry,
@@ -303,16 +722,16 @@ mod tests {
                         .zip(query_sizes.clone())
                         .map(|((buffer, ctx), query_size)| {
                             let query_params = HybridQueryParams {
-                                with_dp: 0,
+                                dp_mechanism: DpMechanism::NoDp,
                                 ..Default::default()
                             };
                             let input = BodyStream::from(buffer);
 
-                            HybridQuery::<_, BA32, KeyRegistry<KeyPair>>::new(
+                            HybridQuery::<_, BA8, BA3, BA32, KeyRegistry<KeyPair>>::new(
                                 query_params,
                                 Arc::clone(&key_registry),
                             )
-                            .execute(ctx, query_size, input)
+                            .execute::<256>(ctx, query_size, input)
                         })
                 },
             ))
@@ -341,40 +760,845 @@ mod tests {
         });
     }
 
-    // cannot test for Err directly because join3v calls unwrap. This should be sufficient.
-    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
-    #[should_panic(expected = "DuplicateBytes")]
-    async fn duplicate_encrypted_hybrid_reports() {
-        const SHARDS: usize = 2;
-        let (test_hybrid_records, _expected) = build_hybrid_records_and_expectation();
+    #[test]
+    fn encrypted_hybrid_reports_configured_widths() {
+        run(|| async {
+            const SHARDS: usize = 2;
+            const B: usize = 32;
+            let (mut test_hybrid_records, mut expected) = build_hybrid_records_and_expectation();
 
-        let BufferAndKeyRegistry {
-            mut buffers,
-            key_registry,
-            query_sizes,
-        } = build_buffers_from_records(&test_hybrid_records, SHARDS);
+            // A value that does not fit into the default 3 bits, attributed to a breakdown
+            // key that fits into 5 bits.
+            test_hybrid_records.extend([
+                TestHybridRecord::TestImpression {
+                    match_key: 90123,
+                    breakdown_key: 20,
+                    key_id: 0,
+                    timestamp: 100,
+                },
+                TestHybridRecord::TestConversion {
+                    match_key: 90123,
+                    value: 1000,
+                    key_id: 0,
+                    conversion_site_domain: "meta.com".to_string(),
+                    timestamp: 106,
+                    epsilon: f64::INFINITY,
+                    sensitivity: f64::INFINITY,
+                },
+            ]);
+            expected.resize(B, 0);
+            expected[20] = 1000;
 
-        // this is double, since we duplicate the data below
-        let query_sizes = query_sizes
-            .into_iter()
-            .map(|query_size| QuerySize::try_from(usize::from(query_size) * 2).unwrap())
-            .collect::<Vec<_>>();
+            let BufferAndKeyRegistry {
+                buffers,
+                key_registry,
+                query_sizes,
+            } = build_buffers_from_records_with_widths::<BA5, BA16>(&test_hybrid_records, SHARDS);
 
-        // duplicate all the data across shards
+            let world = TestWorld::<WithShards<SHARDS>>::with_shards(TestWorldConfig::default());
+            let contexts = world.malicious_contexts();
 
-        for helper_buffers in &mut buffers {
-            // Get the last shard buffer to use for the first shard buffer extension
-            let last_shard_buffer = helper_buffers.last().unwrap().clone();
-            let len = helper_buffers.len();
-            for i in 0..len {
-                if i > 0 {
-                    let previous = &helper_buffers[i - 1].clone();
-                    helper_buffers[i].extend_from_slice(previous);
-                } else {
-                    helper_buffers[i].extend_from_slice(&last_shard_buffer);
-                }
+            #[allow(clippy::large_futures)]
+            let results = flatten3v(buffers.into_iter().zip(contexts).map(
+                |(helper_buffers, helper_ctxs)| {
+                    helper_buffers
+                        .into_iter()
+                        .zip(helper_ctxs)
+                        .zip(query_sizes.clone())
+                        .map(|((buffer, ctx), query_size)| {
+                            let query_params = HybridQueryParams {
+                                per_user_credit_cap: 1000,
+                                dp_mechanism: DpMechanism::NoDp,
+                                breakdown_key_bits: 5.try_into().unwrap(),
+                                value_bits: 16.try_into().unwrap(),
+                                ..Default::default()
+                            };
+                            let input = BodyStream::from(buffer);
+
+                            HybridQuery::<_, BA5, BA16, BA32, KeyRegistry<KeyPair>>::new(
+                                query_params,
+                                Arc::clone(&key_registry),
+                            )
+                            .execute::<B>(ctx, query_size, input)
+                        })
+                },
+            ))
+            .await;
+
+            let leader_results: Vec<u32> = [
+                results[0].as_ref().unwrap().clone(),
+                results[1].as_ref().unwrap().clone(),
+                results[2].as_ref().unwrap().clone(),
+            ]
+            .reconstruct()
+            .iter()
+            .map(U128Conversions::as_u128)
+            .map(|x| u32::try_from(x).expect("test values constructed to fit in u32"))
+            .collect::<Vec<u32>>();
+
+            assert_eq!(expected, leader_results);
+        });
+    }
+
+    #[test]
+    fn encrypted_hybrid_reports_wide_breakdown_keys() {
+        run(|| async {
+            const SHARDS: usize = 2;
+            const B: usize = 1024;
+            let (mut test_hybrid_records, mut expected) = build_hybrid_records_and_expectation();
+            expected.resize(B, 0);
+
+            // Breakdown keys that need more than 8 bits. The last one does not fit into the
+            // histogram, so its value is dropped.
+            for (match_key, breakdown_key, value) in
+                [(90123, 300, 5), (90124, 1000, 3), (90125, 2000, 4)]
+            {
+                test_hybrid_records.extend([
+                    TestHybridRecord::TestImpression {
+                        match_key,
+                        breakdown_key,
+                        key_id: 0,
+                        timestamp: 100,
+                    },
+                    TestHybridRecord::TestConversion {
+                        match_key,
+                        value,
+                        key_id: 0,
+                        conversion_site_domain: "meta.com".to_string(),
+                        timestamp: 106,
+                        epsilon: f64::INFINITY,
+                        sensitivity: f64::INFINITY,
+                    },
+                ]);
             }
-        }
+            expected[300] = 5;
+            expected[1000] = 3;
+
+            let BufferAndKeyRegistry {
+                buffers,
+                key_registry,
+                query_sizes,
+            } = build_buffers_from_records_with_widths::<BA16, BA3>(&test_hybrid_records, SHARDS);
+
+            let world = TestWorld::<WithShards<SHARDS>>::with_shards(TestWorldConfig::default());
+            let contexts = world.malicious_contexts();
+
+            #[allow(clippy::large_futures)]
+            let results = flatten3v(buffers.into_iter().zip(contexts).map(
+                |(helper_buffers, helper_ctxs)| {
+                    helper_buffers
+                        .into_iter()
+                        .zip(helper_ctxs)
+                        .zip(query_sizes.clone())
+                        .map(|((buffer, ctx), query_size)| {
+                            let query_params = HybridQueryParams {
+                                max_breakdown_key: 1024,
+                                dp_mechanism: DpMechanism::NoDp,
+                                breakdown_key_bits: 16.try_into().unwrap(),
+                                ..Default::default()
+                            };
+                            let input = BodyStream::from(buffer);
+
+                            HybridQuery::<_, BA16, BA3, BA32, KeyRegistry<KeyPair>>::new(
+                                query_params,
+                                Arc::clone(&key_registry),
+                            )
+                            .execute::<B>(ctx, query_size, input)
+                        })
+                },
+            ))
+            .await;
+
+            let leader_results: Vec<u32> = [
+                results[0].as_ref().unwrap().clone(),
+                results[1].as_ref().unwrap().clone(),
+                results[2].as_ref().unwrap().clone(),
+            ]
+            .reconstruct()
+            .iter()
+            .map(U128Conversions::as_u128)
+            .map(|x| u32::try_from(x).expect("test values constructed to fit in u32"))
+            .collect::<Vec<u32>>();
+
+            assert_eq!(expected, leader_results);
+        });
+    }
+
+    #[test]
+    fn encrypted_hybrid_reports_sparse_output() {
+        run(|| async {
+            const SHARDS: usize = 2;
+            const B: usize = 1024;
+            // Ten users contribute 5 each to breakdown 300. With this epsilon, each helper adds
+            // at most 9 to a bucket, so breakdown 300 is always reported, and no empty bucket is
+            // reported unless its noise exceeds the threshold, which happens with probability at
+            // most delta.
+            let test_hybrid_records = (0..10)
+                .flat_map(|match_key| {
+                    [
+                        TestHybridRecord::TestImpression {
+                            match_key,
+                            breakdown_key: 300,
+                            key_id: 0,
+                            timestamp: 100,
+                        },
+                        TestHybridRecord::TestConversion {
+                            match_key,
+                            value: 5,
+                            key_id: 0,
+                            conversion_site_domain: "meta.com".to_string(),
+                            timestamp: 106,
+                            epsilon: f64::INFINITY,
+                            sensitivity: f64::INFINITY,
+                        },
+                    ]
+                })
+                .collect::<Vec<_>>();
+
+            let BufferAndKeyRegistry {
+                buffers,
+                key_registry,
+                query_sizes,
+            } = build_buffers_from_records_with_widths::<BA16, BA3>(&test_hybrid_records, SHARDS);
+
+            let world = TestWorld::<WithShards<SHARDS>>::with_shards(TestWorldConfig::default());
+            let contexts = world.malicious_contexts();
+
+            #[allow(clippy::large_futures)]
+            let results = flatten3v(buffers.into_iter().zip(contexts).map(
+                |(helper_buffers, helper_ctxs)| {
+                    helper_buffers
+                        .into_iter()
+                        .zip(helper_ctxs)
+                        .zip(query_sizes.clone())
+                        .map(|((buffer, ctx), query_size)| {
+                            let query_params = HybridQueryParams {
+                                max_breakdown_key: 1024,
+                                dp_mechanism: DpMechanism::DiscreteLaplace { epsilon: 10.0 },
+                                histogram_output: HistogramOutput::Sparse { delta: 1e-6 },
+                                breakdown_key_bits: 16.try_into().unwrap(),
+                                ..Default::default()
+                            };
+                            let input = BodyStream::from(buffer);
+
+                            HybridQuery::<_, BA16, BA3, BA32, KeyRegistry<KeyPair>>::new(
+                                query_params,
+                                Arc::clone(&key_registry),
+                            )
+                            .execute::<B>(ctx, query_size, input)
+                        })
+                },
+            ))
+            .await;
+
+            let leader_results = [
+                results[0].as_ref().unwrap().clone(),
+                results[1].as_ref().unwrap().clone(),
+                results[2].as_ref().unwrap().clone(),
+            ]
+            .reconstruct();
+
+            let [breakdown_key, value] = <[BA32; 2]>::try_from(leader_results).unwrap();
+            assert_eq!(breakdown_key.as_u128(), 300);
+            assert!((50 - 27..=50 + 27).contains(&value.as_u128()));
+        });
+    }
+
+    #[test]
+    fn encrypted_hybrid_reports_attribution_window() {
+        run(|| async {
+            const SHARDS: usize = 2;
+            let attribution_window_seconds = NonZeroU32::new(10);
+            let (test_hybrid_records, _) = build_hybrid_records_and_expectation();
+            let mut expected = hybrid_in_the_clear(
+                &test_hybrid_records,
+                256,
+                8,
+                attribution_window_seconds,
+                None,
+                HybridAggregationMode::Sum,
+            );
+            assert_eq!(expected[..6], [0, 0, 7, 5, 6, 0]);
+
+            let BufferAndKeyRegistry {
+                buffers,
+                key_registry,
+                query_sizes,
+            } = build_buffers_from_records(&test_hybrid_records, SHARDS);
+
+            let world = TestWorld::<WithShards<SHARDS>>::with_shards(TestWorldConfig::default());
+            let contexts = world.malicious_contexts();
+
+            #[allow(clippy::large_futures)]
+            let results = flatten3v(buffers.into_iter().zip(contexts).map(
+                |(helper_buffers, helper_ctxs)| {
+                    helper_buffers
+                        .into_iter()
+                        .zip(helper_ctxs)
+                        .zip(query_sizes.clone())
+                        .map(|((buffer, ctx), query_size)| {
+                            let query_params = HybridQueryParams {
+                                attribution_window_seconds,
+                                dp_mechanism: DpMechanism::NoDp,
+                                ..Default::default()
+                            };
+                            let input = BodyStream::from(buffer);
+
+                            HybridQuery::<_, BA8, BA3, BA32, KeyRegistry<KeyPair>>::new(
+                                query_params,
+                                Arc::clone(&key_registry),
+                            )
+                            .execute::<256>(ctx, query_size, input)
+                        })
+                },
+            ))
+            .await;
+
+            let leader_results: Vec<u32> = [
+                results[0].as_ref().unwrap().clone(),
+                results[1].as_ref().unwrap().clone(),
+                results[2].as_ref().unwrap().clone(),
+            ]
+            .reconstruct()
+            .iter()
+            .map(U128Conversions::as_u128)
+            .map(|x| u32::try_from(x).expect("test values constructed to fit in u32"))
+            .collect::<Vec<u32>>();
+
+            expected.resize(256, 0);
+            assert_eq!(expected, leader_results);
+        });
+    }
+
+    #[test]
+    fn encrypted_hybrid_reports_breakdown_dimensions() {
+        run(|| async {
+            const SHARDS: usize = 2;
+            const B: usize = 32;
+            let breakdown_dimensions = BreakdownDimensions::new(3, 7).unwrap();
+            let (mut test_hybrid_records, _) = build_hybrid_records_and_expectation();
+
+            // Keys of the two dimensions, and keys outside of the first dimension.
+            let impressions = [(1, 6), (2, 3), (3, 1)].map(|(first_key, second_key)| {
+                first_key | (second_key << breakdown_dimensions.first_bits())
+            });
+            for (match_key, breakdown_key) in zip(90123.., impressions) {
+                test_hybrid_records.extend([
+                    TestHybridRecord::TestImpression {
+                        match_key,
+                        breakdown_key,
+                        key_id: 0,
+                        timestamp: 100,
+                    },
+                    TestHybridRecord::TestConversion {
+                        match_key,
+                        value: 3,
+                        key_id: 0,
+                        conversion_site_domain: "meta.com".to_string(),
+                        timestamp: 106,
+                        epsilon: f64::INFINITY,
+                        sensitivity: f64::INFINITY,
+                    },
+                ]);
+            }
+            let mut expected = hybrid_in_the_clear(
+                &test_hybrid_records,
+                B,
+                8,
+                None,
+                Some(breakdown_dimensions),
+                HybridAggregationMode::Sum,
+            );
+            assert_eq!(expected[1 + 3 * 6], 3);
+            assert_eq!(expected[2 + 3 * 3], 3);
+
+            let BufferAndKeyRegistry {
+                buffers,
+                key_registry,
+                query_sizes,
+            } = build_buffers_from_records_with_widths::<BA5, BA3>(&test_hybrid_records, SHARDS);
+
+            let world = TestWorld::<WithShards<SHARDS>>::with_shards(TestWorldConfig::default());
+            let contexts = world.malicious_contexts();
+
+            #[allow(clippy::large_futures)]
+            let results = flatten3v(buffers.into_iter().zip(contexts).map(
+                |(helper_buffers, helper_ctxs)| {
+                    helper_buffers
+                        .into_iter()
+                        .zip(helper_ctxs)
+                        .zip(query_sizes.clone())
+                        .map(|((buffer, ctx), query_size)| {
+                            let query_params = HybridQueryParams {
+                                max_breakdown_key: breakdown_dimensions.buckets(),
+                                dp_mechanism: DpMechanism::NoDp,
+                                breakdown_key_bits: 5.try_into().unwrap(),
+                                breakdown_dimensions: Some(breakdown_dimensions),
+                                ..Default::default()
+                            };
+                            let input = BodyStream::from(buffer);
+
+                            HybridQuery::<_, BA5, BA3, BA32, KeyRegistry<KeyPair>>::new(
+                                query_params,
+                                Arc::clone(&key_registry),
+                            )
+                            .execute::<B>(ctx, query_size, input)
+                        })
+                },
+            ))
+            .await;
+
+            let leader_results: Vec<u32> = [
+                results[0].as_ref().unwrap().clone(),
+                results[1].as_ref().unwrap().clone(),
+                results[2].as_ref().unwrap().clone(),
+            ]
+            .reconstruct()
+            .iter()
+            .map(U128Conversions::as_u128)
+            .map(|x| u32::try_from(x).expect("test values constructed to fit in u32"))
+            .collect::<Vec<u32>>();
+
+            expected.resize(B, 0);
+            assert_eq!(expected, leader_results);
+        });
+    }
+
+    #[test]
+    fn encrypted_hybrid_reports_reach() {
+        run(|| async {
+            const SHARDS: usize = 2;
+            let (test_hybrid_records, _) = build_hybrid_records_and_expectation();
+            let expected = hybrid_in_the_clear(
+                &test_hybrid_records,
+                256,
+                8,
+                None,
+                None,
+                HybridAggregationMode::SumAndReach,
+            );
+            assert_eq!(expected[256..256 + 6], [1, 0, 1, 1, 2, 0]);
+
+            let BufferAndKeyRegistry {
+                buffers,
+                key_registry,
+                query_sizes,
+            } = build_buffers_from_records(&test_hybrid_records, SHARDS);
+
+            let world = TestWorld::<WithShards<SHARDS>>::with_shards(TestWorldConfig::default());
+            let contexts = world.malicious_contexts();
+
+            #[allow(clippy::large_futures)]
+            let results = flatten3v(buffers.into_iter().zip(contexts).map(
+                |(helper_buffers, helper_ctxs)| {
+                    helper_buffers
+                        .into_iter()
+                        .zip(helper_ctxs)
+                        .zip(query_sizes.clone())
+                        .map(|((buffer, ctx), query_size)| {
+                            let query_params = HybridQueryParams {
+                                dp_mechanism: DpMechanism::NoDp,
+                                aggregation_mode: HybridAggregationMode::SumAndReach,
+                                ..Default::default()
+                            };
+                            let input = BodyStream::from(buffer);
+
+                            HybridQuery::<_, BA8, BA3, BA32, KeyRegistry<KeyPair>>::new(
+                                query_params,
+                                Arc::clone(&key_registry),
+                            )
+                            .execute::<256>(ctx, query_size, input)
+                        })
+                },
+            ))
+            .await;
+
+            let leader_results: Vec<u32> = [
+                results[0].as_ref().unwrap().clone(),
+                results[1].as_ref().unwrap().clone(),
+                results[2].as_ref().unwrap().clone(),
+            ]
+            .reconstruct()
+            .iter()
+            .map(U128Conversions::as_u128)
+            .map(|x| u32::try_from(x).expect("test values constructed to fit in u32"))
+            .collect::<Vec<u32>>();
+
+            assert_eq!(expected, leader_results);
+        });
+    }
+
+    #[test]
+    fn incompatible_privacy_reports_dropped() {
+        run(|| async {
+            const SHARDS: usize = 2;
+            let (mut test_hybrid_records, _) = build_hybrid_records_and_expectation();
+            // The query does not add noise and caps credit at 8, so the conversions attributed
+            // to breakdowns 4 and 3 are dropped.
+            for record in &mut test_hybrid_records {
+                if let TestHybridRecord::TestConversion {
+                    match_key,
+                    epsilon,
+                    sensitivity,
+                    ..
+                } = record
+                {
+                    match *match_key {
+                        23456 => *epsilon = 5.0,
+                        45678 => *sensitivity = 4.0,
+                        _ => {}
+                    }
+                }
+            }
+            let expected = hybrid_in_the_clear(
+                test_hybrid_records.iter().filter(|r| {
+                    !matches!(
+                        r,
+                        TestHybridRecord::TestConversion {
+                            match_key: 23456 | 45678,
+                            ..
+                        }
+                    )
+                }),
+                256,
+                8,
+                None,
+                None,
+                HybridAggregationMode::Sum,
+            );
+            assert_eq!(expected[3..5], [0, 6]);
+
+            let BufferAndKeyRegistry {
+                buffers,
+                key_registry,
+                query_sizes,
+            } = build_buffers_from_records(&test_hybrid_records, SHARDS);
+
+            let world = TestWorld::<WithShards<SHARDS>>::with_shards(TestWorldConfig::default());
+            let contexts = world.malicious_contexts();
+
+            #[allow(clippy::large_futures)]
+            let results = flatten3v(buffers.into_iter().zip(contexts).map(
+                |(helper_buffers, helper_ctxs)| {
+                    helper_buffers
+                        .into_iter()
+                        .zip(helper_ctxs)
+                        .zip(query_sizes.clone())
+                        .map(|((buffer, ctx), query_size)| {
+                            let query_params = HybridQueryParams {
+                                dp_mechanism: DpMechanism::NoDp,
+                                ..Default::default()
+                            };
+                            let input = BodyStream::from(buffer);
+
+                            HybridQuery::<_, BA8, BA3, BA32, KeyRegistry<KeyPair>>::new(
+                                query_params,
+                                Arc::clone(&key_registry),
+                            )
+                            .execute::<256>(ctx, query_size, input)
+                        })
+                },
+            ))
+            .await;
+
+            let leader_results: Vec<u32> = [
+                results[0].as_ref().unwrap().clone(),
+                results[1].as_ref().unwrap().clone(),
+                results[2].as_ref().unwrap().clone(),
+            ]
+            .reconstruct()
+            .iter()
+            .map(U128Conversions::as_u128)
+            .map(|x| u32::try_from(x).expect("test values constructed to fit in u32"))
+            .collect::<Vec<u32>>();
+
+            assert_eq!(expected, leader_results);
+        });
+    }
+
+    // cannot test for Err directly because join3v calls unwrap. This should be sufficient.
+    #[tokio::test]
+    #[should_panic(expected = "UnexpectedLength")]
+    async fn mismatched_value_width() {
+        const SHARDS: usize = 2;
+        let (test_hybrid_records, _expected) = build_hybrid_records_and_expectation();
+
+        let BufferAndKeyRegistry {
+            buffers,
+            key_registry,
+            query_sizes,
+        } = build_buffers_from_records_with_widths::<BA8, BA16>(&test_hybrid_records, SHARDS);
+
+        let world: TestWorld<WithShards<SHARDS, RoundRobinInputDistribution>> =
+            TestWorld::with_shards(TestWorldConfig::default());
+        let contexts = world.malicious_contexts();
+
+        #[allow(clippy::large_futures)]
+        let results = flatten3v(buffers.into_iter().zip(contexts).map(
+            |(helper_buffers, helper_ctxs)| {
+                helper_buffers
+                    .into_iter()
+                    .zip(helper_ctxs)
+                    .zip(query_sizes.clone())
+                    .map(|((buffer, ctx), query_size)| {
+                        let query_params = HybridQueryParams::default();
+                        let input = BodyStream::from(buffer);
+
+                        HybridQuery::<_, BA8, BA3, BA32, KeyRegistry<KeyPair>>::new(
+                            query_params,
+                            Arc::clone(&key_registry),
+                        )
+                        .execute::<256>(ctx, query_size, input)
+                    })
+            },
+        ))
+        .await;
+
+        results.into_iter().map(|r| r.unwrap()).for_each(drop);
+    }
+
+    // cannot test for Err directly because join3v calls unwrap. This should be sufficient.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    #[should_panic(expected = "DuplicateReports")]
+    async fn duplicate_encrypted_hybrid_reports() {
+        const SHARDS: usize = 2;
+        let (test_hybrid_records, _expected) = build_hybrid_records_and_expectation();
+
+        let BufferAndKeyRegistry {
+            mut buffers,
+            key_registry,
+            query_sizes,
+        } = build_buffers_from_records(&test_hybrid_records, SHARDS);
+
+        // this is double, since we duplicate the data below
+        let query_sizes = query_sizes
+            .into_iter()
+            .map(|query_size| QuerySize::try_from(usize::from(query_size) * 2).unwrap())
+            .collect::<Vec<_>>();
+
+        // duplicate all the data across shards
+
+        for helper_buffers in &mut buffers {
+            // every shard gets a copy of the previous shard's input, the first shard gets
+            // a copy of the last one
+            let original = helper_buffers.clone();
+            let len = helper_buffers.len();
+            for (i, buffer) in helper_buffers.iter_mut().enumerate() {
+                buffer.extend_from_slice(&original[(i + len - 1) % len]);
+            }
+        }
+
+        let world: TestWorld<WithShards<SHARDS, RoundRobinInputDistribution>> =
+            TestWorld::with_shards(TestWorldConfig::default());
+        let contexts = world.malicious_contexts();
+
+        #[allow(clippy::large_futures)]
+        let results = flatten3v(buffers.into_iter().zip(contexts).map(
+            |(helper_buffers, helper_ctxs)| {
+                helper_buffers
+                    .into_iter()
+                    .zip(helper_ctxs)
+                    .zip(query_sizes.clone())
+                    .map(|((buffer, ctx), query_size)| {
+                        let query_params = HybridQueryParams::default();
+                        let input = BodyStream::from(buffer);
+
+                        HybridQuery::<_, BA8, BA3, BA32, KeyRegistry<KeyPair>>::new(
+                            query_params,
+                            Arc::clone(&key_registry),
+                        )
+                        .execute::<256>(ctx, query_size, input)
+                    })
+            },
+        ))
+        .await;
+
+        results.into_iter().map(|r| r.unwrap()).for_each(drop);
+    }
+
+    // cannot test for Err directly because join3v calls unwrap. This should be sufficient.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    #[should_panic(expected = "ReplayedReports")]
+    async fn replayed_encrypted_hybrid_reports() {
+        const SHARDS: usize = 2;
+        let (test_hybrid_records, _expected) = build_hybrid_records_and_expectation();
+
+        let BufferAndKeyRegistry {
+            buffers,
+            key_registry,
+            query_sizes,
+        } = build_buffers_from_records(&test_hybrid_records, SHARDS);
+
+        // Helpers and shards can share the store, because every one of them sees different tags.
+        let dir = tempfile::tempdir().unwrap();
+        let replay_store = Arc::new(ReplayStore::open(dir.path(), key_registry.as_ref()).unwrap());
+
+        // the first query succeeds, the second one gets the same reports and must fail
+        for _ in 0..2 {
+            let world: TestWorld<WithShards<SHARDS, RoundRobinInputDistribution>> =
+                TestWorld::with_shards(TestWorldConfig::default());
+            let contexts = world.malicious_contexts();
+
+            #[allow(clippy::large_futures)]
+            let results = flatten3v(buffers.clone().into_iter().zip(contexts).map(
+                |(helper_buffers, helper_ctxs)| {
+                    helper_buffers
+                        .into_iter()
+                        .zip(helper_ctxs)
+                        .zip(query_sizes.clone())
+                        .map(|((buffer, ctx), query_size)| {
+                            let query_params = HybridQueryParams::default();
+                            let input = BodyStream::from(buffer);
+
+                            HybridQuery::<_, BA8, BA3, BA32, KeyRegistry<KeyPair>>::new(
+                                query_params,
+                                Arc::clone(&key_registry),
+                            )
+                            .with_replay_store(Some(Arc::clone(&replay_store)))
+                            .execute::<256>(ctx, query_size, input)
+                        })
+                },
+            ))
+            .await;
+
+            results.into_iter().map(|r| r.unwrap()).for_each(drop);
+        }
+    }
+
+    // cannot test for Err directly because join3v calls unwrap. This should be sufficient.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    #[should_panic(expected = "PrivacyBudgetExceeded")]
+    async fn exceeds_privacy_budget() {
+        const SHARDS: usize = 2;
+        let (test_hybrid_records, _expected) = build_hybrid_records_and_expectation();
+
+        let BufferAndKeyRegistry {
+            buffers,
+            key_registry,
+            query_sizes,
+        } = build_buffers_from_records(&test_hybrid_records, SHARDS);
+
+        // Each helper keeps its own ledger, that its shards share. The budget allows one query
+        // with the default epsilon of 5, but not two.
+        let dir = tempfile::tempdir().unwrap();
+        let ledgers = (0..3)
+            .map(|i| {
+                Arc::new(
+                    BudgetLedger::open(
+                        dir.path().join(format!("h{i}.json")),
+                        8.0,
+                        Duration::from_secs(60 * 60 * 24),
+                    )
+                    .unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let report_collector = Some(ReportCollector("rc".to_string()));
+
+        for _ in 0..2 {
+            let world: TestWorld<WithShards<SHARDS, RoundRobinInputDistribution>> =
+                TestWorld::with_shards(TestWorldConfig::default());
+            let contexts = world.malicious_contexts();
+
+            #[allow(clippy::large_futures)]
+            let results = flatten3v(buffers.clone().into_iter().zip(contexts).zip(&ledgers).map(
+                |((helper_buffers, helper_ctxs), ledger)| {
+                    helper_buffers
+                        .into_iter()
+                        .zip(helper_ctxs)
+                        .zip(query_sizes.clone())
+                        .map(|((buffer, ctx), query_size)| {
+                            let query_params = HybridQueryParams::default();
+                            let input = BodyStream::from(buffer);
+
+                            HybridQuery::<_, BA8, BA3, BA32, KeyRegistry<KeyPair>>::new(
+                                query_params,
+                                Arc::clone(&key_registry),
+                            )
+                            .with_privacy_budget(Some(
+                                ledger.for_collector(report_collector.clone()),
+                            ))
+                            .execute::<256>(ctx, query_size, input)
+                        })
+                },
+            ))
+            .await;
+
+            results.into_iter().map(|r| r.unwrap()).for_each(drop);
+        }
+    }
+
+    // cannot test for Err directly because join3v calls unwrap. This should be sufficient.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    #[should_panic(expected = "InconsistentReportInfo")]
+    async fn mismatched_conversion_info() {
+        const SHARDS: usize = 2;
+        let (test_hybrid_records, _expected) = build_hybrid_records_and_expectation();
+
+        // The conversions declare an epsilon to H2 that the query does not allow, so H2 alone
+        // would drop them. Every shard reads some of them, so every shard fails.
+        let mut shares: [Vec<HybridReport<BA8, BA3>>; 3] =
+            test_hybrid_records.iter().cloned().share();
+        for report in &mut shares[1] {
+            if let HybridReport::Conversion(conversion) = report {
+                conversion.info.epsilon = 1.0;
+            }
+        }
+
+        let BufferAndKeyRegistry {
+            buffers,
+            key_registry,
+            query_sizes,
+        } = build_buffers_from_shares(shares, SHARDS);
+
+        let world = TestWorld::<WithShards<SHARDS>>::with_shards(TestWorldConfig::default());
+        let contexts = world.malicious_contexts();
+
+        #[allow(clippy::large_futures)]
+        let results = flatten3v(buffers.into_iter().zip(contexts).map(
+            |(helper_buffers, helper_ctxs)| {
+                helper_buffers
+                    .into_iter()
+                    .zip(helper_ctxs)
+                    .zip(query_sizes.clone())
+                    .map(|((buffer, ctx), query_size)| {
+                        let query_params = HybridQueryParams {
+                            dp_mechanism: DpMechanism::NoDp,
+                            ..Default::default()
+                        };
+                        let input = BodyStream::from(buffer);
+
+                        HybridQuery::<_, BA8, BA3, BA32, KeyRegistry<KeyPair>>::new(
+                            query_params,
+                            Arc::clone(&key_registry),
+                        )
+                        .execute::<256>(ctx, query_size, input)
+                    })
+            },
+        ))
+        .await;
+
+        results.into_iter().map(|r| r.unwrap()).for_each(drop);
+    }
+
+    // cannot test for Err directly because join3v calls unwrap. This should be sufficient.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    #[should_panic(expected = "RecordCountMismatch")]
+    async fn record_count_mismatch() {
+        const SHARDS: usize = 2;
+        let (test_hybrid_records, _expected) = build_hybrid_records_and_expectation();
+
+        let BufferAndKeyRegistry {
+            buffers,
+            key_registry,
+            query_sizes,
+        } = build_buffers_from_records(&test_hybrid_records, SHARDS);
+
+        // input has one more record than the query expects
+        let query_sizes = query_sizes
+            .into_iter()
+            .map(|query_size| QuerySize::try_from(usize::from(query_size) - 1).unwrap())
+            .collect::<Vec<_>>();
 
         let world: TestWorld<WithShards<SHARDS, RoundRobinInputDistribution>> =
             TestWorld::with_shards(TestWorldConfig::default());
@@ -391,11 +1615,11 @@ mod tests {
                         let query_params = HybridQueryParams::default();
                         let input = BodyStream::from(buffer);
 
-                        HybridQuery::<_, BA32, KeyRegistry<KeyPair>>::new(
+                        HybridQuery::<_, BA8, BA3, BA32, KeyRegistry<KeyPair>>::new(
                             query_params,
                             Arc::clone(&key_registry),
                         )
-                        .execute(ctx, query_size, input)
+                        .execute::<256>(ctx, query_size, input)
                     })
             },
         ))
@@ -437,11 +1661,11 @@ mod tests {
                         };
                         let input = BodyStream::from(buffer);
 
-                        HybridQuery::<_, BA32, KeyRegistry<KeyPair>>::new(
+                        HybridQuery::<_, BA8, BA3, BA32, KeyRegistry<KeyPair>>::new(
                             query_params,
                             Arc::clone(&key_registry),
                         )
-                        .execute(ctx, query_size, input)
+                        .execute::<256>(ctx, query_size, input)
                     })
             },
         ))
@@ -5,8 +5,10 @@ mod oprf_ipa;
 mod reshard_tag;
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 mod sharded_shuffle;
+mod spill;
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 mod test_multiply;
+mod walr;
 
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 pub(super) use add_in_prime_field::execute as test_add_in_prime_field;
@@ -15,7 +17,7 @@ pub(super) use sharded_shuffle::execute_sharded_shuffle;
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 pub(super) use test_multiply::execute_test_multiply;
 
-pub use self::{hybrid::execute_hybrid_protocol, oprf_ipa::OprfIpaQuery};
+pub use self::{hybrid::execute_hybrid_protocol, oprf_ipa::OprfIpaQuery, walr::WalrQuery};
 use crate::{error::Error, query::ProtocolResult};
 
 pub(super) type QueryResult = Result<Box<dyn ProtocolResult>, Error>;
@@ -1,39 +1,44 @@
-use std::{convert::Infallible, marker::PhantomData};
+use std::{convert::Infallible, future::ready, marker::PhantomData};
 
-use futures::{stream::iter, StreamExt, TryStreamExt};
+use futures::{stream::iter, Stream, StreamExt, TryStreamExt};
 use futures_util::stream::repeat;
 
 use crate::{
     error::{Error, LengthError},
     ff::{
         boolean::Boolean,
-        boolean_array::{BooleanArray, BA20, BA3, BA8},
+        boolean_array::{BooleanArray, BA16, BA20, BA3, BA8},
         curve_points::RP25519,
         ec_prime_field::Fp25519,
         Field, Serializable, U128Conversions,
     },
     helpers::{
-        query::{DpMechanism, IpaQueryConfig, QuerySize},
+        query::{DpMechanism, HistogramOutput, IpaQueryConfig, QuerySize},
+        stream::TryFlattenItersExt,
         BodyStream, LengthDelimitedStream, RecordsStream,
     },
     hpke::PrivateKeyRegistry,
     protocol::{
         basics::{BooleanArrayMul, Reveal, ShareKnownValue},
         context::{DZKPUpgraded, MacUpgraded, UpgradableContext},
+        dp::{dp_threshold_histogram, sparse_histogram_threshold},
         ipa_prf::{
-            oprf_ipa, oprf_padding::PaddingParameters, prf_eval::PrfSharing, OPRFIPAInputRow,
-            Shuffle, AGG_CHUNK, CONV_CHUNK, PRF_CHUNK, SORT_CHUNK,
+            oprf_ipa, oprf_padding::validate_padding_parameters, prf_eval::PrfSharing,
+            step::IpaPrfStep, OPRFIPAInputRow, Shuffle, AGG_CHUNK, CONV_CHUNK, PRF_CHUNK,
+            SORT_CHUNK,
         },
         prss::FromPrss,
         step::ProtocolStep::IpaPrf,
         BooleanProtocols,
     },
+    query::runner::spill::{SpillBuffer, MAX_IN_MEMORY_REPORTS},
     report::{EncryptedOprfReport, EventType},
     secret_sharing::{
         replicated::semi_honest::{AdditiveShare as Replicated, AdditiveShare},
         BitDecomposed, SharedValue, TransposeFrom, Vectorizable,
     },
     sync::Arc,
+    telemetry::memory,
 };
 
 pub struct OprfIpaQuery<C, HV, R: PrivateKeyRegistry> {
@@ -59,7 +64,8 @@ where
     HV: BooleanArray + U128Conversions,
     R: PrivateKeyRegistry,
     Replicated<Boolean>: Serializable + ShareKnownValue<C, Boolean>,
-    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>
+        + Reveal<DZKPUpgraded<C>, Output = <Boolean as Vectorizable<1>>::Array>,
     Replicated<Boolean, 256>: BooleanProtocols<DZKPUpgraded<C>, 256>,
     Replicated<Boolean, AGG_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, AGG_CHUNK>,
     Replicated<Boolean, CONV_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, CONV_CHUNK>,
@@ -72,6 +78,7 @@ where
         + Reveal<DZKPUpgraded<C>, Output = <BA8 as Vectorizable<1>>::Array>,
     Replicated<BA20>: BooleanArrayMul<DZKPUpgraded<C>>,
     Replicated<BA3>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<BA16>: BooleanArrayMul<DZKPUpgraded<C>>,
     Vec<Replicated<HV>>:
         for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, 256>>, Error = LengthError>,
     BitDecomposed<AdditiveShare<Boolean, 256>>:
@@ -93,76 +100,114 @@ where
         let ctx = ctx.narrow(&IpaPrf);
         let sz = usize::from(query_size);
 
+        validate_padding_parameters(
+            ctx.narrow(&IpaPrfStep::ValidatePaddingParameters),
+            &config.padding_params(),
+        )
+        .await?;
+
         let input = if config.plaintext_match_keys {
-            let mut v = RecordsStream::<OPRFIPAInputRow<BA8, BA3, BA20>, _>::new(input_stream)
-                .try_concat()
-                .await?;
-            v.truncate(sz);
-            v
+            collect_input(
+                RecordsStream::<OPRFIPAInputRow<BA8, BA3, BA20>, _>::new(input_stream)
+                    .try_flatten_iters(),
+                sz,
+            )
+            .await?
         } else {
-            LengthDelimitedStream::<EncryptedOprfReport<BA8, BA3, BA20, _>, _>::new(input_stream)
-                .map_err(Into::<Error>::into)
-                .map_ok(|enc_reports| {
-                    iter(enc_reports.into_iter().map(|enc_report| {
-                        enc_report
-                            .decrypt(key_registry.as_ref())
-                            .map_err(Into::<Error>::into)
-                    }))
-                })
-                .try_flatten()
-                .take(sz)
-                .zip(repeat(ctx.clone()))
-                .map(|(res, ctx)| {
-                    res.map(|report| {
-                        let is_trigger = Replicated::<Boolean>::share_known_value(
-                            &ctx,
-                            match report.event_type {
-                                EventType::Source => Boolean::ZERO,
-                                EventType::Trigger => Boolean::ONE,
-                            },
-                        );
+            let stream = LengthDelimitedStream::<EncryptedOprfReport<BA8, BA3, BA20, _>, _>::new(
+                input_stream,
+            )
+            .map_err(Into::<Error>::into)
+            .map_ok(|enc_reports| {
+                iter(enc_reports.into_iter().map(|enc_report| {
+                    enc_report
+                        .decrypt(key_registry.as_ref())
+                        .map_err(Into::<Error>::into)
+                }))
+            })
+            .try_flatten()
+            .zip(repeat(ctx.clone()))
+            .map(|(res, ctx)| {
+                res.map(|report| {
+                    let is_trigger = Replicated::<Boolean>::share_known_value(
+                        &ctx,
+                        match report.event_type {
+                            EventType::Source => Boolean::ZERO,
+                            EventType::Trigger => Boolean::ONE,
+                        },
+                    );
 
-                        OPRFIPAInputRow {
-                            timestamp: report.timestamp,
-                            match_key: report.match_key,
-                            is_trigger,
-                            breakdown_key: report.breakdown_key,
-                            trigger_value: report.trigger_value,
-                        }
-                    })
+                    OPRFIPAInputRow {
+                        timestamp: report.timestamp,
+                        match_key: report.match_key,
+                        is_trigger,
+                        breakdown_key: report.breakdown_key,
+                        trigger_value: report.trigger_value,
+                    }
                 })
-                .try_collect::<Vec<_>>()
-                .await?
+            });
+            collect_input(stream, sz).await?
         };
+        memory::report_memory("oprf_ipa input");
 
-        let aws = config.attribution_window_seconds;
-        let dp_params: DpMechanism = match config.with_dp {
-            0 => DpMechanism::NoDp,
-            _ => DpMechanism::DiscreteLaplace {
-                epsilon: config.epsilon,
-            },
-        };
+        let histogram = oprf_ipa::<_, BA8, BA3, HV, BA20, 256>(
+            ctx.clone(),
+            input,
+            config.per_user_credit_cap,
+            config.attribution_window_seconds,
+            config.attribution_model,
+            config.dp_mechanism,
+            config.padding_params(),
+        )
+        .await?;
 
-        #[cfg(feature = "relaxed-dp")]
-        let padding_params = PaddingParameters::relaxed();
-        #[cfg(not(feature = "relaxed-dp"))]
-        let padding_params = PaddingParameters::default();
-        match config.per_user_credit_cap {
-            1 => oprf_ipa::<_, BA8, BA3, HV, BA20, 1, 256>(ctx, input, aws, dp_params, padding_params).await,
-            2 | 4 => oprf_ipa::<_, BA8, BA3, HV, BA20, 2, 256>(ctx, input, aws, dp_params, padding_params).await,
-            8 => oprf_ipa::<_, BA8, BA3, HV, BA20, 3, 256>(ctx, input, aws, dp_params, padding_params).await,
-            16 => oprf_ipa::<_, BA8, BA3, HV, BA20, 4, 256>(ctx, input, aws, dp_params, padding_params).await,
-            32 => oprf_ipa::<_, BA8, BA3, HV, BA20, 5, 256>(ctx, input, aws, dp_params, padding_params).await,
-            64 => oprf_ipa::<_, BA8, BA3, HV, BA20, 6, 256>(ctx, input, aws, dp_params, padding_params).await,
-            128 => oprf_ipa::<_, BA8, BA3, HV, BA20, 7, 256>(ctx, input, aws, dp_params, padding_params).await,
-            _ => panic!(
-                "Invalid value specified for per-user cap: {:?}. Must be one of 1, 2, 4, 8, 16, 32, 64, or 128.",
-                config.per_user_credit_cap
-            ),
+        match (config.histogram_output, config.dp_mechanism) {
+            (HistogramOutput::Dense, _) => Ok(histogram),
+            // Thresholding runs on the full 256-bucket histogram, so it shrinks the output but not
+            // the cost of computing it. Wider breakdown keys need a separate change to the protocol.
+            (HistogramOutput::Sparse { delta }, DpMechanism::DiscreteLaplace { epsilon }) => {
+                let threshold =
+                    sparse_histogram_threshold(epsilon, delta, config.per_user_credit_cap)?;
+                tracing::info!("Suppressing histogram buckets that do not exceed {threshold}");
+                let buckets = dp_threshold_histogram(ctx.clone(), histogram, threshold).await?;
+
+                // Every reported bucket is returned as a pair of its breakdown key and its value.
+                Ok(buckets
+                    .into_iter()
+                    .flat_map(|(breakdown_key, value)| {
+                        let breakdown_key = Replicated::<HV>::share_known_value(
+                            &ctx,
+                            HV::truncate_from(breakdown_key as u128),
+                        );
+                        [breakdown_key, value]
+                    })
+                    .collect())
+            }
+            (output @ HistogramOutput::Sparse { .. }, dp_mechanism) => Err(Error::Unsupported(
+                format!("{output} histogram output is not supported with {dp_mechanism}"),
+            )),
         }
     }
 }
 
+/// Collects at most `sz` input records. At most [`MAX_IN_MEMORY_REPORTS`] of them are kept in
+/// memory while the input is read, the rest are spilled to a temporary file and loaded back once
+/// the input is exhausted, because the protocol needs all of the input at once.
+async fn collect_input<T, S>(input: S, sz: usize) -> Result<Vec<T>, Error>
+where
+    T: Serializable,
+    S: Stream<Item = Result<T, Error>>,
+{
+    input
+        .take(sz)
+        .try_fold(
+            SpillBuffer::new(MAX_IN_MEMORY_REPORTS),
+            |mut records, record| ready(records.push(record).map(|()| records)),
+        )
+        .await?
+        .into_vec()
+}
+
 #[cfg(all(test, unit_test))]
 mod tests {
     use std::{iter::zip, sync::Arc};
@@ -176,7 +221,7 @@ mod tests {
             U128Conversions,
         },
         helpers::{
-            query::{IpaQueryConfig, QuerySize},
+            query::{DpMechanism, HistogramOutput, IpaQueryConfig, QuerySize},
             BodyStream,
         },
         hpke::{KeyPair, KeyRegistry},
@@ -186,6 +231,44 @@ mod tests {
         test_fixture::{ipa::TestRawDataRecord, join3v, Reconstruct, TestWorld},
     };
 
+    async fn run_encrypted_query(
+        records: Vec<TestRawDataRecord>,
+        query_config: IpaQueryConfig,
+    ) -> Vec<BA16> {
+        let query_size = QuerySize::try_from(records.len()).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let key_id = DEFAULT_KEY_ID;
+        let key_registry = Arc::new(KeyRegistry::<KeyPair>::random(1, &mut rng));
+
+        let mut buffers: [_; 3] = std::array::from_fn(|_| Vec::new());
+
+        let shares: [Vec<OprfReport<BA8, BA3, BA20>>; 3] = records.into_iter().share();
+        for (buf, shares) in zip(&mut buffers, shares) {
+            for share in shares {
+                share
+                    .delimited_encrypt_to(key_id, key_registry.as_ref(), &mut rng, buf)
+                    .unwrap();
+            }
+        }
+
+        let world = TestWorld::default();
+        let contexts = world.contexts();
+        #[allow(clippy::large_futures)]
+        let results = join3v(buffers.into_iter().zip(contexts).map(|(buffer, ctx)| {
+            let input = BodyStream::from(buffer);
+
+            OprfIpaQuery::<_, BA16, KeyRegistry<KeyPair>>::new(
+                query_config,
+                Arc::clone(&key_registry),
+            )
+            .execute(ctx, query_size, input)
+        }))
+        .await;
+
+        results.reconstruct()
+    }
+
     #[tokio::test]
     async fn encrypted_reports() {
         const EXPECTED: &[u128] = &[0, 8, 5];
@@ -235,51 +318,55 @@ mod tests {
             },
         ];
 
-        let query_size = QuerySize::try_from(records.len()).unwrap();
-
-        let mut rng = StdRng::seed_from_u64(42);
-        let key_id = DEFAULT_KEY_ID;
-        let key_registry = Arc::new(KeyRegistry::<KeyPair>::random(1, &mut rng));
-
-        let mut buffers: [_; 3] = std::array::from_fn(|_| Vec::new());
-
-        let shares: [Vec<OprfReport<BA8, BA3, BA20>>; 3] = records.into_iter().share();
-        for (buf, shares) in zip(&mut buffers, shares) {
-            for share in shares {
-                share
-                    .delimited_encrypt_to(key_id, key_registry.as_ref(), &mut rng, buf)
-                    .unwrap();
-            }
-        }
-
-        let world = TestWorld::default();
-        let contexts = world.contexts();
+        let query_config = IpaQueryConfig {
+            per_user_credit_cap: 8,
+            attribution_window_seconds: None,
+            max_breakdown_key: 3,
+            dp_mechanism: DpMechanism::NoDp,
+            plaintext_match_keys: false,
+            ..Default::default()
+        };
         #[allow(clippy::large_futures)]
-        let results = join3v(buffers.into_iter().zip(contexts).map(|(buffer, ctx)| {
-            let query_config = IpaQueryConfig {
-                per_user_credit_cap: 8,
-                attribution_window_seconds: None,
-                max_breakdown_key: 3,
-                with_dp: 0,
-                epsilon: 5.0,
-                plaintext_match_keys: false,
-            };
-            let input = BodyStream::from(buffer);
-
-            OprfIpaQuery::<_, BA16, KeyRegistry<KeyPair>>::new(
-                query_config,
-                Arc::clone(&key_registry),
-            )
-            .execute(ctx, query_size, input)
-        }))
-        .await;
+        let results = run_encrypted_query(records, query_config).await;
 
         assert_eq!(
-            results.reconstruct()[0..3]
+            results[0..3]
                 .iter()
                 .map(U128Conversions::as_u128)
                 .collect::<Vec<u128>>(),
             EXPECTED
         );
     }
+
+    #[tokio::test]
+    async fn encrypted_reports_sparse_output() {
+        // Ten users contribute 5 each to breakdown 1. With this epsilon, each helper adds at most
+        // 9 to a bucket, so breakdown 1 is always reported, and no empty bucket is reported
+        // unless its noise exceeds the threshold, which happens with probability at most delta.
+        let records = (0..10)
+            .flat_map(|user_id| {
+                [false, true].map(|is_trigger_report| TestRawDataRecord {
+                    timestamp: u64::from(is_trigger_report),
+                    user_id,
+                    is_trigger_report,
+                    breakdown_key: 1,
+                    trigger_value: if is_trigger_report { 5 } else { 0 },
+                })
+            })
+            .collect();
+        let query_config = IpaQueryConfig {
+            per_user_credit_cap: 8,
+            max_breakdown_key: 3,
+            dp_mechanism: DpMechanism::DiscreteLaplace { epsilon: 10.0 },
+            histogram_output: HistogramOutput::Sparse { delta: 1e-6 },
+            ..Default::default()
+        };
+
+        #[allow(clippy::large_futures)]
+        let results = run_encrypted_query(records, query_config).await;
+
+        let [breakdown_key, value] = <[BA16; 2]>::try_from(results).unwrap();
+        assert_eq!(breakdown_key.as_u128(), 1);
+        assert!((50 - 27..=50 + 27).contains(&value.as_u128()));
+    }
 }
@@ -1,5 +1,5 @@
 use std::{
-    pin::{pin, Pin},
+    pin::Pin,
     task::{Context, Poll},
 };
 
@@ -8,11 +8,13 @@ use pin_project::pin_project;
 
 use crate::{
     error::Error,
+    ff::Serializable,
     helpers::Message,
     protocol::{
-        context::{reshard_try_stream, ShardedContext},
+        context::{reshard_try_stream_for_each, ShardedContext},
         RecordId,
     },
+    query::runner::spill::{SpillBuffer, MAX_IN_MEMORY_REPORTS},
     sharding::ShardIndex,
 };
 
@@ -23,54 +25,59 @@ type DataWithTag<D, A> = Result<(D, A), Error>;
 /// being collected, AAD tags need to be resharded. This function does both at the same
 /// time which should reduce the perceived latency of queries.
 ///
-/// The output contains two separate collections: one for data and another one
-/// for AAD tags that are "owned" by this shard. The tags can later be checked for
-/// uniqueness.
+/// The output contains the data collected from the input. AAD tags that are "owned" by this
+/// shard are not buffered, instead they are passed to `on_tag` as soon as they arrive, so they
+/// can be checked for uniqueness while the input is being read. At most
+/// [`MAX_IN_MEMORY_REPORTS`] items of data are kept in memory while the input is read, the rest
+/// is spilled to a temporary file and loaded back once the input is exhausted, see
+/// [`SpillBuffer`].
 ///
 /// ## Errors
-/// This will return an error, if input stream contains at least one `Err` element.
-#[allow(dead_code)]
-pub async fn reshard_aad<L, K, A, C, S>(
+/// This will return an error, if input stream contains at least one `Err` element, or if the
+/// data can't be spilled to disk.
+pub async fn reshard_aad<L, K, A, C, S, F>(
     ctx: C,
     input: L,
     shard_picker: S,
-) -> Result<(Vec<K>, Vec<A>), crate::error::Error>
+    mut on_tag: F,
+) -> Result<Vec<K>, crate::error::Error>
 where
     L: Stream<Item = DataWithTag<K, A>>,
     S: Fn(C, RecordId, &A) -> ShardIndex + Send,
+    K: Serializable,
     A: Message + Clone,
     C: ShardedContext,
+    F: FnMut(A),
 {
-    let mut k_buf = Vec::with_capacity(input.size_hint().1.unwrap_or(0));
+    let mut k_buf = SpillBuffer::new(MAX_IN_MEMORY_REPORTS);
     let splitter = StreamSplitter {
         inner: input,
         buf: &mut k_buf,
     };
-    let a_buf = reshard_try_stream(ctx, splitter, shard_picker).await?;
+    reshard_try_stream_for_each(ctx, splitter, shard_picker, |_, tag| on_tag(tag)).await?;
 
-    Ok((k_buf, a_buf))
+    k_buf.into_vec()
 }
 
 /// Takes a fallible input stream that yields a tuple `(K, A)` and produces a new stream
 /// over `A` while collecting `K` elements into the provided buffer.
-/// Any error encountered from the input stream is propagated.
+/// Any error encountered from the input stream or the buffer is propagated.
 #[pin_project]
 struct StreamSplitter<'a, S: Stream<Item = DataWithTag<K, A>>, K, A> {
     #[pin]
     inner: S,
-    buf: &'a mut Vec<K>,
+    buf: &'a mut SpillBuffer<K>,
 }
 
-impl<S: Stream<Item = Result<(K, A), Error>>, K, A> Stream for StreamSplitter<'_, S, K, A> {
+impl<S: Stream<Item = Result<(K, A), Error>>, K: Serializable, A> Stream
+    for StreamSplitter<'_, S, K, A>
+{
     type Item = Result<A, crate::error::Error>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.project();
         match ready!(this.inner.poll_next(cx)) {
-            Some(Ok((k, a))) => {
-                this.buf.push(k);
-                Poll::Ready(Some(Ok(a)))
-            }
+            Some(Ok((k, a))) => Poll::Ready(Some(this.buf.push(k).map(|()| a))),
             Some(Err(e)) => Poll::Ready(Some(Err(e))),
             None => Poll::Ready(None),
         }
@@ -105,10 +112,12 @@ mod tests {
                     |ctx, input| async move {
                         let shard_id = ctx.shard_id();
                         let sz = input.len();
-                        let (values, tags) = reshard_aad(
+                        let mut tags = Vec::new();
+                        let values = reshard_aad(
                             ctx,
                             stream::iter(input).map(|v| Ok((v, BA8::ZERO))),
                             |_, _, _| ShardIndex::FIRST,
+                            |tag| tags.push(tag),
                         )
                         .await
                         .unwrap();
@@ -138,6 +147,7 @@ mod tests {
                             stream::iter(input)
                                 .map(|_| Err::<(BA8, BA8), _>(Error::InconsistentShares)),
                             |_, _, _| ShardIndex::FIRST,
+                            |_| {},
                         )
                         .await
                         .unwrap();
@@ -0,0 +1,133 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, IntoInnerError, Read, Seek, SeekFrom, Write},
+};
+
+use generic_array::GenericArray;
+
+use crate::{error::Error, ff::Serializable};
+
+/// Number of decrypted reports the input stage of a query keeps in memory. Reports beyond it
+/// are written to a temporary file until the protocol starts.
+pub const MAX_IN_MEMORY_REPORTS: usize = 1 << 20;
+
+/// Collects items in order, keeping at most `limit` of them in memory. Once the limit is
+/// reached, further items are serialized to an anonymous file in the system temporary
+/// directory, which is removed when the buffer is dropped.
+///
+/// The protocols that run after the input stage need all of the input at once, so
+/// [`Self::into_vec`] still loads every item. Spilling bounds the memory used while the input is
+/// being decrypted, deduplicated and resharded, and the vector returned by [`Self::into_vec`] is
+/// allocated once, at its final size.
+///
+/// Writes are buffered and done on the calling thread.
+pub struct SpillBuffer<T> {
+    in_memory: Vec<T>,
+    limit: usize,
+    spilled: Option<BufWriter<File>>,
+    spilled_len: usize,
+}
+
+impl<T: Serializable> SpillBuffer<T> {
+    #[must_use]
+    pub fn new(limit: usize) -> Self {
+        Self {
+            in_memory: Vec::new(),
+            limit,
+            spilled: None,
+            spilled_len: 0,
+        }
+    }
+
+    /// Number of items in the buffer, both in memory and spilled.
+    pub fn len(&self) -> usize {
+        self.in_memory.len() + self.spilled_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// ## Errors
+    /// If the temporary file can't be created or written to.
+    pub fn push(&mut self, item: T) -> Result<(), Error> {
+        if self.in_memory.len() < self.limit {
+            self.in_memory.push(item);
+            return Ok(());
+        }
+
+        let file = if let Some(file) = &mut self.spilled {
+            file
+        } else {
+            tracing::info!(
+                "More than {} reports received, writing the rest to a temporary file",
+                self.limit
+            );
+            self.spilled.insert(BufWriter::new(tempfile::tempfile()?))
+        };
+        let mut buf = GenericArray::default();
+        item.serialize(&mut buf);
+        file.write_all(&buf)?;
+        self.spilled_len += 1;
+
+        Ok(())
+    }
+
+    /// Returns all items, in the order they were pushed.
+    ///
+    /// ## Errors
+    /// If the spilled items can't be read back.
+    pub fn into_vec(self) -> Result<Vec<T>, Error> {
+        let Self {
+            in_memory,
+            spilled,
+            spilled_len,
+            ..
+        } = self;
+        let Some(spilled) = spilled else {
+            return Ok(in_memory);
+        };
+
+        let mut file = spilled.into_inner().map_err(IntoInnerError::into_error)?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(file);
+        let mut items = Vec::with_capacity(in_memory.len() + spilled_len);
+        items.extend(in_memory);
+        let mut buf = GenericArray::default();
+        for _ in 0..spilled_len {
+            reader.read_exact(&mut buf)?;
+            items.push(T::deserialize(&buf).map_err(|e| Error::ParseError(e.into()))?);
+        }
+
+        Ok(items)
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::SpillBuffer;
+    use crate::ff::{boolean_array::BA32, U128Conversions};
+
+    fn values(n: u128) -> impl Iterator<Item = BA32> {
+        (0..n).map(BA32::truncate_from)
+    }
+
+    #[test]
+    fn keeps_order_when_spilling() {
+        for limit in [0, 3, 10, 20] {
+            let mut buf = SpillBuffer::new(limit);
+            for value in values(10) {
+                buf.push(value).unwrap();
+            }
+            assert_eq!(10, buf.len());
+            assert_eq!(values(10).collect::<Vec<_>>(), buf.into_vec().unwrap());
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let buf = SpillBuffer::<BA32>::new(0);
+        assert!(buf.is_empty());
+        assert!(buf.into_vec().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,278 @@
+use std::{convert::Infallible, marker::PhantomData};
+
+use futures::{stream::iter, StreamExt, TryStreamExt};
+use futures_util::stream::repeat;
+
+use crate::{
+    error::{Error, LengthError},
+    ff::{
+        boolean::Boolean,
+        boolean_array::{BA20, BA32, BA8},
+        curve_points::RP25519,
+        ec_prime_field::Fp25519,
+        Field, Serializable,
+    },
+    helpers::{
+        query::{QuerySize, WalrQueryParams},
+        BodyStream, LengthDelimitedStream,
+    },
+    hpke::PrivateKeyRegistry,
+    protocol::{
+        basics::{Reveal, ShareKnownValue},
+        context::{DZKPUpgraded, MacUpgraded, UpgradableContext},
+        ipa_prf::{
+            oprf_padding::validate_padding_parameters,
+            prf_eval::PrfSharing,
+            step::IpaPrfStep,
+            walr::{walr, WalrInputRow},
+            Shuffle, CONV_CHUNK, PRF_CHUNK, SORT_CHUNK,
+        },
+        prss::FromPrss,
+        step::ProtocolStep::IpaPrf,
+        BooleanProtocols,
+    },
+    report::{walr::EncryptedWalrReport, EventType},
+    secret_sharing::{
+        replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, FieldSimd,
+        SharedValue, TransposeFrom, Vectorizable,
+    },
+    sync::Arc,
+};
+
+pub struct WalrQuery<C, R: PrivateKeyRegistry> {
+    config: WalrQueryParams,
+    key_registry: Arc<R>,
+    phantom_data: PhantomData<C>,
+}
+
+impl<C, R: PrivateKeyRegistry> WalrQuery<C, R> {
+    pub fn new(config: WalrQueryParams, key_registry: Arc<R>) -> Self {
+        Self {
+            config,
+            key_registry,
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+impl<C, R> WalrQuery<C, R>
+where
+    C: UpgradableContext + Shuffle,
+    R: PrivateKeyRegistry,
+    Replicated<Boolean>: Serializable + ShareKnownValue<C, Boolean>,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<Boolean, 8>: BooleanProtocols<DZKPUpgraded<C>, 8>,
+    Replicated<Boolean, 16>: BooleanProtocols<DZKPUpgraded<C>, 16>,
+    Replicated<Boolean, CONV_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, CONV_CHUNK>,
+    Replicated<Boolean, SORT_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, SORT_CHUNK>,
+    Replicated<Fp25519, PRF_CHUNK>:
+        PrfSharing<MacUpgraded<C, Fp25519>, PRF_CHUNK, Field = Fp25519> + FromPrss,
+    Replicated<RP25519, PRF_CHUNK>:
+        Reveal<MacUpgraded<C, Fp25519>, Output = <RP25519 as Vectorizable<PRF_CHUNK>>::Array>,
+{
+    /// Runs the query on reports that carry [`WalrQueryParams::features`] features each. The
+    /// output is the noisy gradient of the model [`WalrQueryParams::model_weights`], with one
+    /// coordinate per feature.
+    ///
+    /// ## Errors
+    /// If the input can't be decrypted, or the protocol fails.
+    #[tracing::instrument("walr_query", skip_all, fields(sz=%query_size))]
+    pub async fn execute(
+        self,
+        ctx: C,
+        query_size: QuerySize,
+        input_stream: BodyStream,
+    ) -> Result<Vec<Replicated<BA32>>, Error> {
+        match self.config.features.count() {
+            8 => self.execute_with::<8>(ctx, query_size, input_stream).await,
+            16 => self.execute_with::<16>(ctx, query_size, input_stream).await,
+            features => Err(Error::Unsupported(format!(
+                "WALR queries do not support {features} features"
+            ))),
+        }
+    }
+
+    async fn execute_with<const B: usize>(
+        self,
+        ctx: C,
+        query_size: QuerySize,
+        input_stream: BodyStream,
+    ) -> Result<Vec<Replicated<BA32>>, Error>
+    where
+        Boolean: FieldSimd<B>,
+        Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+        BitDecomposed<Replicated<Boolean, B>>: FromPrss<usize>
+            + for<'a> TransposeFrom<&'a [Replicated<BA8>; B], Error = Infallible>
+            + for<'a> TransposeFrom<&'a [Replicated<BA32>; B], Error = Infallible>,
+        Vec<Replicated<BA32>>:
+            for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
+    {
+        let Self {
+            config,
+            key_registry,
+            phantom_data: _,
+        } = self;
+        tracing::info!("New query: {config:?}");
+        let ctx = ctx.narrow(&IpaPrf);
+        let sz = usize::from(query_size);
+
+        let weights = config.model_weights.weights();
+        if weights.len() > B {
+            return Err(Error::InvalidQueryParameter(
+                format!(
+                    "{} model weights were given for {B} features",
+                    weights.len()
+                )
+                .into(),
+            ));
+        }
+        let mut model_weights = [0; B];
+        model_weights[..weights.len()].copy_from_slice(weights);
+
+        validate_padding_parameters(
+            ctx.narrow(&IpaPrfStep::ValidatePaddingParameters),
+            &config.padding_params(),
+        )
+        .await?;
+
+        let input =
+            LengthDelimitedStream::<EncryptedWalrReport<BA8, BA20, B, _>, _>::new(input_stream)
+                .map_err(Into::<Error>::into)
+                .map_ok(|enc_reports| {
+                    iter(enc_reports.into_iter().map(|enc_report| {
+                        enc_report
+                            .decrypt(key_registry.as_ref())
+                            .map_err(Into::<Error>::into)
+                    }))
+                })
+                .try_flatten()
+                .take(sz)
+                .zip(repeat(ctx.clone()))
+                .map(|(res, ctx)| {
+                    res.map(|report| {
+                        let is_trigger = Replicated::<Boolean>::share_known_value(
+                            &ctx,
+                            match report.event_type {
+                                EventType::Source => Boolean::ZERO,
+                                EventType::Trigger => Boolean::ONE,
+                            },
+                        );
+
+                        WalrInputRow {
+                            match_key: report.match_key,
+                            is_trigger,
+                            timestamp: report.timestamp,
+                            feature_vector: report.feature_vector,
+                        }
+                    })
+                })
+                .try_collect::<Vec<_>>()
+                .await?;
+
+        walr::<_, BA8, BA20, BA32, B>(
+            ctx,
+            input,
+            &model_weights,
+            config.dp_mechanism,
+            config.padding_params(),
+        )
+        .await
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{iter::zip, sync::Arc};
+
+    use rand::{rngs::StdRng, Rng};
+    use rand_core::SeedableRng;
+
+    use super::WalrQuery;
+    use crate::{
+        ff::{
+            boolean_array::{BA20, BA8},
+            U128Conversions,
+        },
+        helpers::{
+            query::{DpMechanism, FeatureCount, ModelWeights, QuerySize, WalrQueryParams},
+            BodyStream,
+        },
+        hpke::{KeyPair, KeyRegistry},
+        report::{walr::WalrReport, DEFAULT_KEY_ID},
+        secret_sharing::IntoShares,
+        test_fixture::{
+            join3v,
+            walr::{walr_gradient_in_the_clear, TestWalrRecord},
+            Reconstruct, TestWorld,
+        },
+    };
+
+    #[tokio::test]
+    async fn encrypted_reports() {
+        Box::pin(encrypted_reports_with::<16>()).await;
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports_with_8_features() {
+        Box::pin(encrypted_reports_with::<8>()).await;
+    }
+
+    async fn encrypted_reports_with<const B: usize>() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut features = || std::array::from_fn(|_| rng.gen_range(0..256));
+
+        let records: Vec<TestWalrRecord<B>> = vec![
+            TestWalrRecord::source(12345, 0, features()),
+            TestWalrRecord::source(68362, 4, features()),
+            TestWalrRecord::trigger(12345, 10),
+            TestWalrRecord::trigger(68362, 12),
+            TestWalrRecord::source(68362, 20, features()),
+            TestWalrRecord::trigger(68362, 30),
+        ];
+        // Features without a weight have a weight of zero.
+        let weights = [2, -1];
+        let mut model_weights = [0; B];
+        model_weights[..weights.len()].copy_from_slice(&weights);
+        let expected = walr_gradient_in_the_clear(&records, &model_weights);
+
+        let query_size = QuerySize::try_from(records.len()).unwrap();
+        let key_registry = Arc::new(KeyRegistry::<KeyPair>::random(1, &mut rng));
+
+        let mut buffers: [_; 3] = std::array::from_fn(|_| Vec::new());
+
+        let shares: [Vec<WalrReport<BA8, BA20, B>>; 3] = records.into_iter().share();
+        for (buf, shares) in zip(&mut buffers, shares) {
+            for share in shares {
+                share
+                    .delimited_encrypt_to(DEFAULT_KEY_ID, key_registry.as_ref(), &mut rng, buf)
+                    .unwrap();
+            }
+        }
+
+        let world = TestWorld::default();
+        let contexts = world.malicious_contexts();
+        #[allow(clippy::large_futures)]
+        let results = join3v(buffers.into_iter().zip(contexts).map(|(buffer, ctx)| {
+            let query_config = WalrQueryParams {
+                features: FeatureCount::try_from(u32::try_from(B).unwrap()).unwrap(),
+                model_weights: ModelWeights::try_from(weights.as_slice()).unwrap(),
+                dp_mechanism: DpMechanism::NoDp,
+                ..Default::default()
+            };
+            let input = BodyStream::from(buffer);
+
+            WalrQuery::<_, KeyRegistry<KeyPair>>::new(query_config, Arc::clone(&key_registry))
+                .execute(ctx, query_size, input)
+        }))
+        .await;
+
+        assert_eq!(
+            results
+                .reconstruct()
+                .iter()
+                .map(U128Conversions::as_u128)
+                .collect::<Vec<u128>>(),
+            expected
+        );
+    }
+}
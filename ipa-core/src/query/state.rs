@@ -5,20 +5,25 @@ use std::{
     task::Poll,
 };
 
-use ::tokio::sync::oneshot::{error::TryRecvError, Receiver};
+use ::tokio::sync::oneshot::{self, error::TryRecvError, Receiver};
 use futures::{ready, FutureExt};
 use serde::{Deserialize, Serialize};
+use tracing::Span;
 
 use crate::{
+    error::Error as ProtocolError,
     executor::IpaJoinHandle,
-    helpers::{query::QueryConfig, RoleAssignment},
+    helpers::{
+        query::{QueryConfig, ReportCollector},
+        RoleAssignment,
+    },
     protocol::QueryId,
     query::runner::QueryResult,
     sync::Mutex,
 };
 
 /// The status of query processing
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum QueryStatus {
     /// Only query running on the coordinator helper can be in this state. Means that coordinator
     /// sent out requests to other helpers and asked them to assume a given role for this query.
@@ -33,11 +38,25 @@ pub enum QueryStatus {
     AwaitingCompletion,
     /// Query has finished and results are available.
     Completed,
+    /// Query did not finish before one of its deadlines and has been cancelled.
+    TimedOut,
+    /// Query execution returned an error. `step` is the protocol step that raised it, if known.
+    Failed {
+        reason: String,
+        step: Option<String>,
+    },
 }
 
 impl Display for QueryStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{self:?}")
+        match self {
+            Self::Failed {
+                reason,
+                step: Some(step),
+            } => write!(f, "Failed at {step}: {reason}"),
+            Self::Failed { reason, step: None } => write!(f, "Failed: {reason}"),
+            _ => write!(f, "{self:?}"),
+        }
     }
 }
 
@@ -46,10 +65,15 @@ impl From<&QueryState> for QueryStatus {
         match source {
             QueryState::Empty => panic!("Query cannot be in the empty state"),
             QueryState::Preparing(_) => QueryStatus::Preparing,
-            QueryState::AwaitingInputs(_, _) => QueryStatus::AwaitingInputs,
+            QueryState::AwaitingInputs(..) => QueryStatus::AwaitingInputs,
             QueryState::Running(_) => QueryStatus::Running,
-            QueryState::AwaitingCompletion => QueryStatus::AwaitingCompletion,
+            QueryState::AwaitingCompletion(_) => QueryStatus::AwaitingCompletion,
             QueryState::Completed(_) => QueryStatus::Completed,
+            QueryState::TimedOut => QueryStatus::TimedOut,
+            QueryState::Failed(error) => QueryStatus::Failed {
+                reason: error.to_string(),
+                step: error.step().map(ToOwned::to_owned),
+            },
         }
     }
 }
@@ -58,10 +82,16 @@ impl From<&QueryState> for QueryStatus {
 /// queried about the state of a sharded helper. In such scenarios, there will be many different
 /// [`QueryStatus`] and the [`Processor`] needs to return a single one that describes the entire
 /// helper. With this function we're saying that the minimum state across all shards is the one
-/// that describes the helper.
+/// that describes the helper. The exceptions are a query that failed or timed out on any shard,
+/// as it can't make progress on the whole helper. Failures take precedence over timeouts, because
+/// they carry the reason why the query did not finish.
 #[must_use]
 pub fn min_status(a: QueryStatus, b: QueryStatus) -> QueryStatus {
     match (a, b) {
+        (failed @ QueryStatus::Failed { .. }, _) | (_, failed @ QueryStatus::Failed { .. }) => {
+            failed
+        }
+        (QueryStatus::TimedOut, _) | (_, QueryStatus::TimedOut) => QueryStatus::TimedOut,
         (QueryStatus::Preparing, _) | (_, QueryStatus::Preparing) => QueryStatus::Preparing,
         (QueryStatus::AwaitingInputs, _) | (_, QueryStatus::AwaitingInputs) => {
             QueryStatus::AwaitingInputs
@@ -78,22 +108,35 @@ pub fn min_status(a: QueryStatus, b: QueryStatus) -> QueryStatus {
 pub enum QueryState {
     Empty,
     Preparing(QueryConfig),
-    AwaitingInputs(QueryConfig, RoleAssignment),
+    /// The span is the parent of all spans emitted by the query once it starts running.
+    AwaitingInputs(QueryConfig, RoleAssignment, Option<ReportCollector>, Span),
     Running(RunningQuery),
-    AwaitingCompletion,
+    /// Sending to this channel cancels the query that is being awaited.
+    AwaitingCompletion(oneshot::Sender<()>),
     Completed(QueryResult),
+    TimedOut,
+    Failed(ProtocolError),
 }
 
 impl QueryState {
+    /// Returns the state of a query whose execution has returned `result`.
+    #[must_use]
+    pub fn finished(result: QueryResult) -> Self {
+        match result {
+            Ok(_) => Self::Completed(result),
+            Err(error) => Self::Failed(error),
+        }
+    }
+
     pub fn transition(cur_state: &Self, new_state: Self) -> Result<Self, StateError> {
         use QueryState::{AwaitingInputs, Empty, Preparing, Running};
 
         match (cur_state, &new_state) {
             // If query is not running, coordinator initial state is preparing
             // and followers initial state is awaiting inputs
-            (Empty, Preparing(_) | AwaitingInputs(_, _))
-            | (Preparing(_), AwaitingInputs(_, _))
-            | (AwaitingInputs(_, _), Running(_)) => Ok(new_state),
+            (Empty, Preparing(_) | AwaitingInputs(..))
+            | (Preparing(_), AwaitingInputs(..))
+            | (AwaitingInputs(..), Running(_)) => Ok(new_state),
             (_, Preparing(_)) => Err(StateError::AlreadyRunning),
             (_, _) => Err(StateError::InvalidState {
                 from: cur_state.into(),
@@ -210,6 +253,60 @@ impl RunningQueries {
             queries: self,
         }
     }
+
+    /// Cancels the query and marks it as timed out, if `is_late` returns `true` for its
+    /// current state. Queries that have finished are never late. Returns whether the query
+    /// has been cancelled.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    pub fn time_out<F: FnOnce(&QueryState) -> bool>(&self, query_id: QueryId, is_late: F) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(state) = inner.get_mut(&query_id) else {
+            return false;
+        };
+        if let QueryState::Running(running) = state {
+            if let Some(result) = running.try_complete() {
+                *state = QueryState::finished(result);
+            }
+        }
+        if matches!(
+            state,
+            QueryState::Completed(_) | QueryState::TimedOut | QueryState::Failed(_)
+        ) || !is_late(state)
+        {
+            return false;
+        }
+
+        match std::mem::replace(state, QueryState::TimedOut) {
+            QueryState::Running(running) => running.join_handle.abort(),
+            QueryState::AwaitingCompletion(cancel) => {
+                // the task awaiting completion may have just finished
+                let _ = cancel.send(());
+            }
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Forgets the query if it has timed out or failed. Returns whether the query has been
+    /// removed.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    pub fn remove_terminated(&self, query_id: QueryId) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if matches!(
+            inner.get(&query_id),
+            Some(QueryState::TimedOut | QueryState::Failed(_))
+        ) {
+            inner.remove(&query_id);
+            true
+        } else {
+            false
+        }
+    }
 }
 
 /// RAII guard to clean up query state when dropped.
@@ -258,6 +355,13 @@ impl Drop for RemoveQuery<'_> {
 mod tests {
     use crate::query::{state::min_status, QueryStatus};
 
+    fn failed(reason: &str) -> QueryStatus {
+        QueryStatus::Failed {
+            reason: reason.to_string(),
+            step: None,
+        }
+    }
+
     #[test]
     fn test_order() {
         // this list sorted in priority order. Preparing is the lowest possible value,
@@ -271,11 +375,62 @@ mod tests {
         ];
 
         for i in 0..all.len() {
-            let this = all[i];
-            for other in all.into_iter().skip(i) {
-                assert_eq!(this, min_status(this, other));
-                assert_eq!(this, min_status(other, this));
+            let this = &all[i];
+            for other in all.iter().skip(i) {
+                assert_eq!(this, &min_status(this.clone(), other.clone()));
+                assert_eq!(this, &min_status(other.clone(), this.clone()));
             }
         }
     }
+
+    #[test]
+    fn timed_out_takes_precedence() {
+        for other in [
+            QueryStatus::Preparing,
+            QueryStatus::AwaitingInputs,
+            QueryStatus::Running,
+            QueryStatus::AwaitingCompletion,
+            QueryStatus::Completed,
+            QueryStatus::TimedOut,
+        ] {
+            assert_eq!(
+                QueryStatus::TimedOut,
+                min_status(QueryStatus::TimedOut, other.clone())
+            );
+            assert_eq!(
+                QueryStatus::TimedOut,
+                min_status(other, QueryStatus::TimedOut)
+            );
+        }
+    }
+
+    #[test]
+    fn failed_takes_precedence() {
+        for other in [
+            QueryStatus::Preparing,
+            QueryStatus::AwaitingInputs,
+            QueryStatus::Running,
+            QueryStatus::AwaitingCompletion,
+            QueryStatus::Completed,
+            QueryStatus::TimedOut,
+        ] {
+            assert_eq!(failed("foo"), min_status(failed("foo"), other.clone()));
+            assert_eq!(failed("foo"), min_status(other, failed("foo")));
+        }
+        assert_eq!(failed("foo"), min_status(failed("foo"), failed("bar")));
+    }
+
+    #[test]
+    fn failed_status_display() {
+        assert_eq!("Failed: foo", failed("foo").to_string());
+        assert_eq!(
+            "Failed at protocol/step: foo",
+            QueryStatus::Failed {
+                reason: "foo".to_string(),
+                step: Some("protocol/step".to_string()),
+            }
+            .to_string()
+        );
+        assert_eq!("Running", QueryStatus::Running.to_string());
+    }
 }
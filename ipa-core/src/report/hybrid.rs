@@ -27,22 +27,23 @@
 //! all secret sharings (including the sharings of zero), making the collection of reports
 //! cryptographically indistinguishable.
 
-use std::{collections::HashSet, convert::Infallible, marker::PhantomData, ops::Add};
+use std::{
+    collections::HashSet, convert::Infallible, marker::PhantomData, ops::Add, time::SystemTime,
+};
 
 use bytes::{Buf, BufMut, Bytes};
 use generic_array::{ArrayLength, GenericArray};
 use hpke::Serializable as _;
 use rand_core::{CryptoRng, RngCore};
-use typenum::{Sum, Unsigned, U12, U16};
+use typenum::{Sum, Unsigned, U16, U17, U18, U32};
 
 use crate::{
     const_assert_eq,
     error::{BoxError, Error},
     ff::{
-        boolean_array::{
-            BooleanArray, BooleanArrayReader, BooleanArrayWriter, BA112, BA3, BA32, BA64, BA8,
-        },
-        Serializable,
+        boolean::Boolean,
+        boolean_array::{BooleanArray, BooleanArrayReader, BooleanArrayWriter, BA128, BA32, BA64},
+        Serializable, U128Conversions,
     },
     hpke::{
         open_in_place, seal_in_place, CryptError, EncapsulationSize, PrivateKeyRegistry,
@@ -64,6 +65,11 @@ pub static HELPER_ORIGIN: &str = "github.com/private-attribution";
 pub type KeyIdentifier = u8;
 pub const DEFAULT_KEY_ID: KeyIdentifier = 0;
 
+/// Event timestamps, in seconds since the Unix epoch, as they are used by the hybrid protocol.
+/// Timestamps are taken modulo `2^32` seconds, so they do not wrap around before 2106 and the
+/// time between an impression and a conversion can be computed exactly.
+pub type HybridTimestamp = BA32;
+
 #[derive(Debug, thiserror::Error)]
 #[error("string contains non-ascii symbols: {0}")]
 pub struct NonAsciiStringError(String);
@@ -88,6 +94,12 @@ pub enum InvalidHybridReportError {
     UnknownEventType(u8),
     #[error("Incorrect hybrid info type: Expected {0}")]
     WrongInfoType(&'static str),
+    #[error("report length {0} does not match the configured breakdown key and value widths, expected: {1}")]
+    UnexpectedLength(usize, usize),
+    #[error("key identifier {0} does not match the key identifier {1} in the report info")]
+    KeyIdMismatch(KeyIdentifier, KeyIdentifier),
+    #[error("key identifier {0} is outside of its validity period")]
+    ExpiredKey(KeyIdentifier),
 }
 
 /// Event type as described [`ipa-issue`]
@@ -121,6 +133,7 @@ where
 {
     pub match_key: Replicated<BA64>,
     pub breakdown_key: Replicated<BK>,
+    pub timestamp: Replicated<HybridTimestamp>,
     pub info: HybridImpressionInfo,
 }
 
@@ -139,9 +152,12 @@ where
         self.match_key.serialize(GenericArray::from_mut_slice(&mut plaintext_mk));
         let mut plaintext_bk = vec![0u8; bk_sz];
         self.breakdown_key.serialize(GenericArray::from_mut_slice(&mut plaintext_bk));
+        let mut plaintext_ts = GenericArray::default();
+        self.timestamp.serialize(&mut plaintext_ts);
 
         buf.put_slice(&plaintext_mk);
         buf.put_slice(&plaintext_bk);
+        buf.put_slice(&plaintext_ts);
         buf.put_slice(&self.info.to_bytes());
     }
 
@@ -155,14 +171,20 @@ where
         let breakdown_key =
             Replicated::<BK>::deserialize(GenericArray::from_slice(&buf[mk_sz..mk_sz + bk_sz]))
             .map_err(|e| InvalidHybridReportError::DeserializationError("breakdown_key", e.into()))?;
-        let info = HybridImpressionInfo::from_bytes(&buf[mk_sz + bk_sz..])?;
+        let ts_offset = mk_sz + bk_sz;
+        let timestamp = Replicated::<HybridTimestamp>::deserialize(GenericArray::from_slice(
+            &buf[ts_offset..ts_offset + Replicated::<HybridTimestamp>::size()],
+        ))
+        .map_err(|e| InvalidHybridReportError::DeserializationError("timestamp", e.into()))?;
+        let info =
+            HybridImpressionInfo::from_bytes(&buf[ts_offset + Replicated::<HybridTimestamp>::size()..])?;
 
-        Ok(Self { match_key, breakdown_key, info })
+        Ok(Self { match_key, breakdown_key, timestamp, info })
     }
 
     #[must_use]
     pub fn serialized_len() -> usize {
-        Replicated::<BK>::size() + Replicated::<BA64>::size()
+        Replicated::<BK>::size() + Replicated::<HybridTimestamp>::size() + Replicated::<BA64>::size()
     }
 }
 
@@ -173,7 +195,8 @@ where
     <Replicated<BK> as Serializable>::Size: Add<U16>,
     <<Replicated<BK> as Serializable>::Size as Add<<Replicated<BA64> as Serializable>::Size>>:: Output: ArrayLength,
 {
-    const BTT_END: usize = <Replicated<BK> as Serializable>::Size::USIZE;
+    const BK_END: usize = <Replicated<BK> as Serializable>::Size::USIZE;
+    const BTT_END: usize = Self::BK_END + Replicated::<HybridTimestamp>::size();
 
     /// # Panics
     /// If report length does not fit in `u16`.
@@ -229,7 +252,9 @@ where
 
         let mut plaintext_btt = vec![0u8; Self::BTT_END];
         self.breakdown_key
-            .serialize(GenericArray::from_mut_slice(&mut plaintext_btt[..]));
+            .serialize(GenericArray::from_mut_slice(&mut plaintext_btt[..Self::BK_END]));
+        self.timestamp
+            .serialize(GenericArray::from_mut_slice(&mut plaintext_btt[Self::BK_END..]));
 
         let pk = key_registry.public_key(key_id).ok_or(CryptError::NoSuchKey(key_id))?;
         let info_enc_bytes = self.info.to_enc_bytes();
@@ -529,8 +554,10 @@ where
         (Self::CIPHERTEXT_MK_OFFSET + TagSize::USIZE + Replicated::<BA64>::size());
     const CIPHERTEXT_BTT_OFFSET: usize = Self::ENCAP_KEY_BTT_OFFSET + EncapsulationSize::USIZE;
 
-    const KEY_IDENTIFIER_OFFSET: usize =
-        (Self::CIPHERTEXT_BTT_OFFSET + TagSize::USIZE + Replicated::<BK>::size());
+    const KEY_IDENTIFIER_OFFSET: usize = (Self::CIPHERTEXT_BTT_OFFSET
+        + TagSize::USIZE
+        + Replicated::<BK>::size()
+        + Replicated::<HybridTimestamp>::size());
     const INFO_OFFSET: usize = Self::KEY_IDENTIFIER_OFFSET + 1;
 
     pub fn encap_key_mk(&self) -> &[u8] {
@@ -554,12 +581,21 @@ where
     }
 
     /// ## Errors
-    /// If the report contents are invalid.
+    /// If the report contents are invalid, including when the report was encrypted
+    /// with a breakdown key width other than `BK`.
     pub fn from_bytes(bytes: Bytes) -> Result<Self, InvalidHybridReportError> {
-        if bytes.len() < Self::INFO_OFFSET {
-            return Err(InvalidHybridReportError::Length(
+        let expected_len = Self::INFO_OFFSET + HybridImpressionInfo::BYTE_LEN;
+        if bytes.len() != expected_len {
+            return Err(InvalidHybridReportError::UnexpectedLength(
                 bytes.len(),
-                Self::INFO_OFFSET,
+                expected_len,
+            ));
+        }
+        let info = HybridImpressionInfo::from_bytes(&bytes[Self::INFO_OFFSET..])?;
+        if info.key_id != bytes[Self::KEY_IDENTIFIER_OFFSET] {
+            return Err(InvalidHybridReportError::KeyIdMismatch(
+                bytes[Self::KEY_IDENTIFIER_OFFSET],
+                info.key_id,
             ));
         }
         Ok(Self {
@@ -579,13 +615,15 @@ where
         key_registry: &P,
     ) -> Result<HybridImpressionReport<BK>, InvalidHybridReportError> {
         type CTMKLength = Sum<<Replicated<BA64> as Serializable>::Size, TagSize>;
-        type CTBTTLength<BK> = <<Replicated<BK> as Serializable>::Size as Add<TagSize>>::Output;
 
         let mut ct_mk: GenericArray<u8, CTMKLength> =
             *GenericArray::from_slice(self.mk_ciphertext());
         let sk = key_registry
             .private_key(self.key_id())
             .ok_or(CryptError::NoSuchKey(self.key_id()))?;
+        if key_registry.is_expired(self.key_id(), SystemTime::now()) {
+            return Err(InvalidHybridReportError::ExpiredKey(self.key_id()));
+        }
         let info =
             HybridImpressionInfo::from_bytes(&self.data[Self::INFO_OFFSET..]).map_err(|e| {
                 InvalidHybridReportError::DeserializationError("HybridImpressionInfo", e.into())
@@ -593,19 +631,23 @@ where
         let info_enc_bytes = info.to_enc_bytes();
 
         let plaintext_mk = open_in_place(sk, self.encap_key_mk(), &mut ct_mk, &info_enc_bytes)?;
-        let mut ct_btt: GenericArray<u8, CTBTTLength<BK>> =
-            GenericArray::from_slice(self.btt_ciphertext()).clone();
+        let mut ct_btt = self.btt_ciphertext().to_vec();
 
         let plaintext_btt = open_in_place(sk, self.encap_key_btt(), &mut ct_btt, &info_enc_bytes)?;
+        let (plaintext_bk, plaintext_ts) = plaintext_btt.split_at(Replicated::<BK>::size());
 
         Ok(HybridImpressionReport::<BK> {
             match_key: Replicated::<BA64>::deserialize_infallible(GenericArray::from_slice(
                 plaintext_mk,
             )),
-            breakdown_key: Replicated::<BK>::deserialize(GenericArray::from_slice(plaintext_btt))
+            breakdown_key: Replicated::<BK>::deserialize(GenericArray::from_slice(plaintext_bk))
                 .map_err(|e| {
-                InvalidHybridReportError::DeserializationError("is_trigger", e.into())
-            })?,
+                    InvalidHybridReportError::DeserializationError("breakdown_key", e.into())
+                })?,
+            timestamp: Replicated::<HybridTimestamp>::deserialize(GenericArray::from_slice(
+                plaintext_ts,
+            ))
+            .map_err(|e| InvalidHybridReportError::DeserializationError("timestamp", e.into()))?,
             info,
         })
     }
@@ -659,12 +701,21 @@ where
     }
 
     /// ## Errors
-    /// If the report contents are invalid.
+    /// If the report contents are invalid, including when the report was encrypted
+    /// with a value width other than `V`.
     pub fn from_bytes(bytes: Bytes) -> Result<Self, InvalidHybridReportError> {
-        if bytes.len() < Self::INFO_OFFSET {
-            return Err(InvalidHybridReportError::Length(
-                bytes.len(),
-                Self::INFO_OFFSET,
+        let min_len = Self::INFO_OFFSET + HybridConversionInfo::MIN_BYTE_LEN;
+        if bytes.len() < min_len {
+            return Err(InvalidHybridReportError::Length(bytes.len(), min_len));
+        }
+        // The conversion info has a variable length site domain, so a report encrypted with
+        // a different value width would still pass the length check above. Parsing the info
+        // and matching its key identifier against the one at the fixed offset catches that.
+        let info = HybridConversionInfo::from_bytes(&bytes[Self::INFO_OFFSET..])?;
+        if info.key_id != bytes[Self::KEY_IDENTIFIER_OFFSET] {
+            return Err(InvalidHybridReportError::KeyIdMismatch(
+                bytes[Self::KEY_IDENTIFIER_OFFSET],
+                info.key_id,
             ));
         }
         Ok(Self {
@@ -691,6 +742,9 @@ where
         let sk = key_registry
             .private_key(self.key_id())
             .ok_or(CryptError::NoSuchKey(self.key_id()))?;
+        if key_registry.is_expired(self.key_id(), SystemTime::now()) {
+            return Err(InvalidHybridReportError::ExpiredKey(self.key_id()));
+        }
         let info =
             HybridConversionInfo::from_bytes(&self.data[Self::INFO_OFFSET..]).map_err(|e| {
                 InvalidHybridReportError::DeserializationError("HybridConversionInfo", e.into())
@@ -718,10 +772,11 @@ where
 pub type PrfHybridReport<BK, V> = IndistinguishableHybridReport<BK, V, u64>;
 
 /// After grouping `IndistinguishableHybridReport`s by the OPRF of thier `match_key`,
-/// that OPRF value is no longer required.
-pub type AggregateableHybridReport<BK, V> = IndistinguishableHybridReport<BK, V, ()>;
+/// that OPRF value is no longer required. Attribution windows are enforced while the reports
+/// are grouped, so neither the timestamp nor the kind of the report is required either.
+pub type AggregateableHybridReport<BK, V> = IndistinguishableHybridReport<BK, V, (), (), ()>;
 
-impl<BK, V> IndistinguishableHybridReport<BK, V, ()>
+impl<BK, V> IndistinguishableHybridReport<BK, V, (), (), ()>
 where
     BK: BooleanArray,
     V: BooleanArray,
@@ -730,6 +785,8 @@ where
         match_key: (),
         value: Replicated::<V>::ZERO,
         breakdown_key: Replicated::<BK>::ZERO,
+        timestamp: (),
+        is_impression: (),
     };
 
     fn join_fields(value: V, breakdown_key: BK) -> <Self as Shuffleable>::Share {
@@ -764,6 +821,8 @@ where
             match_key: (),
             breakdown_key: report.breakdown_key,
             value,
+            timestamp: (),
+            is_impression: (),
         }
     }
 }
@@ -773,14 +832,23 @@ where
 /// Note: these need to be shuffled (and secret shares need to be rerandomized)
 /// to provide any formal indistinguishability.
 #[derive(Clone, Debug, Eq, PartialEq, Default)]
-pub struct IndistinguishableHybridReport<BK, V, MK = Replicated<BA64>>
-where
+pub struct IndistinguishableHybridReport<
+    BK,
+    V,
+    MK = Replicated<BA64>,
+    TS = Replicated<HybridTimestamp>,
+    IM = Replicated<Boolean>,
+> where
     BK: BooleanArray,
     V: BooleanArray,
 {
     pub match_key: MK,
     pub value: Replicated<V>,
     pub breakdown_key: Replicated<BK>,
+    pub timestamp: TS,
+    /// Set for impressions, unset for conversions and padding. Breakdown keys can't tell
+    /// impressions apart, as an impression may carry a breakdown key of 0.
+    pub is_impression: IM,
 }
 
 impl<BK, V> IndistinguishableHybridReport<BK, V>
@@ -792,25 +860,39 @@ where
         match_key: Replicated::<BA64>::ZERO,
         value: Replicated::<V>::ZERO,
         breakdown_key: Replicated::<BK>::ZERO,
+        timestamp: Replicated::<HybridTimestamp>::ZERO,
+        is_impression: Replicated::<Boolean>::ZERO,
     };
 
-    fn join_fields(match_key: BA64, value: V, breakdown_key: BK) -> <Self as Shuffleable>::Share {
+    fn join_fields(
+        match_key: BA64,
+        value: V,
+        breakdown_key: BK,
+        timestamp: HybridTimestamp,
+        is_impression: Boolean,
+    ) -> <Self as Shuffleable>::Share {
         let mut share = <Self as Shuffleable>::Share::ZERO;
 
         BooleanArrayWriter::new(&mut share)
             .write(&match_key)
             .write(&value)
-            .write(&breakdown_key);
+            .write(&breakdown_key)
+            .write(&timestamp)
+            .write_boolean(is_impression);
 
         share
     }
 
-    fn split_fields(share: &<Self as Shuffleable>::Share) -> (BA64, V, BK) {
+    fn split_fields(
+        share: &<Self as Shuffleable>::Share,
+    ) -> (BA64, V, BK, HybridTimestamp, Boolean) {
         let bits = BooleanArrayReader::new(share);
         let (match_key, bits) = bits.read();
         let (value, bits) = bits.read();
-        let (breakdown_key, _) = bits.read();
-        (match_key, value, breakdown_key)
+        let (breakdown_key, bits) = bits.read();
+        let (timestamp, bits) = bits.read();
+        let (is_impression, _) = bits.read_boolean();
+        (match_key, value, breakdown_key, timestamp, is_impression)
     }
 }
 
@@ -824,6 +906,8 @@ where
             match_key,
             value: Replicated::<V>::ZERO,
             breakdown_key: Replicated::<BK>::ZERO,
+            timestamp: Replicated::<HybridTimestamp>::ZERO,
+            is_impression: Replicated::<Boolean>::ZERO,
         }
     }
 }
@@ -847,10 +931,14 @@ where
     V: BooleanArray,
 {
     fn from(impression_report: HybridImpressionReport<BK>) -> Self {
+        // Like the conversion timestamp below, the kind of the report is public, so every
+        // helper holds it as both of its shares.
         Self {
             match_key: impression_report.match_key,
             value: Replicated::ZERO,
             breakdown_key: impression_report.breakdown_key,
+            timestamp: impression_report.timestamp,
+            is_impression: ReplicatedSecretSharing::new(Boolean::TRUE, Boolean::TRUE),
         }
     }
 }
@@ -861,10 +949,16 @@ where
     V: BooleanArray,
 {
     fn from(conversion_report: HybridConversionReport<V>) -> Self {
+        // The conversion timestamp is public, so every helper can hold it as both of its shares:
+        // the three shares XOR to the timestamp. The shuffle rerandomizes these shares along
+        // with all the other fields.
+        let timestamp = HybridTimestamp::truncate_from(conversion_report.info.timestamp);
         Self {
             match_key: conversion_report.match_key,
             value: conversion_report.value,
             breakdown_key: Replicated::ZERO,
+            timestamp: ReplicatedSecretSharing::new(timestamp, timestamp),
+            is_impression: Replicated::ZERO,
         }
     }
 }
@@ -874,20 +968,18 @@ where
     BK: BooleanArray,
     V: BooleanArray,
 {
-    // this requires BK:BAXX + V:BAYY  such that XX + YY <= 48
+    // this requires BK:BAXX + V:BAYY  such that XX + YY <= 31, as the match key takes 64 bits,
+    // the timestamp takes 32 bits and the impression bit takes 1
     // this is checked in a debud_assert call in ::new below
-    // PERF OPPORTUNITY
-    // note that BA96 would likely be a better fit here. however, that would require a `BA128`
-    // in order to use `impl_malicious_shuffle_share!` and `BA128` cannot currently be
-    // implemented with `the boolean_array_impl!` macro as the trait `secret_sharing::Block`
-    // is not implemented for `bitvec::array::BitArray<[u8; 16]>`
-    type Share = BA112;
+    type Share = BA128;
 
     fn left(&self) -> Self::Share {
         Self::join_fields(
             ReplicatedSecretSharing::left(&self.match_key),
             self.value.left(),
             self.breakdown_key.left(),
+            ReplicatedSecretSharing::left(&self.timestamp),
+            self.is_impression.left(),
         )
     }
 
@@ -896,12 +988,14 @@ where
             ReplicatedSecretSharing::right(&self.match_key),
             self.value.right(),
             self.breakdown_key.right(),
+            ReplicatedSecretSharing::right(&self.timestamp),
+            self.is_impression.right(),
         )
     }
 
     fn new(l: Self::Share, r: Self::Share) -> Self {
         debug_assert!(
-            BA64::BITS + BK::BITS + V::BITS <= Self::Share::BITS,
+            BA64::BITS + BK::BITS + V::BITS + HybridTimestamp::BITS < Self::Share::BITS,
             "share type {} is too small",
             std::any::type_name::<Self::Share>(),
         );
@@ -913,11 +1007,38 @@ where
             match_key: ReplicatedSecretSharing::new(left.0, right.0),
             value: ReplicatedSecretSharing::new(left.1, right.1),
             breakdown_key: ReplicatedSecretSharing::new(left.2, right.2),
+            timestamp: ReplicatedSecretSharing::new(left.3, right.3),
+            is_impression: ReplicatedSecretSharing::new(left.4, right.4),
         }
     }
 }
 
-impl<BK, V> Shuffleable for IndistinguishableHybridReport<BK, V, ()>
+/// Reports are serialized as the left and right shares produced by [`Shuffleable`], so that
+/// decrypted reports can be written to disk while a query reads its input.
+impl<BK, V> Serializable for IndistinguishableHybridReport<BK, V>
+where
+    BK: BooleanArray,
+    V: BooleanArray,
+{
+    type Size = U32;
+    type DeserializationError = Infallible;
+
+    fn serialize(&self, buf: &mut GenericArray<u8, Self::Size>) {
+        let (left, right) = buf.split_at_mut(<BA128 as Serializable>::Size::USIZE);
+        Shuffleable::left(self).serialize(GenericArray::from_mut_slice(left));
+        Shuffleable::right(self).serialize(GenericArray::from_mut_slice(right));
+    }
+
+    fn deserialize(buf: &GenericArray<u8, Self::Size>) -> Result<Self, Self::DeserializationError> {
+        let (left, right) = buf.split_at(<BA128 as Serializable>::Size::USIZE);
+        Ok(Shuffleable::new(
+            BA128::deserialize_infallible(GenericArray::from_slice(left)),
+            BA128::deserialize_infallible(GenericArray::from_slice(right)),
+        ))
+    }
+}
+
+impl<BK, V> Shuffleable for IndistinguishableHybridReport<BK, V, (), (), ()>
 where
     BK: BooleanArray,
     V: BooleanArray,
@@ -948,18 +1069,44 @@ where
             match_key: (),
             value: ReplicatedSecretSharing::new(left.0, right.0),
             breakdown_key: ReplicatedSecretSharing::new(left.1, right.1),
+            timestamp: (),
+            is_impression: (),
         }
     }
 }
 
-impl PrfHybridReport<BA8, BA3> {
+impl<BK, V> PrfHybridReport<BK, V>
+where
+    BK: BooleanArray,
+    V: BooleanArray,
+    Replicated<BK>: Serializable,
+    Replicated<V>: Serializable,
+{
     const PRF_MK_SZ: usize = 8;
-    const V_SZ: usize = <Replicated<BA3> as Serializable>::Size::USIZE;
-    const BK_SZ: usize = <Replicated<BA8> as Serializable>::Size::USIZE;
+    const V_SZ: usize = <Replicated<V> as Serializable>::Size::USIZE;
+    const BK_SZ: usize = <Replicated<BK> as Serializable>::Size::USIZE;
+    const TS_OFFSET: usize = Self::PRF_MK_SZ + Self::V_SZ + Self::BK_SZ;
+    const IM_OFFSET: usize =
+        Self::TS_OFFSET + <Replicated<HybridTimestamp> as Serializable>::Size::USIZE;
 }
 
-impl Serializable for PrfHybridReport<BA8, BA3> {
-    type Size = U12;
+impl<BK, V> Serializable for PrfHybridReport<BK, V>
+where
+    BK: BooleanArray,
+    V: BooleanArray,
+    Replicated<BK>: Serializable,
+    Replicated<V>: Serializable,
+    <Replicated<BK> as Serializable>::Size: Add<U18>,
+    <Replicated<V> as Serializable>::Size: Add<Sum<<Replicated<BK> as Serializable>::Size, U18>>,
+    Sum<<Replicated<V> as Serializable>::Size, Sum<<Replicated<BK> as Serializable>::Size, U18>>:
+        ArrayLength,
+{
+    // 8 bytes of OPRF, 8 bytes of timestamp shares and 2 bytes of impression bit shares,
+    // in addition to `V` and `BK`
+    type Size = Sum<
+        <Replicated<V> as Serializable>::Size,
+        Sum<<Replicated<BK> as Serializable>::Size, U18>,
+    >;
     type DeserializationError = InvalidHybridReportError;
 
     fn serialize(&self, buf: &mut GenericArray<u8, Self::Size>) {
@@ -970,26 +1117,47 @@ impl Serializable for PrfHybridReport<BA8, BA3> {
         ));
 
         self.breakdown_key.serialize(GenericArray::from_mut_slice(
-            &mut buf[Self::PRF_MK_SZ + Self::V_SZ..Self::PRF_MK_SZ + Self::V_SZ + Self::BK_SZ],
+            &mut buf[Self::PRF_MK_SZ + Self::V_SZ..Self::TS_OFFSET],
+        ));
+
+        self.timestamp.serialize(GenericArray::from_mut_slice(
+            &mut buf[Self::TS_OFFSET..Self::IM_OFFSET],
         ));
+
+        self.is_impression
+            .serialize(GenericArray::from_mut_slice(&mut buf[Self::IM_OFFSET..]));
     }
 
     fn deserialize(buf: &GenericArray<u8, Self::Size>) -> Result<Self, Self::DeserializationError> {
         let prf_of_match_key = u64::from_le_bytes(buf[..Self::PRF_MK_SZ].try_into().unwrap());
 
-        let value = Replicated::<BA3>::deserialize(GenericArray::from_slice(
+        let value = Replicated::<V>::deserialize(GenericArray::from_slice(
             &buf[Self::PRF_MK_SZ..Self::PRF_MK_SZ + Self::V_SZ],
         ))
         .map_err(|e| InvalidHybridReportError::DeserializationError("value", e.into()))?;
 
-        let breakdown_key = Replicated::<BA8>::deserialize_infallible(GenericArray::from_slice(
-            &buf[Self::PRF_MK_SZ + Self::V_SZ..Self::PRF_MK_SZ + Self::V_SZ + Self::BK_SZ],
-        ));
+        let breakdown_key = Replicated::<BK>::deserialize(GenericArray::from_slice(
+            &buf[Self::PRF_MK_SZ + Self::V_SZ..Self::TS_OFFSET],
+        ))
+        .map_err(|e| InvalidHybridReportError::DeserializationError("breakdown_key", e.into()))?;
+
+        let timestamp = Replicated::<HybridTimestamp>::deserialize(GenericArray::from_slice(
+            &buf[Self::TS_OFFSET..Self::IM_OFFSET],
+        ))
+        .map_err(|e| InvalidHybridReportError::DeserializationError("timestamp", e.into()))?;
+
+        let is_impression =
+            Replicated::<Boolean>::deserialize(GenericArray::from_slice(&buf[Self::IM_OFFSET..]))
+                .map_err(|e| {
+                InvalidHybridReportError::DeserializationError("is_impression", e.into())
+            })?;
 
         Ok(Self {
             match_key: prf_of_match_key,
             value,
             breakdown_key,
+            timestamp,
+            is_impression,
         })
     }
 }
@@ -1189,6 +1357,69 @@ impl Serializable for UniqueTag {
     }
 }
 
+/// [`UniqueTag`] of a report along with the identifier of the key the report was encrypted
+/// under. Helpers remember these tags across queries for as long as the key stays valid.
+#[derive(Clone, Debug)]
+pub struct KeyedUniqueTag {
+    key_id: KeyIdentifier,
+    tag: UniqueTag,
+}
+
+impl KeyedUniqueTag {
+    pub fn from_report<BK, V>(report: &EncryptedHybridReport<BK, V>) -> Self
+    where
+        V: SharedValue,
+        BK: SharedValue,
+        Replicated<V>: Serializable,
+        Replicated<BK>: Serializable,
+        <Replicated<V> as Serializable>::Size: Add<U16>,
+        <<Replicated<V> as Serializable>::Size as Add<U16>>::Output: ArrayLength,
+        <Replicated<BK> as Serializable>::Size: Add<U16>,
+        <<Replicated<BK> as Serializable>::Size as Add<U16>>::Output: ArrayLength,
+    {
+        Self {
+            key_id: report.key_id(),
+            tag: UniqueTag::from_unique_bytes(report),
+        }
+    }
+
+    #[must_use]
+    pub fn key_id(&self) -> KeyIdentifier {
+        self.key_id
+    }
+
+    /// Maps the tag into a consistent shard, see [`UniqueTag::shard_picker`].
+    #[must_use]
+    pub fn shard_picker(&self, shard_count: ShardIndex) -> ShardIndex {
+        self.tag.shard_picker(shard_count)
+    }
+}
+
+impl UniqueBytes for KeyedUniqueTag {
+    fn unique_bytes(&self) -> [u8; TAG_SIZE] {
+        self.tag.bytes
+    }
+}
+
+impl Serializable for KeyedUniqueTag {
+    type Size = U17;
+    type DeserializationError = Infallible;
+
+    fn serialize(&self, buf: &mut GenericArray<u8, Self::Size>) {
+        buf[0] = self.key_id;
+        buf[1..].copy_from_slice(&self.tag.bytes);
+    }
+
+    fn deserialize(buf: &GenericArray<u8, Self::Size>) -> Result<Self, Self::DeserializationError> {
+        let mut bytes = [0u8; TAG_SIZE];
+        bytes.copy_from_slice(&buf[1..]);
+        Ok(Self {
+            key_id: buf[0],
+            tag: UniqueTag { bytes },
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct UniqueTagValidator {
     hash_set: HashSet<[u8; TAG_SIZE]>,
@@ -1232,21 +1463,24 @@ impl UniqueTagValidator {
 
 #[cfg(all(test, unit_test))]
 mod test {
+    use std::time::{Duration, SystemTime};
+
     use bytes::Bytes;
     use rand::Rng;
 
     use super::{
         EncryptedHybridImpressionReport, EncryptedHybridReport, GenericArray,
-        HybridConversionReport, HybridImpressionReport, HybridReport,
-        IndistinguishableHybridReport, PrfHybridReport, UniqueTag, UniqueTagValidator,
+        HybridConversionReport, HybridImpressionReport, HybridReport, HybridTimestamp,
+        IndistinguishableHybridReport, InvalidHybridReportError, PrfHybridReport, UniqueTag,
+        UniqueTagValidator,
     };
     use crate::{
         error::Error,
         ff::{
-            boolean_array::{BA3, BA8},
-            Serializable,
+            boolean_array::{BA16, BA3, BA5, BA8},
+            Serializable, U128Conversions,
         },
-        hpke::{KeyPair, KeyRegistry},
+        hpke::{KeyPair, KeyRegistry, KeyValidity},
         report::{
             hybrid::{EncryptedHybridConversionReport, HybridEventType},
             hybrid_info::{HybridConversionInfo, HybridImpressionInfo},
@@ -1267,6 +1501,7 @@ mod test {
                 HybridReport::Impression(HybridImpressionReport::<BA8> {
                     match_key: AdditiveShare::new(rng.gen(), rng.gen()),
                     breakdown_key: AdditiveShare::new(rng.gen(), rng.gen()),
+                    timestamp: AdditiveShare::new(rng.gen(), rng.gen()),
                     info: HybridImpressionInfo::new(0),
                 })
             }
@@ -1319,6 +1554,12 @@ mod test {
             );
             assert_eq!(conversion_report.value, indistinguishable_report.value);
             assert_eq!(AdditiveShare::ZERO, indistinguishable_report.breakdown_key);
+            // the public timestamp is shared as the same value on every helper
+            let timestamp = HybridTimestamp::truncate_from(1_234_567_u64);
+            assert_eq!(
+                AdditiveShare::new(timestamp, timestamp),
+                indistinguishable_report.timestamp
+            );
 
             let hybrid_report = HybridReport::Conversion::<BA8, BA3>(conversion_report.clone());
             let indistinguishable_report2: IndistinguishableHybridReport<BA8, BA3> =
@@ -1339,6 +1580,7 @@ mod test {
             let impression_report = HybridImpressionReport::<BA8> {
                 match_key: AdditiveShare::new(rng.gen(), rng.gen()),
                 breakdown_key: AdditiveShare::new(rng.gen(), rng.gen()),
+                timestamp: AdditiveShare::new(rng.gen(), rng.gen()),
                 info: HybridImpressionInfo::new(0),
             };
             let indistinguishable_report: IndistinguishableHybridReport<BA8, BA3> =
@@ -1352,6 +1594,10 @@ mod test {
                 impression_report.breakdown_key,
                 indistinguishable_report.breakdown_key
             );
+            assert_eq!(
+                impression_report.timestamp,
+                indistinguishable_report.timestamp
+            );
 
             let hybrid_report = HybridReport::Impression::<BA8, BA3>(impression_report.clone());
             let indistinguishable_report2: IndistinguishableHybridReport<BA8, BA3> =
@@ -1360,6 +1606,25 @@ mod test {
         });
     }
 
+    #[test]
+    fn serialize_indistinguishable_report() {
+        run_random(|mut rng| async move {
+            let report = IndistinguishableHybridReport::<BA16, BA8> {
+                match_key: AdditiveShare::new(rng.gen(), rng.gen()),
+                value: AdditiveShare::new(rng.gen(), rng.gen()),
+                breakdown_key: AdditiveShare::new(rng.gen(), rng.gen()),
+                timestamp: AdditiveShare::new(rng.gen(), rng.gen()),
+                is_impression: AdditiveShare::new(rng.gen(), rng.gen()),
+            };
+            let mut buf = GenericArray::default();
+            report.serialize(&mut buf);
+            assert_eq!(
+                report,
+                IndistinguishableHybridReport::<BA16, BA8>::deserialize_infallible(&buf)
+            );
+        });
+    }
+
     #[test]
     fn unique_encrypted_hybrid_reports() {
         run_random(|mut rng| async move {
@@ -1389,6 +1654,7 @@ mod test {
             let hybrid_impression_report = HybridImpressionReport::<BA8> {
                 match_key: AdditiveShare::new(rng.gen(), rng.gen()),
                 breakdown_key: AdditiveShare::new(rng.gen(), rng.gen()),
+                timestamp: AdditiveShare::new(rng.gen(), rng.gen()),
                 info: HybridImpressionInfo::new(0),
             };
             let mut hybrid_impression_report_bytes =
@@ -1431,6 +1697,7 @@ mod test {
             let hybrid_impression_report = HybridImpressionReport::<BA8> {
                 match_key: AdditiveShare::new(rng.gen(), rng.gen()),
                 breakdown_key: AdditiveShare::new(rng.gen(), rng.gen()),
+                timestamp: AdditiveShare::new(rng.gen(), rng.gen()),
                 info: HybridImpressionInfo::new(key_id),
             };
 
@@ -1474,6 +1741,43 @@ mod test {
         });
     }
 
+    #[test]
+    fn dec_rejects_expired_key() {
+        run_random(|mut rng| async move {
+            let key_id = 2;
+            let expired = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+            let key_registry = KeyRegistry::<KeyPair>::from_entries([(
+                key_id,
+                KeyPair::gen(&mut rng),
+                KeyValidity {
+                    not_before: None,
+                    not_after: Some(expired),
+                },
+            )]);
+
+            for event_type in [HybridEventType::Impression, HybridEventType::Conversion] {
+                let mut hybrid_report = build_hybrid_report(event_type, &mut rng);
+                match &mut hybrid_report {
+                    HybridReport::Impression(r) => r.info = HybridImpressionInfo::new(key_id),
+                    HybridReport::Conversion(r) => {
+                        r.info =
+                            HybridConversionInfo::new(key_id, "meta.com", 1, 0.0, 0.0).unwrap();
+                    }
+                }
+                let enc_report_bytes = hybrid_report
+                    .encrypt(key_id, &key_registry, &mut rng)
+                    .unwrap();
+                let enc_report =
+                    EncryptedHybridReport::<BA8, BA3>::from_bytes(enc_report_bytes.into()).unwrap();
+
+                assert!(matches!(
+                    enc_report.decrypt(&key_registry),
+                    Err(InvalidHybridReportError::ExpiredKey(2))
+                ));
+            }
+        });
+    }
+
     #[test]
     fn enc_dec_roundtrip_hybrid() {
         run_random(|mut rng| async move {
@@ -1552,6 +1856,8 @@ mod test {
                 match_key: rng.gen(),
                 breakdown_key: Replicated::new(rng.gen(), rng.gen()),
                 value: Replicated::new(rng.gen(), rng.gen()),
+                timestamp: Replicated::new(rng.gen(), rng.gen()),
+                is_impression: Replicated::new(rng.gen(), rng.gen()),
             };
             let mut buf = GenericArray::default();
             report.serialize(&mut buf);
@@ -1559,4 +1865,75 @@ mod test {
             assert_eq!(report, deserialized_report.unwrap());
         });
     }
+
+    #[test]
+    fn serde_wide_value() {
+        run_random(|mut rng| async move {
+            let report = PrfHybridReport::<BA5, BA16> {
+                match_key: rng.gen(),
+                breakdown_key: Replicated::new(rng.gen(), rng.gen()),
+                value: Replicated::new(rng.gen(), rng.gen()),
+                timestamp: Replicated::new(rng.gen(), rng.gen()),
+                is_impression: Replicated::new(rng.gen(), rng.gen()),
+            };
+            let mut buf = GenericArray::default();
+            report.serialize(&mut buf);
+            let deserialized_report = PrfHybridReport::<BA5, BA16>::deserialize(&buf);
+            assert_eq!(report, deserialized_report.unwrap());
+        });
+    }
+
+    #[test]
+    fn reject_mismatched_breakdown_key_width() {
+        run_random(|mut rng| async move {
+            let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+            let hybrid_impression_report = HybridImpressionReport::<BA8> {
+                match_key: AdditiveShare::new(rng.gen(), rng.gen()),
+                breakdown_key: AdditiveShare::new(rng.gen(), rng.gen()),
+                timestamp: AdditiveShare::new(rng.gen(), rng.gen()),
+                info: HybridImpressionInfo::new(0),
+            };
+
+            let enc_report_bytes = hybrid_impression_report
+                .encrypt(0, &key_registry, &mut rng)
+                .unwrap();
+
+            assert!(matches!(
+                EncryptedHybridImpressionReport::<BA16>::from_bytes(enc_report_bytes.into()),
+                Err(InvalidHybridReportError::UnexpectedLength(_, _))
+            ));
+        });
+    }
+
+    #[test]
+    fn reject_mismatched_value_width() {
+        run_random(|mut rng| async move {
+            let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+            let wide_report = HybridConversionReport::<BA16> {
+                match_key: AdditiveShare::new(rng.gen(), rng.gen()),
+                value: AdditiveShare::new(rng.gen(), rng.gen()),
+                info: HybridConversionInfo::new(0, "meta.com", 1_729_707_432, 5.0, 1.1).unwrap(),
+            };
+            let narrow_report = HybridConversionReport::<BA3> {
+                match_key: AdditiveShare::new(rng.gen(), rng.gen()),
+                value: AdditiveShare::new(rng.gen(), rng.gen()),
+                info: HybridConversionInfo::new(0, "meta.com", 1_729_707_432, 5.0, 1.1).unwrap(),
+            };
+
+            let wide_bytes = wide_report.encrypt(0, &key_registry, &mut rng).unwrap();
+            let narrow_bytes = narrow_report.encrypt(0, &key_registry, &mut rng).unwrap();
+
+            assert!(
+                EncryptedHybridConversionReport::<BA3>::from_bytes(wide_bytes.clone().into())
+                    .is_err()
+            );
+            assert!(
+                EncryptedHybridConversionReport::<BA16>::from_bytes(narrow_bytes.into()).is_err()
+            );
+
+            let enc_report =
+                EncryptedHybridConversionReport::<BA16>::from_bytes(wide_bytes.into()).unwrap();
+            assert_eq!(wide_report, enc_report.decrypt(&key_registry).unwrap());
+        });
+    }
 }
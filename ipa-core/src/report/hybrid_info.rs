@@ -11,6 +11,9 @@ pub struct HybridImpressionInfo {
 }
 
 impl HybridImpressionInfo {
+    /// Length of the serialized impression info.
+    pub const BYTE_LEN: usize = std::mem::size_of::<KeyIdentifier>();
+
     /// Creates a new instance.
     #[must_use]
     pub fn new(key_id: KeyIdentifier) -> Self {
@@ -56,9 +59,13 @@ impl HybridImpressionInfo {
 
     /// ## Errors
     /// If deserialization fails.
-    /// ## Panics
-    /// If not enough delimiters are found in the input bytes.
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, InvalidHybridReportError> {
+        if bytes.len() != Self::BYTE_LEN {
+            return Err(InvalidHybridReportError::UnexpectedLength(
+                bytes.len(),
+                Self::BYTE_LEN,
+            ));
+        }
         let key_id = bytes[0];
         Ok(Self { key_id })
     }
@@ -74,6 +81,10 @@ pub struct HybridConversionInfo {
 }
 
 impl HybridConversionInfo {
+    /// Length of the serialized conversion info with an empty site domain: the delimiter,
+    /// key identifier, timestamp, epsilon and sensitivity.
+    pub const MIN_BYTE_LEN: usize = 1 + std::mem::size_of::<KeyIdentifier>() + 3 * 8;
+
     /// Creates a new instance.
     ///
     /// ## Errors
@@ -163,15 +174,25 @@ impl HybridConversionInfo {
     }
 
     /// ## Errors
-    /// If deserialization fails.
+    /// If deserialization fails, including when no delimiter is found in the input bytes or
+    /// the input has an incorrect length.
     /// ## Panics
-    /// If not enough delimiters are found in the input bytes.
+    /// Should not panic. The input length is validated before the fixed-size fields are read.
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, InvalidHybridReportError> {
         let mut pos = 0;
-        let delimiter_pos = bytes[pos..]
-            .iter()
-            .position(|&b| b == 0)
-            .unwrap_or_else(|| panic!("not enough delimiters for HybridConversionInfo"));
+        let delimiter_pos = bytes[pos..].iter().position(|&b| b == 0).ok_or_else(|| {
+            InvalidHybridReportError::DeserializationError(
+                "HybridConversionInfo: conversion_site_domain",
+                "not enough delimiters for HybridConversionInfo".into(),
+            )
+        })?;
+        let expected_len = delimiter_pos + Self::MIN_BYTE_LEN;
+        if bytes.len() != expected_len {
+            return Err(InvalidHybridReportError::UnexpectedLength(
+                bytes.len(),
+                expected_len,
+            ));
+        }
         let conversion_site_domain = String::from_utf8(bytes[pos..pos + delimiter_pos].to_vec())
             .map_err(|e| {
                 InvalidHybridReportError::DeserializationError(
@@ -180,7 +201,6 @@ impl HybridConversionInfo {
                 )
             })?;
         pos += delimiter_pos + 1;
-        debug_assert!(pos + 3*8 + 1 == bytes.len(), "{}", format!("bytes for HybridConversionInfo::from_bytes has incorrect length. Expected: {}, Actual: {}", pos + 3*8 + 1, bytes.len()).to_string());
 
         let key_id = bytes[pos];
         pos += 1;
@@ -277,6 +297,25 @@ mod test {
         assert_eq!(info.to_bytes(), info2.to_bytes());
     }
 
+    #[test]
+    fn test_hybrid_conversion_deserialization_errors() {
+        let info = HybridConversionInfo::new(0, "https://www.example2.com", 1_234_567, 1.151, 0.95)
+            .unwrap();
+        let bytes = info.to_bytes();
+        assert!(matches!(
+            HybridConversionInfo::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(InvalidHybridReportError::UnexpectedLength(_, _))
+        ));
+        assert!(matches!(
+            HybridConversionInfo::from_bytes(b"https://www.example2.com"),
+            Err(InvalidHybridReportError::DeserializationError(_, _))
+        ));
+        assert!(matches!(
+            HybridImpressionInfo::from_bytes(&[]),
+            Err(InvalidHybridReportError::UnexpectedLength(0, 1))
+        ));
+    }
+
     #[test]
     fn test_hybrid_info_serialization() {
         let info = HybridInfo::new(0, "https://www.example2.com", 1_234_567, 1.151, 0.95).unwrap();
@@ -2,3 +2,4 @@ pub mod ipa;
 pub use self::ipa::*;
 pub mod hybrid;
 pub mod hybrid_info;
+pub mod walr;
@@ -0,0 +1,381 @@
+//! Provides report types which are consumed by the WALR (logistic regression) query.
+//!
+//! A `WalrReport` is an IPA event that carries a vector of `B` secret-shared features in
+//! addition to the match key and timestamp. The processing pipeline mirrors the one for
+//! [`OprfReport`]:
+//!
+//! `BodyStream` → `EncryptedWalrReport` → `WalrReport`
+//!
+//! Unlike `EncryptedOprfReport`, the match key, timestamp and features are sealed in a single
+//! HPKE ciphertext, because the feature vector is only useful together with the match key.
+//!
+//! [`OprfReport`]: crate::report::OprfReport
+
+use std::{marker::PhantomData, ops::Deref};
+
+use bytes::{BufMut, Bytes};
+use generic_array::GenericArray;
+use hpke::Serializable as _;
+use rand_core::{CryptoRng, RngCore};
+use typenum::Unsigned;
+
+use crate::{
+    ff::{boolean_array::BA64, Serializable},
+    hpke::{
+        open_in_place, seal_in_place, CryptError, EncapsulationSize, Info, PrivateKeyRegistry,
+        PublicKeyRegistry, TagSize,
+    },
+    report::{
+        Epoch, EventType, InvalidReportError, KeyIdentifier, NonAsciiStringError, HELPER_ORIGIN,
+    },
+    secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, SharedValue},
+};
+
+/// A binary report as submitted by a report collector, containing an encrypted `WalrReport`.
+/// An `EncryptedWalrReport` consists of:
+///     `ct`: Enc(`match_key`, `timestamp`, `feature_vector`)
+///     associated data of `ct`: `key_id`, `epoch`, `event_type`, `site_domain`
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct EncryptedWalrReport<FV, TS, const B: usize, Buf>
+where
+    Buf: Deref<Target = [u8]>,
+    FV: SharedValue,
+    TS: SharedValue,
+{
+    data: Buf,
+    phantom_data: PhantomData<(FV, TS)>,
+}
+
+// Report structure:
+//  * 0..a: `encap_key`
+//  * a..b: `ciphertext`
+//  * b: `event_type`
+//  * b+1: `key_id`
+//  * b+2..b+4: `epoch`
+//  * b+4..: `site_domain`
+
+// ciphertext structure
+// * 0..a `match_key`
+// * a..b `timestamp`
+// * b..c `feature_vector`
+impl<FV, TS, const B: usize, Buf> EncryptedWalrReport<FV, TS, B, Buf>
+where
+    Buf: Deref<Target = [u8]>,
+    FV: SharedValue,
+    TS: SharedValue,
+    Replicated<FV>: Serializable,
+    Replicated<TS>: Serializable,
+{
+    const ENCAP_KEY_OFFSET: usize = 0;
+    const CIPHERTEXT_OFFSET: usize = Self::ENCAP_KEY_OFFSET + EncapsulationSize::USIZE;
+    const EVENT_TYPE_OFFSET: usize =
+        Self::CIPHERTEXT_OFFSET + WalrReport::<FV, TS, B>::PLAINTEXT_LEN + TagSize::USIZE;
+    const KEY_IDENTIFIER_OFFSET: usize = Self::EVENT_TYPE_OFFSET + 1;
+    const EPOCH_OFFSET: usize = Self::KEY_IDENTIFIER_OFFSET + 1;
+    const SITE_DOMAIN_OFFSET: usize = Self::EPOCH_OFFSET + 2;
+
+    pub fn encap_key(&self) -> &[u8] {
+        &self.data[Self::ENCAP_KEY_OFFSET..Self::CIPHERTEXT_OFFSET]
+    }
+
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.data[Self::CIPHERTEXT_OFFSET..Self::EVENT_TYPE_OFFSET]
+    }
+
+    /// ## Panics
+    /// Only if a `Report` constructor failed to validate the contents properly, which would be a bug.
+    pub fn event_type(&self) -> EventType {
+        EventType::try_from(self.data[Self::EVENT_TYPE_OFFSET]).unwrap() // validated on construction
+    }
+
+    pub fn key_id(&self) -> KeyIdentifier {
+        self.data[Self::KEY_IDENTIFIER_OFFSET]
+    }
+
+    /// ## Panics
+    /// Never.
+    pub fn epoch(&self) -> Epoch {
+        u16::from_le_bytes(
+            self.data[Self::EPOCH_OFFSET..Self::SITE_DOMAIN_OFFSET]
+                .try_into()
+                .unwrap(), // infallible slice-to-array conversion
+        )
+    }
+
+    /// ## Panics
+    /// Only if a `Report` constructor failed to validate the contents properly, which would be a bug.
+    pub fn site_domain(&self) -> &str {
+        std::str::from_utf8(&self.data[Self::SITE_DOMAIN_OFFSET..]).unwrap() // validated on construction
+    }
+
+    /// ## Errors
+    /// If the report contents are invalid.
+    pub fn from_bytes(bytes: Buf) -> Result<Self, InvalidReportError> {
+        if bytes.len() <= Self::SITE_DOMAIN_OFFSET {
+            return Err(InvalidReportError::Length(
+                bytes.len(),
+                Self::SITE_DOMAIN_OFFSET,
+            ));
+        }
+        EventType::try_from(bytes[Self::EVENT_TYPE_OFFSET])?;
+        let site_domain = &bytes[Self::SITE_DOMAIN_OFFSET..];
+        if !site_domain.is_ascii() {
+            return Err(NonAsciiStringError::from(site_domain).into());
+        }
+        Ok(Self {
+            data: bytes,
+            phantom_data: PhantomData,
+        })
+    }
+
+    /// ## Errors
+    /// If the shares in the report cannot be decrypted (e.g. due to a
+    /// failure of the authenticated encryption).
+    /// ## Panics
+    /// Should not panic. Only panics if a `Report` constructor failed to validate the
+    /// contents properly, which would be a bug.
+    pub fn decrypt<P: PrivateKeyRegistry>(
+        &self,
+        key_registry: &P,
+    ) -> Result<WalrReport<FV, TS, B>, InvalidReportError> {
+        let info = Info::new(
+            self.key_id(),
+            self.epoch(),
+            self.event_type(),
+            HELPER_ORIGIN,
+            self.site_domain(),
+        )
+        .unwrap(); // validated on construction
+
+        let sk = key_registry
+            .private_key(self.key_id())
+            .ok_or(CryptError::NoSuchKey(self.key_id()))?;
+        let mut ct = self.ciphertext().to_vec();
+        let plaintext = open_in_place(sk, self.encap_key(), &mut ct, &info.to_bytes())?;
+
+        let (match_key, plaintext) =
+            plaintext.split_at(<Replicated<BA64> as Serializable>::Size::USIZE);
+        let (timestamp, plaintext) =
+            plaintext.split_at(<Replicated<TS> as Serializable>::Size::USIZE);
+        let mut features = plaintext.chunks_exact(<Replicated<FV> as Serializable>::Size::USIZE);
+        let mut feature_vector = std::array::from_fn(|_| Replicated::<FV>::ZERO);
+        for feature in &mut feature_vector {
+            *feature = Replicated::<FV>::deserialize(GenericArray::from_slice(
+                features.next().unwrap(), // length checked by the AEAD
+            ))
+            .map_err(|e| InvalidReportError::DeserializationError("feature_vector", e.into()))?;
+        }
+
+        Ok(WalrReport {
+            match_key: Replicated::<BA64>::deserialize(GenericArray::from_slice(match_key))
+                .map_err(|e| InvalidReportError::DeserializationError("matchkey", e.into()))?,
+            event_type: self.event_type(),
+            timestamp: Replicated::<TS>::deserialize(GenericArray::from_slice(timestamp))
+                .map_err(|e| InvalidReportError::DeserializationError("timestamp", e.into()))?,
+            feature_vector,
+            epoch: self.epoch(),
+            site_domain: self.site_domain().to_owned(),
+        })
+    }
+}
+
+impl<FV, TS, const B: usize> TryFrom<Bytes> for EncryptedWalrReport<FV, TS, B, Bytes>
+where
+    FV: SharedValue,
+    TS: SharedValue,
+    Replicated<FV>: Serializable,
+    Replicated<TS>: Serializable,
+{
+    type Error = InvalidReportError;
+
+    fn try_from(bytes: Bytes) -> Result<Self, InvalidReportError> {
+        EncryptedWalrReport::from_bytes(bytes)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WalrReport<FV, TS, const B: usize>
+where
+    FV: SharedValue,
+    TS: SharedValue,
+{
+    pub match_key: Replicated<BA64>,
+    pub event_type: EventType,
+    pub timestamp: Replicated<TS>,
+    pub feature_vector: [Replicated<FV>; B],
+    pub epoch: Epoch,
+    pub site_domain: String,
+}
+
+impl<FV, TS, const B: usize> WalrReport<FV, TS, B>
+where
+    FV: SharedValue,
+    TS: SharedValue,
+    Replicated<FV>: Serializable,
+    Replicated<TS>: Serializable,
+{
+    // offsets within the plaintext
+    const MK_OFFSET: usize = 0;
+    const TS_OFFSET: usize = Self::MK_OFFSET + <Replicated<BA64> as Serializable>::Size::USIZE;
+    const FV_OFFSET: usize = Self::TS_OFFSET + <Replicated<TS> as Serializable>::Size::USIZE;
+    const PLAINTEXT_LEN: usize =
+        Self::FV_OFFSET + B * <Replicated<FV> as Serializable>::Size::USIZE;
+
+    /// # Panics
+    /// If report length does not fit in `u16`.
+    pub fn encrypted_len(&self) -> u16 {
+        let len =
+            EncryptedWalrReport::<FV, TS, B, &[u8]>::SITE_DOMAIN_OFFSET + self.site_domain.len();
+        len.try_into().unwrap()
+    }
+
+    /// # Errors
+    /// If there is a problem encrypting the report.
+    pub fn delimited_encrypt_to<R: CryptoRng + RngCore, BM: BufMut>(
+        &self,
+        key_id: KeyIdentifier,
+        key_registry: &impl PublicKeyRegistry,
+        rng: &mut R,
+        out: &mut BM,
+    ) -> Result<(), InvalidReportError> {
+        out.put_u16_le(self.encrypted_len());
+        self.encrypt_to(key_id, key_registry, rng, out)
+    }
+
+    /// # Errors
+    /// If there is a problem encrypting the report.
+    pub fn encrypt<R: CryptoRng + RngCore>(
+        &self,
+        key_id: KeyIdentifier,
+        key_registry: &impl PublicKeyRegistry,
+        rng: &mut R,
+    ) -> Result<Vec<u8>, InvalidReportError> {
+        let mut out = Vec::with_capacity(usize::from(self.encrypted_len()));
+        self.encrypt_to(key_id, key_registry, rng, &mut out)?;
+        debug_assert_eq!(out.len(), usize::from(self.encrypted_len()));
+        Ok(out)
+    }
+
+    /// # Errors
+    /// If there is a problem encrypting the report.
+    pub fn encrypt_to<R: CryptoRng + RngCore, BM: BufMut>(
+        &self,
+        key_id: KeyIdentifier,
+        key_registry: &impl PublicKeyRegistry,
+        rng: &mut R,
+        out: &mut BM,
+    ) -> Result<(), InvalidReportError> {
+        let info = Info::new(
+            key_id,
+            self.epoch,
+            self.event_type,
+            HELPER_ORIGIN,
+            self.site_domain.as_ref(),
+        )?;
+
+        let mut plaintext = vec![0u8; Self::PLAINTEXT_LEN];
+        self.match_key.serialize(GenericArray::from_mut_slice(
+            &mut plaintext[Self::MK_OFFSET..Self::TS_OFFSET],
+        ));
+        self.timestamp.serialize(GenericArray::from_mut_slice(
+            &mut plaintext[Self::TS_OFFSET..Self::FV_OFFSET],
+        ));
+        for (feature, buf) in self.feature_vector.iter().zip(
+            plaintext[Self::FV_OFFSET..]
+                .chunks_exact_mut(<Replicated<FV> as Serializable>::Size::USIZE),
+        ) {
+            feature.serialize(GenericArray::from_mut_slice(buf));
+        }
+
+        let pk = key_registry
+            .public_key(key_id)
+            .ok_or(CryptError::NoSuchKey(key_id))?;
+
+        let (encap_key, ciphertext, tag) =
+            seal_in_place(pk, plaintext.as_mut(), &info.to_bytes(), rng)?;
+
+        out.put_slice(&encap_key.to_bytes());
+        out.put_slice(ciphertext);
+        out.put_slice(&tag.to_bytes());
+        out.put_slice(&[u8::from(&self.event_type)]);
+        out.put_slice(&[key_id]);
+        out.put_slice(&self.epoch.to_le_bytes());
+        out.put_slice(self.site_domain.as_bytes());
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod test {
+    use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+    use super::*;
+    use crate::{
+        ff::boolean_array::{BA20, BA8},
+        hpke::{KeyPair, KeyRegistry},
+        secret_sharing::replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+    };
+
+    fn random_report<R: Rng>(rng: &mut R) -> WalrReport<BA8, BA20, 16> {
+        WalrReport {
+            match_key: AdditiveShare::new(rng.gen(), rng.gen()),
+            event_type: if rng.gen::<bool>() {
+                EventType::Trigger
+            } else {
+                EventType::Source
+            },
+            timestamp: AdditiveShare::new(rng.gen(), rng.gen()),
+            feature_vector: std::array::from_fn(|_| AdditiveShare::new(rng.gen(), rng.gen())),
+            epoch: rng.gen(),
+            site_domain: rng
+                .sample_iter(Alphanumeric)
+                .map(char::from)
+                .take(10)
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn enc_dec_roundtrip_walr() {
+        let mut rng = thread_rng();
+        let report = random_report(&mut rng);
+
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+        let key_id = 0;
+
+        let enc_report_bytes = report.encrypt(key_id, &key_registry, &mut rng).unwrap();
+        assert_eq!(enc_report_bytes.len(), usize::from(report.encrypted_len()));
+        let enc_report =
+            EncryptedWalrReport::<BA8, BA20, 16, _>::from_bytes(enc_report_bytes.as_slice())
+                .unwrap();
+        let dec_report = enc_report.decrypt(&key_registry).unwrap();
+
+        assert_eq!(dec_report, report);
+    }
+
+    #[test]
+    fn decryption_fails_with_wrong_key() {
+        let mut rng = thread_rng();
+        let report = random_report(&mut rng);
+
+        let enc_key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+        let dec_key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+
+        let enc_report_bytes = report.encrypt(0, &enc_key_registry, &mut rng).unwrap();
+        let enc_report =
+            EncryptedWalrReport::<BA8, BA20, 16, _>::from_bytes(enc_report_bytes.as_slice())
+                .unwrap();
+
+        assert!(enc_report.decrypt(&dec_key_registry).is_err());
+    }
+
+    #[test]
+    fn short_report() {
+        let bytes = vec![0_u8; 40];
+        let err = EncryptedWalrReport::<BA8, BA20, 16, _>::from_bytes(bytes.as_slice())
+            .err()
+            .unwrap();
+        assert!(matches!(err, InvalidReportError::Length(40, _)));
+    }
+}
@@ -3,7 +3,7 @@
 use crate::{
     ff::{
         boolean::Boolean,
-        boolean_array::{BA16, BA20, BA256, BA3, BA32, BA5, BA64, BA8},
+        boolean_array::{BA1024, BA16, BA20, BA256, BA3, BA32, BA5, BA64, BA8},
         ec_prime_field::Fp25519,
         Fp32BitPrime, Gf32Bit,
     },
@@ -71,3 +71,4 @@ boolean_vector!(bav_20, 20, BA20);
 boolean_vector!(bav_32, 32, BA32);
 boolean_vector!(bav_64, 64, BA64);
 boolean_vector!(bav_256, 256, BA256);
+boolean_vector!(bav_1024, 1024, BA1024);
@@ -618,6 +618,7 @@ impl_transpose_shares_bool_to_ba!(BA16, 16, 256, test_transpose_shares_bool_to_b
 impl_transpose_shares_bool_to_ba!(BA16, 16, 32, test_transpose_shares_bool_to_ba_16x32);
 impl_transpose_shares_bool_to_ba!(BA32, 32, 256, test_transpose_shares_bool_to_ba_32x256);
 impl_transpose_shares_bool_to_ba_small!(BA8, 8, 32, test_transpose_shares_bool_to_ba_8x32);
+impl_transpose_shares_bool_to_ba!(BA32, 32, 1024, test_transpose_shares_bool_to_ba_32x1024);
 // added to support HV = BA32 to hold results when adding Binomial noise
 impl_transpose_shares_bool_to_ba_small!(BA32, 32, 32, test_transpose_shares_bool_to_ba_32x32);
 
@@ -630,6 +631,10 @@ impl_transpose_shares_bool_to_ba!(BA16, 16, 16, test_transpose_shares_bool_to_ba
 // Usage: ?
 impl_transpose_shares_bool_to_ba_small!(BA8, 8, 16, test_transpose_shares_bool_to_ba_8x16);
 
+// Usage: WALR gradient output. M = HV bits, N = number of features.
+impl_transpose_shares_bool_to_ba!(BA32, 32, 16, test_transpose_shares_bool_to_ba_32x16);
+impl_transpose_shares_bool_to_ba_small!(BA32, 32, 8, test_transpose_shares_bool_to_ba_32x8);
+
 /// Implement a transpose of a MxN matrix of secret-shared bits represented as
 /// `[AdditiveShare<BA<N>>; M]` into a NxM bit matrix represented as `[AdditiveShare<Boolean, M>; N]`.
 ///
@@ -789,6 +794,11 @@ impl_transpose_shares_ba_to_bool_small!(BA8, 256, 8, test_transpose_shares_ba_to
 impl_transpose_shares_ba_to_bool_small!(BA5, 256, 5, test_transpose_shares_ba_to_bool_256x5);
 impl_transpose_shares_ba_to_bool_small!(BA3, 256, 3, test_transpose_shares_ba_to_bool_256x3);
 
+// Usage: Hybrid aggregation for 16-bit breakdown keys. M = number of breakdowns, N = V or HV bits.
+impl_transpose_shares_ba_to_bool_small!(BA32, 1024, 32, test_transpose_shares_ba_to_bool_1024x32);
+impl_transpose_shares_ba_to_bool_small!(BA8, 1024, 8, test_transpose_shares_ba_to_bool_1024x8);
+impl_transpose_shares_ba_to_bool_small!(BA3, 1024, 3, test_transpose_shares_ba_to_bool_1024x3);
+
 // Usage: feature_label_dot_product aggregation input. M = number of features, N = BK or TV bits.
 impl_transpose_shares_ba_to_bool_small!(BA8, 32, 8, test_transpose_shares_ba_to_bool_32x8);
 
@@ -797,8 +807,20 @@ impl_transpose_shares_ba_to_bool_small!(BA3, 32, 3, test_transpose_shares_ba_to_
 
 // Usage: Laplace noise mechanism. M = number of breakdowns (2^|bk|), N = OV bits.
 impl_transpose_shares_ba_to_bool!(BA32, 32, 32, test_transpose_shares_ba_to_bool_32x32);
+// Usage: Hybrid histogram for 5-bit breakdown keys. M = number of breakdowns, N = HV bits.
+impl_transpose_shim!(
+    &Vec<AdditiveShare<BA32>>, AdditiveShare<BA32>,
+    BitDecomposed<AdditiveShare<Boolean, 32>>, AdditiveShare<Boolean, 32>,
+    32, 32,
+    LengthError,
+);
 impl_transpose_shares_ba_to_bool!(BA16, 32, 16, test_transpose_shares_ba_to_bool_32x16);
 impl_transpose_shares_ba_to_bool_small!(BA8, 16, 8, test_transpose_shares_ba_to_bool_16x8);
+// Usage: WALR gradient noise. M = number of features, N = HV bits.
+impl_transpose_shares_ba_to_bool!(BA32, 16, 32, test_transpose_shares_ba_to_bool_16x32);
+impl_transpose_shares_ba_to_bool_small!(BA32, 8, 32, test_transpose_shares_ba_to_bool_8x32);
+// Usage: WALR attribution input with 8 features. M = number of features, N = FV bits.
+impl_transpose_shares_ba_to_bool_small!(BA8, 8, 8, test_transpose_shares_ba_to_bool_8x8);
 
 // Special transpose used for "aggregation intermediate". See [`aggregate_contributions`] for
 // additional details.
@@ -9,7 +9,7 @@ use futures::{stream::Fuse, Stream, StreamExt};
 use pin_project::pin_project;
 use tracing::{Instrument, Span};
 
-use crate::telemetry::memory::periodic_memory_report;
+use crate::telemetry::{memory::periodic_memory_report, partition::MetricsPartitionExt};
 
 #[cfg(feature = "shuttle")]
 mod shuttle_spawner {
@@ -108,10 +108,12 @@ where
                 // Cancellable futures will be cancelled when spawner is dropped which is
                 // the behavior we want.
                 let task_index = *this.spawned;
-                this.spawner
-                    .spawn_cancellable(f.into_future().instrument(Span::current()), move || {
-                        panic!("SequentialFutures: spawned task {task_index} cancelled")
-                    });
+                this.spawner.spawn_cancellable(
+                    f.into_future()
+                        .in_current_partition()
+                        .instrument(Span::current()),
+                    move || panic!("SequentialFutures: spawned task {task_index} cancelled"),
+                );
 
                 periodic_memory_report(*this.spawned);
                 *this.spawned += 1;
@@ -166,9 +168,10 @@ where
             // it is important to make those cancellable to avoid deadlocks if one of the spawned future panics.
             // If there is a dependency between futures, pending one will never complete.
             // Cancellable futures will be cancelled when spawner is dropped which is the behavior we want.
-            scope.spawn_cancellable(element.instrument(Span::current()), || {
-                panic!("parallel_join: task cancelled")
-            });
+            scope.spawn_cancellable(
+                element.in_current_partition().instrument(Span::current()),
+                || panic!("parallel_join: task cancelled"),
+            );
         }
         scope
     };
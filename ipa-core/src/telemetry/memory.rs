@@ -1,3 +1,7 @@
+use ipa_metrics::{gauge, MetricsCurrentThreadContext};
+
+use crate::telemetry::metrics::{PEAK_RESIDENT_MEMORY, RESIDENT_MEMORY};
+
 pub fn periodic_memory_report(count: usize) {
     #[cfg(not(jemalloc))]
     let _ = count;
@@ -6,6 +10,89 @@ pub fn periodic_memory_report(count: usize) {
     jemalloc::periodic_memory_report(count);
 }
 
+/// Returns the resident set size of this process in bytes, or [`None`] if the platform
+/// does not report it.
+#[must_use]
+pub fn resident_memory() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        parse_status_kb(
+            &std::fs::read_to_string("/proc/self/status").ok()?,
+            "VmRSS:",
+        )
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// Returns the peak resident set size of this process in bytes, or [`None`] if the platform
+/// does not report it.
+#[must_use]
+pub fn peak_resident_memory() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        parse_status_kb(
+            &std::fs::read_to_string("/proc/self/status").ok()?,
+            "VmHWM:",
+        )
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// Logs the current and the peak resident memory of this process at the end of `stage`, and
+/// sets the [`RESIDENT_MEMORY`] and [`PEAK_RESIDENT_MEMORY`] gauges to them, in bytes.
+///
+/// Query inputs are decrypted and deduplicated as they are read. Only a bounded number of
+/// decrypted reports is kept in memory while the input is read, the rest is spilled to disk,
+/// but all of them are loaded back once the protocol starts, because it needs all of them at
+/// once. Peak memory of a query is therefore still proportional to the number of reports.
+///
+/// [`RESIDENT_MEMORY`]: crate::telemetry::metrics::RESIDENT_MEMORY
+/// [`PEAK_RESIDENT_MEMORY`]: crate::telemetry::metrics::PEAK_RESIDENT_MEMORY
+pub fn report_memory(stage: &str) {
+    let resident = resident_memory();
+    let peak = peak_resident_memory();
+    if let Some(resident) = resident {
+        set_gauge(RESIDENT_MEMORY, resident);
+    }
+    if let Some(peak) = peak {
+        set_gauge(PEAK_RESIDENT_MEMORY, peak);
+    }
+    if let (Some(resident), Some(peak)) = (resident, peak) {
+        tracing::info!(
+            "{stage}: resident memory {} MiB, peak resident memory {} MiB",
+            resident >> 20,
+            peak >> 20
+        );
+    }
+}
+
+/// Memory is an absolute value, so the gauge is set rather than adjusted.
+fn set_gauge(metric: &'static str, bytes: u64) {
+    let name = gauge!(metric);
+    MetricsCurrentThreadContext::store_mut(|store| {
+        store
+            .gauge(&name)
+            .set(i64::try_from(bytes).unwrap_or(i64::MAX));
+    });
+}
+
+/// Reads the `entry` of `/proc/self/status`, which is reported in kB, i.e. `VmRSS:` for
+/// resident memory or `VmHWM:` ("high water mark") for peak resident memory.
+#[cfg(any(target_os = "linux", test))]
+fn parse_status_kb(status: &str, entry: &str) -> Option<u64> {
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix(entry))?
+        .trim()
+        .strip_suffix("kB")?;
+
+    kb.trim().parse::<u64>().ok().map(|kb| kb * 1024)
+}
+
 #[cfg(jemalloc)]
 pub mod jemalloc {
     use std::sync::RwLock;
@@ -74,3 +161,25 @@ pub mod jemalloc {
         }
     }
 }
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::parse_status_kb;
+
+    #[test]
+    fn parse_status() {
+        let status =
+            "Name:\thelper\nVmPeak:\t  204800 kB\nVmHWM:\t    2048 kB\nVmRSS:\t    1024 kB\n";
+        assert_eq!(Some(2048 * 1024), parse_status_kb(status, "VmHWM:"));
+        assert_eq!(Some(1024 * 1024), parse_status_kb(status, "VmRSS:"));
+        assert_eq!(None, parse_status_kb("Name:\thelper\n", "VmHWM:"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn resident() {
+        let resident = super::resident_memory().unwrap();
+        assert!(resident > 0);
+        assert!(super::peak_resident_memory().unwrap() >= resident);
+    }
+}
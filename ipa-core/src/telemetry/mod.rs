@@ -1,4 +1,7 @@
 pub mod memory;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod partition;
 pub mod stats;
 mod step_stats;
 
@@ -7,6 +10,7 @@ pub use step_stats::CsvExporter as StepStatsCsvExporter;
 pub mod labels {
     pub use ::ipa_step::descriptive::labels::STEP;
     pub const ROLE: &str = "role";
+    pub const SHARD: &str = "shard";
 }
 
 pub mod metrics {
@@ -18,6 +22,11 @@ pub mod metrics {
     pub const SEQUENTIAL_PRSS_GENERATED: &str = "s.prss.gen";
     pub use ::ipa_step::descriptive::labels::STEP_NARROWED;
     pub const DZKP_BATCH_INCREMENTS: &str = "batch.realloc.front";
+    pub const REPLAYED_REPORTS: &str = "reports.replayed";
+    pub const QUERIES_OVER_BUDGET: &str = "queries.over_budget";
+    pub const INCOMPATIBLE_PRIVACY_REPORTS: &str = "reports.incompatible_privacy";
+    pub const RESIDENT_MEMORY: &str = "memory.resident";
+    pub const PEAK_RESIDENT_MEMORY: &str = "memory.resident.peak";
 
     #[cfg(feature = "web-app")]
     pub mod web {
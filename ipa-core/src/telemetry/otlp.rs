@@ -0,0 +1,216 @@
+//! Export of tracing spans to an OpenTelemetry collector over OTLP.
+//!
+//! Spans are sent over gRPC to a collector, usually one that runs next to the helper. Helpers
+//! propagate the trace context with every request they send to their peers and shards (see
+//! `net::trace_context`), so each query appears as a single distributed trace.
+
+use std::{fs, path::Path, time::Duration};
+
+use opentelemetry::{global, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Config, Sampler, Tracer, TracerProvider},
+    Resource,
+};
+use serde::Deserialize;
+
+use crate::error::BoxError;
+
+/// Configuration of the OTLP trace exporter. It is read from a TOML file, for example
+///
+/// ```toml
+/// endpoint = "http://localhost:4317"
+/// service_name = "ipa-helper-1"
+/// sample_ratio = 0.1
+/// ```
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpConfig {
+    /// gRPC endpoint of the collector.
+    #[serde(default = "OtlpConfig::default_endpoint")]
+    pub endpoint: String,
+    /// Name of the service that spans are reported by. Every helper should use a distinct one.
+    #[serde(default = "OtlpConfig::default_service_name")]
+    pub service_name: String,
+    /// Fraction of traces that are exported. The decision is made by the helper that starts
+    /// the trace and is followed by all its peers and shards.
+    #[serde(default = "OtlpConfig::default_sample_ratio")]
+    pub sample_ratio: f64,
+    /// How long to wait for the collector to accept a batch of spans, in seconds.
+    #[serde(default = "OtlpConfig::default_export_timeout_secs")]
+    pub export_timeout_secs: u64,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: Self::default_endpoint(),
+            service_name: Self::default_service_name(),
+            sample_ratio: Self::default_sample_ratio(),
+            export_timeout_secs: Self::default_export_timeout_secs(),
+        }
+    }
+}
+
+impl OtlpConfig {
+    fn default_endpoint() -> String {
+        "http://localhost:4317".to_string()
+    }
+
+    fn default_service_name() -> String {
+        "ipa".to_string()
+    }
+
+    fn default_sample_ratio() -> f64 {
+        1.0
+    }
+
+    fn default_export_timeout_secs() -> u64 {
+        10
+    }
+
+    /// Reads the exporter configuration from a TOML file.
+    ///
+    /// ## Errors
+    /// If the file can't be read or is not a valid configuration.
+    pub fn from_file(path: &Path) -> Result<Self, BoxError> {
+        let config: Self =
+            toml::from_str(&fs::read_to_string(path).map_err(|e| {
+                format!("failed to read OTLP configuration {}: {e}", path.display())
+            })?)?;
+        if !(0.0..=1.0).contains(&config.sample_ratio) {
+            return Err(format!(
+                "{}: sample ratio must be between 0 and 1, got {}",
+                path.display(),
+                config.sample_ratio
+            )
+            .into());
+        }
+
+        Ok(config)
+    }
+}
+
+/// Exports spans created by [`Self::tracer`] until dropped. Spans that are still buffered are
+/// flushed to the collector on drop.
+pub struct OtlpExporter {
+    provider: TracerProvider,
+}
+
+impl OtlpExporter {
+    /// Starts the span exporter and makes it the global one, along with the W3C trace context
+    /// propagator.
+    ///
+    /// ## Errors
+    /// If the exporter can't be created with the given configuration.
+    ///
+    /// ## Panics
+    /// If called outside of a Tokio runtime.
+    pub fn install(config: &OtlpConfig) -> Result<Self, BoxError> {
+        let provider = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(&config.endpoint)
+                    .with_timeout(Duration::from_secs(config.export_timeout_secs)),
+            )
+            .with_trace_config(
+                Config::default()
+                    .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                        config.sample_ratio,
+                    ))))
+                    .with_resource(Resource::new([KeyValue::new(
+                        "service.name",
+                        config.service_name.clone(),
+                    )])),
+            )
+            .install_batch(runtime::Tokio)?;
+
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        global::set_tracer_provider(provider.clone());
+
+        Ok(Self { provider })
+    }
+
+    #[must_use]
+    pub fn tracer(&self) -> Tracer {
+        self.provider.tracer("ipa")
+    }
+}
+
+impl Drop for OtlpExporter {
+    fn drop(&mut self) {
+        if let Err(e) = self
+            .provider
+            .force_flush()
+            .into_iter()
+            .collect::<Result<(), _>>()
+        {
+            tracing::warn!("failed to flush spans to the collector: {e}");
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::io::Write;
+
+    use super::OtlpConfig;
+
+    #[test]
+    fn parse_config() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(
+            br#"
+endpoint = "http://collector:4317"
+service_name = "ipa-helper-2"
+sample_ratio = 0.5
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            OtlpConfig {
+                endpoint: "http://collector:4317".to_string(),
+                service_name: "ipa-helper-2".to_string(),
+                sample_ratio: 0.5,
+                ..OtlpConfig::default()
+            },
+            OtlpConfig::from_file(file.path()).unwrap()
+        );
+    }
+
+    #[test]
+    fn defaults_to_local_collector() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+
+        assert_eq!(
+            OtlpConfig::default(),
+            OtlpConfig::from_file(file.path()).unwrap()
+        );
+        assert_eq!("http://localhost:4317", OtlpConfig::default().endpoint);
+    }
+
+    #[test]
+    fn rejects_invalid_sample_ratio() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"sample_ratio = 2.0").unwrap();
+
+        assert!(OtlpConfig::from_file(file.path())
+            .unwrap_err()
+            .to_string()
+            .contains("sample ratio"));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"endpont = \"http://collector:4317\"")
+            .unwrap();
+
+        assert!(OtlpConfig::from_file(file.path()).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+//! Attributes metrics to the query that emitted them.
+//!
+//! Metric stores are partitioned by the value set in the thread local context. Futures
+//! that belong to a query can be polled by any runtime thread, so the partition is set
+//! before each poll and restored afterwards.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use ipa_metrics::{CurrentThreadPartitionContext, MetricPartition};
+use pin_project::pin_project;
+
+use crate::protocol::QueryId;
+
+/// Returns the metric partition that holds metrics emitted by the given query. Helpers drop
+/// it from the metrics collector once the query has completed and its metrics were scraped.
+#[must_use]
+pub fn query_partition(query_id: QueryId) -> MetricPartition {
+    u64::from(query_id)
+}
+
+#[pin_project]
+#[must_use = "Futures do nothing unless polled"]
+pub struct Partitioned<F> {
+    #[pin]
+    inner: F,
+    partition: Option<MetricPartition>,
+}
+
+impl<F: Future> Future for Partitioned<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let prev = CurrentThreadPartitionContext::get();
+        CurrentThreadPartitionContext::toggle(*this.partition);
+        let r = this.inner.poll(cx);
+        CurrentThreadPartitionContext::toggle(prev);
+
+        r
+    }
+}
+
+pub trait MetricsPartitionExt: Future + Sized {
+    /// Records all metrics emitted while polling this future in the given partition.
+    fn in_partition(self, partition: Option<MetricPartition>) -> Partitioned<Self> {
+        Partitioned {
+            inner: self,
+            partition,
+        }
+    }
+
+    /// Records all metrics emitted while polling this future in the partition that is
+    /// active on the current thread. Futures spawned as separate tasks must use it to stay
+    /// in the partition of the task that spawned them.
+    fn in_current_partition(self) -> Partitioned<Self> {
+        self.in_partition(CurrentThreadPartitionContext::get())
+    }
+}
+
+impl<F: Future> MetricsPartitionExt for F {}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::future::poll_fn;
+
+    use futures::FutureExt;
+    use ipa_metrics::CurrentThreadPartitionContext;
+
+    use crate::telemetry::partition::MetricsPartitionExt;
+
+    #[test]
+    fn partition_is_set_while_polling() {
+        CurrentThreadPartitionContext::set(1);
+        let partition = poll_fn(|_| CurrentThreadPartitionContext::get().into())
+            .in_partition(Some(2))
+            .now_or_never()
+            .unwrap();
+
+        assert_eq!(Some(2), partition);
+        assert_eq!(Some(1), CurrentThreadPartitionContext::get());
+        CurrentThreadPartitionContext::toggle(None);
+    }
+}
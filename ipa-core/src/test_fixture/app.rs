@@ -71,7 +71,11 @@ impl Default for TestApp {
         let shard_network = InMemoryShardNetwork::with_shards(1);
         let drivers = zip3(mpc_network.transports().each_ref(), setup).map(|(t, s)| {
             let metrics_handle = install_collector().unwrap();
-            let logging_handle = LoggingHandle { metrics_handle };
+            let logging_handle = LoggingHandle {
+                metrics_handle,
+                #[cfg(feature = "otlp")]
+                otlp_exporter: None,
+            };
             s.connect(
                 Clone::clone(t),
                 shard_network.transport(t.identity(), 0),
@@ -142,8 +146,8 @@ impl TestApp {
     pub async fn complete_query(&self, query_id: QueryId) -> Result<[Vec<u8>; 3], ApiError> {
         let results =
             try_join3_array([0, 1, 2].map(|i| self.drivers[i].complete_query(query_id))).await;
-        self.mpc_network.reset();
-        self.shard_network.reset();
+        self.mpc_network.reset_query(query_id);
+        self.shard_network.reset_query(query_id);
         results
     }
 
@@ -1,19 +1,23 @@
-use std::{borrow::Borrow, collections::HashMap, iter::zip};
+use std::{borrow::Borrow, collections::HashMap, iter::zip, num::NonZeroU32};
 
 use crate::{
     ff::{
         boolean_array::{BooleanArray, BA64},
         U128Conversions,
     },
+    helpers::query::{BreakdownDimensions, HybridAggregationMode},
+    protocol::hybrid::agg::MAX_REPORTS_PER_MATCH_KEY,
     rand::Rng,
     report::{
         hybrid::{
             AggregateableHybridReport, HybridConversionReport, HybridImpressionReport,
-            HybridReport, IndistinguishableHybridReport, KeyIdentifier,
+            HybridReport, HybridTimestamp, IndistinguishableHybridReport, KeyIdentifier,
         },
         hybrid_info::{HybridConversionInfo, HybridImpressionInfo},
     },
-    secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, IntoShares},
+    secret_sharing::{
+        replicated::semi_honest::AdditiveShare as Replicated, IntoShares, SharedValue,
+    },
     test_fixture::sharing::Reconstruct,
 };
 
@@ -23,6 +27,7 @@ pub enum TestHybridRecord {
         match_key: u64,
         breakdown_key: u32,
         key_id: KeyIdentifier,
+        timestamp: u64,
     },
     TestConversion {
         match_key: u64,
@@ -76,7 +81,7 @@ where
 }
 
 impl<BK, V> Reconstruct<TestAggregateableHybridReport>
-    for [&IndistinguishableHybridReport<BK, V, ()>; 3]
+    for [&IndistinguishableHybridReport<BK, V, (), (), ()>; 3]
 where
     BK: BooleanArray + U128Conversions + IntoShares<Replicated<BK>>,
     V: BooleanArray + U128Conversions + IntoShares<Replicated<V>>,
@@ -116,6 +121,8 @@ where
                 match_key: (),
                 breakdown_key,
                 value,
+                timestamp: (),
+                is_impression: (),
             })
             .collect::<Vec<_>>()
             .try_into()
@@ -134,6 +141,7 @@ where
                 match_key,
                 breakdown_key,
                 key_id,
+                timestamp,
             } => {
                 let ba_match_key = BA64::try_from(u128::from(match_key))
                     .unwrap()
@@ -141,14 +149,18 @@ where
                 let ba_breakdown_key = BK::try_from(u128::from(breakdown_key))
                     .unwrap()
                     .share_with(rng);
-                zip(ba_match_key, ba_breakdown_key)
-                    .map(|(match_key_share, breakdown_key_share)| {
-                        HybridReport::Impression::<BK, V>(HybridImpressionReport {
-                            match_key: match_key_share,
-                            breakdown_key: breakdown_key_share,
-                            info: HybridImpressionInfo::new(key_id),
-                        })
-                    })
+                let ba_timestamp = HybridTimestamp::truncate_from(timestamp).share_with(rng);
+                zip(ba_match_key, zip(ba_breakdown_key, ba_timestamp))
+                    .map(
+                        |(match_key_share, (breakdown_key_share, timestamp_share))| {
+                            HybridReport::Impression::<BK, V>(HybridImpressionReport {
+                                match_key: match_key_share,
+                                breakdown_key: breakdown_key_share,
+                                timestamp: timestamp_share,
+                                info: HybridImpressionInfo::new(key_id),
+                            })
+                        },
+                    )
                     .collect::<Vec<_>>()
                     .try_into()
                     .unwrap()
@@ -189,71 +201,98 @@ where
     }
 }
 
-enum MatchEntry {
-    SingleImpression { breakdown_key: u32 },
-    SingleConversion { value: u32 },
-    Attributed(Option<(u32, u32)>),
-    MoreThanTwo,
+/// All the reports that share a `match_key`, merged the same way `aggregate_reports` does it.
+#[derive(Default)]
+struct MatchEntry {
+    impressions: Vec<(u32, u64)>,
+    conversions: Vec<(u32, u64)>,
+    reports: usize,
 }
 
 impl MatchEntry {
-    pub fn from_record(record: &TestHybridRecord) -> Self {
-        match record {
-            TestHybridRecord::TestImpression { breakdown_key, .. } => Self::SingleImpression {
-                breakdown_key: *breakdown_key,
-            },
-            TestHybridRecord::TestConversion { value, .. } => {
-                Self::SingleConversion { value: *value }
-            }
-        }
-    }
-
     pub fn add_record(&mut self, new_record: &TestHybridRecord) {
-        match self {
-            MatchEntry::SingleImpression { breakdown_key, .. } => {
-                *self = Self::attribute_impression(*breakdown_key, new_record);
-            }
-            MatchEntry::SingleConversion { value } => {
-                *self = Self::attribute_conversion(*value, new_record);
-            }
-            _ => *self = Self::MoreThanTwo,
-        }
-    }
-
-    fn attribute_impression(breakdown_key: u32, new_record: &TestHybridRecord) -> Self {
+        // the protocol compares the timestamps modulo 2^32
+        let truncate = |timestamp: &u64| timestamp & ((1 << HybridTimestamp::BITS) - 1);
+        self.reports += 1;
         match new_record {
-            TestHybridRecord::TestImpression { .. } => Self::Attributed(None),
-            TestHybridRecord::TestConversion { value, .. } => {
-                Self::Attributed(Some((breakdown_key, *value)))
-            }
-        }
-    }
-
-    fn attribute_conversion(value: u32, new_record: &TestHybridRecord) -> Self {
-        match new_record {
-            TestHybridRecord::TestImpression { breakdown_key, .. } => {
-                Self::Attributed(Some((*breakdown_key, value)))
+            TestHybridRecord::TestImpression {
+                breakdown_key,
+                timestamp,
+                ..
+            } => {
+                // impressions with breakdown key 0 cannot be told apart from conversions
+                if *breakdown_key != 0 {
+                    self.impressions.push((*breakdown_key, truncate(timestamp)));
+                }
             }
             TestHybridRecord::TestConversion {
-                value: other_value, ..
-            } => Self::Attributed(Some((0, value + *other_value))),
+                value, timestamp, ..
+            } => self.conversions.push((*value, truncate(timestamp))),
         }
     }
 
-    pub fn into_breakdown_key_and_value_tuple(self) -> Option<(u32, u32)> {
-        match self {
-            Self::Attributed(v) => v,
-            _ => None,
+    pub fn into_breakdown_key_and_value_tuple(
+        self,
+        per_user_credit_cap: u32,
+        attribution_window: Option<NonZeroU32>,
+    ) -> Option<(u32, u32)> {
+        if !(2..=MAX_REPORTS_PER_MATCH_KEY).contains(&self.reports) {
+            return None;
         }
+        // last touch: the latest impression that does not follow the latest conversion
+        let conversion_timestamp = self
+            .conversions
+            .iter()
+            .map(|(_, timestamp)| *timestamp)
+            .max();
+        let impression = self
+            .impressions
+            .iter()
+            .filter(|(_, timestamp)| Some(*timestamp) <= conversion_timestamp)
+            .max_by_key(|(_, timestamp)| *timestamp)
+            .copied();
+        let breakdown_key = impression.map_or(0, |(breakdown_key, _)| breakdown_key);
+        let value = match (attribution_window, impression) {
+            (None, _) => self.conversions.iter().map(|(value, _)| value).sum(),
+            (Some(_), None) => 0,
+            (Some(window), Some((_, impression_timestamp))) => self
+                .conversions
+                .iter()
+                .filter(|(_, timestamp)| {
+                    timestamp
+                        .checked_sub(impression_timestamp)
+                        .is_some_and(|time_delta| time_delta <= u64::from(window.get()))
+                })
+                .map(|(value, _)| value)
+                .sum(),
+        };
+        Some((breakdown_key, value.min(per_user_credit_cap)))
     }
 }
 
+/// Computes the hybrid histogram in the clear.
+///
+/// Reports that share a `match_key` are attributed to the breakdown key of the latest impression
+/// among them that does not follow their latest conversion, and their conversion values are
+/// summed and capped at `per_user_credit_cap`.
+/// When `attribution_window` is set, only conversions that happen within that many seconds
+/// after the impression are counted, and conversions without an impression are dropped.
+/// `match_key`s with a single report or more than `MAX_REPORTS_PER_MATCH_KEY` reports
+/// are dropped. When `breakdown_dimensions` are set, breakdown keys are mapped to the bucket
+/// of the two keys they carry, and values of keys outside the dimensions are dropped.
+/// When the `aggregation_mode` includes reach, the number of users attributed a non-zero value
+/// follows the sums for every breakdown key.
+///
 /// # Panics
 /// It won't, so long as you can convert a u32 to a usize
 #[must_use]
 pub fn hybrid_in_the_clear<I: IntoIterator<Item: Borrow<TestHybridRecord>>>(
     input_rows: I,
     max_breakdown: usize,
+    per_user_credit_cap: u32,
+    attribution_window: Option<NonZeroU32>,
+    breakdown_dimensions: Option<BreakdownDimensions>,
+    aggregation_mode: HybridAggregationMode,
 ) -> Vec<u32> {
     let mut attributed_conversions = HashMap::<u64, MatchEntry>::new();
     for input in input_rows {
@@ -262,16 +301,30 @@ pub fn hybrid_in_the_clear<I: IntoIterator<Item: Borrow<TestHybridRecord>>>(
             | TestHybridRecord::TestImpression { match_key, .. }) => {
                 attributed_conversions
                     .entry(*match_key)
-                    .and_modify(|e| e.add_record(r))
-                    .or_insert(MatchEntry::from_record(r));
+                    .or_default()
+                    .add_record(r);
             }
         }
     }
 
-    let mut output = vec![0; max_breakdown];
+    let histograms = usize::try_from(aggregation_mode.histograms()).unwrap();
+    let mut output = vec![0; max_breakdown * histograms];
     for entry in attributed_conversions.into_values() {
-        if let Some((breakdown_key, value)) = entry.into_breakdown_key_and_value_tuple() {
-            output[usize::try_from(breakdown_key).unwrap()] += value;
+        if let Some((breakdown_key, value)) =
+            entry.into_breakdown_key_and_value_tuple(per_user_credit_cap, attribution_window)
+        {
+            let bucket = match breakdown_dimensions {
+                Some(dimensions) => match dimensions.bucket(breakdown_key) {
+                    Some(bucket) => bucket,
+                    None => continue,
+                },
+                None => breakdown_key,
+            };
+            let bucket = usize::try_from(bucket).unwrap();
+            output[bucket] += value;
+            if aggregation_mode == HybridAggregationMode::SumAndReach && value > 0 {
+                output[max_breakdown + bucket] += 1;
+            }
         }
     }
 
@@ -289,8 +342,8 @@ pub fn build_hybrid_records_and_expectation() -> (Vec<TestHybridRecord>, Vec<u32
             key_id: 0,
             conversion_site_domain: conversion_site_domain.clone(),
             timestamp: 100,
-            epsilon: 0.0,
-            sensitivity: 0.0,
+            epsilon: f64::INFINITY,
+            sensitivity: f64::INFINITY,
         }, // malicious client attributed to breakdown 0
         TestHybridRecord::TestConversion {
             match_key: 12345,
@@ -298,32 +351,35 @@ pub fn build_hybrid_records_and_expectation() -> (Vec<TestHybridRecord>, Vec<u32
             key_id: 0,
             conversion_site_domain: conversion_site_domain.clone(),
             timestamp: 101,
-            epsilon: 0.0,
-            sensitivity: 0.0,
+            epsilon: f64::INFINITY,
+            sensitivity: f64::INFINITY,
         }, // malicious client attributed to breakdown 0
         TestHybridRecord::TestImpression {
             match_key: 23456,
             breakdown_key: 4,
             key_id: 0,
-        }, // attributed
+            timestamp: 90,
+        }, // attributed, unless the attribution window is shorter than 12 seconds
         TestHybridRecord::TestConversion {
             match_key: 23456,
             value: 7,
             key_id: 0,
             conversion_site_domain: conversion_site_domain.clone(),
             timestamp: 102,
-            epsilon: 0.0,
-            sensitivity: 0.0,
+            epsilon: f64::INFINITY,
+            sensitivity: f64::INFINITY,
         }, // attributed
         TestHybridRecord::TestImpression {
             match_key: 34567,
             breakdown_key: 1,
             key_id: 0,
+            timestamp: 100,
         }, // no conversion
         TestHybridRecord::TestImpression {
             match_key: 45678,
             breakdown_key: 3,
             key_id: 0,
+            timestamp: 100,
         }, // attributed
         TestHybridRecord::TestConversion {
             match_key: 45678,
@@ -331,13 +387,14 @@ pub fn build_hybrid_records_and_expectation() -> (Vec<TestHybridRecord>, Vec<u32
             key_id: 0,
             conversion_site_domain: conversion_site_domain.clone(),
             timestamp: 103,
-            epsilon: 0.0,
-            sensitivity: 0.0,
+            epsilon: f64::INFINITY,
+            sensitivity: f64::INFINITY,
         }, // attributed
         TestHybridRecord::TestImpression {
             match_key: 56789,
             breakdown_key: 5,
             key_id: 0,
+            timestamp: 100,
         }, // no conversion
         TestHybridRecord::TestConversion {
             match_key: 67890,
@@ -345,36 +402,38 @@ pub fn build_hybrid_records_and_expectation() -> (Vec<TestHybridRecord>, Vec<u32
             key_id: 0,
             conversion_site_domain: conversion_site_domain.clone(),
             timestamp: 104,
-            epsilon: 0.0,
-            sensitivity: 0.0,
+            epsilon: f64::INFINITY,
+            sensitivity: f64::INFINITY,
         }, // NOT attributed
         TestHybridRecord::TestImpression {
             match_key: 78901,
             breakdown_key: 2,
             key_id: 0,
-        }, // too many reports
+            timestamp: 100,
+        }, // attributed
         TestHybridRecord::TestConversion {
             match_key: 78901,
             value: 3,
             key_id: 0,
             conversion_site_domain: conversion_site_domain.clone(),
             timestamp: 105,
-            epsilon: 0.0,
-            sensitivity: 0.0,
-        }, // not attributed, too many reports
+            epsilon: f64::INFINITY,
+            sensitivity: f64::INFINITY,
+        }, // attributed to the same impression
         TestHybridRecord::TestConversion {
             match_key: 78901,
             value: 4,
             key_id: 0,
             conversion_site_domain: conversion_site_domain.clone(),
             timestamp: 103,
-            epsilon: 0.0,
-            sensitivity: 0.0,
-        }, // not attributed, too many reports
+            epsilon: f64::INFINITY,
+            sensitivity: f64::INFINITY,
+        }, // attributed to the same impression
         TestHybridRecord::TestImpression {
             match_key: 89012,
             breakdown_key: 4,
             key_id: 0,
+            timestamp: 102,
         }, // attributed
         TestHybridRecord::TestConversion {
             match_key: 89012,
@@ -382,14 +441,15 @@ pub fn build_hybrid_records_and_expectation() -> (Vec<TestHybridRecord>, Vec<u32
             key_id: 0,
             conversion_site_domain: conversion_site_domain.clone(),
             timestamp: 103,
-            epsilon: 0.0,
-            sensitivity: 0.0,
+            epsilon: f64::INFINITY,
+            sensitivity: f64::INFINITY,
         }, // attributed
     ];
 
     let expected = vec![
         7, // two conversions goes to bucket 0: 2 + 5
-        0, 0, 5, 13, // 4: 7 + 6
+        0, 7, // 2: 3 + 4 from a single user
+        5, 13, // 4: 7 + 6
         0,
     ];
 
@@ -398,16 +458,79 @@ pub fn build_hybrid_records_and_expectation() -> (Vec<TestHybridRecord>, Vec<u32
 
 #[cfg(all(test, unit_test))]
 mod tests {
+    use std::num::NonZeroU32;
+
     use rand::{seq::SliceRandom, thread_rng};
 
-    use crate::test_fixture::hybrid::{build_hybrid_records_and_expectation, hybrid_in_the_clear};
+    use crate::{
+        helpers::query::HybridAggregationMode,
+        test_fixture::hybrid::{build_hybrid_records_and_expectation, hybrid_in_the_clear},
+    };
 
     #[test]
     fn hybrid_basic() {
         let (mut test_hybrid_records, expected) = build_hybrid_records_and_expectation();
         let mut rng = thread_rng();
         test_hybrid_records.shuffle(&mut rng);
-        let result = hybrid_in_the_clear(&test_hybrid_records, 6);
+        let result = hybrid_in_the_clear(
+            &test_hybrid_records,
+            6,
+            8,
+            None,
+            None,
+            HybridAggregationMode::Sum,
+        );
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn hybrid_attribution_window() {
+        let (test_hybrid_records, _) = build_hybrid_records_and_expectation();
+        let result = hybrid_in_the_clear(
+            &test_hybrid_records,
+            6,
+            8,
+            NonZeroU32::new(10),
+            None,
+            HybridAggregationMode::Sum,
+        );
+        // conversions without an impression or more than 10 seconds after the impression are
+        // not attributed
+        assert_eq!(result, vec![0, 0, 7, 5, 6, 0]);
+    }
+
+    #[test]
+    fn hybrid_per_user_credit_cap() {
+        let (test_hybrid_records, mut expected) = build_hybrid_records_and_expectation();
+        let result = hybrid_in_the_clear(
+            &test_hybrid_records,
+            6,
+            5,
+            None,
+            None,
+            HybridAggregationMode::Sum,
+        );
+        // every user's contribution is capped at 5
+        expected[0] = 5;
+        expected[2] = 5;
+        expected[4] = 5 + 5;
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn hybrid_reach() {
+        let (test_hybrid_records, expected) = build_hybrid_records_and_expectation();
+        let result = hybrid_in_the_clear(
+            &test_hybrid_records,
+            6,
+            8,
+            None,
+            None,
+            HybridAggregationMode::SumAndReach,
+        );
+        assert_eq!(result[..6], expected);
+        // one user converted without an impression, one user each was attributed to
+        // breakdowns 2 and 3, and two users to breakdown 4
+        assert_eq!(result[6..], [1, 0, 1, 1, 2, 0]);
+    }
 }
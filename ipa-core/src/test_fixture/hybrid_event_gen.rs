@@ -22,6 +22,14 @@ pub struct Config {
     /// Indicates the distribution of impression to conversion reports.
     #[cfg_attr(feature = "clap", arg(value_enum, long, default_value_t = ConversionDistribution::Default))]
     pub conversion_distribution: ConversionDistribution,
+    /// Epsilon declared by conversion reports. Queries that spend more are not allowed to use
+    /// them.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = f64::INFINITY))]
+    pub epsilon: f64,
+    /// Sensitivity declared by conversion reports. Queries with a larger per-user credit cap
+    /// are not allowed to use them.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = f64::INFINITY))]
+    pub sensitivity: f64,
 }
 
 impl Default for Config {
@@ -45,6 +53,8 @@ impl Config {
             max_conversion_value: NonZeroU32::try_from(max_conversion_value).unwrap(),
             max_breakdown_key: NonZeroU32::try_from(max_breakdown_key).unwrap(),
             conversion_distribution,
+            epsilon: f64::INFINITY,
+            sensitivity: f64::INFINITY,
         }
     }
 }
@@ -114,8 +124,8 @@ impl<R: Rng> EventGenerator<R> {
             key_id: 0,
             conversion_site_domain: "meta.com".to_string(),
             timestamp: self.rng.gen_range(0..1000),
-            epsilon: 0.0,
-            sensitivity: 0.0,
+            epsilon: self.config.epsilon,
+            sensitivity: self.config.sensitivity,
         }
     }
 
@@ -124,6 +134,7 @@ impl<R: Rng> EventGenerator<R> {
             match_key,
             breakdown_key: self.rng.gen_range(0..self.config.max_breakdown_key.get()),
             key_id: 0,
+            timestamp: self.rng.gen_range(0..1000),
         }
     }
 }
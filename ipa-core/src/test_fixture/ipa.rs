@@ -1,16 +1,13 @@
-use std::{collections::HashMap, num::NonZeroU32};
+use std::{cmp::min, collections::HashMap, iter::zip, num::NonZeroU32};
 
 use rand::{thread_rng, Rng};
 
-use crate::protocol::ipa_prf::prf_sharding::GroupingKey;
 #[cfg(feature = "in-memory-infra")]
 use crate::{
     ff::{PrimeField, Serializable},
     helpers::query::{DpMechanism, IpaQueryConfig},
     protocol::{
-        dp::NoiseParams,
-        ipa_prf::oprf_padding::{insecure::OPRFPaddingDp, PaddingParameters},
-        ipa_prf::OPRFIPAInputRow,
+        dp::NoiseParams, ipa_prf::oprf_padding::insecure::OPRFPaddingDp, ipa_prf::OPRFIPAInputRow,
     },
     secret_sharing::{
         replicated::{
@@ -19,6 +16,13 @@ use crate::{
         IntoShares,
     },
 };
+use crate::{
+    helpers::query::AttributionModel,
+    protocol::ipa_prf::prf_sharding::{
+        multi_touch::{DECAY_SHIFT_BITS, FRACTIONAL_BITS},
+        GroupingKey,
+    },
+};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
@@ -68,12 +72,16 @@ fn insert_sorted(user_records: &mut Vec<TestRawDataRecord>, record: TestRawDataR
 /// order those records are considered by the attribution algorithm is undefined, and the output
 /// may be non-deterministic.
 ///
+/// Multi-touch models reproduce the fixed-point arithmetic of the MPC circuit, including its
+/// rounding, and always cap trigger values in the order they occurred, ignoring `order`.
+///
 /// ## Panics
 /// Will panic if you run in on Intel 80286 or any other 16 bit hardware.
 pub fn ipa_in_the_clear(
     input: &[TestRawDataRecord],
     per_user_cap: u32,
     attribution_window: Option<NonZeroU32>,
+    attribution_model: AttributionModel,
     max_breakdown: u32,
     order: &CappingOrder,
 ) -> Vec<u32> {
@@ -90,14 +98,25 @@ pub fn ipa_in_the_clear(
 
     let mut breakdowns = vec![0u32; usize::try_from(max_breakdown).unwrap()];
     for records_per_user in user_events.values() {
-        let rev_records = records_per_user.iter().rev();
-        update_expected_output_for_user(
-            rev_records,
-            &mut breakdowns,
-            per_user_cap,
-            attribution_window,
-            order,
-        );
+        if attribution_model.is_multi_touch() {
+            update_multi_touch_output_for_user(
+                records_per_user,
+                &mut breakdowns,
+                per_user_cap,
+                attribution_window,
+                attribution_model,
+            );
+        } else {
+            let rev_records = records_per_user.iter().rev();
+            update_expected_output_for_user(
+                rev_records,
+                &mut breakdowns,
+                per_user_cap,
+                attribution_window,
+                attribution_model == AttributionModel::FirstTouch,
+                order,
+            );
+        }
     }
 
     breakdowns
@@ -108,6 +127,16 @@ pub enum CappingOrder {
     CapMostRecentFirst,
 }
 
+fn within_window(attribution_window_seconds: Option<NonZeroU32>, value: u64) -> bool {
+    if let Some(window) = attribution_window_seconds {
+        value <= u64::from(window.get())
+    } else {
+        // if window is not specified, it is considered of infinite size. Everything is
+        // within that window.
+        true
+    }
+}
+
 /// Assumes records all belong to the same user, and are in reverse chronological order
 /// Will give incorrect results if this is not true
 #[allow(clippy::missing_panics_doc)]
@@ -116,30 +145,29 @@ fn update_expected_output_for_user<'a, I: IntoIterator<Item = &'a TestRawDataRec
     expected_results: &mut [u32],
     per_user_cap: u32,
     attribution_window_seconds: Option<NonZeroU32>,
+    first_touch: bool,
     order: &CappingOrder,
 ) {
-    let within_window = |value: u64| -> bool {
-        if let Some(window) = attribution_window_seconds {
-            value <= u64::from(window.get())
-        } else {
-            // if window is not specified, it is considered of infinite size. Everything is
-            // within that window.
-            true
-        }
-    };
-
     let mut attributed_triggers = Vec::new();
     let mut pending_trigger_reports = Vec::new();
+    // For first touch, every trigger report that follows the first source report is attributed
+    // to it. These are the first `first_touch_triggers` pending reports once the first source
+    // report (the last one in this order) has been seen.
+    let mut first_touch_triggers = 0;
+    let mut first_source_report = None;
     for record in records_for_user {
         if record.is_trigger_report {
             pending_trigger_reports.push(record);
+        } else if first_touch {
+            first_touch_triggers = pending_trigger_reports.len();
+            first_source_report = Some(record);
         } else if !pending_trigger_reports.is_empty() {
             for trigger_report in pending_trigger_reports {
                 let time_delta_to_source_report = trigger_report.timestamp - record.timestamp;
 
                 // only count trigger reports that are within the attribution window
                 // only if attribution_window is set. This matches the behaviour in MPC
-                if !within_window(time_delta_to_source_report) {
+                if !within_window(attribution_window_seconds, time_delta_to_source_report) {
                     continue;
                 }
 
@@ -148,6 +176,14 @@ fn update_expected_output_for_user<'a, I: IntoIterator<Item = &'a TestRawDataRec
             pending_trigger_reports = Vec::new();
         }
     }
+    if let Some(source_report) = first_source_report {
+        for trigger_report in pending_trigger_reports.drain(..first_touch_triggers) {
+            let time_delta_to_source_report = trigger_report.timestamp - source_report.timestamp;
+            if within_window(attribution_window_seconds, time_delta_to_source_report) {
+                attributed_triggers.push((trigger_report, source_report));
+            }
+        }
+    }
 
     match order {
         CappingOrder::CapOldestFirst => {
@@ -176,6 +212,84 @@ where
     }
 }
 
+/// Splits the trigger values of a user between their source reports the way the multi-touch MPC
+/// circuit does, in fixed point with the same rounding. Assumes records all belong to the same
+/// user, and are in chronological order.
+fn update_multi_touch_output_for_user(
+    records_for_user: &[TestRawDataRecord],
+    expected_results: &mut [u32],
+    per_user_cap: u32,
+    attribution_window_seconds: Option<NonZeroU32>,
+    attribution_model: AttributionModel,
+) {
+    let half_life_bits = match attribution_model {
+        AttributionModel::TimeDecay { half_life_seconds } => {
+            Some(half_life_seconds.trailing_zeros())
+        }
+        _ => None,
+    };
+    // time decay rounds the sum of weights up
+    let source_weight = (1 << FRACTIONAL_BITS) + u32::from(half_life_bits.is_some());
+    let decay = |value: u32, periods: u64| {
+        if periods >= 1 << DECAY_SHIFT_BITS {
+            0
+        } else {
+            value >> periods
+        }
+    };
+
+    // Oldest to newest: divide the capped trigger values by the sum of source weights. Also
+    // remember the number of periods between each source report and the one before it.
+    let mut last_source_report: Option<&TestRawDataRecord> = None;
+    let mut total_contribution = 0;
+    let mut weights = 0;
+    let mut shares = Vec::with_capacity(records_for_user.len());
+    for record in records_for_user {
+        if record.is_trigger_report {
+            let capped_contribution = match last_source_report {
+                Some(source_report)
+                    if within_window(
+                        attribution_window_seconds,
+                        record.timestamp - source_report.timestamp,
+                    ) =>
+                {
+                    min(per_user_cap - total_contribution, record.trigger_value)
+                }
+                _ => 0,
+            };
+            total_contribution += capped_contribution;
+            let share = if capped_contribution == 0 {
+                0
+            } else {
+                (capped_contribution << (2 * FRACTIONAL_BITS)) / weights
+            };
+            shares.push((share, 0));
+        } else {
+            let periods = match (half_life_bits, last_source_report) {
+                (Some(bits), Some(previous)) => {
+                    (record.timestamp >> bits) - (previous.timestamp >> bits)
+                }
+                _ => 0,
+            };
+            weights = decay(weights, periods) + source_weight;
+            last_source_report = Some(record);
+            shares.push((0, periods));
+        }
+    }
+
+    // Newest to oldest: credit each source report with the shares of the trigger reports after
+    // it, decayed by the periods between the source reports.
+    let mut accumulator = 0;
+    for (record, (share, periods)) in zip(records_for_user, shares).rev() {
+        accumulator += share;
+        if !record.is_trigger_report {
+            let bk: usize = record.breakdown_key.try_into().unwrap();
+            expected_results[bk] += accumulator >> FRACTIONAL_BITS;
+        }
+        accumulator = decay(accumulator, periods);
+    }
+}
+
 /// # Panics
 /// If any of the IPA protocol modules panic
 #[allow(clippy::too_many_lines)]
@@ -202,19 +316,13 @@ pub async fn test_oprf_ipa<F>(
     };
 
     let aws = config.attribution_window_seconds;
-    let dp_params: DpMechanism = match config.with_dp {
-        0 => DpMechanism::NoDp,
-        _ => DpMechanism::DiscreteLaplace {
-            epsilon: config.epsilon,
-        },
-    };
-    let padding_params = PaddingParameters::default();
+    let dp_params = config.dp_mechanism;
+    let padding_params = config.padding_params();
 
     let result: Vec<_> = if security_model == IpaSecurityModel::SemiHonest
         && matches!(
             config,
             IpaQueryConfig {
-                per_user_credit_cap: 8,
                 max_breakdown_key: 32,
                 ..
             }
@@ -222,10 +330,12 @@ pub async fn test_oprf_ipa<F>(
         world.semi_honest(
             records.into_iter(),
             |ctx, input_rows: Vec<OPRFIPAInputRow<BA5, BA3, BA20>>| async move {
-                oprf_ipa::<_, BA5, BA3, BA32, BA20, 3, 32>(
+                oprf_ipa::<_, BA5, BA3, BA32, BA20, 32>(
                     ctx,
                     input_rows,
+                    config.per_user_credit_cap,
                     aws,
+                    config.attribution_model,
                     dp_params,
                     padding_params,
                 )
@@ -237,7 +347,6 @@ pub async fn test_oprf_ipa<F>(
         && matches!(
             config,
             IpaQueryConfig {
-                per_user_credit_cap: 8,
                 max_breakdown_key: 256,
                 ..
             }
@@ -246,10 +355,12 @@ pub async fn test_oprf_ipa<F>(
         world.semi_honest(
             records.into_iter(),
             |ctx, input_rows: Vec<OPRFIPAInputRow<BA8, BA3, BA20>>| async move {
-                oprf_ipa::<_, BA8, BA3, BA32, BA20, 3, 256>(
+                oprf_ipa::<_, BA8, BA3, BA32, BA20, 256>(
                     ctx,
                     input_rows,
+                    config.per_user_credit_cap,
                     aws,
+                    config.attribution_model,
                     dp_params,
                     padding_params,
                 )
@@ -261,7 +372,6 @@ pub async fn test_oprf_ipa<F>(
         && matches!(
             config,
             IpaQueryConfig {
-                per_user_credit_cap: 8,
                 max_breakdown_key: 32,
                 ..
             }
@@ -270,10 +380,12 @@ pub async fn test_oprf_ipa<F>(
         world.malicious(
             records.into_iter(),
             |ctx, input_rows: Vec<OPRFIPAInputRow<BA5, BA3, BA20>>| async move {
-                oprf_ipa::<_, BA5, BA3, BA32, BA20, 3, 32>(
+                oprf_ipa::<_, BA5, BA3, BA32, BA20, 32>(
                     ctx,
                     input_rows,
+                    config.per_user_credit_cap,
                     aws,
+                    config.attribution_model,
                     dp_params,
                     padding_params,
                 )
@@ -285,7 +397,6 @@ pub async fn test_oprf_ipa<F>(
         && matches!(
             config,
             IpaQueryConfig {
-                per_user_credit_cap: 8,
                 max_breakdown_key: 256,
                 ..
             }
@@ -294,10 +405,12 @@ pub async fn test_oprf_ipa<F>(
         world.malicious(
             records.into_iter(),
             |ctx, input_rows: Vec<OPRFIPAInputRow<BA8, BA3, BA20>>| async move {
-                oprf_ipa::<_, BA8, BA3, BA32, BA20, 3, 256>(
+                oprf_ipa::<_, BA8, BA3, BA32, BA20, 256>(
                     ctx,
                     input_rows,
+                    config.per_user_credit_cap,
                     aws,
+                    config.attribution_model,
                     dp_params,
                     padding_params,
                 )
@@ -307,8 +420,8 @@ pub async fn test_oprf_ipa<F>(
         )
     } else {
         panic!(
-            "Unsupported configuration: per_user_credit_cap = {:?}, max_breakdown_key = {:?}.",
-            config.per_user_credit_cap, config.max_breakdown_key,
+            "Unsupported configuration: max_breakdown_key = {:?}.",
+            config.max_breakdown_key,
         )
     }
     .await
@@ -326,10 +439,10 @@ pub async fn test_oprf_ipa<F>(
         DpMechanism::NoDp => {
             assert_eq!(result, expected_results);
         }
-        DpMechanism::Binomial { epsilon } => {
+        DpMechanism::Binomial { epsilon, delta } => {
             let noise_params = NoiseParams {
                 epsilon,
-                delta: 1e-6,
+                delta,
                 per_user_credit_cap: config.per_user_credit_cap,
                 ell_1_sensitivity: f64::from(config.per_user_credit_cap),
                 ell_2_sensitivity: f64::from(config.per_user_credit_cap),
@@ -407,6 +520,82 @@ mod tests {
         actual
     }
 
+    #[test]
+    fn in_the_clear_attribution_models() {
+        fn record(
+            user_id: u64,
+            is_trigger_report: bool,
+            breakdown_key: u32,
+            trigger_value: u32,
+            timestamp: u64,
+        ) -> TestRawDataRecord {
+            TestRawDataRecord {
+                timestamp,
+                user_id,
+                is_trigger_report,
+                breakdown_key,
+                trigger_value,
+            }
+        }
+
+        let records = [
+            record(1, false, 17, 0, 0),
+            record(1, true, 0, 7, 10),
+            record(1, false, 20, 0, 64),
+            record(1, true, 0, 3, 70),
+            record(2, false, 12, 0, 0),
+            record(2, true, 0, 5, 10),
+            record(3, false, 20, 0, 0),
+            record(3, true, 0, 7, 100),
+            record(3, false, 18, 0, 128),
+            record(3, false, 12, 0, 256),
+            record(3, true, 0, 7, 300),
+            record(3, true, 0, 7, 400),
+            record(3, true, 0, 7, 500),
+            record(3, true, 0, 7, 600),
+            record(4, false, 5, 0, 0),
+            record(4, false, 6, 0, 2000),
+            record(4, true, 0, 6, 2010),
+        ];
+
+        let expected = |credits: &[(usize, u32)]| {
+            let mut expected = vec![0; 32];
+            for &(breakdown_key, credit) in credits {
+                expected[breakdown_key] = credit;
+            }
+            expected
+        };
+        let run = |model| {
+            ipa_in_the_clear(
+                &records,
+                32,
+                None,
+                model,
+                32,
+                &CappingOrder::CapMostRecentFirst,
+            )
+        };
+
+        assert_eq!(
+            run(AttributionModel::LastTouch),
+            expected(&[(6, 6), (12, 30), (17, 7), (20, 10)])
+        );
+        assert_eq!(
+            run(AttributionModel::FirstTouch),
+            expected(&[(5, 6), (12, 5), (17, 10), (20, 32)])
+        );
+        assert_eq!(
+            run(AttributionModel::Linear),
+            expected(&[(5, 3), (6, 3), (12, 13), (17, 8), (18, 8), (20, 16)])
+        );
+        assert_eq!(
+            run(AttributionModel::TimeDecay {
+                half_life_seconds: 64
+            }),
+            expected(&[(6, 5), (12, 22), (17, 7), (18, 4), (20, 8)])
+        );
+    }
+
     #[test]
     fn insert_sorted() {
         insert_sorted_test([1, 2, 3, 4]);
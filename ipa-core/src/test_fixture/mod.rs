@@ -20,6 +20,7 @@ pub mod metrics;
 mod shard_configurator;
 #[cfg(feature = "in-memory-infra")]
 mod test_gate;
+pub mod walr;
 
 use std::{fmt::Debug, future::Future};
 
@@ -0,0 +1,188 @@
+use std::{collections::HashMap, iter::zip};
+
+use crate::{
+    ff::{
+        boolean::Boolean,
+        boolean_array::{BooleanArray, BA64},
+        U128Conversions,
+    },
+    protocol::ipa_prf::walr::WalrInputRow,
+    rand::Rng,
+    report::{walr::WalrReport, EventType},
+    secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, IntoShares},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestWalrRecord<const B: usize> {
+    pub user_id: u64,
+    pub timestamp: u64,
+    pub is_trigger_report: bool,
+    pub feature_vector: [u32; B],
+}
+
+impl<const B: usize> TestWalrRecord<B> {
+    #[must_use]
+    pub fn source(user_id: u64, timestamp: u64, feature_vector: [u32; B]) -> Self {
+        Self {
+            user_id,
+            timestamp,
+            is_trigger_report: false,
+            feature_vector,
+        }
+    }
+
+    #[must_use]
+    pub fn trigger(user_id: u64, timestamp: u64) -> Self {
+        Self {
+            user_id,
+            timestamp,
+            is_trigger_report: true,
+            feature_vector: [0; B],
+        }
+    }
+}
+
+impl<FV, TS, const B: usize> IntoShares<WalrInputRow<FV, TS, B>> for TestWalrRecord<B>
+where
+    FV: BooleanArray + U128Conversions + IntoShares<Replicated<FV>>,
+    TS: BooleanArray + U128Conversions + IntoShares<Replicated<TS>>,
+{
+    fn share_with<R: Rng>(self, rng: &mut R) -> [WalrInputRow<FV, TS, B>; 3] {
+        let match_key = BA64::try_from(u128::from(self.user_id))
+            .unwrap()
+            .share_with(rng);
+        let is_trigger = Boolean::from(self.is_trigger_report).share_with(rng);
+        let timestamp = TS::try_from(u128::from(self.timestamp))
+            .unwrap()
+            .share_with(rng);
+        let mut features = self
+            .feature_vector
+            .map(|feature| FV::try_from(u128::from(feature)).unwrap().share_with(rng));
+
+        let mut rows = zip(match_key, zip(is_trigger, timestamp)).enumerate().map(
+            |(i, (match_key, (is_trigger, timestamp)))| WalrInputRow {
+                match_key,
+                is_trigger,
+                timestamp,
+                feature_vector: features
+                    .each_mut()
+                    .map(|shares| std::mem::replace(&mut shares[i], Replicated::ZERO)),
+            },
+        );
+
+        [
+            rows.next().unwrap(),
+            rows.next().unwrap(),
+            rows.next().unwrap(),
+        ]
+    }
+}
+
+impl<FV, TS, const B: usize> IntoShares<WalrReport<FV, TS, B>> for TestWalrRecord<B>
+where
+    FV: BooleanArray + U128Conversions + IntoShares<Replicated<FV>>,
+    TS: BooleanArray + U128Conversions + IntoShares<Replicated<TS>>,
+{
+    fn share_with<R: Rng>(self, rng: &mut R) -> [WalrReport<FV, TS, B>; 3] {
+        let event_type = if self.is_trigger_report {
+            EventType::Trigger
+        } else {
+            EventType::Source
+        };
+        let rows: [WalrInputRow<FV, TS, B>; 3] = self.share_with(rng);
+
+        rows.map(|row| WalrReport {
+            match_key: row.match_key,
+            event_type,
+            timestamp: row.timestamp,
+            feature_vector: row.feature_vector,
+            epoch: 1,
+            site_domain: "example.com".to_owned(),
+        })
+    }
+}
+
+/// Computes the sigmoid approximation evaluated by the WALR protocol in the clear.
+///
+/// The input is a fixed-point number with 4 fractional bits, which is saturated to the 8-bit
+/// range of the approximation. The output is in units of 1/256.
+///
+/// # Panics
+/// Never, the approximation is in the range of `u8` everywhere.
+#[must_use]
+pub fn sigmoid_in_the_clear(x: i64) -> u8 {
+    let x = x.clamp(-128, 127);
+    let y = match x {
+        -128..=-113 => 0,
+        -112..=-97 => 1,
+        -96..=-81 => 2 + ((x + 96) >> 3),
+        -80..=-65 => 4 + ((x + 80) >> 2),
+        -64..=-49 => 8 + ((x + 64) >> 1),
+        -48..=-33 => 16 + (x + 48),
+        -32..=-17 => 32 + ((x + 32) << 1),
+        -16..=15 => 64 + ((x + 16) << 2),
+        16..=31 => 192 + ((x - 16) << 1),
+        32..=47 => 224 + (x - 32),
+        48..=63 => 240 + ((x - 48) >> 1),
+        64..=79 => 248 + ((x - 64) >> 2),
+        80..=95 => 252 + ((x - 80) >> 3),
+        96..=111 => 254,
+        _ => 255,
+    };
+    u8::try_from(y).unwrap()
+}
+
+/// Computes the gradient of the logistic loss of the model `weights` in the clear, the way the
+/// WALR protocol does, without any MPC helpers involved.
+///
+/// The gradient is `X^T σ(Xw) - X^T y`, scaled by 256 and reduced modulo `2^32`. See
+/// [`walr_in_the_clear`] for the label term.
+#[must_use]
+pub fn walr_gradient_in_the_clear<const B: usize>(
+    input: &[TestWalrRecord<B>],
+    weights: &[i8; B],
+) -> Vec<u128> {
+    let mut prediction_term = vec![0_u128; B];
+    for record in input.iter().filter(|r| !r.is_trigger_report) {
+        let dot_product = zip(record.feature_vector, weights)
+            .map(|(feature, &weight)| i64::from(feature) * i64::from(weight))
+            .sum();
+        let prediction = u128::from(sigmoid_in_the_clear(dot_product));
+        zip(prediction_term.iter_mut(), record.feature_vector)
+            .for_each(|(sum, feature)| *sum += u128::from(feature) * prediction);
+    }
+
+    zip(prediction_term, walr_in_the_clear(input))
+        .map(|(prediction, label)| prediction.wrapping_sub(label << 8) % (1 << 32))
+        .collect()
+}
+
+/// Computes the label term `X^T y` of the WALR gradient in the clear, without any MPC helpers
+/// involved.
+///
+/// For every user, the most recent source event that precedes a trigger event receives
+/// attribution, and the feature vectors of all attributed source events are summed.
+/// If a source and a trigger event share a timestamp, the source event is considered to precede
+/// the trigger event.
+#[must_use]
+pub fn walr_in_the_clear<const B: usize>(input: &[TestWalrRecord<B>]) -> Vec<u128> {
+    let mut user_records = HashMap::<u64, Vec<&TestWalrRecord<B>>>::new();
+    for record in input {
+        user_records.entry(record.user_id).or_default().push(record);
+    }
+
+    let mut gradient = vec![0_u128; B];
+    for records in user_records.values_mut() {
+        records.sort_by_key(|r| (r.timestamp, r.is_trigger_report));
+        let mut seen_trigger = false;
+        if let Some(attributed) = records.iter().rev().find(|r| {
+            seen_trigger |= r.is_trigger_report;
+            seen_trigger && !r.is_trigger_report
+        }) {
+            zip(gradient.iter_mut(), attributed.feature_vector)
+                .for_each(|(sum, feature)| *sum += u128::from(feature));
+        }
+    }
+
+    gradient
+}
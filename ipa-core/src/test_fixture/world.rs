@@ -856,7 +856,7 @@ impl<S: ShardingScheme> ShardWorld<S> {
 
         let mut gateways = zip3_ref(&network.transports(), &transports).map(|(mpc, shard)| {
             Gateway::new(
-                QueryId,
+                QueryId::from(0),
                 config.gateway_config,
                 config.role_assignment().clone(),
                 Transport::clone_ref(mpc),
@@ -459,16 +459,13 @@ pub fn test_ipa_with_config(
             &config.per_user_credit_cap.to_string(),
         ]);
 
-    match config.with_dp {
-        0 => {
-            command.args(["--with-dp", &config.with_dp.to_string()]);
-        }
-        _ => {
-            command
-                .args(["--with-dp", &config.with_dp.to_string()])
-                .args(["--epsilon", &config.epsilon.to_string()]);
-        }
-    }
+    command
+        .args(["--dp-mechanism", &config.dp_mechanism.to_string()])
+        .args(["--oprf-padding", &config.oprf_padding.to_string()])
+        .args([
+            "--aggregation-padding",
+            &config.aggregation_padding.to_string(),
+        ]);
     command.stdin(Stdio::piped());
 
     if config.attribution_window_seconds.is_some() {
@@ -6,7 +6,10 @@ mod common;
 use std::num::NonZeroU32;
 
 use common::test_ipa_with_config;
-use ipa_core::{helpers::query::IpaQueryConfig, test_fixture::ipa::IpaSecurityModel};
+use ipa_core::{
+    helpers::query::{DpMechanism, IpaQueryConfig},
+    test_fixture::ipa::IpaSecurityModel,
+};
 
 fn test_compact_gate<I: TryInto<NonZeroU32>>(
     mode: IpaSecurityModel,
@@ -17,7 +20,7 @@ fn test_compact_gate<I: TryInto<NonZeroU32>>(
     let config = IpaQueryConfig {
         per_user_credit_cap,
         attribution_window_seconds: attribution_window_seconds.try_into().ok(),
-        with_dp: 0,
+        dp_mechanism: DpMechanism::NoDp,
         ..Default::default()
     };
 
@@ -54,7 +57,7 @@ fn compact_gate_cap_8_no_window_semi_honest_plaintext_input() {
 #[test]
 /// This test is turned off because of [`issue`].
 ///
-/// This test will hang without `relaxed-dp` feature turned out until it is fixed
+/// This test will hang with the default padding parameters until it is fixed
 /// [`issue`]: https://github.com/private-attribution/ipa/issues/1298
 #[ignore]
 fn compact_gate_cap_8_no_window_malicious_encrypted_input() {
@@ -64,7 +67,7 @@ fn compact_gate_cap_8_no_window_malicious_encrypted_input() {
 #[test]
 /// This test is turned off because of [`issue`].
 ///
-/// This test will hang without `relaxed-dp` feature turned out until it is fixed
+/// This test will hang with the default padding parameters until it is fixed
 /// [`issue`]: https://github.com/private-attribution/ipa/issues/1298
 #[ignore]
 fn compact_gate_cap_8_no_window_malicious_plaintext_input() {
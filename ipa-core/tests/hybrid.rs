@@ -22,7 +22,11 @@ use futures_util::{StreamExt, TryStreamExt};
 use ipa_core::{
     cli::playbook::HybridQueryResult,
     error::BoxError,
-    helpers::{query::HybridQueryParams, LengthDelimitedStream},
+    helpers::{
+        query::{DpMechanism, HybridQueryParams},
+        LengthDelimitedStream,
+    },
+    protocol::ipa_prf::oprf_padding::PaddingParameters,
 };
 use rand::thread_rng;
 use rand_core::RngCore;
@@ -38,11 +42,14 @@ fn test_hybrid() {
     const SHARDS: usize = 5;
     const MAX_CONVERSION_VALUE: usize = 5;
 
+    let relaxed_padding = PaddingParameters::relaxed();
     let config = HybridQueryParams {
         max_breakdown_key: 5,
-        with_dp: 0,
-        epsilon: 0.0,
+        dp_mechanism: DpMechanism::NoDp,
         plaintext_match_keys: false, // this shouldn't be necessary
+        oprf_padding: relaxed_padding.oprf_padding,
+        aggregation_padding: relaxed_padding.aggregation_padding,
+        ..Default::default()
     };
 
     let dir = TempDir::new_delete_on_drop();
@@ -107,16 +114,13 @@ fn test_hybrid() {
         .args(["--enc-input-file3".as_ref(), enc3.as_os_str()])
         .args(["--max-breakdown-key", &config.max_breakdown_key.to_string()]);
 
-    match config.with_dp {
-        0 => {
-            command.args(["--with-dp", &config.with_dp.to_string()]);
-        }
-        _ => {
-            command
-                .args(["--with-dp", &config.with_dp.to_string()])
-                .args(["--epsilon", &config.epsilon.to_string()]);
-        }
-    }
+    command
+        .args(["--dp-mechanism", &config.dp_mechanism.to_string()])
+        .args(["--oprf-padding", &config.oprf_padding.to_string()])
+        .args([
+            "--aggregation-padding",
+            &config.aggregation_padding.to_string(),
+        ]);
     command.stdin(Stdio::piped());
 
     let test_mpc = command.spawn().unwrap().terminate_on_drop();
@@ -153,12 +157,15 @@ fn test_hybrid_poll() {
     const SHARDS: usize = 5;
     const MAX_CONVERSION_VALUE: usize = 5;
 
+    let relaxed_padding = PaddingParameters::relaxed();
     let config = HybridQueryParams {
         max_breakdown_key: 5,
-        with_dp: 0,
-        epsilon: 0.0,
+        dp_mechanism: DpMechanism::NoDp,
         // only encrypted inputs are supported
         plaintext_match_keys: false,
+        oprf_padding: relaxed_padding.oprf_padding,
+        aggregation_padding: relaxed_padding.aggregation_padding,
+        ..Default::default()
     };
 
     let dir = TempDir::new_delete_on_drop();
@@ -249,16 +256,13 @@ fn test_hybrid_poll() {
         .args(["--url-file-list".into(), upload_metadata])
         .args(["--max-breakdown-key", &config.max_breakdown_key.to_string()]);
 
-    match config.with_dp {
-        0 => {
-            command.args(["--with-dp", &config.with_dp.to_string()]);
-        }
-        _ => {
-            command
-                .args(["--with-dp", &config.with_dp.to_string()])
-                .args(["--epsilon", &config.epsilon.to_string()]);
-        }
-    }
+    command
+        .args(["--dp-mechanism", &config.dp_mechanism.to_string()])
+        .args(["--oprf-padding", &config.oprf_padding.to_string()])
+        .args([
+            "--aggregation-padding",
+            &config.aggregation_padding.to_string(),
+        ]);
     command.stdin(Stdio::piped());
 
     let test_mpc = command.spawn().unwrap().terminate_on_drop();
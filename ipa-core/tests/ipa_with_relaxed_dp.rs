@@ -3,14 +3,21 @@ mod common;
 
 use std::num::NonZeroU32;
 
-use common::{test_ipa, test_ipa_with_config};
-use ipa_core::{helpers::query::IpaQueryConfig, test_fixture::ipa::IpaSecurityModel};
+use common::test_ipa_with_config;
+use ipa_core::{
+    helpers::query::{DpMechanism, IpaQueryConfig},
+    protocol::ipa_prf::oprf_padding::PaddingParameters,
+    test_fixture::ipa::IpaSecurityModel,
+};
 
 fn build_config() -> IpaQueryConfig {
+    let padding = PaddingParameters::relaxed();
     IpaQueryConfig {
         per_user_credit_cap: 8,
         attribution_window_seconds: NonZeroU32::new(0),
-        with_dp: 0,
+        dp_mechanism: DpMechanism::NoDp,
+        oprf_padding: padding.oprf_padding,
+        aggregation_padding: padding.aggregation_padding,
         ..Default::default()
     }
 }
@@ -44,5 +51,12 @@ fn relaxed_dp_malicious() {
 #[test]
 #[cfg(all(test, web_test))]
 fn relaxed_dp_https_malicious_ipa() {
-    test_ipa(IpaSecurityModel::Malicious, true, true);
+    let padding = PaddingParameters::relaxed();
+    let config = IpaQueryConfig {
+        oprf_padding: padding.oprf_padding,
+        aggregation_padding: padding.aggregation_padding,
+        ..Default::default()
+    };
+
+    test_ipa_with_config(IpaSecurityModel::Malicious, true, config, true);
 }
@@ -1,41 +1,92 @@
-use std::io;
+use std::{borrow::Cow, collections::BTreeMap, io, time::SystemTime};
 
-use ipa_metrics::MetricsStore;
-use opentelemetry::{metrics::MeterProvider, KeyValue};
-use opentelemetry_sdk::metrics::SdkMeterProvider;
+use ipa_metrics::{HistogramValue, MetricsStore, OwnedName};
+use opentelemetry::{
+    metrics::{MeterProvider, Result},
+    KeyValue,
+};
+use opentelemetry_sdk::{
+    metrics::{
+        data::{Histogram, HistogramDataPoint, Metric, ScopeMetrics, Temporality},
+        reader::MetricProducer,
+        SdkMeterProvider,
+    },
+    Scope,
+};
 use prometheus::{self, Encoder, TextEncoder};
 
+// TODO : We need to define a proper scope for the metrics
+const SCOPE: &str = "ipa-helper";
+
+/// Name of the label that identifies the partition metrics were recorded in.
+/// Helpers partition metrics by query, so this label holds the query id.
+#[cfg(feature = "partitions")]
+pub const PARTITION_LABEL: &str = "query_id";
+
 pub trait PrometheusMetricsExporter {
-    fn export<W: io::Write>(&mut self, w: &mut W);
+    /// Writes all metrics to `w` in Prometheus text format.
+    fn export<W: io::Write>(&mut self, w: &mut W) {
+        self.export_with_labels(w, &[]);
+    }
+
+    /// Same as [`Self::export`], but attaches `labels` to every exported metric.
+    /// This is meant for labels that are the same for the whole process, like
+    /// the shard a helper runs.
+    fn export_with_labels<W: io::Write>(&mut self, w: &mut W, labels: &[(&'static str, String)]);
 }
 
 impl PrometheusMetricsExporter for MetricsStore {
-    fn export<W: io::Write>(&mut self, w: &mut W) {
+    fn export_with_labels<W: io::Write>(&mut self, w: &mut W, labels: &[(&'static str, String)]) {
+        let labels = labels
+            .iter()
+            .map(|(name, val)| KeyValue::new(*name, val.clone()))
+            .collect::<Vec<_>>();
+
+        // Every partition is exported with its own label, so metrics emitted by
+        // different queries don't get mixed together.
+        #[cfg(feature = "partitions")]
+        let stores = self
+            .partitions()
+            .map(|(partition, store)| {
+                let mut labels = labels.clone();
+                labels.extend(partition.map(|p| KeyValue::new(PARTITION_LABEL, p.to_string())));
+                (labels, store)
+            })
+            .collect::<Vec<_>>();
+        #[cfg(not(feature = "partitions"))]
+        let stores = vec![(labels, &*self)];
+
         // Setup prometheus registry and open-telemetry exporter
         let registry = prometheus::Registry::new();
 
         let exporter = opentelemetry_prometheus::exporter()
             .with_registry(registry.clone())
+            .with_producer(HistogramProducer::new(stores.iter().flat_map(
+                |(labels, store)| {
+                    store
+                        .histograms()
+                        .map(move |(name, value)| (name, attributes(name, labels), value))
+                },
+            )))
             .build()
             .unwrap();
 
         let meter_provider = SdkMeterProvider::builder().with_reader(exporter).build();
 
         // Convert the snapshot to otel struct
-        // TODO : We need to define a proper scope for the metrics
-        let meter = meter_provider.meter("ipa-helper");
-
-        let counters = self.counters();
-        counters.for_each(|(counter_name, counter_value)| {
-            let otlp_counter = meter.u64_counter(counter_name.key).init();
+        let meter = meter_provider.meter(SCOPE);
 
-            let attributes: Vec<KeyValue> = counter_name
-                .labels()
-                .map(|l| KeyValue::new(l.name, l.val.to_string()))
-                .collect();
+        for (labels, store) in &stores {
+            store.counters().for_each(|(counter_name, counter_value)| {
+                let otlp_counter = meter.u64_counter(counter_name.key).init();
+                otlp_counter.add(counter_value, &attributes(counter_name, labels)[..]);
+            });
 
-            otlp_counter.add(counter_value, &attributes[..]);
-        });
+            store.gauges().for_each(|(gauge_name, gauge_value)| {
+                let otlp_gauge = meter.i64_gauge(gauge_name.key).init();
+                otlp_gauge.record(gauge_value, &attributes(gauge_name, labels)[..]);
+            });
+        }
 
         let encoder = TextEncoder::new();
         let metric_families = registry.gather();
@@ -43,12 +94,79 @@ impl PrometheusMetricsExporter for MetricsStore {
     }
 }
 
+fn attributes(name: &OwnedName, extra: &[KeyValue]) -> Vec<KeyValue> {
+    name.labels()
+        .map(|l| KeyValue::new(l.name, l.val.to_string()))
+        .chain(extra.iter().cloned())
+        .collect()
+}
+
+/// Histograms in [`MetricsStore`] are already aggregated, so they can't be recorded
+/// through OpenTelemetry histogram instruments. Instead, they are handed to the
+/// exporter as histogram data points.
+#[derive(Debug)]
+struct HistogramProducer {
+    histograms: BTreeMap<&'static str, Vec<HistogramDataPoint<f64>>>,
+}
+
+impl HistogramProducer {
+    fn new<'a, I>(histograms: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a OwnedName, Vec<KeyValue>, &'a HistogramValue)>,
+    {
+        let now = SystemTime::now();
+        let mut data_points = BTreeMap::<_, Vec<_>>::new();
+        for (name, attributes, value) in histograms {
+            data_points
+                .entry(name.key)
+                .or_default()
+                .push(HistogramDataPoint {
+                    attributes,
+                    start_time: now,
+                    time: now,
+                    count: value.count(),
+                    bounds: value.bounds().to_vec(),
+                    bucket_counts: value.bucket_counts().to_vec(),
+                    min: None,
+                    max: None,
+                    sum: value.sum(),
+                    exemplars: Vec::new(),
+                });
+        }
+
+        Self {
+            histograms: data_points,
+        }
+    }
+}
+
+impl MetricProducer for HistogramProducer {
+    fn produce(&self) -> Result<ScopeMetrics> {
+        Ok(ScopeMetrics {
+            scope: Scope::builder(SCOPE).build(),
+            metrics: self
+                .histograms
+                .iter()
+                .map(|(name, data_points)| Metric {
+                    name: Cow::Borrowed(name),
+                    description: Cow::Borrowed(""),
+                    unit: Cow::Borrowed(""),
+                    data: Box::new(Histogram {
+                        data_points: data_points.clone(),
+                        temporality: Temporality::Cumulative,
+                    }),
+                })
+                .collect(),
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
 
     use std::thread;
 
-    use ipa_metrics::{counter, install_new_thread, MetricChannelType};
+    use ipa_metrics::{counter, gauge, histogram, install_new_thread, MetricChannelType};
 
     use super::PrometheusMetricsExporter;
 
@@ -76,6 +194,74 @@ bar_total{otel_scope_name=\"ipa-helper\"} 1
 baz_total{otel_scope_name=\"ipa-helper\"} 4
 # HELP target_info Target metadata
 # TYPE target_info gauge
+target_info{service_name=\"unknown_service\",telemetry_sdk_language=\"rust\",telemetry_sdk_name=\"opentelemetry\",telemetry_sdk_version=\"0.24.1\"} 1\n";
+        let result = String::from_utf8(buff).unwrap();
+        assert_eq!(result, expected_result);
+    }
+
+    #[test]
+    fn export_gauges_and_histograms() {
+        let (producer, controller, _) = install_new_thread(MetricChannelType::Rendezvous).unwrap();
+
+        thread::spawn(move || {
+            producer.install();
+            gauge!("in_flight", 5);
+            gauge!("in_flight", -2);
+            histogram!("latency", &[0.5, 1.0], 0.25, "step" => &1_u32);
+            histogram!("latency", &[0.5, 1.0], 2.0, "step" => &1_u32);
+            let _ = producer.drop_handle();
+        })
+        .join()
+        .unwrap();
+
+        let mut store = controller.snapshot().unwrap();
+
+        let mut buff = Vec::new();
+        store.export(&mut buff);
+
+        let expected_result = "# TYPE in_flight gauge
+in_flight{otel_scope_name=\"ipa-helper\"} 3
+# TYPE latency histogram
+latency_bucket{step=\"1\",otel_scope_name=\"ipa-helper\",le=\"0.5\"} 1
+latency_bucket{step=\"1\",otel_scope_name=\"ipa-helper\",le=\"1\"} 1
+latency_bucket{step=\"1\",otel_scope_name=\"ipa-helper\",le=\"+Inf\"} 2
+latency_sum{step=\"1\",otel_scope_name=\"ipa-helper\"} 2.25
+latency_count{step=\"1\",otel_scope_name=\"ipa-helper\"} 2
+# HELP target_info Target metadata
+# TYPE target_info gauge
+target_info{service_name=\"unknown_service\",telemetry_sdk_language=\"rust\",telemetry_sdk_name=\"opentelemetry\",telemetry_sdk_version=\"0.24.1\"} 1\n";
+        let result = String::from_utf8(buff).unwrap();
+        assert_eq!(result, expected_result);
+    }
+
+    #[test]
+    #[cfg(feature = "partitions")]
+    fn export_partitions_with_labels() {
+        use ipa_metrics::CurrentThreadPartitionContext;
+
+        let (producer, controller, _) = install_new_thread(MetricChannelType::Rendezvous).unwrap();
+
+        thread::spawn(move || {
+            producer.install();
+            counter!("records", 1);
+            CurrentThreadPartitionContext::set(42);
+            counter!("records", 2);
+            CurrentThreadPartitionContext::toggle(None);
+            let _ = producer.drop_handle();
+        })
+        .join()
+        .unwrap();
+
+        let mut store = controller.snapshot().unwrap();
+
+        let mut buff = Vec::new();
+        store.export_with_labels(&mut buff, &[("shard", "1".to_string())]);
+
+        let expected_result = "# TYPE records_total counter
+records_total{shard=\"1\",otel_scope_name=\"ipa-helper\"} 1
+records_total{query_id=\"42\",shard=\"1\",otel_scope_name=\"ipa-helper\"} 2
+# HELP target_info Target metadata
+# TYPE target_info gauge
 target_info{service_name=\"unknown_service\",telemetry_sdk_language=\"rust\",telemetry_sdk_name=\"opentelemetry\",telemetry_sdk_version=\"0.24.1\"} 1\n";
         let result = String::from_utf8(buff).unwrap();
         assert_eq!(result, expected_result);
@@ -1,3 +1,5 @@
 mod exporter;
 
 pub use exporter::PrometheusMetricsExporter;
+#[cfg(feature = "partitions")]
+pub use exporter::PARTITION_LABEL;
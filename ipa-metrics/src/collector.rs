@@ -80,6 +80,14 @@ impl MetricsCollector {
                     Ok(Command::Status(tx)) => {
                         tx.send(state).unwrap();
                     }
+                    #[cfg(feature = "partitions")]
+                    Ok(Command::RemovePartitions(partitions, tx)) => {
+                        tracing::trace!("Removing partitions {partitions:?}");
+                        for partition in partitions {
+                            self.local_store.remove_partition(partition);
+                        }
+                        tx.send(()).unwrap();
+                    }
                     Err(e) => {
                         tracing::debug!("Metric controller is disconnected: {e}");
                         break;
@@ -200,4 +208,36 @@ mod tests {
 
         assert_eq!(8, counter);
     }
+
+    #[test]
+    #[cfg(feature = "partitions")]
+    fn remove_partitions() {
+        use crate::CurrentThreadPartitionContext;
+
+        let (producer, controller, _handle) =
+            install_new_thread(MetricChannelType::Rendezvous).unwrap();
+        let snapshot = thread::scope(move |s| {
+            let s = s.metered(producer);
+            s.spawn(|| {
+                CurrentThreadPartitionContext::set(1);
+                counter!("foo", 3);
+            })
+            .join()
+            .unwrap();
+            s.spawn(|| {
+                CurrentThreadPartitionContext::set(2);
+                counter!("foo", 5);
+            })
+            .join()
+            .unwrap();
+            controller.remove_partitions(vec![1]).unwrap();
+            controller.snapshot().unwrap()
+        });
+
+        assert_eq!(None, snapshot.with_partition(1, |_| ()));
+        assert_eq!(
+            Some(5),
+            snapshot.with_partition(2, |store| store.counter_val(counter!("foo")))
+        );
+    }
 }
@@ -19,6 +19,33 @@ macro_rules! counter {
     }};
 }
 
+/// Adjusts the gauge by the given signed value, i.e. `gauge!("in_flight", -1)` decrements it.
+/// Called without a value, returns the metric name.
+#[macro_export]
+macro_rules! gauge {
+    ($metric:expr, $val:expr $(, $l:expr => $v:expr)*) => {{
+        let name = $crate::metric_name!($metric $(, $l => $v)*);
+        $crate::MetricsCurrentThreadContext::store_mut(|store| store.gauge(&name).inc($val))
+    }};
+    ($metric:expr $(, $l:expr => $v:expr)*) => {{
+        $crate::metric_name!($metric $(, $l => $v)*)
+    }};
+}
+
+/// Records a value in the histogram with the given buckets, i.e.
+/// `histogram!("latency", &[0.1, 1.0], 0.3)`. Called without buckets and value,
+/// returns the metric name.
+#[macro_export]
+macro_rules! histogram {
+    ($metric:expr, $buckets:expr, $val:expr $(, $l:expr => $v:expr)*) => {{
+        let name = $crate::metric_name!($metric $(, $l => $v)*);
+        $crate::MetricsCurrentThreadContext::store_mut(|store| store.histogram(&name, $buckets).record($val))
+    }};
+    ($metric:expr $(, $l:expr => $v:expr)*) => {{
+        $crate::metric_name!($metric $(, $l => $v)*)
+    }};
+}
+
 /// Provides access to the metric store associated with the current thread.
 /// If there is no store associated with the current thread, it will create a new one.
 pub struct CurrentThreadContext;
@@ -183,4 +210,21 @@ mod tests {
         assert!(CurrentThreadContext::is_connected());
         assert_eq!(1, rx.recv().unwrap().counter_val(counter!("foo")));
     }
+
+    #[test]
+    fn flush_gauges_and_histograms() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut ctx = MetricsContext::new();
+        ctx.init(tx);
+        ctx.store_mut().gauge(gauge!("foo")).inc(3);
+        ctx.store_mut()
+            .histogram(histogram!("bar"), &[1.0])
+            .record(2.0);
+        ctx.flush();
+
+        let store = rx.recv().unwrap();
+        assert_eq!(3, store.gauge_val(gauge!("foo")));
+        assert_eq!(1, store.histogram_val(histogram!("bar")).unwrap().count());
+        assert!(ctx.store().is_empty());
+    }
 }
@@ -18,6 +18,8 @@ pub enum Command {
     Snapshot(Sender<MetricsStore>),
     Stop(Sender<()>),
     Status(Sender<Status>),
+    #[cfg(feature = "partitions")]
+    RemovePartitions(Vec<crate::MetricPartition>, Sender<()>),
 }
 
 /// Handle to communicate with centralized metrics collection system.
@@ -95,4 +97,28 @@ impl Controller {
             .map_err(|e| format!("An error occurred while requesting status: {e}"))?;
         rx.recv().map_err(|e| format!("Disconnected channel: {e}"))
     }
+
+    /// Request the collector to drop all metrics recorded in the given partitions.
+    /// Blocks current thread until they are removed. Metrics that are sent to the
+    /// collector afterwards create the partitions again.
+    ///
+    /// ## Errors
+    /// If collector thread is disconnected or an error occurs while sending
+    /// or receiving data from the collector thread.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use ipa_metrics::{install_new_thread, MetricChannelType};
+    ///
+    /// let (_, controller, _handle) = install_new_thread(MetricChannelType::Unbounded).unwrap();
+    /// controller.remove_partitions(vec![1, 2]).unwrap();
+    /// ```
+    #[cfg(feature = "partitions")]
+    pub fn remove_partitions(&self, partitions: Vec<crate::MetricPartition>) -> Result<(), String> {
+        let (tx, rx) = crossbeam_channel::bounded(0);
+        self.tx
+            .send(Command::RemovePartitions(partitions, tx))
+            .map_err(|e| format!("An error occurred while requesting partition removal: {e}"))?;
+        rx.recv().map_err(|e| format!("Disconnected channel: {e}"))
+    }
 }
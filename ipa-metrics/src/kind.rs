@@ -1,5 +1,172 @@
 //! Different metric types supported by this crate.
-//! Currently, only counters are supported.
+//! Counters, gauges and histograms are supported.
 
 /// Counters are simple 8 byte values.
 pub type CounterValue = u64;
+
+/// Upper bounds of histogram buckets, in increasing order. Every histogram has
+/// one more bucket than the number of bounds, for values that exceed the last bound.
+pub type HistogramBuckets = &'static [f64];
+
+/// Gauges track a value that can go up and down, like the number of records in flight.
+///
+/// Metric stores are thread-local and merged by the collector, so gauges are
+/// tracked as deltas: increments from different threads add up. Setting
+/// the gauge overrides all the changes made before the store was flushed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GaugeValue {
+    value: i64,
+    /// Whether the value is absolute rather than a delta.
+    is_set: bool,
+}
+
+impl GaugeValue {
+    #[must_use]
+    pub fn get(&self) -> i64 {
+        self.value
+    }
+
+    pub(crate) fn inc(&mut self, delta: i64) {
+        self.value += delta;
+    }
+
+    pub(crate) fn set(&mut self, value: i64) {
+        self.value = value;
+        self.is_set = true;
+    }
+
+    /// `other` is expected to contain the changes made after `self`.
+    pub(crate) fn merge(&mut self, other: Self) {
+        if other.is_set {
+            *self = other;
+        } else {
+            self.value += other.value;
+        }
+    }
+}
+
+/// Distribution of recorded values across a fixed set of buckets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistogramValue {
+    bounds: HistogramBuckets,
+    /// Number of values in each bucket. Bucket `i` holds values in
+    /// `(bounds[i - 1], bounds[i]]`, the last one holds everything above the last bound.
+    bucket_counts: Box<[u64]>,
+    sum: f64,
+    count: u64,
+}
+
+impl HistogramValue {
+    pub(crate) fn new(bounds: HistogramBuckets) -> Self {
+        debug_assert!(
+            bounds.windows(2).all(|w| w[0] < w[1]),
+            "Histogram bucket bounds must be sorted: {bounds:?}"
+        );
+        Self {
+            bounds,
+            bucket_counts: vec![0; bounds.len() + 1].into_boxed_slice(),
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub(crate) fn record(&mut self, value: f64) {
+        let bucket = self.bounds.partition_point(|&bound| bound < value);
+        self.bucket_counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Adds all values recorded in `other` to this histogram. Histograms with
+    /// different buckets can't be merged, `other` is dropped if that is the case.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        if self.bounds != other.bounds {
+            tracing::warn!(
+                "Histogram buckets mismatch: {:?} != {:?}. Values are dropped",
+                self.bounds,
+                other.bounds
+            );
+            return;
+        }
+        for (count, other) in self
+            .bucket_counts
+            .iter_mut()
+            .zip(other.bucket_counts.iter())
+        {
+            *count += other;
+        }
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+
+    #[must_use]
+    pub fn bounds(&self) -> &[f64] {
+        self.bounds
+    }
+
+    #[must_use]
+    pub fn bucket_counts(&self) -> &[u64] {
+        &self.bucket_counts
+    }
+
+    #[must_use]
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kind::{GaugeValue, HistogramValue};
+
+    #[test]
+    fn gauge_merge() {
+        let mut gauge = GaugeValue::default();
+        gauge.inc(5);
+
+        let mut delta = GaugeValue::default();
+        delta.inc(-2);
+        gauge.merge(delta);
+        assert_eq!(3, gauge.get());
+
+        let mut set = GaugeValue::default();
+        set.inc(4);
+        set.set(10);
+        gauge.merge(set);
+        assert_eq!(10, gauge.get());
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = HistogramValue::new(&[1.0, 5.0]);
+        for v in [0.5, 1.0, 1.5, 5.0, 7.0, 100.0] {
+            histogram.record(v);
+        }
+
+        assert_eq!(&[2, 2, 2], histogram.bucket_counts());
+        assert_eq!(6, histogram.count());
+        assert!((histogram.sum() - 115.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn histogram_merge() {
+        let mut h1 = HistogramValue::new(&[1.0]);
+        let mut h2 = HistogramValue::new(&[1.0]);
+        h1.record(0.5);
+        h2.record(2.0);
+        h2.record(3.0);
+
+        h1.merge(&h2);
+        assert_eq!(&[1, 2], h1.bucket_counts());
+        assert_eq!(3, h1.count());
+
+        // buckets don't match
+        h1.merge(&HistogramValue::new(&[2.0]));
+        assert_eq!(3, h1.count());
+    }
+}
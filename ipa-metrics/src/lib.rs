@@ -22,6 +22,7 @@ pub use controller::{
     Status as ControllerStatus,
 };
 pub use key::{MetricName, OwnedName, UniqueElements};
+pub use kind::{CounterValue, GaugeValue, HistogramBuckets, HistogramValue};
 pub use label::{label_hasher, Label, LabelValue};
 #[cfg(feature = "partitions")]
 pub use partitioned::{
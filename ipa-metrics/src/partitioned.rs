@@ -14,15 +14,15 @@
 //! Because partitioned stores carry additional cost of extra lookup (partition -> store),
 //! it is disabled by default and requires explicit opt-in via `partitioning` feature.
 
-use std::{borrow::Borrow, cell::Cell};
+use std::{borrow::Borrow, cell::Cell, iter};
 
 use hashbrown::hash_map::Entry;
 use rustc_hash::FxBuildHasher;
 
 use crate::{
     key::OwnedMetricName,
-    kind::CounterValue,
-    store::{CounterHandle, Store},
+    kind::{CounterValue, HistogramBuckets, HistogramValue},
+    store::{CounterHandle, GaugeHandle, HistogramHandle, Store},
     MetricName,
 };
 
@@ -121,14 +121,77 @@ impl PartitionedStore {
         self.get_mut(CurrentThreadContext::get()).counter(key)
     }
 
-    pub fn counters(&self) -> impl Iterator<Item = (&OwnedMetricName, CounterValue)> {
+    pub fn gauge_val<'a, const LABELS: usize, B: Borrow<MetricName<'a, LABELS>>>(
+        &'a self,
+        key: B,
+    ) -> i64 {
+        let name = key.borrow();
         if let Some(partition) = CurrentThreadContext::get() {
-            return match self.inner.get(&partition) {
-                Some(store) => store.counters(),
-                None => self.default_store.counters(),
-            };
+            self.inner
+                .get(&partition)
+                .map(|store| store.gauge_val(name))
+                .unwrap_or_default()
+        } else {
+            self.default_store.gauge_val(name)
         }
-        self.default_store.counters()
+    }
+
+    pub fn gauge<'a, const LABELS: usize, B: Borrow<MetricName<'a, LABELS>>>(
+        &'a mut self,
+        key: B,
+    ) -> GaugeHandle<'a, LABELS> {
+        self.get_mut(CurrentThreadContext::get()).gauge(key)
+    }
+
+    pub fn histogram_val<'a, const LABELS: usize, B: Borrow<MetricName<'a, LABELS>>>(
+        &'a self,
+        key: B,
+    ) -> Option<HistogramValue> {
+        let name = key.borrow();
+        if let Some(partition) = CurrentThreadContext::get() {
+            self.inner
+                .get(&partition)
+                .and_then(|store| store.histogram_val(name))
+        } else {
+            self.default_store.histogram_val(name)
+        }
+    }
+
+    pub fn histogram<'a, const LABELS: usize, B: Borrow<MetricName<'a, LABELS>>>(
+        &'a mut self,
+        key: B,
+        buckets: HistogramBuckets,
+    ) -> HistogramHandle<'a, LABELS> {
+        self.get_mut(CurrentThreadContext::get())
+            .histogram(key, buckets)
+    }
+
+    pub fn counters(&self) -> impl Iterator<Item = (&OwnedMetricName, CounterValue)> {
+        self.current().counters()
+    }
+
+    pub fn gauges(&self) -> impl Iterator<Item = (&OwnedMetricName, i64)> {
+        self.current().gauges()
+    }
+
+    pub fn histograms(&self) -> impl Iterator<Item = (&OwnedMetricName, &HistogramValue)> {
+        self.current().histograms()
+    }
+
+    /// Iterates over all stores, including the default one that holds metrics
+    /// emitted outside of any partition.
+    pub fn partitions(&self) -> impl Iterator<Item = (Option<Partition>, &Store)> {
+        iter::once((None, &self.default_store)).chain(
+            self.inner
+                .iter()
+                .map(|(partition, store)| (Some(*partition), store)),
+        )
+    }
+
+    /// Drops all metrics recorded in the given partition. Returns `false` if there
+    /// were none.
+    pub fn remove_partition(&mut self, partition: Partition) -> bool {
+        self.inner.remove(&partition).is_some()
     }
 
     #[must_use]
@@ -151,6 +214,14 @@ impl PartitionedStore {
         f(store)
     }
 
+    /// Store for the partition set on the current thread, or the default store if
+    /// there is no partition set or nothing has been recorded for it.
+    fn current(&self) -> &Store {
+        CurrentThreadContext::get()
+            .and_then(|partition| self.inner.get(&partition))
+            .unwrap_or(&self.default_store)
+    }
+
     fn get_mut(&mut self, partition: Option<Partition>) -> &mut Store {
         if let Some(v) = partition {
             match self.inner.entry(v) {
@@ -166,7 +237,7 @@ impl PartitionedStore {
 #[cfg(test)]
 mod tests {
     use crate::{
-        counter, metric_name,
+        counter, gauge, histogram, metric_name,
         partitioned::{CurrentThreadContext, PartitionedStore},
     };
 
@@ -261,4 +332,48 @@ mod tests {
         );
         assert_eq!(6, store1.counter_val(counter!("foo")));
     }
+
+    #[test]
+    fn gauges_and_histograms() {
+        let mut store = PartitionedStore::new();
+        store.gauge(gauge!("foo")).inc(1);
+        store.histogram(histogram!("bar"), &[1.0]).record(0.5);
+
+        CurrentThreadContext::set(4);
+        store.gauge(gauge!("foo")).inc(2);
+        assert_eq!(2, store.gauge_val(gauge!("foo")));
+        assert!(store.histogram_val(histogram!("bar")).is_none());
+        assert_eq!(0, store.histograms().count());
+
+        CurrentThreadContext::toggle(None);
+        assert_eq!(1, store.gauge_val(gauge!("foo")));
+        assert_eq!(1, store.histogram_val(histogram!("bar")).unwrap().count());
+        assert_eq!(1, store.gauges().count());
+    }
+
+    #[test]
+    fn partitions() {
+        let mut store = PartitionedStore::new();
+        store.counter(counter!("foo")).inc(1);
+        store.with_partition_mut(3, |store| store.counter(counter!("foo")).inc(3));
+
+        let mut partitions = store
+            .partitions()
+            .map(|(partition, store)| (partition, store.counter_val(counter!("foo"))))
+            .collect::<Vec<_>>();
+        partitions.sort_unstable();
+        assert_eq!(vec![(None, 1), (Some(3), 3)], partitions);
+    }
+
+    #[test]
+    fn remove_partition() {
+        let mut store = PartitionedStore::new();
+        store.counter(counter!("foo")).inc(1);
+        store.with_partition_mut(3, |store| store.counter(counter!("foo")).inc(3));
+
+        assert!(store.remove_partition(3));
+        assert!(!store.remove_partition(3));
+        assert_eq!(None, store.with_partition(3, |_| ()));
+        assert_eq!(1, store.counter_val(counter!("foo")));
+    }
 }
@@ -3,15 +3,23 @@ use std::{borrow::Borrow, hash::BuildHasher};
 use hashbrown::hash_map::RawEntryMut;
 use rustc_hash::FxBuildHasher;
 
-use crate::{key::OwnedMetricName, kind::CounterValue, MetricName};
+use crate::{
+    key::OwnedMetricName,
+    kind::{CounterValue, GaugeValue, HistogramBuckets, HistogramValue},
+    MetricName,
+};
 
-/// A basic store. Currently only supports counters.
+type MetricMap<V> = hashbrown::HashMap<OwnedMetricName, V, FxBuildHasher>;
+
+/// A basic store that supports counters, gauges and histograms.
 /// Counters and other metrics are stored to optimize writes. That means, one lookup
 /// per write. The cost of assembling the total count across all dimensions is absorbed
 /// by readers
 #[derive(Clone, Debug)]
 pub struct Store {
-    counters: hashbrown::HashMap<OwnedMetricName, CounterValue, FxBuildHasher>,
+    counters: MetricMap<CounterValue>,
+    gauges: MetricMap<GaugeValue>,
+    histograms: MetricMap<HistogramValue>,
 }
 
 impl Default for Store {
@@ -25,44 +33,61 @@ impl Store {
     pub const fn new() -> Self {
         Self {
             counters: hashbrown::HashMap::with_hasher(FxBuildHasher),
+            gauges: hashbrown::HashMap::with_hasher(FxBuildHasher),
+            histograms: hashbrown::HashMap::with_hasher(FxBuildHasher),
         }
     }
 
+    /// Merges `other` into this store. `other` is expected to contain the values
+    /// recorded after the values in this store, which matters for gauges that are set.
     pub fn merge(&mut self, other: Self) {
-        for (k, v) in other.counters {
-            let hash_builder = self.counters.hasher();
-            let hash = hash_builder.hash_one(&k);
-            *self
-                .counters
-                .raw_entry_mut()
-                .from_hash(hash, |other| other.eq(&k))
-                .or_insert(k, 0)
-                .1 += v;
-        }
+        merge_map(&mut self.counters, other.counters, |this, other| {
+            *this += other;
+        });
+        merge_map(&mut self.gauges, other.gauges, GaugeValue::merge);
+        merge_map(&mut self.histograms, other.histograms, |this, other| {
+            this.merge(&other);
+        });
     }
 
     pub fn counter<'a, const LABELS: usize, B: Borrow<MetricName<'a, LABELS>>>(
         &'a mut self,
         key: B,
     ) -> CounterHandle<'a, LABELS> {
-        let key = key.borrow();
-        let hash_builder = self.counters.hasher();
-        let hash = hash_builder.hash_one(key);
-        let entry = self
-            .counters
-            .raw_entry_mut()
-            .from_hash(hash, |key_found| key_found.eq(key));
-        match entry {
-            RawEntryMut::Occupied(slot) => CounterHandle {
-                val: slot.into_mut(),
-            },
-            RawEntryMut::Vacant(slot) => {
-                let (_, val) = slot.insert_hashed_nocheck(hash, key.to_owned(), Default::default());
-                CounterHandle { val }
-            }
+        CounterHandle {
+            val: get_or_insert(&mut self.counters, key.borrow(), Default::default),
+        }
+    }
+
+    /// Returns a handle to the gauge with the specified name.
+    pub fn gauge<'a, const LABELS: usize, B: Borrow<MetricName<'a, LABELS>>>(
+        &'a mut self,
+        key: B,
+    ) -> GaugeHandle<'a, LABELS> {
+        GaugeHandle {
+            val: get_or_insert(&mut self.gauges, key.borrow(), Default::default),
         }
     }
 
+    /// Returns a handle to the histogram with the specified name. If the histogram
+    /// does not exist yet, it is created with the given `buckets`.
+    pub fn histogram<'a, const LABELS: usize, B: Borrow<MetricName<'a, LABELS>>>(
+        &'a mut self,
+        key: B,
+        buckets: HistogramBuckets,
+    ) -> HistogramHandle<'a, LABELS> {
+        let val = get_or_insert(&mut self.histograms, key.borrow(), || {
+            HistogramValue::new(buckets)
+        });
+        debug_assert_eq!(
+            buckets,
+            val.bounds(),
+            "Histogram is already registered with different buckets"
+        );
+
+        HistogramHandle { val }
+    }
+
     /// Returns the value for the specified metric, limited by any specified dimensions,
     /// but not by any unspecified dimensions. If metric foo has dimensions dim1 and dim2,
     /// a query for (foo, dim1 = 1) will sum the counter values having dim1 = 1
@@ -85,9 +110,48 @@ impl Store {
             .sum()
     }
 
+    /// Same as [`Self::counter_val`], but for gauges. Values of gauges across
+    /// unspecified dimensions are summed up.
+    pub fn gauge_val<'a, const LABELS: usize, B: Borrow<MetricName<'a, LABELS>>>(
+        &'a self,
+        key: B,
+    ) -> i64 {
+        let key = key.borrow();
+
+        self.gauges
+            .iter()
+            .filter(|(gauge, _)| gauge.partial_match(key))
+            .map(|(_, val)| val.get())
+            .sum()
+    }
+
+    /// Same as [`Self::counter_val`], but for histograms. Histograms across
+    /// unspecified dimensions are merged together. Returns `None` if there are
+    /// no histograms matching `key`.
+    pub fn histogram_val<'a, const LABELS: usize, B: Borrow<MetricName<'a, LABELS>>>(
+        &'a self,
+        key: B,
+    ) -> Option<HistogramValue> {
+        let key = key.borrow();
+
+        self.histograms
+            .iter()
+            .filter(|(histogram, _)| histogram.partial_match(key))
+            .map(|(_, val)| val)
+            .fold(None, |acc: Option<HistogramValue>, val| {
+                Some(match acc {
+                    Some(mut acc) => {
+                        acc.merge(val);
+                        acc
+                    }
+                    None => val.clone(),
+                })
+            })
+    }
+
     #[must_use]
     pub fn len(&self) -> usize {
-        self.counters.len()
+        self.counters.len() + self.gauges.len() + self.histograms.len()
     }
 
     #[must_use]
@@ -101,6 +165,47 @@ impl Store {
     pub fn counters(&self) -> impl Iterator<Item = (&OwnedMetricName, CounterValue)> {
         self.counters.iter().map(|(key, value)| (key, *value))
     }
+
+    /// Returns an iterator over the gauges in the store.
+    ///
+    /// The iterator item is a tuple of the metric name and the gauge value.
+    pub fn gauges(&self) -> impl Iterator<Item = (&OwnedMetricName, i64)> {
+        self.gauges.iter().map(|(key, value)| (key, value.get()))
+    }
+
+    /// Returns an iterator over the histograms in the store.
+    pub fn histograms(&self) -> impl Iterator<Item = (&OwnedMetricName, &HistogramValue)> {
+        self.histograms.iter()
+    }
+}
+
+fn get_or_insert<'a, V, const LABELS: usize>(
+    map: &'a mut MetricMap<V>,
+    key: &MetricName<'_, LABELS>,
+    init: impl FnOnce() -> V,
+) -> &'a mut V {
+    let hash_builder = map.hasher();
+    let hash = hash_builder.hash_one(key);
+    let entry = map
+        .raw_entry_mut()
+        .from_hash(hash, |key_found| key_found.eq(key));
+    match entry {
+        RawEntryMut::Occupied(slot) => slot.into_mut(),
+        RawEntryMut::Vacant(slot) => slot.insert_hashed_nocheck(hash, key.to_owned(), init()).1,
+    }
+}
+
+fn merge_map<V>(this: &mut MetricMap<V>, other: MetricMap<V>, merge: impl Fn(&mut V, V)) {
+    for (k, v) in other {
+        let hash_builder = this.hasher();
+        let hash = hash_builder.hash_one(&k);
+        match this.raw_entry_mut().from_hash(hash, |other| other.eq(&k)) {
+            RawEntryMut::Occupied(mut slot) => merge(slot.get_mut(), v),
+            RawEntryMut::Vacant(slot) => {
+                slot.insert_hashed_nocheck(hash, k, v);
+            }
+        }
+    }
 }
 
 pub struct CounterHandle<'a, const LABELS: usize> {
@@ -117,11 +222,47 @@ impl<const LABELS: usize> CounterHandle<'_, LABELS> {
     }
 }
 
+pub struct GaugeHandle<'a, const LABELS: usize> {
+    val: &'a mut GaugeValue,
+}
+
+impl<const LABELS: usize> GaugeHandle<'_, LABELS> {
+    pub fn inc(&mut self, inc: i64) {
+        self.val.inc(inc);
+    }
+
+    pub fn dec(&mut self, dec: i64) {
+        self.val.inc(-dec);
+    }
+
+    pub fn set(&mut self, val: i64) {
+        self.val.set(val);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.val.get()
+    }
+}
+
+pub struct HistogramHandle<'a, const LABELS: usize> {
+    val: &'a mut HistogramValue,
+}
+
+impl<const LABELS: usize> HistogramHandle<'_, LABELS> {
+    pub fn record(&mut self, val: f64) {
+        self.val.record(val);
+    }
+
+    pub fn get(&self) -> &HistogramValue {
+        self.val
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::hash::{DefaultHasher, Hash, Hasher};
 
-    use crate::{counter, metric_name, store::Store, LabelValue};
+    use crate::{counter, gauge, histogram, metric_name, store::Store, LabelValue};
 
     impl LabelValue for &'static str {
         fn hash(&self) -> u64 {
@@ -240,4 +381,70 @@ mod tests {
 
         assert_eq!((4, Some(4)), store.counters().size_hint());
     }
+
+    #[test]
+    fn gauge() {
+        let mut store = Store::default();
+        store.gauge(gauge!("foo", "h1" => &1)).inc(3);
+        store.gauge(gauge!("foo", "h1" => &2)).inc(2);
+        store.gauge(gauge!("foo", "h1" => &1)).dec(1);
+
+        assert_eq!(2, store.gauge(gauge!("foo", "h1" => &1)).get());
+        assert_eq!(4, store.gauge_val(gauge!("foo")));
+
+        store.gauge(gauge!("foo", "h1" => &2)).set(7);
+        assert_eq!(9, store.gauge_val(gauge!("foo")));
+    }
+
+    #[test]
+    fn histogram() {
+        const BUCKETS: &[f64] = &[1.0, 10.0];
+        let mut store = Store::default();
+        store
+            .histogram(histogram!("foo", "h1" => &1), BUCKETS)
+            .record(0.5);
+        store
+            .histogram(histogram!("foo", "h1" => &2), BUCKETS)
+            .record(5.0);
+        store
+            .histogram(histogram!("foo", "h1" => &2), BUCKETS)
+            .record(50.0);
+
+        assert_eq!(
+            &[0, 1, 1],
+            store
+                .histogram(histogram!("foo", "h1" => &2), BUCKETS)
+                .get()
+                .bucket_counts()
+        );
+        let merged = store.histogram_val(histogram!("foo")).unwrap();
+        assert_eq!(&[1, 1, 1], merged.bucket_counts());
+        assert_eq!(3, merged.count());
+        assert!(store.histogram_val(histogram!("bar")).is_none());
+    }
+
+    #[test]
+    fn merge_all_kinds() {
+        const BUCKETS: &[f64] = &[1.0];
+        let mut store1 = Store::default();
+        let mut store2 = Store::default();
+        store1.gauge(gauge!("gauge")).inc(2);
+        store2.gauge(gauge!("gauge")).dec(1);
+        store1.histogram(histogram!("hist"), BUCKETS).record(0.0);
+        store2.histogram(histogram!("hist"), BUCKETS).record(2.0);
+
+        store1.merge(store2);
+
+        assert_eq!(1, store1.gauge_val(gauge!("gauge")));
+        assert_eq!(
+            &[1, 1],
+            store1
+                .histogram_val(histogram!("hist"))
+                .unwrap()
+                .bucket_counts()
+        );
+        assert_eq!(2, store1.len());
+        assert_eq!(1, store1.gauges().count());
+        assert_eq!(1, store1.histograms().count());
+    }
 }